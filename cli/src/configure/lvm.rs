@@ -4,9 +4,9 @@ use errors::DistinstError;
 
 pub(crate) fn lvm(
     disks: &mut Disks,
-    logical: Option<Values>,
-    modify: Option<Values>,
-    remove: Option<Values>,
+    logical: &[&str],
+    modify: &[&str],
+    remove: &[&str],
     remove_all: bool,
 ) -> Result<(), DistinstError> {
     eprintln!("distinst: configuring lvm / luks partitions");
@@ -14,7 +14,7 @@ pub(crate) fn lvm(
         for device in disks.get_logical_devices_mut() {
             device.clear_partitions();
         }
-    } else if let Some(remove) = remove {
+    } else {
         for value in remove {
             let values: Vec<&str> = value.split(':').collect();
             if values.len() != 2 {
@@ -30,80 +30,72 @@ pub(crate) fn lvm(
         }
     }
 
-    if let Some(modify) = modify {
-        for value in modify {
-            let values: Vec<&str> = value.split(':').collect();
-            if values.len() < 3 {
-                return Err(DistinstError::ModifyArgs);
-            }
+    for value in modify {
+        let values: Vec<&str> = value.split(':').collect();
+        if values.len() < 3 {
+            return Err(DistinstError::ModifyArgs);
+        }
 
-            let (group, volume) = (values[0], values[1]);
-            let (mut fs, mut mount) = (None, None);
-
-            for field in values.iter().skip(2) {
-                if field.starts_with("fs=") {
-                    fs = Some(parse_fs(&field[3..])?)
-                } else if field.starts_with("mount=") {
-                    mount = Some(&field[6..]);
-                } else {
-                    unimplemented!()
-                }
+        let (group, volume) = (values[0], values[1]);
+        let (mut fs, mut mount) = (None, None);
+
+        for field in values.iter().skip(2) {
+            if field.starts_with("fs=") {
+                fs = Some(parse_fs(&field[3..])?)
+            } else if field.starts_with("mount=") {
+                mount = Some(&field[6..]);
+            } else {
+                unimplemented!()
             }
+        }
 
-            let device = disks
-                .get_logical_device_mut(group)
-                .ok_or(DistinstError::LogicalDeviceNotFound { group: group.into() })?;
+        let device = disks
+            .get_logical_device_mut(group)
+            .ok_or(DistinstError::LogicalDeviceNotFound { group: group.into() })?;
 
-            let partition = device.get_partition_mut(volume).ok_or(
-                DistinstError::LogicalPartitionNotFound {
-                    group:  group.into(),
-                    volume: volume.into(),
-                },
-            )?;
+        let partition =
+            device.get_partition_mut(volume).ok_or(DistinstError::LogicalPartitionNotFound {
+                group:  group.into(),
+                volume: volume.into(),
+            })?;
 
-            if let Some(fs) = fs {
-                let fs = match fs {
-                    PartType::Fs(fs) => fs,
-                    PartType::Lvm(volume_group, encryption) => {
-                        partition.set_volume_group(volume_group, encryption);
-                        Some(FileSystem::Lvm)
-                    }
-                };
-
-                if let Some(fs) = fs {
-                    partition.format_and_keep_name(fs);
+        if let Some(fs) = fs {
+            let fs = match fs {
+                PartType::Fs(fs) => fs,
+                PartType::Lvm(volume_group, encryption) => {
+                    partition.set_volume_group(volume_group, encryption);
+                    Some(FileSystem::Lvm)
                 }
-            }
+            };
 
-            if let Some(mount) = mount {
-                partition.set_mount(PathBuf::from(mount.to_owned()));
+            if let Some(fs) = fs {
+                partition.format_and_keep_name(fs);
             }
         }
-    }
 
-    if let Some(logical) = logical {
-        parse_logical(logical, |args| match disks.get_logical_device_mut(&args.group) {
-            Some(lvm_device) => {
-                let start = lvm_device.get_last_sector();
-                let end = start + lvm_device.get_sector(args.size);
-                let mut builder =
-                    PartitionBuilder::new(start, end, args.fs).name(args.name.clone());
+        if let Some(mount) = mount {
+            partition.set_mount(PathBuf::from(mount.to_owned()));
+        }
+    }
 
-                if let Some(mount) = args.mount.as_ref() {
-                    builder = builder.mount(mount.clone());
-                }
+    parse_logical(logical, |args| match disks.get_logical_device_mut(&args.group) {
+        Some(lvm_device) => {
+            let start = lvm_device.get_last_sector();
+            let end = start + lvm_device.get_sector(args.size);
+            let mut builder = PartitionBuilder::new(start, end, args.fs).name(args.name.clone());
 
-                if let Some(flags) = args.flags.as_ref() {
-                    builder = builder.flags(flags.clone());
-                }
+            if let Some(mount) = args.mount.as_ref() {
+                builder = builder.mount(mount.clone());
+            }
 
-                lvm_device
-                    .add_partition(builder)
-                    .map_err(|why| DistinstError::LvmPartitionAdd { why })
+            if let Some(flags) = args.flags.as_ref() {
+                builder = builder.flags(flags.clone());
             }
-            None => Err(DistinstError::NoVolumeGroupAssociated { group: args.group }),
-        })?;
-    }
+
+            lvm_device.add_partition(builder).map_err(|why| DistinstError::LvmPartitionAdd { why })
+        }
+        None => Err(DistinstError::NoVolumeGroupAssociated { group: args.group }),
+    })?;
 
     Ok(())
 }
@@ -125,7 +117,7 @@ struct LogicalArgs {
 }
 
 fn parse_logical<F: FnMut(LogicalArgs) -> Result<(), DistinstError>>(
-    values: Values,
+    values: &[&str],
     mut action: F,
 ) -> Result<(), DistinstError> {
     for value in values {
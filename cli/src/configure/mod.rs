@@ -6,36 +6,159 @@ mod removed;
 mod reuse;
 mod table;
 
-use self::{decrypt::*, lvm::*, moved::*, new::*, removed::*, reuse::*, table::*};
+use self::{lvm::*, moved::*, new::*, removed::*, reuse::*, table::*};
+
+pub(crate) use self::decrypt::decrypt;
 
 use super::*;
+use clap::Arg;
 use errors::DistinstError;
 
-pub(crate) fn configure_disks(matches: &ArgMatches) -> Result<Disks, DistinstError> {
+/// The requested disk configuration, sourced from either command line arguments or a preseed file.
+///
+/// Each field contains values in the same colon-delimited syntax accepted by the command line.
+#[derive(Debug, Default)]
+pub(crate) struct DiskLayout<'a> {
+    pub blocks:             Vec<&'a str>,
+    pub tables:             Vec<&'a str>,
+    pub delete:             Vec<&'a str>,
+    pub moved:              Vec<&'a str>,
+    pub reuse:              Vec<&'a str>,
+    pub new:                Vec<&'a str>,
+    pub decrypt:            Vec<&'a str>,
+    pub logical:            Vec<&'a str>,
+    pub logical_modify:     Vec<&'a str>,
+    pub logical_remove:     Vec<&'a str>,
+    pub logical_remove_all: bool,
+}
+
+/// The argument which defines the disks to configure. Its requirement is left to each
+/// subcommand.
+pub(crate) fn disk_arg() -> Arg<'static, 'static> {
+    Arg::with_name("disk")
+        .short("b")
+        .long("block")
+        .help("defines a disk that will be manipulated in the installation process")
+        .takes_value(true)
+        .multiple(true)
+}
+
+/// The arguments which define the changes to make to the disks, as read by
+/// `DiskLayout::from_matches`.
+pub(crate) fn layout_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("table")
+            .short("t")
+            .long("new-table")
+            .help(
+                "defines a new partition table to apply to the disk, clobbering it in the process",
+            )
+            .multiple(true)
+            .takes_value(true),
+        Arg::with_name("new")
+            .short("n")
+            .long("new")
+            .help(
+                "defines a new partition that will be created on the disk, with optional \
+                 label=LABEL, name=NAME, and uuid=UUID fields for its file system label, GPT \
+                 name, and file system UUID",
+            )
+            .multiple(true)
+            .takes_value(true),
+        Arg::with_name("use")
+            .short("u")
+            .long("use")
+            .help(
+                "defines to reuse an existing partition on the disk, or a logical volume given as \
+                 VG/LV or by its /dev/mapper path",
+            )
+            .takes_value(true)
+            .multiple(true),
+        Arg::with_name("delete")
+            .short("d")
+            .long("delete")
+            .help("defines to delete the specified partitions")
+            .takes_value(true)
+            .multiple(true),
+        Arg::with_name("move")
+            .short("m")
+            .long("move")
+            .help("defines to move and/or resize an existing partition")
+            .takes_value(true)
+            .multiple(true),
+        Arg::with_name("logical")
+            .long("logical")
+            .help("creates a partition on a LVM volume group")
+            .takes_value(true)
+            .multiple(true),
+        Arg::with_name("logical-modify")
+            .long("logical-modify")
+            .help("modifies an existing LVM volume group")
+            .takes_value(true)
+            .multiple(true),
+        Arg::with_name("logical-remove")
+            .long("logical-remove")
+            .help("removes an existing LVM logical volume")
+            .takes_value(true)
+            .multiple(true),
+        Arg::with_name("logical-remove-all")
+            .long("logical-remove-all")
+            .help("TODO")
+            .takes_value(true),
+        Arg::with_name("decrypt")
+            .long("decrypt")
+            .help("decrypts an existing LUKS partition")
+            .takes_value(true)
+            .multiple(true),
+    ]
+}
+
+impl<'a> DiskLayout<'a> {
+    pub fn from_matches(matches: &'a ArgMatches) -> Self {
+        let values = |key| matches.values_of(key).map_or_else(Vec::new, Iterator::collect);
+
+        DiskLayout {
+            blocks:             values("disk"),
+            tables:             values("table"),
+            delete:             values("delete"),
+            moved:              values("move"),
+            reuse:              values("use"),
+            new:                values("new"),
+            decrypt:            values("decrypt"),
+            logical:            values("logical"),
+            logical_modify:     values("logical-modify"),
+            logical_remove:     values("logical-remove"),
+            logical_remove_all: matches.is_present("logical-remove-all"),
+        }
+    }
+}
+
+pub(crate) fn configure_disks(layout: &DiskLayout) -> Result<Disks, DistinstError> {
     let mut disks = Disks::default();
 
     {
         let disks = &mut disks;
 
-        for block in matches.values_of("disk").unwrap() {
+        for block in &layout.blocks {
             eprintln!("distinst: adding {} to disks configuration", block);
             disks.add(Disk::from_name(block)?);
         }
 
-        tables(disks, matches.values_of("table"))
-            .and_then(|_| removed(disks, matches.values_of("delete")))
-            .and_then(|_| moved(disks, matches.values_of("move")))
-            .and_then(|_| reused(disks, matches.values_of("use")))
-            .and_then(|_| new(disks, matches.values_of("new")))
+        tables(disks, &layout.tables)
+            .and_then(|_| removed(disks, &layout.delete))
+            .and_then(|_| moved(disks, &layout.moved))
+            .and_then(|_| reused(disks, &layout.reuse))
+            .and_then(|_| new(disks, &layout.new))
             .and_then(|_| initialize_logical(disks))
-            .and_then(|_| decrypt(disks, matches.values_of("decrypt")))
+            .and_then(|_| decrypt(disks, &layout.decrypt))
+            .and_then(|_| reused_logical(disks, &layout.reuse))
             .and_then(|_| {
                 lvm(
                     disks,
-                    matches.values_of("logical"),
-                    matches.values_of("logical-modify"),
-                    matches.values_of("logical-remove"),
-                    matches.is_present("logical-remove-all"),
+                    &layout.logical,
+                    &layout.logical_modify,
+                    &layout.logical_remove,
+                    layout.logical_remove_all,
                 )
             })?;
 
@@ -1,77 +1,143 @@
 use super::*;
 use errors::DistinstError;
 
-pub(crate) fn reused(disks: &mut Disks, parts: Option<Values>) -> Result<(), DistinstError> {
+pub(crate) fn reused(disks: &mut Disks, parts: &[&str]) -> Result<(), DistinstError> {
     eprintln!("distinst: configuring reused partitions");
-    if let Some(parts) = parts {
-        for part in parts {
-            let values: Vec<&str> = part.split(':').collect();
-            if values.len() < 3 || values.len() > 5 {
-                return Err(DistinstError::ReusedArgs);
-            }
+    for part in parts.iter().filter(|part| !is_logical(part)) {
+        let values: Vec<&str> = part.split(':').collect();
+        if values.len() < 3 || values.len() > 5 {
+            return Err(DistinstError::ReusedArgs);
+        }
 
-            let (block_dev, part_id, fs) = (
-                values[0],
-                values[1]
-                    .parse::<u32>()
-                    .map(|id| id as i32)
-                    .map_err(|_| DistinstError::ArgNaN { arg: values[1].into() })?,
-                match values[2] {
-                    "reuse" => None,
-                    fs => Some(parse_fs(fs)?),
-                },
-            );
-
-            let (mut key, mut mount, mut flags) = (None, None, None);
-
-            for value in values.iter().skip(3) {
-                if value.starts_with("mount=") {
-                    mount = Some(Path::new(&value[6..]));
-                } else if value.starts_with("flags=") {
-                    flags = Some(parse_flags(&value[6..]));
-                } else if value.starts_with("keyid=") {
-                    key = Some(String::from(&value[6..]));
-                } else {
-                    return Err(DistinstError::InvalidField { field: (*value).into() });
-                }
-            }
+        let (block_dev, part_id) = (
+            values[0],
+            values[1]
+                .parse::<u32>()
+                .map(|id| id as i32)
+                .map_err(|_| DistinstError::ArgNaN { arg: values[1].into() })?,
+        );
+
+        let disk = find_disk_mut(disks, block_dev)?;
+        let partition = find_partition_mut(disk, part_id)?;
+        reuse(partition, &values[2..], false)?;
+    }
+
+    Ok(())
+}
+
+/// Reuses logical volumes and LUKS devices, which are only known after the volume groups
+/// have been initialized and decrypted.
+///
+/// These are referenced by their `VG/LV` names, or by their `/dev/mapper` paths.
+pub(crate) fn reused_logical(disks: &mut Disks, parts: &[&str]) -> Result<(), DistinstError> {
+    eprintln!("distinst: configuring reused logical volumes");
+    for part in parts.iter().filter(|part| is_logical(part)) {
+        let values: Vec<&str> = part.split(':').collect();
+        if values.len() < 2 || values.len() > 4 {
+            return Err(DistinstError::ReusedArgs);
+        }
+
+        let device = values[0];
+        let partition = if device.starts_with('/') {
+            let path = Path::new(device)
+                .canonicalize()
+                .map_err(|_| DistinstError::LogicalPathNotFound { path: device.into() })?;
+
+            disks
+                .get_partition_by_path_mut(&path)
+                .ok_or_else(|| DistinstError::LogicalPathNotFound { path: device.into() })?
+        } else {
+            let mut fields = device.splitn(2, '/');
+            let (group, volume) = (fields.next().unwrap_or(""), fields.next().unwrap_or(""));
+            disks
+                .get_logical_device_mut(group)
+                .ok_or_else(|| DistinstError::LogicalDeviceNotFound { group: group.into() })?
+                .get_partition_mut(volume)
+                .ok_or_else(|| DistinstError::LogicalPartitionNotFound {
+                    group:  group.into(),
+                    volume: volume.into(),
+                })?
+        };
+
+        reuse(partition, &values[1..], true)?;
+    }
+
+    Ok(())
+}
+
+/// Logical devices are given as `VG/LV`, `/dev/VG/LV`, or `/dev/mapper/NAME`, whereas
+/// physical disks are given as `sda`, `/dev/sda`, or a path within `/dev/disk`.
+fn is_logical(part: &str) -> bool {
+    let device = part.split(':').next().unwrap_or("");
+    let components = Path::new(device).components().count();
+    if device.starts_with('/') {
+        components == 4 && !device.starts_with("/dev/disk/")
+    } else {
+        components == 2
+    }
+}
+
+/// Applies the file system and fields of a `--use` argument to a partition. The names of
+/// logical volumes are kept when they are formatted, as they identify the volume.
+fn reuse(
+    partition: &mut PartitionInfo,
+    values: &[&str],
+    logical: bool,
+) -> Result<(), DistinstError> {
+    let fs = match values[0] {
+        "reuse" => None,
+        fs => Some(parse_fs(fs)?),
+    };
 
-            let disk = find_disk_mut(disks, block_dev)?;
-            let partition = find_partition_mut(disk, part_id)?;
-
-            if let Some(keyid) = key {
-                match mount {
-                    Some(mount) => {
-                        partition.associate_keyfile(keyid);
-                        partition.set_mount(mount.into());
-                    }
-                    None => {
-                        return Err(DistinstError::NoMountPath);
-                    }
-                }
-            } else if let Some(mount) = mount {
-                partition.set_mount(Path::new(mount).to_path_buf());
+    let (mut key, mut mount, mut flags) = (None, None, None);
+
+    for value in values.iter().skip(1) {
+        if value.starts_with("mount=") {
+            mount = Some(Path::new(&value[6..]));
+        } else if value.starts_with("flags=") {
+            flags = Some(parse_flags(&value[6..]));
+        } else if value.starts_with("keyid=") {
+            key = Some(String::from(&value[6..]));
+        } else {
+            return Err(DistinstError::InvalidField { field: (*value).into() });
+        }
+    }
+
+    if let Some(keyid) = key {
+        match mount {
+            Some(mount) => {
+                partition.associate_keyfile(keyid);
+                partition.set_mount(mount.into());
             }
+            None => {
+                return Err(DistinstError::NoMountPath);
+            }
+        }
+    } else if let Some(mount) = mount {
+        partition.set_mount(Path::new(mount).to_path_buf());
+    }
 
-            if let Some(fs) = fs {
-                let fs = match fs {
-                    PartType::Fs(fs) => fs,
-                    PartType::Lvm(volume_group, encryption) => {
-                        partition.set_volume_group(volume_group, encryption);
-                        Some(FileSystem::Lvm)
-                    }
-                };
-
-                if let Some(fs) = fs {
-                    partition.format_with(fs);
-                }
+    if let Some(fs) = fs {
+        let fs = match fs {
+            PartType::Fs(fs) => fs,
+            PartType::Lvm(volume_group, encryption) => {
+                partition.set_volume_group(volume_group, encryption);
+                Some(FileSystem::Lvm)
             }
+        };
 
-            if let Some(flags) = flags {
-                partition.flags = flags;
+        if let Some(fs) = fs {
+            if logical {
+                partition.format_and_keep_name(fs);
+            } else {
+                partition.format_with(fs);
             }
         }
     }
 
+    if let Some(flags) = flags {
+        partition.flags = flags;
+    }
+
     Ok(())
 }
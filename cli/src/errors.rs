@@ -1,4 +1,5 @@
 use super::*;
+use distinst::{auto::ReinstallError, locale::InvalidKeyboard};
 
 #[derive(Debug, Fail)]
 pub(crate) enum DistinstError {
@@ -42,6 +43,8 @@ pub(crate) enum DistinstError {
     LogicalPartitionNotFound { group: String, volume: String },
     #[fail(display = "invalid number of arguments supplied to --logical-modify")]
     ModifyArgs,
+    #[fail(display = "no logical volume or LUKS device at '{}' was found", path)]
+    LogicalPathNotFound { path: String },
     #[fail(display = "could not find volume group associated with '{}'", group)]
     NoVolumeGroupAssociated { group: String },
     #[fail(display = "invalid number of arguments supplied to --use")]
@@ -56,12 +59,59 @@ pub(crate) enum DistinstError {
     NoMountPath,
     #[fail(display = "mount value is empty")]
     EmptyMount,
+    #[fail(display = "'{}' is not a host entry of the form ADDRESS=NAME[,NAME...]", host)]
+    InvalidHost { host: String },
+    #[fail(display = "'{}' is not the path of a service unit", path)]
+    InvalidFirstBootService { path: String },
+    #[fail(display = "'{}' is not a flatpak remote of the form NAME=URL", remote)]
+    InvalidFlatpakRemote { remote: String },
+    #[fail(
+        display = "'{}' is not an I/O priority of idle, best-effort[:LEVEL], or realtime[:LEVEL]",
+        priority
+    )]
+    InvalidIoPriority { priority: String },
+    #[fail(display = "unable to read keyboard layouts: {}", why)]
+    KeyboardLayouts { why: io::Error },
+    #[fail(display = "{}", why)]
+    InvalidKeyboard { why: InvalidKeyboard },
+    #[fail(display = "'{}' is not a locale override of the form CATEGORY=LOCALE", value)]
+    InvalidLocaleOverride { value: String },
     #[fail(display = "unable to add partition to lvm device: {}", why)]
     LvmPartitionAdd { why: DiskError },
     #[fail(display = "unable to initialize volume groups: {}", why)]
     InitializeVolumes { why: DiskError },
+    #[fail(display = "failed to read preseed file at {:?}: {}", path, why)]
+    PreseedRead { path: PathBuf, why: io::Error },
+    #[fail(display = "failed to parse preseed file: {}", why)]
+    PreseedParse { why: toml::de::Error },
+    #[fail(display = "preseed file is invalid:\n    {}", issues)]
+    PreseedInvalid { issues: String },
+    #[fail(display = "invalid Tang server: {}", why)]
+    InvalidTang { why: io::Error },
+    #[fail(display = "'{}' is not one of discard, nvme-format, or overwrite[=PASSES]", mode)]
+    InvalidEraseMode { mode: String },
+    #[fail(display = "install failed: {}", why)]
+    Install { why: io::Error },
+    #[fail(display = "partitioning failed: {}", why)]
+    Partition { why: io::Error },
+    #[fail(display = "recovery partition error: {}", why)]
+    Recovery { why: io::Error },
+    #[fail(display = "failed to read the recovery configuration: {}", why)]
+    RecoveryConf { why: io::Error },
+    #[fail(display = "no recovery partition was found to upgrade from")]
+    NoRecoveryOption,
+    #[fail(display = "upgrade failed: {}", why)]
+    Upgrade { why: UpgradeError },
+    #[fail(display = "no file system was found on '{}'", device)]
+    NoFileSystem { device: String },
+    #[fail(display = "rollback failed: {}", why)]
+    Rollback { why: ReinstallError },
 }
 
 impl From<DiskError> for DistinstError {
     fn from(why: DiskError) -> DistinstError { DistinstError::Disk { why } }
 }
+
+impl From<ReinstallError> for DistinstError {
+    fn from(why: ReinstallError) -> DistinstError { DistinstError::Rollback { why } }
+}
@@ -0,0 +1,18 @@
+//! The `grow` subcommand, which grows the last partition of a disk, and its file system, to the
+//! end of the disk, such as after an image was written to a disk that is larger than it.
+
+use super::*;
+use clap::{App, Arg, SubCommand};
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("grow")
+        .about("grows the last partition of a disk, and its file system, to the end of the disk")
+        .arg(Arg::with_name("disk").help("the disk to grow the last partition of").required(true))
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), DiskError> {
+    let mut disk = Disk::from_name(matches.value_of("disk").unwrap())?;
+    let end = disk.grow_last_partition_to_max()?;
+    println!("the last partition of {} now ends at sector {}", disk.path().display(), end);
+    Ok(())
+}
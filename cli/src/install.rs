@@ -0,0 +1,1030 @@
+//! The `install` subcommand, which installs a distribution to the disks, from either command line
+//! arguments or a preseed file.
+
+use super::*;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use distinst::{
+    auto::FileBackup,
+    timezones::{Region, Timezones},
+};
+use pbr::ProgressBar;
+use preseed::Preseed;
+use std::{cell::RefCell, rc::Rc};
+
+/// The arguments which a preseed file answers, and which therefore may not be given with it.
+/// Only the arguments which control how the installer runs, rather than what it installs, may be.
+const PRESEED_CONFLICTS: &[&str] = &[
+    "username",
+    "password",
+    "realname",
+    "profile_icon",
+    "ssh-key",
+    "shell",
+    "sudo",
+    "homed",
+    "groups",
+    "timezone",
+    "squashfs",
+    "hostname",
+    "keyboard",
+    "bios-device",
+    "make-mbr-gap",
+    "image-sha256sums",
+    "image-signature",
+    "image-keyring",
+    "verify-extraction",
+    "extraction-manifest",
+    "backup-path",
+    "backup-source",
+    "backup-destination",
+    "backup-directory",
+    "kernel-variant",
+    "lang",
+    "additional-locale",
+    "locale-override",
+    "remove",
+    "cleanup",
+    "esp-policy",
+    "trim",
+    "extract-threads",
+    "extract-io-priority",
+    "extract-backend",
+    "tang-url",
+    "tang-thumbprint",
+    "post-install-script",
+    "first-boot-service",
+    "offline-repo",
+    "offline-repo-keyring",
+    "flatpak",
+    "flatpak-remote",
+    "rtc",
+    "minimal",
+    "hardware-support",
+    "modify-boot",
+    "run-ubuntu-drivers",
+    "mok-password",
+    "journal-commands",
+    "rename-vgs",
+    "host",
+    "mdns",
+    "read-only-root",
+    "no-initramfs-keymap",
+    "restore-packages",
+    "disk",
+    "table",
+    "new",
+    "use",
+    "delete",
+    "move",
+    "logical",
+    "logical-modify",
+    "logical-remove",
+    "logical-remove-all",
+    "decrypt",
+];
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("install")
+        .about("installs a distribution to the disks")
+        .arg(
+            Arg::with_name("preseed")
+                .long("preseed")
+                .help("performs an unattended install from the answers in a TOML preseed file")
+                .takes_value(true)
+                .conflicts_with_all(PRESEED_CONFLICTS),
+        )
+        .arg(
+            Arg::with_name("username")
+                .long("username")
+                .requires("profile_icon")
+                .help("specifies a default user account to create")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("password")
+                .long("password")
+                .help("set the password for the username")
+                .requires("username")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("realname")
+                .long("realname")
+                .help("the full name of user to create")
+                .requires("username")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("profile_icon")
+                .long("profile_icon")
+                .help("path to icon for user profile")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ssh-key")
+                .long("ssh-key")
+                .help("authorizes a public key to log in as the user over SSH")
+                .requires("username")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("shell")
+                .long("shell")
+                .help("the login shell of the user, instead of /bin/bash")
+                .requires("username")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sudo")
+                .long("sudo")
+                .help(
+                    "grants the user sudo in a sudoers.d drop-in, which requires their password, \
+                     or allows any command without a password",
+                )
+                .requires("username")
+                .possible_values(&["password", "nopasswd"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("homed")
+                .long("homed")
+                .help(
+                    "creates the user with systemd-homed on the first boot, with a home that is \
+                     encrypted with their password",
+                )
+                .requires("username"),
+        )
+        .arg(
+            Arg::with_name("groups")
+                .long("groups")
+                .help(
+                    "comma-separated supplementary groups of the user, instead of the \
+                     administrator groups adm, sudo, and lpadmin; none creates a standard user",
+                )
+                .requires("username")
+                .takes_value(true)
+                .use_delimiter(true)
+                .min_values(0),
+        )
+        .arg(
+            Arg::with_name("timezone")
+                .long("tz")
+                .help(
+                    "the timezone to set for the new install, such as America/Denver, or \
+                     America/Argentina/Buenos_Aires",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("squashfs")
+                .short("s")
+                .long("squashfs")
+                .help("define the squashfs image which will be installed")
+                .takes_value(true)
+                .required_unless("preseed"),
+        )
+        .arg(
+            Arg::with_name("hostname")
+                .short("h")
+                .long("hostname")
+                .help("define the hostname that the new system will have")
+                .takes_value(true)
+                .required_unless("preseed"),
+        )
+        .arg(
+            Arg::with_name("keyboard")
+                .short("k")
+                .long("keyboard")
+                .help("define the keyboard configuration to use")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(3)
+                .default_value("us"),
+        )
+        .arg(
+            Arg::with_name("bios-device")
+                .long("bios-device")
+                .help("install GRUB to this device on BIOS systems, instead of the boot disk")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("make-mbr-gap")
+                .long("make-mbr-gap")
+                .help("on BIOS systems, move the first partition to make room to embed GRUB"),
+        )
+        .arg(
+            Arg::with_name("image-sha256sums")
+                .long("image-sha256sums")
+                .help("verify the image against its entry in this SHA256SUMS file")
+                .takes_value(true)
+                .conflicts_with("image-signature"),
+        )
+        .arg(
+            Arg::with_name("image-signature")
+                .long("image-signature")
+                .help("verify the image against this detached GPG signature")
+                .requires("image-keyring")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("image-keyring")
+                .long("image-keyring")
+                .help("the keyring which the signature of the image is verified with")
+                .requires("image-signature")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("verify-extraction")
+                .long("verify-extraction")
+                .help("compare the extracted files with the files of the image"),
+        )
+        .arg(
+            Arg::with_name("extraction-manifest")
+                .long("extraction-manifest")
+                .help("compare the extracted files with the digests of this manifest")
+                .takes_value(true)
+                .conflicts_with("verify-extraction"),
+        )
+        .arg(
+            Arg::with_name("backup-path")
+                .long("backup-path")
+                .help("copies this file or directory of the backup source before the install")
+                .requires_all(&["backup-source", "backup-destination"])
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("backup-source")
+                .long("backup-source")
+                .help("the partition which the backup paths are copied from")
+                .requires("backup-path")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backup-destination")
+                .long("backup-destination")
+                .help("the partition, which the install leaves untouched, to copy the backup to")
+                .requires("backup-path")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backup-directory")
+                .long("backup-directory")
+                .help("the directory within the backup destination to copy the backup into")
+                .requires("backup-path")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("kernel-variant")
+                .long("kernel-variant")
+                .help("install this variant of the kernel in the casper directory, such as hwe")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("lang")
+                .short("l")
+                .long("lang")
+                .help("define the locale that the new system will use")
+                .takes_value(true)
+                .default_value("en_US.UTF-8"),
+        )
+        .arg(
+            Arg::with_name("additional-locale")
+                .long("additional-locale")
+                .help("generates another locale, whose language messages will fall back to")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("locale-override")
+                .long("locale-override")
+                .help("sets a CATEGORY=LOCALE, such as LC_TIME=de_DE.UTF-8, in the new system")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("remove")
+                .short("r")
+                .long("remove")
+                .help("defines the manifest file that contains the packages to remove post-install")
+                .takes_value(true)
+                .required_unless("preseed"),
+        )
+        .arg(
+            Arg::with_name("cleanup")
+                .long("cleanup")
+                .help(
+                    "removes the packages that apt downloaded, its caches, journals, and \
+                     temporary files from the installed system; full also removes the package \
+                     lists, for systems whose sources will change on their first boot",
+                )
+                .possible_values(&["none", "standard", "full"])
+                .default_value("none"),
+        )
+        .arg(
+            Arg::with_name("esp-policy")
+                .long("esp-policy")
+                .help(
+                    "whether an existing ESP may be formatted; reuse fails if it lacks room for \
+                     the boot files, and reuse-with-cleanup first removes the boot files of \
+                     systems which no longer exist",
+                )
+                .possible_values(&["reuse", "reuse-with-cleanup", "format"])
+                .default_value("format"),
+        )
+        .arg(
+            Arg::with_name("trim")
+                .long("trim")
+                .help(
+                    "how file systems on disks which support discard are trimmed; periodic \
+                     enables fstrim.timer, and continuous mounts them with discard",
+                )
+                .possible_values(&["periodic", "continuous", "disabled"])
+                .default_value("periodic"),
+        )
+        .arg(
+            Arg::with_name("extract-threads")
+                .long("extract-threads")
+                .help(
+                    "the number of threads which decompress the image; by default, this is \
+                     selected from the CPUs and memory of the machine",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("extract-io-priority")
+                .long("extract-io-priority")
+                .help(
+                    "the I/O scheduling class of the extraction, as idle, best-effort[:LEVEL], \
+                     or realtime[:LEVEL], where the level ranges from 0, the highest, to 7",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("extract-backend")
+                .long("extract-backend")
+                .help(
+                    "what extracts a squashfs image; auto uses unsquashfs when it is installed, \
+                     and otherwise extracts it in-process",
+                )
+                .possible_values(&["auto", "unsquashfs", "in-process"])
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::with_name("tang-url")
+                .long("tang-url")
+                .help(
+                    "binds the encrypted volumes to the Tang server at this URL with clevis, so \
+                     that they are unlocked automatically while the server can be reached",
+                )
+                .requires("tang-thumbprint")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tang-thumbprint")
+                .long("tang-thumbprint")
+                .help("the thumbprint of the Tang server's signing key, which verifies its keys")
+                .requires("tang-url")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("post-install-script")
+                .long("post-install-script")
+                .help(
+                    "executes a script within the installed system once it has been \
+                     configured, which may be given several times",
+                )
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("first-boot-service")
+                .long("first-boot-service")
+                .help(
+                    "enables a service unit which runs once, on the first boot of the installed \
+                     system, which may be given several times",
+                )
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("offline-repo")
+                .long("offline-repo")
+                .help(
+                    "installs packages from a flat apt repository in this directory, which is \
+                     only a source of packages while installing",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("offline-repo-keyring")
+                .long("offline-repo-keyring")
+                .help("the keyring which verifies the offline repository, instead of trusting it")
+                .requires("offline-repo")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("flatpak")
+                .long("flatpak")
+                .help("installs a flatpak system-wide, by its application ID or ref")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("flatpak-remote")
+                .long("flatpak-remote")
+                .help("adds a NAME=URL flatpak remote, from the URL of its .flatpakrepo file")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("rtc")
+                .long("rtc")
+                .help(
+                    "whether the hardware clock keeps local time or UTC; by default, it keeps \
+                     local time if Windows is found",
+                )
+                .possible_values(&["local", "utc"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("minimal")
+                .long("minimal")
+                .help("defines a manifest of additional packages to remove for a minimal install")
+                .takes_value(true),
+        )
+        .arg(disk_arg().required_unless("preseed"))
+        .args(&layout_args())
+        .arg(
+            Arg::with_name("test")
+                .long("test")
+                .help("simply test whether the provided arguments pass the partitioning stage"),
+        )
+        .arg(
+            Arg::with_name("hardware-support")
+                .long("hardware-support")
+                .help("install hardware support packages based on detected hardware"),
+        )
+        .arg(
+            Arg::with_name("modify-boot")
+                .long("modify-boot")
+                .help("modify the boot order after installing"),
+        )
+        .arg(
+            Arg::with_name("force-bios")
+                .long("force-bios")
+                .help("performs a BIOS installation even if the running system is EFI"),
+        )
+        .arg(
+            Arg::with_name("force-efi")
+                .long("force-efi")
+                .help("performs an EFI installation even if the running system is BIOS"),
+        )
+        .arg(
+            Arg::with_name("force-uboot")
+                .long("force-uboot")
+                .help("performs a U-Boot installation, as on ARM boards without EFI"),
+        )
+        .arg(
+            Arg::with_name("no-efi-vars")
+                .long("no-efi-vars")
+                .help("disables mounting of the efivars directory"),
+        )
+        .arg(Arg::with_name("run-ubuntu-drivers").long("run-ubuntu-drivers").help(
+            "use ubuntu-drivers to find drivers then install in the chroot, some may have \
+             proprietary licenses",
+        ))
+        .arg(
+            Arg::with_name("mok-password")
+                .long("mok-password")
+                .help(
+                    "signs DKMS modules for Secure Boot with a key which is enrolled on the next \
+                     boot with this password",
+                )
+                .requires("run-ubuntu-drivers")
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("journal-commands").long("journal-commands").help(
+            "record every external command, with its output, in /var/log/distinst/commands.log",
+        ))
+        .arg(Arg::with_name("rename-vgs").long("rename-vgs").help(
+            "give new volume groups a unique name if a volume group with the same name already \
+             exists, instead of failing",
+        ))
+        .arg(
+            Arg::with_name("host")
+                .long("host")
+                .help("adds an ADDRESS=NAME[,NAME...] entry to the hosts file of the new system")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("mdns")
+                .long("mdns")
+                .help("resolve .local names with multicast DNS, by enabling avahi"),
+        )
+        .arg(Arg::with_name("read-only-root").long("read-only-root").help(
+            "mount the root file system as read-only, with overlays of /etc and /var which are \
+             discarded on each boot",
+        ))
+        .arg(Arg::with_name("no-initramfs-keymap").long("no-initramfs-keymap").help(
+            "leave the initramfs with the US keymap, rather than the keyboard layout, when \
+             encrypted volumes are unlocked while booting",
+        ))
+        .arg(Arg::with_name("restore-packages").long("restore-packages").help(
+            "reinstall the manually installed packages of the system which a refresh install \
+             replaces",
+        ))
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), DistinstError> {
+    let request = match matches.value_of("preseed") {
+        Some(path) => Preseed::from_path(Path::new(path)).and_then(|preseed| preseed.prepare()),
+        None => request_from_args(matches),
+    };
+
+    let InstallRequest { disks, config, timezone, user_accounts, mok_password } = request?;
+
+    let pb_opt: Rc<RefCell<Option<ProgressBar<io::Stdout>>>> = Rc::new(RefCell::new(None));
+
+    let res = {
+        let mut installer = Installer::default();
+
+        {
+            let pb_opt = pb_opt.clone();
+            installer.on_error(move |error| {
+                if let Some(mut pb) = pb_opt.borrow_mut().take() {
+                    pb.finish_println("");
+                }
+
+                eprintln!("Error: {:?}", error);
+            });
+        }
+
+        {
+            let pb_opt = pb_opt.clone();
+            let mut step_opt = None;
+            installer.on_status(move |status| {
+                if step_opt != Some(status.step) {
+                    if let Some(mut pb) = pb_opt.borrow_mut().take() {
+                        pb.finish_println("");
+                    }
+
+                    step_opt = Some(status.step);
+
+                    let mut pb = ProgressBar::new(100);
+                    pb.show_speed = false;
+                    pb.show_counter = false;
+                    pb.message(match status.step {
+                        Step::Backup => "Backing up files",
+                        Step::Init => "Initializing",
+                        Step::Verify => "Verifying image",
+                        Step::Partition => "Partitioning disk ",
+                        Step::Extract => "Extracting filesystem ",
+                        Step::Configure => "Configuring installation",
+                        Step::Bootloader => "Installing bootloader ",
+                        Step::Cleanup => "Cleaning up",
+                    });
+                    *pb_opt.borrow_mut() = Some(pb);
+                }
+
+                if let Some(ref mut pb) = *pb_opt.borrow_mut() {
+                    pb.set(status.percent as u64);
+                }
+            });
+        }
+
+        if let Some(timezone) = timezone {
+            installer.set_timezone_callback(move || timezone.clone());
+        }
+
+        if !user_accounts.is_empty() {
+            installer.set_user_callback(move || user_accounts.clone());
+        }
+
+        if let Some(mok_password) = mok_password {
+            installer.set_mok_password_callback(move || Some(mok_password.clone()));
+        }
+
+        installer.set_unmount_callback(|mounts| {
+            for mount in mounts {
+                eprintln!("distinst: releasing {}", mount);
+            }
+
+            true
+        });
+
+        configure_signal_handling();
+
+        if matches.is_present("test") {
+            PARTITIONING_TEST.store(true, Ordering::Relaxed);
+        }
+
+        if matches.is_present("force-bios") {
+            FORCE_BOOTLOADER.store(1, Ordering::Relaxed);
+        } else if matches.is_present("force-efi") {
+            FORCE_BOOTLOADER.store(2, Ordering::Relaxed);
+        } else if matches.is_present("force-uboot") {
+            FORCE_BOOTLOADER.store(3, Ordering::Relaxed);
+        }
+
+        if matches.is_present("no-efi-vars") {
+            NO_EFI_VARIABLES.store(true, Ordering::Relaxed);
+        }
+
+        // The lock is an `OwnedFd`, which on drop will close / unlock the inhibitor.
+        let _inhibit_suspend = match distinst::dbus_interfaces::LoginManager::new() {
+            Ok(manager) => match manager.connect().inhibit_suspend(
+                "Distinst Installer",
+                "prevent suspension while installing a distribution",
+            ) {
+                Ok(lock) => Some(lock),
+                Err(why) => {
+                    eprintln!("distinst: failed to inhibit suspend: {}", why);
+                    None
+                }
+            },
+            Err(why) => {
+                eprintln!("distinst: failed to get logind dbus connection: {}", why);
+                None
+            }
+        };
+
+        installer.install(disks, &config)
+    };
+
+    if let Some(mut pb) = pb_opt.borrow_mut().take() {
+        pb.finish_println("");
+    }
+
+    res.map_err(|why| DistinstError::Install { why })?;
+    println!("install was successful");
+    Ok(())
+}
+
+/// Everything that is required to start an installation.
+pub(crate) struct InstallRequest {
+    pub disks:         Disks,
+    pub config:        Config,
+    pub timezone:      Option<Region>,
+    pub user_accounts: Vec<UserAccountCreate>,
+    pub mok_password:  Option<String>,
+}
+
+fn request_from_args(matches: &ArgMatches) -> Result<InstallRequest, DistinstError> {
+    fn take_optional_string(argument: Option<&str>) -> Option<String> {
+        argument.map(String::from).and_then(|x| if x.is_empty() { None } else { Some(x) })
+    }
+
+    let squashfs = matches.value_of("squashfs").unwrap();
+    let hostname = matches.value_of("hostname").unwrap();
+    let mut keyboard = matches.values_of("keyboard").unwrap();
+    let keyboard_layout = keyboard.next().map(String::from).unwrap();
+    let keyboard_model = take_optional_string(keyboard.next());
+    let keyboard_variant = take_optional_string(keyboard.next());
+    distinst::locale::get_keyboard_layouts()
+        .map_err(|why| DistinstError::KeyboardLayouts { why })?
+        .validate(&keyboard_layout, keyboard_model.as_deref(), keyboard_variant.as_deref())
+        .map_err(|why| DistinstError::InvalidKeyboard { why })?;
+
+    let lang = matches.value_of("lang").unwrap();
+    let remove = matches.value_of("remove").unwrap();
+    let hosts = matches
+        .values_of("host")
+        .map_or_else(|| Ok(Vec::new()), |hosts| hosts.map(parse_host).collect());
+    let additional_locales = matches
+        .values_of("additional-locale")
+        .map_or_else(Vec::new, |locales| locales.map(String::from).collect());
+    let locale_overrides = matches
+        .values_of("locale-override")
+        .map_or_else(|| Ok(Vec::new()), |overrides| overrides.map(parse_locale_override).collect());
+    let flatpak_remotes = matches
+        .values_of("flatpak-remote")
+        .map_or_else(|| Ok(Vec::new()), |remotes| remotes.map(parse_flatpak_remote).collect());
+    let first_boot_services = matches
+        .values_of("first-boot-service")
+        .map_or_else(|| Ok(Vec::new()), |units| units.map(parse_first_boot_service).collect());
+
+    let timezone = matches.value_of("timezone").map(|timezone| {
+        let timezones = Timezones::new().expect("failed to get timezones");
+        timezones
+            .find(timezone)
+            .cloned()
+            .unwrap_or_else(|| panic!("failed to find timezone: {}", timezone))
+    });
+
+    let user_account = matches.value_of("username").map(|username| {
+        let username = username.to_owned();
+        let profile_icon = matches.value_of("profile_icon").map(String::from);
+
+        let realname = matches.value_of("realname").map(String::from);
+        let password = matches.value_of("password").map(String::from).or_else(|| {
+            if unsafe { libc::isatty(0) } == 0 {
+                let mut pass = String::new();
+                io::stdin().read_line(&mut pass).unwrap();
+                pass.pop();
+                Some(pass)
+            } else {
+                None
+            }
+        });
+
+        let ssh_authorized_keys = matches
+            .values_of("ssh-key")
+            .map_or_else(Vec::new, |keys| keys.map(String::from).collect());
+        let shell = matches.value_of("shell").map(String::from);
+        let groups = matches
+            .values_of("groups")
+            .map_or_else(UserAccountCreate::admin_groups, |groups| {
+                groups.filter(|group| !group.is_empty()).map(String::from).collect()
+            });
+
+        UserAccountCreate {
+            realname,
+            username,
+            password,
+            profile_icon,
+            ssh_authorized_keys,
+            shell,
+            groups,
+            system: false,
+            sudo: matches.value_of("sudo").and_then(parse_sudo),
+            backend: if matches.is_present("homed") {
+                UserBackend::Homed
+            } else {
+                UserBackend::Passwd
+            },
+        }
+    });
+
+    Ok(InstallRequest {
+        disks: configure_disks(&DiskLayout::from_matches(matches))?,
+        config: Config {
+            flags:                install_flags(matches),
+            hostname:             hostname.into(),
+            keyboard_layout,
+            keyboard_model,
+            keyboard_variant,
+            old_root:             None,
+            lang:                 lang.into(),
+            additional_locales,
+            locale_overrides:     locale_overrides?,
+            remove:               remove.into(),
+            minimal_remove:       matches.value_of("minimal").map(String::from),
+            squashfs:             squashfs.into(),
+            bios_device:          matches.value_of("bios-device").map(String::from),
+            files:                Vec::new(),
+            dconf:                Vec::new(),
+            hosts:                hosts?,
+            rtc_in_localtime:     matches.value_of("rtc").map(|rtc| rtc == "local"),
+            cleanup:              matches.value_of("cleanup").and_then(parse_cleanup).unwrap(),
+            post_install_scripts: matches
+                .values_of("post-install-script")
+                .map_or(Vec::new(), |scripts| scripts.map(PathBuf::from).collect()),
+            first_boot_services:  first_boot_services?,
+            offline_repository:   matches.value_of("offline-repo").map(|path| OfflineRepository {
+                path:    PathBuf::from(path),
+                keyring: matches.value_of("offline-repo-keyring").map(PathBuf::from),
+            }),
+            flatpak_remotes:      flatpak_remotes?,
+            flatpaks:             matches
+                .values_of("flatpak")
+                .map_or_else(Vec::new, |flatpaks| flatpaks.map(String::from).collect()),
+            kernel_variant:       matches.value_of("kernel-variant").map(String::from),
+            image_verification:   image_verification(matches),
+            extract_verification: extract_verification(matches),
+            file_backup:          file_backup(matches),
+            esp_policy:           matches
+                .value_of("esp-policy")
+                .and_then(parse_esp_policy)
+                .unwrap(),
+            trim_policy:          matches.value_of("trim").and_then(parse_trim_policy).unwrap(),
+            tang_binding:         tang_binding(matches)?,
+            extract_options:      extract_options(matches)?,
+        },
+        timezone,
+        user_accounts: user_account.into_iter().collect(),
+        mok_password: matches.value_of("mok-password").map(String::from),
+    })
+}
+
+fn tang_binding(matches: &ArgMatches) -> Result<Option<TangBinding>, DistinstError> {
+    let url = match matches.value_of("tang-url") {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+
+    let tang = TangBinding {
+        url:        url.into(),
+        thumbprint: matches.value_of("tang-thumbprint").unwrap_or_default().into(),
+    };
+
+    tang.validate().map_err(|why| DistinstError::InvalidTang { why })?;
+    Ok(Some(tang))
+}
+
+fn extract_options(matches: &ArgMatches) -> Result<ExtractOptions, DistinstError> {
+    let threads = match matches.value_of("extract-threads") {
+        Some(threads) => Some(
+            threads
+                .parse::<usize>()
+                .ok()
+                .filter(|&threads| threads != 0)
+                .ok_or_else(|| DistinstError::ArgNaN { arg: threads.into() })?,
+        ),
+        None => None,
+    };
+
+    let io_priority = match matches.value_of("extract-io-priority") {
+        Some(priority) => Some(
+            parse_io_priority(priority)
+                .ok_or_else(|| DistinstError::InvalidIoPriority { priority: priority.into() })?,
+        ),
+        None => None,
+    };
+
+    Ok(ExtractOptions {
+        threads,
+        io_priority,
+        backend: matches.value_of("extract-backend").and_then(parse_extract_backend).unwrap(),
+    })
+}
+
+fn image_verification(matches: &ArgMatches) -> Option<ImageVerification> {
+    if let Some(sums) = matches.value_of("image-sha256sums") {
+        return Some(ImageVerification::Sha256Sums(PathBuf::from(sums)));
+    }
+
+    matches.value_of("image-signature").map(|signature| ImageVerification::Signature {
+        signature: PathBuf::from(signature),
+        keyring:   PathBuf::from(matches.value_of("image-keyring").unwrap()),
+    })
+}
+
+fn extract_verification(matches: &ArgMatches) -> Option<ExtractVerification> {
+    match matches.value_of("extraction-manifest") {
+        Some(manifest) => Some(ExtractVerification::Manifest(PathBuf::from(manifest))),
+        None if matches.is_present("verify-extraction") => Some(ExtractVerification::Image),
+        None => None,
+    }
+}
+
+fn file_backup(matches: &ArgMatches) -> Option<FileBackup> {
+    let paths = matches.values_of("backup-path")?;
+    Some(FileBackup {
+        source:      PathBuf::from(matches.value_of("backup-source")?),
+        paths:       paths.map(PathBuf::from).collect(),
+        destination: PathBuf::from(matches.value_of("backup-destination")?),
+        directory:   PathBuf::from(matches.value_of("backup-directory").unwrap_or("")),
+    })
+}
+
+fn install_flags(matches: &ArgMatches) -> InstallFlags {
+    let mut flags = InstallFlags::empty();
+    flags.set(InstallFlags::MODIFY_BOOT_ORDER, matches.occurrences_of("modify-boot") != 0);
+    flags.set(
+        InstallFlags::INSTALL_HARDWARE_SUPPORT,
+        matches.occurrences_of("hardware-support") != 0,
+    );
+    flags.set(InstallFlags::RUN_UBUNTU_DRIVERS, matches.occurrences_of("run-ubuntu-drivers") != 0);
+    flags.set(InstallFlags::JOURNAL_COMMANDS, matches.occurrences_of("journal-commands") != 0);
+    flags.set(
+        InstallFlags::RENAME_CONFLICTING_VOLUME_GROUPS,
+        matches.occurrences_of("rename-vgs") != 0,
+    );
+    flags.set(InstallFlags::ENABLE_MDNS, matches.occurrences_of("mdns") != 0);
+    flags.set(InstallFlags::READ_ONLY_ROOT, matches.occurrences_of("read-only-root") != 0);
+    flags.set(
+        InstallFlags::SKIP_INITRAMFS_KEYMAP,
+        matches.occurrences_of("no-initramfs-keymap") != 0,
+    );
+    flags.set(InstallFlags::RESTORE_PACKAGES, matches.occurrences_of("restore-packages") != 0);
+    flags.set(InstallFlags::MAKE_MBR_GAP, matches.occurrences_of("make-mbr-gap") != 0);
+    flags
+}
+
+/// Parses a host entry in the `ADDRESS=NAME[,NAME...]` format.
+fn parse_host(host: &str) -> Result<HostEntry, DistinstError> {
+    let invalid = || DistinstError::InvalidHost { host: host.into() };
+    let mut fields = host.splitn(2, '=');
+    let address = fields.next().and_then(|address| address.parse().ok()).ok_or_else(invalid)?;
+    let names = fields
+        .next()
+        .ok_or_else(invalid)?
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    if names.is_empty() || !names.iter().all(|name| distinst::hostname::is_valid(name)) {
+        return Err(invalid());
+    }
+
+    Ok(HostEntry { address, names })
+}
+
+/// Parses a flatpak remote in the `NAME=URL` format.
+fn parse_flatpak_remote(remote: &str) -> Result<FlatpakRemote, DistinstError> {
+    let mut fields = remote.splitn(2, '=');
+    match (fields.next(), fields.next()) {
+        (Some(name), Some(url)) if !name.is_empty() && !url.is_empty() => {
+            Ok(FlatpakRemote { name: name.into(), url: url.into() })
+        }
+        _ => Err(DistinstError::InvalidFlatpakRemote { remote: remote.into() }),
+    }
+}
+
+/// A service unit on the live system, which is named after its file.
+fn parse_first_boot_service(path: &str) -> Result<FirstBootService, DistinstError> {
+    match Path::new(path).file_name().and_then(|name| name.to_str()) {
+        Some(name) if name.ends_with(".service") && Path::new(path).is_file() => {
+            Ok(FirstBootService {
+                name:   name.to_owned(),
+                source: TargetFileSource::Path(PathBuf::from(path)),
+            })
+        }
+        _ => Err(DistinstError::InvalidFirstBootService { path: path.into() }),
+    }
+}
+
+/// Parses a cleanup profile: `none`, `standard`, or `full`.
+pub(crate) fn parse_cleanup(profile: &str) -> Option<CleanupProfile> {
+    match profile {
+        "none" => Some(CleanupProfile::None),
+        "standard" => Some(CleanupProfile::Standard),
+        "full" => Some(CleanupProfile::Full),
+        _ => None,
+    }
+}
+
+/// Parses an ESP policy: `reuse`, `reuse-with-cleanup`, or `format`.
+pub(crate) fn parse_esp_policy(policy: &str) -> Option<EspPolicy> {
+    match policy {
+        "reuse" => Some(EspPolicy::Reuse),
+        "reuse-with-cleanup" => Some(EspPolicy::ReuseWithCleanup),
+        "format" => Some(EspPolicy::Format),
+        _ => None,
+    }
+}
+
+/// Parses a trim policy: `periodic`, `continuous`, or `disabled`.
+pub(crate) fn parse_trim_policy(policy: &str) -> Option<TrimPolicy> {
+    match policy {
+        "periodic" => Some(TrimPolicy::Periodic),
+        "continuous" => Some(TrimPolicy::Continuous),
+        "disabled" => Some(TrimPolicy::Disabled),
+        _ => None,
+    }
+}
+
+/// Parses an I/O priority: `idle`, `best-effort[:LEVEL]`, or `realtime[:LEVEL]`, where the
+/// level defaults to 4.
+pub(crate) fn parse_io_priority(priority: &str) -> Option<IoPriority> {
+    let mut fields = priority.splitn(2, ':');
+    let class = fields.next()?;
+    let level = match fields.next() {
+        Some(_) if class == "idle" => return None,
+        Some(level) => level.parse::<u8>().ok().filter(|&level| level <= 7)?,
+        None => 4,
+    };
+
+    match class {
+        "idle" => Some(IoPriority::Idle),
+        "best-effort" => Some(IoPriority::BestEffort(level)),
+        "realtime" => Some(IoPriority::Realtime(level)),
+        _ => None,
+    }
+}
+
+/// Parses an extraction backend: `auto`, `unsquashfs`, or `in-process`.
+pub(crate) fn parse_extract_backend(backend: &str) -> Option<ExtractBackend> {
+    match backend {
+        "auto" => Some(ExtractBackend::Auto),
+        "unsquashfs" => Some(ExtractBackend::Unsquashfs),
+        "in-process" => Some(ExtractBackend::InProcess),
+        _ => None,
+    }
+}
+
+/// Parses the access to sudo which is granted to a user, as `password` or `nopasswd`.
+pub(crate) fn parse_sudo(access: &str) -> Option<SudoAccess> {
+    match access {
+        "password" => Some(SudoAccess::Password),
+        "nopasswd" => Some(SudoAccess::NoPassword),
+        _ => None,
+    }
+}
+
+/// Parses a locale override in the `CATEGORY=LOCALE` format.
+fn parse_locale_override(value: &str) -> Result<(String, String), DistinstError> {
+    let mut fields = value.splitn(2, '=');
+    match (fields.next(), fields.next()) {
+        (Some(category), Some(locale))
+            if distinst::LOCALE_CATEGORIES.contains(&category) && !locale.is_empty() =>
+        {
+            Ok((category.to_owned(), locale.to_owned()))
+        }
+        _ => Err(DistinstError::InvalidLocaleOverride { value: value.into() }),
+    }
+}
@@ -0,0 +1,1119 @@
+//! Unattended installations driven by a TOML preseed (answers) file.
+//!
+//! The entire document is parsed and validated before the installer is started, so that a
+//! mistake in the answers file will never leave a disk in a half-configured state.
+//!
+//! ```toml
+//! squashfs = "/cdrom/casper/filesystem.squashfs"
+//! remove = "/cdrom/casper/filesystem.manifest-remove"
+//! minimal = "/cdrom/casper/filesystem.manifest-minimal-remove"
+//! hostname = "pop-os"
+//! lang = "en_US.UTF-8"
+//! timezone = "America/Denver"
+//!
+//! [keyboard]
+//! layout = "us"
+//!
+//! [user]
+//! username = "user"
+//! realname = "User"
+//! password = "password"
+//!
+//! [flags]
+//! modify_boot = true
+//! hardware_support = true
+//!
+//! [disks]
+//! blocks = ["/dev/sda"]
+//! tables = ["/dev/sda:gpt"]
+//! new = [
+//!     "/dev/sda:primary:start:512M:fat32:mount=/boot/efi:flags=esp",
+//!     "/dev/sda:primary:512M:end:ext4:mount=/:label=root",
+//! ]
+//! ```
+//!
+//! Files may be written into the installed system, from either their `contents` or a `source`
+//! file on the live system. The `mode` is in octal, and defaults to `644`.
+//!
+//! ```toml
+//! [[files]]
+//! path = "/etc/oem/eula-accepted"
+//! contents = "1"
+//!
+//! [[files]]
+//! path = "/usr/local/share/ca-certificates/fleet.crt"
+//! source = "/cdrom/oem/fleet.crt"
+//! mode = "600"
+//! owner = "root:root"
+//! ```
+//!
+//! Default dconf settings for every user are keyed by their absolute path, with values in the
+//! GVariant text format.
+//!
+//! ```toml
+//! [dconf]
+//! "/org/gnome/desktop/background/picture-uri" = "'file:///usr/share/backgrounds/oem.jpg'"
+//! "/org/gnome/shell/favorite-apps" = "['firefox.desktop', 'org.gnome.Nautilus.desktop']"
+//! ```
+//!
+//! Additional entries may be added to the hosts file, and `.local` names can be resolved with
+//! multicast DNS by setting the `mdns` flag.
+//!
+//! ```toml
+//! [[hosts]]
+//! address = "192.168.1.10"
+//! names = ["nas", "nas.office.lan"]
+//! ```
+//!
+//! When Secure Boot is enabled, the DKMS modules which are installed by `run_ubuntu_drivers`
+//! are signed with a key that is enrolled on the next boot, once the user enters the
+//! `mok_password`.
+//!
+//! Locales of other languages may be generated, which messages fall back to when they have not
+//! been translated to `lang`. Locale categories may also take their formats from another
+//! locale, such as English messages with German dates.
+//!
+//! ```toml
+//! additional_locales = ["de_DE.UTF-8"]
+//!
+//! [locale_overrides]
+//! LC_TIME = "de_DE.UTF-8"
+//! LC_PAPER = "de_DE.UTF-8"
+//! ```
+//!
+//! Servers and headless systems may be reached over SSH as soon as they have booted, by
+//! authorizing public keys for the user, which also installs the SSH server.
+//!
+//! ```toml
+//! [user]
+//! username = "admin"
+//! shell = "/usr/bin/zsh"
+//! ssh_authorized_keys = ["ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIG4n admin@laptop"]
+//! ```
+//!
+//! Further accounts may be created with `[[users]]`, which are standard users when their
+//! `groups` do not include the administrator groups, `adm`, `sudo`, and `lpadmin`, that are
+//! given to users by default. Services may be given a `system` account without a home, which
+//! is not a member of any group by default, and may not be an administrator.
+//!
+//! ```toml
+//! [[users]]
+//! username = "student"
+//! password = "password"
+//! groups = ["audio", "video"]
+//!
+//! [[users]]
+//! username = "backup"
+//! system = true
+//! ```
+//!
+//! Kiosks and automation may grant a user `sudo` without a password, with `sudo = "nopasswd"`,
+//! or with their password, with `sudo = "password"`, regardless of their groups.
+//!
+//! With `homed = true`, a user is created by systemd-homed on the first boot, and their home is
+//! a LUKS-encrypted image which is unlocked with their password, which is then required.
+//!
+//! The hardware clock keeps local time when Windows is found on the system, as Windows expects,
+//! or else UTC. This may be decided by setting `rtc_in_localtime = true` or `false`.
+//!
+//! Setting `cleanup = "standard"` removes the packages which apt downloaded, its caches,
+//! journals, and temporary files from the installed system. `"full"` also removes the package
+//! lists of apt, for systems whose sources will change on their first boot.
+//!
+//! Scripts on the live system may be executed within the installed system once it has been
+//! configured, in order, with `post_install_scripts = ["/cdrom/preseed/enroll.sh"]`. They are
+//! given the `DISTINST_ROOT_UUID`, `DISTINST_ESP`, `DISTINST_USERNAME`, and `DISTINST_HOSTNAME`
+//! environment variables, and the installation fails if one of them fails.
+//!
+//! Services which run once, on the first boot of the installed system, such as an enrollment,
+//! are given by their unit's `name`, and either its `contents` or a `source` to copy it from.
+//!
+//! ```toml
+//! [[first_boot_services]]
+//! name = "oem-enroll.service"
+//! source = "/cdrom/preseed/oem-enroll.service"
+//! ```
+//!
+//! Packages may be installed on machines without a network from a flat apt repository, which
+//! has a `Packages` index at its top. It is trusted, unless a `keyring` is given to verify it.
+//!
+//! ```toml
+//! [offline_repository]
+//! path = "/cdrom/offline"
+//! keyring = "/cdrom/offline/archive-keyring.gpg"
+//! ```
+//!
+//! Flatpaks are installed system-wide by their application ID, from remotes that are added to
+//! the installed system:
+//!
+//! ```toml
+//! flatpaks = ["org.gnome.Calculator"]
+//!
+//! [[flatpak_remotes]]
+//! name = "flathub"
+//! url = "https://dl.flathub.org/repo/flathub.flatpakrepo"
+//! ```
+//!
+//! Kiosks and appliances may set the `read_only_root` flag, which mounts the root file system
+//! as read-only, and discards changes to `/etc` and `/var` on each boot.
+//!
+//! When encrypted volumes are unlocked while booting, the keymap of the keyboard layout is
+//! included in the initramfs, unless the `no_initramfs_keymap` flag is set.
+//!
+//! On a refresh install, the `restore_packages` flag reinstalls the packages which were
+//! manually installed on the replaced system, where the repositories of the new system
+//! provide them.
+//!
+//! On BIOS systems, the `make_mbr_gap` flag moves the first partition of a MSDOS disk when
+//! the gap before it is too small to embed GRUB.
+//!
+//! The image may be verified before the disks are modified, against either its entry in a
+//! `SHA256SUMS` file, or a detached signature:
+//!
+//! ```toml
+//! [image_verification]
+//! signature = "/cdrom/casper/filesystem.squashfs.gpg"
+//! keyring = "/usr/share/keyrings/pop-archive-keyring.gpg"
+//! ```
+//!
+//! The extracted files may be compared with the files of the image by setting
+//! `verify_extraction = "image"`, or with the digests of a manifest by giving its path.
+//!
+//! Files may be copied to a partition which the install leaves untouched, such as a second
+//! drive, before the disks are modified:
+//!
+//! ```toml
+//! [backup]
+//! source = "/dev/sda3"
+//! paths = ["/home/user/Documents"]
+//! destination = "/dev/sdb1"
+//! directory = "backup"
+//! ```
+//!
+//! An existing ESP may be formatted by the disk configuration unless `esp_policy` is set to
+//! `"reuse"`, in which case the installation fails before the disks are modified if the ESP
+//! lacks room for the boot files, or `"reuse-with-cleanup"`, which first removes the boot files
+//! of systems which no longer exist.
+//!
+//! File systems on disks which support discard, such as SSDs, are trimmed weekly by
+//! `fstrim.timer`, unless `trim` is set to `"continuous"`, which mounts them with `discard`, or
+//! to `"disabled"`.
+//!
+//! The threads which decompress the image, the I/O scheduling class of the extraction, and what
+//! extracts a squashfs image may be set, instead of being tuned to the machine:
+//!
+//! ```toml
+//! [extract]
+//! threads = 2
+//! io_priority = "best-effort:7"
+//! backend = "in-process"
+//! ```
+//!
+//! Installation media with several kernels in its casper directory, such as `hwe-vmlinuz`, may
+//! select one with `kernel_variant = "hwe"`. The generic kernel is otherwise installed.
+//!
+//! Encrypted volumes may be bound to a Tang server with clevis, so that the initramfs unlocks
+//! them while the server can be reached. The keys that the server advertises are verified with
+//! the `thumbprint` of its signing key, which is required:
+//!
+//! ```toml
+//! [tang]
+//! url = "http://tang.example.com"
+//! thumbprint = "x100_1k6GPiDOaMlL3WbpCjHOy9u"
+//! ```
+
+use super::*;
+use distinst::{
+    auto::FileBackup,
+    timezones::{Region, Timezones},
+};
+use errors::DistinstError;
+use install::{
+    parse_cleanup, parse_esp_policy, parse_extract_backend, parse_io_priority, parse_sudo,
+    parse_trim_policy, InstallRequest,
+};
+use std::{collections::BTreeMap, fs};
+
+/// The answers supplied by a preseed file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Preseed {
+    squashfs:             String,
+    remove:               String,
+    /// A manifest of additional packages to remove for a minimal installation.
+    minimal:              Option<String>,
+    hostname:             String,
+    #[serde(default = "default_lang")]
+    lang:                 String,
+    #[serde(default)]
+    additional_locales:   Vec<String>,
+    /// Locale categories, such as `LC_TIME`, and the locales that they take their formats from.
+    #[serde(default)]
+    locale_overrides:     BTreeMap<String, String>,
+    /// A timezone in the `Zone/Region` format, such as `America/Denver`.
+    timezone:             Option<String>,
+    /// The device that GRUB will be installed to on BIOS systems.
+    bios_device:          Option<String>,
+    #[serde(default)]
+    keyboard:             PreseedKeyboard,
+    user:                 Option<PreseedUser>,
+    /// Accounts which are created in addition to the `user`.
+    #[serde(default)]
+    users:                Vec<PreseedUser>,
+    /// The password which enrolls the key that DKMS modules are signed with, when Secure Boot
+    /// is enabled and `run_ubuntu_drivers` is set.
+    mok_password:         Option<String>,
+    #[serde(default)]
+    flags:                PreseedFlags,
+    disks:                PreseedDisks,
+    #[serde(default)]
+    files:                Vec<PreseedFile>,
+    #[serde(default)]
+    dconf:                BTreeMap<String, String>,
+    #[serde(default)]
+    hosts:                Vec<PreseedHost>,
+    /// Whether the hardware clock keeps local time, rather than UTC. If not set, it keeps local
+    /// time when Windows is found on the system.
+    rtc_in_localtime:     Option<bool>,
+    /// What is removed from the installed system: `none`, `standard`, or `full`.
+    cleanup:              Option<String>,
+    /// Scripts which are executed within the installed system once it has been configured.
+    #[serde(default)]
+    post_install_scripts: Vec<String>,
+    /// Services which run once, on the first boot of the installed system.
+    #[serde(default)]
+    first_boot_services:  Vec<PreseedService>,
+    /// A flat apt repository which packages are installed from while installing.
+    offline_repository:   Option<PreseedRepository>,
+    #[serde(default)]
+    flatpak_remotes:      Vec<PreseedFlatpakRemote>,
+    /// Flatpaks which are installed system-wide, by their application ID or ref.
+    #[serde(default)]
+    flatpaks:             Vec<String>,
+    /// The variant of the kernel in the casper directory, such as `hwe`.
+    kernel_variant:       Option<String>,
+    /// Verifies the image before the disks are modified.
+    image_verification:   Option<PreseedVerification>,
+    /// Either `image`, or the path of a manifest which the extracted files are compared with.
+    verify_extraction:    Option<String>,
+    /// Files which are copied to another partition before the disks are modified.
+    backup:               Option<PreseedBackup>,
+    /// Whether an existing ESP may be formatted: `reuse`, `reuse-with-cleanup`, or `format`.
+    esp_policy:           Option<String>,
+    /// How file systems on disks which support discard are trimmed: `periodic`, `continuous`,
+    /// or `disabled`.
+    trim:                 Option<String>,
+    /// A Tang server which the encrypted volumes are bound to.
+    tang:                 Option<PreseedTang>,
+    /// How the image is extracted, which is otherwise tuned to the machine.
+    extract:              Option<PreseedExtract>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PreseedKeyboard {
+    layout:  String,
+    model:   Option<String>,
+    variant: Option<String>,
+}
+
+impl Default for PreseedKeyboard {
+    fn default() -> Self { PreseedKeyboard { layout: "us".into(), model: None, variant: None } }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PreseedUser {
+    username:            String,
+    realname:            Option<String>,
+    password:            Option<String>,
+    profile_icon:        Option<String>,
+    /// Public keys which may log in as the user over SSH.
+    #[serde(default)]
+    ssh_authorized_keys: Vec<String>,
+    /// The login shell of the user, instead of `/bin/bash`.
+    shell:               Option<String>,
+    /// Supplementary groups of the user, instead of the administrator groups, or of none for a
+    /// system account.
+    groups:              Option<Vec<String>>,
+    #[serde(default)]
+    system:              bool,
+    /// Grants `sudo` in a sudoers.d drop-in: `password`, or `nopasswd`.
+    sudo:                Option<String>,
+    /// Creates the user with systemd-homed, whose home is encrypted with their password.
+    #[serde(default)]
+    homed:               bool,
+}
+
+impl PreseedUser {
+    fn account(&self) -> UserAccountCreate {
+        UserAccountCreate {
+            username:            self.username.clone(),
+            realname:            self.realname.clone(),
+            password:            self.password.clone(),
+            profile_icon:        self.profile_icon.clone(),
+            ssh_authorized_keys: self.ssh_authorized_keys.clone(),
+            shell:               self.shell.clone(),
+            groups:              self
+                .groups
+                .clone()
+                .unwrap_or_else(|| UserAccountCreate::default_groups(self.system)),
+            system:              self.system,
+            sudo:                self.sudo.as_deref().and_then(parse_sudo),
+            backend:             if self.homed { UserBackend::Homed } else { UserBackend::Passwd },
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PreseedFlags {
+    modify_boot:         bool,
+    hardware_support:    bool,
+    run_ubuntu_drivers:  bool,
+    journal_commands:    bool,
+    rename_vgs:          bool,
+    mdns:                bool,
+    read_only_root:      bool,
+    no_initramfs_keymap: bool,
+    restore_packages:    bool,
+    make_mbr_gap:        bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PreseedFile {
+    path:     String,
+    #[serde(default = "default_mode")]
+    mode:     String,
+    owner:    Option<String>,
+    contents: Option<String>,
+    source:   Option<String>,
+}
+
+impl PreseedFile {
+    fn target_file(&self) -> Result<TargetFile, String> {
+        if !self.path.starts_with('/') {
+            return Err(format!("file path is not absolute: '{}'", self.path));
+        }
+
+        let mode = u32::from_str_radix(&self.mode, 8)
+            .ok()
+            .filter(|&mode| mode <= 0o7777)
+            .ok_or_else(|| format!("file mode for '{}' is invalid: '{}'", self.path, self.mode))?;
+
+        let source = match (&self.contents, &self.source) {
+            (Some(contents), None) => TargetFileSource::Contents(contents.clone().into_bytes()),
+            (None, Some(source)) if Path::new(source).exists() => {
+                TargetFileSource::Path(PathBuf::from(source))
+            }
+            (None, Some(source)) => {
+                return Err(format!("source of '{}' does not exist at '{}'", self.path, source))
+            }
+            _ => {
+                return Err(format!(
+                    "file '{}' must have either contents or a source, but not both",
+                    self.path
+                ))
+            }
+        };
+
+        Ok(TargetFile {
+            path:  PathBuf::from(&self.path),
+            mode,
+            owner: self.owner.clone(),
+            source,
+        })
+    }
+}
+
+/// A flatpak remote, which is described by the `.flatpakrepo` file at its `url`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PreseedFlatpakRemote {
+    name: String,
+    url:  String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PreseedRepository {
+    path:    String,
+    /// The keyring which verifies the repository, which is otherwise trusted.
+    keyring: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PreseedTang {
+    url:        String,
+    /// The thumbprint of the server's signing key.
+    thumbprint: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PreseedBackup {
+    source:      String,
+    paths:       Vec<String>,
+    destination: String,
+    /// The directory within the destination, which is otherwise its root.
+    #[serde(default)]
+    directory:   String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PreseedExtract {
+    /// The number of threads which decompress the image.
+    threads:     Option<usize>,
+    /// `idle`, `best-effort[:LEVEL]`, or `realtime[:LEVEL]`.
+    io_priority: Option<String>,
+    /// `auto`, `unsquashfs`, or `in-process`.
+    backend:     Option<String>,
+}
+
+impl PreseedExtract {
+    fn options(&self) -> Result<ExtractOptions, String> {
+        if self.threads == Some(0) {
+            return Err("extract threads must be greater than zero".into());
+        }
+
+        let io_priority = match self.io_priority {
+            Some(ref priority) => Some(parse_io_priority(priority).ok_or_else(|| {
+                format!(
+                    "extract io_priority must be idle, best-effort[:LEVEL], or \
+                     realtime[:LEVEL], not '{}'",
+                    priority
+                )
+            })?),
+            None => None,
+        };
+
+        let backend = match self.backend {
+            Some(ref backend) => parse_extract_backend(backend).ok_or_else(|| {
+                format!(
+                    "extract backend must be auto, unsquashfs, or in-process, not '{}'",
+                    backend
+                )
+            })?,
+            None => ExtractBackend::Auto,
+        };
+
+        Ok(ExtractOptions { threads: self.threads, io_priority, backend })
+    }
+}
+
+/// Either a `SHA256SUMS` file, or a detached signature and the keyring which verifies it.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PreseedVerification {
+    sha256sums: Option<String>,
+    signature:  Option<String>,
+    keyring:    Option<String>,
+}
+
+impl PreseedVerification {
+    fn verification(&self) -> Result<ImageVerification, String> {
+        let exists = |path: &str| {
+            if Path::new(path).is_file() {
+                Ok(PathBuf::from(path))
+            } else {
+                Err(format!("image verification file does not exist at '{}'", path))
+            }
+        };
+
+        match (&self.sha256sums, &self.signature, &self.keyring) {
+            (Some(sums), None, None) => exists(sums).map(ImageVerification::Sha256Sums),
+            (None, Some(signature), Some(keyring)) => Ok(ImageVerification::Signature {
+                signature: exists(signature)?,
+                keyring:   exists(keyring)?,
+            }),
+            _ => Err("image verification needs sha256sums, or a signature and keyring".into()),
+        }
+    }
+}
+
+/// A service unit, whose text is either given as its `contents`, or copied from its `source`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PreseedService {
+    name:     String,
+    contents: Option<String>,
+    source:   Option<String>,
+}
+
+impl PreseedService {
+    fn service(&self) -> Result<FirstBootService, String> {
+        if !self.name.ends_with(".service") || self.name.contains('/') {
+            return Err(format!("first boot service is not a service unit: '{}'", self.name));
+        }
+
+        let source = match (&self.contents, &self.source) {
+            (Some(contents), None) => TargetFileSource::Contents(contents.clone().into_bytes()),
+            (None, Some(source)) if Path::new(source).exists() => {
+                TargetFileSource::Path(PathBuf::from(source))
+            }
+            (None, Some(source)) => {
+                return Err(format!("source of '{}' does not exist at '{}'", self.name, source))
+            }
+            _ => {
+                return Err(format!(
+                    "service '{}' must have either contents or a source, but not both",
+                    self.name
+                ))
+            }
+        };
+
+        Ok(FirstBootService { name: self.name.clone(), source })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PreseedHost {
+    address: String,
+    names:   Vec<String>,
+}
+
+impl PreseedHost {
+    fn host_entry(&self) -> Result<HostEntry, String> {
+        let address = self
+            .address
+            .parse()
+            .map_err(|_| format!("host address is invalid: '{}'", self.address))?;
+
+        if self.names.is_empty() {
+            return Err(format!("host entry for '{}' has no names", self.address));
+        }
+
+        if let Some(name) = self.names.iter().find(|name| !distinst::hostname::is_valid(name)) {
+            return Err(format!("host name for '{}' is invalid: '{}'", self.address, name));
+        }
+
+        Ok(HostEntry { address, names: self.names.clone() })
+    }
+}
+
+/// The disk layout, where each value uses the same syntax as its command line equivalent.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PreseedDisks {
+    blocks:             Vec<String>,
+    tables:             Vec<String>,
+    delete:             Vec<String>,
+    #[serde(rename = "move")]
+    moved:              Vec<String>,
+    #[serde(rename = "use")]
+    reuse:              Vec<String>,
+    new:                Vec<String>,
+    decrypt:            Vec<String>,
+    logical:            Vec<String>,
+    logical_modify:     Vec<String>,
+    logical_remove:     Vec<String>,
+    logical_remove_all: bool,
+}
+
+fn default_lang() -> String { "en_US.UTF-8".into() }
+
+fn default_mode() -> String { "644".into() }
+
+impl Preseed {
+    pub fn from_path(path: &Path) -> Result<Self, DistinstError> {
+        let data = fs::read_to_string(path)
+            .map_err(|why| DistinstError::PreseedRead { path: path.to_path_buf(), why })?;
+
+        Self::parse(&data)
+    }
+
+    pub fn parse(data: &str) -> Result<Self, DistinstError> {
+        toml::from_str(data).map_err(|why| DistinstError::PreseedParse { why })
+    }
+
+    /// Validates every answer in the document, and generates an install request from them.
+    ///
+    /// All issues that were found are reported at once. No disk is modified by this method.
+    pub fn prepare(&self) -> Result<InstallRequest, DistinstError> {
+        let mut issues = Vec::new();
+
+        let paths = [
+            Some(("squashfs", &self.squashfs)),
+            Some(("remove", &self.remove)),
+            self.minimal.as_ref().map(|path| ("minimal", path)),
+        ];
+
+        for &(name, path) in paths.iter().flatten() {
+            if !Path::new(path).exists() {
+                issues.push(format!("{} file does not exist at '{}'", name, path));
+            }
+        }
+
+        if !distinst::hostname::is_valid(&self.hostname) {
+            issues.push(format!("hostname is invalid: '{}'", self.hostname));
+        }
+
+        let lang_code = self.lang.split(|c: char| c == '_' || c == '.').next().unwrap_or("");
+        if !distinst::locale::get_language_codes().contains(&lang_code) {
+            issues.push(format!("language is not supported: '{}'", self.lang));
+        }
+
+        for locale in self.additional_locales.iter().chain(self.locale_overrides.values()) {
+            let code = locale.split(|c: char| c == '_' || c == '.').next().unwrap_or("");
+            if !distinst::locale::get_language_codes().contains(&code) {
+                issues.push(format!("locale is not supported: '{}'", locale));
+            }
+        }
+
+        for category in self.locale_overrides.keys() {
+            if !distinst::LOCALE_CATEGORIES.contains(&category.as_str()) {
+                issues.push(format!("locale category may not be overridden: '{}'", category));
+            }
+        }
+
+        if let Err(why) = self.validate_keyboard() {
+            issues.push(why);
+        }
+
+        let timezone = match self.timezone.as_ref().map(|tz| find_timezone(tz)) {
+            Some(Ok(region)) => Some(region),
+            Some(Err(why)) => {
+                issues.push(why);
+                None
+            }
+            None => None,
+        };
+
+        let mut usernames = Vec::new();
+        for user in self.user.iter().chain(&self.users) {
+            if usernames.contains(&&user.username) {
+                issues.push(format!("user account '{}' is defined more than once", user.username));
+            }
+
+            usernames.push(&user.username);
+            if user.username.is_empty() {
+                issues.push("user account has an empty username".into());
+            }
+
+            if let Some(ref icon) = user.profile_icon {
+                if !Path::new(icon).exists() {
+                    issues.push(format!("profile icon does not exist at '{}'", icon));
+                }
+            }
+
+            if let Some(ref shell) = user.shell {
+                if !Path::new(shell).is_absolute() {
+                    issues.push(format!("login shell '{}' is not an absolute path", shell));
+                }
+            }
+
+            if user.homed && user.password.as_ref().map_or(true, String::is_empty) {
+                issues.push(format!("homed user '{}' requires a password", user.username));
+            }
+
+            if let Some(ref sudo) = user.sudo {
+                if parse_sudo(sudo).is_none() {
+                    issues.push(format!("sudo must be 'password' or 'nopasswd', not '{}'", sudo));
+                }
+            }
+        }
+
+        let cleanup = self.cleanup.as_ref().and_then(|profile| {
+            let cleanup = parse_cleanup(profile);
+            if cleanup.is_none() {
+                issues.push(format!("cleanup must be none, standard, or full, not '{}'", profile));
+            }
+
+            cleanup
+        });
+
+        let esp_policy = self.esp_policy.as_ref().and_then(|policy| {
+            let esp_policy = parse_esp_policy(policy);
+            if esp_policy.is_none() {
+                issues.push(format!(
+                    "esp_policy must be reuse, reuse-with-cleanup, or format, not '{}'",
+                    policy
+                ));
+            }
+
+            esp_policy
+        });
+
+        let trim_policy = self.trim.as_ref().and_then(|policy| {
+            let trim_policy = parse_trim_policy(policy);
+            if trim_policy.is_none() {
+                issues.push(format!(
+                    "trim must be periodic, continuous, or disabled, not '{}'",
+                    policy
+                ));
+            }
+
+            trim_policy
+        });
+
+        let extract_options = match self.extract.as_ref().map(PreseedExtract::options) {
+            Some(Ok(options)) => options,
+            Some(Err(why)) => {
+                issues.push(why);
+                ExtractOptions::default()
+            }
+            None => ExtractOptions::default(),
+        };
+
+        let tang_binding = self.tang.as_ref().and_then(|tang| {
+            let tang = TangBinding { url: tang.url.clone(), thumbprint: tang.thumbprint.clone() };
+            tang.validate().map_err(|why| issues.push(why.to_string())).ok().map(|_| tang)
+        });
+
+        let missing_scripts =
+            self.post_install_scripts.iter().filter(|script| !Path::new(script).is_file());
+        for script in missing_scripts {
+            issues.push(format!("post-install script does not exist at '{}'", script));
+        }
+
+        let files = self
+            .files
+            .iter()
+            .filter_map(|file| file.target_file().map_err(|why| issues.push(why)).ok())
+            .collect::<Vec<_>>();
+
+        let image_verification = self.image_verification.as_ref().and_then(|verification| {
+            verification.verification().map_err(|why| issues.push(why)).ok()
+        });
+
+        let extract_verification = self.verify_extraction.as_ref().and_then(|verification| {
+            if verification == "image" {
+                Some(ExtractVerification::Image)
+            } else if Path::new(verification).is_file() {
+                Some(ExtractVerification::Manifest(PathBuf::from(verification)))
+            } else {
+                issues.push(format!("extraction manifest does not exist at '{}'", verification));
+                None
+            }
+        });
+
+        let file_backup = self.backup.as_ref().and_then(|backup| {
+            if backup.paths.is_empty() {
+                issues.push("backup has no paths to copy".into());
+                return None;
+            }
+
+            Some(FileBackup {
+                source:      PathBuf::from(&backup.source),
+                paths:       backup.paths.iter().map(PathBuf::from).collect(),
+                destination: PathBuf::from(&backup.destination),
+                directory:   PathBuf::from(&backup.directory),
+            })
+        });
+
+        if let Some(ref repo) = self.offline_repository {
+            if !Path::new(&repo.path).is_dir() {
+                issues.push(format!("offline repository does not exist at '{}'", repo.path));
+            }
+
+            if let Some(ref keyring) = repo.keyring {
+                if !Path::new(keyring).is_file() {
+                    issues.push(format!("offline keyring does not exist at '{}'", keyring));
+                }
+            }
+        }
+
+        for remote in &self.flatpak_remotes {
+            if remote.name.is_empty() || remote.url.is_empty() {
+                issues.push(format!("flatpak remote '{}' must have a name and a url", remote.name));
+            }
+        }
+
+        let first_boot_services = self
+            .first_boot_services
+            .iter()
+            .filter_map(|service| service.service().map_err(|why| issues.push(why)).ok())
+            .collect::<Vec<_>>();
+
+        let hosts = self
+            .hosts
+            .iter()
+            .filter_map(|host| host.host_entry().map_err(|why| issues.push(why)).ok())
+            .collect::<Vec<_>>();
+
+        for key in self.dconf.keys().filter(|key| !key.starts_with('/') || key.ends_with('/')) {
+            issues.push(format!("dconf key is not an absolute path to a key: '{}'", key));
+        }
+
+        let disks = if self.disks.blocks.is_empty() {
+            issues.push("no disks were defined in the disk layout".into());
+            None
+        } else {
+            match configure_disks(&self.disk_layout()) {
+                Ok(disks) => {
+                    let root_minimum = minimum_root_size(Path::new(&self.squashfs));
+                    let invalid = disks.verify_partitions(Bootloader::detect(), root_minimum);
+                    if invalid.is_empty() {
+                        Some(disks)
+                    } else {
+                        for issue in invalid {
+                            issues.push(format!("disk layout is invalid: {}", issue));
+                        }
+                        None
+                    }
+                }
+                Err(why) => {
+                    issues.push(format!("disk layout is invalid: {}", why));
+                    None
+                }
+            }
+        };
+
+        if !issues.is_empty() {
+            return Err(DistinstError::PreseedInvalid { issues: issues.join("\n    ") });
+        }
+
+        Ok(InstallRequest {
+            disks: disks.expect("disks were validated"),
+            config: Config {
+                flags:                self.install_flags(),
+                hostname:             self.hostname.clone(),
+                keyboard_layout:      self.keyboard.layout.clone(),
+                keyboard_model:       self.keyboard.model.clone(),
+                keyboard_variant:     self.keyboard.variant.clone(),
+                old_root:             None,
+                lang:                 self.lang.clone(),
+                additional_locales:   self.additional_locales.clone(),
+                locale_overrides:     self.locale_overrides.clone().into_iter().collect(),
+                remove:               self.remove.clone(),
+                minimal_remove:       self.minimal.clone(),
+                squashfs:             self.squashfs.clone(),
+                bios_device:          self.bios_device.clone(),
+                files,
+                dconf:                self.dconf.clone().into_iter().collect(),
+                hosts,
+                rtc_in_localtime:     self.rtc_in_localtime,
+                cleanup:              cleanup.unwrap_or_default(),
+                post_install_scripts: self.post_install_scripts.iter().map(PathBuf::from).collect(),
+                first_boot_services,
+                offline_repository:   self.offline_repository.as_ref().map(|repo| {
+                    OfflineRepository {
+                        path:    PathBuf::from(&repo.path),
+                        keyring: repo.keyring.as_ref().map(PathBuf::from),
+                    }
+                }),
+                flatpak_remotes:      self
+                    .flatpak_remotes
+                    .iter()
+                    .map(|remote| FlatpakRemote {
+                        name: remote.name.clone(),
+                        url:  remote.url.clone(),
+                    })
+                    .collect(),
+                flatpaks:             self.flatpaks.clone(),
+                kernel_variant:       self.kernel_variant.clone(),
+                image_verification,
+                extract_verification,
+                file_backup,
+                esp_policy:           esp_policy.unwrap_or_default(),
+                trim_policy:          trim_policy.unwrap_or_default(),
+                tang_binding,
+                extract_options,
+            },
+            timezone,
+            user_accounts: self.user.iter().chain(&self.users).map(PreseedUser::account).collect(),
+            mok_password: self.mok_password.clone(),
+        })
+    }
+
+    fn disk_layout(&self) -> DiskLayout {
+        fn values(values: &[String]) -> Vec<&str> { values.iter().map(String::as_str).collect() }
+
+        let disks = &self.disks;
+
+        DiskLayout {
+            blocks:             values(&disks.blocks),
+            tables:             values(&disks.tables),
+            delete:             values(&disks.delete),
+            moved:              values(&disks.moved),
+            reuse:              values(&disks.reuse),
+            new:                values(&disks.new),
+            decrypt:            values(&disks.decrypt),
+            logical:            values(&disks.logical),
+            logical_modify:     values(&disks.logical_modify),
+            logical_remove:     values(&disks.logical_remove),
+            logical_remove_all: disks.logical_remove_all,
+        }
+    }
+
+    fn install_flags(&self) -> InstallFlags {
+        let mut flags = InstallFlags::empty();
+        flags.set(InstallFlags::MODIFY_BOOT_ORDER, self.flags.modify_boot);
+        flags.set(InstallFlags::INSTALL_HARDWARE_SUPPORT, self.flags.hardware_support);
+        flags.set(InstallFlags::RUN_UBUNTU_DRIVERS, self.flags.run_ubuntu_drivers);
+        flags.set(InstallFlags::JOURNAL_COMMANDS, self.flags.journal_commands);
+        flags.set(InstallFlags::RENAME_CONFLICTING_VOLUME_GROUPS, self.flags.rename_vgs);
+        flags.set(InstallFlags::ENABLE_MDNS, self.flags.mdns);
+        flags.set(InstallFlags::READ_ONLY_ROOT, self.flags.read_only_root);
+        flags.set(InstallFlags::SKIP_INITRAMFS_KEYMAP, self.flags.no_initramfs_keymap);
+        flags.set(InstallFlags::RESTORE_PACKAGES, self.flags.restore_packages);
+        flags.set(InstallFlags::MAKE_MBR_GAP, self.flags.make_mbr_gap);
+        flags
+    }
+
+    fn validate_keyboard(&self) -> Result<(), String> {
+        let layouts = distinst::locale::get_keyboard_layouts()
+            .map_err(|why| format!("unable to read keyboard layouts: {}", why))?;
+
+        let keyboard = &self.keyboard;
+        layouts
+            .validate(&keyboard.layout, keyboard.model.as_deref(), keyboard.variant.as_deref())
+            .map_err(|why| why.to_string())
+    }
+}
+
+fn find_timezone(timezone: &str) -> Result<Region, String> {
+    if !timezone.contains('/') {
+        return Err(format!("timezone must be in the Zone/Region format: '{}'", timezone));
+    }
+
+    let timezones = Timezones::new().map_err(|why| format!("unable to read timezones: {}", why))?;
+
+    timezones
+        .find(timezone)
+        .cloned()
+        .ok_or_else(|| format!("timezone was not found: '{}'", timezone))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRESEED: &str = r#"
+squashfs = "/cdrom/casper/filesystem.squashfs"
+remove = "/cdrom/casper/filesystem.manifest-remove"
+hostname = "pop-os"
+timezone = "America/Denver"
+
+[user]
+username = "user"
+
+[flags]
+modify_boot = true
+
+[disks]
+blocks = ["/dev/sda"]
+tables = ["/dev/sda:gpt"]
+use = ["/dev/sda:1:reuse:mount=/boot/efi"]
+new = ["/dev/sda:primary:512M:end:ext4:mount=/"]
+"#;
+
+    #[test]
+    fn parse() {
+        let preseed = Preseed::parse(PRESEED).unwrap();
+        assert_eq!(preseed.lang, "en_US.UTF-8");
+        assert_eq!(preseed.keyboard.layout, "us");
+        assert_eq!(preseed.install_flags(), InstallFlags::MODIFY_BOOT_ORDER);
+
+        let layout = preseed.disk_layout();
+        assert_eq!(layout.blocks, vec!["/dev/sda"]);
+        assert_eq!(layout.reuse, vec!["/dev/sda:1:reuse:mount=/boot/efi"]);
+        assert!(layout.moved.is_empty());
+    }
+
+    #[test]
+    fn files() {
+        let document = [
+            PRESEED,
+            "\n[[files]]\npath = \"/etc/oem/eula-accepted\"\ncontents = \"1\"\nmode = \"600\"\n\
+             \n[[files]]\npath = \"etc/relative\"\ncontents = \"\"\n",
+        ]
+        .concat();
+
+        let preseed = Preseed::parse(&document).unwrap();
+        assert_eq!(
+            preseed.files[0].target_file(),
+            Ok(TargetFile {
+                path:   PathBuf::from("/etc/oem/eula-accepted"),
+                mode:   0o600,
+                owner:  None,
+                source: TargetFileSource::Contents(b"1".to_vec()),
+            })
+        );
+        assert!(preseed.files[1].target_file().is_err());
+    }
+
+    #[test]
+    fn hosts() {
+        let document = [
+            PRESEED,
+            "\n[[hosts]]\naddress = \"192.168.1.10\"\nnames = [\"nas\", \"nas.office.lan\"]\n\
+             \n[[hosts]]\naddress = \"192.168.1\"\nnames = [\"printer\"]\n\
+             \n[[hosts]]\naddress = \"fd00::20\"\nnames = []\n",
+        ]
+        .concat();
+
+        let preseed = Preseed::parse(&document).unwrap();
+        assert_eq!(
+            preseed.hosts[0].host_entry(),
+            Ok(HostEntry {
+                address: "192.168.1.10".parse().unwrap(),
+                names:   vec!["nas".into(), "nas.office.lan".into()],
+            })
+        );
+        assert!(preseed.hosts[1].host_entry().is_err());
+        assert!(preseed.hosts[2].host_entry().is_err());
+    }
+
+    #[test]
+    fn users() {
+        let document = [
+            PRESEED,
+            "\n[[users]]\nusername = \"student\"\ngroups = [\"audio\"]\n\
+             \n[[users]]\nusername = \"backup\"\nsystem = true\n",
+        ]
+        .concat();
+
+        let preseed = Preseed::parse(&document).unwrap();
+        let accounts = preseed.user.iter().chain(&preseed.users).map(PreseedUser::account);
+        let accounts = accounts.map(|user| (user.username, user.groups, user.system));
+        assert_eq!(accounts.collect::<Vec<_>>(), vec![
+            ("user".to_owned(), UserAccountCreate::admin_groups(), false),
+            ("student".to_owned(), vec!["audio".to_owned()], false),
+            ("backup".to_owned(), Vec::new(), true),
+        ]);
+    }
+
+    #[test]
+    fn extract() {
+        let document = [
+            PRESEED,
+            "\n[extract]\nthreads = 2\nio_priority = \"best-effort:7\"\nbackend = \"in-process\"\n",
+        ]
+        .concat();
+
+        let preseed = Preseed::parse(&document).unwrap();
+        assert_eq!(
+            preseed.extract.as_ref().unwrap().options(),
+            Ok(ExtractOptions {
+                threads:     Some(2),
+                io_priority: Some(IoPriority::BestEffort(7)),
+                backend:     ExtractBackend::InProcess,
+            })
+        );
+
+        let document = [PRESEED, "\n[extract]\nio_priority = \"idle:3\"\n"].concat();
+        assert!(Preseed::parse(&document).unwrap().extract.unwrap().options().is_err());
+    }
+
+    #[test]
+    fn unknown_fields() {
+        // Keys before the first table header belong to the document itself.
+        let document = ["hostnmae = \"pop-os\"\n", PRESEED].concat();
+        assert!(Preseed::parse(&document).is_err());
+
+        let document = PRESEED.replace("[user]\n", "[user]\nrealnmae = \"User\"\n");
+        assert!(Preseed::parse(&document).is_err());
+
+        let document = PRESEED.replace("[flags]\n", "[flags]\nmodfy_boot = true\n");
+        assert!(Preseed::parse(&document).is_err());
+
+        // Keys after the last table header belong to `[disks]`.
+        let document = [PRESEED, "unknown = true"].concat();
+        assert!(Preseed::parse(&document).is_err());
+    }
+}
@@ -0,0 +1,451 @@
+//! The `probe` subcommand, which prints everything that distinst discovers about the disks of
+//! the system, for bug reports and for frontends which script the installer.
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use distinst::{
+    auto::InstallOptions,
+    os_detect::OS,
+    Disk, DiskError, Disks, FileSystem, PartitionExt, PartitionInfo, PartitionType, SectorExt,
+};
+use std::{fmt::Write, io};
+
+/// Gaps between partitions which are smaller than this, in bytes, are left by alignment.
+const MIN_FREE_BYTES: u64 = 1024 * 1024;
+
+/// Sectors at the end of a disk which hold the backup GPT header.
+const GPT_BACKUP_SECTORS: u64 = 34;
+
+#[derive(Debug, Serialize)]
+struct Report {
+    disks:         Vec<DiskReport>,
+    volume_groups: Vec<VolumeGroupReport>,
+    /// Intel RST arrays, whose member disks may not be partitioned on their own.
+    rst_arrays:    Vec<RstArrayReport>,
+    options:       OptionsReport,
+}
+
+#[derive(Debug, Serialize)]
+struct DiskReport {
+    path:       String,
+    model:      String,
+    serial:     String,
+    sectors:    u64,
+    table:      Option<String>,
+    read_only:  bool,
+    /// The format of the RAID metadata on the disk, which is wiped if the disk is erased.
+    raid:       Option<String>,
+    topology:   TopologyReport,
+    partitions: Vec<PartitionReport>,
+    free:       Vec<Region>,
+}
+
+#[derive(Debug, Serialize)]
+struct TopologyReport {
+    transport:            String,
+    rotational:           bool,
+    discard:              bool,
+    logical_sector_size:  u64,
+    physical_sector_size: u64,
+    numa_node:            Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct PartitionReport {
+    path:        String,
+    number:      i32,
+    kind:        String,
+    start:       u64,
+    end:         u64,
+    filesystem:  Option<String>,
+    label:       Option<String>,
+    name:        Option<String>,
+    uuid:        Option<String>,
+    mount_point: Option<String>,
+    flags:       Vec<String>,
+    os:          Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct VolumeGroupReport {
+    name:        String,
+    path:        String,
+    /// The LUKS partition which contains the volume group, if it is encrypted.
+    luks_parent: Option<String>,
+    sectors:     u64,
+    sector_size: u64,
+    volumes:     Vec<PartitionReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct RstArrayReport {
+    uuid:    Option<String>,
+    members: Vec<String>,
+    volume:  Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Region {
+    start: u64,
+    end:   u64,
+}
+
+#[derive(Debug, Serialize)]
+struct OptionsReport {
+    refresh:         Vec<String>,
+    erase:           Vec<String>,
+    alongside:       Vec<String>,
+    esp:             Vec<String>,
+    recovery:        Option<String>,
+    /// Guidance for when disks are hidden by an Intel storage controller in RAID mode.
+    intel_raid_mode: Option<String>,
+}
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("probe")
+        .about("prints the disks, partitions, volume groups, and install options of the system")
+        .arg(Arg::with_name("json").long("json").help("prints the report as JSON"))
+        .arg(Arg::with_name("unlock").long("unlock").help(
+            "decrypts the encrypted partitions first, with a password for each read from a line \
+             of standard input; an empty line leaves the partition encrypted",
+        ))
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), DiskError> {
+    let mut disks = Disks::probe_devices()?;
+    if let Err(why) = disks.initialize_volume_groups() {
+        eprintln!("distinst: unable to initialize volume groups: {}", why);
+    }
+
+    if matches.is_present("unlock") {
+        let stdin = io::stdin();
+        let unlocked = disks.unlock_all(|path| {
+            eprintln!("distinst: password for {}:", path.display());
+            let mut password = String::new();
+            stdin.read_line(&mut password).ok()?;
+            let password = password.trim_end_matches('\n');
+            if password.is_empty() {
+                None
+            } else {
+                Some(password.to_owned())
+            }
+        });
+
+        for why in unlocked.failed {
+            eprintln!("distinst: unable to decrypt: {}", why);
+        }
+    }
+
+    let report = report(&disks);
+    if matches.is_present("json") {
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(|why| DiskError::IO { why: io::Error::new(io::ErrorKind::Other, why) })?;
+        println!("{}", json);
+    } else {
+        print!("{}", text(&report));
+    }
+
+    Ok(())
+}
+
+fn report(disks: &Disks) -> Report {
+    let required = distinst::minimum_disk_size(5_000_000_000) / 512 + 1;
+    let options = InstallOptions::new(disks, required, 0);
+
+    Report {
+        disks:         disks.get_physical_devices().iter().map(disk_report).collect(),
+        volume_groups: disks
+            .get_logical_devices()
+            .iter()
+            .map(|device| VolumeGroupReport {
+                name:        device.volume_group.clone(),
+                path:        device.device_path.display().to_string(),
+                luks_parent: device.luks_parent.as_ref().map(|path| path.display().to_string()),
+                sectors:     device.get_sectors(),
+                sector_size: device.sector_size,
+                volumes:     device.partitions.iter().map(partition_report).collect(),
+            })
+            .collect(),
+        rst_arrays:    disks
+            .rst_arrays()
+            .into_iter()
+            .map(|array| RstArrayReport {
+                uuid:    array.uuid,
+                members: array.members.iter().map(|path| path.display().to_string()).collect(),
+                volume:  array.volume.map(|path| path.display().to_string()),
+            })
+            .collect(),
+        options:       OptionsReport {
+            refresh:         options.refresh_options.iter().map(ToString::to_string).collect(),
+            erase:           options.erase_options.iter().map(ToString::to_string).collect(),
+            alongside:       options.alongside_options.iter().map(ToString::to_string).collect(),
+            esp:             options.esp_options.iter().map(ToString::to_string).collect(),
+            recovery:        options.recovery_option.as_ref().map(|option| {
+                format!("recovery partition {}", option.recovery_uuid)
+            }),
+            intel_raid_mode: options.intel_raid_mode.as_ref().map(ToString::to_string),
+        },
+    }
+}
+
+fn disk_report(disk: &Disk) -> DiskReport {
+    DiskReport {
+        path:       disk.device_path.display().to_string(),
+        model:      disk.model_name.clone(),
+        serial:     disk.serial.clone(),
+        sectors:    disk.get_sectors(),
+        table:      disk.table_type.map(|table| format!("{:?}", table).to_lowercase()),
+        read_only:  disk.read_only,
+        raid:       disk.raid.as_ref().map(|raid| raid.format.to_string()),
+        topology:   TopologyReport {
+            transport:            disk.topology.transport.to_string(),
+            rotational:           disk.topology.rotational,
+            discard:              disk.topology.discard,
+            logical_sector_size:  disk.topology.logical_sector_size,
+            physical_sector_size: disk.topology.physical_sector_size,
+            numa_node:            disk.topology.numa_node,
+        },
+        partitions: disk.partitions.iter().map(partition_report).collect(),
+        free:       free_regions(disk),
+    }
+}
+
+fn partition_report(part: &PartitionInfo) -> PartitionReport {
+    let probe = match part.filesystem {
+        Some(FileSystem::Luks) | Some(FileSystem::Lvm) | Some(FileSystem::Swap) => false,
+        _ => part.part_type != PartitionType::Extended,
+    };
+
+    PartitionReport {
+        path:        part.device_path.display().to_string(),
+        number:      part.number,
+        kind:        format!("{:?}", part.part_type).to_lowercase(),
+        start:       part.start_sector,
+        end:         part.end_sector,
+        filesystem:  part.filesystem.map(|fs| fs.to_string()),
+        label:       part.fs_label.clone(),
+        name:        part.name.clone(),
+        uuid:        part.fs_uuid.clone(),
+        mount_point: part.mount_point.as_ref().map(|path| path.display().to_string()),
+        flags:       part.flags.iter().map(|flag| format!("{:?}", flag)).collect(),
+        os:          if probe { part.probe_os().map(|os| os_name(&os)) } else { None },
+    }
+}
+
+fn os_name(os: &OS) -> String {
+    match *os {
+        OS::Windows(ref version) => format!("Windows ({})", version),
+        OS::Linux { ref info, .. } => info.pretty_name.clone(),
+        OS::MacOs(ref version) => format!("Mac OS ({})", version),
+        OS::Bsd { ref name, ref version } => match *version {
+            Some(ref version) => format!("{} ({})", name, version),
+            None => name.clone(),
+        },
+    }
+}
+
+/// Regions of the disk which are not allocated to a partition.
+fn free_regions(disk: &Disk) -> Vec<Region> {
+    if disk.table_type.is_none() {
+        return Vec::new();
+    }
+
+    let mut partitions = disk.partitions.iter().collect::<Vec<_>>();
+    partitions.sort_by_key(|part| part.start_sector);
+
+    let min_free = MIN_FREE_BYTES / disk.topology.logical_sector_size.max(1);
+    let mut regions = Vec::new();
+    let mut start = min_free;
+    let end = disk.get_sectors().saturating_sub(GPT_BACKUP_SECTORS);
+    for part in partitions.into_iter().filter(|part| part.part_type != PartitionType::Extended) {
+        if part.start_sector >= start + min_free {
+            regions.push(Region { start, end: part.start_sector - 1 });
+        }
+
+        start = start.max(part.end_sector + 1);
+    }
+
+    if end >= start + min_free {
+        regions.push(Region { start, end: end - 1 });
+    }
+
+    regions
+}
+
+fn text(report: &Report) -> String {
+    let mut out = String::new();
+    for disk in &report.disks {
+        let _ = writeln!(
+            out,
+            "{}: {} ({}), {} MiB, {}{}{}",
+            disk.path,
+            disk.model,
+            disk.serial,
+            mib(disk.sectors, disk.topology.logical_sector_size),
+            disk.table.as_deref().unwrap_or("no partition table"),
+            if disk.read_only { ", read-only" } else { "" },
+            disk.raid.as_ref().map_or(String::new(), |raid| {
+                format!(", contains {} RAID metadata", raid)
+            })
+        );
+
+        let topology = &disk.topology;
+        let _ = writeln!(
+            out,
+            "    {}, {}, {}/{} byte sectors{}{}",
+            topology.transport,
+            if topology.rotational { "rotational" } else { "solid state" },
+            topology.logical_sector_size,
+            topology.physical_sector_size,
+            if topology.discard { ", discard" } else { "" },
+            topology.numa_node.map_or(String::new(), |node| format!(", NUMA node {}", node))
+        );
+
+        for part in &disk.partitions {
+            partition_text(&mut out, part, topology.logical_sector_size);
+        }
+
+        for region in &disk.free {
+            let _ = writeln!(
+                out,
+                "    free: sectors {} - {}, {} MiB",
+                region.start,
+                region.end,
+                mib(region.end - region.start + 1, topology.logical_sector_size)
+            );
+        }
+    }
+
+    for group in &report.volume_groups {
+        let _ = writeln!(
+            out,
+            "volume group {} ({}): {} MiB{}",
+            group.name,
+            group.path,
+            mib(group.sectors, group.sector_size),
+            group.luks_parent.as_ref().map_or(String::new(), |parent| {
+                format!(", encrypted on {}", parent)
+            })
+        );
+
+        for volume in &group.volumes {
+            partition_text(&mut out, volume, group.sector_size);
+        }
+    }
+
+    for array in &report.rst_arrays {
+        let _ = writeln!(
+            out,
+            "Intel RST array {}: {}, {}",
+            array.uuid.as_deref().unwrap_or("without a UUID"),
+            array.members.join(", "),
+            array.volume.as_ref().map_or("not assembled".into(), |volume| {
+                format!("assembled as {}", volume)
+            })
+        );
+    }
+
+    let options = &report.options;
+    let _ = writeln!(out, "install options:");
+    let categories = [
+        ("refresh", &options.refresh),
+        ("erase", &options.erase),
+        ("alongside", &options.alongside),
+        ("esp", &options.esp),
+    ];
+
+    for &(category, entries) in &categories {
+        for option in entries.iter() {
+            let _ = writeln!(out, "    {}: {}", category, option);
+        }
+    }
+
+    if let Some(ref recovery) = options.recovery {
+        let _ = writeln!(out, "    recovery: {}", recovery);
+    }
+
+    if let Some(ref raid) = options.intel_raid_mode {
+        let _ = writeln!(out, "warning: {}", raid);
+    }
+
+    out
+}
+
+/// The size of `sectors` of `sector_size` bytes, in MiB.
+fn mib(sectors: u64, sector_size: u64) -> u64 { sectors * sector_size / (1024 * 1024) }
+
+fn partition_text(out: &mut String, part: &PartitionReport, sector_size: u64) {
+    let _ = write!(
+        out,
+        "    {}: {}, {} MiB",
+        part.path,
+        part.filesystem.as_deref().unwrap_or(&part.kind),
+        mib(part.end - part.start + 1, sector_size)
+    );
+
+    if let Some(ref label) = part.label {
+        let _ = write!(out, ", label {:?}", label);
+    }
+
+    if let Some(ref mount) = part.mount_point {
+        let _ = write!(out, ", mounted at {}", mount);
+    }
+
+    if !part.flags.is_empty() {
+        let _ = write!(out, ", flags: {}", part.flags.join(" "));
+    }
+
+    if let Some(ref os) = part.os {
+        let _ = write!(out, ", contains {}", os);
+    }
+
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use distinst::{DiskTopology, PartitionBuilder, PartitionTable};
+
+    #[test]
+    fn free() {
+        let mut disk = Disk {
+            model_name:  "TEST".into(),
+            serial:      "TEST".into(),
+            device_path: "/dev/sdz".into(),
+            file_system: None,
+            mount_point: None,
+            size:        1_000_000,
+            device_type: "TEST".into(),
+            table_type:  Some(PartitionTable::Gpt),
+            read_only:   false,
+            removable:   false,
+            mklabel:     false,
+            erase:       None,
+            raid:        None,
+            rst_active:  false,
+            topology:    DiskTopology::default(),
+            partitions:  Vec::new(),
+        };
+
+        for &(start, end) in &[(2048, 100_000), (100_001, 200_000), (400_000, 500_000)] {
+            let part = PartitionBuilder::new(start, end, FileSystem::Ext4).build();
+            disk.partitions.push(part);
+        }
+
+        let regions = free_regions(&disk)
+            .into_iter()
+            .map(|region| (region.start, region.end))
+            .collect::<Vec<_>>();
+        assert_eq!(regions, vec![(200_000, 399_999), (500_000, 999_965)]);
+
+        disk.table_type = None;
+        assert!(free_regions(&disk).is_empty());
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(mib(2048, 512), 1);
+        assert_eq!(mib(2048, 4096), 8);
+        assert_eq!(mib(2047, 512), 0);
+    }
+}
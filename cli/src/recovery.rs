@@ -0,0 +1,59 @@
+//! The `recovery` subcommand, which refreshes the recovery partition of an installed system with
+//! newer live media, or verifies that it can be booted.
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use distinst::recovery::RecoveryPartition;
+use std::{io, path::Path};
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("recovery")
+        .about("updates or verifies the recovery partition of an installed system")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("recovery-path")
+                .long("recovery-path")
+                .help("where the recovery partition is mounted")
+                .takes_value(true)
+                .default_value("/recovery"),
+        )
+        .arg(
+            Arg::with_name("efi-path")
+                .long("efi-path")
+                .help("where the EFI system partition is mounted")
+                .takes_value(true)
+                .default_value("/boot/efi"),
+        )
+        .subcommand(
+            SubCommand::with_name("update")
+                .about("replaces the live media of the recovery partition, keeping its settings")
+                .arg(
+                    Arg::with_name("source")
+                        .help("the root of the new live media, such as a mounted ISO")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("ensures that the recovery partition has live media, and can be booted"),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> io::Result<()> {
+    let partition = RecoveryPartition::new(
+        Path::new(matches.value_of("recovery-path").unwrap()),
+        Path::new(matches.value_of("efi-path").unwrap()),
+    )?;
+
+    match matches.subcommand() {
+        ("update", Some(matches)) => {
+            partition.update(Path::new(matches.value_of("source").unwrap()))?;
+            partition.verify()
+        }
+        ("verify", _) => {
+            partition.verify()?;
+            println!("recovery partition at {} is valid", partition.path.display());
+            Ok(())
+        }
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,16 @@
+//! The `resize` subcommand, which applies changes to the partitions of the disks, such as
+//! resizing, moving, deleting, and creating partitions, without installing a distribution.
+
+use super::*;
+use clap::{App, SubCommand};
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("resize")
+        .about("resizes, moves, deletes, and creates partitions, without installing")
+        .arg(disk_arg().required(true))
+        .args(&layout_args())
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), DistinstError> {
+    partition_disks(configure_disks(&DiskLayout::from_matches(matches))?)
+}
@@ -0,0 +1,94 @@
+//! The `rollback` subcommand, which manages the systems that refresh installs with the
+//! `KEEP_OLD_ROOT` flag retained as generations, and restores them.
+
+use super::*;
+use clap::{App, AppSettings, Arg, SubCommand};
+use distinst::auto::{delete_generation, generation_size, generations, rollback};
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("rollback")
+        .about("lists, restores, or deletes the systems which refresh installs retained")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("root")
+                .long("root")
+                .help("the root partition of the installed system")
+                .takes_value(true)
+                .required(true),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("lists the retained systems, with the newest first")
+                .arg(
+                    Arg::with_name("sizes")
+                        .long("sizes")
+                        .help("measures the space which each system occupies"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("restores a retained system, retaining the current system in its place")
+                .arg(Arg::with_name("generation").help("the system to restore").required(true))
+                .arg(
+                    Arg::with_name("esp")
+                        .long("esp")
+                        .help("the EFI system partition which the boot loader is installed to")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("delete")
+                .about("deletes a retained system")
+                .arg(Arg::with_name("generation").help("the system to delete").required(true)),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), DistinstError> {
+    let disks = Disks::probe_devices()?;
+    let root = Path::new(matches.value_of("root").unwrap());
+    let root_fs = file_system(&disks, root)?;
+
+    match matches.subcommand() {
+        ("list", Some(matches)) => {
+            for generation in generations(root, root_fs)? {
+                let release = generation.release.as_deref().unwrap_or("unknown release");
+                if matches.is_present("sizes") {
+                    let size = generation_size(root, root_fs, generation.id)?;
+                    println!("{}: {} ({} MiB)", generation.id, release, size / 1024 / 1024);
+                } else {
+                    println!("{}: {}", generation.id, release);
+                }
+            }
+        }
+        ("restore", Some(matches)) => {
+            let esp = match matches.value_of("esp") {
+                Some(esp) => Some((Path::new(esp), file_system(&disks, Path::new(esp))?)),
+                None => None,
+            };
+
+            let generation = generation(matches)?;
+            rollback(root, root_fs, generation, esp)?;
+            println!("restored the system of generation {}", generation);
+        }
+        ("delete", Some(matches)) => {
+            let generation = generation(matches)?;
+            delete_generation(root, root_fs, generation)?;
+            println!("deleted the system of generation {}", generation);
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+fn generation(matches: &ArgMatches) -> Result<u64, DistinstError> {
+    let generation = matches.value_of("generation").unwrap();
+    generation.parse().map_err(|_| DistinstError::ArgNaN { arg: generation.into() })
+}
+
+fn file_system(disks: &Disks, device: &Path) -> Result<FileSystem, DistinstError> {
+    disks
+        .get_partition_by_path(device.canonicalize().as_deref().unwrap_or(device))
+        .and_then(|partition| partition.filesystem)
+        .ok_or_else(|| DistinstError::NoFileSystem { device: device.display().to_string() })
+}
@@ -0,0 +1,59 @@
+//! The `upgrade` subcommand, which upgrades an installed system to the release of the recovery
+//! partition that was booted to upgrade it.
+
+use super::*;
+use clap::{App, Arg, SubCommand};
+use distinst::auto::InstallOptions;
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("upgrade")
+        .about("upgrades the installed system from the live session of its recovery partition")
+        .arg(
+            Arg::with_name("decrypt")
+                .long("decrypt")
+                .help("decrypts the LUKS partition of the installed system")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("repair")
+                .long("repair")
+                .help("attempts to repair the installed system if the upgrade fails"),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), DistinstError> {
+    let mut recovery_conf = RecoveryEnv::new().map_err(|why| DistinstError::RecoveryConf { why })?;
+
+    let mut disks = Disks::probe_devices()?;
+    disks.initialize_volume_groups().map_err(|why| DistinstError::InitializeVolumes { why })?;
+    if let Some(devices) = matches.values_of("decrypt") {
+        decrypt(&mut disks, &devices.collect::<Vec<_>>())?;
+    }
+
+    let option =
+        InstallOptions::new(&disks, 0, 0).recovery_option.ok_or(DistinstError::NoRecoveryOption)?;
+
+    let repair = matches.is_present("repair");
+    distinst::upgrade(&mut recovery_conf, &mut disks, &option, print_event, || repair)
+        .map_err(|why| DistinstError::Upgrade { why })?;
+
+    println!("upgrade was successful");
+    Ok(())
+}
+
+fn print_event(event: UpgradeEvent) {
+    match event {
+        UpgradeEvent::AttemptingRepair => println!("upgrade failed: attempting to repair"),
+        UpgradeEvent::AttemptingUpgrade => println!("attempting to upgrade"),
+        UpgradeEvent::ResumingUpgrade => println!("repair succeeded: resuming upgrade"),
+        UpgradeEvent::DpkgInfo(info) | UpgradeEvent::UpgradeInfo(info) => println!("{}", info),
+        UpgradeEvent::DpkgErr(why) | UpgradeEvent::UpgradeErr(why) => eprintln!("{}", why),
+        UpgradeEvent::PackageProcessing(package) => println!("processing {}", package),
+        UpgradeEvent::PackageProgress(percent) => println!("progress: {}%", percent),
+        UpgradeEvent::PackageSettingUp(package) => println!("setting up {}", package),
+        UpgradeEvent::PackageUnpacking { package, version, over } => {
+            println!("unpacking {} ({}) over ({})", package, version, over)
+        }
+    }
+}
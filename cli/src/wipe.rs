@@ -0,0 +1,78 @@
+//! The `wipe` subcommand, which writes a new, empty partition table to disks, and may erase
+//! their data so that it cannot be recovered.
+
+use super::*;
+use clap::{App, Arg, SubCommand};
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("wipe")
+        .about("writes a new partition table to disks, removing their partitions and RAID metadata")
+        .arg(Arg::with_name("disk").help("the disks to wipe").required(true).multiple(true))
+        .arg(
+            Arg::with_name("table")
+                .short("t")
+                .long("table")
+                .help("the type of the new partition table")
+                .possible_values(&["gpt", "msdos"])
+                .default_value("gpt"),
+        )
+        .arg(
+            Arg::with_name("erase")
+                .long("erase")
+                .help(
+                    "erases the data on the disks before the table is written, with discard, \
+                     nvme-format, or overwrite[=PASSES]",
+                )
+                .takes_value(true),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), DistinstError> {
+    let blocks = matches.values_of("disk").unwrap().collect::<Vec<_>>();
+    let table = matches.value_of("table").unwrap();
+    let tables = blocks.iter().map(|block| [block, ":", table].concat()).collect::<Vec<_>>();
+
+    let mut disks = configure_disks(&DiskLayout {
+        blocks,
+        tables: tables.iter().map(String::as_str).collect(),
+        ..DiskLayout::default()
+    })?;
+
+    if let Some(erase) = matches.value_of("erase") {
+        let mode = parse_erase(erase)?;
+        for disk in disks.get_physical_devices_mut() {
+            disk.secure_erase(mode)?;
+        }
+    }
+
+    partition_disks(disks)
+}
+
+fn parse_erase(erase: &str) -> Result<EraseMode, DistinstError> {
+    match erase {
+        "discard" => Ok(EraseMode::Discard),
+        "nvme-format" => Ok(EraseMode::NvmeFormat),
+        "overwrite" => Ok(EraseMode::Overwrite(1)),
+        _ => erase
+            .strip_prefix("overwrite=")
+            .and_then(|passes| passes.parse::<u8>().ok())
+            .filter(|&passes| passes != 0)
+            .map(EraseMode::Overwrite)
+            .ok_or_else(|| DistinstError::InvalidEraseMode { mode: erase.into() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn erase_modes() {
+        assert_eq!(parse_erase("discard").unwrap(), EraseMode::Discard);
+        assert_eq!(parse_erase("nvme-format").unwrap(), EraseMode::NvmeFormat);
+        assert_eq!(parse_erase("overwrite").unwrap(), EraseMode::Overwrite(1));
+        assert_eq!(parse_erase("overwrite=3").unwrap(), EraseMode::Overwrite(3));
+        assert!(parse_erase("overwrite=0").is_err());
+        assert!(parse_erase("shred").is_err());
+    }
+}
@@ -1,15 +1,17 @@
-//! Detect whether a Linux system is in EFI or BIOS mode.
+//! Detect whether a Linux system is in EFI or BIOS mode, or was booted by U-Boot.
 //!
 //! ```rust,no_run
 //! use distinst_bootloader::Bootloader;
 //!
 //! match Bootloader::detect() {
 //!     Bootloader::Efi => println!("System is in EFI mode"),
-//!     Bootloader::Bios => println!("System is in BIOS mode")
+//!     Bootloader::Bios => println!("System is in BIOS mode"),
+//!     Bootloader::UBoot => println!("System was booted by U-Boot")
 //! }
 //! ```
 
 use std::{
+    env, fs,
     path::Path,
     sync::atomic::{AtomicUsize, Ordering},
 };
@@ -17,15 +19,25 @@ use std::{
 /// Force the installation to perform either a BIOS or EFI installation.
 pub static FORCE_BOOTLOADER: AtomicUsize = AtomicUsize::new(0);
 
+/// The compatible strings of the boards which are known to boot from U-Boot, which older
+/// releases of U-Boot do not identify themselves to. These are the boards of `distinst::uboot`.
+pub const UBOOT_BOARDS: &[&str] =
+    &["pine64,rockpro64", "pine64,pinebook-pro", "pine64,pine64-plus", "hardkernel,odroid-n2"];
+
 /// Bootloader type
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Bootloader {
     Bios,
     Efi,
+    /// An ARM board whose firmware, such as U-Boot, loads the kernel described by an
+    /// `extlinux.conf` or `boot.scr` on the boot partition.
+    UBoot,
 }
 
 impl Bootloader {
-    /// Detects whether the system is running from EFI.
+    /// Detects whether the system is running from EFI. ARM systems which were not booted from
+    /// EFI are U-Boot systems if U-Boot identified itself in their device tree, or if they are
+    /// one of the `UBOOT_BOARDS`.
     pub fn detect() -> Bootloader {
         match FORCE_BOOTLOADER.load(Ordering::SeqCst) {
             1 => {
@@ -34,13 +46,61 @@ impl Bootloader {
             2 => {
                 return Bootloader::Efi;
             }
+            3 => {
+                return Bootloader::UBoot;
+            }
             _ => (),
         }
 
         if Path::new("/sys/firmware/efi").is_dir() {
             Bootloader::Efi
+        } else if is_arm() && is_uboot(Path::new("/proc/device-tree")) {
+            Bootloader::UBoot
         } else {
             Bootloader::Bios
         }
     }
+
+    /// Whether a boot loader can be installed for this system. ARM systems which were booted
+    /// by neither EFI nor U-Boot are not supported, as they do not boot from BIOS.
+    pub fn is_supported(self) -> bool { self != Bootloader::Bios || !is_arm() }
+}
+
+/// Whether the `device_tree` was given by U-Boot, or is that of a board which boots from it.
+fn is_uboot(device_tree: &Path) -> bool {
+    if device_tree.join("chosen/u-boot,version").exists() {
+        return true;
+    }
+
+    fs::read(device_tree.join("compatible")).is_ok_and(|compatible| {
+        compatible
+            .split(|&byte| byte == 0)
+            .any(|compatible| UBOOT_BOARDS.iter().any(|board| board.as_bytes() == compatible))
+    })
+}
+
+fn is_arm() -> bool { matches!(env::consts::ARCH, "aarch64" | "arm") }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn uboot_device_trees() {
+        let tempdir = TempDir::new("distinst").unwrap();
+        let device_tree = tempdir.path();
+        fs::write(device_tree.join("compatible"), b"raspberrypi,4-model-b\0brcm,bcm2711\0")
+            .unwrap();
+        assert!(!is_uboot(device_tree));
+
+        fs::create_dir(device_tree.join("chosen")).unwrap();
+        fs::write(device_tree.join("chosen/u-boot,version"), b"2023.01\0").unwrap();
+        assert!(is_uboot(device_tree));
+
+        fs::remove_file(device_tree.join("chosen/u-boot,version")).unwrap();
+        fs::write(device_tree.join("compatible"), b"pine64,rockpro64-v2.1\0pine64,rockpro64\0")
+            .unwrap();
+        assert!(is_uboot(device_tree));
+    }
 }
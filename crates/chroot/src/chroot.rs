@@ -60,6 +60,7 @@ impl<'a> Chroot<'a> {
             ..arg(&self.path);
             ..arg(cmd.as_ref());
             ..args(args);
+            ..program(cmd.as_ref());
             ..stderr(Stdio::piped());
             ..stdout(Stdio::piped());
         };
@@ -1,18 +1,34 @@
+use misc::{
+    journal::{self, CommandRecord},
+    metrics,
+    output::{CommandError, TailBuffer, JOURNAL_LIMIT, STDERR_LIMIT},
+};
 use std::{
     ffi::OsStr,
     io::{self, BufRead, BufReader, Error, ErrorKind, Write},
     process::{self, Child, ExitStatus, Stdio},
+    time::{Duration, Instant},
 };
 
 /// Convenient wrapper around `process::Command` to make it easier to work with.
 pub struct Command<'a> {
-    cmd:   process::Command,
-    stdin: Option<&'a str>,
+    cmd:     process::Command,
+    stdin:   Option<&'a str>,
+    /// The name of the program, which its metrics are recorded under.
+    program: String,
 }
 
 impl<'a> Command<'a> {
     pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
-        Command { cmd: process::Command::new(program), stdin: None }
+        let name = program.as_ref().to_string_lossy().into_owned();
+        Command { cmd: process::Command::new(program), stdin: None, program: name }
+    }
+
+    /// Records the metrics of the command under the `program` which it runs, rather than the
+    /// program which runs it, such as `chroot`.
+    pub fn program<S: AsRef<OsStr>>(&mut self, program: S) -> &mut Command<'a> {
+        self.program = program.as_ref().to_string_lossy().into_owned();
+        self
     }
 
     pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Command<'a> {
@@ -67,6 +83,7 @@ impl<'a> Command<'a> {
 
         self.cmd.stdout(Stdio::piped());
 
+        let start = Instant::now();
         let mut child = self.cmd.spawn().map_err(|why| {
             Error::new(why.kind(), format!("failed to spawn process {}: {}", cmd, why))
         })?;
@@ -78,6 +95,13 @@ impl<'a> Command<'a> {
             .map_err(|why| {
                 Error::new(why.kind(), format!("failed to get output of {}: {}", cmd, why))
             })
+            .map(|output| {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let duration = start.elapsed();
+                record(&self.program, &cmd, output.status, duration, &stdout, &stderr);
+                output
+            })
             .and_then(|output| {
                 String::from_utf8(output.stdout).map_err(|why| {
                     Error::new(
@@ -94,6 +118,8 @@ impl<'a> Command<'a> {
     }
 
     /// Run the program and check the status.
+    ///
+    /// If the program fails, the tail of its stderr is included in the error.
     pub fn run_with_callbacks<I, E>(&mut self, info: I, error: E) -> io::Result<()>
     where
         I: Fn(&str),
@@ -102,6 +128,7 @@ impl<'a> Command<'a> {
         let cmd = format!("{:?}", self.cmd);
         info!("running {}", cmd);
 
+        let start = Instant::now();
         let mut child = self.cmd.spawn().map_err(|why| {
             Error::new(why.kind(), format!("failed to spawn process {}: {}", cmd, why))
         })?;
@@ -134,27 +161,61 @@ impl<'a> Command<'a> {
             });
         }
 
+        let journaled = journal::enabled();
+        let mut stdout = String::new();
+        let mut stderr = TailBuffer::new(if journaled { JOURNAL_LIMIT } else { STDERR_LIMIT });
         for message in rx {
             match message {
-                Message::Stdout(line) => info(&line),
-                Message::Stderr(line) => error(&line),
+                Message::Stdout(line) => {
+                    info(&line);
+                    if journaled {
+                        stdout.push_str(&line);
+                        stdout.push('\n');
+                    }
+                }
+                Message::Stderr(line) => {
+                    error(&line);
+                    stderr.push_line(&line);
+                }
             }
         }
 
-        child.wait().and_then(|status| status_as_result(status, &cmd))
+        let status = child.wait()?;
+        record(&self.program, &cmd, status, start.elapsed(), &stdout, &stderr.as_string());
+        status_as_result(status, &cmd, &stderr)
     }
 }
 
-fn status_as_result(status: ExitStatus, cmd: &str) -> io::Result<()> {
+/// Records the duration of the command, and the command in the command journal, if it is enabled.
+fn record(
+    program: &str,
+    cmd: &str,
+    status: ExitStatus,
+    duration: Duration,
+    stdout: &str,
+    stderr: &str,
+) {
+    metrics::record(["command:", program].concat(), duration);
+    if journal::enabled() {
+        journal::record(&CommandRecord {
+            command: cmd.to_owned(),
+            status:  status.code(),
+            duration,
+            stdout:  stdout.to_owned(),
+            stderr:  stderr.to_owned(),
+        });
+    }
+}
+
+fn status_as_result(status: ExitStatus, cmd: &str, stderr: &TailBuffer) -> io::Result<()> {
     if status.success() {
         Ok(())
     } else if let Some(127) = status.code() {
         Err(io::Error::new(io::ErrorKind::NotFound, format!("command {} was not found", cmd)))
     } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("command failed with exit status: {}", status),
-        ))
+        let stderr = stderr.tail(STDERR_LIMIT);
+        Err(CommandError { command: cmd.to_owned(), status: status.code(), stderr }
+            .into_io_error(io::ErrorKind::Other))
     }
 }
 
@@ -167,6 +228,19 @@ mod tests {
         assert!(Command::new("asdfasdf").run().unwrap_err().kind() == io::ErrorKind::NotFound);
     }
 
+    #[test]
+    fn command_with_stderr() {
+        let error = Command::new("sh")
+            .args(&["-c", "echo 'cannot find EFI directory' >&2; exit 1"])
+            .stderr(Stdio::piped())
+            .run()
+            .unwrap_err();
+
+        let error = CommandError::from_io_error(&error).unwrap();
+        assert_eq!(error.status, Some(1));
+        assert_eq!(error.stderr, "cannot find EFI directory\n");
+    }
+
     #[test]
     fn command_with_output() {
         assert_eq!(
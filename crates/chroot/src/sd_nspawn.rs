@@ -42,6 +42,7 @@ impl<'a> SystemdNspawn<'a> {
             ..arg(&self.path);
             ..arg(cmd.as_ref());
             ..args(args);
+            ..program(cmd.as_ref());
             ..stderr(Stdio::piped());
             ..stdout(Stdio::piped());
         };
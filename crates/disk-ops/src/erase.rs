@@ -0,0 +1,37 @@
+//! Erases the data on a disk before a new partition table is written to it, so that the data
+//! cannot be recovered by whoever next owns the disk.
+
+use external::{blkdiscard, nvme_format, shred};
+use std::{io, path::Path};
+
+/// How the data on a disk will be erased.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EraseMode {
+    /// Discards every block of a solid state drive, securely if the drive supports it.
+    Discard,
+    /// Erases an NVMe namespace with the NVMe format command.
+    NvmeFormat,
+    /// Overwrites every sector of the disk this many times, with the last pass writing zeroes.
+    Overwrite(u8),
+}
+
+/// Erases the data on the disk at `device`.
+///
+/// If the disk does not support being discarded or formatted, it will be overwritten once.
+/// The `progress` callback receives the percentage of an overwrite which has been written.
+pub fn secure_erase<F: FnMut(u8)>(device: &Path, mode: EraseMode, progress: F) -> io::Result<()> {
+    info!("erasing {} with {:?}", device.display(), mode);
+    let result = match mode {
+        EraseMode::Discard => blkdiscard(device, true).or_else(|why| {
+            warn!("{}: secure discard failed, discarding instead: {}", device.display(), why);
+            blkdiscard(device, false)
+        }),
+        EraseMode::NvmeFormat => nvme_format(device),
+        EraseMode::Overwrite(passes) => return shred(device, passes.max(1), progress),
+    };
+
+    result.or_else(|why| {
+        warn!("{}: {:?} failed, overwriting instead: {}", device.display(), mode, why);
+        shred(device, 1, progress)
+    })
+}
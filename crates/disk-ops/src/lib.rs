@@ -14,6 +14,7 @@ extern crate smart_default;
 extern crate sys_mount;
 extern crate tempdir;
 
+mod erase;
 mod mklabel;
 mod mkpart;
 mod mvpart;
@@ -21,8 +22,14 @@ mod ops;
 pub mod parted;
 mod resize;
 mod rmpart;
+mod wipe;
 
-pub use self::{mklabel::*, mkpart::*, mvpart::*, ops::*, resize::*, rmpart::*};
+pub use self::{erase::*, mklabel::*, mkpart::*, mvpart::*, ops::*, resize::*, rmpart::*, wipe::*};
+
+use std::sync::atomic::AtomicBool;
+
+/// When set to true, partition moves will be cancelled, and rolled back to where they began.
+pub static KILL_SWITCH: AtomicBool = AtomicBool::new(false);
 
 const MEBIBYTE: u64 = 1_048_576;
 const MEGABYTE: u64 = 1_000_000;
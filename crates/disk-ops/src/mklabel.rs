@@ -1,7 +1,6 @@
 use disk_types::PartitionTable;
-use external::wipefs;
 use libparted::{Disk as PedDisk, DiskType as PedDiskType};
-use crate::parted::*;
+use crate::{parted::*, wipe::wipe_signatures};
 use std::{
     fs::File,
     io::{self, Seek, SeekFrom, Write},
@@ -10,8 +9,8 @@ use std::{
 
 /// Writes a new partition table to the disk, clobbering it in the process.
 pub fn mklabel<P: AsRef<Path>>(device_path: P, kind: PartitionTable) -> io::Result<()> {
+    wipe_signatures(&device_path)?;
     let _ = zero(&device_path, 2047, 1);
-    let _ = wipefs(&device_path);
 
     info!("writing {:?} table on {}", kind, device_path.as_ref().display());
 
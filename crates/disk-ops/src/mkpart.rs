@@ -3,7 +3,7 @@ use libparted::{
     Device, FileSystemType as PedFileSystem, Geometry, Partition as PedPartition, PartitionFlag,
     PartitionType as PedPartitionType,
 };
-use crate::parted::*;
+use crate::{parted::*, rmpart::remove_partition_by_number};
 use std::{
     io,
     path::{Path, PathBuf},
@@ -29,6 +29,10 @@ pub struct PartitionCreate {
     pub flags:        Vec<PartitionFlag>,
     /// Defines the label to apply
     pub label:        Option<String>,
+    /// The label of the file system that the partition will be formatted with.
+    pub fs_label:     Option<String>,
+    /// The UUID of the file system that the partition will be formatted with.
+    pub fs_uuid:      Option<String>,
 }
 
 impl BlockDeviceExt for PartitionCreate {
@@ -60,6 +64,21 @@ impl SectorExt for PartitionCreate {
 /// Creates a new partition on the device using the info in the `partition` parameter.
 /// The partition table should reflect the changes before this function exits.
 pub fn create_partition<P>(device: &mut Device, partition: &P) -> io::Result<()>
+where
+    P: PartitionExt,
+{
+    replace_partition(device, None, partition)
+}
+
+/// Identical to `create_partition`, but will also remove the partition numbered `replace`.
+///
+/// Both changes are written to the partition table in a single commit, so that the table
+/// is never left without the partition if the process is interrupted.
+pub fn replace_partition<P>(
+    device: &mut Device,
+    replace: Option<u32>,
+    partition: &P,
+) -> io::Result<()>
 where
     P: PartitionExt,
 {
@@ -83,6 +102,10 @@ where
     let fs_type = partition.get_file_system().and_then(|fs| PedFileSystem::get(fs.into()));
 
     let mut disk = open_disk(device)?;
+    if let Some(num) = replace {
+        remove_partition_by_number(&mut disk, num)?;
+    }
+
     let mut part =
         PedPartition::new(&disk, part_type, fs_type.as_ref(), start, end).map_err(|why| {
             io::Error::new(
@@ -1,17 +1,28 @@
 use super::OffsetCoordinates;
 use std::{
-    fs::OpenOptions,
+    fs::{File, OpenOptions},
     io::{self, Read, Seek, SeekFrom, Write},
     path::Path,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
+/// The number of bytes which will be copied at a time.
+const CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
 /// Performs direct reads & writes on the disk to shift a partition either to the left or right,
 /// using the supplied offset coordinates to determine where the partition is, and where it
 /// should be.
-pub fn move_partition<P: AsRef<Path>>(
+///
+/// The `progress` callback receives the number of sectors copied, and the total number of
+/// sectors to copy. If `cancel`, such as the `KILL_SWITCH`, is set while the partition is
+/// being moved, the chunk being copied will be completed, and all sectors copied so far will
+/// be moved back to their original location, before returning an `Interrupted` error.
+pub fn move_partition<P: AsRef<Path>, F: FnMut(u64, u64)>(
     path: P,
     coords: OffsetCoordinates,
     bs: u64,
+    cancel: &AtomicBool,
+    mut progress: F,
 ) -> io::Result<()> {
     info!(
         "moving partition on {} with {} sector size: {{ skip: {}; offset: {}; length: {} }}",
@@ -26,29 +37,136 @@ pub fn move_partition<P: AsRef<Path>>(
 
     let source_skip = coords.skip;
     let offset_skip = (source_skip as i64 + coords.offset) as u64;
+    let chunk = (CHUNK_SIZE / bs).max(1);
+    let mut buffer = vec![0; (chunk * bs) as usize];
+
+    // Sectors are copied in the opposite direction of the move, so that sectors which
+    // overlap with the destination are copied before they are overwritten.
+    let forward = coords.offset <= 0;
+
+    let mut copied = 0;
+    while copied < coords.length {
+        let len = chunk.min(coords.length - copied);
+        let sector = if forward { copied } else { coords.length - copied - len };
+
+        copy_sectors(&mut disk, &mut buffer, bs, source_skip + sector, offset_skip + sector, len)?;
+
+        copied += len;
+        progress(copied, coords.length);
+
+        if copied < coords.length && cancel.load(Ordering::SeqCst) {
+            warn!("partition move cancelled after {} of {} sectors", copied, coords.length);
+
+            // Move the sectors that were copied back in the opposite direction.
+            let mut restored = 0;
+            while restored < copied {
+                let len = chunk.min(copied - restored);
+                let sector = if forward {
+                    copied - restored - len
+                } else {
+                    coords.length - copied + restored
+                };
+
+                copy_sectors(
+                    &mut disk,
+                    &mut buffer,
+                    bs,
+                    offset_skip + sector,
+                    source_skip + sector,
+                    len,
+                )?;
 
-    // TODO: Rather than writing one sector at a time, use a significantly larger
-    // buffer size to improve the performance of partition moves.
-    let mut buffer = vec![0; bs as usize];
-
-    // Some dynamic dispatching, based on whether we need to move forward or
-    // backwards.
-    let range: Box<dyn Iterator<Item = u64>> = if coords.offset > 0 {
-        Box::new((0..coords.length).rev())
-    } else {
-        Box::new(0..coords.length)
-    };
-
-    // Write one sector at a time, until all sectors have been moved.
-    for sector in range {
-        let input = source_skip + sector;
-        disk.seek(SeekFrom::Start(input * bs))?;
-        disk.read_exact(&mut buffer[..bs as usize])?;
-
-        let offset = offset_skip + sector;
-        disk.seek(SeekFrom::Start(offset * bs))?;
-        disk.write_all(&buffer[..bs as usize])?;
+                restored += len;
+            }
+
+            disk.sync_all()?;
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "partition move cancelled"));
+        }
     }
 
     disk.sync_all()
 }
+
+fn copy_sectors(
+    disk: &mut File,
+    buffer: &mut [u8],
+    bs: u64,
+    input: u64,
+    output: u64,
+    length: u64,
+) -> io::Result<()> {
+    let buffer = &mut buffer[..(length * bs) as usize];
+    disk.seek(SeekFrom::Start(input * bs))?;
+    disk.read_exact(buffer)?;
+    disk.seek(SeekFrom::Start(output * bs))?;
+    disk.write_all(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::PathBuf};
+    use tempdir::TempDir;
+
+    const BS: u64 = 512;
+
+    fn image(dir: &TempDir, sectors: u64) -> PathBuf {
+        let path = dir.path().join("disk.img");
+        let data = (0..sectors * BS).map(|byte| (byte / BS) as u8).collect::<Vec<u8>>();
+        fs::write(&path, &data).unwrap();
+        path
+    }
+
+    fn sector(data: &[u8], sector: u64) -> u8 { data[(sector * BS) as usize] }
+
+    #[test]
+    fn move_overlapping() {
+        let length = CHUNK_SIZE / BS * 2 + 7;
+        let sectors = length + 100;
+
+        for &offset in &[50i64, -50] {
+            let skip = if offset > 0 { 10 } else { 60 };
+            let dir = TempDir::new("distinst-mvpart").unwrap();
+            let path = image(&dir, sectors);
+            let mut reports = Vec::new();
+
+            let coords = OffsetCoordinates { skip, offset, length };
+            let cancel = AtomicBool::new(false);
+            move_partition(&path, coords, BS, &cancel, |copied, total| {
+                reports.push((copied, total))
+            })
+            .unwrap();
+
+            let data = fs::read(&path).unwrap();
+            for source in (skip..skip + length).step_by(97) {
+                let target = (source as i64 + offset) as u64;
+                assert_eq!(sector(&data, target), source as u8);
+            }
+
+            assert_eq!(reports.len(), 3);
+            assert_eq!(reports.last(), Some(&(length, length)));
+        }
+    }
+
+    #[test]
+    fn move_cancelled() {
+        let length = CHUNK_SIZE / BS * 2 + 7;
+        let sectors = length + 100;
+
+        // A cancelled move should leave the partition where it was.
+        for &offset in &[50i64, -50] {
+            let skip = if offset > 0 { 10 } else { 60 };
+            let dir = TempDir::new("distinst-mvpart").unwrap();
+            let path = image(&dir, sectors);
+            let original = fs::read(&path).unwrap();
+
+            let coords = OffsetCoordinates { skip, offset, length };
+            let cancel = AtomicBool::new(true);
+            let result = move_partition(&path, coords, BS, &cancel, |_, _| ());
+
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+            let range = (skip * BS) as usize..((skip + length) * BS) as usize;
+            assert!(fs::read(&path).unwrap()[range.clone()] == original[range]);
+        }
+    }
+}
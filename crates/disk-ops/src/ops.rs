@@ -1,8 +1,8 @@
 //! Contains source code for applying physical disk operations to disks.
 
 use super::*;
-use disk_types::{FileSystem, PartitionTable, PartitionType};
-use external::{blockdev, mkfs};
+use disk_types::{partition_path, FileSystem, PartitionTable, PartitionType};
+use external::{mkfs, synchronize};
 use libparted::{Device, Disk as PedDisk, Partition as PedPartition};
 use mkpart::PartitionCreate;
 use parted::*;
@@ -29,6 +29,9 @@ pub fn get_partition<'a>(disk: &'a mut PedDisk, part: u32) -> io::Result<PedPart
 /// partitions.
 #[derive(Debug, Clone, PartialEq)]
 pub struct DiskOps<'a> {
+    /// Stops the arrays which hold the disk, and erases its RAID metadata.
+    pub wipe_raid:         bool,
+    pub erase:             Option<EraseMode>,
     pub mklabel:           Option<PartitionTable>,
     pub device_path:       &'a Path,
     pub remove_partitions: Vec<u64>,
@@ -47,8 +50,29 @@ impl<'a> DiskOps<'a> {
     /// The first stage of disk operations, where a new partition table may be
     /// generated
     pub fn remove(self) -> io::Result<ChangePartitions<'a>> {
+        self.remove_with_progress(|_, _, _| ())
+    }
+
+    /// Identical to `remove`, but reports the progress of overwriting the disk, if it is to be
+    /// erased.
+    ///
+    /// The callback receives the path of the disk, the percentage of the overwrite which has
+    /// been written, and 100.
+    pub fn remove_with_progress<F: FnMut(&Path, u64, u64)>(
+        self,
+        mut progress: F,
+    ) -> io::Result<ChangePartitions<'a>> {
         info!("{}: executing remove operations", self.device_path.display(),);
 
+        if self.wipe_raid {
+            wipe_raid_metadata(self.device_path)?;
+        }
+
+        if let Some(mode) = self.erase {
+            let device = self.device_path;
+            secure_erase(device, mode, |percent| progress(device, u64::from(percent), 100))?;
+        }
+
         if let Some(table) = self.mklabel {
             mklabel(self.device_path, table)?;
         }
@@ -91,6 +115,17 @@ impl<'a> ChangePartitions<'a> {
     /// The second stage of disk operations, where existing partitions will be
     /// modified.
     pub fn change(self) -> io::Result<CreatePartitions<'a>> {
+        self.change_with_progress(|_, _, _| ())
+    }
+
+    /// Identical to `change`, but reports the progress of partition moves.
+    ///
+    /// The callback receives the path of the partition being moved, the number of sectors
+    /// that have been moved, and the total number of sectors to move.
+    pub fn change_with_progress<F: FnMut(&Path, u64, u64)>(
+        self,
+        mut progress: F,
+    ) -> io::Result<CreatePartitions<'a>> {
         info!("{}: executing change operations", self.device_path.display(),);
 
         let mut device = open_device(self.device_path)?;
@@ -112,10 +147,9 @@ impl<'a> ChangePartitions<'a> {
                             Ok(()) => flags_changed = true,
                             Err(_) => {
                                 error!(
-                                    "unable to set {:?} for {}{}",
+                                    "unable to set {:?} for {}",
                                     flag,
-                                    self.device_path.display(),
-                                    change.num
+                                    partition_path(self.device_path, change.num as u32).display()
                                 );
                             }
                         }
@@ -149,7 +183,8 @@ impl<'a> ChangePartitions<'a> {
 
             if flags_changed || name_changed {
                 if name_changed {
-                    info!("renaming {}", change.num);
+                    let path = partition_path(self.device_path, change.num as u32);
+                    info!("renaming {}", path.display());
                 }
 
                 commit(&mut disk)?;
@@ -162,6 +197,7 @@ impl<'a> ChangePartitions<'a> {
         // TODO: Maybe not require a raw pointer here?
         let device = &mut device as *mut Device;
         for (change, resize_op) in resize_partitions {
+            let path = change.path.clone();
             transform(
                 change,
                 resize_op,
@@ -173,10 +209,11 @@ impl<'a> ChangePartitions<'a> {
 
                     Ok(())
                 },
-                // And this is the partition-creation function
-                |start, end, fs, flags, label, kind| {
-                    create_partition(
+                // And this is the partition-creation function, which may replace a partition.
+                |replace, start, end, fs, flags, label, kind| {
+                    replace_partition(
                         unsafe { &mut (*device) },
+                        replace,
                         &PartitionCreate {
                             path: self.device_path.to_path_buf(),
                             start_sector: start,
@@ -186,12 +223,15 @@ impl<'a> ChangePartitions<'a> {
                             kind,
                             flags,
                             label,
+                            fs_label: None,
+                            fs_uuid: None,
                         },
                     )?;
 
                     let res = get_partition_id_and_path(self.device_path, start as i64)?;
                     Ok(res)
                 },
+                |moved, total| progress(&path, moved, total),
             )?;
         }
 
@@ -209,7 +249,7 @@ impl<'a> ChangePartitions<'a> {
 pub struct CreatePartitions<'a> {
     device_path:       &'a Path,
     create_partitions: Vec<PartitionCreate>,
-    format_partitions: Vec<(PathBuf, FileSystem)>,
+    format_partitions: Vec<(PathBuf, FileSystem, Option<String>, Option<String>)>,
 }
 
 impl<'a> CreatePartitions<'a> {
@@ -234,22 +274,17 @@ impl<'a> CreatePartitions<'a> {
                     partition
                         .file_system
                         .expect("file system does not exist when creating partition"),
+                    partition.fs_label.clone(),
+                    partition.fs_uuid.clone(),
                 ));
             }
         }
 
-        // Attempt to sync three times before returning an error.
-        for attempt in 0..3 {
-            ::std::thread::sleep(::std::time::Duration::from_secs(1));
-            let result = blockdev(self.device_path, &["--flushbufs", "--rereadpt"]);
-            if result.is_err() && attempt == 2 {
-                result.map_err(|why| {
-                    io::Error::new(why.kind(), format!("failed to synchronize disk: {}", why))
-                })?
-            } else {
-                break;
-            }
-        }
+        // Ensure that the devices of the partitions exist before they are formatted.
+        let partitions = self.format_partitions.iter().map(|(path, ..)| path.as_path());
+        synchronize(self.device_path, &partitions.collect::<Vec<_>>()).map_err(|why| {
+            io::Error::new(why.kind(), format!("failed to synchronize disk: {}", why))
+        })?;
 
         Ok(FormatPartitions(self.format_partitions))
     }
@@ -284,7 +319,7 @@ pub fn get_partition_id_and_path(path: &Path, start_sector: i64) -> io::Result<(
 
 /// The final stage of disk operations, where all partitions to be formatted can be
 /// formatted in parallel.
-pub struct FormatPartitions(pub Vec<(PathBuf, FileSystem)>);
+pub struct FormatPartitions(pub Vec<(PathBuf, FileSystem, Option<String>, Option<String>)>);
 
 impl FormatPartitions {
     /// Finally, format all of the modified and created partitions.
@@ -292,9 +327,10 @@ impl FormatPartitions {
         info!("executing format operations");
         self.0
             .par_iter()
-            .map(|&(ref part, fs)| {
+            .map(|&(ref part, fs, ref label, ref uuid)| {
                 info!("formatting {} with {:?}", part.display(), fs);
-                mkfs(part, fs).map_err(|why| {
+                wipe_signatures(part)?;
+                mkfs(part, fs, label.as_deref(), uuid.as_deref()).map_err(|why| {
                     io::Error::new(
                         why.kind(),
                         format!("failed to format {} with {}: {}", part.display(), fs, why),
@@ -38,7 +38,9 @@ pub fn open_disk<'a>(device: &'a mut Device) -> io::Result<PedDisk<'a>> {
                 PedDisk::new_fresh(
                     &mut *device,
                     match Bootloader::detect() {
-                        Bootloader::Bios => PedDiskType::get("msdos").unwrap(),
+                        Bootloader::Bios | Bootloader::UBoot => {
+                            PedDiskType::get("msdos").unwrap()
+                        }
                         Bootloader::Efi => PedDiskType::get("gpt").unwrap(),
                     },
                 )
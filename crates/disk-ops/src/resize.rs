@@ -1,9 +1,15 @@
 use self::FileSystem::*;
-use super::{move_partition, BlockCoordinates, OffsetCoordinates, MEBIBYTE, MEGABYTE};
-use disk_types::{FileSystem, PartitionType};
-use external::{blockdev, fsck};
+use super::{move_partition, BlockCoordinates, OffsetCoordinates, KILL_SWITCH, MEBIBYTE, MEGABYTE};
+use disk_types::{sectors_used, FileSystem, PartitionType};
+use external::{
+    blkid_partition, block_sectors, blockdev, cryptsetup_resize, cryptsetup_resize_to, fsck,
+    lv_sizes, lvextend_free, lvreduce, physical_volumes_to_deactivate, pv_allocated_end,
+    pv_extents, pv_volume_extents, pvmove_extents, pvresize, pvresize_to, pvs,
+    retry::is_transient, udev_settle, vg_free_extents, LuksCredential, Retry,
+};
 use libparted::PartitionFlag;
 use std::{
+    fmt,
     io::{self, Write},
     path::{Path, PathBuf},
     process::{Command, Stdio},
@@ -21,6 +27,13 @@ pub const BTRFS: u8 = 0b100;
 pub const XFS: u8 = 0b1000;
 /// This is a NTFS partition.
 pub const NTFS: u8 = 0b10000;
+/// This is a LUKS or LVM partition, which is grown by growing the volumes within it.
+pub const VOLUME: u8 = 0b100000;
+
+/// Sectors at the start of a physical volume which are reserved for its label and metadata.
+const PV_METADATA: u64 = 2048;
+/// Sectors which must remain free in a file system after it has been shrunk.
+const SHRINK_MARGIN: u64 = 512 * 2048;
 
 /// Defines the unit of measurement to pass on to resizing tools.
 ///
@@ -113,16 +126,10 @@ pub fn resize_partition<P: AsRef<Path>>(
         resize_cmd.args(args);
     }
 
-    // Attempt to sync three times before returning an error.
-    for attempt in 0..3 {
-        ::std::thread::sleep(::std::time::Duration::from_secs(1));
-        let result = blockdev(&path, &["--flushbufs"]);
-        if result.is_err() && attempt == 2 {
-            result?;
-        } else {
-            break;
-        }
-    }
+    Retry::default().attempts(3).interval(1000).jitter(0.25).retry_if(is_transient, || {
+        let _ = udev_settle();
+        blockdev(&path, &["--flushbufs"])
+    })?;
 
     let fsck_options = if options & BTRFS != 0 {
         Some(("btrfsck", "--repair"))
@@ -154,6 +161,7 @@ pub fn resize_partition<P: AsRef<Path>>(
         };
 
         let status = if options & NTFS != 0 {
+            ntfs_verify(&npath, size)?;
             ntfs_dry_run(&npath, size)?;
 
             info!("executing {:?}", resize_cmd);
@@ -176,6 +184,322 @@ pub fn resize_partition<P: AsRef<Path>>(
     })
 }
 
+/// Grows the LVM physical volume on a partition which has been grown, then extends its largest
+/// logical volume, and the file system within it, into the space that was gained.
+///
+/// If the partition is a LUKS container, it must have been decrypted, so that the encrypted
+/// device can be grown with the `key` which unlocked it before the physical volume within it.
+/// The `progress` callback receives the number of steps that have been completed, and the
+/// total number of steps.
+pub fn grow_volume<F: FnMut(u64, u64)>(
+    path: &Path,
+    fs: FileSystem,
+    key: Option<&LuksCredential>,
+    mut progress: F,
+) -> io::Result<()> {
+    // The kernel must be aware of the new size of the partition before it is grown.
+    let _ = udev_settle();
+
+    let pv = if fs == Luks {
+        let pv = physical_volumes_to_deactivate(&[path]).into_iter().next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} must be decrypted before it can be grown", path.display()),
+            )
+        })?;
+
+        let name = pv.file_name().and_then(|name| name.to_str()).unwrap_or("").to_owned();
+        info!("growing encrypted device {}", name);
+        cryptsetup_resize(&name, key.map(LuksCredential::as_key))?;
+        pv
+    } else {
+        path.to_path_buf()
+    };
+
+    progress(1, 3);
+    info!("growing physical volume {}", pv.display());
+    pvresize(&pv)?;
+    progress(2, 3);
+
+    if let Some(Some(vg)) = pvs()?.remove(&pv) {
+        if vg_free_extents(&vg)? != 0 {
+            let largest = lv_sizes(&vg)?.into_iter().max_by_key(|&(_, size)| size);
+            if let Some((lv, _)) = largest {
+                info!("extending logical volume {}/{}", vg, lv);
+                lvextend_free(&vg, &lv)?;
+            }
+        }
+    }
+
+    progress(3, 3);
+    Ok(())
+}
+
+/// A layer of a LUKS or LVM stack that is shrunk, in the order that the layers are shrunk,
+/// from the file system inwards to the encrypted device outwards.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShrinkStep {
+    /// Shrinks the ext4 or btrfs file system of a logical volume to `sectors`.
+    FileSystem { path: PathBuf, fs: FileSystem, sectors: u64 },
+    /// Removes `extents` from the end of a logical volume.
+    LogicalVolume { group: String, volume: String, extents: u64 },
+    /// Moves the extents which are allocated from the extent `from` onwards before it.
+    MoveExtents { pv: PathBuf, from: u64 },
+    /// Shrinks a physical volume to `sectors`.
+    PhysicalVolume { pv: PathBuf, sectors: u64 },
+    /// Shrinks the encrypted device that contains the physical volume to `sectors`.
+    Encryption { name: String, sectors: u64, key: Option<LuksCredential> },
+}
+
+impl ShrinkStep {
+    pub fn apply(&self) -> io::Result<()> {
+        info!("{}", self);
+        match *self {
+            ShrinkStep::FileSystem { ref path, fs, sectors } => {
+                let (cmd, args, size, opts): (_, &[&str], _, _) = match fs {
+                    Btrfs => (
+                        "btrfs",
+                        &["filesystem", "resize"],
+                        format!("{}M", sectors / 2048),
+                        BTRFS | SIZE_BEFORE_PATH,
+                    ),
+                    _ => ("resize2fs", &[], format!("{}s", sectors), 0),
+                };
+
+                resize_partition(cmd, args, &size, path, fs.into(), opts)
+            }
+            ShrinkStep::LogicalVolume { ref group, ref volume, extents } => {
+                lvreduce(group, volume, extents, false)
+            }
+            ShrinkStep::MoveExtents { ref pv, from } => {
+                // Reducing a logical volume may have already freed the extents.
+                if pv_allocated_end(pv)? <= from {
+                    info!("no extents of {} need to be moved", pv.display());
+                    return Ok(());
+                }
+
+                pvmove_extents(pv, from)
+            }
+            ShrinkStep::PhysicalVolume { ref pv, sectors } => pvresize_to(pv, sectors, false),
+            ShrinkStep::Encryption { ref name, sectors, ref key } => {
+                cryptsetup_resize_to(name, sectors, key.as_ref().map(LuksCredential::as_key))
+            }
+        }
+    }
+}
+
+impl fmt::Display for ShrinkStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShrinkStep::FileSystem { ref path, sectors, .. } => {
+                write!(f, "shrinking file system of {} to {} sectors", path.display(), sectors)
+            }
+            ShrinkStep::LogicalVolume { ref group, ref volume, extents } => {
+                write!(f, "reducing {}/{} by {} extents", group, volume, extents)
+            }
+            ShrinkStep::MoveExtents { ref pv, from } => {
+                write!(f, "moving extents of {} from extent {} onwards", pv.display(), from)
+            }
+            ShrinkStep::PhysicalVolume { ref pv, sectors } => {
+                write!(f, "shrinking physical volume {} to {} sectors", pv.display(), sectors)
+            }
+            ShrinkStep::Encryption { ref name, sectors, .. } => {
+                write!(f, "shrinking encrypted device {} to {} sectors", name, sectors)
+            }
+        }
+    }
+}
+
+/// The steps required to shrink a LUKS or LVM partition, which are planned before any of them
+/// are performed. The partition itself must be shrunk after they are applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolumeShrink {
+    pub steps: Vec<ShrinkStep>,
+}
+
+impl VolumeShrink {
+    /// Determines the steps required to shrink a LUKS or LVM partition to `sectors`, without
+    /// making any changes. LVM tests the reduction of the logical volume, and the resize of the
+    /// physical volume. The other steps are only checked as they are applied.
+    ///
+    /// If the remaining extents can not hold the logical volumes on the physical volume, the
+    /// largest logical volume which lies entirely within the physical volume, and the ext4 or
+    /// btrfs file system within it, are reduced. Extents that remain allocated beyond the new
+    /// end of the physical volume, such as those of a swap volume which follows the root volume,
+    /// are then moved before it. If the partition is a LUKS container, it must have been
+    /// decrypted, and the `key` which unlocked it is required to resize it.
+    pub fn plan(
+        path: &Path,
+        fs: FileSystem,
+        sectors: u64,
+        key: Option<&LuksCredential>,
+    ) -> io::Result<VolumeShrink> {
+        let (pv, luks, header) = volume_of(path, fs)?;
+        let pv_sectors = sectors.saturating_sub(header);
+        if pv_sectors <= PV_METADATA {
+            return Err(shrink_error(format!(
+                "{} is too small for a physical volume",
+                path.display()
+            )));
+        }
+
+        let mut steps = Vec::new();
+        let mut tested = pv_sectors;
+        // Without a volume group, no extents are allocated.
+        if let Some(Some(group)) = pvs()?.remove(&pv) {
+            let (extent_size, _, allocated) = pv_extents(&pv)?;
+            let available = (pv_sectors - PV_METADATA) / extent_size;
+            if allocated > available {
+                let reduce = reducible_volume(&group, &pv, extent_size)?;
+                let extents = allocated - available;
+                if extents > reduce.extents {
+                    return Err(shrink_error(format!(
+                        "{}/{} can only be reduced by {} extents, but {} are required",
+                        group, reduce.volume, reduce.extents, extents
+                    )));
+                }
+
+                lvreduce(&group, &reduce.volume, extents, true)?;
+                steps.push(ShrinkStep::FileSystem {
+                    path:    reduce.path,
+                    fs:      reduce.fs,
+                    sectors: reduce.size - extents * extent_size,
+                });
+                steps.push(ShrinkStep::LogicalVolume { group, volume: reduce.volume, extents });
+            }
+
+            let allocated_end = pv_allocated_end(&pv)?;
+            if !steps.is_empty() || allocated_end > available {
+                steps.push(ShrinkStep::MoveExtents { pv: pv.clone(), from: available });
+            }
+
+            // Extents beyond the new end are only freed as the steps are applied, so until
+            // then, the physical volume can only be tested down to its last allocated extent.
+            tested = tested.max(PV_METADATA + allocated_end * extent_size);
+        }
+
+        pvresize_to(&pv, tested, true)?;
+        steps.push(ShrinkStep::PhysicalVolume { pv, sectors: pv_sectors });
+        if let Some(name) = luks {
+            steps.push(ShrinkStep::Encryption { name, sectors: pv_sectors, key: key.cloned() });
+        }
+
+        Ok(VolumeShrink { steps })
+    }
+
+    /// Applies each step in order. The `progress` callback receives the number of steps that
+    /// have been completed, and the total number of steps.
+    pub fn apply<F: FnMut(u64, u64)>(&self, mut progress: F) -> io::Result<()> {
+        let total = self.steps.len() as u64;
+        for (completed, step) in self.steps.iter().enumerate() {
+            step.apply()?;
+            progress(completed as u64 + 1, total);
+        }
+
+        Ok(())
+    }
+}
+
+/// Shrinks the LVM physical volume on a partition which will be shrunk to `sectors`, and the
+/// layers within and around it, as planned by `VolumeShrink::plan`.
+pub fn shrink_volume<F: FnMut(u64, u64)>(
+    path: &Path,
+    fs: FileSystem,
+    sectors: u64,
+    key: Option<&LuksCredential>,
+    progress: F,
+) -> io::Result<()> {
+    let _ = udev_settle();
+    VolumeShrink::plan(path, fs, sectors, key)?.apply(progress)
+}
+
+/// The sectors of a LUKS or LVM partition which can not be freed by shrinking the largest
+/// logical volume that lies within it, which is the smallest size the partition can be shrunk
+/// to.
+pub fn volume_sectors_used(path: &Path, fs: FileSystem) -> io::Result<u64> {
+    let (pv, _, header) = volume_of(path, fs)?;
+    let group = match pvs()?.remove(&pv) {
+        Some(Some(group)) => group,
+        _ => return Ok(header + PV_METADATA),
+    };
+
+    let (extent_size, _, allocated) = pv_extents(&pv)?;
+    let reducible =
+        reducible_volume(&group, &pv, extent_size).map_or(0, |reduce| reduce.extents);
+    Ok(header + PV_METADATA + allocated.saturating_sub(reducible) * extent_size)
+}
+
+/// The largest logical volume whose extents are all on a physical volume, which may be reduced
+/// by `extents`, leaving enough space for the files within it.
+struct ReducibleVolume {
+    volume:  String,
+    path:    PathBuf,
+    fs:      FileSystem,
+    size:    u64,
+    extents: u64,
+}
+
+fn reducible_volume(group: &str, pv: &Path, extent_size: u64) -> io::Result<ReducibleVolume> {
+    let (volume, size) =
+        largest_volume_within(&pv_volume_extents(pv)?, lv_sizes(group)?, extent_size)
+            .ok_or_else(|| {
+                shrink_error(format!(
+                    "no logical volume of {} lies entirely within {}",
+                    group,
+                    pv.display()
+                ))
+            })?;
+
+    let path = PathBuf::from(["/dev/", group, "/", &volume].concat());
+    let fs = match blkid_partition(&path) {
+        Some(fs @ Ext2) | Some(fs @ Ext3) | Some(fs @ Ext4) | Some(fs @ Btrfs) => fs,
+        fs => {
+            return Err(shrink_error(format!(
+                "{}/{} can not be shrunk, as its file system is {:?}",
+                group, volume, fs
+            )))
+        }
+    };
+
+    let used = sectors_used(&path, fs)?;
+    let extents = size.saturating_sub(used + SHRINK_MARGIN) / extent_size;
+    Ok(ReducibleVolume { volume, path, fs, size, extents })
+}
+
+/// The largest of the logical volumes, with their `sizes` in sectors, whose extents are all on
+/// the physical volume which holds the `extents` of each volume. The extents which are freed by
+/// reducing a volume that spans several physical volumes may be on any of them.
+fn largest_volume_within(
+    extents: &[(String, u64)],
+    sizes: Vec<(String, u64)>,
+    extent_size: u64,
+) -> Option<(String, u64)> {
+    sizes
+        .into_iter()
+        .filter(|(volume, size)| {
+            extents.iter().any(|(other, extents)| other == volume && extents * extent_size == *size)
+        })
+        .max_by_key(|&(_, size)| size)
+}
+
+/// The physical volume of a LUKS or LVM partition, the name of the encrypted device which
+/// contains it, and the sectors of the LUKS header before it.
+fn volume_of(path: &Path, fs: FileSystem) -> io::Result<(PathBuf, Option<String>, u64)> {
+    if fs != Luks {
+        return Ok((path.to_path_buf(), None, 0));
+    }
+
+    let pv = physical_volumes_to_deactivate(&[path]).into_iter().next().ok_or_else(|| {
+        shrink_error(format!("{} must be decrypted before it can be shrunk", path.display()))
+    })?;
+
+    let name = pv.file_name().and_then(|name| name.to_str()).unwrap_or("").to_owned();
+    let header = block_sectors(path)?.saturating_sub(block_sectors(&pv)?);
+    Ok((pv, Some(name), header))
+}
+
+fn shrink_error(why: String) -> io::Error { io::Error::new(io::ErrorKind::Other, why) }
+
 /// Defines the move and resize operations that the partition with this number
 /// will need to perform.
 ///
@@ -209,18 +533,26 @@ pub struct PartitionChange {
     pub new_flags:   Vec<PartitionFlag>,
     /// Defines the label to apply
     pub label:       Option<String>,
+    /// The credential which unlocked the partition, if it is an encrypted volume.
+    pub key:         Option<LuksCredential>,
 }
 
 /// Performs all move & resize operations for a given partition.
-pub fn transform<DELETE, CREATE>(
+///
+/// The `progress` callback receives the number of sectors moved, and the total number of
+/// sectors to move. If a move is cancelled, the partition will be recreated where its data
+/// was restored to, and an `Interrupted` error will be returned.
+pub fn transform<DELETE, CREATE, PROGRESS>(
     mut change: PartitionChange,
     mut resize: ResizeOperation,
     mut delete: DELETE,
     mut create: CREATE,
+    mut progress: PROGRESS,
 ) -> io::Result<()>
 where
     DELETE: FnMut(u32) -> io::Result<()>,
     CREATE: FnMut(
+        Option<u32>,
         u64,
         u64,
         Option<FileSystem>,
@@ -228,6 +560,7 @@ where
         Option<String>,
         PartitionType,
     ) -> io::Result<(i32, PathBuf)>,
+    PROGRESS: FnMut(u64, u64),
 {
     let mut moving = resize.is_moving();
     let shrinking = resize.is_shrinking();
@@ -262,6 +595,7 @@ where
             SIZE_BEFORE_PATH | NTFS,
         ),
         Some(Swap) => unreachable!("Disk::diff() handles this"),
+        Some(Luks) | Some(Lvm) => ("", &[], ResizeUnit::AbsoluteSectors, VOLUME),
         Some(Xfs) => {
             if shrinking {
                 return Err(io::Error::new(
@@ -300,17 +634,28 @@ where
     // moving, and recreated with the new size before attempting to grow.
     if shrinking {
         info!("shrinking {}", change.path.display());
-        resize_partition(cmd, args, &size, &change.path, fs, opts).map_err(|why| {
+        let result = if opts & VOLUME != 0 {
+            let (path, filesystem) = (&change.path, change.filesystem.expect("volume file system"));
+            let key = change.key.as_ref();
+            shrink_volume(path, filesystem, resize.absolute_sectors(), key, |step, total| {
+                info!("{}: completed step {} of {} to shrink volumes", path.display(), step, total)
+            })
+        } else {
+            resize_partition(cmd, args, &size, &change.path, fs, opts)
+        };
+
+        result.map_err(|why| {
             io::Error::new(
                 why.kind(),
                 format!("failed to shrink {}: {}", change.path.display(), why),
             )
         })?;
 
-        delete(change.num as u32)?;
+        // The data remains at the original start sector until it is moved.
         let (num, path) = create(
-            resize.new.start,
-            resize.new.end,
+            Some(change.num as u32),
+            resize.old.start,
+            resize.old.start + resize.absolute_sectors(),
             change.filesystem,
             change.new_flags.clone(),
             change.label.clone(),
@@ -320,23 +665,30 @@ where
         change.num = num;
         change.path = path;
     } else if growing {
-        delete(change.num as u32)?;
+        let mut replace = Some(change.num as u32);
         if resize.new.start != resize.old.start {
+            delete(change.num as u32)?;
+            replace = None;
+
             info!("moving before growing {}", change.path.display());
+            let original = (resize.old.start, resize.old.end);
             let abs_sectors = resize.absolute_sectors();
             resize.old.resize_to(abs_sectors); // TODO: NLL
 
-            move_partition(&change.device_path, resize.offset(), 512).map_err(|why| {
-                io::Error::new(
-                    why.kind(),
-                    format!("failed to move partition at {}: {}", change.path.display(), why),
-                )
-            })?;
+            let result = move_partition(
+                &change.device_path,
+                resize.offset(),
+                512,
+                &KILL_SWITCH,
+                &mut progress,
+            );
+            rollback_on_cancel(result, original, &change, &mut create)?;
 
             moving = false;
         }
 
         let (num, path) = create(
+            replace,
             resize.new.start,
             resize.new.end,
             change.filesystem,
@@ -349,7 +701,16 @@ where
         change.path = path;
 
         info!("growing {}", change.path.display());
-        resize_partition(cmd, args, &size, &change.path, fs, opts).map_err(|why| {
+        let result = if opts & VOLUME != 0 {
+            let (path, filesystem) = (&change.path, change.filesystem.expect("volume file system"));
+            grow_volume(path, filesystem, change.key.as_ref(), |step, total| {
+                info!("{}: completed step {} of {} to grow volumes", path.display(), step, total)
+            })
+        } else {
+            resize_partition(cmd, args, &size, &change.path, fs, opts)
+        };
+
+        result.map_err(|why| {
             io::Error::new(
                 why.kind(),
                 format!("failed to resize partition at {}: {}", change.path.display(), why),
@@ -365,15 +726,14 @@ where
         delete(change.num as u32)?;
         let abs_sectors = resize.absolute_sectors();
         resize.old.resize_to(abs_sectors); // TODO: NLL
+        let original = (resize.old.start, resize.old.end);
 
-        move_partition(&change.device_path, resize.offset(), 512).map_err(|why| {
-            io::Error::new(
-                why.kind(),
-                format!("failed to move partition at {}: {}", change.path.display(), why),
-            )
-        })?;
+        let result =
+            move_partition(&change.device_path, resize.offset(), 512, &KILL_SWITCH, &mut progress);
+        rollback_on_cancel(result, original, &change, &mut create)?;
 
         create(
+            None,
             resize.new.start,
             resize.new.end,
             change.filesystem,
@@ -386,6 +746,49 @@ where
     Ok(())
 }
 
+/// Recreates the deleted partition at its original location if the move was cancelled,
+/// as the data will have been moved back to where it was.
+fn rollback_on_cancel<CREATE>(
+    result: io::Result<()>,
+    (start, end): (u64, u64),
+    change: &PartitionChange,
+    create: &mut CREATE,
+) -> io::Result<()>
+where
+    CREATE: FnMut(
+        Option<u32>,
+        u64,
+        u64,
+        Option<FileSystem>,
+        Vec<PartitionFlag>,
+        Option<String>,
+        PartitionType,
+    ) -> io::Result<(i32, PathBuf)>,
+{
+    match result {
+        Ok(()) => Ok(()),
+        Err(why) => {
+            if why.kind() == io::ErrorKind::Interrupted {
+                warn!("restoring partition table entry for {}", change.path.display());
+                create(
+                    None,
+                    start,
+                    end,
+                    change.filesystem,
+                    change.new_flags.clone(),
+                    change.label.clone(),
+                    change.kind,
+                )?;
+            }
+
+            Err(io::Error::new(
+                why.kind(),
+                format!("failed to move partition at {}: {}", change.path.display(), why),
+            ))
+        }
+    }
+}
+
 fn ntfs_dry_run(path: &Path, size: &str) -> io::Result<()> {
     let mut consistency_check = Command::new("ntfsresize");
     consistency_check.args(&["-f", "-f", "--no-action", "-s"]).arg(size).arg(path);
@@ -402,18 +805,114 @@ fn ntfs_dry_run(path: &Path, size: &str) -> io::Result<()> {
     }
 }
 
-fn ntfs_consistency_check(path: &Path) -> io::Result<()> {
-    let mut consistency_check = Command::new("ntfsresize");
-    consistency_check.args(&["-i", "-f"]).arg(path);
+/// Ensures that the NTFS volume is safe to resize, and that it can be resized to `size` bytes.
+///
+/// Windows leaves the volume in an inconsistent state when it is hibernated, or when
+/// Fast Startup is enabled, so the volume must be cleanly shut down before resizing.
+fn ntfs_verify(path: &Path, size: &str) -> io::Result<()> {
+    let mut info = Command::new("ntfsresize");
+    info.args(&["--info", "--no-progress-bar"]).arg(path);
+
+    info!("executing {:?}", info);
+    let output = info.stdin(Stdio::null()).output()?;
+    let output = [output.stdout, output.stderr].concat();
+    let minimum = parse_ntfs_info(&String::from_utf8_lossy(&output))?;
+
+    let size = size.parse::<u64>().map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("invalid NTFS size: {}", size))
+    })?;
+
+    if size < minimum {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "NTFS volume cannot be shrunk below {} bytes, but {} was requested",
+                minimum, size
+            ),
+        ));
+    }
 
-    info!("executing {:?}", consistency_check);
-    let mut child = consistency_check.stdin(Stdio::piped()).spawn()?;
-    child.stdin.as_mut().expect("failed to get stdin").write_all(b"y\n")?;
+    Ok(())
+}
 
-    let status = child.wait()?;
-    if status.success() {
-        Ok(())
-    } else {
-        Err(io::Error::new(io::ErrorKind::Other, format!("ntfsresize exited with {:?}", status)))
+/// Parses the minimum size of the volume, in bytes, from the output of `ntfsresize --info`.
+fn parse_ntfs_info(output: &str) -> io::Result<u64> {
+    let lower = output.to_lowercase();
+    if lower.contains("hibernat") || lower.contains("unsafe state") {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Windows is hibernated, or Fast Startup is enabled: shut down Windows fully before \
+             resizing its partition",
+        ));
+    }
+
+    if lower.contains("scheduled for") || lower.contains("uncleanly") {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "NTFS volume is marked as dirty: run chkdsk from Windows before resizing its partition",
+        ));
+    }
+
+    output
+        .lines()
+        .find(|line| line.starts_with("You might resize at"))
+        .and_then(|line| line.split_whitespace().nth(4))
+        .and_then(|bytes| bytes.parse::<u64>().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                format!("unable to determine the minimum size of NTFS volume: {}", output.trim()),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NTFS_INFO: &str = r#"ntfsresize v2017.3.23 (libntfs-3g)
+Device name        : /dev/sdb4
+NTFS volume version: 3.1
+Cluster size       : 4096 bytes
+Current volume size: 21474832896 bytes (21475 MB)
+Current device size: 21474836480 bytes (21475 MB)
+Checking filesystem consistency ...
+Accounting clusters ...
+Space in use       : 69 MB (0.3%)
+Collecting resizing constraints ...
+You might resize at 68227072 bytes or 69 MB (freeing 21406 MB).
+Please make a test run using both the -n and -s options before real resizing!"#;
+
+    #[test]
+    fn ntfs_info() {
+        assert_eq!(parse_ntfs_info(NTFS_INFO).unwrap(), 68_227_072);
+
+        let hibernated = "The NTFS partition is in an unsafe state. Please resume and shutdown \
+                          Windows fully (no hibernation or fast restarting)";
+        assert!(parse_ntfs_info(hibernated).is_err());
+
+        let dirty = "The volume is scheduled for a check or it was shutdown uncleanly.";
+        assert!(parse_ntfs_info(dirty).is_err());
+    }
+
+    #[test]
+    fn volumes_within_two_pvs() {
+        // The home volume of the group is the largest, but it spans both physical volumes.
+        let extent_size = 8192;
+        let sizes = || {
+            vec![
+                ("root".to_owned(), 100 * extent_size),
+                ("home".to_owned(), 250 * extent_size),
+                ("swap".to_owned(), 10 * extent_size),
+            ]
+        };
+
+        let sda2 = [("root".to_owned(), 100), ("home".to_owned(), 50), ("swap".to_owned(), 10)];
+        let sdb1 = [("home".to_owned(), 200)];
+        assert_eq!(
+            largest_volume_within(&sda2, sizes(), extent_size),
+            Some(("root".to_owned(), 100 * extent_size))
+        );
+        assert_eq!(largest_volume_within(&sdb1, sizes(), extent_size), None);
     }
 }
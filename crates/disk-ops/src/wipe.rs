@@ -0,0 +1,124 @@
+//! Clears stale signatures from disks and partitions that are about to be reused.
+//!
+//! Superblocks of ZFS pools, md arrays, and LVM physical volumes survive the partition table
+//! being rewritten, as they are stored within the partitions and at the end of the disk. If
+//! left behind, they are detected by blkid and udev, and may be assembled by the new system.
+
+use external::{mdadm_stop, mdadm_zero_superblock, retry::is_transient, wipefs, Retry};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Erases all signatures from a device, and from each of its existing partitions.
+///
+/// Partitions are wiped first, as their signatures are no longer reachable once a new
+/// partition table has been written.
+pub fn wipe_signatures<P: AsRef<Path>>(device: P) -> io::Result<()> {
+    let device = device.as_ref();
+    for partition in partitions_of(device) {
+        wipe(&partition)?;
+    }
+
+    wipe(device)
+}
+
+/// Stops the RAID arrays which hold the disk at `device` or its partitions, and erases the
+/// RAID metadata from the disk, so that it will not be assembled into an array again.
+pub fn wipe_raid_metadata<P: AsRef<Path>>(device: P) -> io::Result<()> {
+    let device = device.as_ref();
+    let mut arrays = Vec::new();
+    for member in partitions_of(device).iter().map(PathBuf::as_path).chain(Some(device)) {
+        for array in holders_of(member) {
+            if !arrays.contains(&array) {
+                arrays.push(array);
+            }
+        }
+    }
+
+    // The volumes of an IMSM or DDF container are numbered below the container, and must be
+    // stopped before it.
+    sort_arrays(&mut arrays);
+    for array in &arrays {
+        mdadm_stop(array)?;
+    }
+
+    mdadm_zero_superblock(device)
+}
+
+/// Sorts the arrays by their number, so that `md9` is before `md10`.
+fn sort_arrays(arrays: &mut [PathBuf]) {
+    let number = |array: &Path| {
+        array.file_name()?.to_str()?.trim_start_matches("md").parse::<u32>().ok()
+    };
+
+    arrays.sort_by(|a, b| number(a).cmp(&number(b)).then_with(|| a.cmp(b)));
+}
+
+fn wipe(device: &Path) -> io::Result<()> {
+    let retry = Retry::default().attempts(3).interval(500);
+    retry.retry_if(is_transient, || wipefs(device)).map_err(|why| {
+        io::Error::new(
+            why.kind(),
+            format!("failed to wipe signatures from {}: {}", device.display(), why),
+        )
+    })
+}
+
+/// The partitions of a disk, as currently known to the kernel.
+fn partitions_of(device: &Path) -> Vec<PathBuf> {
+    let entries = match sys_block(device).and_then(|path| fs::read_dir(path).ok()) {
+        Some(entries) => entries,
+        None => return Vec::new(),
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().join("partition").exists())
+        .map(|entry| Path::new("/dev").join(entry.file_name()))
+        .collect()
+}
+
+/// The software RAID arrays which are assembled from the device.
+fn holders_of(device: &Path) -> Vec<PathBuf> {
+    let holders = sys_block(device).map(|path| path.join("holders"));
+    let entries = match holders.and_then(|path| fs::read_dir(path).ok()) {
+        Some(entries) => entries,
+        None => return Vec::new(),
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("md"))
+        .map(|entry| Path::new("/dev").join(entry.file_name()))
+        .collect()
+}
+
+fn sys_block(device: &Path) -> Option<PathBuf> {
+    device
+        .canonicalize()
+        .ok()
+        .and_then(|path| path.file_name().map(|name| Path::new("/sys/class/block").join(name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn array_order() {
+        let mut arrays = ["/dev/md127", "/dev/md10", "/dev/md2", "/dev/md126", "/dev/md9"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+
+        sort_arrays(&mut arrays);
+        assert_eq!(arrays, vec![
+            PathBuf::from("/dev/md2"),
+            PathBuf::from("/dev/md9"),
+            PathBuf::from("/dev/md10"),
+            PathBuf::from("/dev/md126"),
+            PathBuf::from("/dev/md127"),
+        ]);
+    }
+}
@@ -1,8 +1,34 @@
-use std::{io, fs, fmt::Debug};
+use std::{io, fs, fmt::{self, Debug}};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use sysfs_class::{Block, SysClass};
 
+/// The reason why a block device may not be written to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadOnlyCause {
+    /// The kernel has flagged the device as read-only.
+    ReadOnlyFlag,
+    /// The write-protect switch on an SD card is enabled.
+    WriteProtectSwitch,
+    /// The iSCSI target exports the LUN as read-only.
+    ReadOnlyLun,
+    /// The device could not be opened for writing.
+    OpenedReadOnly,
+}
+
+impl fmt::Display for ReadOnlyCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            ReadOnlyCause::ReadOnlyFlag => "the kernel has marked the device as read-only",
+            ReadOnlyCause::WriteProtectSwitch => {
+                "the write-protect switch on the SD card is enabled"
+            }
+            ReadOnlyCause::ReadOnlyLun => "the iSCSI target exports the LUN as read-only",
+            ReadOnlyCause::OpenedReadOnly => "the device could not be opened for writing",
+        })
+    }
+}
+
 /// Methods that all block devices share, whether they are partitions or disks.
 ///
 /// This trait is required to implement other disk traits.
@@ -24,6 +50,37 @@ pub trait BlockDeviceExt {
             .map_or(false, |block| block.ro().ok() == Some(1))
     }
 
+    /// Determines why the device is read-only, if it is read-only.
+    ///
+    /// # Notes
+    /// This is only applicable for disk devices.
+    fn read_only_cause(&self) -> Option<ReadOnlyCause> {
+        let sys_path = self.sys_block_path();
+        let block = Block::from_path(&sys_path).ok()?;
+        if block.ro().ok() != Some(1) {
+            return None;
+        }
+
+        // SD cards report the state of their write-protect switch through the ro flag.
+        let device_type = fs::read_to_string(sys_path.join("device/type")).unwrap_or_default();
+        if device_type.trim() == "SD" {
+            return Some(ReadOnlyCause::WriteProtectSwitch);
+        }
+
+        // iSCSI devices are attached to the host through a session.
+        let is_iscsi = sys_path.join("device").canonicalize().ok().map_or(false, |device| {
+            device
+                .components()
+                .any(|c| c.as_os_str().to_str().map_or(false, |c| c.starts_with("session")))
+        });
+
+        if is_iscsi {
+            return Some(ReadOnlyCause::ReadOnlyLun);
+        }
+
+        Some(ReadOnlyCause::ReadOnlyFlag)
+    }
+
     /// Checks if the device is a removable device.
     ///
     /// # Notes
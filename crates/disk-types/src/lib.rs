@@ -5,10 +5,11 @@ extern crate log;
 
 mod device;
 mod fs;
+mod naming;
 mod partition;
 mod sector;
 mod table;
 mod usage;
 mod utils;
 
-pub use self::{device::*, fs::*, partition::*, sector::*, table::*, usage::*};
+pub use self::{device::*, fs::*, naming::*, partition::*, sector::*, table::*, usage::*};
@@ -0,0 +1,79 @@
+//! How the kernel names the partitions of a disk.
+
+use std::path::{Path, PathBuf};
+
+/// The path of partition `number` of a disk, such as `/dev/sda1`. When the name of the disk
+/// ends with a digit, as with eMMC and NVMe devices, the number is separated from it by a `p`,
+/// as in `/dev/mmcblk0p1`.
+pub fn partition_path(disk: &Path, number: u32) -> PathBuf {
+    let mut path = disk.as_os_str().to_owned();
+    if disk.to_str().map_or(false, |disk| disk.ends_with(|c: char| c.is_ascii_digit())) {
+        path.push("p");
+    }
+
+    path.push(number.to_string());
+    PathBuf::from(path)
+}
+
+/// Whether `partition` names a partition of `disk`, such as `mmcblk0p1` of `mmcblk0`, but not
+/// `mmcblk0boot0`, `mmcblk10p1`, or `sdaa1`.
+pub fn is_partition_of(partition: &str, disk: &str) -> bool {
+    let number = match partition.strip_prefix(disk) {
+        Some(number) if disk.ends_with(|c: char| c.is_ascii_digit()) => number.strip_prefix('p'),
+        number => number,
+    };
+
+    number.map_or(false, |number| {
+        number.starts_with(|c: char| c.is_ascii_digit() && c != '0')
+            && number.bytes().all(|byte| byte.is_ascii_digit())
+    })
+}
+
+/// Whether the device is one of the hardware partitions of an eMMC device, such as
+/// `mmcblk0boot0`, which hold the firmware of the board, and are read-only until they are
+/// unlocked. These are listed beside the disks, but may not be installed to.
+pub fn is_mmc_hardware_partition(name: &str) -> bool {
+    let suffix = match name.strip_prefix("mmcblk") {
+        Some(rest) => rest.trim_start_matches(|c: char| c.is_ascii_digit()),
+        None => return false,
+    };
+
+    suffix == "rpmb"
+        || suffix.strip_prefix("boot").map_or(false, |number| {
+            !number.is_empty() && number.bytes().all(|byte| byte.is_ascii_digit())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partition_paths() {
+        assert_eq!(partition_path(Path::new("/dev/sda"), 1), Path::new("/dev/sda1"));
+        assert_eq!(partition_path(Path::new("/dev/mmcblk0"), 2), Path::new("/dev/mmcblk0p2"));
+        assert_eq!(partition_path(Path::new("/dev/nvme0n1"), 10), Path::new("/dev/nvme0n1p10"));
+    }
+
+    #[test]
+    fn partitions_of() {
+        assert!(is_partition_of("sda1", "sda"));
+        assert!(is_partition_of("/dev/mmcblk0p1", "/dev/mmcblk0"));
+        assert!(is_partition_of("nvme0n1p12", "nvme0n1"));
+        assert!(!is_partition_of("sda", "sda"));
+        assert!(!is_partition_of("sdaa1", "sda"));
+        assert!(!is_partition_of("mmcblk0boot0", "mmcblk0"));
+        assert!(!is_partition_of("mmcblk10p1", "mmcblk1"));
+        assert!(!is_partition_of("nvme0n10", "nvme0n1"));
+    }
+
+    #[test]
+    fn mmc_hardware_partitions() {
+        assert!(is_mmc_hardware_partition("mmcblk0boot0"));
+        assert!(is_mmc_hardware_partition("mmcblk1boot1"));
+        assert!(is_mmc_hardware_partition("mmcblk0rpmb"));
+        assert!(!is_mmc_hardware_partition("mmcblk0"));
+        assert!(!is_mmc_hardware_partition("mmcblk0p1"));
+        assert!(!is_mmc_hardware_partition("sda"));
+    }
+}
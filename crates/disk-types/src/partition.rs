@@ -5,7 +5,7 @@ use crate::{
     usage::sectors_used,
 };
 use libparted::PartitionFlag;
-use os_detect::{detect_os_from_device, OS};
+use os_detect::{detect_bsd_from_device, detect_os_from_device, OS};
 use std::{io, path::Path};
 use sys_mount::*;
 use tempdir::TempDir;
@@ -80,8 +80,13 @@ pub trait PartitionExt: BlockDeviceExt + SectorExt {
 
     /// Detects if an OS is installed to this partition, and if so, what the OS
     /// is named.
+    ///
+    /// Partitions without a supported file system are checked for BSD installs.
     fn probe_os(&self) -> Option<OS> {
-        self.get_file_system().and_then(|fs| detect_os_from_device(self.get_device_path(), fs))
+        match self.get_file_system() {
+            Some(fs) => detect_os_from_device(self.get_device_path(), fs),
+            None => detect_bsd_from_device(self.get_device_path()),
+        }
     }
 
     /// True if the sectors in the compared partition differs from the source.
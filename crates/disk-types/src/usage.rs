@@ -1,8 +1,10 @@
 use crate::fs::FileSystem;
 use std::{
+    fs,
     io::{self, BufRead, Cursor},
-    path::Path,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
+    str::FromStr,
 };
 
 /// Executes a given file system's dump command to obtain the minimum shrink
@@ -81,14 +83,162 @@ pub fn sectors_used<P: AsRef<Path>>(part: P, fs: FileSystem) -> io::Result<u64>
             let reader = Cursor::new(cmd.stdout).lines().skip(1);
             get_btrfs_usage(reader)
         }
+        Xfs => {
+            let cmd = Command::new("xfs_db")
+                .args(&["-r", "-c", "sb 0", "-c", "print dblocks fdblocks blocksize"])
+                .arg(part.as_ref())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .output()?;
+
+            get_xfs_usage(Cursor::new(cmd.stdout).lines())
+        }
+        F2fs => {
+            let cmd = Command::new("dump.f2fs")
+                .args(&["-d", "1"])
+                .arg(part.as_ref())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .output()?;
+
+            get_f2fs_usage(Cursor::new(cmd.stdout).lines())
+        }
+        Exfat => {
+            let cmd = Command::new("dump.exfat")
+                .arg(part.as_ref())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .output()?;
+
+            get_exfat_usage(Cursor::new(cmd.stdout).lines())
+        }
+        Lvm => {
+            let cmd = Command::new("pvs")
+                .args(&["--noheadings", "--nosuffix", "--units", "s", "-o", "pe_start,pv_used"])
+                .arg(part.as_ref())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .output()?;
+
+            get_lvm_usage(&String::from_utf8_lossy(&cmd.stdout))
+        }
+        Luks => {
+            // The file system within can only be measured once the partition is unlocked.
+            let mapped = luks_mapping(part.as_ref()).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "encrypted partition is not unlocked")
+            })?;
+
+            let inner = probe_file_system(&mapped)?;
+            let header = device_sectors(part.as_ref())?.saturating_sub(device_sectors(&mapped)?);
+            sectors_used(&mapped, inner).map(|used| used + header)
+        }
         _ => Err(io::Error::new(io::ErrorKind::NotFound, "unsupported file system")),
     }
 }
 
+/// The device that an unlocked LUKS partition is mapped to.
+fn luks_mapping(part: &Path) -> Option<PathBuf> {
+    let name = part.canonicalize().ok()?.file_name()?.to_owned();
+    let mut holders = fs::read_dir(Path::new("/sys/class/block").join(name).join("holders")).ok()?;
+    holders.next()?.ok().map(|holder| Path::new("/dev").join(holder.file_name()))
+}
+
+/// The size of a block device, in 512-byte sectors.
+fn device_sectors(device: &Path) -> io::Result<u64> {
+    let name = device.canonicalize()?;
+    let name = name
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "device has no name"))?;
+
+    fs::read_to_string(Path::new("/sys/class/block").join(name).join("size"))?
+        .trim()
+        .parse::<u64>()
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+}
+
+/// Determines the file system of a device with blkid.
+fn probe_file_system(device: &Path) -> io::Result<FileSystem> {
+    let cmd = Command::new("blkid")
+        .args(&["-o", "export"])
+        .arg(device)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .output()?;
+
+    let output = String::from_utf8_lossy(&cmd.stdout);
+    let field = |name: &str| {
+        output.lines().find_map(|line| split_at_char(line, '=').filter(|&(key, _)| key == name))
+    };
+
+    match field("TYPE").map(|(_, value)| value) {
+        Some("vfat") => match field("VERSION") {
+            Some((_, "FAT16")) => Ok(FileSystem::Fat16),
+            _ => Ok(FileSystem::Fat32),
+        },
+        Some(kind) => FileSystem::from_str(kind)
+            .map_err(|why| io::Error::new(io::ErrorKind::NotFound, why)),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "device has no file system")),
+    }
+}
+
 fn get_btrfs_usage<R: Iterator<Item = io::Result<String>>>(mut reader: R) -> io::Result<u64> {
     parse_field_as_unit(&mut reader, "Total devices", 6).map(|used| used / 512)
 }
 
+fn get_xfs_usage<R: Iterator<Item = io::Result<String>>>(reader: R) -> io::Result<u64> {
+    let keys = &["dblocks", "fdblocks", "blocksize"];
+    match parse_keys(reader, keys, |line| split_at_char(line, '='))?[..] {
+        [total, free, block_size] => Ok((total.saturating_sub(free) * block_size) / 512),
+        _ => unreachable!(),
+    }
+}
+
+fn get_f2fs_usage<R: Iterator<Item = io::Result<String>>>(reader: R) -> io::Result<u64> {
+    // Blocks are always 4 KiB, and the main area begins after the metadata.
+    match parse_keys(reader, &["main_blkaddr", "valid_block_count"], split_f2fs_field)?[..] {
+        [metadata, used] => Ok((metadata + used) * 8),
+        _ => unreachable!(),
+    }
+}
+
+/// Fields are displayed by dump.f2fs as `name [0x hex : decimal]`.
+fn split_f2fs_field(line: &str) -> Option<(&str, &str)> {
+    let open = line.find('[')?;
+    let colon = line.rfind(':')?;
+    let close = line.rfind(']')?;
+    if open < colon && colon < close {
+        Some((line[..open].trim(), line[colon + 1..close].trim()))
+    } else {
+        None
+    }
+}
+
+fn get_exfat_usage<R: Iterator<Item = io::Result<String>>>(reader: R) -> io::Result<u64> {
+    let keys = &[
+        "Cluster Heap Offset (sector offset)",
+        "Cluster Count",
+        "Free Clusters",
+        "Bytes per Sector",
+        "Sectors per Cluster",
+    ];
+
+    match parse_keys(reader, keys, |line| split_at_char(line, ':'))?[..] {
+        [heap_offset, clusters, free, sector_size, cluster_sectors] => {
+            let used = heap_offset + clusters.saturating_sub(free) * cluster_sectors;
+            Ok(used * sector_size / 512)
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn get_lvm_usage(output: &str) -> io::Result<u64> {
+    let mut fields = output.split_whitespace().map(|field| field.parse::<u64>());
+    match (fields.next(), fields.next()) {
+        (Some(Ok(metadata)), Some(Ok(used))) => Ok(metadata + used),
+        _ => Err(io::Error::new(io::ErrorKind::Other, "invalid pvs output")),
+    }
+}
+
 fn get_ext4_usage<R: Iterator<Item = io::Result<String>>>(mut reader: R) -> io::Result<u64> {
     let total_blocks = parse_field(&mut reader, "Block count:", 2)?;
     let free_blocks = parse_field(&mut reader, "Free blocks:", 2)?;
@@ -166,6 +316,39 @@ fn parse_fsck_cluster_summary<R: Iterator<Item = io::Result<String>>>(
     }
 }
 
+/// Splits a line into a trimmed key and value at the first occurrence of `separator`.
+fn split_at_char(line: &str, separator: char) -> Option<(&str, &str)> {
+    line.find(separator).map(|pos| (line[..pos].trim(), line[pos + 1..].trim()))
+}
+
+/// Obtains the values of each of the `keys`, in the order that the keys are given, from lines
+/// which `split` divides into keys and values.
+fn parse_keys<R, F>(reader: R, keys: &[&str], split: F) -> io::Result<Vec<u64>>
+where
+    R: Iterator<Item = io::Result<String>>,
+    F: Fn(&str) -> Option<(&str, &str)>,
+{
+    let mut values = vec![None; keys.len()];
+    for line in reader {
+        let line = line?;
+        if let Some((key, value)) = split(&line) {
+            if let Some(pos) = keys.iter().position(|&wanted| wanted == key.trim()) {
+                values[pos] = value.trim().parse::<u64>().ok();
+            }
+        }
+    }
+
+    values
+        .into_iter()
+        .zip(keys)
+        .map(|(value, key)| {
+            value.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::Other, format!("invalid usage output: no {}", key))
+            })
+        })
+        .collect()
+}
+
 fn parse_field<R: Iterator<Item = io::Result<String>>>(
     reader: &mut R,
     field: &str,
@@ -429,4 +612,53 @@ Please make a test run using both the -n and -s options before real resizing!"#;
         let reader = BTRFS_INPUT.lines().map(|x| Ok(x.into()));
         assert_eq!(get_btrfs_usage(reader).unwrap(), 224);
     }
+
+    const XFS_INPUT: &str = "dblocks = 5242880\nfdblocks = 5215143\nblocksize = 4096\n";
+
+    #[test]
+    fn xfs_usage() {
+        let reader = XFS_INPUT.lines().map(|x| Ok(x.into()));
+        assert_eq!(get_xfs_usage(reader).unwrap(), 221_896);
+    }
+
+    const F2FS_INPUT: &str = r#"Info: Segments per section = 1
+Info: total FS sectors = 41943040 (20480 MB)
+main_blkaddr                  		[0x    5200 : 20992]
+ssa_blkaddr                   		[0x    3a00 : 14848]
+user_block_count              		[0x  4f0a00 : 5179904]
+valid_block_count             		[0x    1a4e : 6734]
+valid_node_count              		[0x       3 : 3]"#;
+
+    #[test]
+    fn f2fs_usage() {
+        let reader = F2FS_INPUT.lines().map(|x| Ok(x.into()));
+        assert_eq!(get_f2fs_usage(reader).unwrap(), (20992 + 6734) * 8);
+    }
+
+    const EXFAT_INPUT: &str = r#"-------------- Dump Boot sector region --------------
+Volume Length(sectors): 		41943040
+FAT Offset(sector offset): 		2048
+FAT Length(sectors): 			2560
+Cluster Heap Offset (sector offset): 	6144
+Cluster Count: 				327632
+Root Cluster (cluster offset): 		5
+Volume Serial: 				0x1234abcd
+Bytes per Sector: 			512
+Sectors per Cluster: 			128
+
+----------------- Dump Root entries -----------------
+Free Clusters: 				327000
+"#;
+
+    #[test]
+    fn exfat_usage() {
+        let reader = EXFAT_INPUT.lines().map(|x| Ok(x.into()));
+        assert_eq!(get_exfat_usage(reader).unwrap(), 6144 + 632 * 128);
+    }
+
+    #[test]
+    fn lvm_usage() {
+        assert_eq!(get_lvm_usage("    2048 41938944\n").unwrap(), 41_940_992);
+        assert!(get_lvm_usage("").is_err());
+    }
 }
@@ -1,13 +1,19 @@
 use super::{
     super::{
-        serial::get_serial, BlockDeviceExt, DiskError, DiskExt, Disks, FileSystem, PartitionError,
-        PartitionFlag, PartitionInfo, PartitionTable, PartitionType,
+        network::NetworkDisk,
+        raid::{RaidFormat, RaidMetadata},
+        rst,
+        serial::{get_serial, DeviceIdentity, UdevSerials},
+        smart::SmartStatus,
+        topology::DiskTopology,
+        BlockDeviceExt, DiskError, DiskExt, Disks, FileSystem, PartitionError, PartitionFlag,
+        PartitionInfo, PartitionTable, PartitionType,
     },
     partitions::{FORMAT, REMOVE, SOURCE, SWAPPED},
-    PVS,
+    LvmEncryption, PVS,
 };
-use disk_types::{PartitionExt, PartitionTableExt, SectorExt};
-use crate::external::{is_encrypted, pvs};
+use disk_types::{PartitionExt, PartitionTableExt, ReadOnlyCause, SectorExt};
+use crate::external::{is_encrypted, pvs, relocate_backup_gpt, reread_partitions};
 use libparted::{Device, DeviceType, Disk as PedDisk};
 use operations::{
     parted::{get_device, open_disk},
@@ -24,6 +30,41 @@ use std::{
 };
 use sys_mount::{unmount, UnmountFlags};
 
+/// The sector which the first partition on a MSDOS disk must start at, or after, in order for
+/// GRUB to embed its core image in the gap that follows the MBR.
+pub const MBR_GAP_END: u64 = 2048;
+
+/// Sectors at the end of a GPT disk which hold the backup header and partition entries.
+const GPT_BACKUP_SECTORS: u64 = 33;
+
+/// The boundary, in bytes, which new partitions begin and end on.
+const ALIGNMENT: u64 = 1024 * 1024;
+
+/// The percentage of its sectors which aligning a new partition may take from it, beyond the
+/// two alignments which rounding its start and end may cost.
+const MAX_ALIGNMENT_SHRINK: u64 = 10;
+
+/// Rounds `sector` up to the next multiple of `alignment`.
+fn align_up(sector: u64, alignment: u64) -> u64 {
+    (sector + alignment - 1) / alignment * alignment
+}
+
+/// Rounds `sector` down to the previous multiple of `alignment`.
+fn align_down(sector: u64, alignment: u64) -> u64 { sector / alignment * alignment }
+
+/// The `end` of a partition which begins at `start`, once the partition is resized to a whole
+/// number of MiB, in 512-byte sectors.
+pub(crate) fn resized_end(start: u64, end: u64) -> u64 {
+    end - end.saturating_sub(start) % (2 * 1024)
+}
+
+/// Whether the `part` does not lie at the `start` and `size` which the kernel reports for it,
+/// which are in 512-byte sectors, of which each sector of the disk holds `sectors`.
+pub(crate) fn is_relocated(part: &PartitionInfo, sectors: u64, start: u64, size: u64) -> bool {
+    part.start_sector * sectors != start
+        || (part.end_sector - part.start_sector + 1) * sectors != size
+}
+
 /// Detects a partition on the device, if it exists.
 /// Useful for detecting if a LUKS device has a file system.
 pub fn detect_fs_on_device(path: &Path) -> Option<PartitionInfo> {
@@ -83,6 +124,25 @@ pub fn detect_fs_on_device(path: &Path) -> Option<PartitionInfo> {
     None
 }
 
+/// What is wrong with a partition which is yet to be created.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlignmentIssueKind {
+    /// The partition does not begin and end on 1 MiB boundaries.
+    Misaligned,
+    /// The partition shares sectors with the partition which begins at the `other` sector.
+    Overlaps { other: u64 },
+    /// The partition extends past the last sector which a partition may end at.
+    OutOfBounds,
+}
+
+/// A partition which is yet to be created, that libparted may fail to create as it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlignmentIssue {
+    pub kind:         AlignmentIssueKind,
+    pub start_sector: u64,
+    pub end_sector:   u64,
+}
+
 /// Contains all of the information relevant to a given device.
 ///
 /// # Note
@@ -108,9 +168,20 @@ pub struct Disk {
     pub table_type:  Option<PartitionTable>,
     /// Whether the device is currently in a read-only state.
     pub read_only:   bool,
+    /// Whether the media of the device may be removed, such as an SD card.
+    pub removable:   bool,
     /// Defines whether the device should be wiped or not. The `table_type`
     /// field will be used to determine which table to write to the disk.
     pub mklabel:     bool,
+    /// Erases the data on the device before the new partition table is written.
+    pub erase:       Option<EraseMode>,
+    /// RAID metadata on the device, which is wiped when a new partition table is written.
+    pub raid:        Option<RaidMetadata>,
+    /// Whether the device is a member of an Intel RST array which is in use, and which may not
+    /// be partitioned at all. The metadata of an array which is not in use is stale.
+    pub rst_active:  bool,
+    /// How the device is attached, and how it reads and writes its sectors.
+    pub topology:    DiskTopology,
     /// The partitions that are stored on the device.
     pub partitions:  Vec<PartitionInfo>,
 }
@@ -121,6 +192,10 @@ impl BlockDeviceExt for Disk {
     fn get_mount_point(&self) -> Option<&Path> { self.mount_point.as_deref() }
 
     fn is_read_only(&self) -> bool { self.read_only }
+
+    fn is_removable(&self) -> bool { self.removable }
+
+    fn is_rotational(&self) -> bool { self.topology.rotational }
 }
 
 impl SectorExt for Disk {
@@ -157,22 +232,78 @@ impl DiskExt for Disk {
     fn get_partitions(&self) -> &[PartitionInfo] { &self.partitions }
 
     fn push_partition(&mut self, partition: PartitionInfo) { self.partitions.push(partition); }
+
+    fn verify_partitionable(&self) -> Result<(), DiskError> {
+        if self.is_rst_member() {
+            return Err(DiskError::RstMember { device: self.device_path.clone() });
+        }
+
+        Ok(())
+    }
 }
 
 impl Disk {
     pub fn new(device: &mut Device, extended_partition_info: bool) -> Result<Disk, DiskError> {
+        Disk::probe(device, extended_partition_info, &mut UdevSerials::default())
+    }
+
+    /// As `new`, where the udev database is shared by all of the devices which are probed.
+    pub(crate) fn probe(
+        device: &mut Device,
+        extended_partition_info: bool,
+        udev: &mut UdevSerials,
+    ) -> Result<Disk, DiskError> {
         info!("obtaining disk information from {}", device.path().display());
-        let model_name = device.model().into();
         let device_path = device.path().to_owned();
-        let serial = match device.type_() {
+        let identity = device_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(DeviceIdentity::from_sysfs)
+            .unwrap_or_default();
+
+        let (model_name, serial) = match device.type_() {
             // Encrypted devices do not have serials
-            DeviceType::PED_DEVICE_DM | DeviceType::PED_DEVICE_LOOP => "".into(),
-            _ => get_serial(&device_path).unwrap_or_else(|_| "".into()),
+            DeviceType::PED_DEVICE_DM | DeviceType::PED_DEVICE_LOOP => {
+                (device.model().into(), "".into())
+            }
+            _ => {
+                let serial =
+                    get_serial(&device_path, &identity, udev).unwrap_or_else(|_| "".into());
+                let model = match device.model() {
+                    "" => identity.model.clone(),
+                    model => model.into(),
+                };
+
+                (model, serial)
+            }
         };
 
         let size = device.length();
         let device_type = format!("{:?}", device.type_());
         let read_only = device.read_only();
+        let raid = match device.type_() {
+            DeviceType::PED_DEVICE_DM | DeviceType::PED_DEVICE_LOOP => None,
+            _ => RaidMetadata::examine(&device_path).unwrap_or_else(|why| {
+                warn!("unable to examine {} for RAID metadata: {}", device_path.display(), why);
+                None
+            }),
+        };
+
+        let topology = match device.type_() {
+            DeviceType::PED_DEVICE_DM | DeviceType::PED_DEVICE_LOOP => DiskTopology::default(),
+            _ => device_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(DiskTopology::from_sysfs)
+                .unwrap_or_default(),
+        };
+
+        if let Some(ref raid) = raid {
+            info!("{} contains {} RAID metadata", device_path.display(), raid.format);
+        }
+
+        let rst_active = raid.as_ref().map_or(false, |raid| raid.format == RaidFormat::Imsm)
+            && rst::is_active(&device_path);
 
         // Attempts to open the disk to obtain information regarding the partition table
         // and the partitions stored on the device.
@@ -188,7 +319,7 @@ impl Disk {
         let mounts = MOUNTS.read().expect("failed to get mounts in Disk::new");
         let swaps = SWAPS.read().expect("failed to get swaps in Disk::new");
 
-        Ok(Disk {
+        let mut disk = Disk {
             model_name,
             mount_point: mounts.get_mount_by_source(&device_path).map(|m| m.dest.clone()),
             device_path,
@@ -197,8 +328,13 @@ impl Disk {
             size,
             device_type,
             read_only,
+            removable: identity.removable,
             table_type,
             mklabel: false,
+            erase: None,
+            raid,
+            rst_active,
+            topology,
             partitions: if table_type.is_some() {
                 let mut partitions = Vec::new();
                 for (ordering, part) in disk.parts().filter(|part| part.num() != -1).enumerate() {
@@ -226,7 +362,12 @@ impl Disk {
             } else {
                 Vec::new()
             },
-        })
+        };
+
+        // libparted will silently fall back to opening the device read-only.
+        disk.read_only = disk.read_only || disk.read_only_cause().is_some();
+
+        Ok(disk)
     }
 
     /// Obtains the disk that corresponds to a given device path.
@@ -264,6 +405,44 @@ impl Disk {
     /// Returns the serial of the device, filled in by the manufacturer.
     pub fn get_serial(&self) -> &str { &self.serial }
 
+    /// Queries the SMART health of the drive, which requires `smartctl`.
+    pub fn smart_status(&self) -> io::Result<SmartStatus> { SmartStatus::new(&self.device_path) }
+
+    /// How the disk is reached, if it is attached over the network.
+    pub fn network(&self) -> Option<NetworkDisk> {
+        if !self.topology.transport.is_network() {
+            return None;
+        }
+
+        let name = self.device_path.file_name().and_then(|name| name.to_str())?;
+        NetworkDisk::from_sysfs(name)
+    }
+
+    /// Determines why the device may not be written to, if it is read-only.
+    pub fn get_read_only_cause(&self) -> Option<ReadOnlyCause> {
+        if !self.read_only {
+            return None;
+        }
+
+        Some(self.read_only_cause().unwrap_or(ReadOnlyCause::OpenedReadOnly))
+    }
+
+    /// Returns an error naming the cause if the device is read-only, or if it is a member of an
+    /// Intel RST array.
+    pub fn verify_writable(&self) -> Result<(), DiskError> {
+        match self.get_read_only_cause() {
+            Some(cause) => Err(DiskError::ReadOnly { device: self.device_path.clone(), cause }),
+            None => self.verify_partitionable(),
+        }
+    }
+
+    /// Whether the disk is a member of an Intel RST array which is in use, which the firmware
+    /// assembles from its members. The disk must then be written to through the volume of the
+    /// array instead.
+    pub fn is_rst_member(&self) -> bool {
+        self.rst_active && self.raid.as_ref().map_or(false, |raid| raid.format == RaidFormat::Imsm)
+    }
+
     pub fn is_being_modified(&self) -> bool {
         self.partitions.iter().any(|x| {
             x.bitflags & REMOVE != 0
@@ -273,6 +452,27 @@ impl Disk {
         })
     }
 
+    /// Whether any existing partition will be moved or resized, judged against where the
+    /// kernel reports that the partition lies now.
+    pub fn is_moving_partitions(&self) -> bool {
+        let sectors = self.get_logical_block_size() / 512;
+        self.partitions
+            .iter()
+            .filter(|part| part.flag_is_enabled(SOURCE) && !part.flag_is_enabled(REMOVE))
+            .any(|part| {
+                let read = |file: &str| {
+                    std::fs::read_to_string(part.sys_block_path().join(file))
+                        .ok()
+                        .and_then(|value| value.trim().parse::<u64>().ok())
+                };
+
+                match (read("start"), read("size")) {
+                    (Some(start), Some(size)) => is_relocated(part, sectors, start, size),
+                    _ => false,
+                }
+            })
+    }
+
     /// Unmounts all partitions on the device
     pub fn unmount_all_partitions(&mut self) -> Result<(), (PathBuf, io::Error)> {
         info!("unmount all partitions on {}", self.path().display());
@@ -345,6 +545,7 @@ impl Disk {
     /// Drops all partitions in the in-memory disk representation, and marks that a new
     /// partition table should be written to the disk during the disk operations phase.
     pub fn mklabel(&mut self, kind: PartitionTable) -> Result<(), DiskError> {
+        self.verify_partitionable()?;
         info!("specifying to write new table on {}", self.path().display());
         self.unmount_all_partitions()
             .map_err(|(device, why)| DiskError::Unmount { device, why })?;
@@ -355,12 +556,27 @@ impl Disk {
         Ok(())
     }
 
+    /// Marks that the data on the disk should be erased before its new partition table is
+    /// written, so that it cannot be recovered afterwards.
+    ///
+    /// A new partition table must have been requested with `mklabel` beforehand.
+    pub fn secure_erase(&mut self, mode: EraseMode) -> Result<(), DiskError> {
+        if !self.mklabel {
+            return Err(DiskError::EraseWithoutTable { device: self.device_path.clone() });
+        }
+
+        info!("specifying to erase {} with {:?}", self.path().display(), mode);
+        self.erase = Some(mode);
+        Ok(())
+    }
+
     /// Marks that the partition should be removed.
     ///
     /// Partitions marked as source partitions (pre-existing on disk) will have their `remove`
     /// field set to `true`, whereas all other theoretical partitions will simply be removed
     /// from the partition vector.
     pub fn remove_partition(&mut self, partition: i32) -> Result<(), DiskError> {
+        self.verify_partitionable()?;
         info!("specifying to remove partition {} on {}", partition, self.path().display());
         let id = self
             .partitions
@@ -418,6 +634,7 @@ impl Disk {
     /// will be located at the provided `end` value, and checks whether or not that this will
     /// be possible to do.
     pub fn resize_partition(&mut self, partition: i32, mut end: u64) -> Result<u64, DiskError> {
+        self.verify_partitionable()?;
         let (backup, num, start);
         {
             let partition = self
@@ -431,10 +648,7 @@ impl Disk {
                 ));
             }
 
-            {
-                let length = end - partition.start_sector;
-                end -= length % (2 * 1024);
-            }
+            end = resized_end(partition.start_sector, end);
 
             info!(
                 "specifying to resize {} to {} sectors",
@@ -471,9 +685,58 @@ impl Disk {
         Ok(end)
     }
 
+    /// Grows the last partition to the end of the disk, along with the file system within it,
+    /// and commits the change. This is intended for disks that an image was written to, which
+    /// are larger than the image, so the backup GPT header is first moved to the end of the
+    /// disk. Returns the new end sector of the partition, which is left as it is if it already
+    /// ends at, or beyond, the aligned end of the disk.
+    pub fn grow_last_partition_to_max(&mut self) -> Result<u64, DiskError> {
+        self.verify_writable()?;
+        let device = self.device_path.clone();
+        let error = |why| DiskError::GrowLastPartition { device: device.clone(), why };
+
+        let reserved = if self.table_type == Some(PartitionTable::Gpt) {
+            relocate_backup_gpt(&self.device_path)
+                .and_then(|_| reread_partitions(&self.device_path))
+                .map_err(error)?;
+            self.reload()?;
+            GPT_BACKUP_SECTORS
+        } else {
+            0
+        };
+
+        let last = self
+            .partitions
+            .iter()
+            .filter(|part| !part.flag_is_enabled(REMOVE))
+            .max_by_key(|part| part.end_sector)
+            .ok_or_else(|| error(io::Error::new(io::ErrorKind::NotFound, "no partitions")))?;
+
+        // A logical partition would also require its extended partition to be grown.
+        if last.part_type != PartitionType::Primary {
+            let why = format!("{} is not a primary partition", last.get_device_path().display());
+            return Err(error(io::Error::new(io::ErrorKind::InvalidInput, why)));
+        }
+
+        let (number, old_end) = (last.number, last.end_sector);
+
+        // The end is aligned as `resize_partition` aligns it, which may be before the old end.
+        let max = self.get_sectors() - reserved - 1;
+        if resized_end(last.start_sector, max) <= old_end {
+            info!("partition {} already ends at sector {}", number, old_end);
+            return Ok(old_end);
+        }
+
+        let end = self.resize_partition(number, max)?;
+        info!("growing partition {} from sector {} to sector {}", number, old_end, end);
+        self.commit()?;
+        Ok(end)
+    }
+
     /// Designates that the provided partition number should be moved to a specified sector,
     /// and calculates whether it will be possible to do that.
     pub fn move_partition(&mut self, partition: i32, start: u64) -> Result<(), DiskError> {
+        self.verify_partitionable()?;
         info!(
             "specifying to move partition {} on {} to sector {}",
             partition,
@@ -508,6 +771,227 @@ impl Disk {
         Ok(())
     }
 
+    /// The number of sectors between the MBR and the first partition, if the disk has a MSDOS
+    /// partition table.
+    pub fn mbr_gap(&self) -> Option<u64> {
+        if self.table_type != Some(PartitionTable::Msdos) {
+            return None;
+        }
+
+        let first = self
+            .partitions
+            .iter()
+            .filter(|part| !part.flag_is_enabled(REMOVE))
+            .map(|part| part.start_sector)
+            .min()
+            .unwrap_or(self.size);
+
+        Some(first.saturating_sub(1))
+    }
+
+    /// Designates that the first partition on a MSDOS disk should be moved to `MBR_GAP_END`, so
+    /// that GRUB's core image can be embedded after the MBR.
+    ///
+    /// Partitions which do not exist on the disk yet are shrunk instead of moved. An existing
+    /// partition is only moved if `move_existing` is set, as moving its data is slow, and is
+    /// not something that a user would expect from installing a bootloader.
+    pub fn make_mbr_gap(&mut self, move_existing: bool) -> Result<(), DiskError> {
+        let gap = match self.mbr_gap() {
+            Some(gap) if gap < MBR_GAP_END - 1 => gap,
+            _ => return Ok(()),
+        };
+
+        let too_small = |disk: &Disk, why| DiskError::MbrGapTooSmall {
+            device: disk.device_path.clone(),
+            gap,
+            required: MBR_GAP_END - 1,
+            why,
+        };
+
+        let index = (0..self.partitions.len())
+            .filter(|&id| !self.partitions[id].flag_is_enabled(REMOVE))
+            .min_by_key(|&id| self.partitions[id].start_sector)
+            .expect("a disk with a small MBR gap must have a partition");
+
+        let (number, kind, start, end, source) = {
+            let part = &self.partitions[index];
+            let source = part.flag_is_enabled(SOURCE);
+            (part.number, part.part_type, part.start_sector, part.end_sector, source)
+        };
+
+        if kind == PartitionType::Extended {
+            return Err(too_small(self, "the first partition is an extended partition"));
+        }
+
+        if !source {
+            if end <= MBR_GAP_END {
+                return Err(too_small(self, "the first partition is too small to be shrunk"));
+            }
+
+            info!(
+                "specifying to start new partition on {} at sector {}",
+                self.path().display(),
+                MBR_GAP_END
+            );
+
+            self.partitions[index].start_sector = MBR_GAP_END;
+            return Ok(());
+        }
+
+        if !move_existing {
+            return Err(too_small(self, "moving the first partition was not requested"));
+        }
+
+        if end + (MBR_GAP_END - start) >= self.size {
+            return Err(too_small(self, "there is no room to move the first partition"));
+        }
+
+        self.move_partition(number, MBR_GAP_END).map_err(|why| match why {
+            DiskError::SectorOverlaps { .. } => {
+                too_small(self, "the first partition cannot be moved without overlapping another")
+            }
+            why => why,
+        })
+    }
+
+    /// Finds the partitions which are yet to be created that do not begin and end on 1 MiB
+    /// boundaries, that overlap other partitions, or that extend past the end of the disk, so
+    /// that they may be corrected before libparted fails to create them.
+    pub fn check_alignment(&self) -> Vec<AlignmentIssue> {
+        let (alignment, last) = (self.alignment(), self.last_usable_sector());
+        let partitions = self.aligned_partitions();
+        let mut issues = Vec::new();
+
+        for (id, &index) in partitions.iter().enumerate() {
+            let part = &self.partitions[index];
+            if part.flag_is_enabled(SOURCE) {
+                continue;
+            }
+
+            let (start_sector, end_sector) = (part.start_sector, part.end_sector);
+            let mut issue = |kind| issues.push(AlignmentIssue { kind, start_sector, end_sector });
+            if start_sector % alignment != 0 || (end_sector + 1) % alignment != 0 {
+                issue(AlignmentIssueKind::Misaligned);
+            }
+
+            if end_sector > last {
+                issue(AlignmentIssueKind::OutOfBounds);
+            }
+
+            // Overlaps between two new partitions are reported once, for the first of them.
+            for (other_id, &other) in partitions.iter().enumerate() {
+                let other = &self.partitions[other];
+                let reported = !other.flag_is_enabled(SOURCE) && other_id < id;
+                let overlaps = part.sectors_overlap(other.start_sector, other.end_sector);
+                if other_id != id && !reported && overlaps {
+                    issue(AlignmentIssueKind::Overlaps { other: other.start_sector });
+                }
+            }
+        }
+
+        issues
+    }
+
+    /// Snaps the partitions which are yet to be created to 1 MiB boundaries, moving their
+    /// starts forward and their ends back where they would overlap their neighbors or extend
+    /// past the end of the disk. Logical partitions are kept clear of the sector before them,
+    /// which holds their EBR.
+    ///
+    /// Nothing is changed if any partition would have no room, or would lose more than
+    /// `MAX_ALIGNMENT_SHRINK` percent of its sectors beyond what rounding to 1 MiB costs.
+    pub fn auto_align(&mut self) -> Result<(), DiskError> {
+        let (alignment, last) = (self.alignment(), self.last_usable_sector());
+        let partitions = self.aligned_partitions();
+        let mut aligned = Vec::new();
+        let mut previous = None;
+
+        for (id, &index) in partitions.iter().enumerate() {
+            let part = &self.partitions[index];
+            if part.flag_is_enabled(SOURCE) {
+                previous = Some(part.end_sector);
+                continue;
+            }
+
+            let ebr = if part.part_type == PartitionType::Logical { 1 } else { 0 };
+            let next = partitions.get(id + 1).map(|&next| &self.partitions[next]);
+            let next = next.map(|next| {
+                let ebr = if next.part_type == PartitionType::Logical { 1 } else { 0 };
+                next.start_sector.saturating_sub(ebr)
+            });
+
+            let floor =
+                previous.map_or(part.start_sector, |end| part.start_sector.max(end + 1 + ebr));
+            let ceiling = next.map_or(last, |start| last.min(start.saturating_sub(1)));
+            let start = align_up(floor, alignment);
+            let end = align_down(part.end_sector.min(ceiling) + 1, alignment).checked_sub(1);
+
+            let end = match end {
+                Some(end) if end > start => end,
+                _ => {
+                    return Err(DiskError::AlignmentNoRoom {
+                        device: self.device_path.clone(),
+                        start:  part.start_sector,
+                    })
+                }
+            };
+
+            let (sectors, kept) = (part.end_sector - part.start_sector + 1, end - start + 1);
+            let allowed = 2 * alignment + sectors * MAX_ALIGNMENT_SHRINK / 100;
+            if sectors.saturating_sub(kept) > allowed {
+                return Err(DiskError::AlignmentShrink {
+                    device:  self.device_path.clone(),
+                    start:   part.start_sector,
+                    sectors,
+                    aligned: kept,
+                });
+            }
+
+            previous = Some(end);
+            aligned.push((index, start, end));
+        }
+
+        for (index, start, end) in aligned {
+            let part = &mut self.partitions[index];
+            if (start, end) != (part.start_sector, part.end_sector) {
+                info!(
+                    "aligning new partition on {} from sectors {}-{} to {}-{}",
+                    self.device_path.display(),
+                    part.start_sector,
+                    part.end_sector,
+                    start,
+                    end
+                );
+            }
+
+            part.start_sector = start;
+            part.end_sector = end;
+        }
+
+        Ok(())
+    }
+
+    /// The number of sectors in the 1 MiB boundaries that new partitions are aligned to.
+    fn alignment(&self) -> u64 { (ALIGNMENT / self.get_logical_block_size()).max(1) }
+
+    /// The last sector which a partition may end at, before the backup header of a GPT disk.
+    fn last_usable_sector(&self) -> u64 {
+        let reserved =
+            if self.table_type == Some(PartitionTable::Gpt) { GPT_BACKUP_SECTORS } else { 0 };
+        self.size.saturating_sub(reserved + 1)
+    }
+
+    /// Indexes of the partitions which are kept or created, ordered by their start sectors.
+    /// Extended partitions are excluded, as they contain the logical partitions.
+    fn aligned_partitions(&self) -> Vec<usize> {
+        let mut partitions = (0..self.partitions.len())
+            .filter(|&id| !self.partitions[id].flag_is_enabled(REMOVE))
+            .filter(|&id| self.partitions[id].part_type != PartitionType::Extended)
+            .collect::<Vec<usize>>();
+
+        partitions.sort_by_key(|&id| self.partitions[id].start_sector);
+        partitions
+    }
+
     /// Designates that the specified partition ID should be formatted with the given file
     /// system.
     ///
@@ -515,6 +999,7 @@ impl Disk {
     ///
     /// The partition name will cleared after calling this function.
     pub fn format_partition(&mut self, partition: i32, fs: FileSystem) -> Result<(), DiskError> {
+        self.verify_partitionable()?;
         info!(
             "specifying to format partition {} on {} with {:?}",
             partition,
@@ -722,6 +1207,8 @@ impl Disk {
                                         kind:         new.part_type,
                                         flags:        new.flags.clone(),
                                         label:        new.name.clone(),
+                                        fs_label:     new.fs_label.clone(),
+                                        fs_uuid:      new.fs_uuid.clone(),
                                     });
                                 } else {
                                     change_partitions.push(PartitionChange {
@@ -738,6 +1225,11 @@ impl Disk {
                                         ),
                                         new_flags:   new.flags.clone(),
                                         label:       new.name.clone(),
+                                        key:         new
+                                            .volume_group
+                                            .as_ref()
+                                            .and_then(|&(_, ref enc)| enc.as_ref())
+                                            .and_then(LvmEncryption::credential),
                                     });
                                 }
                             }
@@ -769,10 +1261,14 @@ impl Disk {
                 kind:         partition.part_type,
                 flags:        partition.flags.clone(),
                 label:        partition.name.clone(),
+                fs_label:     partition.fs_label.clone(),
+                fs_uuid:      partition.fs_uuid.clone(),
             });
         }
 
         Ok(DiskOps {
+            wipe_raid: new.mklabel && self.raid.is_some(),
+            erase: if new.mklabel { new.erase } else { None },
             mklabel,
             device_path: &self.device_path,
             remove_partitions,
@@ -783,6 +1279,19 @@ impl Disk {
 
     /// Attempts to commit all changes that have been made to the disk.
     pub fn commit(&mut self) -> Result<Option<FormatPartitions>, DiskError> {
+        self.commit_with_progress(|_, _, _| ())
+    }
+
+    /// Identical to `commit`, but reports the progress of erasing the disk, and of partition
+    /// moves.
+    ///
+    /// The callback receives the path of the disk or partition, the amount of work that has
+    /// been done, and the total amount of work: the percentage of an overwrite of the disk out
+    /// of 100, or the number of sectors that have been moved out of the number to move.
+    pub fn commit_with_progress<F: FnMut(&Path, u64, u64)>(
+        &mut self,
+        mut progress: F,
+    ) -> Result<Option<FormatPartitions>, DiskError> {
         info!("committing changes to {}: {:#?}", self.path().display(), self);
         Disk::from_name_with_serial(&self.device_path, &self.serial).and_then(|source| {
             source.diff(self).and_then(|ops| {
@@ -790,8 +1299,8 @@ impl Disk {
                     Ok(None)
                 } else {
                     let partitions_to_format = ops
-                        .remove()
-                        .and_then(|ops| ops.change())
+                        .remove_with_progress(&mut progress)
+                        .and_then(|ops| ops.change_with_progress(progress))
                         .and_then(|ops| ops.create())
                         .map(Some)?;
 
@@ -815,8 +1324,9 @@ impl Disk {
                 let mount = partition.target.as_ref().map(|ref path| path.to_path_buf());
                 let vg = partition.volume_group.as_ref().cloned();
                 let keyid = partition.key_id.as_ref().cloned();
-                if mount.is_some() || vg.is_some() || keyid.is_some() {
-                    Some((start, mount, vg, keyid))
+                let subvolumes = partition.subvolumes.clone();
+                if mount.is_some() || vg.is_some() || keyid.is_some() || !subvolumes.is_empty() {
+                    Some((start, mount, vg, keyid, subvolumes))
                 } else {
                     None
                 }
@@ -827,7 +1337,7 @@ impl Disk {
         *self = Disk::from_name_with_serial(&self.device_path, &self.serial)?;
 
         // Then re-add the critical information which was lost.
-        for (sector, mount, vg, keyid) in collected {
+        for (sector, mount, vg, keyid, subvolumes) in collected {
             info!("checking for mount target at {}", sector);
             let part = self
                 .get_partition_at(sector)
@@ -837,6 +1347,7 @@ impl Disk {
             part.target = mount;
             part.volume_group = vg;
             part.key_id = keyid;
+            part.subvolumes = subvolumes;
         }
 
         Ok(())
@@ -83,6 +83,10 @@ pub trait DiskExt: BlockDeviceExt + SectorExt + PartitionTableExt {
 
     fn is_logical(&self) -> bool { Self::LOGICAL }
 
+    /// Fails if the partitions of the device may not be changed on their own, as with a member
+    /// of an Intel RST array.
+    fn verify_partitionable(&self) -> Result<(), DiskError> { Ok(()) }
+
     /// If a given start and end range overlaps a pre-existing partition, that
     /// partition's number will be returned to indicate a potential conflict.
     fn overlaps_region(&self, start: u64, end: u64) -> Option<i32> {
@@ -113,6 +117,8 @@ pub trait DiskExt: BlockDeviceExt + SectorExt + PartitionTableExt {
     ///
     /// An error can occur if the partition will not fit onto the disk.
     fn add_partition(&mut self, mut builder: PartitionBuilder) -> Result<(), DiskError> {
+        self.verify_partitionable()?;
+
         // Ensure that the values aren't already contained within an existing partition.
         if !Self::LOGICAL && builder.part_type != PartitionType::Extended {
             info!("checking if {}:{} overlaps", builder.start_sector, builder.end_sector);
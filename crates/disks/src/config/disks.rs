@@ -1,22 +1,28 @@
 use super::{
     super::{
-        Bootloader, DecryptionError, DiskError, DiskExt, FileSystem, LogicalDevice, PartitionFlag,
-        PartitionInfo,
+        serial::UdevSerials, Bootloader, DecryptionError, DiskError, DiskExt, FileSystem,
+        LogicalDevice, NonPosixTarget, PartitionError, PartitionFlag, PartitionInfo, RstArray, OS,
     },
     detect_fs_on_device, find_partition, find_partition_mut,
+    labels::{collisions, max_label_len},
     partitions::{FORMAT, REMOVE, SOURCE},
+    session_mounts::{backing_devices, session_mounts_on, SessionMount},
+    validation::{PartitionLocation, ValidationIssue, ValidationIssueKind},
     Disk, LvmEncryption, PartitionTable, PVS,
 };
-use disk_types::{BlockDeviceExt, PartitionExt, PartitionTableExt, SectorExt};
+use disk_types::{
+    is_mmc_hardware_partition, BlockDeviceExt, PartitionExt, PartitionTableExt, PartitionType,
+    SectorExt,
+};
 use crate::external::{
-    cryptsetup_close, cryptsetup_open, lvs, physical_volumes_to_deactivate, pvs, vgdeactivate,
-    CloseBy,
+    btrfs_create_subvolumes, cryptsetup_close, cryptsetup_open, generate_unique_id, lvs,
+    physical_volumes_to_deactivate, pvs, set_label, udev_settle, vgdeactivate, CloseBy, Retry,
 };
 use itertools::Itertools;
 use libparted::{Device, DeviceType};
 use misc;
 use partition_identity::{PartitionID, PartitionSource};
-use proc_mounts::{MountIter, MOUNTS, SWAPS};
+use proc_mounts::{MountIter, MountList, SwapList, MOUNTS, SWAPS};
 use rayon::{iter::IntoParallelRefIterator, prelude::*};
 use std::{
     collections::{BTreeMap, HashSet},
@@ -24,12 +30,52 @@ use std::{
     fs, io,
     iter::{self, FromIterator},
     os::unix::ffi::{OsStrExt, OsStringExt},
-    path::{Path, PathBuf},
-    str, thread,
-    time::Duration,
+    path::{Component, Path, PathBuf},
+    str,
 };
 use sys_mount::{swapoff, unmount, Mount, MountFlags, Mounts, Unmount, UnmountFlags};
 
+/// Paths which are mounted by the system, and therefore may not be used as mount targets.
+const VIRTUAL_TARGETS: &[&str] = &["/dev", "/proc", "/run", "/sys"];
+
+/// Targets which are managed by the installer, and therefore may not contain other mounts.
+const MANAGED_TARGETS: &[&str] = &["/boot/efi", "/boot/efi2", "/recovery"];
+
+/// Where a second ESP is mounted, which the bootloader will be mirrored to so that the system
+/// remains bootable if the disk of the first ESP fails.
+pub const SECONDARY_ESP_TARGET: &str = "/boot/efi2";
+
+/// Paths which hold the system, and therefore must be stored on a file system with Unix
+/// permissions. Data partitions may be mounted within `/` and `/home`, but not at them.
+const SYSTEM_TARGETS: &[&str] =
+    &["/boot", "/etc", "/opt", "/recovery", "/root", "/srv", "/tmp", "/usr", "/var"];
+
+/// System targets which the installer formats as FAT, because the firmware reads from them.
+const FAT_TARGETS: &[&str] = &["/boot/efi", SECONDARY_ESP_TARGET, "/recovery"];
+
+/// The boot manager of Windows, which an ESP holds when Windows is installed, even when the
+/// file system of Windows is hidden by BitLocker.
+const WINDOWS_BOOT_MANAGER: &str = "EFI/Microsoft/Boot/bootmgfw.efi";
+
+/// File systems which GRUB is able to read the kernels from, when they are stored at `/boot`.
+const BOOT_FILE_SYSTEMS: &[FileSystem] = &[
+    FileSystem::Btrfs,
+    FileSystem::Ext2,
+    FileSystem::Ext3,
+    FileSystem::Ext4,
+    FileSystem::F2fs,
+    FileSystem::Xfs,
+];
+
+/// The partitions which `Disks::unlock_all` decrypted, and those which it failed to.
+#[derive(Debug, Default)]
+pub struct Unlocked {
+    /// The paths of the partitions which were decrypted.
+    pub unlocked: Vec<PathBuf>,
+    /// The errors of the partitions which failed to decrypt, other than rejected passwords.
+    pub failed:   Vec<DecryptionError>,
+}
+
 /// A configuration of disks, both physical and logical.
 #[derive(Debug, Default, PartialEq)]
 pub struct Disks {
@@ -84,6 +130,33 @@ impl Disks {
             .any(|p| p.filesystem == Some(FileSystem::Luks))
     }
 
+    /// Whether Windows is installed on a partition which will not be removed or formatted, or
+    /// is booted from an ESP which will be kept. The latter finds Windows on BitLocker volumes,
+    /// whose file systems cannot be probed.
+    pub fn contains_windows(&self) -> bool {
+        let kept = || {
+            self.physical
+                .iter()
+                .filter(|disk| !disk.mklabel)
+                .flat_map(|disk| disk.partitions.iter())
+                .filter(|part| !part.flag_is_enabled(REMOVE) && !part.will_format())
+        };
+
+        let windows = kept().filter(|part| part.filesystem == Some(FileSystem::Ntfs)).any(|part| {
+            match part.probe_os() {
+                Some(OS::Windows(_)) => true,
+                _ => false,
+            }
+        });
+
+        windows
+            || kept().filter(|part| part.is_esp_partition()).any(|part| {
+                part.probe(|mount| {
+                    mount.map_or(false, |(base, _)| base.join(WINDOWS_BOOT_MANAGER).is_file())
+                })
+            })
+    }
+
     pub fn get_physical_device<P: AsRef<Path>>(&self, path: P) -> Option<&Disk> {
         self.physical.iter().find(|d| d.get_device_path() == path.as_ref())
     }
@@ -183,66 +256,57 @@ impl Disks {
     }
 
     /// Mounts all targets in this disks object.
+    ///
+    /// The btrfs subvolumes of each partition are created if they do not already exist, and
+    /// are mounted at their own targets, even when the partition is already mounted elsewhere.
     pub fn mount_all_targets<P: AsRef<Path>>(&self, base_dir: P) -> io::Result<Mounts> {
         let base_dir = base_dir.as_ref();
-        let targets =
-            self.get_partitions().filter(|part| part.target.is_some() && part.filesystem.is_some());
+        let targets = self.get_partitions().filter(|part| {
+            (part.target.is_some() || !part.subvolumes.is_empty()) && part.filesystem.is_some()
+        });
 
         enum MountKind {
-            Direct { device: PathBuf, fs: &'static str },
+            Direct { device: PathBuf, fs: &'static str, data: Option<String> },
             Bind { source: PathBuf },
         }
 
-        // The mount path will actually consist of the target concatenated with the
-        // root. NOTE: It is assumed that the target is an absolute path.
-        let paths: BTreeMap<PathBuf, MountKind> = targets
-            .map(|target| {
-                // Path mangling commences here, since we need to concatenate an absolute
-                // path onto another absolute path, and the standard library opts for
-                // overwriting the original path when doing that.
-                let target_mount: PathBuf = {
-                    // Ensure that the base_dir path has the ending '/'.
-                    let base_dir = base_dir.as_os_str().as_bytes();
-                    let mut target_mount: Vec<u8> = if base_dir[base_dir.len() - 1] == b'/' {
-                        base_dir.to_owned()
-                    } else {
-                        let mut temp = base_dir.to_owned();
-                        temp.push(b'/');
-                        temp
-                    };
-
-                    // Cut the starting '/' from the target path if it exists.
-                    let target_path = target.target.as_ref().unwrap().as_os_str().as_bytes();
-                    let target_path = if !target_path.is_empty() && target_path[0] == b'/' {
-                        if target_path.len() > 1 {
-                            &target_path[1..]
-                        } else {
-                            b""
-                        }
-                    } else {
-                        target_path
-                    };
-
-                    // Append the target path to the base_dir, and return it as a path type.
-                    target_mount.extend_from_slice(target_path);
-                    PathBuf::from(OsString::from_vec(target_mount))
-                };
+        let mut paths: BTreeMap<PathBuf, MountKind> = BTreeMap::new();
+        for target in targets {
+            // A btrfs file system may be mounted more than once, so the top level is mounted
+            // again to create the subvolumes of a partition which is already mounted.
+            if target.filesystem == Some(FileSystem::Btrfs) && !target.subvolumes.is_empty() {
+                let names = target.subvolumes.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+                btrfs_create_subvolumes(&target.device_path, &names)?;
+            }
 
-                // If a partition is already mounted, we should perform a bind mount.
-                // If it is not mounted, we can mount it directly.
-                let kind = if let Some(source) = target.mount_point.clone() {
-                    MountKind::Bind { source }
-                } else {
+            // If a partition is already mounted, we should perform a bind mount.
+            // If it is not mounted, we can mount it directly. Subvolumes are always mounted
+            // directly, as the existing mount may not be of the top level.
+            let kind = |subvolume: Option<&str>| match (&target.mount_point, subvolume) {
+                (Some(source), None) => MountKind::Bind { source: source.clone() },
+                _ => {
                     let fs = match target.filesystem.unwrap() {
                         FileSystem::Fat16 | FileSystem::Fat32 => "vfat",
                         fs => fs.into(),
                     };
 
-                    MountKind::Direct { device: target.device_path.clone(), fs }
-                };
-                (target_mount, kind)
-            })
-            .collect();
+                    let data = subvolume.map(|name| ["subvol=", name].concat());
+                    MountKind::Direct { device: target.device_path.clone(), fs, data }
+                }
+            };
+
+            // A subvolume at the partition's target is mounted in place of the top level.
+            if let Some(ref path) = target.target {
+                if !target.subvolumes.iter().any(|subvolume| &subvolume.target == path) {
+                    paths.insert(mount_path(base_dir, path), kind(None));
+                }
+            }
+
+            for subvolume in &target.subvolumes {
+                let path = mount_path(base_dir, &subvolume.target);
+                paths.insert(path, kind(Some(&subvolume.name)));
+            }
+        }
 
         // Each mount directory will be created and then mounted before progressing to
         // the next mount in the map. The BTreeMap that the mount targets were
@@ -256,9 +320,9 @@ impl Disks {
             }
 
             let mount = match kind {
-                MountKind::Direct { device, fs } => {
-                    info!("mounting {:?} ({}) to {:?}", device, fs, target_mount);
-                    Mount::new(device, &target_mount, fs, MountFlags::empty(), None)?
+                MountKind::Direct { device, fs, data } => {
+                    info!("mounting {:?} ({}, {:?}) to {:?}", device, fs, data, target_mount);
+                    Mount::new(device, &target_mount, fs, MountFlags::empty(), data.as_deref())?
                 }
                 MountKind::Bind { source } => {
                     info!("bind mounting {:?} to {:?}", source, target_mount);
@@ -472,18 +536,23 @@ impl Disks {
             enc: &LvmEncryption,
         ) -> Result<LogicalDevice, DecryptionError> {
             // Attempt to decrypt the device.
-            cryptsetup_open(path, &enc)
-                .map_err(|why| DecryptionError::Open { device: path.to_path_buf(), why })?;
+            cryptsetup_open(path, &enc).map_err(|why| match why.kind() {
+                io::ErrorKind::PermissionDenied => {
+                    DecryptionError::WrongKey { device: path.to_path_buf() }
+                }
+                _ => DecryptionError::Open { device: path.to_path_buf(), why },
+            })?;
 
             // Determine which VG the newly-decrypted device belongs to.
             let pv = &PathBuf::from(["/dev/mapper/", &enc.physical_volume].concat());
             info!("which belongs to PV {:?}", pv);
-            let mut attempt = 0;
-            while !pv.exists() && attempt < 10 {
-                info!("waiting 1 second for {:?} to activate", pv);
-                attempt += 1;
-                thread::sleep(Duration::from_millis(1000));
-            }
+            Retry::default().attempts(8).interval(250).max_interval(4000).wait_until(|| {
+                pv.exists() || {
+                    info!("waiting for {:?} to activate", pv);
+                    let _ = udev_settle();
+                    pv.exists()
+                }
+            });
 
             match pvs().expect("pvs() failed in decrypt_partition").remove(pv) {
                 Some(Some(vg)) => {
@@ -562,6 +631,58 @@ impl Disks {
         }
     }
 
+    /// Decrypts every LUKS partition which has not already been decrypted, and activates the
+    /// volume groups within them, so that the systems they contain are found by
+    /// `InstallOptions`.
+    ///
+    /// The `prompt` is given the path of each partition, and returns its password, or `None`
+    /// to leave it encrypted. If a password is rejected, the prompt is asked again for the
+    /// same partition. A partition which fails to decrypt for any other reason is recorded
+    /// among the failures, and the remaining partitions are still unlocked.
+    pub fn unlock_all<F: FnMut(&Path) -> Option<String>>(&mut self, mut prompt: F) -> Unlocked {
+        let locked = self
+            .get_encrypted_partitions()
+            .into_iter()
+            .map(|part| part.get_device_path())
+            .filter(|path| {
+                !self
+                    .get_logical_devices()
+                    .iter()
+                    .any(|device| device.luks_parent.as_ref().map_or(false, |p| p == path))
+            })
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+
+        let mut result = Unlocked::default();
+        for path in locked {
+            while let Some(password) = prompt(&path) {
+                let decrypted = generate_unique_id("cryptdata", &[])
+                    .map_err(|why| DecryptionError::Open { device: path.clone(), why })
+                    .and_then(|pv| {
+                        let enc = LvmEncryption::new(pv, Some(password), None);
+                        self.decrypt_partition(&path, &enc)
+                    });
+
+                match decrypted {
+                    Ok(()) => {
+                        result.unlocked.push(path.clone());
+                        break;
+                    }
+                    Err(DecryptionError::WrongKey { .. }) => {
+                        warn!("the password for {} was rejected", path.display());
+                    }
+                    Err(why) => {
+                        error!("unable to decrypt {}: {}", path.display(), why);
+                        result.failed.push(why);
+                        break;
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
     /// Sometimes, physical devices themselves may be mounted directly.
     pub fn unmount_devices(&self) -> Result<(), DiskError> {
         info!("unmounting devices");
@@ -585,14 +706,76 @@ impl Disks {
             .collect::<Result<(), DiskError>>()
     }
 
+    /// Mounts and swaps of the live session which hold partitions of the disks to be modified,
+    /// such as partitions which were mounted by the file manager. These must be released
+    /// before the disks are partitioned.
+    pub fn session_mounts(&self) -> io::Result<Vec<SessionMount>> {
+        let disks = self.modified_disks().map(|disk| disk.path()).collect::<Vec<_>>();
+
+        let mounts = MountList::new()?
+            .0
+            .into_iter()
+            .map(|mount| (mount.source, mount.dest))
+            .collect::<Vec<_>>();
+        let swaps = SwapList::new()?.0.into_iter().map(|swap| swap.source).collect::<Vec<_>>();
+
+        Ok(session_mounts_on(&disks, &mounts, &swaps, backing_devices))
+    }
+
+    /// The partition names and file system labels of every partition, except for partitions
+    /// which are to be removed, paired with the path of the partition.
+    pub fn labels(&self) -> Vec<(&Path, &str)> {
+        self.get_partitions()
+            .filter(|part| !part.flag_is_enabled(REMOVE))
+            .flat_map(|part| {
+                let path = part.get_device_path();
+                part.labels().map(move |label| (path, label))
+            })
+            .collect()
+    }
+
+    /// Labels which are shared by more than one partition, such as the `recovery` partitions
+    /// of several installs, with the paths of the partitions that share them. Labels are
+    /// compared without regard to case.
+    pub fn label_collisions(&self) -> BTreeMap<String, Vec<PathBuf>> { collisions(self.labels()) }
+
+    /// Changes the label of the existing file system on the partition at `path`.
+    pub fn relabel(&mut self, path: &Path, label: &str) -> Result<(), DiskError> {
+        let part = self
+            .get_partition_by_path_mut(path)
+            .ok_or_else(|| DiskError::PartitionGet { device: path.to_path_buf() })?;
+
+        let fs = part.filesystem.ok_or_else(|| DiskError::PartitionError {
+            partition: path.to_path_buf(),
+            why:       PartitionError::NoFilesystem,
+        })?;
+
+        let max = max_label_len(fs);
+        if label.len() > max {
+            return Err(DiskError::LabelTooLong { label: label.into(), fs, max });
+        }
+
+        set_label(path, fs, label)
+            .map_err(|why| DiskError::Relabel { device: path.to_path_buf(), why })?;
+        part.fs_label = Some(label.into());
+        Ok(())
+    }
+
     /// Probes for and returns disk information for every disk in the system.
     pub fn probe_devices() -> Result<Disks, DiskError> {
         let mut disks = Disks::default();
+        let mut udev = UdevSerials::default();
         for mut device in Device::devices(true) {
             if let Some(name) = device.path().file_name().and_then(|x| x.to_str()) {
                 // Ignore CDROM devices
                 if name.starts_with("sr") || name.starts_with("scd") { continue }
 
+                // The boot partitions of eMMC devices hold the firmware of the board.
+                if is_mmc_hardware_partition(name) {
+                    info!("skipping eMMC hardware partition {:?}", device.path());
+                    continue;
+                }
+
                 info!("probed {:?}", device.path());
 
                 match device.type_() {
@@ -600,7 +783,7 @@ impl Disks {
                     | DeviceType::PED_DEVICE_LOOP
                     | DeviceType::PED_DEVICE_FILE
                     | DeviceType::PED_DEVICE_DM => continue,
-                    _ => disks.add(Disk::new(&mut device, false)?),
+                    _ => disks.add(Disk::probe(&mut device, false, &mut udev)?),
                 }
             }
         }
@@ -706,6 +889,18 @@ impl Disks {
         self.physical.iter().find(|disk| disk.device_path == path.as_ref())
     }
 
+    /// Groups the members of Intel RST arrays which are in use by the array that they belong to.
+    pub fn rst_arrays(&self) -> Vec<RstArray> {
+        let members = self.physical.iter().filter(|disk| disk.rst_active);
+        RstArray::group(members.map(|disk| (disk.path(), disk.raid.as_ref())))
+    }
+
+    /// The Intel RST array which the disk at `path` is a member of, if any.
+    pub fn find_rst_array<P: AsRef<Path>>(&self, path: P) -> Option<RstArray> {
+        let path = path.as_ref();
+        self.rst_arrays().into_iter().find(|array| array.members.iter().any(|m| m == path))
+    }
+
     /// Returns a mutable reference to the disk specified by its path, if it
     /// exists.
     pub fn find_disk_mut<P: AsRef<Path>>(&mut self, path: P) -> Option<&mut Disk> {
@@ -790,14 +985,14 @@ impl Disks {
     }
 
     /// Obtains the paths to the device and partition block paths where the root and EFI
-    /// partitions are installed. The paths for the EFI partition will not be collected if
-    /// the provided boot loader was of the EFI variety.
+    /// partitions are installed. Without EFI, the boot partition is collected instead, if
+    /// there is one.
     pub fn get_base_partitions(
         &self,
         bootloader: Bootloader,
     ) -> ((&Path, &PartitionInfo), Option<(&Path, &PartitionInfo)>) {
         match bootloader {
-            Bootloader::Bios => {
+            Bootloader::Bios | Bootloader::UBoot => {
                 let boot = self.find_partition(Path::new("/boot"));
 
                 let root = self.find_partition(Path::new("/")).expect(
@@ -820,6 +1015,160 @@ impl Disks {
         }
     }
 
+    /// The device and partition of the second ESP, if one will be mounted at
+    /// `SECONDARY_ESP_TARGET`.
+    pub fn get_secondary_esp(&self) -> Option<(&Path, &PartitionInfo)> {
+        self.find_partition(Path::new(SECONDARY_ESP_TARGET))
+    }
+
+    /// Ensure that every disk which will be modified may be written to.
+    pub fn verify_writable(&self) -> Result<(), DiskError> {
+        info!("verifying that modified disks are writable");
+        self.modified_disks().try_for_each(Disk::verify_writable)
+    }
+
+    /// Disks which the configuration will write to, including those whose partitions are only
+    /// moved or resized.
+    ///
+    /// Reused partitions and logical volumes which are mounted are written to by the install,
+    /// though they are not formatted, so the disks which hold them are included.
+    fn modified_disks(&self) -> impl Iterator<Item = &Disk> {
+        let modified_groups = self
+            .logical
+            .iter()
+            .filter(|lvm| {
+                lvm.partitions.iter().any(|part| {
+                    part.flag_is_enabled(FORMAT | REMOVE)
+                        || (part.target.is_some() && !part.flag_is_enabled(REMOVE))
+                })
+            })
+            .map(|lvm| lvm.volume_group.as_str())
+            .collect::<Vec<_>>();
+
+        self.physical.iter().filter(move |disk| {
+            disk.mklabel
+                || disk.is_being_modified()
+                || disk.partitions.iter().any(|part| !part.flag_is_enabled(SOURCE))
+                || disk.is_moving_partitions()
+                || disk.partitions.iter().any(|part| {
+                    let group =
+                        part.volume_group.as_ref().map(|vg| &vg.0).or(part.original_vg.as_ref());
+                    group.map_or(false, |group| modified_groups.contains(&group.as_str()))
+                })
+        })
+    }
+
+    /// Ensure that every mount target is an absolute path, and that targets do not conflict.
+    ///
+    /// A target may only be assigned to one partition, and it may not be nested within a
+    /// virtual file system, or within a partition that is managed by the installer.
+    pub fn verify_mount_targets(&self) -> io::Result<()> {
+        let invalid = |why: String| io::Error::new(io::ErrorKind::InvalidInput, why);
+
+        let not_btrfs = self.get_partitions().find(|part| {
+            !part.subvolumes.is_empty() && part.filesystem != Some(FileSystem::Btrfs)
+        });
+
+        if let Some(part) = not_btrfs {
+            return Err(invalid(format!(
+                "{} has subvolumes, but its file system is not btrfs",
+                part.get_device_path().display()
+            )));
+        }
+
+        // A subvolume which shares the partition's target is mounted in place of the top level.
+        let targets = self
+            .get_partitions()
+            .filter(|part| !part.flag_is_enabled(REMOVE))
+            .flat_map(|part| {
+                let top_level = part.target.as_deref().filter(|&target| {
+                    !part.subvolumes.iter().any(|subvolume| subvolume.target == target)
+                });
+
+                let subvolumes = part.subvolumes.iter().map(|subvolume| subvolume.target.as_path());
+                top_level.into_iter().chain(subvolumes).map(move |target| (target, part))
+            })
+            .collect::<Vec<_>>();
+
+        let mut defined = HashSet::new();
+        for &(target, part) in &targets {
+            let normalized = target.is_absolute()
+                && target.components().all(|component| match component {
+                    Component::RootDir | Component::Normal(_) => true,
+                    _ => false,
+                });
+
+            if !normalized {
+                return Err(invalid(format!(
+                    "mount target {} is not an absolute path",
+                    target.display()
+                )));
+            }
+
+            if let Some(parent) = VIRTUAL_TARGETS.iter().find(|&path| target.starts_with(path)) {
+                return Err(invalid(format!(
+                    "mount target {} is within {}, which is a virtual file system",
+                    target.display(),
+                    parent
+                )));
+            }
+
+            match part.filesystem {
+                Some(FileSystem::Luks) | Some(FileSystem::Lvm) | Some(FileSystem::Swap) | None => {
+                    return Err(invalid(format!(
+                        "partition mounted at {} does not have a mountable file system",
+                        target.display()
+                    )));
+                }
+                Some(fs @ FileSystem::Exfat)
+                | Some(fs @ FileSystem::Ntfs)
+                | Some(fs @ FileSystem::Fat16)
+                | Some(fs @ FileSystem::Fat32) => {
+                    let system = target == Path::new("/")
+                        || target == Path::new("/home")
+                        || SYSTEM_TARGETS.iter().any(|path| target.starts_with(path));
+
+                    let firmware = (fs == FileSystem::Fat16 || fs == FileSystem::Fat32)
+                        && FAT_TARGETS.iter().any(|path| target == Path::new(path));
+
+                    if system && !firmware {
+                        return Err(NonPosixTarget {
+                            device:      part.get_device_path().to_path_buf(),
+                            filesystem:  fs,
+                            target:      target.to_path_buf(),
+                            alternative: media_target(part),
+                        }
+                        .into_io_error());
+                    }
+                }
+                Some(_) => (),
+            }
+
+            if !defined.insert(target) {
+                return Err(invalid(format!(
+                    "multiple partitions are mounted at {}",
+                    target.display()
+                )));
+            }
+
+            let managed_parent = MANAGED_TARGETS.iter().map(Path::new).find(|&path| {
+                target != path
+                    && target.starts_with(path)
+                    && targets.iter().any(|&(other, _)| other == path)
+            });
+
+            if let Some(parent) = managed_parent {
+                return Err(invalid(format!(
+                    "mount target {} may not be nested within {}",
+                    target.display(),
+                    parent.display()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
     /// Ensure that keyfiles have key paths.
     pub fn verify_keyfile_paths(&self) -> Result<(), DiskError> {
         info!("verifying if keyfiles have paths");
@@ -915,123 +1264,128 @@ impl Disks {
         self.get_logical_devices().iter().any(|d| d.get_device_path() == device)
     }
 
-    /// Validates that partitions are configured correctly.
+    /// Validates that partitions are configured correctly, returning every issue that was found.
     ///
+    /// - Disks which will be modified must be writable, and not members of an Intel RST array
+    /// - Mount targets must be valid
+    /// - The root partition must have a file system with Unix permissions, of at least
+    ///   `root_minimum` bytes
     /// - EFI installs must contain a `/boot/efi` partition as Fat16 / Fat32
     /// - MBR installs on logical devices must have a `/boot` partition
-    /// - Boot partitions must not be on a logical volume
+    /// - Boot partitions must not be on a logical volume, and `/boot` must be readable by GRUB
     /// - EFI boot partitions must have the ESP flag set
-    pub fn verify_partitions(&self, bootloader: Bootloader) -> io::Result<()> {
-        let (root_device, root) = self.find_partition(Path::new("/")).ok_or_else(|| {
-            io::Error::new(io::ErrorKind::InvalidInput, "root partition was not defined")
-        })?;
+    /// - Partitions must not overlap other partitions on their disk
+    pub fn verify_partitions(
+        &self,
+        bootloader: Bootloader,
+        root_minimum: u64,
+    ) -> Vec<ValidationIssue> {
+        use ValidationIssueKind::*;
+        let mut issues = Vec::new();
+        let issue = |kind, device: &Path, partition: &PartitionInfo| {
+            ValidationIssue::new(kind, Some(PartitionLocation::new(device, partition)))
+        };
 
-        use FileSystem::*;
-        match root.filesystem {
-            Some(Fat16) | Some(Fat32) | Some(Ntfs) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "root partition has invalid file system",
-                ));
-            }
-            Some(_) => (),
-            None => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "root partition does not have a file system",
-                ));
+        for disk in self.modified_disks() {
+            let device = disk.get_device_path().to_path_buf();
+            if let Some(cause) = disk.get_read_only_cause() {
+                issues.push(ValidationIssue::new(ReadOnly { device, cause }, None));
+            } else if disk.is_rst_member() {
+                issues.push(ValidationIssue::new(RstMember { device }, None));
             }
         }
 
-        let boot_partition = if bootloader == Bootloader::Efi {
-            Some(("/boot/efi", "EFI", true))
-        } else if self.device_is_logical(root_device) {
-            Some(("/boot", "boot", false))
-        } else {
-            None
-        };
+        if let Err(why) = self.verify_mount_targets() {
+            issues.push(ValidationIssue::new(MountTarget { why: why.to_string() }, None));
+        }
 
-        if let Some((partition, kind, is_efi)) = boot_partition {
-            let device = {
-                let (device, boot) =
-                    self.find_partition(Path::new(partition)).ok_or_else(|| {
-                        io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            format!("{} partition was not defined", kind),
-                        )
-                    })?;
-
-                let device = match self.find_disk(device) {
-                    Some(device) => device,
-                    None => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            "Unable to find the disk that the boot partition exists on",
-                        ))
+        let root = self.find_partition(Path::new("/"));
+        match root {
+            Some((device, root)) => {
+                use FileSystem::*;
+                match root.filesystem {
+                    Some(Exfat) | Some(Fat16) | Some(Fat32) | Some(Ntfs) | None => {
+                        let filesystem = root.filesystem;
+                        issues.push(issue(RootFileSystem { filesystem }, device, root));
                     }
-                };
+                    Some(_) => (),
+                }
 
-                if is_efi {
-                    // Check if the EFI partition is on a GPT disk.
-                    if device.get_partition_table() != Some(PartitionTable::Gpt) {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            "EFI installs cannot be done on disks without a GPT partition layout.",
-                        ));
-                    }
+                let size = partition_bytes(root);
+                if size < root_minimum {
+                    issues.push(issue(RootTooSmall { size, required: root_minimum }, device, root));
+                }
+            }
+            None => issues.push(ValidationIssue::new(MissingRoot, None)),
+        }
 
-                    if !boot.flags.contains(&PartitionFlag::PED_PARTITION_ESP) {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            format!("{} partition did not have ESP flag set", kind),
-                        ));
-                    }
+        let esp = self.find_partition(Path::new("/boot/efi"));
+        if bootloader == Bootloader::Efi {
+            match esp {
+                Some((device, esp)) => match self.find_disk(device) {
+                    Some(disk) => verify_esp(disk, esp, &mut issues),
+                    None => issues.push(issue(BootOnLogicalDevice, device, esp)),
+                },
+                None => issues.push(ValidationIssue::new(MissingEsp, None)),
+            }
+        }
 
-                    match boot.filesystem {
-                        Some(Fat16) | Some(Fat32) => (),
-                        Some(_) => {
-                            return Err(io::Error::new(
-                                io::ErrorKind::InvalidInput,
-                                format!("{} partition has invalid file system", kind),
-                            ));
-                        }
-                        None => {
-                            return Err(io::Error::new(
-                                io::ErrorKind::InvalidInput,
-                                format!("{} partition does not have a file system", kind),
-                            ));
-                        }
-                    }
+        match self.find_partition(Path::new("/boot")) {
+            Some((device, boot)) => {
+                let readable = boot.filesystem.map_or(false, |fs| BOOT_FILE_SYSTEMS.contains(&fs));
+                if !readable {
+                    let filesystem = boot.filesystem;
+                    issues.push(issue(BootFileSystem { filesystem }, device, boot));
+                }
 
-                    // 256 MiB should be the minimal size of the ESP partition.
-                    const REQUIRED_ESP_SIZE: u64 = 256 * 1024 * 1024;
-                    const REQUIRED_SECTORS: u64 = 524_288;
+                if bootloader != Bootloader::Efi && self.device_is_logical(device) {
+                    issues.push(issue(BootOnLogicalDevice, device, boot));
+                }
+            }
+            None => {
+                let logical_root = root.map_or(false, |(root, _)| self.device_is_logical(root));
+                if bootloader != Bootloader::Efi && logical_root {
+                    issues.push(ValidationIssue::new(MissingBoot, None));
+                }
+            }
+        }
 
-                    if (boot.get_device_path().read_link().is_err()
-                        && boot.get_sectors() < REQUIRED_SECTORS)
-                        || (boot.get_device_path().read_link().is_ok()
-                            && (boot.get_sectors() * boot.get_logical_block_size()
-                                < REQUIRED_ESP_SIZE))
-                    {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            "the ESP partition must be at least 256 MiB in size",
+        if let Some((device, secondary)) = self.get_secondary_esp() {
+            if bootloader != Bootloader::Efi {
+                issues.push(issue(SecondaryEspWithoutEfi, device, secondary));
+            } else if esp.map(|(primary, _)| primary) == Some(device) {
+                issues.push(issue(SecondaryEspSameDisk, device, secondary));
+            } else {
+                match self.find_disk(device) {
+                    Some(disk) => verify_esp(disk, secondary, &mut issues),
+                    None => issues.push(issue(BootOnLogicalDevice, device, secondary)),
+                }
+            }
+        }
+
+        for disk in &self.physical {
+            let partitions = disk
+                .partitions
+                .iter()
+                .filter(|part| !part.flag_is_enabled(REMOVE))
+                .filter(|part| part.part_type != PartitionType::Extended)
+                .collect::<Vec<_>>();
+
+            for (id, part) in partitions.iter().enumerate() {
+                for other in &partitions[id + 1..] {
+                    if part.sectors_overlap(other.start_sector, other.end_sector) {
+                        let other = PartitionLocation::new(disk.get_device_path(), other);
+                        issues.push(issue(
+                            OverlappingPartitions { other },
+                            disk.get_device_path(),
+                            part,
                         ));
                     }
                 }
-
-                device
-            };
-
-            if device.is_logical() {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    format!("{} partition cannot be on logical device", kind),
-                ));
             }
         }
 
-        Ok(())
+        issues
     }
 
     /// Loads existing logical volume data into memory, excluding encrypted volumes.
@@ -1098,6 +1452,74 @@ impl Disks {
         Ok(())
     }
 
+    /// Ensures that none of the volume groups that will be created share their name with a
+    /// volume group that already exists on the system, and which will not be replaced.
+    pub fn verify_volume_groups(&self) -> Result<(), DiskError> {
+        let existing = self.retained_volume_groups()?;
+        let conflict = self.logical.iter().find(|device| {
+            !device.is_source && existing.contains(&device.volume_group)
+        });
+
+        match conflict {
+            Some(device) => Err(DiskError::VolumeGroupConflict {
+                vg:       device.volume_group.clone(),
+                existing: existing.join(", "),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Gives a unique name to each volume group that would conflict with a volume group that
+    /// already exists on the system, returning the original and new names of each.
+    pub fn resolve_volume_group_conflicts(&mut self) -> Result<Vec<(String, String)>, DiskError> {
+        let existing = self.retained_volume_groups()?;
+        let mut renamed: Vec<(String, String)> = Vec::new();
+
+        for device in &mut self.logical {
+            if device.is_source || !existing.contains(&device.volume_group) {
+                continue;
+            }
+
+            let exclude =
+                renamed.iter().map(|(_, new)| misc::hasher(&new.as_str())).collect::<Vec<_>>();
+            let name = generate_unique_id(&device.volume_group, &exclude)
+                .map_err(|why| DiskError::ExternalCommand { why })?;
+
+            info!(
+                "renaming volume group '{}' to '{}' to avoid a conflict",
+                device.volume_group, name
+            );
+            renamed.push((device.volume_group.clone(), name.clone()));
+            device.rename(name);
+        }
+
+        for partition in self.physical.iter_mut().flat_map(|disk| disk.get_partitions_mut()) {
+            if let Some((ref mut vg, _)) = partition.volume_group {
+                if let Some((_, new)) = renamed.iter().find(|(old, _)| old == vg) {
+                    *vg = new.clone();
+                }
+            }
+        }
+
+        Ok(renamed)
+    }
+
+    /// Volume groups on the system which have a physical volume that will not be modified.
+    fn retained_volume_groups(&self) -> Result<Vec<String>, DiskError> {
+        let modified = self.get_device_paths_to_modify();
+        let mut replaced = physical_volumes_to_deactivate(&modified);
+        replaced.extend_from_slice(&modified);
+
+        let mut groups: BTreeMap<String, bool> = BTreeMap::new();
+        for (pv, vg) in pvs().map_err(|why| DiskError::ExternalCommand { why })? {
+            if let Some(vg) = vg {
+                *groups.entry(vg).or_insert(false) |= !replaced.contains(&pv);
+            }
+        }
+
+        Ok(groups.into_iter().filter(|&(_, retained)| retained).map(|(vg, _)| vg).collect())
+    }
+
     pub fn remove_logical_device(&mut self, volume: &str) {
         let mut remove_id = None;
         for (id, device) in self.logical.iter_mut().enumerate() {
@@ -1207,6 +1629,39 @@ impl FromIterator<Disk> for Disks {
     }
 }
 
+/// The path that a target will be mounted at within the base directory.
+fn mount_path(base_dir: &Path, target: &Path) -> PathBuf {
+    // Path mangling commences here, since we need to concatenate an absolute
+    // path onto another absolute path, and the standard library opts for
+    // overwriting the original path when doing that.
+
+    // Ensure that the base_dir path has the ending '/'.
+    let base_dir = base_dir.as_os_str().as_bytes();
+    let mut target_mount: Vec<u8> = if base_dir[base_dir.len() - 1] == b'/' {
+        base_dir.to_owned()
+    } else {
+        let mut temp = base_dir.to_owned();
+        temp.push(b'/');
+        temp
+    };
+
+    // Cut the starting '/' from the target path if it exists.
+    let target_path = target.as_os_str().as_bytes();
+    let target_path = if !target_path.is_empty() && target_path[0] == b'/' {
+        if target_path.len() > 1 {
+            &target_path[1..]
+        } else {
+            b""
+        }
+    } else {
+        target_path
+    };
+
+    // Append the target path to the base_dir, and return it as a path type.
+    target_mount.extend_from_slice(target_path);
+    PathBuf::from(OsString::from_vec(target_mount))
+}
+
 fn find_device_path_of_mount<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
     let path = path.as_ref();
     for mount in MountIter::new()? {
@@ -1218,3 +1673,67 @@ fn find_device_path_of_mount<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
 
     Err(io::Error::new(io::ErrorKind::NotFound, "mount not found"))
 }
+
+/// Where a data partition may be mounted within `/media`, named after its label or device.
+fn media_target(part: &PartitionInfo) -> PathBuf {
+    let name = part
+        .fs_label
+        .as_ref()
+        .map(|label| label.trim())
+        .filter(|label| !label.is_empty())
+        .map(|label| {
+            label
+                .chars()
+                .map(|c| if c.is_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+                .collect::<String>()
+        })
+        .filter(|label| !label.starts_with('.'))
+        .unwrap_or_else(|| {
+            let path = part.get_device_path();
+            path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned()
+        });
+
+    Path::new("/media").join(name)
+}
+
+/// Ensures that an EFI system partition on the `disk` can be booted from.
+fn verify_esp(disk: &Disk, esp: &PartitionInfo, issues: &mut Vec<ValidationIssue>) {
+    use FileSystem::*;
+    use ValidationIssueKind::*;
+    let mut issue = |kind| {
+        let location = PartitionLocation::new(disk.get_device_path(), esp);
+        issues.push(ValidationIssue::new(kind, Some(location)));
+    };
+
+    // Check if the EFI partition is on a GPT disk.
+    if disk.get_partition_table() != Some(PartitionTable::Gpt) {
+        issue(EspWithoutGpt);
+    }
+
+    if !esp.flags.contains(&PartitionFlag::PED_PARTITION_ESP) {
+        issue(BootFlagMissing);
+    }
+
+    match esp.filesystem {
+        Some(Fat16) | Some(Fat32) => (),
+        filesystem => issue(EspFileSystem { filesystem }),
+    }
+
+    // 256 MiB should be the minimal size of the ESP partition.
+    const REQUIRED_ESP_SIZE: u64 = 256 * 1024 * 1024;
+
+    let size = partition_bytes(esp);
+    if size < REQUIRED_ESP_SIZE {
+        issue(EspTooSmall { size, required: REQUIRED_ESP_SIZE });
+    }
+}
+
+/// The size of a partition in bytes. Logical volumes are measured in the sectors of their
+/// volume group, and physical partitions in 512-byte sectors.
+fn partition_bytes(partition: &PartitionInfo) -> u64 {
+    if partition.get_device_path().read_link().is_ok() {
+        partition.get_sectors() * partition.get_logical_block_size()
+    } else {
+        partition.get_sectors() * 512
+    }
+}
@@ -0,0 +1,175 @@
+//! Labels of the partitions and file systems which are created for an install. When a system
+//! holds several installs, each would otherwise have its own `recovery` partition and `EFI`
+//! file system, so a label which is already in use on another partition is suffixed with a
+//! short identifier of the machine, such as `recovery-3fa2`. The installed system is given a
+//! machine ID which begins with the suffix, so that the labels can be traced back to it.
+
+use disk_types::FileSystem;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// The longest name that a GPT partition may be given, in UTF-16 code units.
+pub const PARTITION_NAME_MAX: usize = 36;
+
+/// The number of characters of the machine ID which are used as a suffix.
+const SUFFIX_LEN: usize = 4;
+
+/// The number of hexadecimal characters in a machine ID.
+const MACHINE_ID_LEN: usize = 32;
+
+/// The longest label that the file system supports, in bytes.
+pub fn max_label_len(fs: FileSystem) -> usize {
+    match fs {
+        FileSystem::Fat16 | FileSystem::Fat32 => 11,
+        FileSystem::Xfs => 12,
+        FileSystem::Exfat | FileSystem::Swap => 15,
+        FileSystem::Ext2 | FileSystem::Ext3 | FileSystem::Ext4 => 16,
+        FileSystem::Ntfs => 128,
+        FileSystem::Btrfs => 255,
+        FileSystem::F2fs => 512,
+        FileSystem::Luks | FileSystem::Lvm => 0,
+    }
+}
+
+/// How the labels of new partitions and file systems are made unique.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelPolicy {
+    suffix: String,
+}
+
+impl LabelPolicy {
+    /// Suffixes labels which are in use with the given identifier.
+    pub fn new(suffix: &str) -> LabelPolicy { LabelPolicy { suffix: suffix.to_lowercase() } }
+
+    /// Suffixes labels which are in use with the beginning of a new machine ID, which the
+    /// installed system is given by `machine_id`. The machine ID of the live system is not
+    /// used, as every install from the same media would share it.
+    pub fn generate() -> LabelPolicy { LabelPolicy::new(&format!("{:04x}", rand::random::<u16>())) }
+
+    /// The policy which suffixed the label, if it ends with a suffix of a machine ID.
+    pub fn from_label(label: &str) -> Option<LabelPolicy> {
+        let suffix = &label[label.rfind('-')? + 1..];
+        if suffix.len() == SUFFIX_LEN && suffix.chars().all(|c| c.is_ascii_hexdigit()) {
+            Some(LabelPolicy::new(suffix))
+        } else {
+            None
+        }
+    }
+
+    pub fn suffix(&self) -> &str { &self.suffix }
+
+    /// A new machine ID which begins with the suffix, for the system whose labels it suffixed.
+    /// The suffix must be hexadecimal, as it is from `generate` and `from_label`.
+    pub fn machine_id(&self) -> String {
+        let mut machine_id = self.suffix.clone();
+        while machine_id.len() < MACHINE_ID_LEN {
+            machine_id.push_str(&format!("{:08x}", rand::random::<u32>()));
+        }
+
+        machine_id.truncate(MACHINE_ID_LEN);
+        machine_id
+    }
+
+    /// The `base` label with the suffix, shortened to fit the file system, if one is given, or
+    /// else the name of a GPT partition. FAT labels are upper case, by convention.
+    pub fn label(&self, base: &str, fs: Option<FileSystem>) -> String {
+        let max = fs.map_or(PARTITION_NAME_MAX, max_label_len);
+        let keep = max.saturating_sub(self.suffix.len() + 1);
+        let mut end = base.len().min(keep);
+        while !base.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let label = [&base[..end], "-", &self.suffix].concat();
+        match fs {
+            Some(FileSystem::Fat16) | Some(FileSystem::Fat32) => label.to_uppercase(),
+            _ => label,
+        }
+    }
+
+    /// The `base` label if no label in `in_use` matches it, or else the suffixed label.
+    /// Labels are compared without regard to case, as FAT labels are upper case.
+    pub fn unique_label<'a, I>(&self, base: &str, fs: Option<FileSystem>, in_use: I) -> String
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let base = match fs {
+            Some(FileSystem::Fat16) | Some(FileSystem::Fat32) => base.to_uppercase(),
+            _ => base.to_owned(),
+        };
+
+        if in_use.into_iter().any(|label| label.eq_ignore_ascii_case(&base)) {
+            self.label(&base, fs)
+        } else {
+            base
+        }
+    }
+}
+
+/// Labels which are shared by more than one partition, without regard to case, along with the
+/// partitions which have them. Both the partition names and file system labels are considered.
+pub(crate) fn collisions<'a, I>(labels: I) -> BTreeMap<String, Vec<PathBuf>>
+where
+    I: IntoIterator<Item = (&'a Path, &'a str)>,
+{
+    let mut found: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for (path, label) in labels {
+        let paths = found.entry(label.to_lowercase()).or_default();
+        if !paths.iter().any(|known| known == path) {
+            paths.push(path.to_path_buf());
+        }
+    }
+
+    found.retain(|_, paths| paths.len() > 1);
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suffixed_labels() {
+        let policy = LabelPolicy::new("3FA2");
+        assert_eq!(policy.label("recovery", None), "recovery-3fa2");
+        assert_eq!(policy.label("EFI", Some(FileSystem::Fat32)), "EFI-3FA2");
+        assert_eq!(policy.label("recovery", Some(FileSystem::Fat32)), "RECOVE-3FA2");
+        assert_eq!(policy.label("Pop_OS-data", Some(FileSystem::Ext4)), "Pop_OS-data-3fa2");
+
+        let in_use = || ["RECOVERY", "Windows"].iter().cloned();
+        assert_eq!(policy.unique_label("recovery", None, in_use()), "recovery-3fa2");
+        assert_eq!(policy.unique_label("EFI", Some(FileSystem::Fat32), in_use()), "EFI");
+    }
+
+    #[test]
+    fn machine_ids() {
+        let policy = LabelPolicy::from_label("EFI-3FA2").unwrap();
+        assert_eq!(policy.suffix(), "3fa2");
+
+        let machine_id = policy.machine_id();
+        assert_eq!(machine_id.len(), 32);
+        assert!(machine_id.starts_with("3fa2"));
+        assert!(machine_id.chars().all(|c| c.is_ascii_hexdigit()));
+
+        assert!(LabelPolicy::from_label("recovery").is_none());
+        assert!(LabelPolicy::from_label("Pop_OS-data").is_none());
+        assert!(LabelPolicy::from_label("EFI-3FA").is_none());
+    }
+
+    #[test]
+    fn label_collisions() {
+        let labels = vec![
+            (Path::new("/dev/sda1"), "EFI"),
+            (Path::new("/dev/sda2"), "recovery"),
+            (Path::new("/dev/sda2"), "RECOVERY"),
+            (Path::new("/dev/sdb1"), "efi"),
+            (Path::new("/dev/sdb2"), "data"),
+        ];
+
+        let found = collisions(labels);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found["efi"], vec![PathBuf::from("/dev/sda1"), PathBuf::from("/dev/sdb1")]);
+    }
+}
@@ -1,24 +1,34 @@
-use crate::external::{cryptsetup_encrypt, cryptsetup_open, pvcreate};
+use crate::external::{cryptsetup_encrypt, cryptsetup_open, pvcreate, LuksCredential};
 use std::{
     fmt,
     path::{Path, PathBuf},
 };
 use crate::DiskError;
 
+/// Where the keyfile which unlocks a volume containing `/boot` is stored within the installed
+/// system, and from where it is copied into the initramfs.
+pub const BOOT_KEYFILE: &str = "/etc/luks/boot_os.keyfile";
+
 /// A structure which contains the encryption settings for a physical volume.
 #[derive(Clone, PartialEq)]
 pub struct LvmEncryption {
     pub physical_volume: String,
     pub password:        Option<String>,
     pub keydata:         Option<(String, Option<(PathBuf, PathBuf)>)>,
+    /// A keyfile on the live system, which unlocks an existing encrypted partition.
+    pub keyfile:         Option<PathBuf>,
+    /// The volume will contain `/boot`, so it is formatted as LUKS1 for GRUB to unlock, and
+    /// the initramfs unlocks it with `BOOT_KEYFILE` rather than asking for the password again.
+    pub encrypted_boot:  bool,
 }
 
 impl fmt::Debug for LvmEncryption {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "LvmEncryption {{ physical_volume: {}, password: hidden, keydata: {:?} }}",
-            self.physical_volume, self.keydata
+            "LvmEncryption {{ physical_volume: {}, password: hidden, keydata: {:?}, \
+             keyfile: {:?}, encrypted_boot: {} }}",
+            self.physical_volume, self.keydata, self.keyfile, self.encrypted_boot
         )
     }
 }
@@ -33,15 +43,44 @@ impl LvmEncryption {
             physical_volume,
             password: password.into(),
             keydata: keydata.into().map(|key| (key, None)),
+            keyfile: None,
+            encrypted_boot: false,
+        }
+    }
+
+    /// Settings for unlocking an existing encrypted partition with a keyfile.
+    pub fn with_keyfile(physical_volume: String, keyfile: PathBuf) -> LvmEncryption {
+        LvmEncryption {
+            physical_volume,
+            password: None,
+            keydata: None,
+            keyfile: Some(keyfile),
+            encrypted_boot: false,
         }
     }
 
+    /// Defines that the volume will contain `/boot`, which GRUB will unlock.
+    pub fn encrypted_boot(mut self) -> LvmEncryption {
+        self.encrypted_boot = true;
+        self
+    }
+
     /// Encrypts a new partition with the settings stored in the structure.
     pub fn encrypt(&self, device: &Path) -> Result<(), DiskError> {
         cryptsetup_encrypt(device, self)
             .map_err(|why| DiskError::Encryption { volume: device.into(), why })
     }
 
+    /// The credential which unlocks an existing encrypted partition, in the order that
+    /// `open` prefers them. A key stored on another device is not available here.
+    pub fn credential(&self) -> Option<LuksCredential> {
+        match (self.keyfile.as_ref(), self.password.as_ref()) {
+            (Some(keyfile), _) => Some(LuksCredential::Keyfile(keyfile.clone())),
+            (None, Some(password)) => Some(LuksCredential::Password(password.clone())),
+            (None, None) => None,
+        }
+    }
+
     /// Opens the previously-encrypted partition with the same settings used to
     /// encrypt it.
     pub fn open(&self, device: &Path) -> Result<(), DiskError> {
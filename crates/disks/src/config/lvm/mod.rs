@@ -1,6 +1,6 @@
 mod encryption;
 
-pub use self::encryption::LvmEncryption;
+pub use self::encryption::{LvmEncryption, BOOT_KEYFILE};
 use super::{
     super::{
         DiskError, DiskExt, PartitionError, PartitionInfo, PartitionTable, PartitionType, FORMAT,
@@ -10,14 +10,16 @@ use super::{
 };
 use disk_types::{BlockDeviceExt, PartitionTableExt, SectorExt};
 pub use crate::external::deactivate_devices;
-use crate::external::{blkid_partition, lvcreate, lvremove, lvs, mkfs, vgactivate, vgcreate};
+use crate::external::{
+    blkid_partition, lvcreate, lvremove, lvs, mkfs, udev_settle, vg_extents, vgactivate,
+    vgcreate, Retry,
+};
 use partition_identity::PartitionIdentifiers;
 use proc_mounts::MOUNTS;
 use std::{
+    cmp,
     ffi::OsStr,
     path::{Path, PathBuf},
-    thread,
-    time::Duration,
 };
 
 #[derive(Debug, Default, Clone, PartialEq)]
@@ -124,6 +126,18 @@ impl LogicalDevice {
 
     pub fn add_sectors(&mut self, sectors: u64) { self.sectors += sectors; }
 
+    /// Changes the name of a volume group which has yet to be created.
+    pub fn rename(&mut self, volume_group: String) {
+        self.device_path =
+            PathBuf::from(format!("/dev/mapper/{}", volume_group.replace("-", "--")));
+        self.model_name = ["LVM ", &volume_group].concat();
+        if let Some(ref mut fs) = self.file_system {
+            fs.volume_group = Some((volume_group.clone(), self.encryption.clone()));
+        }
+
+        self.volume_group = volume_group;
+    }
+
     #[rustfmt::skip]
     pub fn validate(&self) -> Result<(), DiskError> {
         if self.get_partitions().iter().any(|p| p.name.is_none()) {
@@ -153,9 +167,29 @@ impl LogicalDevice {
 
     pub fn get_pe_size_in_sectors(&self) -> u64 { self.vg_data.pe_size }
 
-    pub fn shrink_vg(&mut self, _pes: u64) -> Result<(), DiskError> { Ok(()) }
+    /// Removes `pes` extents from the volume group, which must not be allocated to any of its
+    /// logical volumes.
+    pub fn shrink_vg(&mut self, pes: u64) -> Result<(), DiskError> {
+        if pes > self.vg_data.free_pe {
+            return Err(DiskError::VolumeGroupShrink {
+                vg:   self.volume_group.clone(),
+                pes,
+                free: self.vg_data.free_pe,
+            });
+        }
 
-    pub fn shrink_pv(&mut self, _sectors: u64) -> Result<(), DiskError> { Ok(()) }
+        self.vg_data.total_pe -= pes;
+        self.vg_data.free_pe -= pes;
+        self.sectors -= cmp::min(self.sectors, pes * self.vg_data.pe_size);
+        Ok(())
+    }
+
+    /// Removes `sectors` from a physical volume of the volume group, rounded up to a whole
+    /// number of extents.
+    pub fn shrink_pv(&mut self, sectors: u64) -> Result<(), DiskError> {
+        let pe_size = cmp::max(1, self.vg_data.pe_size);
+        self.shrink_vg((sectors + pe_size - 1) / pe_size)
+    }
 
     pub fn get_last_sector(&self) -> u64 {
         self.get_partitions()
@@ -183,17 +217,27 @@ impl LogicalDevice {
         info!("adding partitions to LVM device");
         let mut start_sector = 0;
         let _ = vgactivate(&self.volume_group);
+        match vg_extents(&self.volume_group) {
+            Ok((pe_size, total_pe, free_pe)) => {
+                let alloc_pe = total_pe.saturating_sub(free_pe);
+                self.vg_data = VgData { pe_size, total_pe, alloc_pe, free_pe };
+            }
+            Err(why) => warn!("unable to get extents of {}: {}", self.volume_group, why),
+        }
+
         if let Ok(logical_paths) = lvs(&self.volume_group) {
             for path in logical_paths {
-                // Wait for the device to be initialized, with a 5 second timeout.
-                let mut nth = 0;
-                while !path.exists() {
-                    info!("waiting 1 second because {:?} does not exist yet", path);
-                    if nth == 5 {
-                        break;
+                // Wait for device-mapper to create the node of the device.
+                let created = Retry::default().attempts(6).interval(250).wait_until(|| {
+                    path.exists() || {
+                        info!("waiting because {:?} does not exist yet", path);
+                        let _ = udev_settle();
+                        path.exists()
                     }
-                    nth += 1;
-                    thread::sleep(Duration::from_millis(1000));
+                });
+
+                if !created {
+                    warn!("{:?} was not created by device-mapper", path);
                 }
 
                 let length = match get_size(&path) {
@@ -225,6 +269,9 @@ impl LogicalDevice {
                     part_type: PartitionType::Primary,
                     flags: vec![],
                     filesystem: blkid_partition(&path),
+                    fs_label: None,
+                    fs_uuid: None,
+                    subvolumes: Vec::new(),
                     name: {
                         let dev = path
                             .file_name()
@@ -317,7 +364,9 @@ impl LogicalDevice {
                     .map_err(|why| DiskError::PartitionRemove { partition: -1, why })?;
             } else if partition.flag_is_enabled(FORMAT) {
                 if let Some(fs) = partition.filesystem {
-                    mkfs(&partition.device_path, fs).map_err(|why| {
+                    let fs_label = partition.fs_label.as_deref();
+                    let fs_uuid = partition.fs_uuid.as_deref();
+                    mkfs(&partition.device_path, fs, fs_label, fs_uuid).map_err(|why| {
                         DiskError::new_partition_error(
                             partition.device_path.clone(),
                             PartitionError::PartitionFormat { why },
@@ -4,15 +4,21 @@
 mod disk;
 mod disk_trait;
 mod disks;
+mod labels;
 mod lvm;
 mod partitions;
+mod session_mounts;
+mod validation;
 
 pub use self::{
     disk::*,
     disk_trait::{find_partition, find_partition_mut, DiskExt},
     disks::*,
+    labels::{max_label_len, LabelPolicy, PARTITION_NAME_MAX},
     lvm::*,
     partitions::*,
+    session_mounts::SessionMount,
+    validation::{validation_result, PartitionLocation, ValidationIssue, ValidationIssueKind},
 };
 pub use disk_types::{PartitionTable, Sector};
 
@@ -43,12 +49,20 @@ pub fn get_size(path: &Path) -> io::Result<u64> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        Bootloader, DiskError, DiskTopology, NonPosixTarget, PartitionFlag, RaidFormat,
+        RaidMetadata,
+    };
     use operations::*;
     use partition_identity::PartitionIdentifiers;
 
     fn get_default() -> Disks {
         Disks {
             physical: vec![Disk {
+                erase:       None,
+                raid:        None,
+                rst_active:  false,
+                topology:    DiskTopology::default(),
                 mklabel:     false,
                 model_name:  "Test Disk".into(),
                 serial:      "Test Disk 123".into(),
@@ -59,6 +73,7 @@ mod tests {
                 device_type: "TEST".into(),
                 table_type:  Some(PartitionTable::Gpt),
                 read_only:   false,
+                removable:   false,
                 partitions:  vec![
                     PartitionInfo {
                         bitflags:     ACTIVE | BUSY | SOURCE,
@@ -70,6 +85,9 @@ mod tests {
                         end_sector:   1026047,
                         filesystem:   Some(FileSystem::Fat16),
                         name:         None,
+                        fs_label:     None,
+                        fs_uuid:      None,
+                        subvolumes:   Vec::new(),
                         number:       1,
                         ordering:     1,
                         part_type:    PartitionType::Primary,
@@ -88,6 +106,9 @@ mod tests {
                         end_sector:   420456447,
                         filesystem:   Some(FileSystem::Btrfs),
                         name:         Some("Pop!_OS".into()),
+                        fs_label:     None,
+                        fs_uuid:      None,
+                        subvolumes:   Vec::new(),
                         number:       2,
                         ordering:     2,
                         part_type:    PartitionType::Primary,
@@ -106,6 +127,9 @@ mod tests {
                         end_sector:   1936738303,
                         filesystem:   Some(FileSystem::Ext4),
                         name:         Some("Solus OS".into()),
+                        fs_label:     None,
+                        fs_uuid:      None,
+                        subvolumes:   Vec::new(),
                         number:       3,
                         ordering:     3,
                         part_type:    PartitionType::Primary,
@@ -124,6 +148,9 @@ mod tests {
                         end_sector:   1953523711,
                         filesystem:   Some(FileSystem::Swap),
                         name:         None,
+                        fs_label:     None,
+                        fs_uuid:      None,
+                        subvolumes:   Vec::new(),
                         number:       4,
                         ordering:     4,
                         part_type:    PartitionType::Primary,
@@ -141,6 +168,10 @@ mod tests {
     fn get_empty() -> Disks {
         Disks {
             physical: vec![Disk {
+                erase:       None,
+                raid:        None,
+                rst_active:  false,
+                topology:    DiskTopology::default(),
                 mklabel:     false,
                 file_system: None,
                 model_name:  "Test Disk".into(),
@@ -151,6 +182,7 @@ mod tests {
                 device_type: "TEST".into(),
                 table_type:  Some(PartitionTable::Gpt),
                 read_only:   false,
+                removable:   false,
                 partitions:  Vec::new(),
             }],
             logical:  Vec::new(),
@@ -184,6 +216,8 @@ mod tests {
         assert_eq!(
             source.diff(&new).unwrap(),
             DiskOps {
+                wipe_raid:         false,
+                erase:             None,
                 mklabel:           None,
                 device_path:       Path::new("/dev/sdz"),
                 remove_partitions: vec![2048, 1026048, 420456448, 1936738304],
@@ -197,6 +231,8 @@ mod tests {
                         flags:        vec![],
                         format:       true,
                         label:        None,
+                        fs_label:     None,
+                        fs_uuid:      None,
                         path:         PathBuf::from("/dev/sdz"),
                     },
                     PartitionCreate {
@@ -207,6 +243,8 @@ mod tests {
                         flags:        vec![],
                         format:       true,
                         label:        None,
+                        fs_label:     None,
+                        fs_uuid:      None,
                         path:         PathBuf::from("/dev/sdz"),
                     },
                     PartitionCreate {
@@ -217,6 +255,8 @@ mod tests {
                         flags:        vec![],
                         format:       true,
                         label:        None,
+                        fs_label:     None,
+                        fs_uuid:      None,
                         path:         PathBuf::from("/dev/sdz"),
                     },
                 ],
@@ -272,4 +312,266 @@ mod tests {
             .unwrap();
         assert!(source.validate_layout(&duplicate).is_ok());
     }
+
+    #[test]
+    fn mount_targets() {
+        fn verify(target: &str, fs: FileSystem) -> io::Result<()> {
+            let mut disks = get_default();
+            let partition = &mut disks.physical[0].partitions[2];
+            partition.target = Some(target.into());
+            partition.filesystem = Some(fs);
+            disks.verify_mount_targets()
+        }
+
+        assert!(get_default().verify_mount_targets().is_ok());
+        assert!(verify("/srv", FileSystem::Ext4).is_ok());
+        assert!(verify("/var/lib/", FileSystem::Xfs).is_ok());
+        assert!(verify("/boot/efi/../srv", FileSystem::Ext4).is_err());
+        assert!(verify("srv", FileSystem::Ext4).is_err());
+        assert!(verify("/", FileSystem::Ext4).is_err());
+        assert!(verify("/proc/srv", FileSystem::Ext4).is_err());
+        assert!(verify("/boot/efi/srv", FileSystem::Ext4).is_err());
+        assert!(verify("/opt", FileSystem::Swap).is_err());
+    }
+
+    #[test]
+    fn non_posix_targets() {
+        fn verify(target: &str, fs: FileSystem, label: Option<&str>) -> io::Result<()> {
+            let mut disks = get_default();
+            let partition = &mut disks.physical[0].partitions[2];
+            partition.target = Some(target.into());
+            partition.filesystem = Some(fs);
+            partition.fs_label = label.map(String::from);
+            disks.verify_mount_targets()
+        }
+
+        fn alternative(result: io::Result<()>) -> Option<PathBuf> {
+            NonPosixTarget::from_io_error(&result.unwrap_err()).map(|why| why.alternative.clone())
+        }
+
+        assert!(verify("/media/data", FileSystem::Ntfs, None).is_ok());
+        assert!(verify("/home/shared", FileSystem::Exfat, None).is_ok());
+        assert!(verify("/recovery", FileSystem::Fat32, None).is_ok());
+        assert!(verify("/boot/efi2", FileSystem::Fat16, None).is_ok());
+        assert!(verify("/recovery", FileSystem::Ntfs, None).is_err());
+        assert!(verify("/boot", FileSystem::Fat32, None).is_err());
+        assert!(verify("/var/lib", FileSystem::Fat32, None).is_err());
+
+        assert_eq!(
+            alternative(verify("/home", FileSystem::Ntfs, None)),
+            Some(PathBuf::from("/media/sdz3"))
+        );
+        assert_eq!(
+            alternative(verify("/", FileSystem::Exfat, Some("My Files/2020"))),
+            Some(PathBuf::from("/media/My_Files_2020"))
+        );
+        assert_eq!(
+            alternative(verify("/srv", FileSystem::Ntfs, Some(".."))),
+            Some(PathBuf::from("/media/sdz3"))
+        );
+    }
+
+    #[test]
+    fn partition_validation() {
+        use ValidationIssueKind::*;
+        fn kinds(disks: &Disks, bootloader: Bootloader) -> Vec<ValidationIssueKind> {
+            disks.verify_partitions(bootloader, 0).into_iter().map(|issue| issue.kind).collect()
+        }
+
+        let mut disks = get_default();
+        assert_eq!(kinds(&disks, Bootloader::Efi), vec![BootFlagMissing]);
+        assert!(kinds(&disks, Bootloader::Bios).is_empty());
+
+        disks.physical[0].partitions[0].flags.push(PartitionFlag::PED_PARTITION_ESP);
+        assert!(kinds(&disks, Bootloader::Efi).is_empty());
+
+        let issues = disks.verify_partitions(Bootloader::Efi, 1 << 40);
+        assert_eq!(issues.len(), 1);
+        assert!(matches!(issues[0].kind, RootTooSmall { required, .. } if required == 1 << 40));
+        let location = issues[0].partition.as_ref().unwrap();
+        assert_eq!(location.partition, Path::new("/dev/sdz2"));
+        assert_eq!(location.target.as_deref(), Some(Path::new("/")));
+
+        // The root partition begins within the ESP.
+        disks.physical[0].partitions[1].start_sector = 1_000_000;
+        let issues = disks.verify_partitions(Bootloader::Efi, 0);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].partition.as_ref().unwrap().partition, Path::new("/dev/sdz1"));
+        match issues[0].kind {
+            OverlappingPartitions { ref other } => {
+                assert_eq!(other.partition, Path::new("/dev/sdz2"))
+            }
+            ref kind => panic!("unexpected issue: {}", kind),
+        }
+
+        disks.physical[0].partitions.remove(1);
+        disks.physical[0].partitions[0].filesystem = Some(FileSystem::Ext4);
+        assert_eq!(
+            kinds(&disks, Bootloader::Efi),
+            vec![MissingRoot, EspFileSystem { filesystem: Some(FileSystem::Ext4) }]
+        );
+    }
+
+    #[test]
+    fn rst_members() {
+        let imsm = RaidMetadata {
+            format: RaidFormat::Imsm,
+            level:  Some("container".into()),
+            uuid:   Some("a1b2c3d4:e5f60718:293a4b5c:6d7e8f90".into()),
+        };
+
+        // Metadata of an array which is not in use is stale, and is wiped with a new table.
+        let mut source = get_empty();
+        source.physical[0].raid = Some(imsm.clone());
+        let mut stale = source.physical[0].clone();
+        assert!(!stale.is_rst_member());
+        stale.mklabel(PartitionTable::Gpt).unwrap();
+        stale.add_partition(boot_part(2048)).unwrap();
+        assert!(source.physical[0].diff(&stale).unwrap().wipe_raid);
+
+        let mut disks = get_empty();
+        disks.physical[0].raid = Some(imsm.clone());
+        disks.physical[0].rst_active = true;
+        let member = &mut disks.physical[0];
+        assert!(member.is_rst_member());
+        assert!(matches!(member.add_partition(boot_part(2048)), Err(DiskError::RstMember { .. })));
+        assert!(matches!(member.mklabel(PartitionTable::Gpt), Err(DiskError::RstMember { .. })));
+
+        let arrays = disks.rst_arrays();
+        assert_eq!(arrays.len(), 1);
+        assert!(source.rst_arrays().is_empty());
+        assert_eq!(arrays[0].members, vec![PathBuf::from("/dev/sdz")]);
+        assert_eq!(disks.find_rst_array("/dev/sdz"), arrays.into_iter().next());
+
+        let mut disks = get_default();
+        disks.physical[0].raid = Some(imsm);
+        disks.physical[0].rst_active = true;
+        let issues = disks.verify_partitions(Bootloader::Bios, 0);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].kind, ValidationIssueKind::RstMember { device: "/dev/sdz".into() });
+    }
+
+    #[test]
+    fn alignment() {
+        use AlignmentIssueKind::*;
+        fn kinds(disk: &Disk) -> Vec<(u64, AlignmentIssueKind)> {
+            let issues = disk.check_alignment().into_iter();
+            issues.map(|issue| (issue.start_sector, issue.kind)).collect()
+        }
+
+        let mut source = get_empty().physical.into_iter().next().unwrap();
+        source.add_partition(boot_part(34)).unwrap();
+        source.add_partition(root_part(1024_035)).unwrap();
+        let overlapping = PartitionBuilder::new(1_000_000, 1_100_000, FileSystem::Ext4);
+        source.partitions.push(overlapping.build());
+
+        assert_eq!(
+            kinds(&source),
+            vec![
+                (34, Misaligned),
+                (34, Overlaps { other: 1_000_000 }),
+                (1_000_000, Misaligned),
+                (1_000_000, Overlaps { other: 1024_035 }),
+                (1024_035, Misaligned),
+            ]
+        );
+
+        // The overlapping partition would lose most of its sectors, so nothing is aligned.
+        assert!(source.auto_align().is_err());
+        assert_eq!(source.partitions[0].start_sector, 34);
+
+        source.partitions[2].end_sector = 1_026_000;
+        source.auto_align().unwrap();
+        assert!(kinds(&source).is_empty());
+        let boot = &source.partitions[0];
+        assert_eq!((boot.start_sector, boot.end_sector), (2048, 999_423));
+        assert_eq!(source.partitions[1].start_sector, 1026_048);
+        assert_eq!(source.partitions[2].end_sector, 1023_999);
+
+        // A partition which is smaller than the alignment has no room once it is aligned.
+        let mut source = get_empty().physical.into_iter().next().unwrap();
+        source.partitions.push(PartitionBuilder::new(2048, 2100, FileSystem::Ext4).build());
+        assert!(source.auto_align().is_err());
+
+        // Logical partitions keep the sector before them free for their EBR.
+        let mut source = get_empty().physical.into_iter().next().unwrap();
+        for &(start, end) in &[(2048, 1050_623), (1050_624, 2099_199)] {
+            let logical = PartitionBuilder::new(start, end, FileSystem::Ext4);
+            source.partitions.push(logical.partition_type(PartitionType::Logical).build());
+        }
+
+        source.auto_align().unwrap();
+        assert_eq!(source.partitions[0].end_sector, 1048_575);
+        assert_eq!(source.partitions[1].start_sector, 1050_624);
+    }
+
+    #[test]
+    fn mbr_gap() {
+        let mut source = get_empty().physical.into_iter().next().unwrap();
+        source.add_partition(root_part(63)).unwrap();
+        assert_eq!(source.mbr_gap(), None);
+
+        source.table_type = Some(PartitionTable::Msdos);
+        assert_eq!(source.mbr_gap(), Some(62));
+
+        // Partitions which do not exist yet are shrunk to make room.
+        let mut new = source.clone();
+        new.make_mbr_gap(false).unwrap();
+        assert_eq!(new.partitions[0].start_sector, MBR_GAP_END);
+        assert_eq!(new.partitions[0].end_sector, GIB20 + 63);
+
+        // Existing partitions are only moved if requested, and if there is room to move them.
+        source.partitions[0].bitflags |= SOURCE;
+        source.partitions[0].number = 1;
+        match source.clone().make_mbr_gap(false) {
+            Err(DiskError::MbrGapTooSmall { gap: 62, .. }) => (),
+            result => panic!("expected the partition not to be moved: {:?}", result),
+        }
+
+        let mut moved = source.clone();
+        moved.make_mbr_gap(true).unwrap();
+        assert_eq!(moved.mbr_gap(), Some(MBR_GAP_END - 1));
+        assert_eq!(moved.partitions[0].end_sector, GIB20 + MBR_GAP_END);
+
+        source.add_partition(boot_part(GIB20 + 64)).unwrap();
+        match source.make_mbr_gap(true) {
+            Err(DiskError::MbrGapTooSmall { gap: 62, .. }) => (),
+            result => panic!("expected the MBR gap to be too small: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn reused_partitions_writable() {
+        let mut disks = get_default();
+        disks.physical[0].read_only = true;
+        assert!(disks.verify_writable().is_err());
+
+        for part in &mut disks.physical[0].partitions {
+            part.target = None;
+        }
+        assert!(disks.verify_writable().is_ok());
+    }
+
+    #[test]
+    fn relocated_partitions() {
+        use super::disk::is_relocated;
+
+        let part = PartitionBuilder::new(2048, 1_050_623, FileSystem::Ext4).build();
+        assert!(!is_relocated(&part, 1, 2048, 1_048_576));
+        assert!(is_relocated(&part, 1, 4096, 1_048_576));
+        assert!(is_relocated(&part, 1, 2048, 2_097_152));
+
+        // The kernel reports 512-byte sectors of a disk with 4096-byte sectors.
+        assert!(!is_relocated(&part, 8, 16_384, 8_388_608));
+    }
+
+    #[test]
+    fn resized_ends() {
+        use super::disk::resized_end;
+
+        assert_eq!(resized_end(2048, 2048 + 4096 - 1), 2048 + 2048);
+        assert_eq!(resized_end(2048, 2048 + 4096), 2048 + 4096);
+        assert_eq!(resized_end(2048, 2048 + 6143), 2048 + 4096);
+        assert_eq!(resized_end(4096, 2048), 2048);
+    }
 }
@@ -1,6 +1,6 @@
 use super::{
     FileSystem, LvmEncryption, PartitionFlag, PartitionIdentifiers, PartitionInfo, PartitionType,
-    FORMAT,
+    Subvolume, FORMAT,
 };
 use std::path::PathBuf;
 
@@ -11,8 +11,12 @@ pub struct PartitionBuilder {
     pub filesystem:   Option<FileSystem>,
     pub part_type:    PartitionType,
     pub name:         Option<String>,
+    pub label:        Option<String>,
+    pub gpt_name:     Option<String>,
+    pub uuid:         Option<String>,
     pub flags:        Vec<PartitionFlag>,
     pub mount:        Option<PathBuf>,
+    pub subvolumes:   Vec<Subvolume>,
     pub volume_group: Option<(String, Option<LvmEncryption>)>,
     pub key_id:       Option<String>,
 }
@@ -26,8 +30,12 @@ impl PartitionBuilder {
             filesystem:   fs.into(),
             part_type:    PartitionType::Primary,
             name:         None,
+            label:        None,
+            gpt_name:     None,
+            uuid:         None,
             flags:        Vec::new(),
             mount:        None,
+            subvolumes:   Vec::new(),
             volume_group: None,
             key_id:       None,
         }
@@ -39,6 +47,24 @@ impl PartitionBuilder {
         self
     }
 
+    /// Defines the label of the file system that the new partition will be formatted with.
+    pub fn label(mut self, label: String) -> PartitionBuilder {
+        self.label = Some(label);
+        self
+    }
+
+    /// Defines the name of the new partition in a GPT partition table, overriding `name`.
+    pub fn gpt_name(mut self, name: String) -> PartitionBuilder {
+        self.gpt_name = Some(name);
+        self
+    }
+
+    /// Defines the UUID of the file system that the new partition will be formatted with.
+    pub fn uuid(mut self, uuid: String) -> PartitionBuilder {
+        self.uuid = Some(uuid);
+        self
+    }
+
     /// Defines whether the partition shall be a logical or primary partition.
     pub fn partition_type(mut self, part_type: PartitionType) -> PartitionBuilder {
         self.part_type = part_type;
@@ -63,6 +89,12 @@ impl PartitionBuilder {
         self
     }
 
+    /// Defines a btrfs subvolume to create on the new partition, and where it will be mounted.
+    pub fn subvolume(mut self, subvolume: Subvolume) -> PartitionBuilder {
+        self.subvolumes.push(subvolume);
+        self
+    }
+
     /// Assigns the new partition to a LVM volume group, which may optionally
     /// be encrypted.
     pub fn logical_volume(
@@ -99,7 +131,10 @@ impl PartitionBuilder {
                 self.filesystem
             },
             flags:        self.flags,
-            name:         self.name,
+            name:         self.gpt_name.or(self.name),
+            fs_label:     self.label,
+            fs_uuid:      self.uuid,
+            subvolumes:   self.subvolumes,
             device_path:  PathBuf::new(),
             mount_point:  None,
             ordering:     -1,
@@ -7,7 +7,7 @@ use super::{
 };
 pub use disk_types::{BlockDeviceExt, FileSystem, PartitionExt, PartitionType, SectorExt};
 use crate::external::{get_label, is_encrypted};
-use fstab_generate::BlockInfo;
+use fstab_generate::{BlockInfo, PartitionID};
 use libparted::{Partition, PartitionFlag};
 pub use os_detect::OS;
 use partition_identity::PartitionIdentifiers;
@@ -22,6 +22,9 @@ use sys_mount::swapoff;
 pub fn get_preferred_options(fs: FileSystem) -> &'static str {
     match fs {
         FileSystem::Fat16 | FileSystem::Fat32 => "umask=0077",
+        // The owner is given by `uid` and `gid` options once the users have been created.
+        FileSystem::Exfat => "umask=022,nofail",
+        FileSystem::Ntfs => "windows_names,nofail",
         FileSystem::Ext4 => "noatime,errors=remount-ro",
         FileSystem::Swap => "sw",
         FileSystem::F2fs => "defaults,compress_algorithm=lz4,compress_chksum,atgc,gc_merge,lazytime,nodiscard",
@@ -42,6 +45,16 @@ pub const BUSY: u8 = 0b01_0000;
 // Defines that this partition is currently swapped.
 pub const SWAPPED: u8 = 0b10_0000;
 
+/// A btrfs subvolume, which will be mounted at its own target.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subvolume {
+    /// The path of the subvolume within the file system, such as `@home`.
+    pub name:    String,
+    pub target:  PathBuf,
+    /// Mount options to use in place of the preferred options of the file system.
+    pub options: Option<String>,
+}
+
 /// Contains relevant information about a certain partition.
 #[derive(Debug, Clone, PartialEq)]
 pub struct PartitionInfo {
@@ -68,6 +81,14 @@ pub struct PartitionInfo {
     pub flags:        Vec<PartitionFlag>,
     /// Specifies the name of the partition.
     pub name:         Option<String>,
+    /// The label to give to the file system when the partition is formatted.
+    pub fs_label:     Option<String>,
+    /// The UUID to give to the file system when the partition is formatted.
+    pub fs_uuid:      Option<String>,
+    /// The btrfs subvolumes which will be created, and mounted at their own targets. A
+    /// subvolume which shares the partition's target is mounted there in place of the top
+    /// level of the file system.
+    pub subvolumes:   Vec<Subvolume>,
     /// Contains the device path of the partition, which is the disk's device path plus
     /// the partition number.
     pub device_path:  PathBuf,
@@ -138,6 +159,9 @@ impl PartitionInfo {
             number: partition.num(),
             ordering: -1,
             name: filesystem.and_then(|fs| get_label(&device_path, fs)),
+            fs_label: None,
+            fs_uuid: None,
+            subvolumes: Vec::new(),
             device_path,
             start_sector: partition.geom_start() as u64,
             end_sector: partition.geom_end() as u64,
@@ -214,6 +238,11 @@ impl PartitionInfo {
         self.flag_is_enabled(SOURCE) && other.flag_is_enabled(SOURCE) && self.number == other.number
     }
 
+    /// The name of the partition and the label of its file system, if they are set.
+    pub fn labels(&self) -> impl Iterator<Item = &str> {
+        self.name.iter().chain(self.fs_label.iter()).map(String::as_str)
+    }
+
     /// Defines a mount target for this partition.
     pub fn set_mount(&mut self, target: PathBuf) { self.target = Some(target); }
 
@@ -249,6 +278,19 @@ impl PartitionInfo {
         self.name = None;
     }
 
+    /// Defines that a new file system will be applied to this partition, with the given UUID.
+    /// If no UUID is given, the UUID of the current file system will be preserved, so long as
+    /// the new file system is of the same type, as the UUIDs of each type differ in format.
+    /// NOTE: this will also unset the partition's name.
+    pub fn format_with_uuid(&mut self, fs: FileSystem, uuid: Option<String>) {
+        self.fs_uuid = match uuid {
+            Some(uuid) => Some(uuid),
+            None if self.filesystem == Some(fs) => self.identifiers.uuid.clone(),
+            None => None,
+        };
+        self.format_with(fs);
+    }
+
     /// Defines that a new file system will be applied to this partition.
     /// Unlike `format_with`, this will not remove the name.
     pub fn format_and_keep_name(&mut self, fs: FileSystem) {
@@ -262,20 +304,52 @@ impl PartitionInfo {
     /// Specifies to delete this partition from the partition table.
     pub fn remove(&mut self) { self.bitflags |= REMOVE; }
 
+    /// Defines a btrfs subvolume to create on this partition, and where it will be mounted.
+    pub fn add_subvolume(&mut self, subvolume: Subvolume) {
+        self.subvolumes.retain(|existing| existing.target != subvolume.target);
+        self.subvolumes.push(subvolume);
+    }
+
     /// Obtains bock information for the partition, if possible, for use with
     /// generating entries in "/etc/fstab".
-    pub fn get_block_info(&self) -> Option<BlockInfo> {
-        let fs = self.get_file_system()?;
-        if fs != FileSystem::Swap && self.target.is_none() {
-            return None;
+    ///
+    /// Each subvolume of the partition has its own entry.
+    pub fn get_block_info(&self) -> Vec<BlockInfo> {
+        let fs = match self.get_file_system() {
+            Some(fs) => fs,
+            None => return Vec::new(),
+        };
+
+        if fs != FileSystem::Swap && self.target.is_none() && self.subvolumes.is_empty() {
+            return Vec::new();
         }
 
-        Some(BlockInfo::new(
-            BlockInfo::get_partition_id(&self.device_path, fs)?,
-            fs,
-            self.target.as_deref(),
-            get_preferred_options(fs),
-        ))
+        BlockInfo::get_partition_id(&self.device_path, fs)
+            .map_or_else(Vec::new, |id| self.block_info_with(id, fs))
+    }
+
+    /// Block information for the partition, where the `id` of its file system has already been
+    /// obtained by the caller.
+    pub fn block_info_with(&self, id: PartitionID, fs: FileSystem) -> Vec<BlockInfo> {
+        let mut entries = Vec::with_capacity(self.subvolumes.len() + 1);
+
+        let target = self.target.as_deref();
+        let subvolume_at_target =
+            self.subvolumes.iter().any(|subvolume| Some(subvolume.target.as_path()) == target);
+
+        if fs == FileSystem::Swap || (target.is_some() && !subvolume_at_target) {
+            entries.push(BlockInfo::new(id.clone(), fs, target, get_preferred_options(fs)));
+        }
+
+        for subvolume in &self.subvolumes {
+            let options = subvolume.options.as_deref().unwrap_or_else(|| get_preferred_options(fs));
+            entries.push(
+                BlockInfo::new(id.clone(), fs, Some(&subvolume.target), options)
+                    .subvolume(&subvolume.name),
+            );
+        }
+
+        entries
     }
 }
 
@@ -311,7 +385,8 @@ fn get_flags(partition: &Partition) -> Vec<PartitionFlag> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::Path;
+    use partition_identity::PartitionSource;
+    use std::{ffi::OsString, path::Path};
 
     fn efi_partition() -> PartitionInfo {
         PartitionInfo {
@@ -324,6 +399,9 @@ mod tests {
             end_sector:   1026047,
             filesystem:   Some(FileSystem::Fat16),
             name:         None,
+            fs_label:     None,
+            fs_uuid:      None,
+            subvolumes:   Vec::new(),
             number:       1,
             ordering:     1,
             part_type:    PartitionType::Primary,
@@ -345,6 +423,9 @@ mod tests {
             end_sector:   420456447,
             filesystem:   Some(FileSystem::Btrfs),
             name:         Some("Pop!_OS".into()),
+            fs_label:     None,
+            fs_uuid:      None,
+            subvolumes:   Vec::new(),
             number:       2,
             ordering:     2,
             part_type:    PartitionType::Primary,
@@ -366,6 +447,9 @@ mod tests {
             end_sector:   1936738303,
             filesystem:   Some(FileSystem::Luks),
             name:         None,
+            fs_label:     None,
+            fs_uuid:      None,
+            subvolumes:   Vec::new(),
             number:       4,
             ordering:     4,
             part_type:    PartitionType::Primary,
@@ -378,6 +462,8 @@ mod tests {
                     physical_volume: "LUKS_PV".into(),
                     password:        Some("password".into()),
                     keydata:         None,
+                    keyfile:         None,
+                    encrypted_boot:  false,
                 }),
             )),
         }
@@ -394,6 +480,9 @@ mod tests {
             end_sector:   1936738303,
             filesystem:   Some(FileSystem::Lvm),
             name:         None,
+            fs_label:     None,
+            fs_uuid:      None,
+            subvolumes:   Vec::new(),
             number:       4,
             ordering:     4,
             part_type:    PartitionType::Primary,
@@ -415,6 +504,9 @@ mod tests {
             end_sector:   1953523711,
             filesystem:   Some(FileSystem::Swap),
             name:         None,
+            fs_label:     None,
+            fs_uuid:      None,
+            subvolumes:   Vec::new(),
             number:       4,
             ordering:     4,
             part_type:    PartitionType::Primary,
@@ -425,6 +517,72 @@ mod tests {
         }
     }
 
+    fn btrfs_partition() -> PartitionInfo {
+        let mut partition = root_partition();
+        partition.format_with(FileSystem::Btrfs);
+        partition.add_subvolume(Subvolume {
+            name:    "@root".into(),
+            target:  PathBuf::from("/"),
+            options: None,
+        });
+        partition.add_subvolume(Subvolume {
+            name:    "@home".into(),
+            target:  PathBuf::from("/home"),
+            options: Some("noatime,compress=zstd".into()),
+        });
+        partition
+    }
+
+    #[test]
+    fn partition_subvolume_block_info() {
+        let id = PartitionID { id: "ROOT".into(), variant: PartitionSource::UUID };
+        let partition = btrfs_partition();
+
+        let mut fstab = OsString::new();
+        for entry in partition.block_info_with(id.clone(), FileSystem::Btrfs) {
+            entry.write_entry(&mut fstab);
+        }
+
+        assert_eq!(
+            fstab,
+            OsString::from(
+                "UUID=ROOT  /  btrfs  subvol=@root,defaults  0  1\n\
+                 UUID=ROOT  /home  btrfs  subvol=@home,noatime,compress=zstd  0  0\n"
+            )
+        );
+
+        // The top level of the file system is mounted at the target if no subvolume is.
+        let mut partition = partition;
+        partition.target = Some(PathBuf::from("/mnt/pool"));
+        let mounts = partition
+            .block_info_with(id, FileSystem::Btrfs)
+            .iter()
+            .map(|entry| (entry.mount().to_owned(), entry.subvol))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            mounts,
+            vec![
+                (OsString::from("/mnt/pool"), None),
+                (OsString::from("/"), Some("@root")),
+                (OsString::from("/home"), Some("@home")),
+            ]
+        );
+    }
+
+    #[test]
+    fn partition_subvolume_replaced() {
+        let mut partition = btrfs_partition();
+        partition.add_subvolume(Subvolume {
+            name:    "@home-new".into(),
+            target:  PathBuf::from("/home"),
+            options: None,
+        });
+
+        let names = partition.subvolumes.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["@root", "@home-new"]);
+    }
+
     #[test]
     fn partition_sectors() {
         assert_eq!(swap_partition().get_sectors(), 16785407);
@@ -465,6 +623,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn partition_format_with_uuid() {
+        let mut root = root_partition();
+        root.identifiers.uuid = Some("0a1b2c3d-4e5f-6071-8293-a4b5c6d7e8f9".into());
+
+        let mut same = root.clone();
+        same.format_with_uuid(FileSystem::Btrfs, None);
+        assert_eq!(same.fs_uuid, root.identifiers.uuid);
+
+        let mut other = root.clone();
+        other.format_with_uuid(FileSystem::Fat32, None);
+        assert_eq!(other.fs_uuid, None);
+
+        let uuid = "c0ffee00-0000-4000-8000-000000000000";
+        let mut given = root.clone();
+        given.format_with_uuid(FileSystem::Ext4, Some(uuid.into()));
+        assert_eq!(given.fs_uuid.as_deref(), Some(uuid));
+    }
+
     #[test]
     fn partition_sectors_differ_from() {
         assert!(root_partition().sectors_differ_from(&efi_partition()));
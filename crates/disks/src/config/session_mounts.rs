@@ -0,0 +1,196 @@
+//! Mounts and swaps of the live session, which hold the partitions of a disk that is about to
+//! be modified. A partition which was opened in the file manager is mounted beneath
+//! `/media`, and the kernel will refuse to re-read the partition table of its disk until it
+//! has been unmounted. File systems of LUKS and LVM volumes are found through the partitions
+//! which their device maps are backed by.
+
+use disk_types::is_partition_of;
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+use sys_mount::{swapoff, unmount, UnmountFlags};
+
+/// Mounts of the live system itself, which are never released.
+const LIVE_MOUNTS: &[&str] = &["/", "/cdrom"];
+
+/// A mount or swap of the live session, on a disk which is to be modified.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionMount {
+    /// A file system of the `source` device which is mounted at `dest`.
+    Mount { source: PathBuf, dest: PathBuf },
+    /// A partition which is in use as swap.
+    Swap(PathBuf),
+}
+
+impl SessionMount {
+    /// Unmounts the file system, or deactivates the swap.
+    pub fn release(&self) -> io::Result<()> {
+        match *self {
+            SessionMount::Mount { ref dest, .. } => unmount(dest, UnmountFlags::empty()),
+            SessionMount::Swap(ref source) => swapoff(source),
+        }
+    }
+}
+
+impl fmt::Display for SessionMount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SessionMount::Mount { ref source, ref dest } => {
+                write!(f, "{} mounted at {}", source.display(), dest.display())
+            }
+            SessionMount::Swap(ref source) => write!(f, "swap on {}", source.display()),
+        }
+    }
+}
+
+/// The devices which the device map at `source`, such as `/dev/mapper/cryptdata`, is backed
+/// by, including the devices which back those in turn.
+pub(crate) fn backing_devices(source: &Path) -> Vec<PathBuf> {
+    let mut devices = Vec::new();
+    let name = match source.canonicalize() {
+        Ok(ref path) if path.starts_with("/dev") => match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => return devices,
+        },
+        _ => return devices,
+    };
+
+    let mut pending = vec![name];
+    while let Some(name) = pending.pop() {
+        let slaves = match fs::read_dir(["/sys/class/block/", &name, "/slaves"].concat()) {
+            Ok(slaves) => slaves,
+            Err(_) => continue,
+        };
+
+        for slave in slaves.flatten() {
+            let slave = slave.file_name().to_string_lossy().into_owned();
+            devices.push(PathBuf::from(["/dev/", &slave].concat()));
+            pending.push(slave);
+        }
+    }
+
+    devices
+}
+
+/// Finds the mounts and swaps whose source is one of the `disks`, a partition of them, or a
+/// device map which they are `backing`, along with any mounts which are nested beneath them.
+/// Nested mounts are ordered before their parents, so that they may be released in order.
+pub(crate) fn session_mounts_on<B: Fn(&Path) -> Vec<PathBuf>>(
+    disks: &[&Path],
+    mounts: &[(PathBuf, PathBuf)],
+    swaps: &[PathBuf],
+    backing: B,
+) -> Vec<SessionMount> {
+    let is_on_disks = |source: &Path| {
+        let source = source.to_string_lossy();
+        disks.iter().any(|disk| {
+            let disk = disk.to_string_lossy();
+            source == disk || is_partition_of(&source, &disk)
+        })
+    };
+
+    let on_disks = |source: &Path| {
+        is_on_disks(source) || backing(source).iter().any(|device| is_on_disks(device))
+    };
+
+    let is_live = |dest: &Path| LIVE_MOUNTS.iter().any(|live| dest == Path::new(live));
+
+    let mut found = BTreeMap::new();
+    for (source, dest) in mounts {
+        if on_disks(source) && !is_live(dest) {
+            found.insert(dest, source);
+        }
+    }
+
+    let parents = found.keys().cloned().collect::<Vec<_>>();
+    for (source, dest) in mounts {
+        if parents.iter().any(|parent| dest.starts_with(parent)) {
+            found.entry(dest).or_insert(source);
+        }
+    }
+
+    let mut session_mounts = found
+        .into_iter()
+        .rev()
+        .map(|(dest, source)| SessionMount::Mount { source: source.clone(), dest: dest.clone() })
+        .collect::<Vec<_>>();
+
+    for source in swaps.iter().filter(|source| on_disks(source)) {
+        session_mounts.push(SessionMount::Swap(source.clone()));
+    }
+
+    session_mounts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mount(source: &str, dest: &str) -> (PathBuf, PathBuf) { (source.into(), dest.into()) }
+
+    #[test]
+    fn mounts_on_disks() {
+        let mounts = vec![
+            mount("/dev/sdb1", "/"),
+            mount("/dev/sr0", "/cdrom"),
+            mount("/dev/sda2", "/media/pop-os/data"),
+            mount("tmpfs", "/media/pop-os/data/cache"),
+            mount("/dev/sda1", "/media/pop-os/ESP"),
+            mount("/dev/sdaa1", "/media/pop-os/other"),
+            mount("/dev/nvme0n1p2", "/mnt"),
+            mount("/dev/mmcblk0p1", "/media/pop-os/sd"),
+        ];
+
+        let swaps = vec![PathBuf::from("/dev/sda3"), PathBuf::from("/dev/sdc2")];
+        let disks = [Path::new("/dev/sda"), Path::new("/dev/sdb")];
+
+        let found = session_mounts_on(&disks, &mounts, &swaps, |_| Vec::new());
+        assert_eq!(found, vec![
+            SessionMount::Mount {
+                source: "tmpfs".into(),
+                dest:   "/media/pop-os/data/cache".into(),
+            },
+            SessionMount::Mount {
+                source: "/dev/sda2".into(),
+                dest:   "/media/pop-os/data".into(),
+            },
+            SessionMount::Mount {
+                source: "/dev/sda1".into(),
+                dest:   "/media/pop-os/ESP".into(),
+            },
+            SessionMount::Swap("/dev/sda3".into()),
+        ]);
+
+        assert_eq!(found[0].to_string(), "tmpfs mounted at /media/pop-os/data/cache");
+        assert_eq!(found[3].to_string(), "swap on /dev/sda3");
+    }
+    #[test]
+    fn mounts_on_device_maps() {
+        let mounts = vec![
+            mount("/dev/mapper/data-root", "/media/pop-os/root"),
+            mount("/dev/mapper/other-root", "/media/pop-os/other"),
+        ];
+
+        let swaps = vec![PathBuf::from("/dev/mapper/data-swap")];
+        let disks = [Path::new("/dev/sda")];
+
+        // The volumes of the `data` group are on a LUKS volume of `/dev/sda3`.
+        let backing = |source: &Path| {
+            if source.to_string_lossy().starts_with("/dev/mapper/data-") {
+                vec![PathBuf::from("/dev/dm-0"), PathBuf::from("/dev/sda3")]
+            } else {
+                vec![PathBuf::from("/dev/sdb1")]
+            }
+        };
+
+        assert_eq!(session_mounts_on(&disks, &mounts, &swaps, backing), vec![
+            SessionMount::Mount {
+                source: "/dev/mapper/data-root".into(),
+                dest:   "/media/pop-os/root".into(),
+            },
+            SessionMount::Swap("/dev/mapper/data-swap".into()),
+        ]);
+    }
+}
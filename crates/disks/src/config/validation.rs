@@ -0,0 +1,194 @@
+//! Issues with a partition configuration, which are found before the disks are modified.
+//!
+//! Every issue names the partition that it concerns, where there is one, so that a frontend may
+//! highlight the offending partition instead of showing a message for the configuration as a
+//! whole.
+
+use super::PartitionInfo;
+use disk_types::{BlockDeviceExt, FileSystem, ReadOnlyCause};
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// Where a partition is, or will be, found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionLocation {
+    /// The disk, or the volume group, which contains the partition.
+    pub device:       PathBuf,
+    /// The device path of the partition, which is empty for a partition that is yet to be
+    /// created.
+    pub partition:    PathBuf,
+    /// The first sector of the partition on its device.
+    pub start_sector: u64,
+    /// Where the partition will be mounted, if at all.
+    pub target:       Option<PathBuf>,
+}
+
+impl PartitionLocation {
+    pub(crate) fn new(device: &Path, partition: &PartitionInfo) -> Self {
+        PartitionLocation {
+            device:       device.to_path_buf(),
+            partition:    partition.get_device_path().to_path_buf(),
+            start_sector: partition.start_sector,
+            target:       partition.target.clone(),
+        }
+    }
+}
+
+impl fmt::Display for PartitionLocation {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.partition.as_os_str().is_empty() {
+            write!(
+                fmt,
+                "the new partition at sector {} of {}",
+                self.start_sector,
+                self.device.display()
+            )?;
+        } else {
+            write!(fmt, "{}", self.partition.display())?;
+        }
+
+        match self.target {
+            Some(ref target) => write!(fmt, " ({})", target.display()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// What is wrong with a partition configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationIssueKind {
+    /// A disk which would be modified may not be written to.
+    ReadOnly { device: PathBuf, cause: ReadOnlyCause },
+    /// A disk which would be modified is a member of an Intel RST array.
+    RstMember { device: PathBuf },
+    /// A mount target is invalid, or conflicts with another target.
+    MountTarget { why: String },
+    /// No partition is mounted at `/`.
+    MissingRoot,
+    /// The root partition lacks a file system with Unix permissions.
+    RootFileSystem { filesystem: Option<FileSystem> },
+    /// The root partition, in bytes, is smaller than the system which will be installed to it.
+    RootTooSmall { size: u64, required: u64 },
+    /// An EFI install lacks a partition at `/boot/efi`.
+    MissingEsp,
+    /// A root partition on a logical device lacks a partition at `/boot`.
+    MissingBoot,
+    /// A boot partition is on a logical device, which the firmware is unable to read.
+    BootOnLogicalDevice,
+    /// The disk of an ESP lacks a GPT partition table.
+    EspWithoutGpt,
+    /// An ESP lacks the `esp` flag, without which the firmware will not boot from it.
+    BootFlagMissing,
+    /// An ESP lacks a FAT file system.
+    EspFileSystem { filesystem: Option<FileSystem> },
+    /// An ESP, in bytes, is smaller than the boot files of the install require.
+    EspTooSmall { size: u64, required: u64 },
+    /// The `/boot` partition has a file system which the boot loader is unable to read.
+    BootFileSystem { filesystem: Option<FileSystem> },
+    /// The partition shares sectors with another partition on its disk.
+    OverlappingPartitions { other: PartitionLocation },
+    /// A secondary ESP was configured for a BIOS install.
+    SecondaryEspWithoutEfi,
+    /// The secondary ESP is on the disk of the primary ESP.
+    SecondaryEspSameDisk,
+}
+
+impl fmt::Display for ValidationIssueKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use self::ValidationIssueKind::*;
+        let mib = |bytes: u64| bytes / 1024 / 1024;
+        match *self {
+            ReadOnly { ref device, cause } => {
+                write!(fmt, "{} may not be written to: {}", device.display(), cause)
+            }
+            RstMember { ref device } => write!(
+                fmt,
+                "{} is a member of an Intel RST array, and may not be partitioned on its own",
+                device.display()
+            ),
+            MountTarget { ref why } => fmt.write_str(why),
+            MissingRoot => fmt.write_str("root partition was not defined"),
+            RootFileSystem { filesystem: Some(fs) } => {
+                write!(fmt, "root partition has invalid file system ({})", fs)
+            }
+            RootFileSystem { filesystem: None } => {
+                fmt.write_str("root partition does not have a file system")
+            }
+            RootTooSmall { size, required } => write!(
+                fmt,
+                "root partition is {} MiB, but the system requires {} MiB",
+                mib(size),
+                mib(required)
+            ),
+            MissingEsp => fmt.write_str("EFI partition was not defined"),
+            MissingBoot => fmt.write_str("boot partition was not defined"),
+            BootOnLogicalDevice => fmt.write_str("boot partition cannot be on logical device"),
+            EspWithoutGpt => fmt.write_str(
+                "EFI installs cannot be done on disks without a GPT partition layout",
+            ),
+            BootFlagMissing => fmt.write_str("EFI partition did not have ESP flag set"),
+            EspFileSystem { filesystem: Some(fs) } => {
+                write!(fmt, "EFI partition has invalid file system ({})", fs)
+            }
+            EspFileSystem { filesystem: None } => {
+                fmt.write_str("EFI partition does not have a file system")
+            }
+            EspTooSmall { size, required } => write!(
+                fmt,
+                "EFI partition is {} MiB, but must be at least {} MiB in size",
+                mib(size),
+                mib(required)
+            ),
+            BootFileSystem { filesystem: Some(fs) } => write!(
+                fmt,
+                "boot partition has a file system which GRUB is unable to read ({})",
+                fs
+            ),
+            BootFileSystem { filesystem: None } => {
+                fmt.write_str("boot partition does not have a file system")
+            }
+            OverlappingPartitions { ref other } => write!(fmt, "partition overlaps {}", other),
+            SecondaryEspWithoutEfi => {
+                fmt.write_str("a secondary EFI partition requires an EFI install")
+            }
+            SecondaryEspSameDisk => {
+                fmt.write_str("the secondary EFI partition must be on a different disk")
+            }
+        }
+    }
+}
+
+/// An issue with a partition configuration, which prevents the install.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationIssue {
+    pub kind:      ValidationIssueKind,
+    /// The partition which the issue concerns, if it concerns one.
+    pub partition: Option<PartitionLocation>,
+}
+
+impl ValidationIssue {
+    pub(crate) fn new(kind: ValidationIssueKind, partition: Option<PartitionLocation>) -> Self {
+        ValidationIssue { kind, partition }
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.partition {
+            Some(ref partition) => write!(fmt, "{}: {}", partition, self.kind),
+            None => write!(fmt, "{}", self.kind),
+        }
+    }
+}
+
+/// Fails with every issue in one error, for callers that have no use for each issue alone.
+pub fn validation_result(issues: &[ValidationIssue]) -> io::Result<()> {
+    if issues.is_empty() {
+        return Ok(());
+    }
+
+    let issues = issues.iter().map(ToString::to_string).collect::<Vec<_>>();
+    Err(io::Error::new(io::ErrorKind::InvalidInput, issues.join("; ")))
+}
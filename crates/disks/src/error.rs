@@ -1,11 +1,22 @@
 pub use disk_types::PartitionSizeError;
-use disk_types::{FileSystem, PartitionTableError};
-use std::{io, path::PathBuf};
+use disk_types::{FileSystem, PartitionTableError, ReadOnlyCause};
+use std::{error::Error, fmt, io, path::PathBuf};
 
 /// Defines a variety of errors that may arise from configuring and committing changes to disks.
 #[rustfmt::skip]
 #[derive(Debug, Fail)]
 pub enum DiskError {
+    #[fail(
+        display = "the new partition at sector {} of {:?} has no room once aligned to 1 MiB",
+        start, device
+    )]
+    AlignmentNoRoom { device: PathBuf, start: u64 },
+    #[fail(
+        display = "the new partition at sector {} of {:?} would shrink from {} to {} sectors \
+                   once aligned to 1 MiB",
+        start, device, sectors, aligned
+    )]
+    AlignmentShrink { device: PathBuf, start: u64, sectors: u64, aligned: u64 },
     #[fail(display = "decryption error: {}", why)]
     Decryption { why: DecryptionError },
     #[fail(display = "unable to get device at {:?}: {}", device, why)]
@@ -26,6 +37,8 @@ pub enum DiskError {
     Encryption { volume: PathBuf, why: io::Error },
     #[fail(display = "unable to open encrypted volume '{:?}': {}", volume, why)]
     EncryptionOpen { volume: PathBuf, why: io::Error },
+    #[fail(display = "{:?} can only be erased when a new partition table is written", device)]
+    EraseWithoutTable { device: PathBuf },
     #[fail(display = "problem executing external command: {}", why)]
     ExternalCommand { why: io::Error },
     #[fail(display = "serial model does not match")]
@@ -46,6 +59,8 @@ pub enum DiskError {
     KeyWithoutPath,
     #[fail(display = "LUKS keyfile partition does not have a mount target")]
     KeyFileWithoutPath,
+    #[fail(display = "label {:?} is longer than the {} bytes that {:?} supports", label, max, fs)]
+    LabelTooLong { label: String, fs: FileSystem, max: usize },
     #[fail(display = "partition layout on disk has changed")]
     LayoutChanged,
     #[fail(display = "unable to create logical volume: {}", why)]
@@ -54,10 +69,19 @@ pub enum DiskError {
     LogicalPartitionNotFound { group: String, volume: String },
     #[fail(display = "unable to get mount points: {}", why)]
     MountsObtain { why: io::Error },
+    #[fail(
+        display = "the gap after the MBR on {:?} is {} sectors, but GRUB requires {}: {}",
+        device, gap, required, why
+    )]
+    MbrGapTooSmall { device: PathBuf, gap: u64, required: u64, why: &'static str },
     #[fail(display = "new partition could not be found")]
     NewPartNotFound,
     #[fail(display = "partition error ({:?}): {}", partition, why)]
     PartitionError { partition: PathBuf, why: PartitionError },
+    #[fail(display = "unable to find partition at {:?}", device)]
+    PartitionGet { device: PathBuf },
+    #[fail(display = "unable to grow the last partition of {:?}: {}", device, why)]
+    GrowLastPartition { device: PathBuf, why: io::Error },
     #[fail(display = "partition {} not be found on disk", partition)]
     PartitionNotFound { partition: i32 },
     #[fail(display = "partition exceeds size of disk")]
@@ -70,6 +94,16 @@ pub enum DiskError {
     PartitionTable { why: PartitionTableError },
     #[fail(display = "unable to create physical volume from '{}': {}", volume, why)]
     PhysicalVolumeCreate { volume: String, why: io::Error },
+    #[fail(display = "unable to write to {:?}: {}", device, cause)]
+    ReadOnly { device: PathBuf, cause: ReadOnlyCause },
+    #[fail(display = "unable to relabel the file system on {:?}: {}", device, why)]
+    Relabel { device: PathBuf, why: io::Error },
+    #[fail(
+        display = "{:?} is a member of an Intel RST array, and may not be partitioned on its own; \
+                   install to the array, or switch the firmware from RAID to AHCI mode",
+        device
+    )]
+    RstMember { device: PathBuf },
     #[fail(display = "multiple devices had the same volume group: currently unsupported")]
     SameGroup,
     #[fail(display = "sector overlaps partition {}", id)]
@@ -80,6 +114,13 @@ pub enum DiskError {
     Unmount { device: PathBuf, why: io::Error },
     #[fail(display = "unable to create volume group '{}' on {:?}: {}", vg, device, why)]
     VolumeGroupCreate { device: PathBuf, vg: String, why: io::Error },
+    #[fail(display = "volume group '{}' already exists (existing volume groups: {})", vg, existing)]
+    VolumeGroupConflict { vg: String, existing: String },
+    #[fail(
+        display = "unable to remove {} extents from volume group '{}', which has {} free",
+        pes, vg, free
+    )]
+    VolumeGroupShrink { vg: String, pes: u64, free: u64 },
     #[fail(display = "logical partition on {:?} lacks a label", device)]
     VolumePartitionLacksLabel { device: PathBuf },
 }
@@ -115,6 +156,8 @@ pub enum PartitionError {
 pub enum DecryptionError {
     #[fail(display = "failed to decrypt '{:?}': {}", device, why)]
     Open { device: PathBuf, why: io::Error },
+    #[fail(display = "the key supplied for '{:?}' was rejected", device)]
+    WrongKey { device: PathBuf },
     #[fail(display = "decrypted partition, '{:?}', lacks volume group", device)]
     DecryptedLacksVG { device: PathBuf },
     #[fail(display = "LUKS partition at '{:?}' was not found", device)]
@@ -157,3 +200,44 @@ impl From<PartitionSizeError> for PartitionError {
 impl From<PartitionTableError> for DiskError {
     fn from(why: PartitionTableError) -> DiskError { DiskError::PartitionTable { why } }
 }
+
+/// A partition with a file system that lacks Unix ownership and permissions, which was assigned
+/// a mount target that holds the system or the home directories of its users.
+///
+/// This is stored within the `io::Error` that is returned when verifying mount targets, and may
+/// be obtained with `io::Error::get_ref` and `downcast_ref`, so that the partition may be
+/// offered to be mounted at the `alternative` target instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NonPosixTarget {
+    pub device:      PathBuf,
+    pub filesystem:  FileSystem,
+    pub target:      PathBuf,
+    /// A target within `/media`, where the partition may be mounted as a data partition.
+    pub alternative: PathBuf,
+}
+
+impl NonPosixTarget {
+    /// Creates an `io::Error` which contains this error.
+    pub fn into_io_error(self) -> io::Error { io::Error::new(io::ErrorKind::InvalidInput, self) }
+
+    /// Obtains the error that an `io::Error` was created from, if there is one.
+    pub fn from_io_error(error: &io::Error) -> Option<&NonPosixTarget> {
+        error.get_ref().and_then(|inner| inner.downcast_ref::<NonPosixTarget>())
+    }
+}
+
+impl fmt::Display for NonPosixTarget {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{} on {} does not support Unix permissions, and may not be mounted at {}: it may be \
+             mounted at {} instead",
+            self.filesystem,
+            self.device.display(),
+            self.target.display(),
+            self.alternative.display()
+        )
+    }
+}
+
+impl Error for NonPosixTarget {}
@@ -66,20 +66,22 @@ pub fn cryptsetup_encrypt(device: &Path, enc: &LvmEncryption) -> io::Result<()>
     info!("cryptsetup is encrypting {} with {:?}", device.display(), enc);
 
     match (enc.password.as_ref(), enc.keydata.as_ref()) {
-        (Some(_password), Some(_keydata)) => unimplemented!(),
-        (Some(password), None) => exec(
-            "cryptsetup",
-            Some(&append_newline(password.as_bytes())),
-            None,
-            &[
-                "-s".into(),
-                "512".into(),
-                "luksFormat".into(),
-                "--type".into(),
-                "luks2".into(),
-                device.into(),
-            ],
-        ),
+        (Some(password), Some(&(_, ref keydata))) => {
+            luks_format(device, password, enc.encrypted_boot)?;
+
+            // The password occupies the first key slot, and a keyfile is enrolled into the
+            // second, so that the device may also be unlocked without user interaction.
+            let keydata = keydata.as_ref().expect("field should have been populated");
+            let tmpfs = TempDir::new("distinst")?;
+            let supported = SupportedFilesystems::new()?;
+            let _mount = Mount::new(&keydata.0, tmpfs.path(), &supported, MountFlags::BIND, None)?
+                .into_unmount_drop(UnmountFlags::DETACH);
+            let keypath = tmpfs.path().join(&enc.physical_volume);
+
+            generate_keyfile(&keypath)?;
+            add_key(device, LuksKey::Password(password), LuksKey::Keyfile(&keypath), Some(1))
+        }
+        (Some(password), None) => luks_format(device, password, enc.encrypted_boot),
         (None, Some(&(_, ref keydata))) => {
             let keydata = keydata.as_ref().expect("field should have been populated");
             let tmpfs = TempDir::new("distinst")?;
@@ -100,7 +102,7 @@ pub fn cryptsetup_encrypt(device: &Path, enc: &LvmEncryption) -> io::Result<()>
                     "512".into(),
                     "luksFormat".into(),
                     "--type".into(),
-                    "luks2".into(),
+                    luks_type(enc.encrypted_boot).into(),
                     device.into(),
                     tmpfs.path().join(&enc.physical_volume).into(),
                 ],
@@ -115,14 +117,12 @@ pub fn cryptsetup_open(device: &Path, enc: &LvmEncryption) -> io::Result<()> {
     deactivate_devices(&[device])?;
     let pv = &enc.physical_volume;
     info!("cryptsetup is opening {} with pv {} and {:?}", device.display(), pv, enc);
+    if let Some(ref keyfile) = enc.keyfile {
+        return luks_open(device, pv, LuksKey::Keyfile(keyfile));
+    }
+
     match (enc.password.as_ref(), enc.keydata.as_ref()) {
-        (Some(_password), Some(_keydata)) => unimplemented!(),
-        (Some(password), None) => exec(
-            "cryptsetup",
-            Some(&append_newline(password.as_bytes())),
-            None,
-            &["open".into(), device.into(), pv.into()],
-        ),
+        (Some(password), _) => luks_open(device, pv, LuksKey::Password(password)),
         (None, Some(&(_, ref keydata))) => {
             let keydata = keydata.as_ref().expect("field should have been populated");
             let tmpfs = TempDir::new("distinst")?;
@@ -132,17 +132,56 @@ pub fn cryptsetup_open(device: &Path, enc: &LvmEncryption) -> io::Result<()> {
             let keypath = tmpfs.path().join(&enc.physical_volume);
             info!("keypath exists: {}", keypath.is_file());
 
-            exec(
-                "cryptsetup",
-                None,
-                None,
-                &["open".into(), device.into(), pv.into(), "--key-file".into(), keypath.into()],
-            )
+            luks_open(device, pv, LuksKey::Keyfile(&keypath))
         }
         (None, None) => unimplemented!(),
     }
 }
 
+/// Formats the device as a LUKS volume which is unlocked by the password.
+fn luks_format(device: &Path, password: &str, encrypted_boot: bool) -> io::Result<()> {
+    exec(
+        "cryptsetup",
+        Some(&append_newline(password.as_bytes())),
+        None,
+        &[
+            "-s".into(),
+            "512".into(),
+            "luksFormat".into(),
+            "--type".into(),
+            luks_type(encrypted_boot).into(),
+            device.into(),
+        ],
+    )
+}
+
+/// GRUB is only able to unlock LUKS1 volumes, so those containing `/boot` must use LUKS1.
+fn luks_type(encrypted_boot: bool) -> &'static str {
+    if encrypted_boot {
+        "luks1"
+    } else {
+        "luks2"
+    }
+}
+
+/// Creates each of the btrfs subvolumes on the device which does not already exist.
+pub fn btrfs_create_subvolumes(device: &Path, names: &[&str]) -> io::Result<()> {
+    let top_level = TempDir::new("distinst")?;
+    let flags = MountFlags::empty();
+    let _mount = Mount::new(device, top_level.path(), "btrfs", flags, Some("subvolid=5"))?
+        .into_unmount_drop(UnmountFlags::DETACH);
+
+    for name in names {
+        let path = top_level.path().join(name);
+        if !path.exists() {
+            info!("creating btrfs subvolume {} on {}", name, device.display());
+            exec("btrfs", None, None, &["subvolume".into(), "create".into(), path.into()])?;
+        }
+    }
+
+    Ok(())
+}
+
 /// Append a newline to the input (used for the password)
 fn append_newline(input: &[u8]) -> Vec<u8> {
     let mut input = input.to_owned();
@@ -27,11 +27,22 @@ extern crate tempdir;
 mod config;
 mod error;
 pub mod external;
+mod network;
+mod raid;
+mod rst;
 mod serial;
+mod smart;
+mod topology;
 
 pub use self::{
     config::*,
-    error::{DecryptionError, DiskError, PartitionError, PartitionSizeError},
+    error::{DecryptionError, DiskError, NonPosixTarget, PartitionError, PartitionSizeError},
+    network::NetworkDisk,
+    raid::{RaidFormat, RaidMetadata},
+    rst::{IntelRaidMode, RstArray},
+    smart::{SmartHealth, SmartStatus},
+    topology::{DiskTopology, Transport},
 };
 pub use bootloader::{Bootloader, FORCE_BOOTLOADER};
 pub use libparted::PartitionFlag;
+pub use operations::EraseMode;
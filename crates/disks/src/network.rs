@@ -0,0 +1,205 @@
+//! Disks which are attached over the network, through iSCSI or NVMe over Fabrics, as with
+//! diskless workstations. The installed system must attach its disks from the initramfs, so the
+//! connection to each disk is read from sysfs, to be written into the installed system.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// NVMe transports which reach the subsystem over a network.
+const FABRICS: &[&str] = &["tcp", "rdma", "fc"];
+
+/// How a disk which is attached over the network is reached.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkDisk {
+    /// A LUN of an iSCSI target.
+    Iscsi {
+        /// The IQN of the target, such as `iqn.2004-01.com.example:storage`.
+        target:    String,
+        address:   String,
+        port:      String,
+        /// The IQN which this system logged in as.
+        initiator: String,
+    },
+    /// A namespace of an NVMe subsystem.
+    NvmeOverFabrics {
+        /// The fabric which the subsystem is reached over: `tcp`, `rdma`, or `fc`.
+        transport: String,
+        address:   String,
+        /// The port of the subsystem, for transports which have ports.
+        service:   Option<String>,
+        /// The NQN of the subsystem.
+        nqn:       String,
+    },
+}
+
+impl NetworkDisk {
+    /// Describes how the disk, such as `sdb` or `nvme1n1`, is attached, if it is attached over
+    /// the network.
+    pub fn from_sysfs(name: &str) -> Option<NetworkDisk> {
+        NetworkDisk::probe_in(Path::new("/sys"), name)
+    }
+
+    fn probe_in(sys: &Path, name: &str) -> Option<NetworkDisk> {
+        let device = sys.join("class/block").join(name).canonicalize().ok()?;
+        match iscsi_session(&device) {
+            Some(session) => iscsi(sys, session),
+            None if name.starts_with("nvme") => nvme(&device),
+            None => None,
+        }
+    }
+}
+
+/// The iSCSI session which the device is attached through, such as `session1`.
+pub(crate) fn iscsi_session(device: &Path) -> Option<&str> {
+    device
+        .components()
+        .filter_map(|component| component.as_os_str().to_str())
+        .find(|component| component.starts_with("session"))
+}
+
+/// The fabric which an NVMe namespace is reached over, or `pcie` for a local drive.
+pub(crate) fn nvme_transport(device: &Path) -> Option<String> {
+    nvme_controller(device).and_then(|controller| read(&controller.join("transport")))
+}
+
+/// Whether an NVMe transport reaches the subsystem over a network.
+pub(crate) fn is_fabric(transport: &str) -> bool { FABRICS.contains(&transport) }
+
+fn iscsi(sys: &Path, session: &str) -> Option<NetworkDisk> {
+    let session_path = sys.join("class/iscsi_session").join(session);
+
+    // The first connection of `session1` is `connection1:0`.
+    let connection = ["connection", &session["session".len()..], ":0"].concat();
+    let connection = sys.join("class/iscsi_connection").join(connection);
+
+    Some(NetworkDisk::Iscsi {
+        target:    read(&session_path.join("targetname"))?,
+        address:   read(&connection.join("persistent_address"))?,
+        port:      read(&connection.join("persistent_port"))?,
+        initiator: read(&session_path.join("initiatorname"))?,
+    })
+}
+
+fn nvme(device: &Path) -> Option<NetworkDisk> {
+    let controller = nvme_controller(device)?;
+    let transport = read(&controller.join("transport"))?;
+    if !is_fabric(&transport) {
+        return None;
+    }
+
+    // Such as `traddr=192.168.1.10,trsvcid=4420`.
+    let address = read(&controller.join("address"))?;
+    let field = |key: &str| {
+        address.split(',').find_map(|field| {
+            field.strip_prefix(key).and_then(|value| value.strip_prefix('=')).map(String::from)
+        })
+    };
+
+    Some(NetworkDisk::NvmeOverFabrics {
+        address: field("traddr")?,
+        service: field("trsvcid"),
+        nqn:     read(&controller.join("subsysnqn"))?,
+        transport,
+    })
+}
+
+/// The controller of an NVMe namespace. With native multipathing, the namespace belongs to the
+/// subsystem instead, and is reached through the first of its controllers.
+fn nvme_controller(device: &Path) -> Option<PathBuf> {
+    let parent = device.parent()?;
+    if parent.join("transport").exists() {
+        return Some(parent.to_path_buf());
+    }
+
+    let mut controllers = parent
+        .read_dir()
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.join("transport").exists())
+        .collect::<Vec<_>>();
+
+    controllers.sort();
+    controllers.into_iter().next()
+}
+
+fn read(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|value| value.trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempdir::TempDir;
+
+    fn write(dir: &Path, files: &[(&str, &str)]) {
+        fs::create_dir_all(dir).unwrap();
+        for &(file, value) in files {
+            fs::write(dir.join(file), [value, "\n"].concat()).unwrap();
+        }
+    }
+
+    #[test]
+    fn iscsi() {
+        let tempdir = TempDir::new("distinst").unwrap();
+        let sys = tempdir.path();
+        let device = sys.join("devices/platform/host3/session1/target3:0:0/3:0:0:0/block/sdb");
+        fs::create_dir_all(&device).unwrap();
+        fs::create_dir_all(sys.join("class/block")).unwrap();
+        symlink(&device, sys.join("class/block/sdb")).unwrap();
+
+        write(&sys.join("class/iscsi_session/session1"), &[
+            ("targetname", "iqn.2004-01.com.example:storage"),
+            ("initiatorname", "iqn.2004-10.com.ubuntu:01:workstation"),
+        ]);
+        write(&sys.join("class/iscsi_connection/connection1:0"), &[
+            ("persistent_address", "192.168.1.10"),
+            ("persistent_port", "3260"),
+        ]);
+
+        assert_eq!(
+            NetworkDisk::probe_in(sys, "sdb"),
+            Some(NetworkDisk::Iscsi {
+                target:    "iqn.2004-01.com.example:storage".into(),
+                address:   "192.168.1.10".into(),
+                port:      "3260".into(),
+                initiator: "iqn.2004-10.com.ubuntu:01:workstation".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn nvme_over_fabrics() {
+        let tempdir = TempDir::new("distinst").unwrap();
+        let sys = tempdir.path();
+        let subsystem = sys.join("devices/virtual/nvme-subsystem/nvme-subsys1");
+        let local = sys.join("devices/pci0000:00/0000:00:1d.0/nvme/nvme0");
+        fs::create_dir_all(subsystem.join("nvme1n1")).unwrap();
+        fs::create_dir_all(local.join("nvme0n1")).unwrap();
+        fs::create_dir_all(sys.join("class/block")).unwrap();
+        symlink(subsystem.join("nvme1n1"), sys.join("class/block/nvme1n1")).unwrap();
+        symlink(local.join("nvme0n1"), sys.join("class/block/nvme0n1")).unwrap();
+
+        write(&subsystem.join("nvme1"), &[
+            ("transport", "tcp"),
+            ("address", "traddr=192.168.1.10,trsvcid=4420,src_addr=192.168.1.2"),
+            ("subsysnqn", "nqn.2014-08.org.example:workstation"),
+        ]);
+        write(&local, &[("transport", "pcie")]);
+
+        assert_eq!(
+            NetworkDisk::probe_in(sys, "nvme1n1"),
+            Some(NetworkDisk::NvmeOverFabrics {
+                transport: "tcp".into(),
+                address:   "192.168.1.10".into(),
+                service:   Some("4420".into()),
+                nqn:       "nqn.2014-08.org.example:workstation".into(),
+            })
+        );
+
+        assert_eq!(NetworkDisk::probe_in(sys, "nvme0n1"), None);
+    }
+}
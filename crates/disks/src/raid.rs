@@ -0,0 +1,182 @@
+//! RAID metadata which was left on a disk by mdadm, or by the firmware RAID of Intel RST and
+//! DDF controllers.
+//!
+//! The metadata is stored at the start or the end of the disk, outside of its partitions, and
+//! survives the partition table being rewritten. If left behind, udev may assemble the disk
+//! into an array, which holds it open and causes its new partitions to fail to be formatted.
+
+use std::{fmt, io, path::Path, process::Command};
+
+/// The format of the RAID metadata.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RaidFormat {
+    /// The native metadata of Linux software RAID.
+    Md,
+    /// Intel Matrix Storage Manager metadata, which is written by Intel RST.
+    Imsm,
+    /// SNIA Disk Data Format metadata, which is written by some hardware RAID controllers.
+    Ddf,
+}
+
+impl fmt::Display for RaidFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            RaidFormat::Md => "Linux software RAID",
+            RaidFormat::Imsm => "Intel RST",
+            RaidFormat::Ddf => "DDF",
+        })
+    }
+}
+
+/// RAID metadata which was found on a disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RaidMetadata {
+    pub format: RaidFormat,
+    /// The RAID level of the array, such as `raid1`, or `container` for IMSM and DDF.
+    pub level:  Option<String>,
+    /// The UUID of the array that the disk is a member of.
+    pub uuid:   Option<String>,
+}
+
+impl RaidMetadata {
+    /// Examines the device at `path` for RAID metadata with `mdadm`, or with `blkid` if mdadm
+    /// is not installed.
+    pub fn examine(path: &Path) -> io::Result<Option<RaidMetadata>> {
+        let output = Command::new("mdadm").args(&["--examine", "--export"]).arg(path).output();
+        let output = match output {
+            Ok(output) => output,
+            // The metadata of a member of an Intel RST array must still be found without mdadm,
+            // as the firmware will assemble the array regardless.
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => {
+                return RaidMetadata::probe(path)
+            }
+            Err(why) => return Err(why),
+        };
+
+        // mdadm exits with a failure status when no metadata was found.
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        Ok(RaidMetadata::parse(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Probes the device at `path` for the signature of a RAID member with `blkid`.
+    fn probe(path: &Path) -> io::Result<Option<RaidMetadata>> {
+        let output = Command::new("blkid").args(&["-p", "-o", "export"]).arg(path).output()?;
+
+        // blkid exits with a failure status when no signature was found.
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        Ok(RaidMetadata::parse_blkid(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn parse(output: &str) -> Option<RaidMetadata> {
+        let mut metadata = None;
+        let mut level = None;
+        let mut uuid = None;
+
+        for line in output.lines() {
+            let mut fields = line.splitn(2, '=');
+            let (key, value) = match (fields.next(), fields.next()) {
+                (Some(key), Some(value)) => (key, Some(value.trim().to_owned())),
+                _ => continue,
+            };
+
+            match key {
+                "MD_METADATA" => metadata = value,
+                "MD_LEVEL" => level = value,
+                "MD_UUID" => uuid = value,
+                _ => (),
+            }
+        }
+
+        if metadata.is_none() && level.is_none() && uuid.is_none() {
+            return None;
+        }
+
+        let format = match metadata.as_deref() {
+            Some("imsm") => RaidFormat::Imsm,
+            Some("ddf") => RaidFormat::Ddf,
+            _ => RaidFormat::Md,
+        };
+
+        Some(RaidMetadata { format, level, uuid })
+    }
+
+    fn parse_blkid(output: &str) -> Option<RaidMetadata> {
+        let mut format = None;
+        let mut uuid = None;
+
+        for line in output.lines() {
+            let mut fields = line.splitn(2, '=');
+            match (fields.next(), fields.next()) {
+                (Some("TYPE"), Some(value)) => {
+                    format = match value.trim() {
+                        "isw_raid_member" => Some(RaidFormat::Imsm),
+                        "ddf_raid_member" => Some(RaidFormat::Ddf),
+                        "linux_raid_member" => Some(RaidFormat::Md),
+                        _ => None,
+                    }
+                }
+                (Some("UUID"), Some(value)) => uuid = Some(value.trim().to_owned()),
+                _ => (),
+            }
+        }
+
+        format.map(|format| RaidMetadata { format, level: None, uuid })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn md() {
+        let output = "MD_LEVEL=raid1\nMD_DEVICES=2\nMD_NAME=pop-os:0\n\
+                      MD_UUID=3b5a1c2e:0b6f4d1a:9c8e7f6d:5a4b3c2d\nMD_EVENTS=19\n";
+        assert_eq!(
+            RaidMetadata::parse(output),
+            Some(RaidMetadata {
+                format: RaidFormat::Md,
+                level:  Some("raid1".into()),
+                uuid:   Some("3b5a1c2e:0b6f4d1a:9c8e7f6d:5a4b3c2d".into()),
+            })
+        );
+    }
+
+    #[test]
+    fn imsm() {
+        let output = "MD_METADATA=imsm\nMD_LEVEL=container\n\
+                      MD_UUID=a1b2c3d4:e5f60718:293a4b5c:6d7e8f90\nMD_DEVICES=2\n";
+        let metadata = RaidMetadata::parse(output).unwrap();
+        assert_eq!(metadata.format, RaidFormat::Imsm);
+        assert_eq!(metadata.level.as_deref(), Some("container"));
+    }
+
+    #[test]
+    fn blkid() {
+        let output = "DEVNAME=/dev/sda\nVERSION=1.3.00\nTYPE=isw_raid_member\n";
+        assert_eq!(
+            RaidMetadata::parse_blkid(output),
+            Some(RaidMetadata { format: RaidFormat::Imsm, level: None, uuid: None })
+        );
+
+        let output = "DEVNAME=/dev/sdb\nUUID=3b5a1c2e-0b6f-4d1a-9c8e-7f6d5a4b3c2d\n\
+                      TYPE=linux_raid_member\n";
+        let metadata = RaidMetadata::parse_blkid(output).unwrap();
+        assert_eq!(metadata.format, RaidFormat::Md);
+        assert_eq!(metadata.uuid.as_deref(), Some("3b5a1c2e-0b6f-4d1a-9c8e-7f6d5a4b3c2d"));
+
+        assert_eq!(RaidMetadata::parse_blkid("DEVNAME=/dev/sdc\nPTTYPE=gpt\n"), None);
+    }
+
+    #[test]
+    fn no_metadata() {
+        assert_eq!(RaidMetadata::parse(""), None);
+        assert_eq!(RaidMetadata::parse("mdadm: No md superblock detected on /dev/sda.\n"), None);
+    }
+}
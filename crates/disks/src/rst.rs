@@ -0,0 +1,348 @@
+//! Intel storage controllers which the firmware has set to RAID mode, for Intel Rapid Storage
+//! Technology (RST).
+//!
+//! In RAID mode, NVMe drives are remapped behind the AHCI controller, where Linux is unable to
+//! reach them, and the controller may be left without a driver entirely. The disks then have
+//! no block devices, and cannot be installed to until the firmware is set to AHCI mode.
+//!
+//! SATA disks remain visible in RAID mode, but those which are members of an array carry IMSM
+//! metadata. The firmware boots from the volume of the array, which mdadm assembles as an md
+//! device, so each member must not be partitioned on its own.
+
+use super::raid::{RaidFormat, RaidMetadata};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+const INTEL_VENDOR: &str = "0x8086";
+
+/// The PCI class of mass storage controllers in RAID mode.
+const RAID_CLASS: u32 = 0x0104;
+
+/// Intel storage controllers which are in RAID mode.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IntelRaidMode {
+    /// The PCI addresses of the controllers, such as `0000:00:17.0`.
+    pub controllers:   Vec<String>,
+    /// The number of NVMe drives which are remapped behind the controllers.
+    pub remapped_nvme: u32,
+    /// Whether a controller has no driver, leaving its disks without block devices.
+    pub unbound:       bool,
+}
+
+impl IntelRaidMode {
+    /// Detects Intel storage controllers in RAID mode, if there are any.
+    pub fn detect() -> Option<IntelRaidMode> { IntelRaidMode::detect_in(Path::new("/sys")) }
+
+    fn detect_in(sys: &Path) -> Option<IntelRaidMode> {
+        let mut raid = IntelRaidMode::default();
+        if let Ok(devices) = sys.join("bus/pci/devices").read_dir() {
+            for device in devices.filter_map(Result::ok) {
+                let path = device.path();
+                let read = |file: &str| fs::read_to_string(path.join(file)).unwrap_or_default();
+                if read("vendor").trim() != INTEL_VENDOR || !is_raid_class(read("class").trim()) {
+                    continue;
+                }
+
+                // VMD controllers are supported by Linux, and their drives remain visible.
+                let driver = path.join("driver").read_link().ok();
+                let driver = driver.as_ref().and_then(|driver| driver.file_name());
+                if driver.map_or(false, |name| name == "vmd") {
+                    continue;
+                }
+
+                raid.unbound |= driver.is_none();
+                raid.controllers.push(device.file_name().to_string_lossy().into_owned());
+            }
+        }
+
+        if raid.controllers.is_empty() {
+            return None;
+        }
+
+        // The AHCI driver reports the NVMe drives that it found remapped behind the controller.
+        if let Ok(hosts) = sys.join("class/scsi_host").read_dir() {
+            raid.remapped_nvme = hosts
+                .filter_map(Result::ok)
+                .filter_map(|host| fs::read_to_string(host.path().join("remapped_nvme")).ok())
+                .filter_map(|count| count.trim().parse::<u32>().ok())
+                .sum();
+        }
+
+        raid.controllers.sort();
+        Some(raid)
+    }
+
+    /// Whether disks are hidden from Linux by the controllers.
+    pub fn hides_disks(&self) -> bool { self.remapped_nvme != 0 || self.unbound }
+}
+
+impl fmt::Display for IntelRaidMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(
+            "the storage controller is in Intel RST RAID mode, which hides disks from Linux; \
+             switch the SATA / storage mode from RAID to AHCI in the firmware settings",
+        )
+    }
+}
+
+/// Whether the Intel RST array which the disk at `path` is a member of is in use. The array is
+/// live while the controller is in RAID mode, from which the firmware boots it, or while its
+/// volume is assembled. Otherwise, its metadata is stale, and may be wiped.
+pub(crate) fn is_active(path: &Path) -> bool { is_active_in(Path::new("/sys"), path) }
+
+fn is_active_in(sys: &Path, path: &Path) -> bool {
+    IntelRaidMode::detect_in(sys).is_some()
+        || path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| assembled_volume(sys, name))
+            .is_some()
+}
+
+/// An array of Intel RST, as a pseudo-device which groups its member disks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RstArray {
+    /// The UUID of the array, if it was found in the metadata of its members.
+    pub uuid:    Option<String>,
+    /// The member disks, such as `/dev/sda` and `/dev/sdb`.
+    pub members: Vec<PathBuf>,
+    /// The volume which was assembled from the members, such as `/dev/md126`, to which the
+    /// install should be redirected.
+    pub volume:  Option<PathBuf>,
+}
+
+impl RstArray {
+    /// Groups disks with IMSM metadata by the array that they are members of.
+    ///
+    /// Members are grouped by the UUID of their array. The UUID is unknown when the metadata was
+    /// probed by `blkid`, so those members are grouped by the volume which holds them instead,
+    /// and are otherwise arrays of their own.
+    pub(crate) fn group<'a, I>(disks: I) -> Vec<RstArray>
+    where
+        I: IntoIterator<Item = (&'a Path, Option<&'a RaidMetadata>)>,
+    {
+        RstArray::group_in(Path::new("/sys"), disks)
+    }
+
+    fn group_in<'a, I>(sys: &Path, disks: I) -> Vec<RstArray>
+    where
+        I: IntoIterator<Item = (&'a Path, Option<&'a RaidMetadata>)>,
+    {
+        let mut arrays: Vec<RstArray> = Vec::new();
+        for (path, raid) in disks {
+            let raid = match raid {
+                Some(raid) if raid.format == RaidFormat::Imsm => raid,
+                _ => continue,
+            };
+
+            let volume = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| assembled_volume(sys, name));
+
+            let same_array = |array: &&mut RstArray| match (&array.uuid, &raid.uuid) {
+                (Some(uuid), Some(other)) => uuid == other,
+                _ => array.volume.is_some() && array.volume == volume,
+            };
+
+            match arrays.iter_mut().find(same_array) {
+                Some(array) => {
+                    array.members.push(path.to_path_buf());
+                    array.volume = array.volume.take().or(volume);
+                }
+                None => arrays.push(RstArray {
+                    uuid:    raid.uuid.clone(),
+                    members: vec![path.to_path_buf()],
+                    volume,
+                }),
+            }
+        }
+
+        arrays
+    }
+}
+
+impl fmt::Display for RstArray {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Intel RST array of {}", self.members.len())?;
+        f.write_str(if self.members.len() == 1 { " disk" } else { " disks" })?;
+        match self.volume {
+            Some(ref volume) => write!(f, ", assembled as {}", volume.display()),
+            None => f.write_str(", which has not been assembled"),
+        }
+    }
+}
+
+/// The md device of the volume which holds the member disk `name`. The member is also held by
+/// the container of the array, whose metadata is `external:imsm`, while that of a volume within
+/// the container is `external:/md127/0`.
+fn assembled_volume(sys: &Path, name: &str) -> Option<PathBuf> {
+    let holders = sys.join("class/block").join(name).join("holders").read_dir().ok()?;
+    let mut volumes = holders
+        .filter_map(Result::ok)
+        .map(|holder| holder.file_name().to_string_lossy().into_owned())
+        .filter(|holder| {
+            let version = sys.join("class/block").join(holder).join("md/metadata_version");
+            fs::read_to_string(version).map_or(false, |version| version.starts_with("external:/"))
+        })
+        .collect::<Vec<_>>();
+
+    volumes.sort();
+    volumes.into_iter().next().map(|volume| Path::new("/dev").join(volume))
+}
+
+/// The class is given as `0xCCSSPP`, of the class, subclass, and programming interface.
+fn is_raid_class(class: &str) -> bool {
+    class
+        .strip_prefix("0x")
+        .and_then(|class| u32::from_str_radix(class, 16).ok())
+        .map_or(false, |class| class >> 8 == RAID_CLASS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempdir::TempDir;
+
+    fn pci_device(sys: &Path, address: &str, vendor: &str, class: &str, driver: Option<&str>) {
+        let path = sys.join("bus/pci/devices").join(address);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("vendor"), [vendor, "\n"].concat()).unwrap();
+        fs::write(path.join("class"), [class, "\n"].concat()).unwrap();
+        if let Some(driver) = driver {
+            symlink(["../../../bus/pci/drivers/", driver].concat(), path.join("driver")).unwrap();
+        }
+    }
+
+    fn imsm(uuid: &str) -> RaidMetadata {
+        RaidMetadata {
+            format: RaidFormat::Imsm,
+            level:  Some("container".into()),
+            uuid:   Some(uuid.into()),
+        }
+    }
+
+    #[test]
+    fn arrays() {
+        let tempdir = TempDir::new("distinst").unwrap();
+        let sys = tempdir.path();
+        for &(holder, version) in &[("md126", "external:/md127/0"), ("md127", "external:imsm")] {
+            let md = sys.join("class/block").join(holder).join("md");
+            fs::create_dir_all(&md).unwrap();
+            fs::write(md.join("metadata_version"), [version, "\n"].concat()).unwrap();
+        }
+
+        for member in &["sda", "sdb"] {
+            let holders = sys.join("class/block").join(member).join("holders");
+            fs::create_dir_all(&holders).unwrap();
+            for holder in &["md126", "md127"] {
+                symlink(["../../", holder].concat(), holders.join(holder)).unwrap();
+            }
+        }
+
+        let first = imsm("a1b2c3d4:e5f60718:293a4b5c:6d7e8f90");
+        let second = imsm("0f1e2d3c:4b5a6978:8796a5b4:c3d2e1f0");
+        let md = RaidMetadata { format: RaidFormat::Md, level: None, uuid: None };
+        let disks = [
+            (Path::new("/dev/sda"), Some(&first)),
+            (Path::new("/dev/sdb"), Some(&first)),
+            (Path::new("/dev/sdc"), Some(&second)),
+            (Path::new("/dev/sdd"), Some(&md)),
+            (Path::new("/dev/nvme0n1"), None),
+        ];
+
+        let arrays = RstArray::group_in(sys, disks.iter().cloned());
+        assert_eq!(arrays, vec![
+            RstArray {
+                uuid:    first.uuid.clone(),
+                members: vec!["/dev/sda".into(), "/dev/sdb".into()],
+                volume:  Some("/dev/md126".into()),
+            },
+            RstArray { uuid: second.uuid.clone(), members: vec!["/dev/sdc".into()], volume: None },
+        ]);
+
+        assert_eq!(arrays[0].to_string(), "Intel RST array of 2 disks, assembled as /dev/md126");
+
+        // Members whose UUID was not found are only grouped by the volume which holds them.
+        let unknown = RaidMetadata { format: RaidFormat::Imsm, level: None, uuid: None };
+        let disks = [
+            (Path::new("/dev/sda"), Some(&unknown)),
+            (Path::new("/dev/sdb"), Some(&unknown)),
+            (Path::new("/dev/sde"), Some(&unknown)),
+            (Path::new("/dev/sdf"), Some(&unknown)),
+        ];
+
+        let arrays = RstArray::group_in(sys, disks.iter().cloned());
+        let members = arrays.into_iter().map(|array| array.members).collect::<Vec<_>>();
+        assert_eq!(members, vec![
+            vec![PathBuf::from("/dev/sda"), PathBuf::from("/dev/sdb")],
+            vec![PathBuf::from("/dev/sde")],
+            vec![PathBuf::from("/dev/sdf")],
+        ]);
+    }
+
+    #[test]
+    fn active() {
+        let tempdir = TempDir::new("distinst").unwrap();
+        let sys = tempdir.path();
+        let member = Path::new("/dev/sda");
+        fs::create_dir_all(sys.join("class/block/sda/holders")).unwrap();
+        assert!(!is_active_in(sys, member));
+
+        let md = sys.join("class/block/md126/md");
+        fs::create_dir_all(&md).unwrap();
+        fs::write(md.join("metadata_version"), "external:/md127/0\n").unwrap();
+        symlink("../../md126", sys.join("class/block/sda/holders/md126")).unwrap();
+        assert!(is_active_in(sys, member));
+
+        fs::remove_file(sys.join("class/block/sda/holders/md126")).unwrap();
+        pci_device(sys, "0000:00:17.0", "0x8086", "0x010400", Some("ahci"));
+        assert!(is_active_in(sys, member));
+    }
+
+    #[test]
+    fn raid_class() {
+        assert!(is_raid_class("0x010400"));
+        assert!(!is_raid_class("0x010601"));
+        assert!(!is_raid_class("0x010802"));
+        assert!(!is_raid_class(""));
+    }
+
+    #[test]
+    fn remapped_nvme() {
+        let tempdir = TempDir::new("distinst").unwrap();
+        let sys = tempdir.path();
+        pci_device(sys, "0000:00:17.0", "0x8086", "0x010400", Some("ahci"));
+        pci_device(sys, "0000:00:0e.0", "0x8086", "0x010400", Some("vmd"));
+        pci_device(sys, "0000:02:00.0", "0x1022", "0x010400", None);
+
+        let host = sys.join("class/scsi_host/host0");
+        fs::create_dir_all(&host).unwrap();
+        fs::write(host.join("remapped_nvme"), "1\n").unwrap();
+
+        let raid = IntelRaidMode::detect_in(sys).unwrap();
+        assert_eq!(raid, IntelRaidMode {
+            controllers:   vec!["0000:00:17.0".into()],
+            remapped_nvme: 1,
+            unbound:       false,
+        });
+        assert!(raid.hides_disks());
+    }
+
+    #[test]
+    fn unbound() {
+        let tempdir = TempDir::new("distinst").unwrap();
+        let sys = tempdir.path();
+        assert_eq!(IntelRaidMode::detect_in(sys), None);
+
+        pci_device(sys, "0000:00:17.0", "0x8086", "0x010601", Some("ahci"));
+        assert_eq!(IntelRaidMode::detect_in(sys), None);
+
+        pci_device(sys, "0000:00:1f.2", "0x8086", "0x010400", None);
+        let raid = IntelRaidMode::detect_in(sys).unwrap();
+        assert!(raid.unbound && raid.hides_disks());
+    }
+}
@@ -2,20 +2,215 @@
 //! are written to the correct drives, as it could be possible, however
 //! unlikely, that a user could hot swap drives after obtaining device
 //! information, but before writing their changes to the disk.
+//!
+//! Model names, serials, and whether a device is removable are read directly from sysfs in
+//! one pass, which works in containers where udev is unavailable. SATA and SCSI disks are
+//! identified by their `vpd_pg80` unit serial, `serial`, or `wwid` attributes. Only when a
+//! device has none of them is the udev database read, which a single `udevadm` process
+//! exports for all of the probed devices.
 
-use std::{io, path::Path, process::Command};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 const PATTERN: &str = "E: ID_SERIAL=";
 
-/// Obtains the serial of the given device by calling out to `udevadm`.
+/// Identifying information about a block device, as reported by sysfs.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DeviceIdentity {
+    pub model:     String,
+    pub serial:    String,
+    /// The world wide identifier of a SCSI or NVMe device, such as `naa.5002538d403d649a`.
+    pub wwid:      String,
+    /// Whether the media of the device may be removed, such as an SD card.
+    pub removable: bool,
+}
+
+impl DeviceIdentity {
+    /// Reads the model, serial, and removability of a device, such as `sda` or `nvme0n1`, from
+    /// sysfs.
+    pub fn from_sysfs(name: &str) -> DeviceIdentity {
+        DeviceIdentity::probe_in(Path::new("/sys/class/block"), name)
+    }
+
+    fn probe_in(class: &Path, name: &str) -> DeviceIdentity {
+        let block = class.join(name);
+        let device = block.join("device");
+
+        let model = read_attribute(&device.join("model"))
+            .or_else(|| read_attribute(&device.join("name")))
+            .unwrap_or_default();
+
+        let serial = read_attribute(&device.join("serial"))
+            .or_else(|| read_attribute(&block.join("serial")))
+            .or_else(|| {
+                fs::read(device.join("vpd_pg80")).ok().and_then(|page| parse_vpd_serial(&page))
+            })
+            .unwrap_or_default();
+
+        let wwid = read_attribute(&device.join("wwid"))
+            .or_else(|| read_attribute(&block.join("wwid")))
+            .unwrap_or_default();
+
+        let removable = read_attribute(&block.join("removable")).map_or(false, |v| v == "1");
+
+        DeviceIdentity { model, serial, wwid, removable }
+    }
+
+    /// The serial of the device `name`, in the format of udev's `ID_SERIAL` property where
+    /// sysfs allows it: `MODEL_SERIAL` for NVMe, and the serial alone for virtio and MMC.
+    ///
+    /// SATA disks without a WWN have a T10 `wwid` which holds their full ATA model and serial,
+    /// from which `MODEL_SERIAL` is built as udev's `ata_id` does. Other SATA and SCSI disks
+    /// are identified by their SCSI model, which sysfs truncates to 16 characters, and their
+    /// unit serial, or else by their `wwid`. These are stable, but may differ from udev's.
+    pub fn id_serial(&self, name: &str) -> Option<String> {
+        let model_serial = |model: &str, serial: &str| {
+            if model.trim().is_empty() {
+                udev_escape(serial)
+            } else {
+                [udev_escape(model), "_".into(), udev_escape(serial)].concat()
+            }
+        };
+
+        if name.starts_with("nvme") {
+            Some(&self.serial).filter(|s| !s.is_empty()).map(|s| model_serial(&self.model, s))
+        } else if name.starts_with("vd") || name.starts_with("mmcblk") {
+            Some(&self.serial).filter(|s| !s.is_empty()).map(|s| udev_escape(s))
+        } else if let Some((model, serial)) = parse_ata_wwid(&self.wwid) {
+            Some(model_serial(model, serial))
+        } else if !self.serial.is_empty() {
+            Some(model_serial(&self.model, &self.serial))
+        } else if !self.wwid.is_empty() {
+            Some(udev_escape(&self.wwid))
+        } else {
+            None
+        }
+    }
+}
+
+/// The `ID_SERIAL` properties of the udev database, by the kernel names of their devices. The
+/// database is exported by one `udevadm` process when the first device which sysfs cannot
+/// identify is probed, rather than spawning a process for each device. This is only a fallback
+/// for devices which have no serial or `wwid` attributes in sysfs.
+#[derive(Debug, Default)]
+pub struct UdevSerials {
+    serials: Option<HashMap<String, String>>,
+}
+
+impl UdevSerials {
+    fn get(&mut self, name: &str) -> io::Result<String> {
+        if self.serials.is_none() {
+            let output = Command::new("udevadm").args(["info", "--export-db"]).output()?;
+            self.serials = Some(parse_database(&output.stdout));
+        }
+
+        self.serials
+            .as_ref()
+            .and_then(|serials| serials.get(name))
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no serial field"))
+    }
+}
+
+/// Obtains the serial of the given device from its identity in sysfs, or else, when sysfs has
+/// no serial or `wwid` for it, from the udev database.
 ///
 /// The `path` should be a value like `/dev/sda`.
-pub fn get_serial(path: &Path) -> io::Result<String> {
+pub fn get_serial(
+    path: &Path,
+    identity: &DeviceIdentity,
+    udev: &mut UdevSerials,
+) -> io::Result<String> {
     info!("obtaining serial model from {}", path.display());
-    Command::new("udevadm")
-        .args(&["info", "--query=all", &format!("--name={}", path.display())])
-        .output()
-        .and_then(|output| parse_serial(&output.stdout))
+    let name = sysfs_name(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "device has no name"))?;
+
+    match identity.id_serial(&name) {
+        Some(serial) => Ok(serial),
+        None => {
+            debug!("no serial in sysfs for {}, reading the udev database", path.display());
+            udev.get(&name)
+        }
+    }
+}
+
+/// Escapes a value as udev does for `ID_SERIAL`: surrounding whitespace is removed, each run
+/// of whitespace becomes an underscore, and characters which are not allowed in the names of
+/// device links are replaced with underscores.
+fn udev_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for (id, word) in value.split_whitespace().enumerate() {
+        if id != 0 {
+            escaped.push('_');
+        }
+
+        escaped.extend(word.chars().map(|c| {
+            if c.is_ascii_alphanumeric() || !c.is_ascii() || "#+-.:=@_".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        }));
+    }
+
+    escaped
+}
+
+/// Resolves symlinks such as `/dev/disk/by-id/*` to find the kernel name of a device.
+fn sysfs_name(path: &Path) -> Option<String> {
+    let path = path.canonicalize().unwrap_or_else(|_| PathBuf::from(path));
+    path.file_name().and_then(|name| name.to_str()).map(String::from)
+}
+
+fn read_attribute(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|value| value.trim().to_owned()).filter(|v| !v.is_empty())
+}
+
+/// Parses the unit serial number from a SCSI VPD page 0x80, as exposed by `vpd_pg80`.
+fn parse_vpd_serial(page: &[u8]) -> Option<String> {
+    if page.len() < 4 || page[1] != 0x80 {
+        return None;
+    }
+
+    let length = page[3] as usize;
+    let serial = page.get(4..4 + length).unwrap_or(&page[4..]);
+    let serial = String::from_utf8_lossy(serial)
+        .trim_matches(|c: char| c.is_whitespace() || c == '\0')
+        .to_owned();
+
+    if serial.is_empty() {
+        None
+    } else {
+        Some(serial)
+    }
+}
+
+/// Splits the T10 vendor identifier which libata reports for a SATA disk, formed from `ATA`
+/// padded to 8 characters, the 40 characters of its model, and the 20 of its serial.
+fn parse_ata_wwid(wwid: &str) -> Option<(&str, &str)> {
+    let id = wwid.strip_prefix("t10.ATA     ")?;
+    if id.len() != 60 || !id.is_char_boundary(40) {
+        return None;
+    }
+
+    let (model, serial) = id.split_at(40);
+    Some((model, serial)).filter(|(_, serial)| !serial.trim().is_empty())
+}
+
+/// Maps the kernel name of each device in an export of the udev database to its `ID_SERIAL`.
+fn parse_database(data: &[u8]) -> HashMap<String, String> {
+    String::from_utf8_lossy(data)
+        .split("\n\n")
+        .filter_map(|record| {
+            let name = record.lines().find_map(|line| line.strip_prefix("N: "))?;
+            let serial = parse_serial(record.as_bytes()).ok()?;
+            Some((name.to_owned(), serial))
+        })
+        .collect()
 }
 
 fn parse_serial(data: &[u8]) -> io::Result<String> {
@@ -29,6 +224,7 @@ fn parse_serial(data: &[u8]) -> io::Result<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempdir::TempDir;
 
     const SAMPLE: &str = r#"P: /devices/pci0000:00/0000:00:17.0/ata4/host3/target3:0:0/3:0:0:0/block/sda
 N: sda
@@ -84,4 +280,92 @@ E: USEC_INITIALIZED=1564088"#;
             String::from("Samsung_SSD_850_EVO_500GB_S21HNXAG806916N")
         );
     }
+
+    #[test]
+    fn vpd_serial() {
+        let mut page = vec![0x00, 0x80, 0x00, 0x14];
+        page.extend_from_slice(b"     S21HNXAG806916N");
+        assert_eq!(parse_vpd_serial(&page), Some("S21HNXAG806916N".into()));
+        assert_eq!(parse_vpd_serial(&[0x00, 0x83, 0x00, 0x00]), None);
+        assert_eq!(parse_vpd_serial(&[0x00, 0x80, 0x00, 0x00]), None);
+    }
+
+    #[test]
+    fn database() {
+        let loop_device = "\n\nP: /devices/virtual/block/loop0\nN: loop0\nE: MAJOR=7\n";
+        let export = [SAMPLE, loop_device].concat();
+        let serials = parse_database(export.as_bytes());
+        assert_eq!(serials.len(), 1);
+        assert_eq!(serials["sda"], "Samsung_SSD_850_EVO_500GB_S21HNXAG806916N");
+    }
+
+    #[test]
+    fn id_serial() {
+        let identity = DeviceIdentity {
+            model:     "Samsung SSD 970 EVO Plus 1TB            ".into(),
+            serial:    "S4EWNX0N123456".into(),
+            wwid:      "eui.0025385891b0a1b2".into(),
+            removable: false,
+        };
+        assert_eq!(
+            identity.id_serial("nvme0n1"),
+            Some("Samsung_SSD_970_EVO_Plus_1TB_S4EWNX0N123456".into())
+        );
+
+        let identity = DeviceIdentity {
+            model:     "SD32G".into(),
+            serial:    "0x1234abcd".into(),
+            wwid:      "".into(),
+            removable: true,
+        };
+        assert_eq!(identity.id_serial("mmcblk0"), Some("0x1234abcd".into()));
+        assert_eq!(DeviceIdentity::default().id_serial("nvme0n1"), None);
+        assert_eq!(DeviceIdentity::default().id_serial("sda"), None);
+
+        let model = "  WDC  WDS500G2B0C-00PXH0 / rev,2 ";
+        assert_eq!(udev_escape(model), "WDC_WDS500G2B0C-00PXH0___rev_2");
+    }
+
+    #[test]
+    fn sata_id_serial() {
+        let wwid =
+            format!("t10.ATA     {:40}{:>20}", "Samsung SSD 850 EVO 500GB", "S21HNXAG806916N");
+        let identity = DeviceIdentity {
+            model: "Samsung SSD 850".into(),
+            serial: "S21HNXAG806916N".into(),
+            wwid,
+            removable: false,
+        };
+        assert_eq!(
+            identity.id_serial("sda"),
+            Some("Samsung_SSD_850_EVO_500GB_S21HNXAG806916N".into())
+        );
+
+        let identity = DeviceIdentity { wwid: "naa.5002538d403d649a".into(), ..identity };
+        assert_eq!(identity.id_serial("sda"), Some("Samsung_SSD_850_S21HNXAG806916N".into()));
+
+        let identity = DeviceIdentity { serial: "".into(), ..identity };
+        assert_eq!(identity.id_serial("sda"), Some("naa.5002538d403d649a".into()));
+    }
+
+    #[test]
+    fn sysfs() {
+        let class = TempDir::new("distinst-serial").unwrap();
+        let block = class.path().join("nvme0n1");
+        fs::create_dir_all(block.join("device")).unwrap();
+        fs::write(block.join("device/model"), "Samsung SSD 970 EVO Plus 1TB\n").unwrap();
+        fs::write(block.join("device/serial"), "S4EWNX0N123456      \n").unwrap();
+        fs::write(block.join("removable"), "0\n").unwrap();
+
+        let identity = DeviceIdentity::probe_in(class.path(), "nvme0n1");
+        assert_eq!(identity, DeviceIdentity {
+            model:     "Samsung SSD 970 EVO Plus 1TB".into(),
+            serial:    "S4EWNX0N123456".into(),
+            wwid:      "".into(),
+            removable: false,
+        });
+
+        fs::write(block.join("removable"), "1\n").unwrap();
+        assert!(DeviceIdentity::probe_in(class.path(), "nvme0n1").removable);
+    }
 }
@@ -0,0 +1,150 @@
+//! The SMART health of a drive, so that users may be warned before installing onto a drive
+//! that is failing.
+//!
+//! The kernel does not expose SMART data through sysfs, so `smartctl` is used to read the
+//! attributes of ATA drives, and the health information log of NVMe drives.
+
+use std::{io, path::Path, process::Command};
+
+/// Bits of smartctl's exit status which signify that the drive could not be queried.
+const SMARTCTL_FAILED: i32 = 0b11;
+
+/// The result of the drive's own health self-assessment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmartHealth {
+    Passed,
+    Failed,
+    /// The drive does not support SMART, or did not report its health.
+    Unknown,
+}
+
+/// Health data which was reported by a drive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmartStatus {
+    pub health:              SmartHealth,
+    /// Sectors which were found to be bad, and were remapped to spare sectors.
+    pub reallocated_sectors: Option<u64>,
+    /// Sectors which could not be read, and are waiting to be remapped.
+    pub pending_sectors:     Option<u64>,
+    /// An estimate of how much of an NVMe drive's life has been used, which may exceed 100.
+    pub percentage_used:     Option<u8>,
+}
+
+impl SmartStatus {
+    /// Queries the health of the drive at `path` with `smartctl`.
+    pub fn new(path: &Path) -> io::Result<SmartStatus> {
+        info!("obtaining SMART status of {}", path.display());
+        let output = Command::new("smartctl").args(&["-H", "-A"]).arg(path).output()?;
+
+        let code = output.status.code().unwrap_or(SMARTCTL_FAILED);
+        if code & SMARTCTL_FAILED != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("smartctl failed to query {}: status {}", path.display(), code),
+            ));
+        }
+
+        Ok(SmartStatus::parse(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Whether the drive is failing, or is likely to fail soon.
+    pub fn is_failing(&self) -> bool {
+        self.health == SmartHealth::Failed
+            || self.pending_sectors.map_or(false, |sectors| sectors != 0)
+            || self.percentage_used.map_or(false, |used| used >= 100)
+    }
+
+    fn parse(output: &str) -> SmartStatus {
+        let mut status = SmartStatus {
+            health:              SmartHealth::Unknown,
+            reallocated_sectors: None,
+            pending_sectors:     None,
+            percentage_used:     None,
+        };
+
+        for line in output.lines() {
+            // ATA and NVMe drives report their self-assessment, and SCSI drives their status.
+            if line.starts_with("SMART overall-health self-assessment test result:")
+                || line.starts_with("SMART Health Status:")
+            {
+                let result = line.splitn(2, ':').nth(1).unwrap_or("").trim();
+                status.health = match result {
+                    "PASSED" | "OK" => SmartHealth::Passed,
+                    _ => SmartHealth::Failed,
+                };
+            } else if line.starts_with("Percentage Used:") {
+                status.percentage_used = line[16..]
+                    .trim()
+                    .trim_end_matches('%')
+                    .parse::<u64>()
+                    .ok()
+                    .map(|used| used.min(255) as u8);
+            } else {
+                // ATA attributes are a table, with the raw value in the last column.
+                let fields = line.split_whitespace().collect::<Vec<_>>();
+                if fields.len() < 10 {
+                    continue;
+                }
+
+                let raw = fields[9].parse::<u64>().ok();
+                match fields[1] {
+                    "Reallocated_Sector_Ct" => status.reallocated_sectors = raw,
+                    "Current_Pending_Sector" => status.pending_sectors = raw,
+                    _ => (),
+                }
+            }
+        }
+
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ATA: &str = r#"smartctl 7.1 2019-12-30 r5022 [x86_64-linux-5.4.0] (local build)
+
+=== START OF READ SMART DATA SECTION ===
+SMART overall-health self-assessment test result: PASSED
+
+SMART Attributes Data Structure revision number: 1
+Vendor Specific SMART Attributes with Thresholds:
+ID# ATTRIBUTE_NAME          FLAG     VALUE WORST THRESH TYPE      UPDATED  WHEN_FAILED RAW_VALUE
+  5 Reallocated_Sector_Ct   0x0033   100   100   010    Pre-fail  Always       -       8
+  9 Power_On_Hours          0x0032   095   095   000    Old_age   Always       -       23456
+197 Current_Pending_Sector  0x0012   100   100   000    Old_age   Always       -       0
+"#;
+
+    const NVME: &str = r#"=== START OF SMART DATA SECTION ===
+SMART overall-health self-assessment test result: FAILED!
+- NVM subsystem reliability has been degraded
+
+SMART/Health Information (NVMe Log 0x02)
+Critical Warning:                   0x04
+Temperature:                        41 Celsius
+Available Spare:                    100%
+Percentage Used:                    112%
+"#;
+
+    #[test]
+    fn ata() {
+        let status = SmartStatus::parse(ATA);
+        assert_eq!(status, SmartStatus {
+            health:              SmartHealth::Passed,
+            reallocated_sectors: Some(8),
+            pending_sectors:     Some(0),
+            percentage_used:     None,
+        });
+        assert!(!status.is_failing());
+    }
+
+    #[test]
+    fn nvme() {
+        let status = SmartStatus::parse(NVME);
+        assert_eq!(status.health, SmartHealth::Failed);
+        assert_eq!(status.percentage_used, Some(112));
+        assert!(status.is_failing());
+        assert_eq!(SmartStatus::parse("").health, SmartHealth::Unknown);
+    }
+}
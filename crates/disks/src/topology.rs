@@ -0,0 +1,213 @@
+//! The topology of a disk, as reported by sysfs, which installers may use to tailor the defaults
+//! of an install to the hardware, such as omitting swap on an SD card, or trimming an SSD.
+
+use crate::network::{is_fabric, iscsi_session, nvme_transport};
+use std::{fmt, fs, path::Path};
+
+/// How a disk is attached to the system.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Transport {
+    Nvme,
+    Sata,
+    Usb,
+    /// SD cards and eMMC storage.
+    Mmc,
+    Virtio,
+    Iscsi,
+    NvmeOverFabrics,
+    #[default]
+    Unknown,
+}
+
+impl Transport {
+    /// Whether the disk is attached over the network, and must be attached by the initramfs of
+    /// the installed system before its file systems can be mounted.
+    pub fn is_network(self) -> bool {
+        matches!(self, Transport::Iscsi | Transport::NvmeOverFabrics)
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(match *self {
+            Transport::Nvme => "nvme",
+            Transport::Sata => "sata",
+            Transport::Usb => "usb",
+            Transport::Mmc => "mmc",
+            Transport::Virtio => "virtio",
+            Transport::Iscsi => "iscsi",
+            Transport::NvmeOverFabrics => "nvme-of",
+            Transport::Unknown => "unknown",
+        })
+    }
+}
+
+/// Characteristics of a disk which affect how it should be partitioned and mounted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiskTopology {
+    /// Whether the disk has spinning platters.
+    pub rotational:           bool,
+    /// Whether the disk supports discard, also known as TRIM.
+    pub discard:              bool,
+    /// The size of the sectors which the disk is addressed by, in bytes.
+    pub logical_sector_size:  u64,
+    /// The size of the sectors which the disk writes at once, in bytes.
+    pub physical_sector_size: u64,
+    pub transport:            Transport,
+    /// The NUMA node of the controller that the disk is attached to, if the system has several.
+    pub numa_node:            Option<u32>,
+}
+
+impl Default for DiskTopology {
+    fn default() -> Self {
+        DiskTopology {
+            rotational:           false,
+            discard:              false,
+            logical_sector_size:  512,
+            physical_sector_size: 512,
+            transport:            Transport::Unknown,
+            numa_node:            None,
+        }
+    }
+}
+
+impl DiskTopology {
+    /// Reads the topology of a disk, such as `sda` or `nvme0n1`, from sysfs.
+    pub fn from_sysfs(name: &str) -> DiskTopology {
+        DiskTopology::probe_in(Path::new("/sys/class/block"), name)
+    }
+
+    fn probe_in(class: &Path, name: &str) -> DiskTopology {
+        let block = class.join(name);
+        let queue = block.join("queue");
+        let number = |file: &str| read_number(&queue.join(file));
+
+        let logical_sector_size = number("logical_block_size").unwrap_or(512);
+        let device = block.canonicalize().unwrap_or_else(|_| block.clone());
+
+        DiskTopology {
+            rotational: number("rotational") == Some(1),
+            discard: number("discard_max_bytes").map_or(false, |max| max != 0),
+            logical_sector_size,
+            physical_sector_size: number("physical_block_size").unwrap_or(logical_sector_size),
+            transport: transport(name, &device),
+            numa_node: numa_node(&device),
+        }
+    }
+}
+
+/// Determines the transport of a disk from its name, and the path of its device in sysfs.
+fn transport(name: &str, device: &Path) -> Transport {
+    let through = |bus: &str| {
+        device.components().any(|component| {
+            component.as_os_str().to_str().map_or(false, |component| component.starts_with(bus))
+        })
+    };
+
+    if iscsi_session(device).is_some() {
+        Transport::Iscsi
+    } else if name.starts_with("nvme") {
+        match nvme_transport(device) {
+            Some(ref transport) if is_fabric(transport) => Transport::NvmeOverFabrics,
+            _ => Transport::Nvme,
+        }
+    } else if name.starts_with("mmcblk") {
+        Transport::Mmc
+    } else if name.starts_with("vd") || through("virtio") {
+        Transport::Virtio
+    } else if through("usb") {
+        Transport::Usb
+    } else if through("ata") {
+        Transport::Sata
+    } else {
+        Transport::Unknown
+    }
+}
+
+/// The NUMA node of the nearest parent of the device which reports one.
+fn numa_node(device: &Path) -> Option<u32> {
+    device
+        .ancestors()
+        .map(|path| path.join("numa_node"))
+        .find(|path| path.exists())
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|node| node.trim().parse::<i32>().ok())
+        // A node of -1 is reported by systems without NUMA.
+        .and_then(|node| if node < 0 { None } else { Some(node as u32) })
+}
+
+fn read_number(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok().and_then(|value| value.trim().parse::<u64>().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tempdir::TempDir;
+
+    #[test]
+    fn transports() {
+        let pci = "/sys/devices/pci0000:00/0000:00:14.0";
+        let path = |suffix: &str| PathBuf::from([pci, suffix].concat());
+        assert_eq!(transport("nvme0n1", &path("/nvme/nvme0/nvme0n1")), Transport::Nvme);
+        assert_eq!(
+            transport("mmcblk0", &path("/mmc_host/mmc0/mmc0:0001/block/mmcblk0")),
+            Transport::Mmc
+        );
+        assert_eq!(transport("vda", &path("/virtio2/block/vda")), Transport::Virtio);
+        assert_eq!(
+            transport("sdb", &path("/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb")),
+            Transport::Usb
+        );
+        assert_eq!(
+            transport("sda", &path("/ata1/host0/target0:0:0/0:0:0:0/block/sda")),
+            Transport::Sata
+        );
+        assert_eq!(
+            transport("sdc", &path("/host2/target2:0:0/2:0:0:0/block/sdc")),
+            Transport::Unknown
+        );
+        let iscsi = Path::new("/sys/devices/platform/host3/session1/target3:0:0/block/sdd");
+        assert_eq!(transport("sdd", iscsi), Transport::Iscsi);
+    }
+
+    #[test]
+    fn probe() {
+        let tempdir = TempDir::new("distinst").unwrap();
+        let controller = tempdir.path().join("devices/pci0000:00/0000:00:1d.0");
+        let device = controller.join("nvme/nvme0/nvme0n1");
+        let class = tempdir.path().join("class/block");
+        fs::create_dir_all(device.join("queue")).unwrap();
+        fs::create_dir_all(&class).unwrap();
+        std::os::unix::fs::symlink(&device, class.join("nvme0n1")).unwrap();
+
+        fs::write(controller.join("numa_node"), "1\n").unwrap();
+        for &(file, value) in &[
+            ("rotational", "0\n"),
+            ("discard_max_bytes", "2199023255040\n"),
+            ("logical_block_size", "512\n"),
+            ("physical_block_size", "4096\n"),
+        ] {
+            fs::write(device.join("queue").join(file), value).unwrap();
+        }
+
+        let topology = DiskTopology::probe_in(&class, "nvme0n1");
+        assert_eq!(
+            topology,
+            DiskTopology {
+                rotational:           false,
+                discard:              true,
+                logical_sector_size:  512,
+                physical_sector_size: 4096,
+                transport:            Transport::Nvme,
+                numa_node:            Some(1),
+            }
+        );
+
+        fs::write(controller.join("numa_node"), "-1\n").unwrap();
+        fs::write(device.join("queue/discard_max_bytes"), "0\n").unwrap();
+        let topology = DiskTopology::probe_in(&class, "nvme0n1");
+        assert_eq!((topology.discard, topology.numa_node), (false, None));
+    }
+}
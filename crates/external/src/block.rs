@@ -1,14 +1,24 @@
 use self::FileSystem::*;
 use super::exec;
 use disk_types::FileSystem;
-use crate::retry::Retry;
+use crate::retry::{is_transient, Retry};
+use misc::output::{CommandError, TailBuffer, STDERR_LIMIT};
 use std::{
     ffi::{OsStr, OsString},
-    io,
+    io::{self, BufRead, BufReader},
+    os::unix::fs::{FileTypeExt, MetadataExt},
     path::Path,
     process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
 };
 
+/// Where udev records the properties of each device once it has processed its events.
+const UDEV_DATA: &str = "/run/udev/data";
+
+/// How long to wait for the devices of a disk to appear after its partition table was changed.
+pub const DEVICE_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Erase all signatures on a disk
 pub fn wipefs<P: AsRef<Path>>(device: P) -> io::Result<()> {
     info!("using wipefs to wipe signatures from {:?}", device.as_ref());
@@ -28,6 +38,190 @@ pub fn blockdev<P: AsRef<Path>, S: AsRef<OsStr>, I: IntoIterator<Item = S>>(
     })
 }
 
+/// Waits for udev to finish processing events, such as the creation of device nodes for new
+/// partitions and device-mapper targets.
+pub fn udev_settle() -> io::Result<()> {
+    exec("udevadm", None, None, &["settle".into(), "--timeout=30".into()])
+}
+
+/// Flushes the buffers of a disk, and has the kernel re-read its partition table.
+///
+/// The kernel will refuse to re-read the table while udev is still probing the disk, so this
+/// waits for udev to settle, and retries for as long as the disk is busy.
+pub fn reread_partitions<P: AsRef<Path>>(disk: P) -> io::Result<()> {
+    Retry::default().attempts(5).interval(500).jitter(0.25).retry_if(is_transient, || {
+        let _ = udev_settle();
+        blockdev(disk.as_ref(), &["--flushbufs", "--rereadpt"])
+    })
+}
+
+/// Moves the backup GPT header to the end of the disk, where it is expected to be. An image
+/// which was written to a disk that is larger than the image leaves it short of the end.
+pub fn relocate_backup_gpt<P: AsRef<Path>>(disk: P) -> io::Result<()> {
+    info!("moving the backup GPT header of {:?} to the end of the disk", disk.as_ref());
+    exec("sgdisk", None, None, &["-e".into(), disk.as_ref().into()])
+}
+
+/// Obtains the size of a block device, in 512-byte sectors.
+pub fn block_sectors<P: AsRef<Path>>(device: P) -> io::Result<u64> {
+    let output = Command::new("blockdev").arg("--getsz").arg(device.as_ref()).output()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse::<u64>().map_err(|_| {
+        let why = format!("blockdev did not report the size of {}", device.as_ref().display());
+        io::Error::new(io::ErrorKind::InvalidData, why)
+    })
+}
+
+/// Discards every block on a device, securely if `secure` is set.
+pub fn blkdiscard<P: AsRef<Path>>(device: P, secure: bool) -> io::Result<()> {
+    let mut args: Vec<OsString> = Vec::with_capacity(2);
+    if secure {
+        args.push("--secure".into());
+    }
+
+    args.push(device.as_ref().into());
+    exec("blkdiscard", None, None, &args)
+}
+
+/// Erases an NVMe namespace, with a secure erase of the user data.
+pub fn nvme_format<P: AsRef<Path>>(device: P) -> io::Result<()> {
+    exec("nvme", None, None, &[
+        "format".into(),
+        device.as_ref().into(),
+        "--ses=1".into(),
+        "--force".into(),
+    ])
+}
+
+/// Overwrites every sector of a device with random data, followed by a final pass of zeroes,
+/// for a total of `passes` passes.
+///
+/// The `progress` callback receives the percentage of all passes which has been written.
+pub fn shred<P: AsRef<Path>, F: FnMut(u8)>(
+    device: P,
+    passes: u8,
+    mut progress: F,
+) -> io::Result<()> {
+    let device = device.as_ref();
+    let iterations = format!("--iterations={}", passes.saturating_sub(1));
+    info!("executing shred with {:?}", [&iterations, "-v", "--zero"]);
+
+    let mut child = Command::new("shred")
+        .args([&iterations, "-v", "--zero"])
+        .arg(device)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Progress is written to stderr, one line at a time, along with any errors.
+    let mut stderr = TailBuffer::new(STDERR_LIMIT);
+    if let Some(reader) = child.stderr.take() {
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            if let Some(percent) = shred_progress(&line) {
+                progress(percent);
+            }
+
+            stderr.push_line(&line);
+        }
+    }
+
+    let status = child.wait()?;
+    if status.success() {
+        progress(100);
+        Ok(())
+    } else {
+        Err(CommandError {
+            command: format!("shred {} -v --zero {}", iterations, device.display()),
+            status:  status.code(),
+            stderr:  stderr.as_string(),
+        }
+        .into_io_error(io::ErrorKind::Other))
+    }
+}
+
+/// The percentage of all passes which has been written, from a line of `shred -v`, such as
+/// `shred: /dev/sda: pass 2/3 (random)...1.0GiB/4.0GiB 25%`.
+fn shred_progress(line: &str) -> Option<u8> {
+    let (_, pass) = line.split_once(": pass ")?;
+    let (pass, passes) = pass.split_once('/')?;
+    let passes = passes.split(|c: char| !c.is_ascii_digit()).next()?;
+    let (pass, passes) = (pass.parse::<u64>().ok()?, passes.parse::<u64>().ok()?);
+    if pass == 0 || pass > passes {
+        return None;
+    }
+
+    let percent = line
+        .strip_suffix('%')
+        .and_then(|line| line.rsplit(' ').next())
+        .and_then(|percent| percent.parse::<u64>().ok())
+        .map_or(0, |percent| percent.min(100));
+
+    Some((((pass - 1) * 100 + percent) / passes) as u8)
+}
+
+/// Informs the kernel of changes to the partition table of a disk, one partition at a time.
+///
+/// Unlike `blockdev --rereadpt`, this succeeds when other partitions on the disk are in use.
+pub fn partprobe<P: AsRef<Path>>(disk: P) -> io::Result<()> {
+    exec("partprobe", None, None, &[disk.as_ref().into()])
+}
+
+/// Waits until the device node at `path` exists, and udev has finished processing it.
+pub fn wait_for_device<P: AsRef<Path>>(path: P, timeout: Duration) -> io::Result<()> {
+    let path = path.as_ref();
+    let start = Instant::now();
+    loop {
+        if let Ok(metadata) = path.metadata() {
+            if metadata.file_type().is_block_device() && udev_initialized(metadata.rdev()) {
+                return Ok(());
+            }
+        }
+
+        if start.elapsed() >= timeout {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("timed out waiting for {} to be initialized", path.display()),
+            ));
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Synchronizes the kernel and udev with the partition table of a disk after it has been
+/// written, and waits for the given partitions of that disk to become available.
+pub fn synchronize<P: AsRef<Path>>(disk: P, partitions: &[&Path]) -> io::Result<()> {
+    let disk = disk.as_ref();
+    if let Err(why) = reread_partitions(disk) {
+        warn!("{}: falling back to partprobe: {}", disk.display(), why);
+        partprobe(disk)?;
+    }
+
+    let _ = udev_settle();
+    for partition in partitions {
+        wait_for_device(partition, DEVICE_TIMEOUT)?;
+    }
+
+    Ok(())
+}
+
+/// Whether udev has recorded the device in its database, or is not running at all.
+fn udev_initialized(rdev: u64) -> bool {
+    let data = Path::new(UDEV_DATA);
+    !data.exists() || {
+        let (major, minor) = device_numbers(rdev);
+        data.join(format!("b{}:{}", major, minor)).exists()
+    }
+}
+
+/// Splits a device ID into its major and minor numbers.
+fn device_numbers(rdev: u64) -> (u64, u64) {
+    let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & 0xffff_f000);
+    let minor = (rdev & 0xff) | ((rdev >> 12) & 0xffff_ff00);
+    (major, minor)
+}
+
 /// Obtains the file system on a partition via blkid
 pub fn blkid_partition<P: AsRef<Path>>(part: P) -> Option<FileSystem> {
     let output = Command::new("blkid")
@@ -62,12 +256,19 @@ pub fn fsck<P: AsRef<Path>>(part: P, cmd: Option<(&str, &str)>) -> io::Result<()
         .retry_until_ok(move || exec(cmd, None, None, &[arg.into(), part.as_ref().into()]))
 }
 
-/// Formats the supplied `part` device with the file system specified.
-pub fn mkfs<P: AsRef<Path>>(part: P, kind: FileSystem) -> io::Result<()> {
+/// Formats the supplied `part` device with the file system specified, and optionally labels it.
+///
+/// If a `uuid` is given, the new file system will be created with that UUID, or serial number
+/// in the case of FAT, NTFS, and exFAT file systems.
+pub fn mkfs<P: AsRef<Path>>(
+    part: P,
+    kind: FileSystem,
+    label: Option<&str>,
+    uuid: Option<&str>,
+) -> io::Result<()> {
     let (cmd, args): (&'static str, &'static [&'static str]) = match kind {
         Btrfs => ("mkfs.btrfs", &["-f"]),
-        // Exfat => ("mkfs.exfat", &[]),
-        Exfat => unimplemented!("exfat is not supported, yet"),
+        Exfat => ("mkfs.exfat", &[]),
         Ext2 => ("mkfs.ext2", &["-F", "-q"]),
         Ext3 => ("mkfs.ext3", &["-F", "-q"]),
         Ext4 => ("mkfs.ext4", &["-F", "-q", "-E", "lazy_itable_init"]),
@@ -88,9 +289,55 @@ pub fn mkfs<P: AsRef<Path>>(part: P, kind: FileSystem) -> io::Result<()> {
 
     exec(cmd, None, None, &{
         let mut args = args.iter().map(Into::into).collect::<Vec<OsString>>();
+        if let Some(label) = label {
+            let flag = match kind {
+                F2fs => "-l",
+                Fat16 | Fat32 => "-n",
+                _ => "-L",
+            };
+
+            args.push(flag.into());
+            args.push(label.into());
+        }
+
+        if let Some(uuid) = uuid {
+            match kind {
+                Fat16 | Fat32 => {
+                    args.push("-i".into());
+                    args.push(uuid.replace('-', "").into());
+                }
+                Xfs => {
+                    args.push("-m".into());
+                    args.push(format!("uuid={}", uuid).into());
+                }
+                Exfat | Ntfs => (),
+                _ => {
+                    args.push("-U".into());
+                    args.push(uuid.into());
+                }
+            }
+        }
+
         args.push(part.as_ref().into());
         args
-    })
+    })?;
+
+    // The serial numbers of NTFS and exFAT file systems can only be set after creation.
+    match (kind, uuid) {
+        (Exfat, Some(uuid)) => exec(
+            "tune.exfat",
+            None,
+            None,
+            &["-I".into(), format!("0x{}", uuid.replace('-', "")).into(), part.as_ref().into()],
+        ),
+        (Ntfs, Some(uuid)) => exec(
+            "ntfslabel",
+            None,
+            None,
+            &[format!("--new-serial={}", uuid).into(), part.as_ref().into()],
+        ),
+        _ => Ok(()),
+    }
 }
 
 /// Get the label from the given partition, if it exists.
@@ -129,6 +376,7 @@ fn get_label_cmd(kind: FileSystem) -> Option<(&'static str, &'static [&'static s
         Ext2 | Ext3 | Ext4 => ("e2label", &[][..]),
         Fat16 | Fat32 => ("dosfslabel", &[][..]),
         Ntfs => ("ntfslabel", &[][..]),
+        Exfat => ("exfatlabel", &[][..]),
         Xfs => ("xfs_admin", &["-l"][..]),
         Swap | Luks | Lvm => {
             return None;
@@ -139,6 +387,29 @@ fn get_label_cmd(kind: FileSystem) -> Option<(&'static str, &'static [&'static s
     Some(cmd)
 }
 
+/// Changes the label of the file system on the given partition.
+pub fn set_label<P: AsRef<Path>>(part: P, kind: FileSystem, label: &str) -> io::Result<()> {
+    let part = part.as_ref();
+    let (cmd, args): (&'static str, Vec<OsString>) = match kind {
+        Btrfs => ("btrfs", vec!["filesystem".into(), "label".into(), part.into(), label.into()]),
+        Ext2 | Ext3 | Ext4 => ("e2label", vec![part.into(), label.into()]),
+        F2fs => ("f2fslabel", vec![part.into(), label.into()]),
+        Fat16 | Fat32 => ("fatlabel", vec![part.into(), label.into()]),
+        Ntfs => ("ntfslabel", vec![part.into(), label.into()]),
+        Exfat => ("exfatlabel", vec![part.into(), label.into()]),
+        Xfs => ("xfs_admin", vec!["-L".into(), label.into(), part.into()]),
+        Swap => ("swaplabel", vec!["-L".into(), label.into(), part.into()]),
+        Luks | Lvm => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} volumes do not have a file system label", kind),
+            ));
+        }
+    };
+
+    exec(cmd, None, None, &args)
+}
+
 pub fn remount_rw<P: AsRef<Path>>(path: P) -> io::Result<()> {
     exec("mount", None, None, &[path.as_ref().into(), "-o".into(), "remount,rw".into()])
 }
@@ -146,3 +417,25 @@ pub fn remount_rw<P: AsRef<Path>>(path: P) -> io::Result<()> {
 fn swap_exists(path: &Path) -> bool {
     Command::new("swaplabel").arg(path).status().ok().map_or(false, |stat| stat.success())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_ids() {
+        assert_eq!(device_numbers(0x0801), (8, 1));
+        assert_eq!(device_numbers(0x10305), (259, 5));
+        assert_eq!(device_numbers(0x1000_0010_0000), (4096, 256));
+    }
+
+    #[test]
+    fn shred_progresses() {
+        const DEVICE: &str = "shred: /dev/sda: ";
+        let progress = |line: &str| shred_progress(&[DEVICE, line].concat());
+        assert_eq!(progress("pass 1/2 (random)..."), Some(0));
+        assert_eq!(progress("pass 1/2 (random)...2.0GiB/8.0GiB 25%"), Some(12));
+        assert_eq!(progress("pass 2/2 (000000)...8.0GiB/8.0GiB 100%"), Some(100));
+        assert_eq!(progress("failed to open for writing: Permission denied"), None);
+    }
+}
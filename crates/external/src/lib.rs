@@ -14,17 +14,30 @@ extern crate tempdir;
 pub mod block;
 pub mod luks;
 pub mod lvm;
-pub(crate) mod retry;
+pub mod network;
+pub mod raid;
+pub mod retry;
 
-pub use self::{block::*, luks::*, lvm::*};
+pub use self::{block::*, luks::*, lvm::*, network::*, raid::*, retry::Retry};
 
+use misc::{
+    journal::{self, CommandRecord},
+    metrics,
+    output::{CommandError, TailBuffer, JOURNAL_LIMIT, STDERR_LIMIT},
+};
 use std::{
     ffi::OsString,
-    io::{self, Write},
+    io::{self, Read, Write},
     process::{Command, Stdio},
+    thread,
+    time::Instant,
 };
 
 /// A generic function for executing a variety of external commands.
+///
+/// The tail of the command's stderr is included in the error that is returned if it fails, and
+/// is logged as a warning if it succeeds. If the command journal is enabled, the output of the
+/// command is also recorded in it.
 pub fn exec(
     cmd: &str,
     stdin: Option<&[u8]>,
@@ -33,35 +46,61 @@ pub fn exec(
 ) -> io::Result<()> {
     info!("executing {} with {:?}", cmd, args);
 
+    let journaled = journal::enabled();
+    let start = Instant::now();
     let mut child = Command::new(cmd)
         .args(args)
         .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
-        .stdout(Stdio::null())
+        .stdout(if journaled { Stdio::piped() } else { Stdio::null() })
+        .stderr(Stdio::piped())
         .spawn()?;
 
-    if let Some(stdin) = stdin {
-        child.stdin.as_mut().expect("stdin not obtained").write_all(stdin)?;
+    if let Some(mut input) = child.stdin.take() {
+        input.write_all(stdin.unwrap_or_default())?;
+    }
+
+    let stdout = child.stdout.take().map(|mut stdout| {
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            let _ = stdout.read_to_end(&mut output);
+            output
+        })
+    });
+
+    let mut stderr = TailBuffer::new(if journaled { JOURNAL_LIMIT } else { STDERR_LIMIT });
+    if let Some(ref mut reader) = child.stderr {
+        stderr.read_from(reader)?;
     }
 
+    let stdout = stdout.and_then(|thread| thread.join().ok()).unwrap_or_default();
     let status = child.wait()?;
+    metrics::record(["command:", cmd].concat(), start.elapsed());
+    let command = format!("{} {:?}", cmd, args);
+    if journaled {
+        journal::record(&CommandRecord {
+            command:  command.clone(),
+            status:   status.code(),
+            duration: start.elapsed(),
+            stdout:   String::from_utf8_lossy(&stdout).into_owned(),
+            stderr:   stderr.as_string(),
+        });
+    }
+
     let success = status.success()
         || valid_codes
             .map_or(false, |codes| status.code().map_or(false, |code| codes.contains(&code)));
 
     if success {
+        let stderr = stderr.tail(STDERR_LIMIT);
+        if !stderr.trim().is_empty() {
+            warn!("{}: {}", cmd, stderr.trim_end());
+        }
+
         Ok(())
     } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!(
-                "{} failed with status: {}",
-                cmd,
-                match status.code() {
-                    Some(code) => format!("{} ({})", code, io::Error::from_raw_os_error(code)),
-                    None => "unknown".into(),
-                }
-            ),
-        ))
+        let error =
+            CommandError { command, status: status.code(), stderr: stderr.tail(STDERR_LIMIT) };
+        Err(error.into_io_error(io::ErrorKind::Other))
     }
 }
 
@@ -1,10 +1,11 @@
 use super::*;
+use disk_types::{sectors_used, FileSystem};
 use std::{
-    io::{self, BufRead, BufReader},
+    ffi::OsString,
+    fmt,
+    io::{self, BufRead, BufReader, Write},
     path::{Path, PathBuf},
     process::{Command, Stdio},
-    thread,
-    time::Duration,
 };
 
 /// Get a vector of encrypted devices
@@ -52,29 +53,27 @@ pub fn encrypted_devices() -> io::Result<Vec<String>> {
 
 /// If `cryptsetup info DEV` has an exit status of 0, the partition is encrypted.
 pub fn is_encrypted(device: &Path) -> bool {
-    let mut attempts = 0;
-    loop {
-        let res = Command::new("cryptsetup")
-            .stdout(Stdio::null())
-            .arg("luksDump")
-            .arg(device)
-            .status()
-            .ok();
-
-        match res.and_then(|stat| stat.code()) {
-            Some(0) => return true,
-            // An exit status of 4 can happen if the partition is scanned too hastily.
-            Some(4) => {
-                thread::sleep(Duration::from_millis(100));
-                if attempts == 3 {
-                    return false;
+    // An exit status of 4 can happen if the partition is scanned too hastily.
+    Retry::default()
+        .attempts(4)
+        .interval(100)
+        .retry_if(
+            |code| *code == Some(4),
+            || {
+                let res = Command::new("cryptsetup")
+                    .stdout(Stdio::null())
+                    .arg("luksDump")
+                    .arg(device)
+                    .status()
+                    .ok();
+
+                match res.and_then(|stat| stat.code()) {
+                    Some(0) => Ok(()),
+                    code => Err(code),
                 }
-                attempts += 1;
-                continue;
-            }
-            _ => return false,
-        }
-    }
+            },
+        )
+        .is_ok()
 }
 
 pub enum CloseBy<'a> {
@@ -94,6 +93,40 @@ pub fn cryptsetup_close(device: CloseBy) -> io::Result<()> {
     exec("cryptsetup", None, Some(&[4]), args)
 }
 
+/// Resizes an open encrypted device to fill the partition that contains it.
+///
+/// LUKS2 devices whose volume key is kept in the kernel keyring can only be resized with the
+/// `key`, which is otherwise requested from a terminal that the installer does not have.
+pub fn cryptsetup_resize(name: &str, key: Option<LuksKey>) -> io::Result<()> {
+    resize(name, None, key)
+}
+
+/// Resizes an open encrypted device to `sectors`, which may be smaller than its partition.
+/// The `key` is required as it is by `cryptsetup_resize`.
+pub fn cryptsetup_resize_to(name: &str, sectors: u64, key: Option<LuksKey>) -> io::Result<()> {
+    resize(name, Some(sectors), key)
+}
+
+fn resize(name: &str, sectors: Option<u64>, key: Option<LuksKey>) -> io::Result<()> {
+    let mut args: Vec<OsString> = vec!["resize".into()];
+    let mut stdin = Vec::new();
+
+    match key {
+        Some(LuksKey::Password(password)) => append_line(&mut stdin, password),
+        Some(LuksKey::Keyfile(path)) => {
+            args.extend_from_slice(&["--key-file".into(), path.into()])
+        }
+        None => (),
+    }
+
+    if let Some(sectors) = sectors {
+        args.extend_from_slice(&["--size".into(), sectors.to_string().into()]);
+    }
+
+    args.push(name.into());
+    exec("cryptsetup", if stdin.is_empty() { None } else { Some(&stdin) }, None, &args)
+}
+
 /// Deactivate all logical devies found on the system.
 pub fn deactivate_logical_devices() -> io::Result<()> {
     let mut res = Ok(());
@@ -119,3 +152,281 @@ pub fn deactivate_logical_devices() -> io::Result<()> {
 
     res
 }
+
+/// A credential which unlocks a LUKS key slot.
+#[derive(Clone, Copy)]
+pub enum LuksKey<'a> {
+    Password(&'a str),
+    Keyfile(&'a Path),
+}
+
+/// An owned `LuksKey`, for operations which are planned before they are performed.
+#[derive(Clone, PartialEq)]
+pub enum LuksCredential {
+    Password(String),
+    Keyfile(PathBuf),
+}
+
+impl LuksCredential {
+    pub fn as_key(&self) -> LuksKey {
+        match *self {
+            LuksCredential::Password(ref password) => LuksKey::Password(password),
+            LuksCredential::Keyfile(ref path) => LuksKey::Keyfile(path),
+        }
+    }
+}
+
+impl fmt::Debug for LuksCredential {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LuksCredential::Password(_) => write!(f, "Password(hidden)"),
+            LuksCredential::Keyfile(ref path) => write!(f, "Keyfile({:?})", path),
+        }
+    }
+}
+
+/// Unlocks an encrypted partition, and maps it to `/dev/mapper/{name}`.
+///
+/// If the key is rejected, the error will be of the `PermissionDenied` kind. If the device
+/// does not exist, or is not a LUKS device, the error will be of the `NotFound` kind.
+pub fn luks_open(device: &Path, name: &str, key: LuksKey) -> io::Result<()> {
+    info!("opening {} as {}", device.display(), name);
+
+    let mut command = Command::new("cryptsetup");
+    command.arg("open").arg(device).arg(name).stdout(Stdio::null()).stderr(Stdio::piped());
+
+    let mut input = Vec::new();
+    match key {
+        LuksKey::Password(password) => {
+            append_line(&mut input, password);
+            command.stdin(Stdio::piped());
+        }
+        LuksKey::Keyfile(path) => {
+            command.arg("--key-file").arg(path).stdin(Stdio::null());
+        }
+    }
+
+    let mut child = command.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(&input)?;
+    }
+
+    let mut stderr = TailBuffer::new(STDERR_LIMIT);
+    if let Some(ref mut reader) = child.stderr {
+        stderr.read_from(reader)?;
+    }
+
+    let status = child.wait()?;
+    let kind = match status.code() {
+        Some(0) => {
+            let stderr = stderr.as_string();
+            if !stderr.trim().is_empty() {
+                warn!("cryptsetup open {}: {}", device.display(), stderr.trim_end());
+            }
+
+            return Ok(());
+        }
+        Some(2) => io::ErrorKind::PermissionDenied,
+        Some(4) => io::ErrorKind::NotFound,
+        _ => io::ErrorKind::Other,
+    };
+
+    let command = format!("cryptsetup open {}", device.display());
+    Err(CommandError { command, status: status.code(), stderr: stderr.as_string() }
+        .into_io_error(kind))
+}
+
+/// The number of 512-byte sectors that are used by an encrypted partition, including its header.
+///
+/// If the partition is not already unlocked, it will be unlocked with the `key` while the file
+/// system within is measured, and locked again afterwards.
+pub fn encrypted_sectors_used(device: &Path, key: LuksKey) -> io::Result<u64> {
+    if let Ok(used) = sectors_used(device, FileSystem::Luks) {
+        return Ok(used);
+    }
+
+    let name = match device.file_name() {
+        Some(name) => ["distinst-usage-", &name.to_string_lossy()].concat(),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "device has no name")),
+    };
+
+    luks_open(device, &name, key)?;
+    let used = sectors_used(device, FileSystem::Luks);
+
+    // A volume group within may have been activated automatically once it was unlocked. Closing
+    // is attempted on every path, so that a failure to measure does not leave the volume open.
+    let mapped = PathBuf::from(["/dev/mapper/", &name].concat());
+    let deactivated = match pvs().ok().and_then(|pvs| pvs.get(&mapped).cloned()) {
+        Some(Some(vg)) => vgdeactivate(&vg),
+        _ => Ok(()),
+    };
+
+    let closed = cryptsetup_close(CloseBy::Name(&name));
+    deactivated.and(closed).and(used)
+}
+
+/// Enrolls the `key` into a key slot of an encrypted partition, which is unlocked with the
+/// `unlock` credential. The first free slot will be used if a `slot` is not specified.
+pub fn add_key(device: &Path, unlock: LuksKey, key: LuksKey, slot: Option<u8>) -> io::Result<()> {
+    info!("adding key to {}", device.display());
+
+    // Passwords are read from stdin, up to the first newline, in the order they are requested.
+    let mut stdin = Vec::new();
+    let mut args: Vec<OsString> = vec!["luksAddKey".into()];
+
+    match unlock {
+        LuksKey::Password(password) => append_line(&mut stdin, password),
+        LuksKey::Keyfile(path) => args.extend_from_slice(&["--key-file".into(), path.into()]),
+    }
+
+    if let Some(slot) = slot {
+        args.extend_from_slice(&["--key-slot".into(), slot.to_string().into()]);
+    }
+
+    args.push(device.into());
+
+    match key {
+        LuksKey::Password(password) => append_line(&mut stdin, password),
+        LuksKey::Keyfile(path) => args.push(path.into()),
+    }
+
+    exec("cryptsetup", if stdin.is_empty() { None } else { Some(&stdin) }, None, &args)
+}
+
+/// Wipes a key slot of an encrypted partition, which is unlocked with a credential that
+/// belongs to one of the remaining key slots.
+pub fn remove_key(device: &Path, slot: u8, unlock: LuksKey) -> io::Result<()> {
+    info!("removing key slot {} from {}", slot, device.display());
+
+    let mut args: Vec<OsString> = vec!["luksKillSlot".into()];
+    let mut stdin = Vec::new();
+
+    match unlock {
+        LuksKey::Password(password) => append_line(&mut stdin, password),
+        LuksKey::Keyfile(path) => args.extend_from_slice(&["--key-file".into(), path.into()]),
+    }
+
+    args.extend_from_slice(&[device.into(), slot.to_string().into()]);
+    exec("cryptsetup", if stdin.is_empty() { None } else { Some(&stdin) }, None, &args)
+}
+
+/// Lists the key slots of an encrypted partition which are in use.
+pub fn list_slots(device: &Path) -> io::Result<Vec<u8>> {
+    let output = Command::new("cryptsetup").arg("luksDump").arg(device).output()?;
+
+    if !output.status.success() {
+        let mut stderr = TailBuffer::new(STDERR_LIMIT);
+        stderr.extend(&output.stderr);
+        return Err(CommandError {
+            command: format!("cryptsetup luksDump {}", device.display()),
+            status:  output.status.code(),
+            stderr:  stderr.as_string(),
+        }
+        .into_io_error(io::ErrorKind::Other));
+    }
+
+    Ok(parse_slots(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses the key slots in use from the output of `cryptsetup luksDump`, which differs
+/// between LUKS1 and LUKS2 headers.
+fn parse_slots(dump: &str) -> Vec<u8> {
+    let mut slots = Vec::new();
+    let mut in_keyslots = false;
+
+    for line in dump.lines() {
+        // LUKS1: `Key Slot 0: ENABLED`
+        if line.starts_with("Key Slot ") {
+            let mut fields = line[9..].split(':');
+            if let (Some(slot), Some(state)) = (fields.next(), fields.next()) {
+                if state.trim() == "ENABLED" {
+                    slots.extend(slot.trim().parse::<u8>().ok());
+                }
+            }
+            continue;
+        }
+
+        // LUKS2: slots are listed as `  0: luks2` within the `Keyslots:` section.
+        if !line.starts_with(char::is_whitespace) {
+            in_keyslots = line.trim_end() == "Keyslots:";
+        } else if in_keyslots {
+            let line = line.trim();
+            if let Some(pos) = line.find(':') {
+                slots.extend(line[..pos].parse::<u8>().ok());
+            }
+        }
+    }
+
+    slots
+}
+
+fn append_line(buffer: &mut Vec<u8>, line: &str) {
+    buffer.extend_from_slice(line.as_bytes());
+    buffer.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LUKS1: &str = "LUKS header information for /dev/sda3
+
+Version:       \t1
+Cipher name:   \taes
+Payload offset:\t4096
+UUID:          \t5a2b3c4d-0000-4000-8000-000000000000
+
+Key Slot 0: ENABLED
+\tIterations:         \t1000000
+\tSalt:               \t1f 2e 3d 4c
+Key Slot 1: DISABLED
+Key Slot 2: ENABLED
+\tIterations:         \t1000000
+Key Slot 3: DISABLED
+";
+
+    const LUKS2: &str = "LUKS header information
+Version:       \t2
+Epoch:         \t5
+UUID:          \t5a2b3c4d-0000-4000-8000-000000000000
+
+Data segments:
+  0: crypt
+\toffset: 16777216 [bytes]
+\tcipher: aes-xts-plain64
+
+Keyslots:
+  0: luks2
+\tKey:        512 bits
+\tPriority:   normal
+\tArea offset:32768 [bytes]
+  1: luks2
+\tKey:        512 bits
+\tArea offset:290816 [bytes]
+  7: luks2
+\tKey:        512 bits
+Tokens:
+Digests:
+  0: pbkdf2
+\tHash:       sha256
+";
+
+    #[test]
+    fn slots() {
+        assert_eq!(parse_slots(LUKS1), vec![0, 2]);
+        assert_eq!(parse_slots(LUKS2), vec![0, 1, 7]);
+    }
+
+    #[test]
+    fn no_slots() {
+        assert!(parse_slots("").is_empty());
+        assert!(parse_slots("Keyslots:\nTokens:\n").is_empty());
+    }
+
+    #[test]
+    fn malformed_slots() {
+        let dump = "Key Slot x: ENABLED\nKey Slot 300: ENABLED\nKey Slot 4\nKey Slot 5: ENABLED\n\
+                    Keyslots:\n  luks2\n  256: luks2\n  -1: luks2\n  six: luks2\n  3: luks2\n";
+        assert_eq!(parse_slots(dump), vec![5, 3]);
+    }
+}
@@ -4,7 +4,7 @@ use proc_mounts::{MOUNTS, SWAPS};
 use rand::{self, distributions::Alphanumeric, Rng};
 use std::{
     collections::BTreeMap,
-    ffi::OsStr,
+    ffi::{OsStr, OsString},
     fs::read_link,
     io::{self, BufRead, BufReader},
     path::{Path, PathBuf},
@@ -149,6 +149,55 @@ pub fn lvcreate(group: &str, name: &str, size: Option<u64>) -> io::Result<()> {
     )
 }
 
+/// Extends the logical volume, `name`, into all of the free space of the volume group, `group`,
+/// and grows the file system within it.
+pub fn lvextend_free(group: &str, name: &str) -> io::Result<()> {
+    exec("lvextend", None, None, &[
+        "--resizefs".into(),
+        "-l".into(),
+        "+100%FREE".into(),
+        [group, "/", name].concat().into(),
+    ])
+}
+
+/// Removes `extents` from the end of the logical volume, `name`. The file system within it
+/// must have been shrunk beforehand. If `test` is set, the change is only validated.
+pub fn lvreduce(group: &str, name: &str, extents: u64, test: bool) -> io::Result<()> {
+    let mut args: Vec<OsString> = vec!["--yes".into(), "--force".into()];
+    if test {
+        args.push("--test".into());
+    }
+
+    args.extend_from_slice(&[
+        "-l".into(),
+        format!("-{}", extents).into(),
+        [group, "/", name].concat().into(),
+    ]);
+    exec("lvreduce", None, None, &args)
+}
+
+/// Obtains the names of the logical volumes in a volume group, and their sizes in sectors.
+pub fn lv_sizes(vg: &str) -> io::Result<Vec<(String, u64)>> {
+    let output = Command::new("lvs")
+        .args(&["--noheadings", "--nosuffix", "--units", "s", "-o", "lv_name,lv_size", vg])
+        .stderr(Stdio::null())
+        .output()?;
+
+    Ok(parse_lv_sizes(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_lv_sizes(output: &str) -> Vec<(String, u64)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?;
+            let size = fields.next()?.parse::<u64>().ok()?;
+            Some((name.to_owned(), size))
+        })
+        .collect()
+}
+
 /// Remove the logical volume, `name`, from the volume group, `group`.
 pub fn lvremove(group: &str, name: &str) -> io::Result<()> {
     exec("lvremove", None, None, &["-y".into(), ["/dev/mapper/", group, "-", name].concat().into()])
@@ -203,6 +252,152 @@ pub fn pvcreate<P: AsRef<Path>>(device: P) -> io::Result<()> {
     exec("pvcreate", None, None, &["-ffy".into(), device.as_ref().into()])
 }
 
+/// Resizes a physical volume to fill the device that contains it.
+pub fn pvresize(physical_volume: &Path) -> io::Result<()> {
+    exec("pvresize", None, None, &[physical_volume.into()])
+}
+
+/// Resizes a physical volume to `sectors`. This will fail if any extents beyond the new end
+/// of the volume are allocated. If `test` is set, the change is only validated.
+pub fn pvresize_to(physical_volume: &Path, sectors: u64, test: bool) -> io::Result<()> {
+    let mut args: Vec<OsString> = vec!["--yes".into()];
+    if test {
+        args.push("--test".into());
+    }
+
+    args.extend_from_slice(&[
+        "--setphysicalvolumesize".into(),
+        format!("{}s", sectors).into(),
+        physical_volume.into(),
+    ]);
+    exec("pvresize", None, None, &args)
+}
+
+/// Obtains the size of the extents of a physical volume in sectors, the number of extents
+/// within it, and how many of those are allocated.
+pub fn pv_extents(physical_volume: &Path) -> io::Result<(u64, u64, u64)> {
+    let output = Command::new("pvs")
+        .args(&["--noheadings", "--nosuffix", "--units", "s", "-o"])
+        .arg("vg_extent_size,pv_pe_count,pv_pe_alloc_count")
+        .arg(physical_volume)
+        .stderr(Stdio::null())
+        .output()?;
+
+    parse_extents(&String::from_utf8_lossy(&output.stdout)).ok_or_else(|| {
+        let why = format!("pvs did not report the extents of {}", physical_volume.display());
+        io::Error::new(io::ErrorKind::InvalidData, why)
+    })
+}
+
+fn parse_extents(output: &str) -> Option<(u64, u64, u64)> {
+    let mut fields = output.split_whitespace().map(|field| field.parse::<u64>().ok());
+    match (fields.next()??, fields.next()??, fields.next()??) {
+        (0, _, _) => None,
+        extents => Some(extents),
+    }
+}
+
+/// Obtains the extent which follows the last allocated extent of a physical volume, or 0 if
+/// none of its extents are allocated.
+pub fn pv_allocated_end(physical_volume: &Path) -> io::Result<u64> {
+    let output = Command::new("pvs")
+        .args(&["--segments", "--noheadings", "-o", "pvseg_start,pvseg_size,lv_name"])
+        .arg(physical_volume)
+        .stderr(Stdio::null())
+        .output()?;
+
+    Ok(parse_allocated_end(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_allocated_end(output: &str) -> u64 {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let start = fields.next()?.parse::<u64>().ok()?;
+            let size = fields.next()?.parse::<u64>().ok()?;
+            // Free segments do not belong to a logical volume.
+            fields.next().map(|_| start + size)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Obtains the logical volumes which have extents on a physical volume, and how many of their
+/// extents are on it.
+pub fn pv_volume_extents(physical_volume: &Path) -> io::Result<Vec<(String, u64)>> {
+    let output = Command::new("pvs")
+        .args(&["--segments", "--noheadings", "-o", "pvseg_size,lv_name"])
+        .arg(physical_volume)
+        .stderr(Stdio::null())
+        .output()?;
+
+    Ok(parse_volume_extents(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_volume_extents(output: &str) -> Vec<(String, u64)> {
+    let mut volumes: Vec<(String, u64)> = Vec::new();
+    for line in output.lines() {
+        let mut fields = line.split_whitespace();
+        let size = match fields.next().and_then(|size| size.parse::<u64>().ok()) {
+            Some(size) => size,
+            None => continue,
+        };
+
+        // Free segments do not belong to a logical volume.
+        if let Some(name) = fields.next() {
+            match volumes.iter_mut().find(|(volume, _)| volume == name) {
+                Some((_, extents)) => *extents += size,
+                None => volumes.push((name.to_owned(), size)),
+            }
+        }
+    }
+
+    volumes
+}
+
+/// Moves the allocated extents from `from` to the end of a physical volume into the free
+/// extents before `from`, so that the volume may be shrunk.
+pub fn pvmove_extents(physical_volume: &Path, from: u64) -> io::Result<()> {
+    let (_, count, _) = pv_extents(physical_volume)?;
+    let pv = physical_volume.as_os_str();
+    exec("pvmove", None, None, &[
+        "--alloc".into(),
+        "anywhere".into(),
+        concat_osstr(&[pv, format!(":{}-{}", from, count.saturating_sub(1)).as_ref()]),
+        concat_osstr(&[pv, format!(":0-{}", from.saturating_sub(1)).as_ref()]),
+    ])
+}
+
+/// Obtains the size of the extents of a volume group in sectors, the number of extents within
+/// it, and how many of those are free.
+pub fn vg_extents(vg: &str) -> io::Result<(u64, u64, u64)> {
+    let output = Command::new("vgs")
+        .args(&["--noheadings", "--nosuffix", "--units", "s", "-o"])
+        .arg("vg_extent_size,vg_extent_count,vg_free_count")
+        .arg(vg)
+        .stderr(Stdio::null())
+        .output()?;
+
+    parse_extents(&String::from_utf8_lossy(&output.stdout)).ok_or_else(|| {
+        let why = format!("vgs did not report the extents of {}", vg);
+        io::Error::new(io::ErrorKind::InvalidData, why)
+    })
+}
+
+/// Obtains the number of extents in a volume group which are not allocated.
+pub fn vg_free_extents(vg: &str) -> io::Result<u64> {
+    let output = Command::new("vgs")
+        .args(&["--noheadings", "-o", "vg_free_count", vg])
+        .stderr(Stdio::null())
+        .output()?;
+
+    String::from_utf8_lossy(&output.stdout).trim().parse::<u64>().map_err(|_| {
+        let why = format!("vgs did not report the free extents of {}", vg);
+        io::Error::new(io::ErrorKind::InvalidData, why)
+    })
+}
+
 /// Obtains a map of physical volume paths and their optionally-assigned volume
 /// groups.
 pub fn pvs() -> io::Result<BTreeMap<PathBuf, Option<String>>> {
@@ -269,7 +464,7 @@ pub fn vgdeactivate(volume_group: &str) -> io::Result<()> {
 }
 
 /// Get a list of all volume groups.
-fn vgdisplay() -> io::Result<Vec<String>> {
+pub fn vgdisplay() -> io::Result<Vec<String>> {
     let mut current_line = String::with_capacity(64);
     let mut output = Vec::new();
 
@@ -304,3 +499,42 @@ pub fn pvremove(physical_volume: &Path) -> io::Result<()> {
     let args = &["-ffy".into(), physical_volume.into()];
     exec("pvremove", None, None, args)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lvs_output() {
+        let output = "  root  483647488\n  swap   8388608\n";
+        assert_eq!(parse_lv_sizes(output), vec![
+            ("root".to_owned(), 483_647_488),
+            ("swap".to_owned(), 8_388_608)
+        ]);
+        assert!(parse_lv_sizes("").is_empty());
+    }
+
+    #[test]
+    fn pvs_extents() {
+        assert_eq!(parse_extents("     8192  119234  117970\n"), Some((8192, 119_234, 117_970)));
+        assert_eq!(parse_extents("     8192  119234\n"), None);
+        assert_eq!(parse_extents(""), None);
+    }
+
+    #[test]
+    fn pvs_segments() {
+        let output = "      0 115000 root\n 115000   2000\n 117000   2234 swap_1\n";
+        assert_eq!(parse_allocated_end(output), 119_234);
+        assert_eq!(parse_allocated_end("      0 119234\n"), 0);
+    }
+
+    #[test]
+    fn pvs_volume_segments() {
+        let output = "  100000 root\n   2000\n   2234 swap_1\n  15000 root\n";
+        assert_eq!(parse_volume_extents(output), vec![
+            ("root".to_owned(), 115_000),
+            ("swap_1".to_owned(), 2234)
+        ]);
+        assert!(parse_volume_extents("  119234\n").is_empty());
+    }
+}
@@ -0,0 +1,47 @@
+use super::*;
+use std::{ffi::OsString, io};
+
+/// Discovers the targets of an iSCSI portal, such as `192.168.1.10:3260`, and logs into the
+/// `target`, or every target of the portal, so that their LUNs are attached as disks.
+pub fn iscsi_login(portal: &str, target: Option<&str>) -> io::Result<()> {
+    info!("discovering the iSCSI targets of {}", portal);
+    exec("iscsiadm", None, None, &[
+        "--mode".into(),
+        "discovery".into(),
+        "--type".into(),
+        "sendtargets".into(),
+        "--portal".into(),
+        portal.into(),
+    ])?;
+
+    let mut args: Vec<OsString> = vec!["--mode".into(), "node".into()];
+    if let Some(target) = target {
+        args.extend_from_slice(&["--targetname".into(), target.into()]);
+    }
+
+    args.extend_from_slice(&["--portal".into(), portal.into(), "--login".into()]);
+    exec("iscsiadm", None, None, &args)
+}
+
+/// Connects to an NVMe subsystem over a fabric, such as `tcp`, so that its namespaces are
+/// attached as disks.
+pub fn nvme_connect(
+    transport: &str,
+    address: &str,
+    service: Option<&str>,
+    nqn: &str,
+) -> io::Result<()> {
+    info!("connecting to the NVMe subsystem {} at {} over {}", nqn, address, transport);
+    let mut args: Vec<OsString> = vec![
+        "connect".into(),
+        ["--transport=", transport].concat().into(),
+        ["--traddr=", address].concat().into(),
+        ["--nqn=", nqn].concat().into(),
+    ];
+
+    if let Some(service) = service {
+        args.push(["--trsvcid=", service].concat().into());
+    }
+
+    exec("nvme", None, None, &args)
+}
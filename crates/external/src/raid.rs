@@ -0,0 +1,14 @@
+use super::*;
+use std::{io, path::Path};
+
+/// Stops the software RAID array at `array`, releasing the devices that it was assembled from.
+pub fn mdadm_stop<P: AsRef<Path>>(array: P) -> io::Result<()> {
+    info!("stopping RAID array {}", array.as_ref().display());
+    exec("mdadm", None, None, &["--stop".into(), array.as_ref().into()])
+}
+
+/// Erases the RAID metadata from a device which is no longer a member of an active array.
+pub fn mdadm_zero_superblock<P: AsRef<Path>>(device: P) -> io::Result<()> {
+    info!("erasing RAID metadata from {}", device.as_ref().display());
+    exec("mdadm", None, None, &["--zero-superblock".into(), device.as_ref().into()])
+}
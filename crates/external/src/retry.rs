@@ -1,11 +1,33 @@
-// NOTE: Possibly make this a crate.
+//! Retries operations which may fail transiently, such as those which race against udev
+//! creating device nodes, or against the kernel re-reading a partition table. Slow media,
+//! such as USB sticks, can take several seconds for either to happen.
 
-#[derive(SmartDefault)]
+use misc::output::CommandError;
+use rand::Rng;
+use std::{cmp, io, thread, time::Duration};
+
+/// The errno returned when a device is still held open by the kernel or another process.
+const EBUSY: i32 = 16;
+
+/// Retries an operation with an exponentially-increasing delay between attempts.
+#[derive(Debug, Clone, SmartDefault)]
 pub struct Retry {
+    /// Number of retries after the first attempt.
     #[default = 3]
-    attempts: u64,
+    attempts:     u64,
+    /// Milliseconds to wait before the first retry.
     #[default = 1000]
-    interval: u64,
+    interval:     u64,
+    /// Multiplies the delay after each retry.
+    #[default = 2.0]
+    backoff:      f64,
+    /// The delay between attempts will never exceed this many milliseconds.
+    #[default = 30_000]
+    max_interval: u64,
+    /// The fraction of each delay which is randomized, so that concurrent retries against
+    /// the same device do not occur in lockstep.
+    #[default = 0.0]
+    jitter:       f64,
 }
 
 impl Retry {
@@ -19,24 +41,150 @@ impl Retry {
         self
     }
 
-    pub fn retry_until_ok<F, T, E>(&self, mut func: F) -> Result<T, E>
+    /// A factor of `1.0` retries at a fixed interval.
+    pub fn backoff(mut self, backoff: f64) -> Self {
+        self.backoff = backoff.max(1.0);
+        self
+    }
+
+    pub fn max_interval(mut self, max_interval: u64) -> Self {
+        self.max_interval = max_interval;
+        self
+    }
+
+    /// A fraction between `0.0` and `1.0`.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.max(0.0).min(1.0);
+        self
+    }
+
+    /// Retries the function until it succeeds, or all attempts have failed.
+    pub fn retry_until_ok<F, T, E>(&self, func: F) -> Result<T, E>
     where
         F: FnMut() -> Result<T, E>,
     {
-        let duration = ::std::time::Duration::from_millis(self.interval);
+        self.retry_if(|_| true, func)
+    }
+
+    /// Retries the function while it fails with errors that the predicate considers to be
+    /// transient. Any other error is returned immediately.
+    pub fn retry_if<P, F, T, E>(&self, mut predicate: P, mut func: F) -> Result<T, E>
+    where
+        P: FnMut(&E) -> bool,
+        F: FnMut() -> Result<T, E>,
+    {
         let mut attempt = 0;
         loop {
             match func() {
                 Ok(value) => return Ok(value),
                 Err(why) => {
-                    if attempt == self.attempts {
+                    if attempt == self.attempts || !predicate(&why) {
                         return Err(why);
-                    } else {
-                        attempt += 1;
-                        ::std::thread::sleep(duration);
                     }
+
+                    thread::sleep(self.delay(attempt));
+                    attempt += 1;
                 }
             }
         }
     }
+
+    /// Waits until the condition is met, returning `false` if all attempts have failed.
+    pub fn wait_until<F: FnMut() -> bool>(&self, mut condition: F) -> bool {
+        self.retry_until_ok(|| if condition() { Ok(()) } else { Err(()) }).is_ok()
+    }
+
+    /// The delay that follows a failed attempt, with jitter applied.
+    fn delay(&self, attempt: u64) -> Duration {
+        let delay = self.base_delay(attempt) as f64;
+        let delay = if self.jitter > 0.0 {
+            let spread = delay * self.jitter;
+            rand::thread_rng().gen_range(delay - spread, delay + spread + 1.0)
+        } else {
+            delay
+        };
+
+        Duration::from_millis(delay as u64)
+    }
+
+    /// The delay that follows a failed attempt, in milliseconds, without jitter.
+    fn base_delay(&self, attempt: u64) -> u64 {
+        let factor = self.backoff.powi(cmp::min(attempt, i32::max_value() as u64) as i32);
+        let delay = self.interval as f64 * factor;
+        if delay >= self.max_interval as f64 {
+            self.max_interval
+        } else {
+            delay as u64
+        }
+    }
+}
+
+/// Whether the error may have been caused by a device which has not finished settling:
+/// its node has not been created yet, or it is still held open.
+pub fn is_transient(error: &io::Error) -> bool {
+    if error.kind() == io::ErrorKind::NotFound || error.raw_os_error() == Some(EBUSY) {
+        return true;
+    }
+
+    CommandError::from_io_error(error).map_or(false, |error| {
+        let stderr = error.stderr.to_lowercase();
+        stderr.contains("busy") || stderr.contains("no such file or directory")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let retry = Retry::default().interval(250).max_interval(1500);
+        let delays = (0..5).map(|attempt| retry.base_delay(attempt)).collect::<Vec<_>>();
+        assert_eq!(delays, vec![250, 500, 1000, 1500, 1500]);
+
+        let retry = retry.backoff(1.0);
+        assert_eq!(retry.base_delay(4), 250);
+
+        let retry = Retry::default().interval(1000).jitter(0.5);
+        for attempt in 0..3 {
+            let delay = retry.delay(attempt).as_millis() as u64;
+            let base = retry.base_delay(attempt);
+            assert!(delay >= base / 2 && delay <= base + base / 2, "{} ~ {}", delay, base);
+        }
+    }
+
+    #[test]
+    fn predicates() {
+        let mut calls = 0;
+        let result: Result<(), io::Error> =
+            Retry::default().attempts(3).interval(1).retry_if(is_transient, || {
+                calls += 1;
+                Err(io::Error::new(io::ErrorKind::PermissionDenied, "denied"))
+            });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+
+        let mut calls = 0;
+        let result = Retry::default().attempts(3).interval(1).retry_if(is_transient, || {
+            calls += 1;
+            if calls < 3 {
+                Err(CommandError {
+                    command: "blockdev".into(),
+                    status:  Some(1),
+                    stderr:  "blockdev: ioctl error on BLKRRPART: Device or resource busy".into(),
+                }
+                .into_io_error(io::ErrorKind::Other))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        assert!(!Retry::default().attempts(2).interval(1).wait_until(|| {
+            calls += 1;
+            false
+        }));
+        assert_eq!(calls, 3);
+    }
 }
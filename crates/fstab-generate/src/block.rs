@@ -12,6 +12,12 @@ pub struct BlockInfo<'a> {
     mount:       Option<PathBuf>,
     pub fs:      &'static str,
     pub options: &'a str,
+    /// The btrfs subvolume to mount, rather than the top level of the file system.
+    pub subvol:  Option<&'a str>,
+    /// Trims blocks as soon as they are freed.
+    pub discard: bool,
+    /// The device is reached over the network, so it is mounted once the network is up.
+    pub netdev:  bool,
     pub dump:    bool,
     pub pass:    bool,
 }
@@ -37,11 +43,32 @@ impl<'a> BlockInfo<'a> {
                 _ => fs.into(),
             },
             options,
+            subvol: None,
+            discard: false,
+            netdev: false,
             dump: false,
             pass,
         }
     }
 
+    /// Mounts the given btrfs subvolume, such as `@home`, at the target.
+    pub fn subvolume(mut self, subvol: &'a str) -> Self {
+        self.subvol = Some(subvol);
+        self
+    }
+
+    /// Mounts the file system with the `discard` option.
+    pub fn discard(mut self) -> Self {
+        self.discard = true;
+        self
+    }
+
+    /// Mounts the file system with the `_netdev` option.
+    pub fn netdev(mut self) -> Self {
+        self.netdev = true;
+        self
+    }
+
     /// Writes a single line to the fstab buffer for this file system.
     pub fn write_entry(&self, fstab: &mut OsString) {
         let mount_variant = match self.uid.variant {
@@ -60,7 +87,18 @@ impl<'a> BlockInfo<'a> {
         fstab.push("  ");
         fstab.push(&self.fs);
         fstab.push("  ");
+        if let Some(subvol) = self.subvol {
+            fstab.push("subvol=");
+            fstab.push(subvol);
+            fstab.push(",");
+        }
         fstab.push(&self.options);
+        if self.discard {
+            fstab.push(",discard");
+        }
+        if self.netdev {
+            fstab.push(",_netdev");
+        }
         fstab.push("  ");
         fstab.push(if self.dump { "1" } else { "0" });
         fstab.push("  ");
@@ -116,6 +154,26 @@ UUID=ROOT  /  ext4  defaults  0  1
         );
     }
 
+    #[test]
+    fn fstab_subvolumes() {
+        let id = PartitionID { id: "ROOT".into(), variant: PartitionSource::UUID };
+        let root = BlockInfo::new(id.clone(), FileSystem::Btrfs, Some(Path::new("/")), "defaults")
+            .subvolume("@root");
+        let home = BlockInfo::new(id, FileSystem::Btrfs, Some(Path::new("/home")), "noatime")
+            .subvolume("@home");
+
+        let fstab = &mut OsString::new();
+        root.write_entry(fstab);
+        home.write_entry(fstab);
+
+        assert_eq!(
+            *fstab,
+            OsString::from(r#"UUID=ROOT  /  btrfs  subvol=@root,defaults  0  1
+UUID=ROOT  /home  btrfs  subvol=@home,noatime  0  0
+"#)
+        );
+    }
+
     #[test]
     fn block_info_swap() {
         let id = PartitionID {
@@ -133,6 +191,9 @@ UUID=ROOT  /  ext4  defaults  0  1
                 mount: None,
                 fs: "swap",
                 options: "sw",
+                subvol: None,
+                discard: false,
+                netdev: false,
                 dump: false,
                 pass: false,
             }
@@ -157,6 +218,9 @@ UUID=ROOT  /  ext4  defaults  0  1
                 mount: Some(PathBuf::from("/boot/efi")),
                 fs: "vfat",
                 options: "defaults",
+                subvol: None,
+                discard: false,
+                netdev: false,
                 dump: false,
                 pass: false,
             }
@@ -181,6 +245,9 @@ UUID=ROOT  /  ext4  defaults  0  1
                 mount: Some(PathBuf::from("/")),
                 fs: FileSystem::Ext4.into(),
                 options: "defaults",
+                subvol: None,
+                discard: false,
+                netdev: false,
                 dump: false,
                 pass: true,
             }
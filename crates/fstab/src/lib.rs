@@ -0,0 +1,673 @@
+//! Generates the `/etc/fstab` and `/etc/crypttab` of an installation from its disk
+//! configuration.
+//!
+//! The identifiers of devices are only known once they have been formatted, so they are
+//! obtained through `DeviceIds`. Otherwise, the files are derived from the configuration
+//! alone, so that the same configuration always generates the same files.
+
+#[macro_use]
+extern crate log;
+
+use disk_types::{BlockDeviceExt, FileSystem, PartitionExt};
+use distinst_disks::{Disk, Disks, LogicalDevice, LvmEncryption, PartitionInfo, BOOT_KEYFILE};
+use partition_identity::PartitionID;
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    path::Path,
+};
+
+/// Options of a swap partition which is encrypted with a random key on each boot.
+const CRYPTSWAP_OPTIONS: &str = "swap,plain,offset=1024,cipher=aes-xts-plain64,size=512";
+
+/// How file systems on disks which support discard are trimmed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrimPolicy {
+    /// Unused blocks are trimmed weekly by `fstrim.timer`. Encrypted volumes do not pass the
+    /// discards on to their disks.
+    Periodic,
+    /// File systems are mounted with `discard`, and trim blocks as soon as they are freed.
+    /// Encrypted volumes pass the discards on to their disks.
+    Continuous,
+    /// Blocks are not trimmed.
+    Disabled,
+}
+
+impl Default for TrimPolicy {
+    fn default() -> Self { TrimPolicy::Periodic }
+}
+
+/// Obtains the identifiers of devices.
+pub trait DeviceIds {
+    /// The UUID of the file system, or of the LUKS header, on the device.
+    fn uuid(&self, device: &Path) -> Option<PartitionID>;
+
+    /// The UUID of the GPT partition.
+    fn partuuid(&self, device: &Path) -> Option<PartitionID>;
+}
+
+/// Obtains identifiers from `/dev/disk`.
+pub struct SystemDeviceIds;
+
+impl DeviceIds for SystemDeviceIds {
+    fn uuid(&self, device: &Path) -> Option<PartitionID> { PartitionID::get_uuid(device) }
+
+    fn partuuid(&self, device: &Path) -> Option<PartitionID> { PartitionID::get_partuuid(device) }
+}
+
+/// Generates the crypttab and fstab files, in that order.
+///
+/// Encrypted volumes on disks which support discard only pass discards on to their disks with
+/// the `Continuous` policy, as this reveals which blocks are unused to those with access to the
+/// disk.
+/// Devices on disks which are attached over the network are marked with `_netdev`, so that they
+/// are only unlocked and mounted once the network is up.
+pub fn generate<I: DeviceIds>(disks: &Disks, ids: &I, trim: TrimPolicy) -> (OsString, OsString) {
+    let mut crypttab = OsString::with_capacity(1024);
+    let mut fstab = OsString::with_capacity(1024);
+    let mut swaps = OsString::new();
+    let mut mounts = Vec::new();
+    let mut volumes: Vec<&str> = Vec::new();
+    let mut cryptswaps = 0;
+
+    for (encrypted, luks_parent, backing, partition) in partitions(disks) {
+        let path = partition.get_device_path();
+
+        if let Some((_, Some(ref enc))) = partition.volume_group {
+            let volume = enc.physical_volume.as_str();
+            if volumes.contains(&volume) || !is_unlocked_at_boot(disks, path) {
+                continue;
+            }
+
+            match ids.uuid(luks_parent.unwrap_or(path)) {
+                Some(uuid) => {
+                    volumes.push(volume);
+                    crypttab.push(&[volume, " UUID=", &uuid.id, " "].concat());
+                    crypttab.push(key(enc));
+                    crypttab.push(" ");
+                    let discard = backing.discard && trim == TrimPolicy::Continuous;
+                    crypttab.push(backing.options("luks", discard));
+                    crypttab.push("\n");
+                }
+                None => warn!("unable to find UUID for {} -- skipping", path.display()),
+            }
+        } else if partition.is_swap() {
+            let swap = ["  none  swap  ", &backing.options("defaults", false), "  0  0\n"].concat();
+            if encrypted {
+                swaps.push(path);
+                swaps.push(&swap);
+                continue;
+            }
+
+            match ids.uuid(path) {
+                Some(uuid) => {
+                    let name = match cryptswaps {
+                        0 => "cryptswap".to_owned(),
+                        n => format!("cryptswap{}", n),
+                    };
+
+                    cryptswaps += 1;
+                    crypttab.push(&[name.as_str(), " UUID=", &uuid.id, " /dev/urandom "].concat());
+                    crypttab.push(backing.options(CRYPTSWAP_OPTIONS, false));
+                    crypttab.push("\n");
+                    swaps.push(&["/dev/mapper/", &name, &swap].concat());
+                }
+                None => warn!("unable to find UUID for {} -- skipping", path.display()),
+            }
+        } else if let Some(fs) = partition.get_file_system() {
+            if partition.target.is_none() && partition.subvolumes.is_empty() {
+                continue;
+            }
+
+            let discard = backing.discard && trim == TrimPolicy::Continuous && supports_discard(fs);
+            match partition_id(ids, path, fs) {
+                Some(id) => mounts.extend(partition.block_info_with(id, fs).into_iter().map(
+                    |entry| {
+                        let entry = if discard { entry.discard() } else { entry };
+                        if backing.network { entry.netdev() } else { entry }
+                    },
+                )),
+                None => warn!("unable to find UUID for {} -- skipping", path.display()),
+            }
+        }
+    }
+
+    // Parent mount points must be listed before the mount points nested within them.
+    mounts.sort_by(|a, b| Path::new(a.mount()).cmp(Path::new(b.mount())));
+    for blockinfo in &mounts {
+        blockinfo.write_entry(&mut fstab);
+    }
+    fstab.push(&swaps);
+
+    (crypttab, fstab)
+}
+
+/// Whether any file system which will be mounted is on a disk which supports discard, and
+/// thus would benefit from being trimmed.
+pub fn is_trimmable(disks: &Disks) -> bool {
+    partitions(disks).any(|(_, _, backing, partition)| {
+        backing.discard
+            && (partition.target.is_some() || !partition.subvolumes.is_empty())
+            && partition.filesystem.map_or(false, supports_discard)
+    })
+}
+
+/// What the disks beneath a device have in common.
+#[derive(Debug, Clone, Copy)]
+struct Backing {
+    /// Every disk supports discard.
+    discard: bool,
+    /// A disk is attached over the network, and is only reachable once the network is up.
+    network: bool,
+}
+
+impl Backing {
+    fn of<'a, D: IntoIterator<Item = &'a Disk>>(disks: D) -> Backing {
+        let mut disks = disks.into_iter().peekable();
+        let mut backing = Backing { discard: disks.peek().is_some(), network: false };
+        for disk in disks {
+            backing.discard &= disk.topology.discard;
+            backing.network |= disk.topology.transport.is_network();
+        }
+
+        backing
+    }
+
+    /// Appends `discard`, if requested, and `_netdev`, if needed, to the options.
+    fn options(self, options: &str, discard: bool) -> Cow<str> {
+        match (discard, self.network) {
+            (false, false) => Cow::Borrowed(options),
+            (true, false) => Cow::Owned([options, ",discard"].concat()),
+            (false, true) => Cow::Owned([options, ",_netdev"].concat()),
+            (true, true) => Cow::Owned([options, ",discard,_netdev"].concat()),
+        }
+    }
+}
+
+type Entry<'a> = (bool, Option<&'a Path>, Backing, &'a PartitionInfo);
+
+/// Every partition, and file system spanning a whole device, paired with whether it is within
+/// an encrypted volume, the LUKS partition which contains the device, if any, and what the
+/// disks beneath it have in common.
+fn partitions(disks: &Disks) -> impl Iterator<Item = Entry> {
+    let physical = disks.physical.iter().flat_map(|disk| {
+        let backing = Backing::of(Some(disk));
+        let partitions = disk.file_system.iter().chain(disk.partitions.iter());
+        partitions.map(move |p| (false, None, backing, p))
+    });
+
+    let logical = disks.logical.iter().flat_map(move |device| {
+        let encrypted = device.encryption.is_some();
+        let luks_parent = device.luks_parent.as_deref();
+        let backing = Backing::of(logical_disks(disks, device));
+        device
+            .file_system
+            .iter()
+            .chain(device.partitions.iter())
+            .map(move |p| (encrypted, luks_parent, backing, p))
+    });
+
+    physical.chain(logical)
+}
+
+/// The disks which a logical device is stored on.
+fn logical_disks<'a>(disks: &'a Disks, device: &LogicalDevice) -> Vec<&'a Disk> {
+    let partitions = match device.luks_parent {
+        Some(ref parent) => vec![parent.as_path()],
+        None => {
+            let volumes = disks.find_volume_paths(&device.volume_group);
+            volumes.into_iter().map(|(_, partition)| partition).collect()
+        }
+    };
+
+    partitions
+        .into_iter()
+        .filter_map(|partition| disks.get_physical_device_with_partition(partition))
+        .collect()
+}
+
+/// File systems which may be mounted with `discard`. F2FS is excluded, as its preferred options
+/// disable discard in favor of `fstrim`.
+fn supports_discard(fs: FileSystem) -> bool {
+    matches!(
+        fs,
+        FileSystem::Btrfs
+            | FileSystem::Ext2
+            | FileSystem::Ext3
+            | FileSystem::Ext4
+            | FileSystem::Fat16
+            | FileSystem::Fat32
+            | FileSystem::Xfs
+    )
+}
+
+/// Whether the LUKS partition at `path` contains a file system which will be mounted, or swap,
+/// and thus must be unlocked at boot.
+fn is_unlocked_at_boot(disks: &Disks, path: &Path) -> bool {
+    disks
+        .logical
+        .iter()
+        .filter(|device| device.luks_parent.as_deref() == Some(path))
+        .flat_map(|device| device.file_system.iter().chain(device.partitions.iter()))
+        .any(|p| p.target.is_some() || !p.subvolumes.is_empty() || p.is_swap())
+}
+
+/// FAT partitions are prone to UUID collisions, so the PartUUID is used instead.
+fn partition_id<I: DeviceIds>(ids: &I, path: &Path, fs: FileSystem) -> Option<PartitionID> {
+    match fs {
+        FileSystem::Fat16 | FileSystem::Fat32 => ids.partuuid(path),
+        _ => ids.uuid(path),
+    }
+}
+
+/// The key which unlocks the volume at boot.
+fn key(enc: &LvmEncryption) -> Cow<OsStr> {
+    // Keyfiles on the live system are not available at boot, so a password will be requested
+    // instead.
+    let prompt = enc.password.is_some() || enc.keyfile.is_some();
+    match (prompt, enc.keydata.as_ref()) {
+        // GRUB has already asked for the password to unlock `/boot`.
+        (true, None) if enc.encrypted_boot => Cow::Borrowed(OsStr::new(BOOT_KEYFILE)),
+        (true, None) => Cow::Borrowed(OsStr::new("none")),
+        (false, None) => Cow::Borrowed(OsStr::new("/dev/urandom")),
+        // When both a password and a keyfile are set, the keyfile is used to unlock the device
+        // at boot.
+        (_, Some(&(_, ref key))) => {
+            let (_, ref directory) = *key.as_ref().expect("should have been populated");
+            Cow::Owned(directory.join(&enc.physical_volume).into_os_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use distinst_disks::{
+        Disk, DiskTopology, LogicalDevice, PartitionBuilder, PartitionTable, Subvolume,
+        Transport,
+    };
+    use partition_identity::PartitionSource;
+    use std::path::PathBuf;
+
+    /// Derives the identifiers from the name of the device.
+    struct TestIds;
+
+    impl DeviceIds for TestIds {
+        fn uuid(&self, device: &Path) -> Option<PartitionID> {
+            id(device, PartitionSource::UUID, "uuid")
+        }
+
+        fn partuuid(&self, device: &Path) -> Option<PartitionID> {
+            id(device, PartitionSource::PartUUID, "partuuid")
+        }
+    }
+
+    fn id(device: &Path, variant: PartitionSource, prefix: &str) -> Option<PartitionID> {
+        let name = device.file_name()?.to_str()?;
+        Some(PartitionID { id: [prefix, "-", name].concat(), variant })
+    }
+
+    fn partition(path: &str, fs: FileSystem, target: Option<&str>) -> PartitionInfo {
+        let mut partition = PartitionBuilder::new(0, 1, fs).build();
+        partition.device_path = PathBuf::from(path);
+        partition.target = target.map(PathBuf::from);
+        partition
+    }
+
+    fn luks(path: &str, enc: LvmEncryption) -> PartitionInfo {
+        let mut partition = PartitionBuilder::new(0, 1, FileSystem::Luks)
+            .logical_volume(enc.physical_volume.clone(), Some(enc))
+            .build();
+        partition.device_path = PathBuf::from(path);
+        partition
+    }
+
+    fn disk(path: &str, partitions: Vec<PartitionInfo>) -> Disk {
+        Disk {
+            model_name:  String::new(),
+            serial:      String::new(),
+            device_path: PathBuf::from(path),
+            file_system: None,
+            mount_point: None,
+            size:        0,
+            device_type: String::new(),
+            table_type:  Some(PartitionTable::Gpt),
+            read_only:   false,
+            removable:   false,
+            mklabel:     false,
+            erase:       None,
+            raid:        None,
+            rst_active:  false,
+            topology:    DiskTopology::default(),
+            partitions,
+        }
+    }
+
+    fn encrypted(
+        volume_group: &str,
+        luks_parent: &str,
+        enc: LvmEncryption,
+        file_system: Option<PartitionInfo>,
+        partitions: Vec<PartitionInfo>,
+    ) -> LogicalDevice {
+        LogicalDevice {
+            volume_group: volume_group.into(),
+            device_path:  PathBuf::from(["/dev/mapper/", volume_group].concat()),
+            luks_parent:  Some(PathBuf::from(luks_parent)),
+            file_system,
+            partitions,
+            encryption:   Some(enc),
+            ..Default::default()
+        }
+    }
+
+    fn generate_strings(disks: &Disks) -> (String, String) {
+        let (crypttab, fstab) = generate(disks, &TestIds, TrimPolicy::default());
+        (crypttab.into_string().unwrap(), fstab.into_string().unwrap())
+    }
+
+    #[test]
+    fn unencrypted() {
+        let disks = Disks {
+            physical: vec![disk("/dev/sda", vec![
+                partition("/dev/sda1", FileSystem::Fat32, Some("/boot/efi")),
+                partition("/dev/sda2", FileSystem::Ext4, Some("/")),
+                partition("/dev/sda3", FileSystem::Swap, None),
+                partition("/dev/sda4", FileSystem::Ntfs, None),
+            ])],
+            logical:  Vec::new(),
+        };
+
+        assert_eq!(
+            generate_strings(&disks),
+            (
+                "cryptswap UUID=uuid-sda3 /dev/urandom swap,plain,offset=1024,\
+                 cipher=aes-xts-plain64,size=512\n"
+                    .into(),
+                "UUID=uuid-sda2  /  ext4  noatime,errors=remount-ro  0  1\n\
+                 PARTUUID=partuuid-sda1  /boot/efi  vfat  umask=0077  0  0\n\
+                 /dev/mapper/cryptswap  none  swap  defaults  0  0\n"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn luks_partition() {
+        // An encrypted file system on one partition of a disk which is shared with another OS.
+        let enc = LvmEncryption::new("cryptdata".into(), Some("password".into()), None);
+        let root = partition("/dev/mapper/cryptdata", FileSystem::Ext4, Some("/"));
+        let disks = Disks {
+            physical: vec![disk("/dev/nvme0n1", vec![
+                partition("/dev/nvme0n1p1", FileSystem::Fat32, Some("/boot/efi")),
+                partition("/dev/nvme0n1p2", FileSystem::Ntfs, None),
+                luks("/dev/nvme0n1p3", enc.clone()),
+            ])],
+            logical:  vec![encrypted("cryptdata", "/dev/nvme0n1p3", enc, Some(root), Vec::new())],
+        };
+
+        assert_eq!(
+            generate_strings(&disks),
+            (
+                "cryptdata UUID=uuid-nvme0n1p3 none luks\n".into(),
+                "UUID=uuid-cryptdata  /  ext4  noatime,errors=remount-ro  0  1\n\
+                 PARTUUID=partuuid-nvme0n1p1  /boot/efi  vfat  umask=0077  0  0\n"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn lvm_on_luks() {
+        let enc = LvmEncryption::new("cryptdata".into(), Some("password".into()), None)
+            .encrypted_boot();
+        let disks = Disks {
+            physical: vec![disk("/dev/sda", vec![
+                partition("/dev/sda1", FileSystem::Fat32, Some("/boot/efi")),
+                luks("/dev/sda2", enc.clone()),
+            ])],
+            logical:  vec![encrypted("data", "/dev/sda2", enc, None, vec![
+                partition("/dev/mapper/data-root", FileSystem::Ext4, Some("/")),
+                partition("/dev/mapper/data-home", FileSystem::Xfs, Some("/home")),
+                partition("/dev/mapper/data-swap", FileSystem::Swap, None),
+            ])],
+        };
+
+        assert_eq!(
+            generate_strings(&disks),
+            (
+                ["cryptdata UUID=uuid-sda2 ", BOOT_KEYFILE, " luks\n"].concat(),
+                "UUID=uuid-data-root  /  ext4  noatime,errors=remount-ro  0  1\n\
+                 PARTUUID=partuuid-sda1  /boot/efi  vfat  umask=0077  0  0\n\
+                 UUID=uuid-data-home  /home  xfs  defaults  0  0\n\
+                 /dev/mapper/data-swap  none  swap  defaults  0  0\n"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn luks_keyfile() {
+        let mut enc = LvmEncryption::new("cryptdata".into(), None, Some("key".into()));
+        enc.keydata = Some(("key".into(), Some(("/dev/sdb1".into(), "/etc/keys".into()))));
+        let disks = Disks {
+            physical: vec![
+                disk("/dev/sda", vec![luks("/dev/sda1", enc.clone())]),
+                disk("/dev/sdb", vec![partition("/dev/sdb1", FileSystem::Ext4, Some("/etc/keys"))]),
+            ],
+            logical:  vec![encrypted("data", "/dev/sda1", enc, None, vec![partition(
+                "/dev/mapper/data-root",
+                FileSystem::Ext4,
+                Some("/"),
+            )])],
+        };
+
+        let (crypttab, _) = generate_strings(&disks);
+        assert_eq!(crypttab, "cryptdata UUID=uuid-sda1 /etc/keys/cryptdata luks\n");
+    }
+
+    #[test]
+    fn unused_luks_partition() {
+        // An encrypted partition that was decrypted, but none of its volumes will be mounted.
+        let enc = LvmEncryption::new("cryptdata".into(), Some("password".into()), None);
+        let data = partition("/dev/mapper/data-root", FileSystem::Ext4, None);
+        let disks = Disks {
+            physical: vec![disk("/dev/sda", vec![
+                partition("/dev/sda1", FileSystem::Ext4, Some("/")),
+                luks("/dev/sda2", enc.clone()),
+            ])],
+            logical:  vec![encrypted("data", "/dev/sda2", enc, None, vec![data])],
+        };
+
+        let (crypttab, fstab) = generate_strings(&disks);
+        assert_eq!(crypttab, "");
+        assert_eq!(fstab, "UUID=uuid-sda1  /  ext4  noatime,errors=remount-ro  0  1\n");
+    }
+
+    #[test]
+    fn btrfs_subvolumes() {
+        let mut root = partition("/dev/sda2", FileSystem::Btrfs, Some("/"));
+        for &(name, target) in &[("@root", "/"), ("@home", "/home")] {
+            root.add_subvolume(Subvolume {
+                name:    name.into(),
+                target:  PathBuf::from(target),
+                options: None,
+            });
+        }
+
+        root.add_subvolume(Subvolume {
+            name:    "@snapshots".into(),
+            target:  PathBuf::from("/.snapshots"),
+            options: Some("noatime,compress=zstd".into()),
+        });
+
+        let disks = Disks {
+            physical: vec![disk("/dev/sda", vec![
+                partition("/dev/sda1", FileSystem::Fat32, Some("/boot/efi")),
+                root,
+            ])],
+            logical:  Vec::new(),
+        };
+
+        let (_, fstab) = generate_strings(&disks);
+        assert_eq!(
+            fstab,
+            "UUID=uuid-sda2  /  btrfs  subvol=@root,defaults  0  1\n\
+             UUID=uuid-sda2  /.snapshots  btrfs  subvol=@snapshots,noatime,compress=zstd  0  0\n\
+             PARTUUID=partuuid-sda1  /boot/efi  vfat  umask=0077  0  0\n\
+             UUID=uuid-sda2  /home  btrfs  subvol=@home,defaults  0  0\n"
+        );
+    }
+
+    #[test]
+    fn swap() {
+        // Swap in an unencrypted volume group is encrypted with a random key, like a partition.
+        let disks = Disks {
+            physical: vec![disk("/dev/sda", vec![
+                partition("/dev/sda1", FileSystem::Ext4, Some("/")),
+                partition("/dev/sda2", FileSystem::Swap, None),
+                partition("/dev/sda3", FileSystem::Lvm, None),
+            ])],
+            logical:  vec![LogicalDevice {
+                volume_group: "data".into(),
+                partitions:   vec![partition("/dev/mapper/data-swap", FileSystem::Swap, None)],
+                ..Default::default()
+            }],
+        };
+
+        let (crypttab, fstab) = generate_strings(&disks);
+        assert_eq!(
+            crypttab,
+            [
+                "cryptswap UUID=uuid-sda2 /dev/urandom ",
+                CRYPTSWAP_OPTIONS,
+                "\ncryptswap1 UUID=uuid-data-swap /dev/urandom ",
+                CRYPTSWAP_OPTIONS,
+                "\n",
+            ]
+            .concat()
+        );
+        assert_eq!(
+            fstab,
+            "UUID=uuid-sda1  /  ext4  noatime,errors=remount-ro  0  1\n\
+             /dev/mapper/cryptswap  none  swap  defaults  0  0\n\
+             /dev/mapper/cryptswap1  none  swap  defaults  0  0\n"
+        );
+    }
+
+    #[test]
+    fn bind_mounted_partitions() {
+        // Partitions which are already mounted on the live system are bind mounted into the
+        // installation, but must still be mounted from their device at boot.
+        let mut home = partition("/dev/sdb1", FileSystem::Ext4, Some("/home"));
+        home.mount_point = Some(PathBuf::from("/media/user/home"));
+        let disks = Disks {
+            physical: vec![
+                disk("/dev/sda", vec![partition("/dev/sda1", FileSystem::Ext4, Some("/"))]),
+                disk("/dev/sdb", vec![home]),
+            ],
+            logical:  Vec::new(),
+        };
+
+        let (_, fstab) = generate_strings(&disks);
+        assert_eq!(
+            fstab,
+            "UUID=uuid-sda1  /  ext4  noatime,errors=remount-ro  0  1\n\
+             UUID=uuid-sdb1  /home  ext4  noatime,errors=remount-ro  0  0\n"
+        );
+    }
+
+    #[test]
+    fn deterministic() {
+        let enc = LvmEncryption::new("cryptdata".into(), Some("password".into()), None);
+        let disks = Disks {
+            physical: vec![disk("/dev/sda", vec![
+                partition("/dev/sda1", FileSystem::Fat32, Some("/boot/efi")),
+                partition("/dev/sda2", FileSystem::Swap, None),
+                partition("/dev/sda3", FileSystem::Swap, None),
+                luks("/dev/sda4", enc.clone()),
+            ])],
+            logical:  vec![encrypted("data", "/dev/sda4", enc, None, vec![
+                partition("/dev/mapper/data-root", FileSystem::Ext4, Some("/")),
+            ])],
+        };
+
+        assert_eq!(generate_strings(&disks), generate_strings(&disks));
+    }
+
+    #[test]
+    fn trim() {
+        let enc = LvmEncryption::new("cryptdata".into(), Some("password".into()), None);
+        let mut ssd = disk("/dev/nvme0n1", vec![
+            partition("/dev/nvme0n1p1", FileSystem::Fat32, Some("/boot/efi")),
+            luks("/dev/nvme0n1p2", enc.clone()),
+        ]);
+        ssd.topology.discard = true;
+        let disks = Disks {
+            physical: vec![ssd, disk("/dev/sda", vec![
+                partition("/dev/sda1", FileSystem::Ext4, Some("/home")),
+            ])],
+            logical:  vec![encrypted("data", "/dev/nvme0n1p2", enc, None, vec![
+                partition("/dev/mapper/data-root", FileSystem::Ext4, Some("/")),
+            ])],
+        };
+
+        let generate = |trim| {
+            let (crypttab, fstab) = generate(&disks, &TestIds, trim);
+            (crypttab.into_string().unwrap(), fstab.into_string().unwrap())
+        };
+
+        assert!(is_trimmable(&disks));
+        assert_eq!(
+            generate(TrimPolicy::Continuous),
+            (
+                "cryptdata UUID=uuid-nvme0n1p2 none luks,discard\n".into(),
+                "UUID=uuid-data-root  /  ext4  noatime,errors=remount-ro,discard  0  1\n\
+                 PARTUUID=partuuid-nvme0n1p1  /boot/efi  vfat  umask=0077,discard  0  0\n\
+                 UUID=uuid-sda1  /home  ext4  noatime,errors=remount-ro  0  0\n"
+                    .into()
+            )
+        );
+
+        let (crypttab, fstab) = generate(TrimPolicy::Periodic);
+        assert_eq!(crypttab, "cryptdata UUID=uuid-nvme0n1p2 none luks\n");
+        assert!(!fstab.contains("discard"));
+
+        let (crypttab, fstab) = generate(TrimPolicy::Disabled);
+        assert_eq!(crypttab, "cryptdata UUID=uuid-nvme0n1p2 none luks\n");
+        assert!(!fstab.contains("discard"));
+    }
+
+    #[test]
+    fn network() {
+        let enc = LvmEncryption::new("cryptdata".into(), Some("password".into()), None);
+        let mut iscsi = disk("/dev/sdb", vec![
+            partition("/dev/sdb1", FileSystem::Ext4, Some("/boot")),
+            luks("/dev/sdb2", enc.clone()),
+            partition("/dev/sdb3", FileSystem::Swap, None),
+        ]);
+        iscsi.topology.transport = Transport::Iscsi;
+        let disks = Disks {
+            physical: vec![iscsi],
+            logical:  vec![encrypted("data", "/dev/sdb2", enc, None, vec![
+                partition("/dev/mapper/data-root", FileSystem::Ext4, Some("/")),
+            ])],
+        };
+
+        let (crypttab, fstab) = generate(&disks, &TestIds, TrimPolicy::Periodic);
+        assert_eq!(
+            crypttab.into_string().unwrap(),
+            format!(
+                "cryptdata UUID=uuid-sdb2 none luks,_netdev\n\
+                 cryptswap UUID=uuid-sdb3 /dev/urandom {},_netdev\n",
+                CRYPTSWAP_OPTIONS
+            )
+        );
+        assert_eq!(
+            fstab.into_string().unwrap(),
+            "UUID=uuid-data-root  /  ext4  noatime,errors=remount-ro,_netdev  0  1\n\
+             UUID=uuid-sdb1  /boot  ext4  noatime,errors=remount-ro,_netdev  0  0\n\
+             /dev/mapper/cryptswap  none  swap  defaults,_netdev  0  0\n"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Sorts lists of names in the order that speakers of a language expect them to be in, and
+//! translates the names that are displayed by installers.
+
+use super::{
+    get_countries, get_country_name, get_default, get_language_codes, get_language_name, translate,
+    with_language,
+};
+use gettextrs::*;
+use libc::{self, c_char, c_int, locale_t, size_t};
+use std::{cmp::Ordering, ffi::CString, ptr};
+
+extern "C" {
+    fn strcoll_l(a: *const c_char, b: *const c_char, locale: locale_t) -> c_int;
+    fn strxfrm_l(dest: *mut c_char, src: *const c_char, n: size_t, locale: locale_t) -> size_t;
+}
+
+/// Compares strings with the collation rules of a language's locale.
+///
+/// If the locale has not been generated on the system, strings are compared by their
+/// lowercase forms instead.
+pub struct Collator {
+    locale: locale_t,
+}
+
+impl Collator {
+    /// Creates a collator for a language code, such as `de`, or a locale, such as `de_DE`.
+    pub fn new(lang: &str) -> Collator {
+        let name = if lang.contains('_') { Some(lang.to_owned()) } else { get_default(lang) };
+        let locale = match name.and_then(|name| CString::new(name).ok()) {
+            Some(name) => unsafe {
+                libc::newlocale(libc::LC_COLLATE_MASK, name.as_ptr(), ptr::null_mut())
+            },
+            None => ptr::null_mut(),
+        };
+
+        if locale.is_null() {
+            warn!("collation rules for {} are not available", lang);
+        }
+
+        Collator { locale }
+    }
+
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        if !self.locale.is_null() {
+            if let (Ok(ca), Ok(cb)) = (CString::new(a), CString::new(b)) {
+                let result = unsafe { strcoll_l(ca.as_ptr(), cb.as_ptr(), self.locale) };
+                return result.cmp(&0).then_with(|| a.cmp(b));
+            }
+        }
+
+        a.to_lowercase().cmp(&b.to_lowercase()).then_with(|| a.cmp(b))
+    }
+
+    /// Computes a key for the string, which orders against the keys of other strings in the
+    /// same way that `compare` orders the strings themselves.
+    ///
+    /// Sorting by keys collates each string once, rather than once per comparison.
+    pub fn key(&self, s: &str) -> CollationKey {
+        let transformed = match CString::new(s) {
+            Ok(ref cs) if !self.locale.is_null() => unsafe {
+                let len = strxfrm_l(ptr::null_mut(), cs.as_ptr(), 0, self.locale);
+                let mut buf = vec![0u8; len + 1];
+                strxfrm_l(buf.as_mut_ptr() as *mut c_char, cs.as_ptr(), buf.len(), self.locale);
+                buf.truncate(len);
+                buf
+            },
+            _ => s.to_lowercase().into_bytes(),
+        };
+
+        CollationKey { transformed, original: s.to_owned() }
+    }
+}
+
+/// The sort key of a string, from `Collator::key`.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct CollationKey {
+    transformed: Vec<u8>,
+    original:    String,
+}
+
+impl Drop for Collator {
+    fn drop(&mut self) {
+        if !self.locale.is_null() {
+            unsafe { libc::freelocale(self.locale) };
+        }
+    }
+}
+
+/// Fetch the name of a language, translated into the display language.
+pub fn get_language_display_name(code: &str, display_lang: &str) -> Option<String> {
+    get_language_name(code).map(|name| translate("iso_639_3", name, display_lang))
+}
+
+/// Fetch the name of a country, translated into the display language.
+pub fn get_country_display_name(code: &str, display_lang: &str) -> Option<String> {
+    get_country_name(code).map(|name| translate("iso_3166", name, display_lang))
+}
+
+/// Fetch the supported language codes, paired with their names in the display language, and
+/// sorted by those names.
+pub fn get_language_names_sorted(display_lang: &str) -> Vec<(&'static str, String)> {
+    let names = with_language(display_lang, || {
+        get_language_codes()
+            .into_iter()
+            .map(|code| {
+                let name = get_language_name(code)
+                    .map_or_else(|| code.into(), |name| dgettext("iso_639_3", name));
+                (name, code)
+            })
+            .collect::<Vec<_>>()
+    });
+
+    sort_names(names, &Collator::new(display_lang))
+}
+
+/// Fetch the country codes associated with a language, paired with their names in the display
+/// language, and sorted by those names.
+pub fn get_country_names_sorted(lang: &str, display_lang: &str) -> Vec<(&'static str, String)> {
+    let names = with_language(display_lang, || {
+        get_countries(lang)
+            .into_iter()
+            .map(|code| {
+                let name = get_country_name(code)
+                    .map_or_else(|| code.into(), |name| dgettext("iso_3166", name));
+                (name, code)
+            })
+            .collect::<Vec<_>>()
+    });
+
+    sort_names(names, &Collator::new(display_lang))
+}
+
+/// Sorts items by the names that will be displayed for them.
+pub fn sort_by_display_name<T, F>(items: &mut Vec<T>, collator: &Collator, mut name: F)
+where
+    F: FnMut(&T) -> String,
+{
+    let named = items.drain(..).map(|item| (name(&item), item)).collect::<Vec<_>>();
+    items.extend(sort_names(named, collator).into_iter().map(|(item, _)| item));
+}
+
+/// Sorts named items by the collation keys of their names, which are computed once each.
+fn sort_names<T>(named: Vec<(String, T)>, collator: &Collator) -> Vec<(T, String)> {
+    let mut keyed =
+        named.into_iter().map(|(name, item)| (collator.key(&name), item, name)).collect::<Vec<_>>();
+
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    keyed.into_iter().map(|(_, item, name)| (item, name)).collect()
+}
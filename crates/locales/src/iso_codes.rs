@@ -16,20 +16,7 @@ pub fn get_language_name(code: &str) -> Option<&'static str> {
 
 /// Fetch a translated version of that name, based on the given language code.
 pub fn get_language_name_translated(code: &str) -> Option<String> {
-    let current_lang = env::var("LANGUAGE");
-    if let Some(locale) = get_default(code) {
-        env::set_var("LANGUAGE", locale);
-    }
-    setlocale(LocaleCategory::LcAll, "");
-
-    let result = get_language_name(code).map(|language_name| dgettext("iso_639_3", language_name));
-
-    match current_lang {
-        Ok(lang) => env::set_var("LANGUAGE", lang),
-        _ => env::remove_var("LANGUAGE"),
-    }
-
-    result
+    get_language_name(code).map(|language_name| translate("iso_639_3", language_name, code))
 }
 
 /// Get the country name of an ISO 3166 country code.
@@ -44,20 +31,33 @@ pub fn get_country(code: &str) -> Option<&'static Country> {
 
 /// Get the country name translated into the given language code.
 pub fn get_country_name_translated(country_code: &str, lang_code: &str) -> Option<String> {
-    get_country_name(country_code).map(|country_name| {
-        let current_lang = env::var("LANGUAGE");
-        if let Some(locale) = get_default(lang_code) {
-            env::set_var("LANGUAGE", locale);
-        }
-
-        setlocale(LocaleCategory::LcAll, "");
-        let result = dgettext("iso_3166", country_name);
-
-        match current_lang {
-            Ok(lang) => env::set_var("LANGUAGE", lang),
-            _ => env::remove_var("LANGUAGE"),
-        }
-
-        result
-    })
+    get_country_name(country_code)
+        .map(|country_name| translate("iso_3166", country_name, lang_code))
+}
+
+/// Translates a message from the gettext `domain` into the given language.
+pub fn translate(domain: &str, message: &str, lang: &str) -> String {
+    with_language(lang, || dgettext(domain, message))
+}
+
+/// Runs `func` with gettext translating into the given language, and then restores the
+/// `LANGUAGE` of the process.
+///
+/// The `LANGUAGE` variable is process-wide, so messages which are translated together should
+/// be translated within one call.
+pub fn with_language<T, F: FnOnce() -> T>(lang: &str, func: F) -> T {
+    let current_lang = env::var("LANGUAGE");
+    if let Some(locale) = get_default(lang) {
+        env::set_var("LANGUAGE", locale);
+    }
+
+    setlocale(LocaleCategory::LcAll, "");
+    let result = func();
+
+    match current_lang {
+        Ok(lang) => env::set_var("LANGUAGE", lang),
+        _ => env::remove_var("LANGUAGE"),
+    }
+
+    result
 }
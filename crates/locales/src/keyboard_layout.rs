@@ -1,10 +1,18 @@
+use super::{sort_by_display_name, translate, with_language, Collator};
+use gettextrs::dgettext;
 use misc;
 use serde_xml_rs as xml;
-use std::io::{self, BufReader};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufReader},
+};
 
 /// A list of keyboard layouts parsed from `/usr/share/X11/xkb/rules/base.xml`.
 #[derive(Debug, Deserialize)]
 pub struct KeyboardLayouts {
+    #[serde(rename = "modelList")]
+    pub model_list:  Option<ModelList>,
     #[serde(rename = "layoutList")]
     pub layout_list: LayoutList,
 }
@@ -15,6 +23,89 @@ impl KeyboardLayouts {
 
     /// Fetch the layouts from the layout list.
     pub fn get_layouts_mut(&mut self) -> &mut [KeyboardLayout] { &mut self.layout_list.layout }
+
+    /// Fetch the keyboard models, such as `pc105`.
+    pub fn get_models(&self) -> &[KeyboardModel] {
+        self.model_list.as_ref().map_or(&[], |list| &list.model)
+    }
+
+    /// Checks that the layout exists, and that the model and variant exist for it, if given.
+    pub fn validate(
+        &self,
+        layout: &str,
+        model: Option<&str>,
+        variant: Option<&str>,
+    ) -> Result<(), InvalidKeyboard> {
+        let found = self
+            .get_layouts()
+            .iter()
+            .find(|l| l.get_name() == layout)
+            .ok_or_else(|| InvalidKeyboard::Layout(layout.into()))?;
+
+        if let Some(model) = model {
+            if !self.get_models().iter().any(|m| m.get_name() == model) {
+                return Err(InvalidKeyboard::Model(model.into()));
+            }
+        }
+
+        if let Some(variant) = variant {
+            let exists = found
+                .get_variants()
+                .map_or(false, |variants| variants.iter().any(|v| v.get_name() == variant));
+
+            if !exists {
+                return Err(InvalidKeyboard::Variant(layout.into(), variant.into()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sorts the layouts, and their variants, by their descriptions in the given language.
+    pub fn sort(&mut self, lang: &str) {
+        let collator = Collator::new(lang);
+        let layouts = &mut self.layout_list.layout;
+        with_language(lang, || {
+            for layout in layouts.iter_mut() {
+                let variants = layout.variant_list.as_mut().and_then(|x| x.variant.as_mut());
+                if let Some(variants) = variants {
+                    sort_by_display_name(variants, &collator, |v| {
+                        dgettext(XKB_DOMAIN, v.get_description())
+                    });
+                }
+            }
+
+            sort_by_display_name(layouts, &collator, |layout| {
+                dgettext(XKB_DOMAIN, layout.get_description())
+            });
+        });
+    }
+}
+
+/// A list of keyboard models.
+#[derive(Debug, Deserialize)]
+pub struct ModelList {
+    pub model: Vec<KeyboardModel>,
+}
+
+/// A keyboard model, such as `pc105`, which defines the physical keys of the keyboard.
+#[derive(Debug, Deserialize)]
+pub struct KeyboardModel {
+    #[serde(rename = "configItem")]
+    pub config_item: ConfigItem,
+}
+
+impl KeyboardModel {
+    /// Fetches the name of the keyboard model.
+    pub fn get_name(&self) -> &str { &self.config_item.name }
+
+    /// Fetches a description of the model.
+    pub fn get_description(&self) -> &str { &self.config_item.description }
+
+    /// Fetches a description of the model, translated into the given language.
+    pub fn get_description_translated(&self, lang: &str) -> String {
+        translate(XKB_DOMAIN, self.get_description(), lang)
+    }
 }
 
 /// A list of keyboard layouts.
@@ -39,6 +130,11 @@ impl KeyboardLayout {
     /// Fetches a description of the layout.
     pub fn get_description(&self) -> &str { &self.config_item.description }
 
+    /// Fetches a description of the layout, translated into the given language.
+    pub fn get_description_translated(&self, lang: &str) -> String {
+        translate(XKB_DOMAIN, self.get_description(), lang)
+    }
+
     /// Fetches a list of possible layout variants.
     pub fn get_variants(&self) -> Option<&Vec<KeyboardVariant>> {
         self.variant_list.as_ref().and_then(|x| x.variant.as_ref())
@@ -73,12 +169,108 @@ impl KeyboardVariant {
 
     /// A description of this variant of a keyboard layout.
     pub fn get_description(&self) -> &str { &self.config_item.description }
+
+    /// A description of this variant, translated into the given language.
+    pub fn get_description_translated(&self, lang: &str) -> String {
+        translate(XKB_DOMAIN, self.get_description(), lang)
+    }
+}
+
+/// A keyboard setting which does not exist in the XKB rules.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvalidKeyboard {
+    Layout(String),
+    Model(String),
+    /// The layout, and the variant which does not exist for it.
+    Variant(String, String),
 }
 
+impl fmt::Display for InvalidKeyboard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvalidKeyboard::Layout(ref layout) => {
+                write!(f, "keyboard layout is invalid: '{}'", layout)
+            }
+            InvalidKeyboard::Model(ref model) => {
+                write!(f, "keyboard model is invalid: '{}'", model)
+            }
+            InvalidKeyboard::Variant(ref layout, ref variant) => write!(
+                f,
+                "keyboard variant '{}' does not exist for the '{}' layout",
+                variant, layout
+            ),
+        }
+    }
+}
+
+impl Error for InvalidKeyboard {}
+
 const X11_BASE_RULES: &str = "/usr/share/X11/xkb/rules/base.xml";
 
+/// The gettext domain that contains translations of the layout descriptions.
+const XKB_DOMAIN: &str = "xkeyboard-config";
+
 /// Fetches a list of keyboard layouts from `/usr/share/X11/xkb/rules/base.xml`.
 pub fn get_keyboard_layouts() -> io::Result<KeyboardLayouts> {
     xml::from_reader(BufReader::new(misc::open(X11_BASE_RULES)?))
         .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, format!("{}", why)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<xkbConfigRegistry version="1.1">
+  <modelList>
+    <model>
+      <configItem>
+        <name>pc105</name>
+        <description>Generic 105-key PC</description>
+      </configItem>
+    </model>
+  </modelList>
+  <layoutList>
+    <layout>
+      <configItem>
+        <name>us</name>
+        <description>English (US)</description>
+      </configItem>
+      <variantList>
+        <variant>
+          <configItem>
+            <name>dvorak</name>
+            <description>English (Dvorak)</description>
+          </configItem>
+        </variant>
+      </variantList>
+    </layout>
+    <layout>
+      <configItem>
+        <name>de</name>
+        <description>German</description>
+      </configItem>
+    </layout>
+  </layoutList>
+</xkbConfigRegistry>"#;
+
+    #[test]
+    fn validate() {
+        let layouts: KeyboardLayouts = xml::from_str(RULES).unwrap();
+        assert_eq!(layouts.get_models().len(), 1);
+        assert_eq!(layouts.validate("us", None, None), Ok(()));
+        assert_eq!(layouts.validate("us", Some("pc105"), Some("dvorak")), Ok(()));
+        assert_eq!(
+            layouts.validate("xx", None, None),
+            Err(InvalidKeyboard::Layout("xx".into()))
+        );
+        assert_eq!(
+            layouts.validate("us", Some("pc999"), None),
+            Err(InvalidKeyboard::Model("pc999".into()))
+        );
+        assert_eq!(
+            layouts.validate("de", None, Some("dvorak")),
+            Err(InvalidKeyboard::Variant("de".into(), "dvorak".into()))
+        );
+    }
+}
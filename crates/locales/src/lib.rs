@@ -3,12 +3,16 @@
 
 extern crate distinst_utils as misc;
 extern crate gettextrs;
+extern crate libc;
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
+extern crate log;
+#[macro_use]
 extern crate serde_derive;
 extern crate serde_xml_rs;
 
+mod collation;
 mod i18n;
 mod iso3166_1;
 mod iso639;
@@ -16,4 +20,4 @@ mod iso_codes;
 mod keyboard_layout;
 mod main_countries;
 
-pub use self::{i18n::*, iso_codes::*, keyboard_layout::*, main_countries::*};
+pub use self::{collation::*, i18n::*, iso_codes::*, keyboard_layout::*, main_countries::*};
@@ -0,0 +1,172 @@
+//! Detects FreeBSD, OpenBSD, and NetBSD installs.
+//!
+//! BSD file systems are not recognized by libparted, so the partition type recorded by udev
+//! and the UFS superblock are checked to find them. UFS partitions are mounted read-only to
+//! determine the release that is installed. ZFS pools cannot be mounted, so only the name of
+//! the OS is reported for them.
+
+use super::OS;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use sys_mount::*;
+use tempdir::TempDir;
+
+/// GPT partition type GUIDs, and MBR partition types, that are used by each BSD.
+const PARTITION_TYPES: &[(&str, &[&str])] = &[
+    ("FreeBSD", &[
+        "0xa5",
+        "516e7cb4-6ecf-11d6-8ff8-00022d09712b",
+        "516e7cb6-6ecf-11d6-8ff8-00022d09712b",
+        "516e7cb8-6ecf-11d6-8ff8-00022d09712b",
+        "516e7cba-6ecf-11d6-8ff8-00022d09712b",
+    ]),
+    ("OpenBSD", &["0xa6", "824cc7a0-36a8-11e3-890a-952519ad3f61"]),
+    ("NetBSD", &[
+        "0xa9",
+        "49f48d5a-b10e-11dc-b99b-0019d1879648",
+        "49f48d82-b10e-11dc-b99b-0019d1879648",
+        "2db519c4-b10f-11dc-b99b-0019d1879648",
+        "2db519ec-b10f-11dc-b99b-0019d1879648",
+    ]),
+];
+
+/// The offset of the magic number within a UFS superblock.
+const UFS_MAGIC_OFFSET: u64 = 1372;
+
+/// The location of the superblock, its magic number, and the `ufstype` mount option for each
+/// version of UFS.
+const UFS_SUPERBLOCKS: &[(u64, u32, &str)] =
+    &[(65536, 0x1954_0119, "ufstype=ufs2"), (8192, 0x0001_1954, "ufstype=44bsd")];
+
+/// Detect if a BSD is installed on a device which does not have a file system that is
+/// supported by the installer.
+pub fn detect_bsd_from_device(device: &Path) -> Option<OS> {
+    let partition_os = partition_type(device).and_then(|kind| bsd_from_partition_type(&kind));
+
+    let mounted_os = ufs_type(device).and_then(|ufstype| {
+        info!("detecting BSD from UFS device: {:?}", device);
+        TempDir::new("distinst").ok().and_then(|tempdir| {
+            let base = tempdir.path();
+            Mount::new(device, base, "ufs", MountFlags::RDONLY, Some(ufstype))
+                .map(|m| m.into_unmount_drop(UnmountFlags::DETACH))
+                .map_err(|why| warn!("unable to mount UFS device {:?}: {}", device, why))
+                .ok()
+                .and_then(|_mount| detect_bsd(base))
+        })
+    });
+
+    mounted_os.or_else(|| partition_os.map(|name| OS::Bsd { name: name.into(), version: None }))
+}
+
+/// Detect if a BSD is installed at the given path.
+pub fn detect_bsd(base: &Path) -> Option<OS> {
+    if let Ok(script) = fs::read_to_string(base.join("bin/freebsd-version")) {
+        return Some(OS::Bsd { name: "FreeBSD".into(), version: freebsd_version(&script) });
+    }
+
+    if base.join("bsd").exists() {
+        let version = first_line(base.join("etc/motd")).and_then(|line| release(&line, "OpenBSD"));
+        return Some(OS::Bsd { name: "OpenBSD".into(), version });
+    }
+
+    if base.join("netbsd").exists() {
+        let version =
+            first_line(base.join("etc/release")).and_then(|line| release(&line, "NetBSD"));
+        return Some(OS::Bsd { name: "NetBSD".into(), version });
+    }
+
+    None
+}
+
+fn bsd_from_partition_type(kind: &str) -> Option<&'static str> {
+    PARTITION_TYPES
+        .iter()
+        .find(|(_, types)| types.iter().any(|t| t.eq_ignore_ascii_case(kind)))
+        .map(|&(name, _)| name)
+}
+
+/// Reads the `USERLAND_VERSION` from the `freebsd-version` script.
+fn freebsd_version(script: &str) -> Option<String> {
+    script
+        .lines()
+        .find(|line| line.starts_with("USERLAND_VERSION="))
+        .map(|line| line[17..].trim_matches('"').to_owned())
+        .filter(|version| !version.is_empty())
+}
+
+/// Obtains the version that follows the name of the OS, such as `7.4` in `OpenBSD 7.4 (GENERIC)`.
+fn release(line: &str, name: &str) -> Option<String> {
+    let mut fields = line.split_whitespace();
+    match (fields.next(), fields.next()) {
+        (Some(os), Some(version)) if os == name => Some(version.to_owned()),
+        _ => None,
+    }
+}
+
+fn first_line<P: AsRef<Path>>(path: P) -> Option<String> {
+    File::open(path).ok().and_then(|file| BufReader::new(file).lines().next()).and_then(Result::ok)
+}
+
+/// Obtains the partition type that udev recorded for the device.
+fn partition_type(device: &Path) -> Option<String> {
+    let rdev = fs::metadata(device).ok()?.rdev();
+    let (major, minor) = unsafe { (libc::major(rdev), libc::minor(rdev)) };
+
+    let data = fs::read_to_string(format!("/run/udev/data/b{}:{}", major, minor)).ok()?;
+    udev_property(&data, "ID_PART_ENTRY_TYPE").map(String::from)
+}
+
+fn udev_property<'a>(data: &'a str, key: &str) -> Option<&'a str> {
+    data.lines()
+        .filter_map(|line| line.get(..2).filter(|&prefix| prefix == "E:").map(|_| &line[2..]))
+        .find(|line| line.starts_with(key) && line[key.len()..].starts_with('='))
+        .map(|line| &line[key.len() + 1..])
+}
+
+/// Checks for a UFS superblock on the device, returning the `ufstype` to mount it with.
+fn ufs_type(device: &Path) -> Option<&'static str> {
+    let mut file = File::open(device).ok()?;
+    UFS_SUPERBLOCKS
+        .iter()
+        .find(|&&(superblock, magic, _)| {
+            read_u32(&mut file, superblock + UFS_MAGIC_OFFSET).ok() == Some(magic)
+        })
+        .map(|&(_, _, ufstype)| ufstype)
+}
+
+fn read_u32(file: &mut File, offset: u64) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        let script = "#!/bin/sh\nKERNEL_VERSION=\"13.2-RELEASE\"\n\
+                      USERLAND_VERSION=\"13.2-RELEASE-p4\"\n";
+        assert_eq!(freebsd_version(script), Some("13.2-RELEASE-p4".into()));
+
+        let motd = "OpenBSD 7.4 (GENERIC.MP) #1397: Tue Oct 10 09:02:37 MDT 2023";
+        assert_eq!(release(motd, "OpenBSD"), Some("7.4".into()));
+        assert_eq!(release("NetBSD 9.3 (GENERIC) #0", "NetBSD"), Some("9.3".into()));
+        assert_eq!(release("Welcome to OpenBSD", "OpenBSD"), None);
+    }
+
+    #[test]
+    fn partition_types() {
+        let data = "S:disk/by-partuuid/abc\nE:ID_PART_ENTRY_NUMBER=2\n\
+                    E:ID_PART_ENTRY_TYPE=516E7CBA-6ECF-11D6-8FF8-00022D09712B\n";
+        let kind = udev_property(data, "ID_PART_ENTRY_TYPE").unwrap();
+        assert_eq!(bsd_from_partition_type(kind), Some("FreeBSD"));
+        assert_eq!(bsd_from_partition_type("0xa9"), Some("NetBSD"));
+        assert_eq!(bsd_from_partition_type("0x83"), None);
+        assert_eq!(udev_property(data, "ID_PART_ENTRY"), None);
+    }
+}
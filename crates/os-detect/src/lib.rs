@@ -15,6 +15,7 @@
 //! }
 //! ```
 
+extern crate libc;
 #[macro_use]
 extern crate log;
 extern crate os_release;
@@ -22,6 +23,11 @@ extern crate partition_identity;
 extern crate sys_mount;
 extern crate tempdir;
 
+mod bsd;
+mod windows;
+
+pub use bsd::{detect_bsd, detect_bsd_from_device};
+
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
 use std::path::Path;
@@ -40,7 +46,11 @@ pub enum OS {
         partitions: Vec<PartitionID>,
         targets: Vec<PathBuf>,
     },
-    MacOs(String)
+    MacOs(String),
+    Bsd {
+        name: String,
+        version: Option<String>,
+    },
 }
 
 /// Mounts the partition to a temporary directory and checks for the existence of an
@@ -75,6 +85,7 @@ pub fn detect_os_from_path(base: &Path) -> Option<OS> {
     detect_linux(base)
         .or_else(|| detect_windows(base))
         .or_else(|| detect_macos(base))
+        .or_else(|| detect_bsd(base))
 }
 
 /// Detect if Linux is installed at the given path.
@@ -103,11 +114,12 @@ pub fn detect_macos(base: &Path) -> Option<OS> {
 }
 
 /// Detect if Windows is installed at the given path.
+///
+/// The edition and version, such as `Windows 11 Pro 23H2`, are reported when they can be found.
 pub fn detect_windows(base: &Path) -> Option<OS> {
-    // TODO: More advanced version-specific detection is possible.
     base.join("Windows/System32/ntoskrnl.exe")
         .exists()
-        .map(|| OS::Windows("Windows".into()))
+        .map(|| OS::Windows(windows::describe(base).unwrap_or_else(|| "Windows".into())))
 }
 
 fn find_linux_parts(base: &Path) -> (Vec<PartitionID>, Vec<PathBuf>) {
@@ -0,0 +1,434 @@
+//! Detects the edition and version of a Windows install.
+//!
+//! The description is read from the `CurrentVersion` key of the `SOFTWARE` registry hive. If
+//! the hive cannot be read, the version resource of the NT kernel is used as a fallback, which
+//! can only distinguish between major releases of Windows.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// The path of the `CurrentVersion` key, relative to the root of the `SOFTWARE` hive.
+const CURRENT_VERSION: &[&str] = &["Microsoft", "Windows NT", "CurrentVersion"];
+
+/// Cell offsets in a hive are relative to the first hive bin, which follows the header.
+const HIVE_BINS_START: u64 = 0x1000;
+
+/// Cells larger than this are not expected in the keys that are read.
+const MAX_CELL_SIZE: usize = 1024 * 1024;
+
+/// The first build of Windows 11, which still identifies itself as Windows 10 in the registry.
+const WINDOWS_11_BUILD: u32 = 22000;
+
+/// Signature of the `VS_FIXEDFILEINFO` structure within a PE version resource.
+const FIXED_FILE_INFO: [u8; 4] = [0xBD, 0x04, 0xEF, 0xFE];
+
+/// Describes the Windows install at `base`, such as `Windows 11 Pro 23H2`.
+pub fn describe(base: &Path) -> Option<String> {
+    let system32 = base.join("Windows/System32");
+
+    let registry = File::open(system32.join("config/SOFTWARE"))
+        .and_then(|file| current_version(BufReader::new(file)));
+
+    match registry {
+        Ok(ref values) => match describe_registry(values) {
+            Some(description) => return Some(description),
+            None => warn!("Windows registry does not contain a product name"),
+        },
+        Err(why) => warn!("unable to read the Windows registry: {}", why),
+    }
+
+    fs::read(system32.join("ntoskrnl.exe"))
+        .ok()
+        .and_then(|kernel| kernel_version(&kernel))
+        .map(describe_kernel)
+}
+
+/// Builds a description from the values of the `CurrentVersion` registry key.
+fn describe_registry(values: &HashMap<String, String>) -> Option<String> {
+    let mut description = values.get("ProductName")?.trim().to_owned();
+
+    let build = values.get("CurrentBuildNumber").and_then(|build| build.parse::<u32>().ok());
+    if build.map_or(false, |build| build >= WINDOWS_11_BUILD)
+        && description.starts_with("Windows 10")
+    {
+        description = description.replacen("Windows 10", "Windows 11", 1);
+    }
+
+    if let Some(release) = values.get("DisplayVersion").or_else(|| values.get("ReleaseId")) {
+        description.push(' ');
+        description.push_str(release.trim());
+    }
+
+    Some(description)
+}
+
+/// Builds a description from the major, minor, and build numbers of the kernel.
+fn describe_kernel((major, minor, build): (u32, u32, u32)) -> String {
+    match (major, minor) {
+        (10, 0) if build >= WINDOWS_11_BUILD => format!("Windows 11 (build {})", build),
+        (10, 0) => format!("Windows 10 (build {})", build),
+        (6, 3) => "Windows 8.1".into(),
+        (6, 2) => "Windows 8".into(),
+        (6, 1) => "Windows 7".into(),
+        (6, 0) => "Windows Vista".into(),
+        _ => "Windows".into(),
+    }
+}
+
+/// Finds the file version in the version resource of a PE image.
+fn kernel_version(image: &[u8]) -> Option<(u32, u32, u32)> {
+    let pos = image.windows(4).position(|window| window == FIXED_FILE_INFO)?;
+    let most = read_u32(image, pos + 8).ok()?;
+    let least = read_u32(image, pos + 12).ok()?;
+    Some((most >> 16, most & 0xFFFF, least >> 16))
+}
+
+/// Reads the string values of the `CurrentVersion` key from a `SOFTWARE` registry hive.
+fn current_version<R: Read + Seek>(reader: R) -> io::Result<HashMap<String, String>> {
+    let mut hive = Hive::new(reader)?;
+    let mut key = hive.root()?;
+
+    for name in CURRENT_VERSION {
+        let offset = hive.subkey(&key, name)?.ok_or_else(|| {
+            invalid_data(format!("registry key {} was not found", CURRENT_VERSION.join("\\")))
+        })?;
+
+        key = hive.cell(offset)?;
+    }
+
+    hive.string_values(&key)
+}
+
+/// A minimal reader of Windows registry hives, which is only capable of finding keys by name
+/// and reading their string values.
+struct Hive<R> {
+    reader: R,
+    root:   u32,
+}
+
+impl<R: Read + Seek> Hive<R> {
+    fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 0x28];
+        reader.read_exact(&mut header)?;
+
+        if &header[..4] != b"regf" {
+            return Err(invalid_data("file is not a registry hive".into()));
+        }
+
+        let root = read_u32(&header, 0x24)?;
+        Ok(Hive { reader, root })
+    }
+
+    /// The key node at the root of the hive.
+    fn root(&mut self) -> io::Result<Vec<u8>> {
+        let root = self.root;
+        self.cell(root)
+    }
+
+    /// Reads the data of the cell at the given offset.
+    fn cell(&mut self, offset: u32) -> io::Result<Vec<u8>> {
+        self.reader.seek(SeekFrom::Start(HIVE_BINS_START + u64::from(offset)))?;
+
+        let mut size = [0; 4];
+        self.reader.read_exact(&mut size)?;
+
+        // Allocated cells have a negative size, which includes the size field itself.
+        let size = (i32::from_le_bytes(size).unsigned_abs() as usize)
+            .checked_sub(4)
+            .filter(|&size| size <= MAX_CELL_SIZE)
+            .ok_or_else(|| invalid_data(format!("invalid cell size at offset {}", offset)))?;
+
+        let mut data = vec![0; size];
+        self.reader.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Finds the offset of a subkey of the key node with the given name.
+    fn subkey(&mut self, key: &[u8], name: &str) -> io::Result<Option<u32>> {
+        expect_signature(key, b"nk")?;
+        if read_u32(key, 0x14)? == 0 {
+            return Ok(None);
+        }
+
+        let mut offsets = Vec::new();
+        self.subkey_offsets(read_u32(key, 0x1C)?, &mut offsets, true)?;
+
+        for offset in offsets {
+            let subkey = self.cell(offset)?;
+            expect_signature(&subkey, b"nk")?;
+
+            let length = read_u16(&subkey, 0x48)? as usize;
+            let raw = subkey
+                .get(0x4C..0x4C + length)
+                .ok_or_else(|| invalid_data("key name exceeds its cell".into()))?;
+
+            // Names of keys and values are compared case-insensitively by Windows.
+            let compressed = read_u16(&subkey, 0x02)? & 0x20 != 0;
+            if decode_name(raw, compressed).eq_ignore_ascii_case(name) {
+                return Ok(Some(offset));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Collects the offsets of subkeys from a subkey list, which may be an index of lists if
+    /// `index` is set. The lists of an index may not be indexes themselves, so a corrupt hive
+    /// cannot recurse without end.
+    fn subkey_offsets(&mut self, list: u32, offsets: &mut Vec<u32>, index: bool) -> io::Result<()> {
+        let cell = self.cell(list)?;
+        let count = read_u16(&cell, 0x02)? as usize;
+
+        match cell.get(..2) {
+            Some(b"lf") | Some(b"lh") => {
+                for id in 0..count {
+                    offsets.push(read_u32(&cell, 0x04 + id * 8)?);
+                }
+            }
+            Some(b"li") => {
+                for id in 0..count {
+                    offsets.push(read_u32(&cell, 0x04 + id * 4)?);
+                }
+            }
+            Some(b"ri") if index => {
+                for id in 0..count {
+                    let list = read_u32(&cell, 0x04 + id * 4)?;
+                    self.subkey_offsets(list, offsets, false)?;
+                }
+            }
+            Some(b"ri") => return Err(invalid_data("nested index of subkey lists".into())),
+            _ => return Err(invalid_data("unknown subkey list type".into())),
+        }
+
+        Ok(())
+    }
+
+    /// Reads all of the `REG_SZ` and `REG_EXPAND_SZ` values of the key node.
+    fn string_values(&mut self, key: &[u8]) -> io::Result<HashMap<String, String>> {
+        const REG_SZ: u32 = 1;
+        const REG_EXPAND_SZ: u32 = 2;
+
+        expect_signature(key, b"nk")?;
+
+        let mut values = HashMap::new();
+        let count = read_u32(key, 0x24)? as usize;
+        if count == 0 {
+            return Ok(values);
+        }
+
+        let list = self.cell(read_u32(key, 0x28)?)?;
+        for id in 0..count {
+            let value = self.cell(read_u32(&list, id * 4)?)?;
+            expect_signature(&value, b"vk")?;
+
+            let kind = read_u32(&value, 0x0C)?;
+            if kind != REG_SZ && kind != REG_EXPAND_SZ {
+                continue;
+            }
+
+            let length = read_u16(&value, 0x02)? as usize;
+            let raw = value
+                .get(0x14..0x14 + length)
+                .ok_or_else(|| invalid_data("value name exceeds its cell".into()))?;
+            let name = decode_name(raw, read_u16(&value, 0x10)? & 0x01 != 0);
+
+            // Data of four bytes or less is stored within the offset field.
+            let size = read_u32(&value, 0x04)?;
+            let data = if size & 0x8000_0000 != 0 {
+                let size = (size & 0x7FFF_FFFF).min(4) as usize;
+                value[0x08..0x08 + size].to_vec()
+            } else {
+                let mut data = self.cell(read_u32(&value, 0x08)?)?;
+                data.truncate(size as usize);
+                data
+            };
+
+            values.insert(name, decode_utf16(&data));
+        }
+
+        Ok(values)
+    }
+}
+
+fn decode_name(raw: &[u8], compressed: bool) -> String {
+    if compressed {
+        raw.iter().map(|&byte| byte as char).collect()
+    } else {
+        decode_utf16(raw)
+    }
+}
+
+fn decode_utf16(raw: &[u8]) -> String {
+    let units = raw
+        .chunks(2)
+        .filter(|unit| unit.len() == 2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect::<Vec<u16>>();
+
+    String::from_utf16_lossy(&units)
+}
+
+fn expect_signature(cell: &[u8], signature: &[u8]) -> io::Result<()> {
+    if cell.get(..2) == Some(signature) {
+        Ok(())
+    } else {
+        Err(invalid_data(format!(
+            "expected a {} cell in the registry hive",
+            String::from_utf8_lossy(signature)
+        )))
+    }
+}
+
+fn read_u16(data: &[u8], pos: usize) -> io::Result<u16> {
+    data.get(pos..pos + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| invalid_data("unexpected end of cell".into()))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> io::Result<u32> {
+    data.get(pos..pos + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| invalid_data("unexpected end of cell".into()))
+}
+
+fn invalid_data(why: String) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, why) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Builds a hive containing the given key path, where the last key holds the values.
+    fn hive(path: &[&str], values: &[(&str, &str)]) -> Vec<u8> {
+        let mut bins = Vec::new();
+
+        fn push_cell(bins: &mut Vec<u8>, data: &[u8]) -> u32 {
+            let offset = bins.len() as u32;
+            let size = (data.len() + 4 + 7) & !7;
+            bins.extend_from_slice(&(-(size as i32)).to_le_bytes());
+            bins.extend_from_slice(data);
+            bins.resize(offset as usize + size, 0);
+            offset
+        }
+
+        fn key(name: &str, subkey: Option<u32>, values: Option<(u32, u32)>) -> Vec<u8> {
+            let mut data = vec![0; 0x4C];
+            data[..2].copy_from_slice(b"nk");
+            data[0x02..0x04].copy_from_slice(&0x20u16.to_le_bytes());
+            if let Some(list) = subkey {
+                data[0x14..0x18].copy_from_slice(&1u32.to_le_bytes());
+                data[0x1C..0x20].copy_from_slice(&list.to_le_bytes());
+            }
+            if let Some((count, list)) = values {
+                data[0x24..0x28].copy_from_slice(&count.to_le_bytes());
+                data[0x28..0x2C].copy_from_slice(&list.to_le_bytes());
+            }
+            data[0x48..0x4A].copy_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(name.as_bytes());
+            data
+        }
+
+        let mut value_offsets = Vec::new();
+        for &(name, value) in values {
+            let encoded = value
+                .encode_utf16()
+                .chain(Some(0))
+                .flat_map(|unit| unit.to_le_bytes().to_vec())
+                .collect::<Vec<u8>>();
+            let size = encoded.len() as u32;
+            let data = push_cell(&mut bins, &encoded);
+
+            let mut vk = vec![0; 0x14];
+            vk[..2].copy_from_slice(b"vk");
+            vk[0x02..0x04].copy_from_slice(&(name.len() as u16).to_le_bytes());
+            vk[0x04..0x08].copy_from_slice(&size.to_le_bytes());
+            vk[0x08..0x0C].copy_from_slice(&data.to_le_bytes());
+            vk[0x0C..0x10].copy_from_slice(&1u32.to_le_bytes());
+            vk[0x10..0x12].copy_from_slice(&1u16.to_le_bytes());
+            vk.extend_from_slice(name.as_bytes());
+            value_offsets.push(push_cell(&mut bins, &vk));
+        }
+
+        let list = value_offsets.iter().flat_map(|o| o.to_le_bytes().to_vec()).collect::<Vec<_>>();
+        let list = push_cell(&mut bins, &list);
+        let mut child = push_cell(
+            &mut bins,
+            &key(path[path.len() - 1], None, Some((values.len() as u32, list))),
+        );
+
+        for name in path[..path.len() - 1].iter().rev().chain(Some(&"ROOT")) {
+            let mut lf = b"lf".to_vec();
+            lf.extend_from_slice(&1u16.to_le_bytes());
+            lf.extend_from_slice(&child.to_le_bytes());
+            lf.extend_from_slice(&[0; 4]);
+            let lf = push_cell(&mut bins, &lf);
+            child = push_cell(&mut bins, &key(name, Some(lf), None));
+        }
+
+        let mut data = vec![0; HIVE_BINS_START as usize];
+        data[..4].copy_from_slice(b"regf");
+        data[0x24..0x28].copy_from_slice(&child.to_le_bytes());
+        data.extend_from_slice(&bins);
+        data
+    }
+
+    #[test]
+    fn registry_description() {
+        let data = hive(
+            &["Microsoft", "Windows NT", "CurrentVersion"],
+            &[
+                ("ProductName", "Windows 10 Pro"),
+                ("CurrentBuildNumber", "22631"),
+                ("DisplayVersion", "23H2"),
+            ],
+        );
+
+        let values = current_version(Cursor::new(data)).unwrap();
+        assert_eq!(values["CurrentBuildNumber"], "22631");
+        assert_eq!(describe_registry(&values), Some("Windows 11 Pro 23H2".into()));
+
+        let data = hive(
+            &["Microsoft", "Windows NT", "CurrentVersion"],
+            &[("ProductName", "Windows 10 Home"), ("ReleaseId", "1909")],
+        );
+
+        let values = current_version(Cursor::new(data)).unwrap();
+        assert_eq!(describe_registry(&values), Some("Windows 10 Home 1909".into()));
+
+        let data = hive(&["Microsoft", "Windows"], &[("ProductName", "Windows 10 Pro")]);
+        assert!(current_version(Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn recursive_subkey_index() {
+        // An index of lists which lists itself.
+        let mut data = vec![0; HIVE_BINS_START as usize];
+        data[..4].copy_from_slice(b"regf");
+        data.extend_from_slice(&(-16i32).to_le_bytes());
+        data.extend_from_slice(b"ri");
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+
+        let mut hive = Hive::new(Cursor::new(data)).unwrap();
+        let error = hive.subkey_offsets(0, &mut Vec::new(), true).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn kernel_description() {
+        let mut image = vec![0u8; 64];
+        image.extend_from_slice(&FIXED_FILE_INFO);
+        image.extend_from_slice(&0x0001_0000u32.to_le_bytes());
+        image.extend_from_slice(&0x000A_0000u32.to_le_bytes());
+        image.extend_from_slice(&0x5867_0AE6u32.to_le_bytes());
+
+        let version = kernel_version(&image).unwrap();
+        assert_eq!(version, (10, 0, 22631));
+        assert_eq!(describe_kernel(version), "Windows 11 (build 22631)");
+        assert_eq!(describe_kernel((6, 1, 7601)), "Windows 7");
+    }
+}
@@ -0,0 +1,328 @@
+//! Fetches remote images over HTTP(S), so that they may be extracted as if they were local.
+//!
+//! A SHA256 checksum must be supplied in the fragment of the URL, such as
+//! `https://example.com/filesystem.squashfs#sha256=<hex digest>`. Partial downloads are
+//! resumed when the destination file already exists, so long as the server confirms through
+//! `If-Range` that the remote file has not changed since the download began.
+
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Error, ErrorKind, Read, Result, Write},
+    path::{Path, PathBuf},
+};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Checks if the image source refers to a remote location.
+pub fn is_remote(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// A remote image, and the checksum that it is expected to have.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteImage<'a> {
+    pub url:    &'a str,
+    pub sha256: &'a str,
+}
+
+impl<'a> RemoteImage<'a> {
+    /// Parses the URL of a remote image, separating the checksum from the fragment.
+    ///
+    /// A URL without a checksum is rejected, as the downloaded image could not be verified.
+    pub fn parse(source: &'a str) -> Result<Self> {
+        if !is_remote(source) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not an HTTP(S) URL", source),
+            ));
+        }
+
+        let (url, fragment) = match source.find('#') {
+            Some(pos) => (&source[..pos], Some(&source[pos + 1..])),
+            None => (source, None),
+        };
+
+        let sha256 = match fragment {
+            Some(fragment) if fragment.starts_with("sha256=") => {
+                let digest = &fragment[7..];
+                if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("invalid sha256 checksum in URL: {}", digest),
+                    ));
+                }
+
+                digest
+            }
+            Some(fragment) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unsupported URL fragment: {}", fragment),
+                ));
+            }
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}: a `#sha256=<digest>` checksum is required", source),
+                ));
+            }
+        };
+
+        Ok(RemoteImage { url, sha256 })
+    }
+
+    /// The name of the file that the URL points to.
+    pub fn file_name(&self) -> &'a str {
+        let path = self.url.split('?').next().unwrap_or(self.url);
+        path.rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or("image")
+    }
+
+    /// Downloads the image into the `directory`, returning the path to the downloaded file. As
+    /// images may be several gigabytes, the directory should not be on a tmpfs.
+    ///
+    /// If a previous download was interrupted, the download will resume from where it left off.
+    /// The callback receives the number of bytes downloaded, and the total length if known.
+    pub fn fetch<F: FnMut(u64, Option<u64>)>(
+        &self,
+        directory: &Path,
+        mut callback: F,
+    ) -> Result<PathBuf> {
+        fs::create_dir_all(directory)?;
+        let path = directory.join(self.file_name());
+        let validator_path = directory.join([self.file_name(), ".validator"].concat());
+
+        // A partial download may only be resumed if it can be validated against the remote file.
+        let validator = fs::read_to_string(&validator_path).ok().filter(|v| !v.is_empty());
+        let existing = match validator {
+            Some(_) => fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0),
+            None => 0,
+        };
+
+        let mut request = ureq::get(self.url);
+        if let (Some(validator), true) = (validator.as_ref(), existing != 0) {
+            info!("resuming download of {} from byte {}", self.url, existing);
+            request.set("Range", &format!("bytes={}-", existing));
+            request.set("If-Range", validator);
+        }
+
+        let response = request.call();
+        if let Some(why) = response.synthetic_error() {
+            return Err(Error::new(ErrorKind::Other, format!("{}: {}", self.url, why)));
+        }
+
+        let (mut file, mut read) = match response.status() {
+            206 if existing != 0 => (OpenOptions::new().append(true).open(&path)?, existing),
+            // The partial file is longer than the remote file, so it can't be a prefix of it.
+            416 if existing != 0 => {
+                warn!("{}: partial download is invalid, so restarting it", self.url);
+                fs::remove_file(&path)?;
+                fs::remove_file(&validator_path)?;
+                return self.fetch(directory, callback);
+            }
+            200 => {
+                // The remote file is sent in full when it has changed since the download began.
+                match validator_of(&response) {
+                    Some(validator) => fs::write(&validator_path, validator)?,
+                    None => {
+                        let _ = fs::remove_file(&validator_path);
+                    }
+                }
+
+                (File::create(&path)?, 0)
+            }
+            status => {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("{}: server responded with {}", self.url, status),
+                ));
+            }
+        };
+
+        let total = response
+            .header("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok())
+            .map(|length| length + read);
+
+        let mut reader = response.into_reader();
+        let mut buffer = vec![0; BUFFER_SIZE];
+        loop {
+            let count = reader.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+
+            file.write_all(&buffer[..count])?;
+            read += count as u64;
+            callback(read, total);
+        }
+
+        file.sync_all()?;
+
+        if let Some(total) = total {
+            if read != total {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("{}: received {} of {} bytes", self.url, read, total),
+                ));
+            }
+        }
+
+        info!("verifying the checksum of {}", path.display());
+        let actual = sha256_file(&path)?;
+        let _ = fs::remove_file(&validator_path);
+        if !actual.eq_ignore_ascii_case(self.sha256) {
+            // A corrupted download cannot be resumed, so start from scratch next time.
+            let _ = fs::remove_file(&path);
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "checksum mismatch for {}: expected {}, found {}",
+                    self.url, self.sha256, actual
+                ),
+            ));
+        }
+
+        Ok(path)
+    }
+}
+
+/// The strong validator of the response, which may be sent back in an `If-Range` header.
+fn validator_of(response: &ureq::Response) -> Option<String> {
+    response
+        .header("ETag")
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| response.header("Last-Modified"))
+        .map(String::from)
+}
+
+/// Calculates the SHA256 digest of a file, as a lowercase hex string.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let count = file.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..count]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        thread,
+    };
+    use tempdir::TempDir;
+
+    const DIGEST: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn parse_url() {
+        let source = ["https://example.com/casper/filesystem.squashfs#sha256=", DIGEST].concat();
+        let image = RemoteImage::parse(&source).unwrap();
+        assert_eq!(image.url, "https://example.com/casper/filesystem.squashfs");
+        assert_eq!(image.sha256, DIGEST);
+        assert_eq!(image.file_name(), "filesystem.squashfs");
+
+        let source = ["http://example.com/rootfs.tar?version=2#sha256=", DIGEST].concat();
+        let image = RemoteImage::parse(&source).unwrap();
+        assert_eq!(image.url, "http://example.com/rootfs.tar?version=2");
+        assert_eq!(image.file_name(), "rootfs.tar");
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(RemoteImage::parse("/cdrom/casper/filesystem.squashfs").is_err());
+        assert!(RemoteImage::parse("https://example.com/fs.squashfs").is_err());
+        assert!(RemoteImage::parse("https://example.com/fs.squashfs#sha256=abc").is_err());
+        assert!(RemoteImage::parse("https://example.com/fs.squashfs#md5=abc").is_err());
+    }
+
+    #[test]
+    fn empty_digest() {
+        let dir = TempDir::new("distinst-squashfs-fetch").unwrap();
+        let path = dir.path().join("empty");
+        File::create(&path).unwrap();
+        assert_eq!(sha256_file(&path).unwrap(), DIGEST);
+    }
+
+    /// Answers one connection with each of the `responses`, returning the URL of the server and
+    /// the headers of the requests that it received.
+    fn serve(responses: Vec<Vec<u8>>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/filesystem.squashfs", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|response| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request = String::new();
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                            break;
+                        }
+
+                        request.push_str(&line);
+                    }
+
+                    stream.write_all(&response).unwrap();
+                    request
+                })
+                .collect()
+        });
+
+        (url, server)
+    }
+
+    #[test]
+    fn resume_interrupted() {
+        let data = (0..=255u8).cycle().take(256 * 1024).collect::<Vec<u8>>();
+        let digest = hex::encode(Sha256::digest(&data));
+        let half = data.len() / 2;
+
+        // The connection is closed after half of the image has been sent.
+        let mut interrupted = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n",
+            data.len()
+        )
+        .into_bytes();
+        interrupted.extend_from_slice(&data[..half]);
+
+        let mut resumed = format!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes \
+             {}-{}/{}\r\nConnection: close\r\n\r\n",
+            data.len() - half,
+            half,
+            data.len() - 1,
+            data.len()
+        )
+        .into_bytes();
+        resumed.extend_from_slice(&data[half..]);
+
+        let (url, server) = serve(vec![interrupted, resumed]);
+        let source = [url.as_str(), "#sha256=", &digest].concat();
+        let image = RemoteImage::parse(&source).unwrap();
+        let dir = TempDir::new("distinst-squashfs-fetch").unwrap();
+
+        assert!(image.fetch(dir.path(), |_, _| ()).is_err());
+        let path = image.fetch(dir.path(), |_, _| ()).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert!(!dir.path().join("filesystem.squashfs.validator").exists());
+
+        let requests = server.join().unwrap();
+        assert!(!requests[0].contains("Range"));
+        assert!(requests[1].contains(&format!("Range: bytes={}-", half)));
+        assert!(requests[1].contains("If-Range: \"v1\""));
+    }
+}
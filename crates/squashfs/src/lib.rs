@@ -1,6 +1,22 @@
+extern crate flate2;
+extern crate hex;
 extern crate libc;
 #[macro_use]
 extern crate log;
+extern crate sha2;
+extern crate tar;
+extern crate ureq;
+extern crate xz2;
+extern crate zstd;
+
+pub mod fetch;
+pub mod manifest;
+pub mod options;
+pub mod tarball;
+pub mod unsquash;
+pub mod verify;
+
+pub use self::options::{Backend, ExtractOptions, IoPriority};
 
 use std::{
     fs::File,
@@ -125,21 +141,46 @@ enum ExtractFormat {
     Squashfs,
 }
 
-/// Extracts an image using either unsquashfs or tar.
+/// Extracts an image using either unsquashfs or tar, with options which are tuned to the
+/// machine.
+///
+/// Compressed tarballs (`.tar.gz`, `.tar.xz`, and `.tar.zst`) are decompressed in-process.
 pub fn extract<P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(i32)>(
     archive: P,
     directory: Q,
     callback: F,
+) -> Result<()> {
+    extract_with(archive, directory, &ExtractOptions::default(), callback)
+}
+
+/// Extracts an image using either unsquashfs or tar, with the given options.
+///
+/// The progress bar of unsquashfs is kept, as the progress is parsed from it. Squashfs images
+/// are extracted in-process instead when unsquashfs is not installed, or when requested.
+pub fn extract_with<P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(i32)>(
+    archive: P,
+    directory: Q,
+    options: &ExtractOptions,
+    callback: F,
 ) -> Result<()> {
     let archive = archive.as_ref().canonicalize()?;
     let directory = directory.as_ref().canonicalize()?;
 
+    if let Some(compression) = tarball::Compression::detect(&archive) {
+        return tarball::extract(&archive, &directory, compression, callback);
+    }
+
+    let squashfs = archive.extension().map_or(false, |ext| ext == "squashfs");
+    if squashfs && options.backend.in_process() {
+        return unsquash::extract(&archive, &directory, callback);
+    }
+
     let directory = directory
         .to_str()
         .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid directory path"))?
         .replace("'", "'\"'\"'");
 
-    let format = if archive.extension().map_or(false, |ext| ext == "squashfs") {
+    let format = if squashfs {
         ExtractFormat::Squashfs
     } else {
         ExtractFormat::Tar
@@ -153,7 +194,7 @@ pub fn extract<P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(i32)>(
     let mut command = match format {
         ExtractFormat::Squashfs => {
             let mut command = Command::new("unsquashfs");
-            command.arg("-f").arg("-d").arg(directory).arg(archive);
+            command.arg("-f").args(options.unsquashfs_args()).arg("-d").arg(directory).arg(archive);
             command
         }
         ExtractFormat::Tar => {
@@ -165,6 +206,7 @@ pub fn extract<P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(i32)>(
 
     debug!("{:?}", command);
 
+    let io_priority = options.io_priority;
     let (master_fd, tty_path) = getpty(80, 30);
     let mut child = {
         let (slave_stdin, slave_stdout, slave_stderr) = slave_stdio(&tty_path)?;
@@ -177,7 +219,10 @@ pub fn extract<P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(i32)>(
                 .env("COLUMNS", "")
                 .env("LINES", "")
                 .env("TERM", "xterm-256color")
-                .pre_exec(before_exec)
+                .pre_exec(move || {
+                    before_exec()?;
+                    io_priority.map_or(Ok(()), IoPriority::apply)
+                })
                 .spawn()?
         }
     };
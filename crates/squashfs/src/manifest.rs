@@ -0,0 +1,187 @@
+//! Manifests of the digests of the files in an image, which the extracted tree is compared
+//! against, so that files which were corrupted by faulty memory or storage controllers while
+//! they were extracted are found before the system is booted.
+//!
+//! A manifest has a line for each regular file, with its SHA256 digest and its path relative
+//! to the root of the image, as written by `sha256sum`.
+//!
+//! Each extracted file is written back and dropped from the page cache before its digest is
+//! calculated, so that the file is read from the storage rather than from memory.
+
+use crate::fetch::sha256_file;
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{Error, ErrorKind, Result},
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+/// The digest of a regular file, by its path relative to the root of the image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub path:   PathBuf,
+    pub sha256: String,
+}
+
+/// A file of the extracted tree which does not match its entry in the manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+    Missing(PathBuf),
+    Differs { path: PathBuf, expected: String, actual: String },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Missing(path) => write!(f, "/{} is missing", path.display()),
+            Mismatch::Differs { path, expected, actual } => write!(
+                f,
+                "/{} has the digest {}, rather than {}",
+                path.display(),
+                actual,
+                expected
+            ),
+        }
+    }
+}
+
+/// Parses the entries of a manifest.
+pub fn parse(manifest: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (number, line) in manifest.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut fields = line.splitn(2, char::is_whitespace);
+        let (sha256, path) = match (fields.next(), fields.next()) {
+            (Some(sha256), Some(path))
+                if sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit()) =>
+            {
+                (sha256, path.trim_start().trim_start_matches('*'))
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {} of the manifest is not a digest and a path", number + 1),
+                ));
+            }
+        };
+
+        let path = path.trim_start_matches("./").trim_start_matches('/');
+        entries.push(Entry { path: PathBuf::from(path), sha256: sha256.to_lowercase() });
+    }
+
+    Ok(entries)
+}
+
+/// Generates the entries of each regular file within `root`, such as an image which has been
+/// mounted, reporting the progress as a percentage.
+pub fn generate<F: FnMut(i32)>(root: &Path, mut callback: F) -> Result<Vec<Entry>> {
+    let mut paths = Vec::new();
+    regular_files(root, Path::new(""), &mut paths)?;
+
+    let total = paths.len();
+    let mut entries = Vec::with_capacity(total);
+    for (hashed, path) in paths.into_iter().enumerate() {
+        let sha256 = sha256_file(&root.join(&path))?;
+        entries.push(Entry { path, sha256 });
+        callback(((hashed + 1) * 100 / total) as i32);
+    }
+
+    Ok(entries)
+}
+
+/// Compares the files of the extracted tree at `root` to the entries, reporting the progress
+/// as a percentage.
+pub fn verify<F: FnMut(i32)>(
+    root: &Path,
+    entries: &[Entry],
+    mut callback: F,
+) -> Result<Vec<Mismatch>> {
+    let mut mismatches = Vec::new();
+    for (verified, entry) in entries.iter().enumerate() {
+        let path = root.join(&entry.path);
+        match fs::symlink_metadata(&path) {
+            Ok(ref metadata) if metadata.is_file() => {
+                drop_cache(&path)?;
+                let actual = sha256_file(&path)?;
+                if actual != entry.sha256 {
+                    mismatches.push(Mismatch::Differs {
+                        path:     entry.path.clone(),
+                        expected: entry.sha256.clone(),
+                        actual,
+                    });
+                }
+            }
+            Ok(_) => mismatches.push(Mismatch::Missing(entry.path.clone())),
+            Err(ref why) if why.kind() == ErrorKind::NotFound => {
+                mismatches.push(Mismatch::Missing(entry.path.clone()))
+            }
+            Err(why) => return Err(why),
+        }
+
+        callback(((verified + 1) * 100 / entries.len()) as i32);
+    }
+
+    Ok(mismatches)
+}
+
+/// Writes the file at `path` to the storage, and then evicts its pages from the page cache.
+fn drop_cache(path: &Path) -> Result<()> {
+    let file = File::open(path)?;
+    file.sync_all()?;
+    match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) } {
+        0 => Ok(()),
+        errno => Err(Error::from_raw_os_error(errno)),
+    }
+}
+
+/// Collects the paths, relative to `root`, of the regular files within `dir`.
+fn regular_files(root: &Path, dir: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(root.join(dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            regular_files(root, &path, paths)?;
+        } else if file_type.is_file() {
+            paths.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    const EMPTY: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn manifests() {
+        let manifest = [EMPTY, "  ./etc/hostname\n\n", EMPTY, " */usr/bin/true\n"].concat();
+        let entries = parse(&manifest).unwrap();
+        assert_eq!(entries[0].path, Path::new("etc/hostname"));
+        assert_eq!(entries[1].path, Path::new("usr/bin/true"));
+        assert!(parse("not a manifest\n").is_err());
+
+        let tempdir = TempDir::new("distinst-squashfs-manifest").unwrap();
+        let root = tempdir.path();
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::write(root.join("etc/hostname"), "").unwrap();
+
+        let generated = generate(root, |_| ()).unwrap();
+        assert_eq!(generated, &entries[..1]);
+
+        let mismatches = verify(root, &entries, |_| ()).unwrap();
+        assert_eq!(mismatches, vec![Mismatch::Missing(PathBuf::from("usr/bin/true"))]);
+
+        fs::write(root.join("etc/hostname"), "pop-os\n").unwrap();
+        let mismatches = verify(root, &entries[..1], |_| ()).unwrap();
+        assert!(matches!(mismatches[0], Mismatch::Differs { .. }));
+    }
+}
@@ -0,0 +1,155 @@
+//! Options which tune the extraction of an image to the resources of the machine.
+//!
+//! By default, unsquashfs is given a decompression thread for each CPU, as far as the available
+//! memory allows, and queues which grow with the available memory, so that the reads of the
+//! image are large enough to keep every thread busy.
+
+use std::{
+    env, fs,
+    io::{Error, Result},
+    thread,
+};
+
+/// The memory which is reserved for each decompression thread, in MiB.
+const THREAD_MEMORY: u64 = 128;
+
+/// The bounds of the data and fragment queues of unsquashfs, in MiB. The queues take no more
+/// than an eighth of the available memory each, down to the lower bound, so that live sessions
+/// with little memory are not exhausted by them.
+const MIN_QUEUE: u64 = 8;
+const MAX_QUEUE: u64 = 1024;
+
+/// The size of each queue when the available memory is not known, in MiB.
+const DEFAULT_QUEUE: u64 = 64;
+
+/// The I/O scheduling class of the extraction, as set by `ionice`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum IoPriority {
+    /// Served before all other I/O, at a level from 0, the highest, to 7.
+    Realtime(u8),
+    /// Served fairly with other I/O, at a level from 0, the highest, to 7.
+    BestEffort(u8),
+    /// Served only when no other process has I/O pending.
+    Idle,
+}
+
+impl IoPriority {
+    /// Applies the priority to the calling process.
+    pub fn apply(self) -> Result<()> {
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+        let (class, level) = match self {
+            IoPriority::Realtime(level) => (1, level.min(7)),
+            IoPriority::BestEffort(level) => (2, level.min(7)),
+            IoPriority::Idle => (3, 0),
+        };
+
+        let priority = class << IOPRIO_CLASS_SHIFT | libc::c_int::from(level);
+        let result =
+            unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, priority) };
+        if result == -1 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// What extracts a squashfs image.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum Backend {
+    /// unsquashfs when it is installed, and otherwise the in-process extractor.
+    #[default]
+    Auto,
+    /// The `unsquashfs` command of squashfs-tools.
+    Unsquashfs,
+    /// The in-process extractor, which needs no external commands.
+    InProcess,
+}
+
+impl Backend {
+    /// Whether the image is extracted in-process.
+    pub fn in_process(self) -> bool {
+        match self {
+            Backend::Auto => !in_path("unsquashfs"),
+            Backend::Unsquashfs => false,
+            Backend::InProcess => true,
+        }
+    }
+}
+
+/// Options for the extraction of an image.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct ExtractOptions {
+    /// The number of threads which decompress a squashfs image, or `None` to select the number
+    /// from the CPUs and the available memory.
+    pub threads:     Option<usize>,
+    /// The I/O scheduling class of unsquashfs or tar, or `None` to inherit that of the caller.
+    pub io_priority: Option<IoPriority>,
+    /// What extracts a squashfs image.
+    pub backend:     Backend,
+}
+
+impl ExtractOptions {
+    /// The arguments which tune unsquashfs to the machine.
+    pub(crate) fn unsquashfs_args(&self) -> Vec<String> {
+        let cpus = thread::available_parallelism().map_or(1, |cpus| cpus.get());
+        let (threads, queue) = tune(cpus, available_memory());
+        let threads = self.threads.unwrap_or(threads).max(1);
+        info!("extracting with {} threads, and queues of {} MiB", threads, queue);
+
+        vec![
+            "-processors".into(),
+            threads.to_string(),
+            "-da".into(),
+            queue.to_string(),
+            "-fr".into(),
+            queue.to_string(),
+        ]
+    }
+}
+
+/// The number of decompression threads, and the size of each queue in MiB, for the number of
+/// `cpus` and the `available` memory in MiB.
+fn tune(cpus: usize, available: Option<u64>) -> (usize, u64) {
+    let available = match available {
+        Some(available) => available,
+        None => return (cpus, DEFAULT_QUEUE),
+    };
+
+    let threads = (available / THREAD_MEMORY).max(1).min(cpus as u64) as usize;
+    let queue = (available / 8).clamp(MIN_QUEUE, MAX_QUEUE);
+    (threads, queue)
+}
+
+/// Whether the `command` is found in a directory of the `PATH`.
+fn in_path(command: &str) -> bool {
+    env::var_os("PATH").map_or(false, |paths| {
+        env::split_paths(&paths).any(|path| path.join(command).is_file())
+    })
+}
+
+/// The memory which is available without swapping, in MiB.
+fn available_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map(|kib| kib / 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tuning() {
+        assert_eq!(tune(16, Some(32 * 1024)), (16, 1024));
+        assert_eq!(tune(16, Some(1024)), (8, 128));
+        assert_eq!(tune(4, Some(100)), (1, 12));
+        assert_eq!(tune(4, Some(32)), (1, 8));
+        assert_eq!(tune(2, None), (2, 64));
+    }
+}
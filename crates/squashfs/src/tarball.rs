@@ -0,0 +1,142 @@
+//! In-process extraction of compressed tarballs, with progress based on the bytes read.
+
+use flate2::read::MultiGzDecoder;
+use std::{
+    fs::File,
+    io::{BufReader, Read, Result},
+    path::Path,
+};
+use xz2::read::XzDecoder;
+
+/// The compression applied to a tarball.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// Determines the compression of a tarball from its file name.
+    pub fn detect(archive: &Path) -> Option<Compression> {
+        let name = archive.file_name()?.to_str()?;
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Compression::Gzip)
+        } else if name.ends_with(".tar.xz") || name.ends_with(".txz") {
+            Some(Compression::Xz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Reports the percentage of the compressed archive which has been read so far.
+struct ProgressReader<R, F> {
+    inner:    R,
+    read:     u64,
+    total:    u64,
+    last:     i32,
+    callback: F,
+}
+
+impl<R: Read, F: FnMut(i32)> Read for ProgressReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.read += count as u64;
+
+        if self.total != 0 {
+            let progress = (self.read * 100 / self.total) as i32;
+            if progress != self.last {
+                (self.callback)(progress);
+                self.last = progress;
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+/// Decompresses and unpacks a tarball into the `directory`.
+///
+/// Permissions, ownership, modification times, and extended attributes are preserved.
+pub fn extract<F: FnMut(i32)>(
+    archive: &Path,
+    directory: &Path,
+    compression: Compression,
+    callback: F,
+) -> Result<()> {
+    let file = File::open(archive)?;
+    let total = file.metadata()?.len();
+    let reader =
+        BufReader::new(ProgressReader { inner: file, read: 0, total, last: 0, callback });
+
+    info!("extracting {:?} tarball {:?} to {:?}", compression, archive, directory);
+
+    match compression {
+        Compression::Gzip => unpack(MultiGzDecoder::new(reader), directory),
+        Compression::Xz => unpack(XzDecoder::new_multi_decoder(reader), directory),
+        Compression::Zstd => unpack(zstd::stream::read::Decoder::with_buffer(reader)?, directory),
+    }
+}
+
+fn unpack<R: Read>(reader: R, directory: &Path) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    archive.set_overwrite(true);
+    archive.set_preserve_mtime(true);
+    archive.set_preserve_ownerships(true);
+    archive.set_preserve_permissions(true);
+    archive.set_unpack_xattrs(true);
+    archive.unpack(directory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression as Level};
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn detect() {
+        let detect = |name: &str| Compression::detect(Path::new(name));
+        assert_eq!(detect("/cdrom/rootfs.tar.gz"), Some(Compression::Gzip));
+        assert_eq!(detect("rootfs.tgz"), Some(Compression::Gzip));
+        assert_eq!(detect("rootfs.tar.xz"), Some(Compression::Xz));
+        assert_eq!(detect("rootfs.tar.zst"), Some(Compression::Zstd));
+        assert_eq!(detect("rootfs.tar"), None);
+        assert_eq!(detect("filesystem.squashfs"), None);
+    }
+
+    #[test]
+    fn extract_gzip() {
+        let tempdir = TempDir::new("distinst-squashfs-extract-gzip").unwrap();
+        let base = tempdir.path();
+        let target = base.join("target");
+        fs::create_dir_all(&target).unwrap();
+
+        let archive = base.join("rootfs.tar.gz");
+        {
+            let encoder = GzEncoder::new(File::create(&archive).unwrap(), Level::default());
+            let mut builder = tar::Builder::new(encoder);
+            let data = b"NAME=\"Pop!_OS\"\n";
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            header.set_cksum();
+            builder.append_data(&mut header, "etc/os-release", &data[..]).unwrap();
+            builder.into_inner().unwrap().finish().unwrap();
+        }
+
+        let mut progress = Vec::new();
+        extract(&archive, &target, Compression::Gzip, |p| progress.push(p)).unwrap();
+
+        let contents = fs::read_to_string(target.join("etc/os-release")).unwrap();
+        assert_eq!(contents, "NAME=\"Pop!_OS\"\n");
+        assert_eq!(progress.last(), Some(&100));
+    }
+}
@@ -0,0 +1,951 @@
+//! In-process extraction of squashfs images, for environments such as an initramfs which lack
+//! squashfs-tools, with progress based on the inodes extracted.
+//!
+//! Images compressed with gzip, lzma, xz, and zstd are supported. Ownership, permissions,
+//! modification times, extended attributes, and hard links are preserved.
+
+use flate2::read::ZlibDecoder;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    ffi::{CString, OsStr},
+    fs::{self, File},
+    io::{Error, ErrorKind, Read, Result},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+use xz2::{read::XzDecoder, stream::Stream};
+
+const MAGIC: u32 = 0x7371_7368;
+const METADATA_SIZE: usize = 8192;
+const METADATA_UNCOMPRESSED: u16 = 1 << 15;
+const DATA_UNCOMPRESSED: u32 = 1 << 24;
+const INVALID_FRAGMENT: u32 = 0xFFFF_FFFF;
+const INVALID_XATTR: u32 = 0xFFFF_FFFF;
+const INVALID_TABLE: u64 = 0xFFFF_FFFF_FFFF_FFFF;
+const XATTR_VALUE_OOL: u16 = 0x100;
+/// The longest symlink target, and xattr value, which Linux permits.
+const MAX_SYMLINK_LENGTH: usize = 4096;
+const MAX_XATTR_LENGTH: usize = 65536;
+
+fn invalid<T>(message: &str) -> Result<T> {
+    Err(Error::new(ErrorKind::InvalidData, format!("invalid squashfs image: {}", message)))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Compressor {
+    Gzip,
+    Lzma,
+    Xz,
+    Zstd,
+}
+
+impl Compressor {
+    fn from_id(id: u16) -> Result<Self> {
+        match id {
+            1 => Ok(Compressor::Gzip),
+            2 => Ok(Compressor::Lzma),
+            4 => Ok(Compressor::Xz),
+            6 => Ok(Compressor::Zstd),
+            3 | 5 => Err(Error::new(
+                ErrorKind::Other,
+                "lzo and lz4 squashfs images can only be extracted by unsquashfs",
+            )),
+            _ => invalid("unknown compressor"),
+        }
+    }
+
+    fn decompress(self, data: &[u8], capacity: usize) -> Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(capacity);
+        match self {
+            Compressor::Gzip => ZlibDecoder::new(data).read_to_end(&mut output)?,
+            Compressor::Lzma => {
+                let stream = Stream::new_lzma_decoder(u64::MAX)?;
+                XzDecoder::new_stream(data, stream).read_to_end(&mut output)?
+            }
+            Compressor::Xz => XzDecoder::new(data).read_to_end(&mut output)?,
+            Compressor::Zstd => {
+                zstd::stream::read::Decoder::new(data)?.read_to_end(&mut output)?
+            }
+        };
+
+        Ok(output)
+    }
+}
+
+/// The fields of the superblock which are needed to read the image.
+#[derive(Debug)]
+struct Superblock {
+    inode_count:     u32,
+    block_size:      u32,
+    fragment_count:  u32,
+    compressor:      Compressor,
+    id_count:        u16,
+    root_inode:      u64,
+    id_table:        u64,
+    xattr_id_table:  u64,
+    inode_table:     u64,
+    directory_table: u64,
+    fragment_table:  u64,
+}
+
+impl Superblock {
+    fn parse(data: &[u8; 96]) -> Result<Self> {
+        if le32(&data[..4]) != MAGIC {
+            return invalid("bad magic");
+        }
+
+        if le16(&data[28..30]) != 4 {
+            return invalid("only version 4.0 is supported");
+        }
+
+        let superblock = Superblock {
+            inode_count:     le32(&data[4..8]),
+            block_size:      le32(&data[12..16]),
+            fragment_count:  le32(&data[16..20]),
+            compressor:      Compressor::from_id(le16(&data[20..22]))?,
+            id_count:        le16(&data[26..28]),
+            root_inode:      le64(&data[32..40]),
+            id_table:        le64(&data[48..56]),
+            xattr_id_table:  le64(&data[56..64]),
+            inode_table:     le64(&data[64..72]),
+            directory_table: le64(&data[72..80]),
+            fragment_table:  le64(&data[80..88]),
+        };
+
+        // The block size must be a power of two from 4 KiB to 1 MiB, of which the block log
+        // is the binary logarithm.
+        let (block_size, block_log) = (superblock.block_size, le16(&data[22..24]));
+        if !block_size.is_power_of_two()
+            || !(4096..=1 << 20).contains(&block_size)
+            || u32::from(block_log) != block_size.trailing_zeros()
+        {
+            return invalid("bad block size");
+        }
+
+        Ok(superblock)
+    }
+}
+
+/// The location of the data of a fragment block.
+#[derive(Debug, Copy, Clone)]
+struct Fragment {
+    start: u64,
+    size:  u32,
+}
+
+/// The location and count of the extended attributes of an inode.
+#[derive(Debug, Copy, Clone)]
+struct XattrId {
+    reference: u64,
+    count:     u32,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Directory { block: u32, offset: u16, size: u32 },
+    File { start: u64, size: u64, fragment: Option<(u32, u32)>, blocks: Vec<u32> },
+    Symlink(Vec<u8>),
+    BlockDevice(u32),
+    CharDevice(u32),
+    Fifo,
+    Socket,
+}
+
+#[derive(Debug)]
+struct Inode {
+    kind:   Kind,
+    mode:   u16,
+    uid:    u16,
+    gid:    u16,
+    mtime:  u32,
+    number: u32,
+    links:  u32,
+    xattr:  u32,
+}
+
+struct Entry {
+    name:  Vec<u8>,
+    inode: u64,
+}
+
+/// A position within the metadata, as the position of a block and an offset into its data.
+#[derive(Debug, Copy, Clone)]
+struct Cursor {
+    block:  u64,
+    offset: usize,
+}
+
+impl Cursor {
+    /// The position which a reference locates, relative to the start of its table. A corrupt
+    /// reference locates a position past the end of the image, which fails to be read.
+    fn new(table: u64, reference: u64) -> Self {
+        let block = table.saturating_add(reference >> 16);
+        Cursor { block, offset: (reference & 0xFFFF) as usize }
+    }
+}
+
+/// A squashfs image, with a cache of the metadata blocks which have been read.
+struct Image {
+    file:        File,
+    superblock:  Superblock,
+    ids:         Vec<u32>,
+    fragments:   Vec<Fragment>,
+    xattr_table: u64,
+    xattr_ids:   Vec<XattrId>,
+    metadata:    HashMap<u64, (Rc<[u8]>, u64)>,
+    fragment:    Option<(u32, Vec<u8>)>,
+}
+
+impl Image {
+    fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let mut data = [0u8; 96];
+        file.read_exact_at(&mut data, 0)?;
+        let superblock = Superblock::parse(&data)?;
+
+        let mut image = Image {
+            file,
+            superblock,
+            ids:         Vec::new(),
+            fragments:   Vec::new(),
+            xattr_table: INVALID_TABLE,
+            xattr_ids:   Vec::new(),
+            metadata:    HashMap::new(),
+            fragment:    None,
+        };
+
+        let (id_table, id_count) = (image.superblock.id_table, image.superblock.id_count);
+        image.ids = image.table(id_table, usize::from(id_count), 4)?.chunks(4).map(le32).collect();
+
+        let fragment_table = image.superblock.fragment_table;
+        let fragment_count = image.superblock.fragment_count as usize;
+        let fragments = image.table(fragment_table, fragment_count, 16)?;
+        image.fragments = fragments
+            .chunks(16)
+            .map(|entry| Fragment { start: le64(&entry[..8]), size: le32(&entry[8..12]) })
+            .collect();
+
+        let xattr_id_table = image.superblock.xattr_id_table;
+        if xattr_id_table != INVALID_TABLE {
+            let mut header = [0u8; 16];
+            image.file.read_exact_at(&mut header, xattr_id_table)?;
+            image.xattr_table = le64(&header[..8]);
+            let count = le32(&header[8..12]) as usize;
+            let ids = image.table(xattr_id_table + 16, count, 16)?;
+            image.xattr_ids = ids
+                .chunks(16)
+                .map(|id| XattrId { reference: le64(&id[..8]), count: le32(&id[8..12]) })
+                .collect();
+        }
+
+        Ok(image)
+    }
+
+    /// Reads a table of `count` entries of `size` bytes, whose metadata blocks are located by
+    /// the list of their positions at `index`.
+    fn table(&mut self, index: u64, count: usize, size: usize) -> Result<Vec<u8>> {
+        let length = count * size;
+        let blocks = (length + METADATA_SIZE - 1) / METADATA_SIZE;
+        let mut positions = vec![0u8; blocks * 8];
+        self.file.read_exact_at(&mut positions, index)?;
+
+        let mut table = Vec::with_capacity(length);
+        for position in positions.chunks(8) {
+            table.extend_from_slice(&self.metadata_block(le64(position))?.0);
+        }
+
+        if table.len() < length {
+            return invalid("truncated table");
+        }
+
+        table.truncate(length);
+        Ok(table)
+    }
+
+    /// Reads the metadata block at `position`, returning its data and the position of the
+    /// block which follows it. Blocks are cached, as the inodes and directories of the image
+    /// are read from the same blocks many times.
+    fn metadata_block(&mut self, position: u64) -> Result<(Rc<[u8]>, u64)> {
+        if let Some((data, next)) = self.metadata.get(&position) {
+            return Ok((data.clone(), *next));
+        }
+
+        let mut header = [0u8; 2];
+        self.file.read_exact_at(&mut header, position)?;
+        let header = u16::from_le_bytes(header);
+        let size = usize::from(header & !METADATA_UNCOMPRESSED);
+        let mut data = vec![0u8; size];
+        self.file.read_exact_at(&mut data, position + 2)?;
+
+        if header & METADATA_UNCOMPRESSED == 0 {
+            data = self.superblock.compressor.decompress(&data, METADATA_SIZE)?;
+        }
+
+        let block = (Rc::from(data), position + 2 + size as u64);
+        self.metadata.insert(position, block.clone());
+        Ok(block)
+    }
+
+    /// Reads `length` bytes of metadata at the cursor, and advances the cursor past them.
+    ///
+    /// The length may be read from a corrupt image, so the data grows with the blocks that are
+    /// actually read, rather than being allocated up front.
+    fn metadata(&mut self, cursor: &mut Cursor, length: usize) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(length.min(METADATA_SIZE));
+        while data.len() < length {
+            let (block, next) = self.metadata_block(cursor.block)?;
+            if block.is_empty() {
+                return invalid("empty metadata block");
+            }
+
+            if cursor.offset >= block.len() {
+                cursor.offset -= block.len();
+                cursor.block = next;
+                continue;
+            }
+
+            let take = (length - data.len()).min(block.len() - cursor.offset);
+            data.extend_from_slice(&block[cursor.offset..cursor.offset + take]);
+            cursor.offset += take;
+        }
+
+        Ok(data)
+    }
+
+    /// Reads the inode which the `reference` locates within the inode table.
+    fn inode(&mut self, reference: u64) -> Result<Inode> {
+        let block_size = u64::from(self.superblock.block_size);
+        let mut cursor = Cursor::new(self.superblock.inode_table, reference);
+        let mut read = |length: usize| self.metadata(&mut cursor, length);
+
+        let header = read(16)?;
+        let kind = le16(&header[..2]);
+        // The size of the fields which follow the header, for each type of inode.
+        let fixed = match kind {
+            6 | 7 => 4,
+            3 | 4 | 5 | 10 | 13 | 14 => 8,
+            11 | 12 => 12,
+            1 | 2 => 16,
+            8 => 24,
+            9 => 40,
+            _ => return invalid("unknown inode type"),
+        };
+
+        let body = read(fixed)?;
+        let mut inode = Inode {
+            kind:   Kind::Fifo,
+            mode:   le16(&header[2..4]),
+            uid:    le16(&header[4..6]),
+            gid:    le16(&header[6..8]),
+            mtime:  le32(&header[8..12]),
+            number: le32(&header[12..16]),
+            links:  1,
+            xattr:  INVALID_XATTR,
+        };
+
+        let block_list = |size: u64, fragment: u32| {
+            if fragment == INVALID_FRAGMENT {
+                size.checked_add(block_size.checked_sub(1)?)?.checked_div(block_size)
+            } else {
+                size.checked_div(block_size)
+            }
+        };
+
+        inode.kind = match kind {
+            1 => {
+                inode.links = le32(&body[4..8]);
+                Kind::Directory {
+                    block:  le32(&body[..4]),
+                    offset: le16(&body[10..12]),
+                    size:   u32::from(le16(&body[8..10])),
+                }
+            }
+            8 => {
+                inode.links = le32(&body[..4]);
+                inode.xattr = le32(&body[20..24]);
+                Kind::Directory {
+                    block:  le32(&body[8..12]),
+                    offset: le16(&body[18..20]),
+                    size:   le32(&body[4..8]),
+                }
+            }
+            2 | 9 => {
+                let (start, size, fragment, fragment_offset) = if kind == 2 {
+                    let size = u64::from(le32(&body[12..16]));
+                    (u64::from(le32(&body[..4])), size, le32(&body[4..8]), le32(&body[8..12]))
+                } else {
+                    inode.links = le32(&body[24..28]);
+                    inode.xattr = le32(&body[36..40]);
+                    (le64(&body[..8]), le64(&body[8..16]), le32(&body[28..32]), le32(&body[32..36]))
+                };
+
+                let length = block_list(size, fragment)
+                    .and_then(|count| usize::try_from(count).ok()?.checked_mul(4));
+                let blocks = match length {
+                    Some(length) => read(length)?,
+                    None => return invalid("bad file size"),
+                };
+                Kind::File {
+                    start,
+                    size,
+                    fragment: if fragment == INVALID_FRAGMENT {
+                        None
+                    } else {
+                        Some((fragment, fragment_offset))
+                    },
+                    blocks: blocks.chunks(4).map(le32).collect(),
+                }
+            }
+            3 | 10 => {
+                inode.links = le32(&body[..4]);
+                let length = le32(&body[4..8]) as usize;
+                if length > MAX_SYMLINK_LENGTH {
+                    return invalid("bad symlink length");
+                }
+
+                let target = read(length)?;
+                if kind == 10 {
+                    inode.xattr = le32(&read(4)?);
+                }
+
+                Kind::Symlink(target)
+            }
+            4 | 5 | 11 | 12 => {
+                inode.links = le32(&body[..4]);
+                if kind > 10 {
+                    inode.xattr = le32(&body[8..12]);
+                }
+
+                let device = le32(&body[4..8]);
+                if kind == 4 || kind == 11 {
+                    Kind::BlockDevice(device)
+                } else {
+                    Kind::CharDevice(device)
+                }
+            }
+            _ => {
+                inode.links = le32(&body[..4]);
+                if kind > 7 {
+                    inode.xattr = le32(&body[4..8]);
+                }
+
+                if kind == 6 || kind == 13 {
+                    Kind::Fifo
+                } else {
+                    Kind::Socket
+                }
+            }
+        };
+
+        Ok(inode)
+    }
+
+    /// Reads the entries of a directory.
+    fn entries(&mut self, block: u32, offset: u16, size: u32) -> Result<Vec<Entry>> {
+        // The size includes the `.` and `..` entries, which are not stored.
+        let length = (size as usize).saturating_sub(3);
+        let reference = u64::from(block) << 16 | u64::from(offset);
+        let mut cursor = Cursor::new(self.superblock.directory_table, reference);
+        let listing = self.metadata(&mut cursor, length)?;
+
+        let mut entries = Vec::new();
+        let mut at = 0;
+        while at + 12 <= listing.len() {
+            let count = match le32(&listing[at..at + 4]).checked_add(1) {
+                Some(count) => count,
+                None => return invalid("bad directory header"),
+            };
+            let start = u64::from(le32(&listing[at + 4..at + 8]));
+            at += 12;
+
+            for _ in 0..count {
+                if at + 8 > listing.len() {
+                    return invalid("truncated directory");
+                }
+
+                let offset = u64::from(le16(&listing[at..at + 2]));
+                let length = usize::from(le16(&listing[at + 6..at + 8])) + 1;
+                let name = listing.get(at + 8..at + 8 + length);
+                let name = name.map_or_else(|| invalid("truncated directory"), Ok)?;
+                entries.push(Entry { name: name.to_vec(), inode: start << 16 | offset });
+                at += 8 + length;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Reads the extended attributes of an inode, by the name and value of each.
+    fn xattrs(&mut self, index: u32) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let id = match self.xattr_ids.get(index as usize) {
+            Some(id) if index != INVALID_XATTR => *id,
+            _ => return Ok(Vec::new()),
+        };
+
+        let mut cursor = Cursor::new(self.xattr_table, id.reference);
+        let mut xattrs = Vec::new();
+        for _ in 0..id.count {
+            let key = self.metadata(&mut cursor, 4)?;
+            let kind = le16(&key[..2]);
+            let name = self.metadata(&mut cursor, usize::from(le16(&key[2..4])))?;
+            let prefix: &[u8] = match kind & 0xFF {
+                0 => b"user.",
+                1 => b"trusted.",
+                2 => b"security.",
+                _ => return invalid("unknown xattr prefix"),
+            };
+
+            let mut value = self.xattr_value(&mut cursor)?;
+            // Values which are shared by many inodes are stored once, and referenced.
+            if kind & XATTR_VALUE_OOL != 0 {
+                if value.len() != 8 {
+                    return invalid("bad xattr reference");
+                }
+
+                let mut cursor = Cursor::new(self.xattr_table, le64(&value));
+                value = self.xattr_value(&mut cursor)?;
+            }
+
+            xattrs.push(([prefix, &name].concat(), value));
+        }
+
+        Ok(xattrs)
+    }
+
+    /// Reads the length of an xattr value at the cursor, and then the value.
+    fn xattr_value(&mut self, cursor: &mut Cursor) -> Result<Vec<u8>> {
+        let length = le32(&self.metadata(cursor, 4)?) as usize;
+        if length > MAX_XATTR_LENGTH {
+            return invalid("bad xattr length");
+        }
+
+        self.metadata(cursor, length)
+    }
+
+    /// Reads the data of a block, and decompresses it if it is compressed.
+    fn data_block(&self, position: u64, size: u32, capacity: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; (size & !DATA_UNCOMPRESSED) as usize];
+        self.file.read_exact_at(&mut data, position)?;
+        if size & DATA_UNCOMPRESSED == 0 {
+            data = self.superblock.compressor.decompress(&data, capacity)?;
+        }
+
+        Ok(data)
+    }
+
+    /// Writes the data of a regular file.
+    fn write_file(
+        &mut self,
+        path: &Path,
+        start: u64,
+        size: u64,
+        fragment: Option<(u32, u32)>,
+        blocks: &[u32],
+    ) -> Result<()> {
+        let block_size = self.superblock.block_size as usize;
+        let file = File::create(path)?;
+        let (mut position, mut written) = (start, 0u64);
+        for &block in blocks {
+            let stored = block & !DATA_UNCOMPRESSED;
+            // Sparse blocks of zeros are not stored, and are left as holes in the file.
+            if stored != 0 {
+                let data = self.data_block(position, block, block_size)?;
+                file.write_all_at(&data, written)?;
+            }
+
+            position += u64::from(stored);
+            written += block_size as u64;
+        }
+
+        if let Some((index, offset)) = fragment {
+            let cached = self.fragment.as_ref().map_or(false, |(cached, _)| *cached == index);
+            if !cached {
+                let entry = match self.fragments.get(index as usize) {
+                    Some(entry) => *entry,
+                    None => return invalid("fragment is out of bounds"),
+                };
+
+                let data = self.data_block(entry.start, entry.size, block_size)?;
+                self.fragment = Some((index, data));
+            }
+
+            let data = &self.fragment.as_ref().expect("fragment was cached").1;
+            let (offset, length) = (offset as usize, (size - written.min(size)) as usize);
+            let tail = data.get(offset..offset + length);
+            file.write_all_at(tail.map_or_else(|| invalid("truncated fragment"), Ok)?, written)?;
+        }
+
+        file.set_len(size)
+    }
+}
+
+fn le16(data: &[u8]) -> u16 { u16::from_le_bytes([data[0], data[1]]) }
+
+fn le32(data: &[u8]) -> u32 { u32::from_le_bytes([data[0], data[1], data[2], data[3]]) }
+
+fn le64(data: &[u8]) -> u64 { u64::from(le32(&data[..4])) | u64::from(le32(&data[4..8])) << 32 }
+
+fn cstring(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|why| Error::new(ErrorKind::InvalidInput, why))
+}
+
+fn cvt(result: libc::c_int) -> Result<()> {
+    if result == -1 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Removes a file which is in the way of an entry of the image, as `unsquashfs -f` does.
+fn remove_existing(path: &Path, directory: bool) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(ref metadata) if metadata.is_dir() && directory => Ok(()),
+        Ok(ref metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(ref why) if why.kind() == ErrorKind::NotFound => Ok(()),
+        Err(why) => Err(why),
+    }
+}
+
+/// Extracts the squashfs `image` into the `directory`, reporting the percentage of inodes
+/// which have been extracted.
+pub fn extract<F: FnMut(i32)>(image: &Path, directory: &Path, mut callback: F) -> Result<()> {
+    info!("extracting squashfs image {:?} to {:?} in-process", image, directory);
+    let mut image = Image::open(image)?;
+    let root = image.inode(image.superblock.root_inode)?;
+
+    let mut extraction = Extraction {
+        total:     u64::from(image.superblock.inode_count.max(1)),
+        extracted: 0,
+        last:      -1,
+        links:     HashMap::new(),
+        callback:  &mut callback,
+    };
+
+    fs::create_dir_all(directory)?;
+    extraction.directory(&mut image, &root, directory)
+}
+
+/// The state of an extraction, which is shared by each directory of the image.
+struct Extraction<'a> {
+    total:     u64,
+    extracted: u64,
+    last:      i32,
+    /// The paths of inodes which have several links, by their number.
+    links:     HashMap<u32, PathBuf>,
+    callback:  &'a mut dyn FnMut(i32),
+}
+
+impl<'a> Extraction<'a> {
+    fn progress(&mut self) {
+        self.extracted += 1;
+        let progress = (self.extracted * 100 / self.total).min(100) as i32;
+        if progress != self.last {
+            (self.callback)(progress);
+            self.last = progress;
+        }
+    }
+
+    fn directory(&mut self, image: &mut Image, inode: &Inode, path: &Path) -> Result<()> {
+        let entries = match inode.kind {
+            Kind::Directory { block, offset, size } => image.entries(block, offset, size)?,
+            _ => return invalid("expected a directory"),
+        };
+
+        for entry in entries {
+            let name = OsStr::from_bytes(&entry.name);
+            if name == "." || name == ".." || entry.name.contains(&b'/') {
+                return invalid("directory entry has an unsafe name");
+            }
+
+            let child = image.inode(entry.inode)?;
+            let path = path.join(name);
+            if let Kind::Directory { .. } = child.kind {
+                remove_existing(&path, true)?;
+                if !path.is_dir() {
+                    fs::create_dir(&path)?;
+                }
+
+                self.directory(image, &child, &path)?;
+            } else {
+                self.file(image, &child, &path)?;
+            }
+        }
+
+        set_metadata(image, inode, path)?;
+        self.progress();
+        Ok(())
+    }
+
+    fn file(&mut self, image: &mut Image, inode: &Inode, path: &Path) -> Result<()> {
+        remove_existing(path, false)?;
+        if inode.links > 1 {
+            if let Some(original) = self.links.get(&inode.number) {
+                return fs::hard_link(original, path);
+            }
+
+            self.links.insert(inode.number, path.to_path_buf());
+        }
+
+        let (kind, device) = match inode.kind {
+            Kind::File { start, size, fragment, ref blocks } => {
+                image.write_file(path, start, size, fragment, blocks)?;
+                (None, 0)
+            }
+            Kind::Symlink(ref target) => {
+                std::os::unix::fs::symlink(OsStr::from_bytes(target), path)?;
+                (None, 0)
+            }
+            Kind::BlockDevice(device) => (Some(libc::S_IFBLK), device),
+            Kind::CharDevice(device) => (Some(libc::S_IFCHR), device),
+            Kind::Fifo => (Some(libc::S_IFIFO), 0),
+            Kind::Socket => (Some(libc::S_IFSOCK), 0),
+            Kind::Directory { .. } => return invalid("unexpected directory"),
+        };
+
+        // Devices are numbered as the kernel encodes them, which is that of `makedev` for the
+        // 12 bits of major and 20 bits of minor numbers which fit in 32 bits.
+        if let Some(kind) = kind {
+            let device = libc::dev_t::from(device);
+            let mode = kind | libc::mode_t::from(inode.mode & 0o7777);
+            cvt(unsafe { libc::mknod(cstring(path)?.as_ptr(), mode, device) })?;
+        }
+
+        set_metadata(image, inode, path)?;
+        self.progress();
+        Ok(())
+    }
+}
+
+/// Applies the ownership, extended attributes, permissions, and modification time of the
+/// inode to its extracted file.
+fn set_metadata(image: &mut Image, inode: &Inode, path: &Path) -> Result<()> {
+    let id = |index: u16| match image.ids.get(usize::from(index)) {
+        Some(id) => Ok(*id),
+        None => invalid("id is out of bounds"),
+    };
+
+    let (uid, gid) = (id(inode.uid)?, id(inode.gid)?);
+    let cpath = cstring(path)?;
+    cvt(unsafe { libc::lchown(cpath.as_ptr(), uid, gid) })?;
+
+    for (name, value) in image.xattrs(inode.xattr)? {
+        let name = CString::new(name).map_err(|why| Error::new(ErrorKind::InvalidData, why))?;
+        let value_ptr = value.as_ptr() as *const libc::c_void;
+        let result =
+            unsafe { libc::lsetxattr(cpath.as_ptr(), name.as_ptr(), value_ptr, value.len(), 0) };
+
+        if let Err(why) = cvt(result) {
+            warn!("failed to set the {:?} xattr of {:?}: {}", name, path, why);
+        }
+    }
+
+    if !matches!(inode.kind, Kind::Symlink(_)) {
+        let permissions = fs::Permissions::from_mode(u32::from(inode.mode & 0o7777));
+        fs::set_permissions(path, permissions)?;
+    }
+
+    let time = libc::timespec { tv_sec: inode.mtime as libc::time_t, tv_nsec: 0 };
+    let times = [time, time];
+    cvt(unsafe {
+        libc::utimensat(libc::AT_FDCWD, cpath.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+    use tempdir::TempDir;
+
+    fn metadata_block(data: &[u8]) -> Vec<u8> {
+        let header = data.len() as u16 | METADATA_UNCOMPRESSED;
+        [&header.to_le_bytes()[..], data].concat()
+    }
+
+    fn inode(kind: u16, mode: u16, number: u32, body: &[&[u8]]) -> Vec<u8> {
+        let header: &[&[u8]] =
+            &[&kind.to_le_bytes(), &mode.to_le_bytes(), &[0; 4], &[0; 4], &number.to_le_bytes()];
+        [header, body].concat().concat()
+    }
+
+    fn entry(offset: u16, number: i16, kind: u16, name: &str) -> Vec<u8> {
+        let size = (name.len() - 1) as u16;
+        let fields: &[&[u8]] = &[&offset.to_le_bytes(), &number.to_le_bytes()];
+        [fields, &[&kind.to_le_bytes(), &size.to_le_bytes(), name.as_bytes()]].concat().concat()
+    }
+
+    /// Builds an uncompressed image with a file of one block, a hard link to it, a file in a
+    /// fragment, a symlink, and an empty directory.
+    fn image() -> Vec<u8> {
+        let (hello, tail) = (b"hello squashfs\n", b"tail\n");
+        let mut data = vec![0u8; 96];
+        data.extend_from_slice(hello);
+        data.extend_from_slice(tail);
+
+        let u16s = |values: &[u16]| values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<_>>();
+        let u32s = |values: &[u32]| values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<_>>();
+        let block = hello.len() as u32 | DATA_UNCOMPRESSED;
+        let file: &[&[u8]] = &[&96u64.to_le_bytes(), &15u64.to_le_bytes(), &[0; 8]];
+        let mut inodes = inode(9, 0o644, 1, file);
+        inodes.extend(u32s(&[2, INVALID_FRAGMENT, 0, INVALID_XATTR, block]));
+        let tail_inode = inodes.len() as u16;
+        inodes.extend(inode(2, 0o600, 2, &[&u32s(&[0, 0, 0, 5])]));
+        let link_inode = inodes.len() as u16;
+        inodes.extend(inode(3, 0o777, 3, &[&u32s(&[1, 5]), b"hello"]));
+        let sub_inode = inodes.len() as u16;
+
+        let mut listing = u32s(&[4, 0, 1]);
+        listing.extend(entry(0, 0, 2, "again"));
+        listing.extend(entry(0, 0, 2, "hello"));
+        listing.extend(entry(link_inode, 2, 3, "link"));
+        listing.extend(entry(sub_inode, 3, 1, "sub"));
+        listing.extend(entry(tail_inode, 1, 2, "tail"));
+
+        // Directories are given their block, link count, size, offset, and parent.
+        let directory = |links: u32, size: usize, offset: usize, parent: u32| {
+            let (size, offset) = (size as u16, offset as u16);
+            [u32s(&[0, links]), u16s(&[size, offset]), u32s(&[parent])].concat()
+        };
+
+        inodes.extend(inode(1, 0o700, 4, &[&directory(2, 3, listing.len(), 5)]));
+        let root_inode = inodes.len() as u64;
+        inodes.extend(inode(1, 0o755, 5, &[&directory(3, listing.len() + 3, 0, 6)]));
+
+        let inode_table = data.len() as u64;
+        data.extend(metadata_block(&inodes));
+        let directory_table = data.len() as u64;
+        data.extend(metadata_block(&listing));
+
+        let fragments = data.len() as u64;
+        let fragment = [&111u64.to_le_bytes()[..], &u32s(&[5 | DATA_UNCOMPRESSED, 0])].concat();
+        data.extend(metadata_block(&fragment));
+        let fragment_table = data.len() as u64;
+        data.extend_from_slice(&fragments.to_le_bytes());
+
+        let ids = data.len() as u64;
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        data.extend(metadata_block(&u32s(&[uid, gid])));
+        let id_table = data.len() as u64;
+        data.extend_from_slice(&ids.to_le_bytes());
+
+        let superblock: &[&[u8]] = &[
+            &u32s(&[MAGIC, 5, 0, 4096, 1]),
+            &[1, 0, 12, 0, 0, 0, 2, 0, 4, 0, 0, 0],
+            &root_inode.to_le_bytes(),
+            &(data.len() as u64).to_le_bytes(),
+            &id_table.to_le_bytes(),
+            &INVALID_TABLE.to_le_bytes(),
+            &inode_table.to_le_bytes(),
+            &directory_table.to_le_bytes(),
+            &fragment_table.to_le_bytes(),
+            &INVALID_TABLE.to_le_bytes(),
+        ];
+
+        data[..96].copy_from_slice(&superblock.concat());
+        data
+    }
+
+    #[test]
+    fn extract_image() {
+        let base = TempDir::new("distinst-squashfs-unsquash").unwrap();
+        let (archive, root) = (base.path().join("filesystem.squashfs"), base.path().join("root"));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(&archive, image()).unwrap();
+        fs::write(root.join("link"), "replaced").unwrap();
+
+        let mut progress = Vec::new();
+        extract(&archive, &root, |percent| progress.push(percent)).unwrap();
+        assert_eq!(progress.last(), Some(&100));
+
+        assert_eq!(fs::read_to_string(root.join("hello")).unwrap(), "hello squashfs\n");
+        assert_eq!(fs::read_to_string(root.join("tail")).unwrap(), "tail\n");
+        assert_eq!(fs::read_link(root.join("link")).unwrap(), Path::new("hello"));
+        assert!(root.join("sub").is_dir());
+
+        let hello = fs::metadata(root.join("hello")).unwrap();
+        assert_eq!(hello.ino(), fs::metadata(root.join("again")).unwrap().ino());
+        assert_eq!(hello.mode() & 0o7777, 0o644);
+        assert_eq!(fs::metadata(root.join("sub")).unwrap().mode() & 0o7777, 0o700);
+    }
+
+    #[test]
+    fn corrupt_superblock() {
+        let superblock = |block_size: u32, block_log: u16| {
+            let mut data = [0u8; 96];
+            data.copy_from_slice(&image()[..96]);
+            data[12..16].copy_from_slice(&block_size.to_le_bytes());
+            data[22..24].copy_from_slice(&block_log.to_le_bytes());
+            Superblock::parse(&data).map(|superblock| superblock.block_size)
+        };
+
+        assert_eq!(superblock(4096, 12).unwrap(), 4096);
+        assert_eq!(superblock(1 << 20, 20).unwrap(), 1 << 20);
+        for &(block_size, block_log) in &[(0, 0), (3000, 12), (2048, 11), (1 << 21, 21), (4096, 13)]
+        {
+            let error = superblock(block_size, block_log).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+
+        let base = TempDir::new("distinst-squashfs-corrupt").unwrap();
+        let (archive, root) = (base.path().join("filesystem.squashfs"), base.path().join("root"));
+        let mut data = image();
+        data[12..16].copy_from_slice(&0u32.to_le_bytes());
+        fs::write(&archive, data).unwrap();
+
+        let error = extract(&archive, &root, |_| ()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn corrupt_lengths() {
+        let base = TempDir::new("distinst-squashfs-lengths").unwrap();
+        let (archive, root) = (base.path().join("filesystem.squashfs"), base.path().join("root"));
+        let extract_corrupt = |data: Vec<u8>| {
+            let _ = fs::remove_dir_all(&root);
+            fs::write(&archive, data).unwrap();
+            extract(&archive, &root, |_| ()).unwrap_err().kind()
+        };
+
+        // The symlink claims a target of 4 GiB.
+        let mut data = image();
+        let target = [&[1, 0, 0, 0, 5, 0, 0, 0][..], b"hello"].concat();
+        let at = data.windows(target.len()).position(|w| w == &target[..]).unwrap();
+        data[at + 4..at + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(extract_corrupt(data), ErrorKind::InvalidData);
+
+        // The first file has an xattr whose out-of-line reference is only four bytes long.
+        let mut data = image();
+        let xattr = [INVALID_FRAGMENT, 0, INVALID_XATTR].iter().flat_map(|v| v.to_le_bytes());
+        let xattr = xattr.collect::<Vec<u8>>();
+        let at = data.windows(xattr.len()).position(|w| w == &xattr[..]).unwrap();
+        data[at + 8..at + 12].copy_from_slice(&0u32.to_le_bytes());
+
+        let xattr_table = data.len() as u64;
+        let kind = XATTR_VALUE_OOL.to_le_bytes();
+        data.extend(metadata_block(&[&kind[..], &[1, 0], b"a", &[4, 0, 0, 0], &[0; 4]].concat()));
+        let ids = data.len() as u64;
+        data.extend(metadata_block(&[&0u64.to_le_bytes()[..], &[1, 0, 0, 0], &[0; 4]].concat()));
+        let xattr_id_table = data.len() as u64;
+        data.extend_from_slice(&xattr_table.to_le_bytes());
+        data.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&ids.to_le_bytes());
+        let bytes_used = data.len() as u64;
+        data[40..48].copy_from_slice(&bytes_used.to_le_bytes());
+        data[56..64].copy_from_slice(&xattr_id_table.to_le_bytes());
+        assert_eq!(extract_corrupt(data.clone()), ErrorKind::InvalidData);
+
+        // The same xattr, with a value of 4 GiB.
+        let value = [&kind[..], &[1, 0], b"a", &[4, 0, 0, 0]].concat();
+        let at = data.windows(value.len()).position(|w| w == &value[..]).unwrap();
+        data[at + 5..at + 9].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(extract_corrupt(data), ErrorKind::InvalidData);
+    }
+}
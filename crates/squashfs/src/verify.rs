@@ -0,0 +1,141 @@
+//! Verifies an image before it is extracted, so that corrupted installation media is found
+//! before the disks are modified.
+//!
+//! An image may be verified against its entry in a `SHA256SUMS` file, or against a detached
+//! GPG signature, which `gpgv` checks with a keyring that is trusted by the caller.
+
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{Error, ErrorKind, Read, Result},
+    path::Path,
+    process::Command,
+};
+
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Verifies that the image has the digest which the `SHA256SUMS` file lists for it, reporting
+/// the progress of the verification as a percentage.
+pub fn sha256sums<F: FnMut(i32)>(image: &Path, sums: &Path, callback: F) -> Result<()> {
+    let name = image.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    let sums = std::fs::read_to_string(sums)?;
+    let expected = sums_entry(&sums, name).ok_or_else(|| {
+        Error::new(ErrorKind::NotFound, format!("{} is not listed in the SHA256SUMS file", name))
+    })?;
+
+    info!("verifying the checksum of {}", image.display());
+    let actual = sha256_with_progress(image, callback)?;
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "checksum mismatch for {}: expected {}, found {}",
+                image.display(),
+                expected,
+                actual
+            ),
+        ))
+    }
+}
+
+/// Verifies the detached `signature` of the image with the keys of the `keyring`.
+pub fn signature(image: &Path, signature: &Path, keyring: &Path) -> Result<()> {
+    info!("verifying the signature of {}", image.display());
+    let output = Command::new("gpgv")
+        .arg("--keyring")
+        .arg(keyring)
+        .arg(signature)
+        .arg(image)
+        .output()
+        .map_err(|why| Error::new(why.kind(), format!("failed to run gpgv: {}", why)))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "the signature of {} is not valid: {}",
+                image.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ))
+    }
+}
+
+/// The digest of the file with the `name` in a `SHA256SUMS` file, in which each line has a
+/// digest, and a path which may be marked as binary with a `*`.
+fn sums_entry<'a>(sums: &'a str, name: &str) -> Option<&'a str> {
+    sums.lines().find_map(|line| {
+        let mut fields = line.splitn(2, char::is_whitespace);
+        let digest = fields.next()?;
+        let path = fields.next()?.trim_start().trim_start_matches('*');
+        let matches = path == name || path.rsplit('/').next() == Some(name);
+        if matches && digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            Some(digest)
+        } else {
+            None
+        }
+    })
+}
+
+fn sha256_with_progress<F: FnMut(i32)>(path: &Path, mut callback: F) -> Result<String> {
+    let mut file = File::open(path)?;
+    let total = file.metadata()?.len().max(1);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut read = 0;
+    loop {
+        let count = file.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..count]);
+        read += count as u64;
+        callback((read * 100 / total) as i32);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    const DIGEST: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn entries() {
+        let sums = [
+            "0000000000000000000000000000000000000000000000000000000000000000 *pop-os.iso\n",
+            DIGEST,
+            "  ./casper/filesystem.squashfs\n",
+        ]
+        .concat();
+
+        assert_eq!(sums_entry(&sums, "filesystem.squashfs"), Some(DIGEST));
+        assert_eq!(sums_entry(&sums, "pop-os.iso").map(str::len), Some(64));
+        assert_eq!(sums_entry(&sums, "filesystem.manifest"), None);
+    }
+
+    #[test]
+    fn verify_sums() {
+        let dir = TempDir::new("distinst-squashfs-verify").unwrap();
+        let (image, sums) = (dir.path().join("filesystem.squashfs"), dir.path().join("SHA256SUMS"));
+        File::create(&image).unwrap();
+
+        fs::write(&sums, [DIGEST, "  filesystem.squashfs\n"].concat()).unwrap();
+        assert!(sha256sums(&image, &sums, |_| ()).is_ok());
+
+        fs::write(&sums, [&DIGEST[1..], "0  filesystem.squashfs\n"].concat()).unwrap();
+        assert_eq!(
+            sha256sums(&image, &sums, |_| ()).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
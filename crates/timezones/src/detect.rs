@@ -0,0 +1,146 @@
+//! Detection of the timezone that the system is located in, so that installers may preselect
+//! it. The location is obtained from GeoClue, or else the timezone is looked up by a geo-IP
+//! endpoint. Either will reveal information about the system to a remote service, so this
+//! is only available with the `geolocation` feature.
+
+use super::{Region, Timezones};
+use dbus::{
+    blocking::{stdintf::org_freedesktop_dbus::Properties, Connection},
+    Path as ObjectPath,
+};
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+const GEOCLUE: &str = "org.freedesktop.GeoClue2";
+const GEOCLUE_MANAGER: &str = "org.freedesktop.GeoClue2.Manager";
+const GEOCLUE_CLIENT: &str = "org.freedesktop.GeoClue2.Client";
+const GEOCLUE_LOCATION: &str = "org.freedesktop.GeoClue2.Location";
+
+/// The accuracy of a city, which is sufficient to find its timezone.
+const ACCURACY_CITY: u32 = 4;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Detects the region of the timezone that the system is located in, with GeoClue, or with
+/// the geo-IP `endpoint` if GeoClue was unable to locate the system.
+///
+/// The endpoint is requested with a GET, and must respond with the name of the timezone, such
+/// as `America/Denver`, in either a `<TimeZone>` element or a `"timezone"` JSON field.
+pub fn detect<'a>(timezones: &'a Timezones, endpoint: Option<&str>) -> io::Result<&'a Region> {
+    let why = match geoclue_location() {
+        Ok((latitude, longitude)) => match timezones.nearest(latitude, longitude)? {
+            Some(region) => return Ok(region),
+            None => io::Error::new(io::ErrorKind::NotFound, "no timezone is near the location"),
+        },
+        Err(why) => why,
+    };
+
+    let endpoint = match endpoint {
+        Some(endpoint) => endpoint,
+        None => return Err(why),
+    };
+
+    let response = fetch(endpoint)?;
+    let timezone = timezone_from_response(&response).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} did not respond with a timezone", endpoint),
+        )
+    })?;
+
+    timezones.find(timezone).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("{} is not a known timezone", timezone))
+    })
+}
+
+/// Asks GeoClue for the latitude and longitude of the system.
+fn geoclue_location() -> io::Result<(f64, f64)> {
+    let conn = Connection::new_system().map_err(dbus_error)?;
+    let manager = conn.with_proxy(GEOCLUE, "/org/freedesktop/GeoClue2/Manager", TIMEOUT);
+    let (client,): (ObjectPath<'static>,) =
+        manager.method_call(GEOCLUE_MANAGER, "GetClient", ()).map_err(dbus_error)?;
+
+    let client = conn.with_proxy(GEOCLUE, client, TIMEOUT);
+    client.set(GEOCLUE_CLIENT, "DesktopId", String::from("distinst")).map_err(dbus_error)?;
+    client.set(GEOCLUE_CLIENT, "RequestedAccuracyLevel", ACCURACY_CITY).map_err(dbus_error)?;
+    client.method_call::<(), _, _, _>(GEOCLUE_CLIENT, "Start", ()).map_err(dbus_error)?;
+
+    // The location remains `/` until GeoClue has located the system.
+    let start = Instant::now();
+    let location = loop {
+        let location: ObjectPath = client.get(GEOCLUE_CLIENT, "Location").map_err(dbus_error)?;
+        if &*location != "/" {
+            break Ok(location);
+        }
+
+        if start.elapsed() >= TIMEOUT {
+            break Err(io::Error::new(io::ErrorKind::TimedOut, "GeoClue did not locate the system"));
+        }
+
+        conn.process(Duration::from_millis(250)).map_err(dbus_error)?;
+    };
+
+    let _ = client.method_call::<(), _, _, _>(GEOCLUE_CLIENT, "Stop", ());
+
+    let location = conn.with_proxy(GEOCLUE, location?, TIMEOUT);
+    let latitude: f64 = location.get(GEOCLUE_LOCATION, "Latitude").map_err(dbus_error)?;
+    let longitude: f64 = location.get(GEOCLUE_LOCATION, "Longitude").map_err(dbus_error)?;
+    Ok((latitude, longitude))
+}
+
+fn fetch(endpoint: &str) -> io::Result<String> {
+    let response = ureq::get(endpoint).timeout(TIMEOUT).call();
+    if let Some(why) = response.synthetic_error() {
+        return Err(io::Error::new(io::ErrorKind::Other, format!("{}: {}", endpoint, why)));
+    }
+
+    if !response.ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("{}: responded with status {}", endpoint, response.status()),
+        ));
+    }
+
+    response.into_string()
+}
+
+/// Finds the timezone in the `<TimeZone>` element, or `"timezone"` JSON field, of a response.
+fn timezone_from_response(response: &str) -> Option<&str> {
+    let element = || {
+        let start = response.find("<TimeZone>")? + "<TimeZone>".len();
+        let end = start + response[start..].find('<')?;
+        Some(&response[start..end])
+    };
+
+    let field = || {
+        let start = response.find("\"timezone\"")? + "\"timezone\"".len();
+        let value = response[start..].trim_start().strip_prefix(':')?.trim_start();
+        let value = value.strip_prefix('"')?;
+        Some(&value[..value.find('"')?])
+    };
+
+    element().or_else(field).map(str::trim).filter(|timezone| !timezone.is_empty())
+}
+
+fn dbus_error(why: dbus::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("GeoClue: {}", why))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses() {
+        let xml = "<Response><Ip>192.0.2.1</Ip><TimeZone>America/Denver</TimeZone></Response>";
+        assert_eq!(timezone_from_response(xml), Some("America/Denver"));
+
+        let json = r#"{"status": "success", "timezone" : "Europe/Berlin", "lat": 52.5}"#;
+        assert_eq!(timezone_from_response(json), Some("Europe/Berlin"));
+
+        assert_eq!(timezone_from_response("<TimeZone></TimeZone>"), None);
+        assert_eq!(timezone_from_response(r#"{"status": "fail"}"#), None);
+    }
+}
@@ -1,31 +1,41 @@
+#[cfg(feature = "geolocation")]
+mod detect;
+
+#[cfg(feature = "geolocation")]
+pub use self::detect::detect;
+
 use std::{
+    cmp::Ordering,
     fs, io,
     os::unix::fs::symlink,
     path::{Path, PathBuf},
 };
 
+/// The directory of the timezone database.
+const ZONE_INFO: &str = "/usr/share/zoneinfo/";
+
+/// The locations of the principal city of each timezone.
+const ZONE_TABLE: &str = "/usr/share/zoneinfo/zone1970.tab";
+
 #[derive(Clone, Debug, Default)]
 pub struct Timezones {
     zones: Vec<Zone>,
 }
 
 impl Timezones {
-    pub fn new() -> io::Result<Self> {
+    pub fn new() -> io::Result<Self> { Timezones::from_dir(Path::new(ZONE_INFO)) }
+
+    /// Reads the timezones of a database in the layout of `/usr/share/zoneinfo`.
+    fn from_dir(directory: &Path) -> io::Result<Self> {
         let mut output = Timezones::default();
 
-        for zone in fs::read_dir("/usr/share/zoneinfo/")? {
+        for zone in fs::read_dir(directory)? {
             let zone = zone?;
             let zone_path = zone.path();
             if zone_path.is_dir() {
                 let zone_name = zone.file_name().into_string().unwrap();
                 let mut regions = Vec::new();
-                for region in zone_path.read_dir()? {
-                    let region = region?;
-                    let region_path = region.path();
-                    let region_name = region.file_name().into_string().unwrap();
-                    regions.push(Region { name: region_name, path: region_path });
-                }
-
+                read_regions(&zone_path, "", &mut regions)?;
                 regions.sort_unstable();
                 output.zones.push(Zone { name: zone_name, regions })
             }
@@ -36,6 +46,34 @@ impl Timezones {
     }
 
     pub fn zones(&self) -> &[Zone] { &self.zones }
+
+    /// Finds the region of a timezone in the `Zone/Region` format, such as `America/Denver`,
+    /// or `America/Argentina/Buenos_Aires`, whose region is `Argentina/Buenos_Aires`.
+    pub fn find(&self, timezone: &str) -> Option<&Region> {
+        let mut fields = timezone.splitn(2, '/');
+        let (zone, region) = (fields.next()?, fields.next()?);
+        self.zones
+            .iter()
+            .find(|z| z.name == zone)
+            .and_then(|zone| zone.regions.iter().find(|r| r.name == region))
+    }
+
+    /// Finds the region of the timezone whose principal city is nearest to the given latitude
+    /// and longitude, in degrees. Timezones which are not in the database are skipped.
+    pub fn nearest(&self, latitude: f64, longitude: f64) -> io::Result<Option<&Region>> {
+        let table = fs::read_to_string(ZONE_TABLE)?;
+        Ok(nearest_timezone(&table, (latitude, longitude), |tz| self.find(tz)))
+    }
+
+    /// Sorts the zones, and their regions, by keys computed once from each display name, which
+    /// may apply the collation rules of the user's language.
+    pub fn sort_by_key<K: Ord, F: FnMut(&str) -> K>(&mut self, mut key: F) {
+        for zone in &mut self.zones {
+            zone.regions.sort_by_cached_key(|region| key(&region.display_name()));
+        }
+
+        self.zones.sort_by_cached_key(|zone| key(&zone.display_name()));
+    }
 }
 
 #[derive(Clone, Debug, Default, Eq, Ord, PartialOrd, PartialEq)]
@@ -47,6 +85,9 @@ pub struct Zone {
 impl Zone {
     pub fn name(&self) -> &str { &self.name }
 
+    /// The name of the zone, with underscores replaced by spaces.
+    pub fn display_name(&self) -> String { display_name(&self.name) }
+
     pub fn regions(&self) -> &[Region] { &self.regions }
 }
 
@@ -59,6 +100,10 @@ pub struct Region {
 impl Region {
     pub fn name(&self) -> &str { &self.name }
 
+    /// The name of the region, with underscores replaced by spaces, such as `New York`, or
+    /// `Argentina/Buenos Aires`.
+    pub fn display_name(&self) -> String { display_name(&self.name) }
+
     pub fn path(&self) -> &Path { &self.path }
 
     pub fn install(&self, dest: &Path) -> io::Result<()> {
@@ -67,3 +112,128 @@ impl Region {
         symlink(&self.path, &timezone)
     }
 }
+
+fn display_name(name: &str) -> String { name.replace('_', " ") }
+
+/// Adds the regions within the `directory` of a zone, where regions which are divided further,
+/// such as `America/Argentina`, are named by their path within the zone.
+fn read_regions(directory: &Path, prefix: &str, regions: &mut Vec<Region>) -> io::Result<()> {
+    for region in directory.read_dir()? {
+        let region = region?;
+        let region_path = region.path();
+        let region_name = [prefix, &region.file_name().into_string().unwrap()].concat();
+        if region_path.is_dir() {
+            read_regions(&region_path, &[&region_name, "/"].concat(), regions)?;
+        } else {
+            regions.push(Region { name: region_name, path: region_path });
+        }
+    }
+
+    Ok(())
+}
+
+/// The nearest `zone1970.tab` entry to the `location` which `find` maps to a timezone.
+fn nearest_timezone<'a, T, F>(table: &'a str, location: (f64, f64), find: F) -> Option<T>
+where
+    F: Fn(&'a str) -> Option<T>,
+{
+    table
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split('\t').skip(1);
+            let coordinates = parse_coordinates(fields.next()?)?;
+            Some((distance(coordinates, location), find(fields.next()?)?))
+        })
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal))
+        .map(|(_, timezone)| timezone)
+}
+
+/// The angle between two locations, from the center of the earth, by the haversine formula.
+fn distance((lat_a, lon_a): (f64, f64), (lat_b, lon_b): (f64, f64)) -> f64 {
+    let (lat_a, lat_b) = (lat_a.to_radians(), lat_b.to_radians());
+    let (d_lat, d_lon) = (lat_b - lat_a, (lon_b - lon_a).to_radians());
+    let h = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * h.sqrt().asin()
+}
+
+/// Parses ISO 6709 coordinates, such as `+4045-07400` or `+394421-1045903`.
+fn parse_coordinates(coordinates: &str) -> Option<(f64, f64)> {
+    let split = coordinates.get(1..)?.find(|c| c == '+' || c == '-')? + 1;
+    let (latitude, longitude) = coordinates.split_at(split);
+    Some((parse_degrees(latitude, 2)?, parse_degrees(longitude, 3)?))
+}
+
+/// Parses a signed angle of whole degrees, minutes, and optionally seconds, where degrees
+/// have the given number of digits.
+fn parse_degrees(angle: &str, digits: usize) -> Option<f64> {
+    let (sign, angle) = match angle.as_bytes().first()? {
+        b'+' => (1.0, &angle[1..]),
+        b'-' => (-1.0, &angle[1..]),
+        _ => return None,
+    };
+
+    if angle.len() < digits || !angle.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let number = |digits: &str| digits.parse::<f64>().ok();
+    let (degrees, rest) = angle.split_at(digits);
+    let (minutes, seconds) = match rest.len() {
+        2 => (number(rest)?, 0.0),
+        4 => (number(&rest[..2])?, number(&rest[2..])?),
+        _ => return None,
+    };
+
+    Some(sign * (number(degrees)? + minutes / 60.0 + seconds / 3600.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    const TABLE: &str = "# tzdb timezone descriptions\n\
+                         US\t+404251-0740023\tAmerica/New_York\tEastern (most areas)\n\
+                         US\t+394421-1045903\tAmerica/Denver\tMountain (most areas)\n\
+                         DE,DK,NO,SE,SJ\t+5230+01322\tEurope/Berlin\tmost of Germany\n\
+                         AU\t-3352+15113\tAustralia/Sydney\tNew South Wales (most areas)\n";
+
+    #[test]
+    fn coordinates() {
+        assert_eq!(parse_coordinates("+5230+01322"), Some((52.5, 13.0 + 22.0 / 60.0)));
+        let (lat, lon) = parse_coordinates("-3352+15113").unwrap();
+        assert!((lat + 33.8667).abs() < 0.001 && (lon - 151.2167).abs() < 0.001);
+        assert_eq!(parse_coordinates("+5230"), None);
+        assert_eq!(parse_coordinates("5230+01322"), None);
+    }
+
+    #[test]
+    fn nearest() {
+        let nearest = |location| nearest_timezone(TABLE, location, Some);
+        assert_eq!(nearest((40.0, -105.3)), Some("America/Denver"));
+        assert_eq!(nearest((42.36, -71.06)), Some("America/New_York"));
+        assert_eq!(nearest((48.14, 11.58)), Some("Europe/Berlin"));
+        assert_eq!(nearest((-37.81, 144.96)), Some("Australia/Sydney"));
+        assert_eq!(nearest_timezone("", (0.0, 0.0), Some), None);
+
+        let known = |tz| Some(tz).filter(|&tz| tz != "America/Denver");
+        assert_eq!(nearest_timezone(TABLE, (40.0, -105.3), known), Some("America/New_York"));
+    }
+
+    #[test]
+    fn three_level_zones() {
+        let database = TempDir::new("distinst-timezones").unwrap();
+        let argentina = database.path().join("America/Argentina");
+        fs::create_dir_all(&argentina).unwrap();
+        fs::write(argentina.join("Buenos_Aires"), b"").unwrap();
+        fs::write(database.path().join("America/Denver"), b"").unwrap();
+
+        let timezones = Timezones::from_dir(database.path()).unwrap();
+        let region = timezones.find("America/Argentina/Buenos_Aires").unwrap();
+        assert_eq!(region.name(), "Argentina/Buenos_Aires");
+        assert_eq!(region.path(), argentina.join("Buenos_Aires"));
+        assert!(timezones.find("America/Denver").is_some());
+        assert!(timezones.find("America/Argentina").is_none());
+    }
+}
@@ -0,0 +1,153 @@
+//! An opt-in journal of the external commands that were executed, including the output that
+//! they produced, so that the cause of a failed command can be found after the fact.
+//!
+//! Commands are only captured while a journal file is open, or a callback is registered.
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+/// An external command that was executed, and how it exited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandRecord {
+    /// The command and its arguments.
+    pub command:  String,
+    /// The exit status, or `None` if the command was terminated by a signal.
+    pub status:   Option<i32>,
+    pub duration: Duration,
+    pub stdout:   String,
+    pub stderr:   String,
+}
+
+impl fmt::Display for CommandRecord {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let status = self.status.map_or_else(|| "terminated".into(), |code| code.to_string());
+        writeln!(
+            fmt,
+            "==> {} (exit status: {}, duration: {}.{:03}s)",
+            self.command,
+            status,
+            self.duration.as_secs(),
+            self.duration.subsec_millis()
+        )?;
+
+        for (name, output) in &[("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            if !output.is_empty() {
+                writeln!(fmt, "--- {}", name)?;
+                write!(fmt, "{}", output)?;
+                if !output.ends_with('\n') {
+                    writeln!(fmt)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A callback which receives every command that is journaled.
+pub type Callback = Arc<Mutex<dyn FnMut(&CommandRecord) + Send>>;
+
+struct Journal {
+    file:     Option<File>,
+    callback: Option<Callback>,
+}
+
+static JOURNAL: Mutex<Journal> = Mutex::new(Journal { file: None, callback: None });
+
+fn journal() -> MutexGuard<'static, Journal> {
+    JOURNAL.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Begins appending commands to the journal at `path`.
+pub fn open(path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    journal().file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+    Ok(())
+}
+
+/// Stops appending commands to the journal file.
+pub fn close() { journal().file = None; }
+
+/// Sets the callback which will receive every command that is journaled, or removes it.
+pub fn set_callback(callback: Option<Callback>) { journal().callback = callback; }
+
+/// Whether commands should capture their output to be journaled.
+pub fn enabled() -> bool {
+    let journal = journal();
+    journal.file.is_some() || journal.callback.is_some()
+}
+
+/// Records a command in the journal file, and passes it to the callback.
+pub fn record(record: &CommandRecord) {
+    let callback = {
+        let mut journal = journal();
+        if let Some(ref mut file) = journal.file {
+            let _ = write!(file, "{}", record);
+        }
+
+        journal.callback.clone()
+    };
+
+    // Called without the lock of the journal, so that the callback may execute commands.
+    if let Some(callback) = callback {
+        let mut callback = callback.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        (*callback)(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let record = CommandRecord {
+            command:  "grub-install \"--target=x86_64-efi\"".into(),
+            status:   Some(5),
+            duration: Duration::from_millis(1250),
+            stdout:   String::new(),
+            stderr:   "grub-install: error: cannot find EFI directory.".into(),
+        };
+
+        assert_eq!(
+            record.to_string(),
+            "==> grub-install \"--target=x86_64-efi\" (exit status: 5, duration: 1.250s)\n\
+             --- stderr\ngrub-install: error: cannot find EFI directory.\n"
+        );
+    }
+
+    #[test]
+    fn callbacks() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+
+        // Commands may be recorded by the callback, as it is called without the journal's lock.
+        set_callback(Some(Arc::new(Mutex::new(move |record: &CommandRecord| {
+            sink.lock().unwrap().push(record.command.clone());
+            let _ = enabled();
+        }))));
+
+        let executed = |command: &str| CommandRecord {
+            command:  command.into(),
+            status:   Some(0),
+            duration: Duration::from_millis(5),
+            stdout:   String::new(),
+            stderr:   String::new(),
+        };
+
+        record(&executed("udevadm settle"));
+        set_callback(None);
+        record(&executed("blockdev --rereadpt"));
+
+        assert_eq!(*received.lock().unwrap(), vec!["udevadm settle".to_owned()]);
+    }
+}
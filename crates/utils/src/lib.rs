@@ -2,6 +2,10 @@
 
 extern crate sedregex;
 
+pub mod journal;
+pub mod metrics;
+pub mod output;
+
 use std::{
     fs::File,
     io::{self, Read, Write},
@@ -54,7 +58,7 @@ use std::{
     borrow::Cow,
     collections::hash_map::DefaultHasher,
     ffi::{OsStr, OsString},
-    fs::{self, DirEntry},
+    fs::DirEntry,
     hash::{Hash, Hasher},
     path::PathBuf,
 };
@@ -172,18 +176,17 @@ pub fn resolve_to_physical(name: &str) -> Option<PathBuf> {
     physical
 }
 
+/// The disk which contains the partition, such as `/dev/mmcblk0` for `mmcblk0p1`. The name of
+/// the disk is not a reliable prefix, as `mmcblk1` is also a prefix of `mmcblk10p1`.
 pub fn resolve_parent(name: &str) -> Option<PathBuf> {
-    for entry in fs::read_dir("/sys/block").ok()? {
-        if let Ok(entry) = entry {
-            if let Some(file) = entry.file_name().to_str() {
-                if name.starts_with(file) {
-                    return Some(PathBuf::from(["/dev/", file].concat()));
-                }
-            }
-        }
+    let block = Path::new("/sys/class/block").join(name);
+    if !block.join("partition").exists() {
+        return None;
     }
 
-    None
+    let device = block.canonicalize().ok()?;
+    let parent = device.parent()?.file_name()?.to_str()?;
+    Some(PathBuf::from(["/dev/", parent].concat()))
 }
 
 /// Apply sed expressions on a file, and overwrite it if there was a change.
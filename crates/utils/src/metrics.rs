@@ -0,0 +1,154 @@
+//! Timing of the operations which are performed during an installation, such as each step and
+//! each external command, so that performance regressions can be measured on real hardware.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+/// The time that an operation took to complete.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    /// The kind and name of the operation, such as `step:Extract` or `command:mkfs.ext4`.
+    pub operation: String,
+    pub duration:  Duration,
+}
+
+/// A callback which receives every metric as it is recorded.
+pub type Callback = Arc<Mutex<dyn FnMut(&Metric) + Send>>;
+
+struct Metrics {
+    /// Only collected between `begin` and `take`, so that they do not accumulate while no
+    /// installation is running.
+    records:    Vec<Metric>,
+    collecting: bool,
+    /// Shared, so that it is called after the lock of the metrics has been released.
+    callback:   Option<Callback>,
+}
+
+static METRICS: Mutex<Metrics> =
+    Mutex::new(Metrics { records: Vec::new(), collecting: false, callback: None });
+
+fn metrics() -> MutexGuard<'static, Metrics> {
+    METRICS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Sets the callback which will receive every metric as it is recorded, or removes it.
+pub fn set_callback(callback: Option<Callback>) { metrics().callback = callback; }
+
+/// Begins collecting metrics, discarding any which were collected before.
+pub fn begin() {
+    let mut metrics = metrics();
+    metrics.records.clear();
+    metrics.collecting = true;
+}
+
+/// Records the duration of an operation, and passes it to the callback.
+pub fn record<S: Into<String>>(operation: S, duration: Duration) {
+    let metric = Metric { operation: operation.into(), duration };
+    let callback = {
+        let mut metrics = metrics();
+        if metrics.collecting {
+            metrics.records.push(metric.clone());
+        }
+
+        metrics.callback.clone()
+    };
+
+    if let Some(callback) = callback {
+        let mut callback = callback.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        (*callback)(&metric);
+    }
+}
+
+/// Takes every metric which has been collected since `begin`, and stops collecting them.
+pub fn take() -> Vec<Metric> {
+    let mut metrics = metrics();
+    metrics.collecting = false;
+    std::mem::replace(&mut metrics.records, Vec::new())
+}
+
+/// Summarizes the metrics by operation, listing the total duration of each operation, and how
+/// many times it was performed, from the longest total duration to the shortest.
+pub fn summary(metrics: &[Metric]) -> String {
+    let mut operations: BTreeMap<&str, (Duration, u32)> = BTreeMap::new();
+    for metric in metrics {
+        let entry = operations.entry(&metric.operation).or_insert((Duration::default(), 0));
+        entry.0 += metric.duration;
+        entry.1 += 1;
+    }
+
+    let mut operations = operations.into_iter().collect::<Vec<_>>();
+    operations.sort_by(|a, b| (b.1).0.cmp(&(a.1).0));
+
+    let mut output = String::new();
+    for (operation, (duration, count)) in operations {
+        let _ = writeln!(
+            output,
+            "{:>5}.{:03}s {:>4}x {}",
+            duration.as_secs(),
+            duration.subsec_millis(),
+            count,
+            operation
+        );
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summaries() {
+        let metric = |operation: &str, millis| Metric {
+            operation: operation.into(),
+            duration:  Duration::from_millis(millis),
+        };
+
+        let metrics = vec![
+            metric("command:mkfs.ext4", 1200),
+            metric("step:Extract", 312_005),
+            metric("command:mkfs.ext4", 850),
+            metric("command:blockdev", 40),
+        ];
+
+        assert_eq!(
+            summary(&metrics),
+            "  312.005s    1x step:Extract\n    2.050s    2x command:mkfs.ext4\n    \
+             0.040s    1x command:blockdev\n"
+        );
+        assert_eq!(summary(&[]), "");
+    }
+
+    #[test]
+    fn callbacks() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+
+        // The metrics may be taken by the callback, as it is called without their lock.
+        set_callback(Some(Arc::new(Mutex::new(move |_: &Metric| {
+            sink.lock().unwrap().extend(take())
+        }))));
+
+        record("command:udevadm", Duration::from_secs(1));
+        assert!(received.lock().unwrap().is_empty());
+
+        begin();
+        record("step:Extract", Duration::from_secs(1));
+        set_callback(None);
+
+        {
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 1);
+            assert_eq!(received[0].operation, "step:Extract");
+        }
+
+        // Nothing is collected once the metrics have been taken.
+        record("command:blockdev", Duration::from_secs(1));
+        assert!(take().is_empty());
+    }
+}
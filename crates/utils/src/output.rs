@@ -0,0 +1,134 @@
+//! Captures the output of external commands, so that the reason a command failed can be
+//! reported in the error that it returns.
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read},
+};
+
+/// The amount of stderr that is retained in a `CommandError`.
+pub const STDERR_LIMIT: usize = 4 * 1024;
+
+/// The amount of output that is retained for the command journal.
+pub const JOURNAL_LIMIT: usize = 1024 * 1024;
+
+/// A buffer which only retains the last `limit` bytes that were written to it.
+#[derive(Debug, Clone)]
+pub struct TailBuffer {
+    data:  Vec<u8>,
+    limit: usize,
+}
+
+impl TailBuffer {
+    pub fn new(limit: usize) -> Self { TailBuffer { data: Vec::new(), limit } }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+        if self.data.len() > self.limit {
+            let excess = self.data.len() - self.limit;
+            self.data.drain(..excess);
+        }
+    }
+
+    pub fn push_line(&mut self, line: &str) {
+        self.extend(line.as_bytes());
+        self.extend(b"\n");
+    }
+
+    /// Reads until the end of the reader, retaining only the tail of what was read.
+    pub fn read_from<R: Read>(&mut self, reader: &mut R) -> io::Result<()> {
+        let mut buffer = [0; 8 * 1024];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(read) => self.extend(&buffer[..read]),
+                Err(ref why) if why.kind() == io::ErrorKind::Interrupted => (),
+                Err(why) => return Err(why),
+            }
+        }
+    }
+
+    pub fn as_string(&self) -> String { String::from_utf8_lossy(&self.data).into_owned() }
+
+    /// The last `limit` bytes of the buffer.
+    pub fn tail(&self, limit: usize) -> String {
+        let start = self.data.len().saturating_sub(limit);
+        String::from_utf8_lossy(&self.data[start..]).into_owned()
+    }
+}
+
+/// An external command which exited unsuccessfully.
+///
+/// This is stored within the `io::Error` that is returned for a failed command, and may be
+/// obtained with `io::Error::get_ref` and `downcast_ref`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandError {
+    pub command: String,
+    /// The exit status, or `None` if the command was terminated by a signal.
+    pub status:  Option<i32>,
+    /// The last `STDERR_LIMIT` bytes that the command wrote to stderr.
+    pub stderr:  String,
+}
+
+impl CommandError {
+    /// Creates an `io::Error` of the given kind, containing this error.
+    pub fn into_io_error(self, kind: io::ErrorKind) -> io::Error { io::Error::new(kind, self) }
+
+    /// Obtains the command error that an `io::Error` was created from, if there is one.
+    pub fn from_io_error(error: &io::Error) -> Option<&CommandError> {
+        error.get_ref().and_then(|inner| inner.downcast_ref::<CommandError>())
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.status {
+            Some(code) => write!(fmt, "{} failed with status: {}", self.command, code)?,
+            None => write!(fmt, "{} was terminated by a signal", self.command)?,
+        }
+
+        let stderr = self.stderr.trim_end();
+        if !stderr.is_empty() {
+            write!(fmt, "\n{}", stderr)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for CommandError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tail_buffer() {
+        let mut buffer = TailBuffer::new(8);
+        buffer.push_line("first");
+        buffer.push_line("second");
+        assert_eq!(buffer.as_string(), "\nsecond\n");
+        assert_eq!(buffer.tail(3), "nd\n");
+
+        let mut buffer = TailBuffer::new(4);
+        buffer.read_from(&mut &b"cannot find EFI directory"[..]).unwrap();
+        assert_eq!(buffer.as_string(), "tory");
+    }
+
+    #[test]
+    fn command_error() {
+        let error = CommandError {
+            command: "grub-install".into(),
+            status:  Some(1),
+            stderr:  "grub-install: error: cannot find EFI directory.\n".into(),
+        }
+        .into_io_error(io::ErrorKind::Other);
+
+        assert_eq!(
+            error.to_string(),
+            "grub-install failed with status: 1\ngrub-install: error: cannot find EFI directory."
+        );
+        assert_eq!(CommandError::from_io_error(&error).and_then(|error| error.status), Some(1));
+    }
+}
@@ -26,15 +26,38 @@ fn main() {
     let options = InstallOptions::new(&disks, required, 0);
 
     let mut config = Config {
-        flags:            distinst::MODIFY_BOOT_ORDER | distinst::INSTALL_HARDWARE_SUPPORT,
-        hostname:         "pop-testing".into(),
-        keyboard_layout:  "us".into(),
-        keyboard_model:   None,
-        keyboard_variant: None,
-        old_root:         None,
-        lang:             "en_US.UTF-8".into(),
-        remove:           "/cdrom/casper/filesystem.manifest-remove".into(),
-        squashfs:         "/cdrom/casper/filesystem.squashfs".into(),
+        flags:                InstallFlags::MODIFY_BOOT_ORDER
+            | InstallFlags::INSTALL_HARDWARE_SUPPORT,
+        hostname:             "pop-testing".into(),
+        keyboard_layout:      "us".into(),
+        keyboard_model:       None,
+        keyboard_variant:     None,
+        old_root:             None,
+        lang:                 "en_US.UTF-8".into(),
+        additional_locales:   Vec::new(),
+        locale_overrides:     Vec::new(),
+        remove:               "/cdrom/casper/filesystem.manifest-remove".into(),
+        minimal_remove:       None,
+        squashfs:             "/cdrom/casper/filesystem.squashfs".into(),
+        bios_device:          None,
+        files:                Vec::new(),
+        dconf:                Vec::new(),
+        hosts:                Vec::new(),
+        rtc_in_localtime:     None,
+        cleanup:              CleanupProfile::Standard,
+        post_install_scripts: Vec::new(),
+        first_boot_services:  Vec::new(),
+        offline_repository:   None,
+        flatpak_remotes:      Vec::new(),
+        flatpaks:             Vec::new(),
+        kernel_variant:       None,
+        image_verification:   None,
+        extract_verification: None,
+        extract_options:      ExtractOptions::default(),
+        file_backup:          None,
+        esp_policy:           EspPolicy::Format,
+        trim_policy:          TrimPolicy::Periodic,
+        tang_binding:         None,
     };
 
     eprintln!("Options: {:#?}", options);
@@ -107,7 +130,12 @@ fn main() {
 
             match options.erase_options.iter().find(|opt| opt.device == disk) {
                 Some(option) => {
-                    let option = InstallOption::Erase { option, password: args.next() };
+                    let option = InstallOption::Erase {
+                        option,
+                        password: args.next(),
+                        erase: None,
+                        encrypt_boot: false,
+                    };
 
                     match option.apply(&mut disks) {
                         Ok(()) => (),
@@ -199,10 +227,12 @@ fn main() {
                 pb.message(match status.step {
                     Step::Backup => "Backing up files",
                     Step::Init => "Initializing",
+                    Step::Verify => "Verifying image",
                     Step::Partition => "Partitioning disk ",
                     Step::Extract => "Extracting filesystem ",
                     Step::Configure => "Configuring installation",
                     Step::Bootloader => "Installing bootloader ",
+                    Step::Cleanup => "Cleaning up",
                 });
                 *pb_opt.borrow_mut() = Some(pb);
             }
@@ -29,6 +29,8 @@ public static string step_name (Step step) {
         return "Configure";
     case Step.BOOTLOADER:
         return "Bootloader";
+    case Step.CLEANUP:
+        return "Cleanup";
     default:
         return "Unknown";
     }
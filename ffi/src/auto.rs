@@ -1,12 +1,12 @@
 use libc;
 
-use super::{gen_object_ptr, get_str, null_check, DistinstDisks, DistinstOsRelease};
+use super::{gen_object_ptr, get_str, null_check, to_cstr, DistinstDisks, DistinstOsRelease};
 use distinst::{
     auto::{
-        AlongsideMethod, AlongsideOption, EraseOption, InstallOption, InstallOptions,
+        AlongsideMethod, AlongsideOption, EraseOption, EspOption, InstallOption, InstallOptions,
         RecoveryOption, RefreshOption,
     },
-    Disks, OS,
+    Disks, EraseMode, OS,
 };
 use std::{os::unix::ffi::OsStrExt, ptr};
 
@@ -89,6 +89,18 @@ pub unsafe extern "C" fn distinst_alongside_option_is_windows(
     }
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn distinst_alongside_option_is_bsd(
+    option: *const DistinstAlongsideOption,
+) -> bool {
+    let option = &*(option as *const AlongsideOption);
+    if let Some(OS::Bsd { .. }) = option.alongside {
+        true
+    } else {
+        false
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_alongside_option_get_partition(
     option: *const DistinstAlongsideOption,
@@ -124,6 +136,7 @@ pub unsafe extern "C" fn distinst_alongside_option_get_sectors_free(
     match option.method {
         AlongsideMethod::Shrink { sectors_free, .. } => sectors_free,
         AlongsideMethod::Free(ref region) => region.size(),
+        AlongsideMethod::VolumeGroup { sectors_free, .. } => sectors_free,
     }
 }
 
@@ -135,6 +148,23 @@ pub unsafe extern "C" fn distinst_alongside_option_get_sectors_total(
     match option.method {
         AlongsideMethod::Shrink { sectors_total, .. } => sectors_total,
         AlongsideMethod::Free(ref region) => region.size(),
+        AlongsideMethod::VolumeGroup { sectors_total, .. } => sectors_total,
+    }
+}
+
+/// The volume group which the install is created in, or null if it is not in a volume group.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_alongside_option_get_volume_group(
+    option: *const DistinstAlongsideOption,
+    len: *mut libc::c_int,
+) -> *const u8 {
+    let option = &*(option as *const AlongsideOption);
+    match option.method {
+        AlongsideMethod::VolumeGroup { ref group, .. } => {
+            *len = group.len() as libc::c_int;
+            group.as_ptr()
+        }
+        _ => ptr::null(),
     }
 }
 
@@ -322,6 +352,79 @@ pub unsafe extern "C" fn distinst_erase_option_meets_requirements(
     option.meets_requirements()
 }
 
+#[repr(C)]
+pub struct DistinstEspOption;
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_esp_option_get_device(
+    option: *const DistinstEspOption,
+    len: *mut libc::c_int,
+) -> *const u8 {
+    if null_check(option).and_then(|_| null_check(len)).is_err() {
+        return ptr::null();
+    }
+
+    let option = &*(option as *const EspOption);
+    let output = option.device.as_os_str().as_bytes();
+    *len = output.len() as libc::c_int;
+    output.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_esp_option_get_partition(
+    option: *const DistinstEspOption,
+    len: *mut libc::c_int,
+) -> *const u8 {
+    if null_check(option).and_then(|_| null_check(len)).is_err() {
+        return ptr::null();
+    }
+
+    let option = &*(option as *const EspOption);
+    let output = option.partition.as_os_str().as_bytes();
+    *len = output.len() as libc::c_int;
+    output.as_ptr()
+}
+
+/// Returns the number of unused sectors in the ESP, or -1 if this could not be determined.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_esp_option_get_sectors_free(
+    option: *const DistinstEspOption,
+) -> i64 {
+    if null_check(option).is_err() {
+        return -1;
+    }
+
+    (&*(option as *const EspOption)).sectors_free.map_or(-1, |free| free as i64)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_esp_option_get_sectors_total(
+    option: *const DistinstEspOption,
+) -> u64 {
+    if null_check(option).is_err() {
+        return 0;
+    }
+
+    (&*(option as *const EspOption)).sectors_total
+}
+
+/// Gets the names of the boot loader directories within the ESP's `/EFI` directory.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_esp_option_get_entries(
+    option: *const DistinstEspOption,
+    len: *mut libc::c_int,
+) -> *mut *mut libc::c_char {
+    if null_check(option).and_then(|_| null_check(len)).is_err() {
+        return ptr::null_mut();
+    }
+
+    let option = &*(option as *const EspOption);
+    let entries = option.entries.iter().cloned().map(to_cstr).collect::<Vec<*mut libc::c_char>>();
+
+    *len = entries.len() as libc::c_int;
+    Box::into_raw(entries.into_boxed_slice()) as *mut *mut libc::c_char
+}
+
 #[repr(C)]
 pub struct DistinstRecoveryOption;
 
@@ -519,12 +622,25 @@ pub enum DISTINST_INSTALL_OPTION_VARIANT {
     UPGRADE,
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_ERASE_MODE {
+    NONE,
+    DISCARD,
+    NVME_FORMAT,
+    OVERWRITE,
+}
+
 #[repr(C)]
 pub struct DistinstInstallOption {
     tag:          DISTINST_INSTALL_OPTION_VARIANT,
     option:       *const libc::c_void,
     encrypt_pass: *const libc::c_char,
     sectors:      u64,
+    esp:          *const DistinstEspOption,
+    erase_mode:   DISTINST_ERASE_MODE,
+    erase_passes: u8,
+    encrypt_boot: bool,
 }
 
 impl<'a> From<&'a DistinstInstallOption> for InstallOption<'a> {
@@ -552,8 +668,17 @@ impl<'a> From<&'a DistinstInstallOption> for InstallOption<'a> {
                     InstallOption::Refresh(&*(opt.option as *const RefreshOption))
                 }
                 DISTINST_INSTALL_OPTION_VARIANT::ERASE => InstallOption::Erase {
-                    option:   &*(opt.option as *const EraseOption),
-                    password: get_passwd(),
+                    option:       &*(opt.option as *const EraseOption),
+                    password:     get_passwd(),
+                    encrypt_boot: opt.encrypt_boot,
+                    erase:        match opt.erase_mode {
+                        DISTINST_ERASE_MODE::NONE => None,
+                        DISTINST_ERASE_MODE::DISCARD => Some(EraseMode::Discard),
+                        DISTINST_ERASE_MODE::NVME_FORMAT => Some(EraseMode::NvmeFormat),
+                        DISTINST_ERASE_MODE::OVERWRITE => {
+                            Some(EraseMode::Overwrite(opt.erase_passes))
+                        }
+                    },
                 },
                 DISTINST_INSTALL_OPTION_VARIANT::UPGRADE => {
                     InstallOption::Upgrade(&*(opt.option as *const RecoveryOption))
@@ -570,6 +695,10 @@ pub unsafe extern "C" fn distinst_install_option_new() -> *mut DistinstInstallOp
         option:       ptr::null(),
         encrypt_pass: ptr::null(),
         sectors:      0,
+        esp:          ptr::null(),
+        erase_mode:   DISTINST_ERASE_MODE::NONE,
+        erase_passes: 1,
+        encrypt_boot: false,
     }))
 }
 
@@ -591,7 +720,10 @@ pub unsafe extern "C" fn distinst_install_option_apply(
         return libc::EIO;
     }
 
-    match InstallOption::from(&*option).apply(&mut *(disks as *mut Disks)) {
+    let esp = (*option).esp as *const EspOption;
+    let esp = if esp.is_null() { None } else { Some(&*esp) };
+
+    match InstallOption::from(&*option).apply_with_esp(&mut *(disks as *mut Disks), esp) {
         Ok(()) => 0,
         Err(why) => {
             error!("failed to apply install option: {}", why);
@@ -737,3 +869,49 @@ pub unsafe extern "C" fn distinst_install_options_get_recovery_option(
         .map(|opt| opt as *const RecoveryOption as *const DistinstRecoveryOption)
         .unwrap_or(ptr::null())
 }
+
+/// Whether disks are hidden by an Intel storage controller in RAID mode, which must be switched to
+/// AHCI mode in the firmware before they can be installed to.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_install_options_has_intel_raid_mode(
+    options: *const DistinstInstallOptions,
+) -> bool {
+    if null_check(options).is_err() {
+        return false;
+    }
+
+    let options = &*(options as *const InstallOptions);
+    options.intel_raid_mode.is_some()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_install_options_has_esp_options(
+    options: *const DistinstInstallOptions,
+) -> bool {
+    if null_check(options).is_err() {
+        return false;
+    }
+
+    let options = &*(options as *const InstallOptions);
+    !options.esp_options.is_empty()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_install_options_get_esp_options(
+    options: *const DistinstInstallOptions,
+    len: *mut libc::c_int,
+) -> *mut *const DistinstEspOption {
+    if null_check(options).and_then(|_| null_check(len)).is_err() {
+        return ptr::null_mut();
+    }
+
+    let options = &*(options as *const InstallOptions);
+
+    let mut output: Vec<*const DistinstEspOption> = Vec::new();
+    for option in &options.esp_options {
+        output.push(option as *const EspOption as *const DistinstEspOption);
+    }
+
+    *len = output.len() as libc::c_int;
+    Box::into_raw(output.into_boxed_slice()) as *mut *const DistinstEspOption
+}
@@ -1,35 +1,606 @@
-use distinst::{Config, UserAccountCreate};
-use crate::get_str;
+use distinst::{
+    auto::FileBackup, CleanupProfile, Config, EspPolicy, ExtractBackend, ExtractOptions,
+    ExtractVerification, FirstBootService, FlatpakRemote, HostEntry, ImageVerification,
+    InstallFlags, IoPriority, OfflineRepository, SudoAccess, TangBinding, TargetFile,
+    TargetFileSource, TrimPolicy, UserAccountCreate, UserBackend,
+};
+use crate::{get_str, null_check};
 use libc;
-use std::io;
+use std::{io, path::PathBuf, slice};
 
 /// Installer configuration
+///
+/// The layout of this struct changed in 0.5.0: `flags` was widened from a `uint8_t` to a
+/// `uint32_t`, and fields were appended to it. Callers which were built against an older
+/// header must be rebuilt.
 #[repr(C)]
 #[derive(Debug)]
 pub struct DistinstConfig {
-    hostname:         *const libc::c_char,
-    keyboard_layout:  *const libc::c_char,
-    keyboard_model:   *const libc::c_char,
-    keyboard_variant: *const libc::c_char,
-    old_root:         *const libc::c_char,
-    lang:             *const libc::c_char,
-    remove:           *const libc::c_char,
-    squashfs:         *const libc::c_char,
-    flags:            u8,
+    hostname:                   *const libc::c_char,
+    keyboard_layout:            *const libc::c_char,
+    keyboard_model:             *const libc::c_char,
+    keyboard_variant:           *const libc::c_char,
+    old_root:                   *const libc::c_char,
+    lang:                       *const libc::c_char,
+    remove:                     *const libc::c_char,
+    squashfs:                   *const libc::c_char,
+    /// The `DISTINST_INSTALL_FLAG` bits which are set.
+    flags:                      u32,
+    bios_device:                *const libc::c_char,
+    files:                      *const DistinstTargetFile,
+    files_len:                  libc::size_t,
+    dconf:                      *const DistinstDconfSetting,
+    dconf_len:                  libc::size_t,
+    minimal_remove:             *const libc::c_char,
+    hosts:                      *const DistinstHostEntry,
+    hosts_len:                  libc::size_t,
+    additional_locales:         *const *const libc::c_char,
+    additional_locales_len:     libc::size_t,
+    locale_overrides:           *const DistinstLocaleOverride,
+    locale_overrides_len:       libc::size_t,
+    rtc:                        DISTINST_RTC,
+    cleanup:                    DISTINST_CLEANUP,
+    post_install_scripts:       *const *const libc::c_char,
+    post_install_scripts_len:   libc::size_t,
+    first_boot_services:        *const DistinstFirstBootService,
+    first_boot_services_len:    libc::size_t,
+    /// A flat apt repository which packages are installed from, or null.
+    offline_repository:         *const libc::c_char,
+    /// The keyring which verifies the offline repository, or null to trust it.
+    offline_repository_keyring: *const libc::c_char,
+    flatpak_remotes:            *const DistinstFlatpakRemote,
+    flatpak_remotes_len:        libc::size_t,
+    /// Flatpaks which are installed system-wide, by their application ID or ref.
+    flatpaks:                   *const *const libc::c_char,
+    flatpaks_len:               libc::size_t,
+    /// The variant of the kernel in the casper directory, such as `hwe`, or null for the
+    /// generic kernel.
+    kernel_variant:             *const libc::c_char,
+    /// A `SHA256SUMS` file which lists the digest of the image, or null.
+    image_sha256sums:           *const libc::c_char,
+    /// A detached GPG signature of the image, which is verified with the `image_keyring`.
+    image_signature:            *const libc::c_char,
+    image_keyring:              *const libc::c_char,
+    /// Compares the extracted files with the files of the image.
+    verify_extraction:          bool,
+    /// A manifest of digests which the extracted files are compared with instead, or null.
+    extraction_manifest:        *const libc::c_char,
+    /// The partition which files are copied from before the disks are modified, or null.
+    backup_source:              *const libc::c_char,
+    /// The files and directories to copy, relative to the root of the source.
+    backup_paths:               *const *const libc::c_char,
+    backup_paths_len:           libc::size_t,
+    /// The partition which the files are copied to, which the install must leave untouched.
+    backup_destination:         *const libc::c_char,
+    /// The directory within the destination, or null for its root.
+    backup_directory:           *const libc::c_char,
+    /// Whether an existing ESP may be formatted.
+    esp_policy:                 DISTINST_ESP_POLICY,
+    /// How file systems on disks which support discard are trimmed.
+    trim_policy:                DISTINST_TRIM_POLICY,
+    /// A Tang server which the encrypted volumes are bound to with clevis, or null.
+    tang_url:                   *const libc::c_char,
+    /// The thumbprint of the Tang server's signing key, which is required with `tang_url`.
+    tang_thumbprint:            *const libc::c_char,
+    /// The number of threads which decompress the image, or 0 to select it from the machine.
+    extract_threads:            libc::size_t,
+    /// The I/O scheduling class of the extraction.
+    extract_io_priority:        DISTINST_IO_PRIORITY,
+    /// The level of a realtime or best-effort priority, from 0, the highest, to 7.
+    extract_io_level:           u8,
+    /// What extracts a squashfs image.
+    extract_backend:            DISTINST_EXTRACT_BACKEND,
+}
+
+/// Whether the hardware clock of the installed system keeps local time or UTC.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_RTC {
+    /// Keeps local time when Windows is found on the system, or else UTC.
+    AUTO,
+    UTC,
+    LOCAL,
+}
+
+/// What is removed from the installed system once it has been configured.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_CLEANUP {
+    NONE,
+    /// Downloaded packages, the package caches of apt, journals, and temporary files.
+    STANDARD,
+    /// Also the package lists of apt, for systems whose sources will change on first boot.
+    FULL,
+}
+
+/// Whether an existing ESP may be formatted, or is reused with the boot files that it holds.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_ESP_POLICY {
+    /// The ESP is formatted when the disk configuration formats it.
+    FORMAT,
+    /// The ESP is never formatted, and must have room for the boot files.
+    REUSE,
+    /// As `REUSE`, after removing the boot files of systems which no longer exist.
+    REUSE_WITH_CLEANUP,
+}
+
+/// How file systems on disks which support discard, such as SSDs, are trimmed.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_TRIM_POLICY {
+    /// Unused blocks are trimmed weekly by `fstrim.timer`, except within encrypted volumes.
+    PERIODIC,
+    /// File systems are mounted with `discard`, and encrypted volumes pass discards on.
+    CONTINUOUS,
+    DISABLED,
+}
+
+/// The I/O scheduling class with which the image is extracted.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_IO_PRIORITY {
+    /// The class of the calling process.
+    INHERIT,
+    REALTIME,
+    BEST_EFFORT,
+    /// Served only when no other process has I/O pending.
+    IDLE,
+}
+
+/// What extracts a squashfs image.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_EXTRACT_BACKEND {
+    /// unsquashfs when it is installed, and otherwise the in-process extractor.
+    AUTO,
+    UNSQUASHFS,
+    IN_PROCESS,
+}
+
+/// Flags which control the behavior of an installation.
+///
+/// Each flag is a distinct bit of `DistinstConfig.flags`, so that they may be OR'd together.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub enum DISTINST_INSTALL_FLAG {
+    MODIFY_BOOT_ORDER = 1,
+    INSTALL_HARDWARE_SUPPORT = 2,
+    KEEP_OLD_ROOT = 4,
+    RUN_UBUNTU_DRIVERS = 8,
+    JOURNAL_COMMANDS = 16,
+    RENAME_CONFLICTING_VOLUME_GROUPS = 32,
+    ENABLE_MDNS = 64,
+    READ_ONLY_ROOT = 128,
+    SKIP_INITRAMFS_KEYMAP = 256,
+    RESTORE_PACKAGES = 512,
+    MAKE_MBR_GAP = 1024,
+}
+
+impl From<DISTINST_INSTALL_FLAG> for InstallFlags {
+    fn from(flag: DISTINST_INSTALL_FLAG) -> Self {
+        use DISTINST_INSTALL_FLAG::*;
+        match flag {
+            MODIFY_BOOT_ORDER => InstallFlags::MODIFY_BOOT_ORDER,
+            INSTALL_HARDWARE_SUPPORT => InstallFlags::INSTALL_HARDWARE_SUPPORT,
+            KEEP_OLD_ROOT => InstallFlags::KEEP_OLD_ROOT,
+            RUN_UBUNTU_DRIVERS => InstallFlags::RUN_UBUNTU_DRIVERS,
+            JOURNAL_COMMANDS => InstallFlags::JOURNAL_COMMANDS,
+            RENAME_CONFLICTING_VOLUME_GROUPS => InstallFlags::RENAME_CONFLICTING_VOLUME_GROUPS,
+            ENABLE_MDNS => InstallFlags::ENABLE_MDNS,
+            READ_ONLY_ROOT => InstallFlags::READ_ONLY_ROOT,
+            SKIP_INITRAMFS_KEYMAP => InstallFlags::SKIP_INITRAMFS_KEYMAP,
+            RESTORE_PACKAGES => InstallFlags::RESTORE_PACKAGES,
+            MAKE_MBR_GAP => InstallFlags::MAKE_MBR_GAP,
+        }
+    }
+}
+
+/// Checks if an installation flag is set in the config.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_config_get_flag(
+    config: *const DistinstConfig,
+    flag: DISTINST_INSTALL_FLAG,
+) -> bool {
+    if null_check(config).is_err() {
+        return false;
+    }
+
+    InstallFlags::from_bits_truncate((*config).flags).contains(flag.into())
+}
+
+/// Sets, or clears, an installation flag in the config.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_config_set_flag(
+    config: *mut DistinstConfig,
+    flag: DISTINST_INSTALL_FLAG,
+    enabled: bool,
+) {
+    if null_check(config).is_err() {
+        return;
+    }
+
+    let mut flags = InstallFlags::from_bits_truncate((*config).flags);
+    flags.set(flag.into(), enabled);
+    (*config).flags = flags.bits();
+}
+
+/// Measures the files of the backup, and the space which is free at its destination, in bytes.
+///
+/// Returns 0 on success, and 1 if the config has no backup, or its partitions or paths were
+/// not found.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_config_backup_space(
+    config: *const DistinstConfig,
+    required: *mut u64,
+    available: *mut u64,
+) -> libc::c_int {
+    if null_check(config).and(null_check(required)).and(null_check(available)).is_err() {
+        return 1;
+    }
+
+    match (*config).file_backup() {
+        Ok(Some(backup)) => match backup.space() {
+            Ok((bytes, free)) => {
+                *required = bytes;
+                *available = free;
+                0
+            }
+            Err(why) => {
+                error!("unable to measure the backup: {}", why);
+                1
+            }
+        },
+        Ok(None) => 1,
+        Err(why) => {
+            error!("invalid backup configuration: {}", why);
+            1
+        }
+    }
 }
 
 impl DistinstConfig {
     pub unsafe fn as_config(&self) -> io::Result<Config> {
         Ok(Config {
-            squashfs:         get_str(self.squashfs)?.to_string(),
-            hostname:         get_str(self.hostname)?.to_string(),
-            lang:             get_str(self.lang)?.to_string(),
-            keyboard_layout:  get_str(self.keyboard_layout)?.to_string(),
-            keyboard_model:   get_str(self.keyboard_model).ok().map(String::from),
-            keyboard_variant: get_str(self.keyboard_variant).ok().map(String::from),
-            old_root:         get_str(self.old_root).ok().map(String::from),
-            remove:           get_str(self.remove)?.to_string(),
-            flags:            self.flags,
+            squashfs:             get_str(self.squashfs)?.to_string(),
+            hostname:             get_str(self.hostname)?.to_string(),
+            lang:                 get_str(self.lang)?.to_string(),
+            additional_locales:   self.additional_locales()?,
+            locale_overrides:     self.locale_overrides()?,
+            keyboard_layout:      get_str(self.keyboard_layout)?.to_string(),
+            keyboard_model:       get_str(self.keyboard_model).ok().map(String::from),
+            keyboard_variant:     get_str(self.keyboard_variant).ok().map(String::from),
+            old_root:             get_str(self.old_root).ok().map(String::from),
+            remove:               get_str(self.remove)?.to_string(),
+            minimal_remove:       get_str(self.minimal_remove).ok().map(String::from),
+            bios_device:          get_str(self.bios_device).ok().map(String::from),
+            flags:                InstallFlags::from_bits_truncate(self.flags),
+            files:                self.target_files()?,
+            dconf:                self.dconf_settings()?,
+            hosts:                self.host_entries()?,
+            rtc_in_localtime:     match self.rtc {
+                DISTINST_RTC::AUTO => None,
+                DISTINST_RTC::UTC => Some(false),
+                DISTINST_RTC::LOCAL => Some(true),
+            },
+            cleanup:              match self.cleanup {
+                DISTINST_CLEANUP::NONE => CleanupProfile::None,
+                DISTINST_CLEANUP::STANDARD => CleanupProfile::Standard,
+                DISTINST_CLEANUP::FULL => CleanupProfile::Full,
+            },
+            post_install_scripts: self.post_install_scripts()?,
+            first_boot_services:  self.first_boot_services()?,
+            offline_repository:   get_str(self.offline_repository).ok().map(|path| {
+                OfflineRepository {
+                    path:    PathBuf::from(path),
+                    keyring: get_str(self.offline_repository_keyring).ok().map(PathBuf::from),
+                }
+            }),
+            flatpak_remotes:      self.flatpak_remotes()?,
+            flatpaks:             self.flatpaks()?,
+            kernel_variant:       get_str(self.kernel_variant).ok().map(String::from),
+            image_verification:   self.image_verification()?,
+            extract_verification: self.extract_verification(),
+            file_backup:          self.file_backup()?,
+            esp_policy:           match self.esp_policy {
+                DISTINST_ESP_POLICY::FORMAT => EspPolicy::Format,
+                DISTINST_ESP_POLICY::REUSE => EspPolicy::Reuse,
+                DISTINST_ESP_POLICY::REUSE_WITH_CLEANUP => EspPolicy::ReuseWithCleanup,
+            },
+            trim_policy:          match self.trim_policy {
+                DISTINST_TRIM_POLICY::PERIODIC => TrimPolicy::Periodic,
+                DISTINST_TRIM_POLICY::CONTINUOUS => TrimPolicy::Continuous,
+                DISTINST_TRIM_POLICY::DISABLED => TrimPolicy::Disabled,
+            },
+            tang_binding:         self.tang_binding()?,
+            extract_options:      self.extract_options(),
+        })
+    }
+
+    fn extract_options(&self) -> ExtractOptions {
+        let level = self.extract_io_level;
+        ExtractOptions {
+            threads:     Some(self.extract_threads).filter(|&threads| threads != 0),
+            io_priority: match self.extract_io_priority {
+                DISTINST_IO_PRIORITY::INHERIT => None,
+                DISTINST_IO_PRIORITY::REALTIME => Some(IoPriority::Realtime(level)),
+                DISTINST_IO_PRIORITY::BEST_EFFORT => Some(IoPriority::BestEffort(level)),
+                DISTINST_IO_PRIORITY::IDLE => Some(IoPriority::Idle),
+            },
+            backend:     match self.extract_backend {
+                DISTINST_EXTRACT_BACKEND::AUTO => ExtractBackend::Auto,
+                DISTINST_EXTRACT_BACKEND::UNSQUASHFS => ExtractBackend::Unsquashfs,
+                DISTINST_EXTRACT_BACKEND::IN_PROCESS => ExtractBackend::InProcess,
+            },
+        }
+    }
+
+    unsafe fn tang_binding(&self) -> io::Result<Option<TangBinding>> {
+        if self.tang_url.is_null() {
+            return Ok(None);
+        }
+
+        let thumbprint =
+            if self.tang_thumbprint.is_null() { "" } else { get_str(self.tang_thumbprint)? };
+
+        let tang = TangBinding {
+            url:        get_str(self.tang_url)?.to_owned(),
+            thumbprint: thumbprint.to_owned(),
+        };
+
+        tang.validate()?;
+        Ok(Some(tang))
+    }
+
+    unsafe fn file_backup(&self) -> io::Result<Option<FileBackup>> {
+        let source = match get_str(self.backup_source) {
+            Ok(source) => PathBuf::from(source),
+            Err(_) => return Ok(None),
+        };
+
+        let paths = if self.backup_paths.is_null() {
+            Vec::new()
+        } else {
+            slice::from_raw_parts(self.backup_paths, self.backup_paths_len)
+                .iter()
+                .map(|&path| get_str(path).map(PathBuf::from))
+                .collect::<io::Result<Vec<PathBuf>>>()?
+        };
+
+        let directory =
+            get_str(self.backup_directory).map_or_else(|_| PathBuf::new(), PathBuf::from);
+        Ok(Some(FileBackup {
+            source,
+            paths,
+            destination: PathBuf::from(get_str(self.backup_destination)?),
+            directory,
+        }))
+    }
+
+    fn extract_verification(&self) -> Option<ExtractVerification> {
+        match get_str(self.extraction_manifest) {
+            Ok(manifest) => Some(ExtractVerification::Manifest(PathBuf::from(manifest))),
+            Err(_) if self.verify_extraction => Some(ExtractVerification::Image),
+            Err(_) => None,
+        }
+    }
+
+    fn image_verification(&self) -> io::Result<Option<ImageVerification>> {
+        if let Ok(sums) = get_str(self.image_sha256sums) {
+            return Ok(Some(ImageVerification::Sha256Sums(PathBuf::from(sums))));
+        }
+
+        match get_str(self.image_signature) {
+            Ok(signature) => Ok(Some(ImageVerification::Signature {
+                signature: PathBuf::from(signature),
+                keyring:   PathBuf::from(get_str(self.image_keyring)?),
+            })),
+            Err(_) => Ok(None),
+        }
+    }
+
+    unsafe fn target_files(&self) -> io::Result<Vec<TargetFile>> {
+        if self.files.is_null() {
+            return Ok(Vec::new());
+        }
+
+        slice::from_raw_parts(self.files, self.files_len)
+            .iter()
+            .map(|file| file.as_file())
+            .collect()
+    }
+
+    unsafe fn dconf_settings(&self) -> io::Result<Vec<(String, String)>> {
+        if self.dconf.is_null() {
+            return Ok(Vec::new());
+        }
+
+        slice::from_raw_parts(self.dconf, self.dconf_len)
+            .iter()
+            .map(|setting| {
+                Ok((get_str(setting.key)?.to_owned(), get_str(setting.value)?.to_owned()))
+            })
+            .collect()
+    }
+
+    unsafe fn additional_locales(&self) -> io::Result<Vec<String>> {
+        if self.additional_locales.is_null() {
+            return Ok(Vec::new());
+        }
+
+        slice::from_raw_parts(self.additional_locales, self.additional_locales_len)
+            .iter()
+            .map(|&locale| get_str(locale).map(String::from))
+            .collect()
+    }
+
+    unsafe fn post_install_scripts(&self) -> io::Result<Vec<PathBuf>> {
+        if self.post_install_scripts.is_null() {
+            return Ok(Vec::new());
+        }
+
+        slice::from_raw_parts(self.post_install_scripts, self.post_install_scripts_len)
+            .iter()
+            .map(|&script| get_str(script).map(PathBuf::from))
+            .collect()
+    }
+
+    unsafe fn locale_overrides(&self) -> io::Result<Vec<(String, String)>> {
+        if self.locale_overrides.is_null() {
+            return Ok(Vec::new());
+        }
+
+        slice::from_raw_parts(self.locale_overrides, self.locale_overrides_len)
+            .iter()
+            .map(|entry| {
+                Ok((get_str(entry.category)?.to_owned(), get_str(entry.locale)?.to_owned()))
+            })
+            .collect()
+    }
+
+    unsafe fn first_boot_services(&self) -> io::Result<Vec<FirstBootService>> {
+        if self.first_boot_services.is_null() {
+            return Ok(Vec::new());
+        }
+
+        slice::from_raw_parts(self.first_boot_services, self.first_boot_services_len)
+            .iter()
+            .map(|service| service.as_service())
+            .collect()
+    }
+
+    unsafe fn flatpak_remotes(&self) -> io::Result<Vec<FlatpakRemote>> {
+        if self.flatpak_remotes.is_null() {
+            return Ok(Vec::new());
+        }
+
+        slice::from_raw_parts(self.flatpak_remotes, self.flatpak_remotes_len)
+            .iter()
+            .map(|remote| {
+                Ok(FlatpakRemote {
+                    name: get_str(remote.name)?.to_owned(),
+                    url:  get_str(remote.url)?.to_owned(),
+                })
+            })
+            .collect()
+    }
+
+    unsafe fn flatpaks(&self) -> io::Result<Vec<String>> {
+        if self.flatpaks.is_null() {
+            return Ok(Vec::new());
+        }
+
+        slice::from_raw_parts(self.flatpaks, self.flatpaks_len)
+            .iter()
+            .map(|&flatpak| get_str(flatpak).map(String::from))
+            .collect()
+    }
+
+    unsafe fn host_entries(&self) -> io::Result<Vec<HostEntry>> {
+        if self.hosts.is_null() {
+            return Ok(Vec::new());
+        }
+
+        slice::from_raw_parts(self.hosts, self.hosts_len)
+            .iter()
+            .map(|host| host.as_entry())
+            .collect()
+    }
+}
+
+/// A service unit which runs once, on the first boot of the installed system. If `contents` is
+/// null, the unit at `source` will be copied instead.
+#[repr(C)]
+#[derive(Debug)]
+pub struct DistinstFirstBootService {
+    name:     *const libc::c_char,
+    contents: *const libc::c_char,
+    source:   *const libc::c_char,
+}
+
+impl DistinstFirstBootService {
+    unsafe fn as_service(&self) -> io::Result<FirstBootService> {
+        let source = if self.contents.is_null() {
+            TargetFileSource::Path(PathBuf::from(get_str(self.source)?))
+        } else {
+            TargetFileSource::Contents(get_str(self.contents)?.as_bytes().to_vec())
+        };
+
+        Ok(FirstBootService { name: get_str(self.name)?.to_owned(), source })
+    }
+}
+
+/// A remote which flatpaks are installed from, by the URL of its `.flatpakrepo` file.
+#[repr(C)]
+#[derive(Debug)]
+pub struct DistinstFlatpakRemote {
+    name: *const libc::c_char,
+    url:  *const libc::c_char,
+}
+
+/// An entry of the hosts file, where `names` are separated by whitespace.
+#[repr(C)]
+#[derive(Debug)]
+pub struct DistinstHostEntry {
+    address: *const libc::c_char,
+    names:   *const libc::c_char,
+}
+
+impl DistinstHostEntry {
+    unsafe fn as_entry(&self) -> io::Result<HostEntry> {
+        let address = get_str(self.address)?;
+        let address = address.parse().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("invalid address: {}", address))
+        })?;
+
+        let names = get_str(self.names)?.split_whitespace().map(String::from).collect();
+        Ok(HostEntry { address, names })
+    }
+}
+
+/// A locale category, such as `LC_TIME`, which takes its formats from another locale.
+#[repr(C)]
+#[derive(Debug)]
+pub struct DistinstLocaleOverride {
+    category: *const libc::c_char,
+    locale:   *const libc::c_char,
+}
+
+/// A default dconf setting, where the value is in the GVariant text format.
+#[repr(C)]
+#[derive(Debug)]
+pub struct DistinstDconfSetting {
+    key:   *const libc::c_char,
+    value: *const libc::c_char,
+}
+
+/// A file that will be written into the installed system. If `contents` is null, the file at
+/// `source` will be copied instead.
+#[repr(C)]
+#[derive(Debug)]
+pub struct DistinstTargetFile {
+    path:         *const libc::c_char,
+    mode:         u32,
+    owner:        *const libc::c_char,
+    contents:     *const u8,
+    contents_len: libc::size_t,
+    source:       *const libc::c_char,
+}
+
+impl DistinstTargetFile {
+    unsafe fn as_file(&self) -> io::Result<TargetFile> {
+        let source = if self.contents.is_null() {
+            TargetFileSource::Path(PathBuf::from(get_str(self.source)?))
+        } else {
+            let contents = slice::from_raw_parts(self.contents, self.contents_len);
+            TargetFileSource::Contents(contents.to_vec())
+        };
+
+        Ok(TargetFile {
+            path:  PathBuf::from(get_str(self.path)?),
+            mode:  self.mode,
+            owner: get_str(self.owner).ok().map(String::from),
+            source,
         })
     }
 }
@@ -40,6 +611,33 @@ pub struct DistinstUserAccountCreate {
     pub realname: *const libc::c_char,
     pub password: *const libc::c_char,
     pub profile_icon: *const libc::c_char,
+    pub ssh_authorized_keys: *const *const libc::c_char,
+    pub ssh_authorized_keys_len: libc::size_t,
+    pub shell: *const libc::c_char,
+    /// Supplementary groups of the user, or null for the administrator groups.
+    pub groups: *const *const libc::c_char,
+    pub groups_len: libc::size_t,
+    pub system: bool,
+    pub sudo: DISTINST_SUDO,
+    pub backend: DISTINST_USER_BACKEND,
+}
+
+/// The access to `sudo` which is granted to a user, in addition to that of their groups.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_SUDO {
+    NONE,
+    PASSWORD,
+    NO_PASSWORD,
+}
+
+/// What creates a user account, and manages its home directory.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_USER_BACKEND {
+    PASSWD,
+    /// systemd-homed, which creates the user on the first boot, with an encrypted home.
+    HOMED,
 }
 
 impl DistinstUserAccountCreate {
@@ -49,6 +647,41 @@ impl DistinstUserAccountCreate {
             realname: get_str(self.realname).ok().map(String::from),
             password: get_str(self.password).ok().map(String::from),
             profile_icon: get_str(self.profile_icon).ok().map(String::from),
+            ssh_authorized_keys: self.ssh_authorized_keys()?,
+            shell: get_str(self.shell).ok().map(String::from),
+            groups: self.groups()?,
+            system: self.system,
+            sudo: match self.sudo {
+                DISTINST_SUDO::NONE => None,
+                DISTINST_SUDO::PASSWORD => Some(SudoAccess::Password),
+                DISTINST_SUDO::NO_PASSWORD => Some(SudoAccess::NoPassword),
+            },
+            backend: match self.backend {
+                DISTINST_USER_BACKEND::PASSWD => UserBackend::Passwd,
+                DISTINST_USER_BACKEND::HOMED => UserBackend::Homed,
+            },
         })
     }
+
+    unsafe fn groups(&self) -> io::Result<Vec<String>> {
+        if self.groups.is_null() {
+            return Ok(UserAccountCreate::default_groups(self.system));
+        }
+
+        slice::from_raw_parts(self.groups, self.groups_len)
+            .iter()
+            .map(|&group| get_str(group).map(String::from))
+            .collect()
+    }
+
+    unsafe fn ssh_authorized_keys(&self) -> io::Result<Vec<String>> {
+        if self.ssh_authorized_keys.is_null() {
+            return Ok(Vec::new());
+        }
+
+        slice::from_raw_parts(self.ssh_authorized_keys, self.ssh_authorized_keys_len)
+            .iter()
+            .map(|&key| get_str(key).map(String::from))
+            .collect()
+    }
 }
@@ -2,18 +2,20 @@ use libc;
 
 use std::{
     ffi::{CStr, CString, OsStr},
+    io,
     os::unix::ffi::OsStrExt,
-    path::Path,
+    path::{Path, PathBuf},
     ptr,
 };
 
 use distinst::{
-    BlockDeviceExt, DecryptionError, Disk, DiskExt, Disks, FileSystem, LogicalDevice,
-    LvmEncryption, PartitionBuilder, PartitionInfo, PartitionTable, PartitionTableExt, Sector,
-    SectorExt,
+    AlignmentIssueKind, BlockDeviceExt, Bootloader, DecryptionError, Disk, DiskExt, Disks,
+    FileSystem, LogicalDevice, LvmEncryption, PartitionBuilder, PartitionInfo, PartitionTable,
+    PartitionTableExt, RaidFormat, Sector, SectorExt, SmartHealth, Transport, ValidationIssueKind,
 };
 
-use super::{get_str, null_check};
+use super::{get_str, null_check, to_cstr};
+use external::{iscsi_login, nvme_connect};
 use crate::ffi::AsMutPtr;
 use crate::filesystem::DISTINST_FILE_SYSTEM;
 use crate::gen_object_ptr;
@@ -103,6 +105,220 @@ pub unsafe extern "C" fn distinst_disk_get_serial(
     serial.as_bytes().as_ptr()
 }
 
+/// The format of the RAID metadata on a disk
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_RAID_FORMAT {
+    NONE,
+    MD,
+    IMSM,
+    DDF,
+}
+
+/// Identifies RAID metadata on the disk, which is wiped if a new partition table is written.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disk_get_raid_format(
+    disk: *const DistinstDisk,
+) -> DISTINST_RAID_FORMAT {
+    if null_check(disk).is_err() {
+        return DISTINST_RAID_FORMAT::NONE;
+    }
+
+    let disk = &*(disk as *const Disk);
+    match disk.raid.as_ref().map(|raid| raid.format) {
+        None => DISTINST_RAID_FORMAT::NONE,
+        Some(RaidFormat::Md) => DISTINST_RAID_FORMAT::MD,
+        Some(RaidFormat::Imsm) => DISTINST_RAID_FORMAT::IMSM,
+        Some(RaidFormat::Ddf) => DISTINST_RAID_FORMAT::DDF,
+    }
+}
+
+/// Whether the disk is a member of an Intel RST array which is in use, and may not be
+/// partitioned on its own.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disk_is_rst_member(disk: *const DistinstDisk) -> bool {
+    if null_check(disk).is_err() {
+        return false;
+    }
+
+    (&*(disk as *const Disk)).is_rst_member()
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_SMART_HEALTH {
+    PASSED,
+    FAILED,
+    UNKNOWN,
+}
+
+/// The SMART health of a drive. Values which the drive did not report are set to -1.
+#[repr(C)]
+#[derive(Debug)]
+pub struct DistinstSmartStatus {
+    health:              DISTINST_SMART_HEALTH,
+    reallocated_sectors: i64,
+    pending_sectors:     i64,
+    percentage_used:     libc::c_int,
+    /// The drive is failing, or is likely to fail soon.
+    failing:             bool,
+}
+
+/// Queries the SMART health of the drive, which requires `smartctl`.
+///
+/// Returns 0 on success, and -1 if the health could not be obtained.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disk_get_smart_status(
+    disk: *const DistinstDisk,
+    status: *mut DistinstSmartStatus,
+) -> libc::c_int {
+    if null_check(disk).and_then(|_| null_check(status)).is_err() {
+        return -1;
+    }
+
+    let disk = &*(disk as *const Disk);
+    match disk.smart_status() {
+        Ok(smart) => {
+            let count = |value: Option<u64>| value.map_or(-1, |value| value as i64);
+            *status = DistinstSmartStatus {
+                health:              match smart.health {
+                    SmartHealth::Passed => DISTINST_SMART_HEALTH::PASSED,
+                    SmartHealth::Failed => DISTINST_SMART_HEALTH::FAILED,
+                    SmartHealth::Unknown => DISTINST_SMART_HEALTH::UNKNOWN,
+                },
+                reallocated_sectors: count(smart.reallocated_sectors),
+                pending_sectors:     count(smart.pending_sectors),
+                percentage_used:     smart.percentage_used.map_or(-1, libc::c_int::from),
+                failing:             smart.is_failing(),
+            };
+            0
+        }
+        Err(why) => {
+            info!("unable to get SMART status of {}: {}", disk.get_device_path().display(), why);
+            -1
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_TRANSPORT {
+    UNKNOWN,
+    NVME,
+    SATA,
+    USB,
+    MMC,
+    VIRTIO,
+    ISCSI,
+    NVME_OVER_FABRICS,
+}
+
+impl From<Transport> for DISTINST_TRANSPORT {
+    fn from(transport: Transport) -> Self {
+        match transport {
+            Transport::Nvme => DISTINST_TRANSPORT::NVME,
+            Transport::Sata => DISTINST_TRANSPORT::SATA,
+            Transport::Usb => DISTINST_TRANSPORT::USB,
+            Transport::Mmc => DISTINST_TRANSPORT::MMC,
+            Transport::Virtio => DISTINST_TRANSPORT::VIRTIO,
+            Transport::Iscsi => DISTINST_TRANSPORT::ISCSI,
+            Transport::NvmeOverFabrics => DISTINST_TRANSPORT::NVME_OVER_FABRICS,
+            Transport::Unknown => DISTINST_TRANSPORT::UNKNOWN,
+        }
+    }
+}
+
+/// How a drive is attached, and how it reads and writes its sectors.
+#[repr(C)]
+#[derive(Debug)]
+pub struct DistinstDiskTopology {
+    rotational:           bool,
+    discard:              bool,
+    logical_sector_size:  u64,
+    physical_sector_size: u64,
+    transport:            DISTINST_TRANSPORT,
+    /// Set to -1 if the system does not have several NUMA nodes.
+    numa_node:            libc::c_int,
+}
+
+/// Obtains the topology of the drive, which was read from sysfs when the disk was probed.
+///
+/// Returns 0 on success, and -1 if an argument was null.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disk_get_topology(
+    disk: *const DistinstDisk,
+    topology: *mut DistinstDiskTopology,
+) -> libc::c_int {
+    if null_check(disk).and_then(|_| null_check(topology)).is_err() {
+        return -1;
+    }
+
+    let disk = &*(disk as *const Disk);
+    *topology = DistinstDiskTopology {
+        rotational:           disk.topology.rotational,
+        discard:              disk.topology.discard,
+        logical_sector_size:  disk.topology.logical_sector_size,
+        physical_sector_size: disk.topology.physical_sector_size,
+        transport:            disk.topology.transport.into(),
+        numa_node:            disk.topology.numa_node.map_or(-1, |node| node as libc::c_int),
+    };
+
+    0
+}
+
+/// An optional string, which is null when it is not given.
+fn get_optional_str<'a>(ptr: *const libc::c_char) -> io::Result<Option<&'a str>> {
+    if ptr.is_null() {
+        Ok(None)
+    } else {
+        get_str(ptr).map(Some)
+    }
+}
+
+/// Logs in to the `target` of an iSCSI `portal`, or to every target of the portal if `target`
+/// is null. The disks must be probed again for the LUNs of the targets to be listed.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_iscsi_login(
+    portal: *const libc::c_char,
+    target: *const libc::c_char,
+) -> libc::c_int {
+    let result = get_str(portal)
+        .and_then(|portal| get_optional_str(target).map(|target| (portal, target)))
+        .and_then(|(portal, target)| iscsi_login(portal, target));
+
+    match result {
+        Ok(()) => 0,
+        Err(why) => {
+            error!("unable to log in to iSCSI target: {}", why);
+            -1
+        }
+    }
+}
+
+/// Connects to an NVMe subsystem over a fabric. `service` may be null for transports which
+/// have no ports. The disks must be probed again for its namespaces to be listed.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_nvme_connect(
+    transport: *const libc::c_char,
+    address: *const libc::c_char,
+    service: *const libc::c_char,
+    nqn: *const libc::c_char,
+) -> libc::c_int {
+    let result = get_str(transport).and_then(|transport| {
+        let address = get_str(address)?;
+        let service = get_optional_str(service)?;
+        nvme_connect(transport, address, service, get_str(nqn)?)
+    });
+
+    match result {
+        Ok(()) => 0,
+        Err(why) => {
+            error!("unable to connect to NVMe subsystem: {}", why);
+            -1
+        }
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_disk_get_partition(
     disk: *mut DistinstDisk,
@@ -366,6 +582,88 @@ pub unsafe extern "C" fn distinst_disk_move_partition(
     }
 }
 
+/// What is wrong with a partition which is yet to be created.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_ALIGNMENT_ISSUE {
+    MISALIGNED,
+    OVERLAPS,
+    OUT_OF_BOUNDS,
+}
+
+/// A partition which is yet to be created, that libparted may fail to create as it is.
+#[repr(C)]
+pub struct DistinstAlignmentIssue {
+    kind:         DISTINST_ALIGNMENT_ISSUE,
+    start_sector: u64,
+    end_sector:   u64,
+    /// The start sector of the partition which is overlapped, or 0.
+    other:        u64,
+}
+
+/// Finds the new partitions which are misaligned, overlap other partitions, or extend past the
+/// end of the disk.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disk_check_alignment(
+    disk: *const DistinstDisk,
+    len: *mut libc::c_int,
+) -> *mut DistinstAlignmentIssue {
+    if null_check(disk).and_then(|_| null_check(len)).is_err() {
+        return ptr::null_mut();
+    }
+
+    let disk = &*(disk as *const Disk);
+    let output = disk
+        .check_alignment()
+        .into_iter()
+        .map(|issue| {
+            use self::DISTINST_ALIGNMENT_ISSUE::*;
+            let (kind, other) = match issue.kind {
+                AlignmentIssueKind::Misaligned => (MISALIGNED, 0),
+                AlignmentIssueKind::Overlaps { other } => (OVERLAPS, other),
+                AlignmentIssueKind::OutOfBounds => (OUT_OF_BOUNDS, 0),
+            };
+
+            DistinstAlignmentIssue {
+                kind,
+                start_sector: issue.start_sector,
+                end_sector: issue.end_sector,
+                other,
+            }
+        })
+        .collect::<Vec<DistinstAlignmentIssue>>();
+
+    *len = output.len() as libc::c_int;
+    Box::into_raw(output.into_boxed_slice()) as *mut DistinstAlignmentIssue
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_alignment_issues_destroy(
+    issues: *mut DistinstAlignmentIssue,
+    len: libc::size_t,
+) {
+    if !issues.is_null() {
+        drop(Box::from_raw(std::slice::from_raw_parts_mut(issues, len)));
+    }
+}
+
+/// Snaps the new partitions to 1 MiB boundaries, without overlapping their neighbors.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disk_auto_align(disk: *mut DistinstDisk) -> libc::c_int {
+    if null_check(disk).is_err() {
+        return -1;
+    }
+
+    let disk = &mut *(disk as *mut Disk);
+
+    if let Err(why) = disk.auto_align() {
+        info!("unable to align partitions: {}", why);
+        -1
+    } else {
+        0
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_disk_format_partition(
     disk: *mut DistinstDisk,
@@ -598,6 +896,67 @@ pub unsafe extern "C" fn distinst_disks_get_physical_device(
     }
 }
 
+/// The member disks of the Intel RST array which the disk at `path` is a member of, or null if
+/// it is not a member of an array.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disks_get_rst_members(
+    disks: *const DistinstDisks,
+    path: *const libc::c_char,
+    len: *mut libc::c_int,
+) -> *mut *mut libc::c_char {
+    if null_check(disks).and_then(|_| null_check(len)).is_err() {
+        return ptr::null_mut();
+    }
+
+    let path = match get_str(path) {
+        Ok(path) => path,
+        Err(why) => {
+            error!("distinst_disks_get_rst_members: path is not UTF-8: {}", why);
+            return ptr::null_mut();
+        }
+    };
+
+    match (&*(disks as *const Disks)).find_rst_array(path) {
+        Some(array) => {
+            let members = array
+                .members
+                .iter()
+                .map(|member| to_cstr(member.display().to_string()))
+                .collect::<Vec<*mut libc::c_char>>();
+
+            *len = members.len() as libc::c_int;
+            Box::into_raw(members.into_boxed_slice()) as *mut *mut libc::c_char
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+/// The volume which was assembled from the Intel RST array that the disk at `path` is a member
+/// of, such as `/dev/md126`, to which the install should be redirected. Null if the disk is not
+/// a member of an array, or the array was not assembled.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disks_get_rst_volume(
+    disks: *const DistinstDisks,
+    path: *const libc::c_char,
+) -> *mut libc::c_char {
+    if null_check(disks).is_err() {
+        return ptr::null_mut();
+    }
+
+    let path = match get_str(path) {
+        Ok(path) => path,
+        Err(why) => {
+            error!("distinst_disks_get_rst_volume: path is not UTF-8: {}", why);
+            return ptr::null_mut();
+        }
+    };
+
+    (&*(disks as *const Disks))
+        .find_rst_array(path)
+        .and_then(|array| array.volume)
+        .map_or(ptr::null_mut(), |volume| to_cstr(volume.display().to_string()))
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_disks_list_logical(
     disks: *mut DistinstDisks,
@@ -647,6 +1006,156 @@ pub unsafe extern "C" fn distinst_disks_find_partition(
         .unwrap_or(ptr::null_mut())
 }
 
+/// What is wrong with a partition configuration.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_VALIDATION_ISSUE {
+    READ_ONLY,
+    MOUNT_TARGET,
+    MISSING_ROOT,
+    ROOT_FILE_SYSTEM,
+    ROOT_TOO_SMALL,
+    MISSING_ESP,
+    MISSING_BOOT,
+    BOOT_ON_LOGICAL_DEVICE,
+    ESP_WITHOUT_GPT,
+    BOOT_FLAG_MISSING,
+    ESP_FILE_SYSTEM,
+    ESP_TOO_SMALL,
+    BOOT_FILE_SYSTEM,
+    OVERLAPPING_PARTITIONS,
+    SECONDARY_ESP_WITHOUT_EFI,
+    SECONDARY_ESP_SAME_DISK,
+    RST_MEMBER,
+}
+
+impl<'a> From<&'a ValidationIssueKind> for DISTINST_VALIDATION_ISSUE {
+    fn from(kind: &ValidationIssueKind) -> Self {
+        use self::DISTINST_VALIDATION_ISSUE::*;
+        match *kind {
+            ValidationIssueKind::ReadOnly { .. } => READ_ONLY,
+            ValidationIssueKind::MountTarget { .. } => MOUNT_TARGET,
+            ValidationIssueKind::MissingRoot => MISSING_ROOT,
+            ValidationIssueKind::RootFileSystem { .. } => ROOT_FILE_SYSTEM,
+            ValidationIssueKind::RootTooSmall { .. } => ROOT_TOO_SMALL,
+            ValidationIssueKind::MissingEsp => MISSING_ESP,
+            ValidationIssueKind::MissingBoot => MISSING_BOOT,
+            ValidationIssueKind::BootOnLogicalDevice => BOOT_ON_LOGICAL_DEVICE,
+            ValidationIssueKind::EspWithoutGpt => ESP_WITHOUT_GPT,
+            ValidationIssueKind::BootFlagMissing => BOOT_FLAG_MISSING,
+            ValidationIssueKind::EspFileSystem { .. } => ESP_FILE_SYSTEM,
+            ValidationIssueKind::EspTooSmall { .. } => ESP_TOO_SMALL,
+            ValidationIssueKind::BootFileSystem { .. } => BOOT_FILE_SYSTEM,
+            ValidationIssueKind::OverlappingPartitions { .. } => OVERLAPPING_PARTITIONS,
+            ValidationIssueKind::SecondaryEspWithoutEfi => SECONDARY_ESP_WITHOUT_EFI,
+            ValidationIssueKind::SecondaryEspSameDisk => SECONDARY_ESP_SAME_DISK,
+            ValidationIssueKind::RstMember { .. } => RST_MEMBER,
+        }
+    }
+}
+
+/// An issue with a partition configuration, which prevents the install.
+#[repr(C)]
+pub struct DistinstValidationIssue {
+    kind:         DISTINST_VALIDATION_ISSUE,
+    /// The disk or volume group of the offending partition, or null if it concerns none.
+    device:       *mut libc::c_char,
+    /// The device path of the offending partition, or null if it is yet to be created.
+    partition:    *mut libc::c_char,
+    /// The first sector of the offending partition on its device.
+    start_sector: u64,
+    /// A description of the issue, which may be shown to the user.
+    message:      *mut libc::c_char,
+}
+
+/// Validates the partition configuration, returning every issue that was found. The root
+/// partition must have at least `root_minimum` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disks_verify_partitions(
+    disks: *const DistinstDisks,
+    root_minimum: u64,
+    len: *mut libc::c_int,
+) -> *mut DistinstValidationIssue {
+    if null_check(disks).and_then(|_| null_check(len)).is_err() {
+        return ptr::null_mut();
+    }
+
+    let disks = &*(disks as *const Disks);
+    let path = |path: &Path| {
+        if path.as_os_str().is_empty() {
+            ptr::null_mut()
+        } else {
+            to_cstr(path.to_string_lossy().into_owned())
+        }
+    };
+
+    let output = disks
+        .verify_partitions(Bootloader::detect(), root_minimum)
+        .into_iter()
+        .map(|issue| {
+            let (device, partition, start_sector) = match issue.partition {
+                Some(ref at) => (path(&at.device), path(&at.partition), at.start_sector),
+                None => (ptr::null_mut(), ptr::null_mut(), 0),
+            };
+
+            DistinstValidationIssue {
+                kind: (&issue.kind).into(),
+                device,
+                partition,
+                start_sector,
+                message: to_cstr(issue.to_string()),
+            }
+        })
+        .collect::<Vec<DistinstValidationIssue>>();
+
+    *len = output.len() as libc::c_int;
+    Box::into_raw(output.into_boxed_slice()) as *mut DistinstValidationIssue
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_validation_issues_destroy(
+    issues: *mut DistinstValidationIssue,
+    len: libc::size_t,
+) {
+    if issues.is_null() {
+        return;
+    }
+
+    let issues = Box::from_raw(std::slice::from_raw_parts_mut(issues, len));
+    for issue in issues.iter() {
+        for string in &[issue.device, issue.partition, issue.message] {
+            if !string.is_null() {
+                drop(CString::from_raw(*string));
+            }
+        }
+    }
+}
+
+/// Changes the label of the existing file system on the partition at `path`.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disks_relabel(
+    disks: *mut DistinstDisks,
+    path: *const libc::c_char,
+    label: *const libc::c_char,
+) -> libc::c_int {
+    if null_check(disks).is_err() {
+        return -1;
+    }
+
+    let (path, label) = match (get_str(path), get_str(label)) {
+        (Ok(path), Ok(label)) => (path, label),
+        _ => return -1,
+    };
+
+    let disks = &mut *(disks as *mut Disks);
+    if let Err(why) = disks.relabel(Path::new(path), label) {
+        error!("unable to relabel {}: {}", path, why);
+        -1
+    } else {
+        0
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_disks_decrypt_partition(
     disks: *mut DistinstDisks,
@@ -676,7 +1185,7 @@ pub unsafe extern "C" fn distinst_disks_decrypt_partition(
                     Err(why) => {
                         error!("decryption error: {}", why);
                         match why {
-                            DecryptionError::Open { .. } => 4,
+                            DecryptionError::Open { .. } | DecryptionError::WrongKey { .. } => 4,
                             DecryptionError::DecryptedLacksVG { .. } => 5,
                             DecryptionError::LuksNotFound { .. } => 6,
                         }
@@ -686,3 +1195,109 @@ pub unsafe extern "C" fn distinst_disks_decrypt_partition(
         })
     })
 }
+
+/// The outcome of decrypting a LUKS partition.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_DECRYPT_STATUS {
+    SUCCESS,
+    /// A required argument was null or not UTF-8, or neither a password or keyfile was given.
+    INVALID_INPUT,
+    /// The password or keyfile was rejected, so the user may be asked to try again.
+    WRONG_KEY,
+    /// The partition does not exist, or is not a LUKS partition.
+    NOT_FOUND,
+    /// The partition could not be activated.
+    ACTIVATION_FAILED,
+    /// The decrypted partition contains neither a volume group or a file system.
+    NO_VOLUME_GROUP,
+}
+
+/// Decrypts the LUKS partition at `path`, with the password in `enc`, or the `keyfile`.
+///
+/// The `keydata` field of `enc` is not used. If a `keyfile` is given, it takes precedence
+/// over the password.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disks_decrypt_partition_v2(
+    disks: *mut DistinstDisks,
+    path: *const libc::c_char,
+    enc: *const DistinstLvmEncryption,
+    keyfile: *const libc::c_char,
+) -> DISTINST_DECRYPT_STATUS {
+    if null_check(disks)
+        .and_then(|_| null_check(path))
+        .and_then(|_| null_check(enc))
+        .and_then(|_| null_check((*enc).physical_volume))
+        .is_err()
+    {
+        return DISTINST_DECRYPT_STATUS::INVALID_INPUT;
+    }
+
+    let (path, pv) = match (get_str(path), get_str((*enc).physical_volume)) {
+        (Ok(path), Ok(pv)) => (path, pv),
+        _ => return DISTINST_DECRYPT_STATUS::INVALID_INPUT,
+    };
+
+    let password = get_str((*enc).password).ok().map(String::from);
+    let keyfile = get_str(keyfile).ok().map(PathBuf::from);
+    if password.is_none() && keyfile.is_none() {
+        return DISTINST_DECRYPT_STATUS::INVALID_INPUT;
+    }
+
+    let mut enc = LvmEncryption::new(pv.into(), password, None);
+    enc.keyfile = keyfile;
+
+    let disks = &mut *(disks as *mut Disks);
+    match disks.decrypt_partition(Path::new(path), &enc) {
+        Ok(_) => DISTINST_DECRYPT_STATUS::SUCCESS,
+        Err(why) => {
+            error!("decryption error: {}", why);
+            decrypt_status(&why)
+        }
+    }
+}
+
+/// Provides the password of the encrypted partition at `path`, or null to leave it encrypted.
+/// The string remains owned by the caller.
+pub type DistinstUnlockCallback =
+    extern "C" fn(path: *const libc::c_char, user_data: *mut libc::c_void) -> *const libc::c_char;
+
+/// Decrypts every LUKS partition which has not already been decrypted, asking the `callback`
+/// for the password of each. The callback is asked again for a partition if its password was
+/// rejected, until it returns null. If any partition failed to decrypt for another reason, the
+/// status of the first failure is returned, after the remaining partitions were unlocked.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disks_unlock_all(
+    disks: *mut DistinstDisks,
+    callback: DistinstUnlockCallback,
+    user_data: *mut libc::c_void,
+) -> DISTINST_DECRYPT_STATUS {
+    if null_check(disks).is_err() {
+        return DISTINST_DECRYPT_STATUS::INVALID_INPUT;
+    }
+
+    let disks = &mut *(disks as *mut Disks);
+    let unlocked = disks.unlock_all(|path| {
+        let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+        let password = callback(path.as_ptr(), user_data);
+        if password.is_null() {
+            None
+        } else {
+            get_str(password).ok().map(String::from)
+        }
+    });
+
+    unlocked.failed.first().map_or(DISTINST_DECRYPT_STATUS::SUCCESS, decrypt_status)
+}
+
+fn decrypt_status(why: &DecryptionError) -> DISTINST_DECRYPT_STATUS {
+    match *why {
+        DecryptionError::WrongKey { .. } => DISTINST_DECRYPT_STATUS::WRONG_KEY,
+        DecryptionError::LuksNotFound { .. } => DISTINST_DECRYPT_STATUS::NOT_FOUND,
+        DecryptionError::Open { ref why, .. } if why.kind() == io::ErrorKind::NotFound => {
+            DISTINST_DECRYPT_STATUS::NOT_FOUND
+        }
+        DecryptionError::Open { .. } => DISTINST_DECRYPT_STATUS::ACTIVATION_FAILED,
+        DecryptionError::DecryptedLacksVG { .. } => DISTINST_DECRYPT_STATUS::NO_VOLUME_GROUP,
+    }
+}
@@ -0,0 +1,142 @@
+use libc;
+
+use super::{get_str, null_check, to_cstr, DISTINST_FILE_SYSTEM};
+use distinst::{
+    auto::{delete_generation, generation_size, generations, rollback, ReinstallError},
+    FileSystem,
+};
+use std::{ffi::CString, path::Path, ptr, slice};
+
+/// A system which a refresh install retained, and which may be restored.
+#[repr(C)]
+pub struct DistinstGeneration {
+    /// The time that the system was replaced, in seconds since the Unix epoch.
+    id:      u64,
+    /// The pretty name of the release of the system, or null.
+    release: *mut libc::c_char,
+}
+
+/// Lists the generations of the root partition at `device`, with the newest first.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_generations(
+    device: *const libc::c_char,
+    fs: DISTINST_FILE_SYSTEM,
+    len: *mut libc::c_int,
+) -> *mut DistinstGeneration {
+    if null_check(len).is_err() {
+        return ptr::null_mut();
+    }
+
+    let generations = match partition(device, fs).map(|(device, fs)| generations(device, fs)) {
+        Some(Ok(generations)) => generations,
+        Some(Err(why)) => return failed("listing generations", why, ptr::null_mut()),
+        None => return ptr::null_mut(),
+    };
+
+    let output = generations
+        .into_iter()
+        .map(|generation| DistinstGeneration {
+            id:      generation.id,
+            release: generation.release.map_or(ptr::null_mut(), to_cstr),
+        })
+        .collect::<Vec<DistinstGeneration>>();
+
+    *len = output.len() as libc::c_int;
+    Box::into_raw(output.into_boxed_slice()) as *mut DistinstGeneration
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_generations_destroy(
+    generations: *mut DistinstGeneration,
+    len: libc::size_t,
+) {
+    if generations.is_null() {
+        return;
+    }
+
+    let generations = Box::from_raw(slice::from_raw_parts_mut(generations, len));
+    for generation in generations.iter().filter(|generation| !generation.release.is_null()) {
+        drop(CString::from_raw(generation.release));
+    }
+}
+
+/// Measures the bytes of the files of a generation. Returns 0 on success.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_generation_size(
+    device: *const libc::c_char,
+    fs: DISTINST_FILE_SYSTEM,
+    id: u64,
+    size: *mut u64,
+) -> libc::c_int {
+    if null_check(size).is_err() {
+        return 1;
+    }
+
+    match partition(device, fs).map(|(device, fs)| generation_size(device, fs, id)) {
+        Some(Ok(bytes)) => {
+            *size = bytes;
+            0
+        }
+        Some(Err(why)) => failed("measuring generation", why, 1),
+        None => 1,
+    }
+}
+
+/// Restores a generation, retaining the current system in its place, and points the boot loader
+/// at it. The `esp` may be null on BIOS systems. Returns 0 on success.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_generation_restore(
+    device: *const libc::c_char,
+    fs: DISTINST_FILE_SYSTEM,
+    id: u64,
+    esp: *const libc::c_char,
+    esp_fs: DISTINST_FILE_SYSTEM,
+) -> libc::c_int {
+    let esp = if esp.is_null() {
+        None
+    } else {
+        match partition(esp, esp_fs) {
+            Some(esp) => Some(esp),
+            None => return 1,
+        }
+    };
+
+    match partition(device, fs).map(|(device, fs)| rollback(device, fs, id, esp)) {
+        Some(Ok(())) => 0,
+        Some(Err(why)) => failed("restoring generation", why, 1),
+        None => 1,
+    }
+}
+
+/// Deletes a generation. Returns 0 on success.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_generation_delete(
+    device: *const libc::c_char,
+    fs: DISTINST_FILE_SYSTEM,
+    id: u64,
+) -> libc::c_int {
+    match partition(device, fs).map(|(device, fs)| delete_generation(device, fs, id)) {
+        Some(Ok(())) => 0,
+        Some(Err(why)) => failed("deleting generation", why, 1),
+        None => 1,
+    }
+}
+
+/// The path of a partition, and its file system, if both were given.
+fn partition<'a>(
+    device: *const libc::c_char,
+    fs: DISTINST_FILE_SYSTEM,
+) -> Option<(&'a Path, FileSystem)> {
+    let device = get_str(device).ok()?;
+    let fs = Option::<FileSystem>::from(fs);
+    if fs.is_none() {
+        error!("libdistinst: no file system was given for {}", device);
+    }
+
+    fs.map(|fs| (Path::new(device), fs))
+}
+
+fn failed<T>(action: &str, why: ReinstallError, value: T) -> T {
+    error!("libdistinst: {}: {}", action, why);
+    value
+}
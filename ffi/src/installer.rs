@@ -1,11 +1,14 @@
 use libc;
 
-use std::{io, mem};
+use std::{ffi::CString, io, mem, os::unix::ffi::OsStrExt, ptr, slice};
 
 use crate::config::DistinstConfig;
 use crate::disk::DistinstDisks;
-use distinst::{timezones::Region, Disks, Error, Installer, Status, Step};
-use crate::gen_object_ptr;
+use distinst::{
+    timezones::Region, Disks, Error, Event, EventHandle, EventKind, InstallProgress,
+    InstallProgressHandle, Installer, Status, Step,
+};
+use crate::{gen_object_ptr, get_str, null_check, to_cstr};
 use crate::DistinstRegion;
 use crate::DistinstUserAccountCreate;
 
@@ -19,6 +22,8 @@ pub enum DISTINST_STEP {
     EXTRACT,
     CONFIGURE,
     BOOTLOADER,
+    CLEANUP,
+    VERIFY,
 }
 
 impl From<DISTINST_STEP> for Step {
@@ -31,6 +36,8 @@ impl From<DISTINST_STEP> for Step {
             EXTRACT => Step::Extract,
             CONFIGURE => Step::Configure,
             BOOTLOADER => Step::Bootloader,
+            CLEANUP => Step::Cleanup,
+            VERIFY => Step::Verify,
         }
     }
 }
@@ -45,6 +52,8 @@ impl From<Step> for DISTINST_STEP {
             Step::Extract => EXTRACT,
             Step::Configure => CONFIGURE,
             Step::Bootloader => BOOTLOADER,
+            Step::Cleanup => CLEANUP,
+            Step::Verify => VERIFY,
         }
     }
 }
@@ -73,6 +82,49 @@ pub struct DistinstStatus {
 pub type DistinstStatusCallback =
     extern "C" fn(status: *const DistinstStatus, user_data: *mut libc::c_void);
 
+/// Whether an installer event is a status or an error
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub enum DISTINST_EVENT_KIND {
+    STATUS,
+    ERROR,
+}
+
+/// A status or error that was emitted by the installer, with its sequence number
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct DistinstEvent {
+    sequence: u64,
+    kind:     DISTINST_EVENT_KIND,
+    step:     DISTINST_STEP,
+    /// The percentage of the step that has been completed, if this is a status.
+    percent:  libc::c_int,
+    /// The error code, if this is an error.
+    err:      libc::c_int,
+}
+
+impl From<&Event> for DistinstEvent {
+    fn from(event: &Event) -> Self {
+        let (kind, step, percent, err) = match event.kind {
+            EventKind::Status(status) => {
+                (DISTINST_EVENT_KIND::STATUS, status.step, status.percent, 0)
+            }
+            EventKind::Error(ref error) => (
+                DISTINST_EVENT_KIND::ERROR,
+                error.step,
+                0,
+                error.err.raw_os_error().unwrap_or(libc::EIO),
+            ),
+        };
+
+        DistinstEvent { sequence: event.sequence, kind, step: step.into(), percent, err }
+    }
+}
+
+/// Installer event callback
+pub type DistinstEventCallback =
+    extern "C" fn(event: *const DistinstEvent, user_data: *mut libc::c_void);
+
 /// Installer timezone callback
 pub type DistinstTimezoneCallback =
     extern "C" fn(user_data: *mut libc::c_void) -> *const DistinstRegion;
@@ -81,6 +133,73 @@ pub type DistinstTimezoneCallback =
 pub type DistinstUserAccountCallback =
     extern "C" fn(user_account_create: *mut DistinstUserAccountCreate, user_data: *mut libc::c_void);
 
+/// Installer callback which provides several user accounts, as an array that remains owned by
+/// the caller, with its length written to `len`
+pub type DistinstUserAccountsCallback = extern "C" fn(
+    len: *mut libc::size_t,
+    user_data: *mut libc::c_void,
+) -> *const DistinstUserAccountCreate;
+
+/// Installer MOK password callback, which returns a string that remains owned by the caller,
+/// or null to leave DKMS modules unsigned
+pub type DistinstMokPasswordCallback =
+    extern "C" fn(user_data: *mut libc::c_void) -> *const libc::c_char;
+
+/// Installer unmount callback, which receives descriptions of the mounts and swaps of the live
+/// session that hold partitions of the disks to be modified, and returns whether they may be
+/// released.
+pub type DistinstUnmountCallback = extern "C" fn(
+    mounts: *const *const libc::c_char,
+    len: libc::size_t,
+    user_data: *mut libc::c_void,
+) -> bool;
+
+/// Installer failure report consent callback, which receives a description of the failure and
+/// returns whether the user agrees to send a report of it
+pub type DistinstReportConsentCallback =
+    extern "C" fn(message: *const libc::c_char, user_data: *mut libc::c_void) -> bool;
+
+/// Installer failure report upload callback, which receives the path of the report's tarball
+pub type DistinstReportUploadCallback =
+    extern "C" fn(tarball: *const libc::c_char, user_data: *mut libc::c_void);
+
+/// An external command that was executed by the installer
+#[repr(C)]
+#[derive(Debug)]
+pub struct DistinstCommandRecord {
+    command:     *const libc::c_char,
+    /// The exit status, or `-1` if the command was terminated by a signal.
+    status:      libc::c_int,
+    duration_ms: u64,
+    stdout:      *const libc::c_char,
+    stderr:      *const libc::c_char,
+}
+
+/// Installer command journal callback
+pub type DistinstCommandCallback =
+    extern "C" fn(record: *const DistinstCommandRecord, user_data: *mut libc::c_void);
+
+/// The time that the installer took to perform an operation
+#[repr(C)]
+#[derive(Debug)]
+pub struct DistinstMetric {
+    /// The kind and name of the operation, such as `step:Extract` or `command:mkfs.ext4`.
+    operation:   *const libc::c_char,
+    duration_ms: u64,
+}
+
+/// Installer metrics callback
+pub type DistinstMetricCallback =
+    extern "C" fn(metric: *const DistinstMetric, user_data: *mut libc::c_void);
+
+/// Installer post-install script output callback, which receives each line that a script wrote
+pub type DistinstHookOutputCallback = extern "C" fn(
+    script: *const libc::c_char,
+    line: *const libc::c_char,
+    stderr: bool,
+    user_data: *mut libc::c_void,
+);
+
 /// An installer object
 #[repr(C)]
 pub struct DistinstInstaller;
@@ -147,6 +266,134 @@ pub unsafe extern "C" fn distinst_installer_on_status(
     });
 }
 
+/// Set the callback which receives every status and error, with its sequence number
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_on_event(
+    installer: *mut DistinstInstaller,
+    callback: DistinstEventCallback,
+    user_data: *mut libc::c_void,
+) {
+    if null_check(installer).is_err() {
+        return;
+    }
+
+    (*(installer as *mut Installer)).on_event(move |event| {
+        callback(&DistinstEvent::from(event) as *const DistinstEvent, user_data)
+    });
+}
+
+/// A handle to the events retained by an installer, which may be shared with other threads.
+#[repr(C)]
+pub struct DistinstEventHandle;
+
+/// Obtains a handle to the events retained by the installer.
+///
+/// The installer may not be accessed from another thread while an installation is running, so
+/// the handle must be obtained before `distinst_installer_install` is called.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_get_event_handle(
+    installer: *const DistinstInstaller,
+) -> *mut DistinstEventHandle {
+    if null_check(installer).is_err() {
+        return ptr::null_mut();
+    }
+
+    gen_object_ptr((*(installer as *const Installer)).events()) as *mut DistinstEventHandle
+}
+
+/// Pass each retained event that came after the `since_seq` sequence number to the callback,
+/// from the oldest to the newest
+#[no_mangle]
+pub unsafe extern "C" fn distinst_event_handle_replay(
+    handle: *const DistinstEventHandle,
+    since_seq: u64,
+    callback: DistinstEventCallback,
+    user_data: *mut libc::c_void,
+) {
+    if null_check(handle).is_err() {
+        return;
+    }
+
+    for event in (*(handle as *const EventHandle)).replay(since_seq) {
+        callback(&DistinstEvent::from(&event) as *const DistinstEvent, user_data);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_event_handle_destroy(handle: *mut DistinstEventHandle) {
+    if handle.is_null() {
+        error!("DistinstEventHandle was to be destroyed even though it is null");
+    } else {
+        Box::from_raw(handle as *mut EventHandle);
+    }
+}
+
+/// Set the callback which receives every external command that the installer executes
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_on_command(
+    installer: *mut DistinstInstaller,
+    callback: DistinstCommandCallback,
+    user_data: *mut libc::c_void,
+) {
+    let user_data_sync = user_data as usize;
+    (*(installer as *mut Installer)).on_command(move |record| {
+        let cstring = |string: &str| CString::new(string.replace('\0', "")).unwrap_or_default();
+        let (command, stdout, stderr) =
+            (cstring(&record.command), cstring(&record.stdout), cstring(&record.stderr));
+
+        callback(
+            &DistinstCommandRecord {
+                command:     command.as_ptr(),
+                status:      record.status.unwrap_or(-1),
+                duration_ms: record.duration.as_millis() as u64,
+                stdout:      stdout.as_ptr(),
+                stderr:      stderr.as_ptr(),
+            } as *const DistinstCommandRecord,
+            user_data_sync as *mut libc::c_void,
+        )
+    });
+}
+
+/// Set the callback which receives the time taken by each step and external command
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_on_metric(
+    installer: *mut DistinstInstaller,
+    callback: DistinstMetricCallback,
+    user_data: *mut libc::c_void,
+) {
+    let user_data_sync = user_data as usize;
+    (*(installer as *mut Installer)).on_metric(move |metric| {
+        let operation = CString::new(metric.operation.replace('\0', "")).unwrap_or_default();
+        callback(
+            &DistinstMetric {
+                operation:   operation.as_ptr(),
+                duration_ms: metric.duration.as_millis() as u64,
+            } as *const DistinstMetric,
+            user_data_sync as *mut libc::c_void,
+        )
+    });
+}
+
+/// Set the callback which receives each line of output from the post-install scripts
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_on_hook_output(
+    installer: *mut DistinstInstaller,
+    callback: DistinstHookOutputCallback,
+    user_data: *mut libc::c_void,
+) {
+    let user_data_sync = user_data as usize;
+    (*(installer as *mut Installer)).on_hook_output(move |output| {
+        let script = CString::new(output.script.as_os_str().as_bytes()).unwrap_or_default();
+        let line = CString::new(output.line.replace('\0', "")).unwrap_or_default();
+        callback(
+            script.as_ptr(),
+            line.as_ptr(),
+            output.stderr,
+            user_data_sync as *mut libc::c_void,
+        )
+    });
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_installer_set_timezone_callback(
     installer: *mut DistinstInstaller,
@@ -166,10 +413,85 @@ pub unsafe extern "C" fn distinst_installer_set_user_callback(
     (*(installer as *mut Installer)).set_user_callback(move || {
         let mut user_account_create = mem::zeroed();
         callback(&mut user_account_create, user_data);
-        user_account_create.as_config().expect("user callback invalid")
+        vec![user_account_create.as_config().expect("user callback invalid")]
     });
 }
 
+/// Creates every user account that the callback provides, such as an administrator along with
+/// standard users, instead of the single account of `distinst_installer_set_user_callback`.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_set_users_callback(
+    installer: *mut DistinstInstaller,
+    callback: DistinstUserAccountsCallback,
+    user_data: *mut libc::c_void,
+) {
+    (*(installer as *mut Installer)).set_user_callback(move || {
+        let mut len = 0;
+        let users = callback(&mut len, user_data);
+        if users.is_null() {
+            return Vec::new();
+        }
+
+        slice::from_raw_parts(users, len)
+            .iter()
+            .map(|user| user.as_config().expect("users callback invalid"))
+            .collect()
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_set_mok_password_callback(
+    installer: *mut DistinstInstaller,
+    callback: DistinstMokPasswordCallback,
+    user_data: *mut libc::c_void,
+) {
+    (*(installer as *mut Installer)).set_mok_password_callback(move || {
+        let password = callback(user_data);
+        if password.is_null() {
+            None
+        } else {
+            get_str(password).ok().map(String::from)
+        }
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_set_unmount_callback(
+    installer: *mut DistinstInstaller,
+    callback: DistinstUnmountCallback,
+    user_data: *mut libc::c_void,
+) {
+    (*(installer as *mut Installer)).set_unmount_callback(move |mounts| {
+        let mounts = mounts
+            .iter()
+            .filter_map(|mount| CString::new(mount.to_string()).ok())
+            .collect::<Vec<_>>();
+        let pointers = mounts.iter().map(|mount| mount.as_ptr()).collect::<Vec<_>>();
+        callback(pointers.as_ptr(), pointers.len(), user_data)
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_set_failure_report_callbacks(
+    installer: *mut DistinstInstaller,
+    consent: DistinstReportConsentCallback,
+    consent_data: *mut libc::c_void,
+    upload: DistinstReportUploadCallback,
+    upload_data: *mut libc::c_void,
+) {
+    (*(installer as *mut Installer)).set_failure_report_callbacks(
+        move |why| {
+            let message = CString::new(why.to_string()).unwrap_or_default();
+            consent(message.as_ptr(), consent_data)
+        },
+        move |tarball| {
+            if let Ok(tarball) = CString::new(tarball.to_string_lossy().into_owned()) {
+                upload(tarball.as_ptr(), upload_data);
+            }
+        },
+    );
+}
+
 /// Install using this installer, whilst retaining home & user accounts.
 #[no_mangle]
 pub unsafe extern "C" fn distinst_installer_install(
@@ -200,6 +522,198 @@ pub unsafe extern "C" fn distinst_installer_install(
     }
 }
 
+/// The working directory of the most recent installation, which contains the artifacts that
+/// were recorded for it. Returns null if no working directory was created.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_get_work_dir(
+    installer: *const DistinstInstaller,
+) -> *mut libc::c_char {
+    if null_check(installer).is_err() {
+        return ptr::null_mut();
+    }
+
+    (*(installer as *const Installer))
+        .work_dir()
+        .map_or(ptr::null_mut(), |path| to_cstr(path.to_string_lossy().into_owned()))
+}
+
+/// What the most recent installation changed compared to the system that it replaced, with the
+/// release, kernel, and root file system on the first lines, followed by each package which was
+/// added, removed, or changed. Returns null if the installation did not replace a system.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_get_summary(
+    installer: *const DistinstInstaller,
+) -> *mut libc::c_char {
+    if null_check(installer).is_err() {
+        return ptr::null_mut();
+    }
+
+    (*(installer as *const Installer))
+        .summary()
+        .map_or(ptr::null_mut(), |summary| to_cstr(summary.to_string()))
+}
+
+/// A snapshot of the progress that an installation has made.
+#[repr(C)]
+pub struct DistinstInstallProgress;
+
+struct InstallProgressFfi {
+    progress:  InstallProgress,
+    completed: Vec<DISTINST_STEP>,
+    pending:   Vec<DISTINST_STEP>,
+}
+
+/// A handle to the progress of an installer, which may be shared with other threads.
+#[repr(C)]
+pub struct DistinstInstallProgressHandle;
+
+/// Obtains a handle to the progress of the installer.
+///
+/// The installer may not be accessed from another thread while an installation is running, so
+/// the handle must be obtained before `distinst_installer_install` is called.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_get_progress_handle(
+    installer: *const DistinstInstaller,
+) -> *mut DistinstInstallProgressHandle {
+    if null_check(installer).is_err() {
+        return ptr::null_mut();
+    }
+
+    gen_object_ptr((*(installer as *const Installer)).progress())
+        as *mut DistinstInstallProgressHandle
+}
+
+/// Obtains a snapshot of the progress that the installer has made.
+///
+/// This may be called from another thread while an installation is running, so that a
+/// frontend may restore its state after being restarted.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_install_progress_handle_get_progress(
+    handle: *const DistinstInstallProgressHandle,
+) -> *mut DistinstInstallProgress {
+    if null_check(handle).is_err() {
+        return ptr::null_mut();
+    }
+
+    let progress = (*(handle as *const InstallProgressHandle)).snapshot();
+    gen_object_ptr(InstallProgressFfi {
+        completed: progress.completed.iter().map(|&step| step.into()).collect(),
+        pending: progress.pending.iter().map(|&step| step.into()).collect(),
+        progress,
+    }) as *mut DistinstInstallProgress
+}
+
+/// Writes the step which is currently being executed into `step`.
+///
+/// Returns `false` if no step is being executed.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_install_progress_get_step(
+    progress: *const DistinstInstallProgress,
+    step: *mut DISTINST_STEP,
+) -> bool {
+    if null_check(progress).and_then(|_| null_check(step)).is_err() {
+        return false;
+    }
+
+    let progress = &*(progress as *const InstallProgressFfi);
+    progress.progress.current.map_or(false, |current| {
+        *step = current.into();
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_install_progress_get_percent(
+    progress: *const DistinstInstallProgress,
+) -> libc::c_int {
+    if null_check(progress).is_err() {
+        return 0;
+    }
+
+    (*(progress as *const InstallProgressFfi)).progress.percent
+}
+
+/// The steps which have been completed, in the order that they were completed.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_install_progress_get_completed(
+    progress: *const DistinstInstallProgress,
+    len: *mut libc::c_int,
+) -> *const DISTINST_STEP {
+    if null_check(progress).and_then(|_| null_check(len)).is_err() {
+        return ptr::null();
+    }
+
+    let completed = &(*(progress as *const InstallProgressFfi)).completed;
+    *len = completed.len() as libc::c_int;
+    completed.as_ptr()
+}
+
+/// The steps which have yet to be started, in the order that they will be executed.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_install_progress_get_pending(
+    progress: *const DistinstInstallProgress,
+    len: *mut libc::c_int,
+) -> *const DISTINST_STEP {
+    if null_check(progress).and_then(|_| null_check(len)).is_err() {
+        return ptr::null();
+    }
+
+    let pending = &(*(progress as *const InstallProgressFfi)).pending;
+    *len = pending.len() as libc::c_int;
+    pending.as_ptr()
+}
+
+/// Writes the step which failed into `step`.
+///
+/// Returns `false` if no step has failed.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_install_progress_get_failed(
+    progress: *const DistinstInstallProgress,
+    step: *mut DISTINST_STEP,
+) -> bool {
+    if null_check(progress).and_then(|_| null_check(step)).is_err() {
+        return false;
+    }
+
+    let progress = &*(progress as *const InstallProgressFfi);
+    progress.progress.failed.map_or(false, |failed| {
+        *step = failed.into();
+        true
+    })
+}
+
+/// Whether mounts and logical devices were cleaned up after the installation.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_install_progress_cleanup_ran(
+    progress: *const DistinstInstallProgress,
+) -> bool {
+    if null_check(progress).is_err() {
+        return false;
+    }
+
+    (*(progress as *const InstallProgressFfi)).progress.cleanup_ran
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_install_progress_destroy(progress: *mut DistinstInstallProgress) {
+    if progress.is_null() {
+        error!("DistinstInstallProgress was to be destroyed even though it is null");
+    } else {
+        Box::from_raw(progress as *mut InstallProgressFfi);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_install_progress_handle_destroy(
+    handle: *mut DistinstInstallProgressHandle,
+) {
+    if handle.is_null() {
+        error!("DistinstInstallProgressHandle was to be destroyed even though it is null");
+    } else {
+        Box::from_raw(handle as *mut InstallProgressHandle);
+    }
+}
+
 /// Destroy an installer object
 #[no_mangle]
 pub unsafe extern "C" fn distinst_installer_destroy(installer: *mut DistinstInstaller) {
@@ -1,5 +1,5 @@
-use super::null_check;
-use distinst::locale::{self, KeyboardLayout, KeyboardLayouts, KeyboardVariant};
+use super::{get_str, null_check, to_cstr};
+use distinst::locale::{self, KeyboardLayout, KeyboardLayouts, KeyboardModel, KeyboardVariant};
 use libc;
 use std::ptr;
 
@@ -11,7 +11,7 @@ pub unsafe extern "C" fn distinst_keyboard_layout_get_name(
     keyboard_layout: *const DistinstKeyboardLayout,
     len: *mut libc::c_int,
 ) -> *const u8 {
-    if null_check(keyboard_layout).or_else(|_| null_check(len)).is_err() {
+    if null_check(keyboard_layout).and_then(|_| null_check(len)).is_err() {
         return ptr::null();
     }
 
@@ -26,7 +26,7 @@ pub unsafe extern "C" fn distinst_keyboard_layout_get_description(
     keyboard_layout: *const DistinstKeyboardLayout,
     len: *mut libc::c_int,
 ) -> *const u8 {
-    if null_check(keyboard_layout).or_else(|_| null_check(len)).is_err() {
+    if null_check(keyboard_layout).and_then(|_| null_check(len)).is_err() {
         return ptr::null();
     }
 
@@ -36,6 +36,21 @@ pub unsafe extern "C" fn distinst_keyboard_layout_get_description(
     desc.as_ptr()
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn distinst_keyboard_layout_get_description_translated(
+    keyboard_layout: *const DistinstKeyboardLayout,
+    lang: *const libc::c_char,
+) -> *mut libc::c_char {
+    if null_check(keyboard_layout).is_err() {
+        return ptr::null_mut();
+    }
+
+    let keyboard_layout = &*(keyboard_layout as *const KeyboardLayout);
+    get_str(lang).ok().map_or(ptr::null_mut(), |lang| {
+        to_cstr(keyboard_layout.get_description_translated(lang))
+    })
+}
+
 #[repr(C)]
 pub struct DistinstKeyboardVariant;
 
@@ -44,7 +59,7 @@ pub unsafe extern "C" fn distinst_keyboard_variant_get_name(
     keyboard_variant: *const DistinstKeyboardVariant,
     len: *mut libc::c_int,
 ) -> *const u8 {
-    if null_check(keyboard_variant).or_else(|_| null_check(len)).is_err() {
+    if null_check(keyboard_variant).and_then(|_| null_check(len)).is_err() {
         return ptr::null();
     }
 
@@ -59,7 +74,7 @@ pub unsafe extern "C" fn distinst_keyboard_variant_get_description(
     keyboard_variant: *const DistinstKeyboardVariant,
     len: *mut libc::c_int,
 ) -> *const u8 {
-    if null_check(keyboard_variant).or_else(|_| null_check(len)).is_err() {
+    if null_check(keyboard_variant).and_then(|_| null_check(len)).is_err() {
         return ptr::null();
     }
 
@@ -69,12 +84,27 @@ pub unsafe extern "C" fn distinst_keyboard_variant_get_description(
     desc.as_ptr()
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn distinst_keyboard_variant_get_description_translated(
+    keyboard_variant: *const DistinstKeyboardVariant,
+    lang: *const libc::c_char,
+) -> *mut libc::c_char {
+    if null_check(keyboard_variant).is_err() {
+        return ptr::null_mut();
+    }
+
+    let keyboard_variant = &*(keyboard_variant as *const KeyboardVariant);
+    get_str(lang).ok().map_or(ptr::null_mut(), |lang| {
+        to_cstr(keyboard_variant.get_description_translated(lang))
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_keyboard_layout_get_variants(
     keyboard_layout: *const DistinstKeyboardLayout,
     len: *mut libc::c_int,
 ) -> *mut *const DistinstKeyboardVariant {
-    if null_check(keyboard_layout).or_else(|_| null_check(len)).is_err() {
+    if null_check(keyboard_layout).and_then(|_| null_check(len)).is_err() {
         return ptr::null_mut();
     }
 
@@ -97,6 +127,54 @@ pub unsafe extern "C" fn distinst_keyboard_layout_get_variants(
     }
 }
 
+#[repr(C)]
+pub struct DistinstKeyboardModel;
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_keyboard_model_get_name(
+    keyboard_model: *const DistinstKeyboardModel,
+    len: *mut libc::c_int,
+) -> *const u8 {
+    if null_check(keyboard_model).and_then(|_| null_check(len)).is_err() {
+        return ptr::null();
+    }
+
+    let keyboard_model = &*(keyboard_model as *const KeyboardModel);
+    let name = keyboard_model.get_name().as_bytes();
+    *len = name.len() as libc::c_int;
+    name.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_keyboard_model_get_description(
+    keyboard_model: *const DistinstKeyboardModel,
+    len: *mut libc::c_int,
+) -> *const u8 {
+    if null_check(keyboard_model).and_then(|_| null_check(len)).is_err() {
+        return ptr::null();
+    }
+
+    let keyboard_model = &*(keyboard_model as *const KeyboardModel);
+    let desc = keyboard_model.get_description().as_bytes();
+    *len = desc.len() as libc::c_int;
+    desc.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_keyboard_model_get_description_translated(
+    keyboard_model: *const DistinstKeyboardModel,
+    lang: *const libc::c_char,
+) -> *mut libc::c_char {
+    if null_check(keyboard_model).is_err() {
+        return ptr::null_mut();
+    }
+
+    let keyboard_model = &*(keyboard_model as *const KeyboardModel);
+    get_str(lang).ok().map_or(ptr::null_mut(), |lang| {
+        to_cstr(keyboard_model.get_description_translated(lang))
+    })
+}
+
 #[repr(C)]
 pub struct DistinstKeyboardLayouts;
 
@@ -116,7 +194,7 @@ pub unsafe extern "C" fn distinst_keyboard_layouts_get_layouts(
     layouts: *mut DistinstKeyboardLayouts,
     len: *mut libc::c_int,
 ) -> *mut *mut DistinstKeyboardLayout {
-    if null_check(layouts).or_else(|_| null_check(len)).is_err() {
+    if null_check(layouts).and_then(|_| null_check(len)).is_err() {
         return ptr::null_mut();
     }
 
@@ -131,6 +209,84 @@ pub unsafe extern "C" fn distinst_keyboard_layouts_get_layouts(
     Box::into_raw(output.into_boxed_slice()) as *mut *mut DistinstKeyboardLayout
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn distinst_keyboard_layouts_get_models(
+    layouts: *const DistinstKeyboardLayouts,
+    len: *mut libc::c_int,
+) -> *mut *const DistinstKeyboardModel {
+    if null_check(layouts).and_then(|_| null_check(len)).is_err() {
+        return ptr::null_mut();
+    }
+
+    let layouts = &*(layouts as *const KeyboardLayouts);
+
+    let mut output: Vec<*const DistinstKeyboardModel> = Vec::new();
+    for model in layouts.get_models() {
+        output.push(model as *const KeyboardModel as *const DistinstKeyboardModel);
+    }
+
+    *len = output.len() as libc::c_int;
+    Box::into_raw(output.into_boxed_slice()) as *mut *const DistinstKeyboardModel
+}
+
+/// Checks that the `layout` exists, and that the `model` and `variant` exist for it, if they
+/// are not null. Strings which are not UTF-8 are invalid. The reason that they are invalid is
+/// logged.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_keyboard_layouts_validate(
+    layouts: *const DistinstKeyboardLayouts,
+    layout: *const libc::c_char,
+    model: *const libc::c_char,
+    variant: *const libc::c_char,
+) -> bool {
+    if null_check(layouts).is_err() {
+        return false;
+    }
+
+    let optional = |value: *const libc::c_char| {
+        if value.is_null() {
+            Ok(None)
+        } else {
+            get_str(value).map(Some)
+        }
+    };
+
+    let strings = get_str(layout)
+        .and_then(|layout| Ok((layout, optional(model)?, optional(variant)?)));
+
+    let (layout, model, variant) = match strings {
+        Ok(strings) => strings,
+        Err(why) => {
+            error!("distinst_keyboard_layouts_validate: {}", why);
+            return false;
+        }
+    };
+
+    let layouts = &*(layouts as *const KeyboardLayouts);
+    match layouts.validate(layout, model, variant) {
+        Ok(()) => true,
+        Err(why) => {
+            error!("distinst_keyboard_layouts_validate: {}", why);
+            false
+        }
+    }
+}
+
+/// Sorts the layouts, and their variants, by their descriptions translated into `lang`.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_keyboard_layouts_sort(
+    layouts: *mut DistinstKeyboardLayouts,
+    lang: *const libc::c_char,
+) {
+    if null_check(layouts).is_err() {
+        return;
+    }
+
+    if let Ok(lang) = get_str(lang) {
+        (&mut *(layouts as *mut KeyboardLayouts)).sort(lang);
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_keyboard_layouts_destroy(
     layouts: *mut DistinstKeyboardLayouts,
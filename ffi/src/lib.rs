@@ -14,14 +14,26 @@ use std::{
 };
 
 pub use self::{
-    auto::*, config::*, dbus::*, disk::*, filesystem::*, installer::*, keyboard_layout::*,
-    locale::*, lvm::*, os::*, partition::*, sector::*, timezones::*, upgrade::*,
+    auto::*, config::*, dbus::*, disk::*, filesystem::*, generations::*, installer::*,
+    keyboard_layout::*, locale::*, lvm::*, os::*, partition::*, sector::*, timezones::*,
+    upgrade::*,
 };
 
+/// Deprecated alias of `DISTINST_INSTALL_FLAG_MODIFY_BOOT_ORDER`.
+#[deprecated(note = "use DISTINST_INSTALL_FLAG::MODIFY_BOOT_ORDER")]
 pub const DISTINST_MODIFY_BOOT_ORDER: u8 = 0b1;
+/// Deprecated alias of `DISTINST_INSTALL_FLAG_INSTALL_HARDWARE_SUPPORT`.
+#[deprecated(note = "use DISTINST_INSTALL_FLAG::INSTALL_HARDWARE_SUPPORT")]
 pub const DISTINST_INSTALL_HARDWARE_SUPPORT: u8 = 0b10;
+/// Deprecated alias of `DISTINST_INSTALL_FLAG_KEEP_OLD_ROOT`.
+#[deprecated(note = "use DISTINST_INSTALL_FLAG::KEEP_OLD_ROOT")]
 pub const DISTINST_KEEP_OLD_ROOT: u8 = 0b100;
+/// Deprecated alias of `DISTINST_INSTALL_FLAG_RUN_UBUNTU_DRIVERS`.
+#[deprecated(note = "use DISTINST_INSTALL_FLAG::RUN_UBUNTU_DRIVERS")]
 pub const DISTINST_RUN_UBUNTU_DRIVERS: u8 = 0b1000;
+/// Deprecated alias of `DISTINST_INSTALL_FLAG_JOURNAL_COMMANDS`.
+#[deprecated(note = "use DISTINST_INSTALL_FLAG::JOURNAL_COMMANDS")]
+pub const DISTINST_JOURNAL_COMMANDS: u8 = 0b10000;
 
 use std::io;
 
@@ -31,6 +43,7 @@ mod dbus;
 mod disk;
 mod ffi;
 mod filesystem;
+mod generations;
 mod installer;
 mod keyboard_layout;
 mod locale;
@@ -50,6 +50,81 @@ pub unsafe extern "C" fn distinst_locale_get_language_codes(
     Box::into_raw(codes.into_boxed_slice()) as *mut *mut libc::c_char
 }
 
+/// Language codes, sorted by their names in the display language.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_locale_get_language_codes_sorted(
+    display_lang: *const libc::c_char,
+    len: *mut libc::c_int,
+) -> *mut *mut libc::c_char {
+    if null_check(len).is_err() {
+        return ptr::null_mut();
+    }
+
+    match get_str(display_lang).ok() {
+        Some(display_lang) => {
+            let codes = locale::get_language_names_sorted(display_lang)
+                .into_iter()
+                .map(|(code, _)| to_cstr(code.into()))
+                .collect::<Vec<*mut libc::c_char>>();
+
+            *len = codes.len() as libc::c_int;
+            Box::into_raw(codes.into_boxed_slice()) as *mut *mut libc::c_char
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+/// Country codes associated with a language, sorted by their names in the display language.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_locale_get_country_codes_sorted(
+    lang: *const libc::c_char,
+    display_lang: *const libc::c_char,
+    len: *mut libc::c_int,
+) -> *mut *mut libc::c_char {
+    if null_check(len).is_err() {
+        return ptr::null_mut();
+    }
+
+    match (get_str(lang), get_str(display_lang)) {
+        (Ok(lang), Ok(display_lang)) => {
+            let codes = locale::get_country_names_sorted(lang, display_lang)
+                .into_iter()
+                .map(|(code, _)| to_cstr(code.into()))
+                .collect::<Vec<*mut libc::c_char>>();
+
+            *len = codes.len() as libc::c_int;
+            Box::into_raw(codes.into_boxed_slice()) as *mut *mut libc::c_char
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_locale_get_language_display_name(
+    code: *const libc::c_char,
+    display_lang: *const libc::c_char,
+) -> *mut libc::c_char {
+    get_str(code)
+        .and_then(|code| get_str(display_lang).map(|display_lang| (code, display_lang)))
+        .ok()
+        .and_then(|(code, display_lang)| locale::get_language_display_name(code, display_lang))
+        .map(to_cstr)
+        .unwrap_or(ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_locale_get_country_display_name(
+    code: *const libc::c_char,
+    display_lang: *const libc::c_char,
+) -> *mut libc::c_char {
+    get_str(code)
+        .and_then(|code| get_str(display_lang).map(|display_lang| (code, display_lang)))
+        .ok()
+        .and_then(|(code, display_lang)| locale::get_country_display_name(code, display_lang))
+        .map(to_cstr)
+        .unwrap_or(ptr::null_mut())
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_locale_get_language_name(
     code: *const libc::c_char,
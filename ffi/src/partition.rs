@@ -3,6 +3,7 @@ use libc;
 use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::PathBuf, ptr};
 
 use distinst::{
+    external::{encrypted_sectors_used, LuksKey},
     BlockDeviceExt, Bootloader, FileSystem, LvmEncryption, PartitionBuilder, PartitionExt,
     PartitionFlag, PartitionInfo, PartitionTable, PartitionType,
 };
@@ -33,7 +34,7 @@ impl From<Option<PartitionTable>> for DISTINST_PARTITION_TABLE {
 #[no_mangle]
 pub unsafe extern "C" fn distinst_bootloader_detect() -> DISTINST_PARTITION_TABLE {
     match Bootloader::detect() {
-        Bootloader::Bios => DISTINST_PARTITION_TABLE::MSDOS,
+        Bootloader::Bios | Bootloader::UBoot => DISTINST_PARTITION_TABLE::MSDOS,
         Bootloader::Efi => DISTINST_PARTITION_TABLE::GPT,
     }
 }
@@ -205,6 +206,39 @@ pub unsafe extern "C" fn distinst_partition_builder_name(
     }
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn distinst_partition_builder_label(
+    builder: *mut DistinstPartitionBuilder,
+    label: *const libc::c_char,
+) -> *mut DistinstPartitionBuilder {
+    match get_str(label) {
+        Ok(string) => builder_action(builder, move |builder| builder.label(string.into())),
+        Err(_) => builder,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_partition_builder_gpt_name(
+    builder: *mut DistinstPartitionBuilder,
+    name: *const libc::c_char,
+) -> *mut DistinstPartitionBuilder {
+    match get_str(name) {
+        Ok(string) => builder_action(builder, move |builder| builder.gpt_name(string.into())),
+        Err(_) => builder,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_partition_builder_uuid(
+    builder: *mut DistinstPartitionBuilder,
+    uuid: *const libc::c_char,
+) -> *mut DistinstPartitionBuilder {
+    match get_str(uuid) {
+        Ok(string) => builder_action(builder, move |builder| builder.uuid(string.into())),
+        Err(_) => builder,
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_partition_builder_mount(
     builder: *mut DistinstPartitionBuilder,
@@ -514,6 +548,37 @@ pub unsafe extern "C" fn distinst_partition_format_with(
     0
 }
 
+/// Formats the partition with the given UUID, or preserves the current UUID if `uuid` is null.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_partition_format_with_uuid(
+    partition: *mut DistinstPartition,
+    fs: DISTINST_FILE_SYSTEM,
+    uuid: *const libc::c_char,
+) -> libc::c_int {
+    if null_check(partition).is_err() {
+        return -1;
+    }
+
+    let uuid = if uuid.is_null() {
+        None
+    } else {
+        match get_str(uuid) {
+            Ok(uuid) => Some(uuid.to_owned()),
+            Err(_) => return -1,
+        }
+    };
+
+    let part = &mut *(partition as *mut PartitionInfo);
+    part.format_with_uuid(
+        match fs.into() {
+            Some(fs) => fs,
+            None => return -1,
+        },
+        uuid,
+    );
+    0
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_partition_is_esp(partition: *const DistinstPartition) -> bool {
     if null_check(partition).is_err() {
@@ -600,3 +665,33 @@ pub unsafe extern "C" fn distinst_partition_sectors_used(
         }
     }
 }
+
+/// Obtains the sectors used by an encrypted partition, temporarily unlocking it with the
+/// `password` if it is not already unlocked.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_partition_sectors_used_encrypted(
+    partition: *const DistinstPartition,
+    password: *const libc::c_char,
+) -> DistinstPartitionUsage {
+    if null_check(partition).is_err() {
+        return DistinstPartitionUsage { tag: 2, value: 0 };
+    }
+
+    let password = match get_str(password) {
+        Ok(password) => password,
+        Err(_) => return DistinstPartitionUsage { tag: 2, value: 0 },
+    };
+
+    let part = &*(partition as *const PartitionInfo);
+    let sector_size = part.get_logical_block_size();
+    match encrypted_sectors_used(&part.device_path, LuksKey::Password(password)) {
+        Ok(used) => DistinstPartitionUsage { tag: 1, value: used / (sector_size / 512) },
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => {
+            DistinstPartitionUsage { tag: 0, value: 0 }
+        }
+        Err(ref why) => {
+            error!("unable to get encrypted partition sector usage: {}", why);
+            DistinstPartitionUsage { tag: 2, value: 0 }
+        }
+    }
+}
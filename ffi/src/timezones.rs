@@ -1,5 +1,5 @@
-use distinst::timezones::*;
-use crate::gen_object_ptr;
+use distinst::{locale::Collator, timezones::*};
+use crate::{gen_object_ptr, get_str, to_cstr};
 use libc;
 use std::ptr;
 
@@ -30,6 +30,60 @@ pub unsafe extern "C" fn distinst_timezones_zones(
     gen_object_ptr(boxed) as *mut DistinstZones
 }
 
+/// Sorts the zones and their regions by their display names, with the collation rules of the
+/// given language.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_timezones_sort(
+    tz: *mut DistinstTimezones,
+    lang: *const libc::c_char,
+) {
+    if tz.is_null() {
+        error!("distinst_timezones_sort: tz input was null");
+        return;
+    }
+
+    if let Ok(lang) = get_str(lang) {
+        let collator = Collator::new(lang);
+        (&mut *(tz as *mut Timezones)).sort_by_key(|name| collator.key(name));
+    }
+}
+
+/// Detects the region of the timezone that the system is located in, with GeoClue, or else
+/// with the geo-IP `endpoint` if it is not null. The region is owned by the timezones.
+///
+/// Null is returned if the region was not detected, or if distinst was built without the
+/// `geolocation` feature.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_timezones_detect(
+    tz: *const DistinstTimezones,
+    endpoint: *const libc::c_char,
+) -> *const DistinstRegion {
+    if tz.is_null() {
+        error!("distinst_timezones_detect: tz input was null");
+        return ptr::null();
+    }
+
+    let endpoint = if endpoint.is_null() { None } else { get_str(endpoint).ok() };
+    detect_region(&*(tz as *const Timezones), endpoint)
+}
+
+#[cfg(feature = "geolocation")]
+fn detect_region(tz: &Timezones, endpoint: Option<&str>) -> *const DistinstRegion {
+    match detect(tz, endpoint) {
+        Ok(region) => region as *const Region as *const DistinstRegion,
+        Err(why) => {
+            error!("distinst_timezones_detect: {}", why);
+            ptr::null()
+        }
+    }
+}
+
+#[cfg(not(feature = "geolocation"))]
+fn detect_region(_tz: &Timezones, _endpoint: Option<&str>) -> *const DistinstRegion {
+    info!("distinst_timezones_detect: distinst was built without the geolocation feature");
+    ptr::null()
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_timezones_destroy(tz: *mut DistinstTimezones) {
     if !tz.is_null() {
@@ -86,6 +140,18 @@ pub unsafe extern "C" fn distinst_zone_name(
     name.as_ptr()
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn distinst_zone_display_name(
+    zone: *const DistinstZone,
+) -> *mut libc::c_char {
+    if zone.is_null() {
+        error!("distinst_zone_display_name: zone input was null");
+        return ptr::null_mut();
+    }
+
+    to_cstr((&*(zone as *const Zone)).display_name())
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_zone_regions(zone: *const DistinstZone) -> *mut DistinstRegions {
     if zone.is_null() {
@@ -148,3 +214,15 @@ pub unsafe extern "C" fn distinst_region_name(
     *len = name.len() as libc::c_int;
     name.as_ptr()
 }
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_region_display_name(
+    region: *const DistinstRegion,
+) -> *mut libc::c_char {
+    if region.is_null() {
+        error!("distinst_region_display_name: region input was null");
+        return ptr::null_mut();
+    }
+
+    to_cstr((&*(region as *const Region)).display_name())
+}
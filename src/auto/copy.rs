@@ -0,0 +1,302 @@
+//! Copies files which were chosen by the user off of the disks before they are erased, such as
+//! their documents to a second drive, as a last chance to keep them.
+
+use super::ReinstallError;
+use crate::disks::{Disks, REMOVE};
+use crate::esp::free_space;
+use crate::misc;
+use disk_types::BlockDeviceExt;
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    os::unix::fs::symlink,
+    path::{Component, Path, PathBuf},
+};
+use sys_mount::{Mount, MountFlags, Unmount, UnmountDrop, UnmountFlags};
+use tempdir::TempDir;
+
+/// Files which are copied from one partition to another before the disks are modified.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileBackup {
+    /// The partition which the files are copied from, such as `/dev/sda2`.
+    pub source:      PathBuf,
+    /// The files and directories to copy, relative to the root of the source.
+    pub paths:       Vec<PathBuf>,
+    /// The partition which the files are copied to, which the install must leave untouched.
+    pub destination: PathBuf,
+    /// The directory within the destination which the files are copied into.
+    pub directory:   PathBuf,
+}
+
+impl FileBackup {
+    /// Checks that the destination is not modified by the disk configuration of the install,
+    /// and that no file would be copied onto itself, or into itself.
+    pub fn validate(&self, disks: &Disks) -> Result<(), ReinstallError> {
+        let mut paths = Vec::with_capacity(self.paths.len());
+        for path in self.paths.iter().chain(Some(&self.directory)) {
+            match relative(path) {
+                Some(relative) if !relative.as_os_str().is_empty() => paths.push(relative),
+                _ => return Err(ReinstallError::BackupPath { path: path.clone() }),
+            }
+        }
+
+        let device = misc::canonicalize(&self.destination);
+        if misc::canonicalize(&self.source) == device {
+            let (directory, paths) = paths.split_last().unwrap();
+            if let Some(path) = paths.iter().find(|path| overlaps(path, directory)) {
+                return Err(ReinstallError::BackupOverlap { path: path.to_path_buf() });
+            }
+        }
+
+        let current = Disks::probe_devices().map_err(|why| ReinstallError::DiskProbe { why })?;
+        let destination = current
+            .get_partition_by_path(&device)
+            .ok_or_else(|| ReinstallError::BackupPartition { device: self.destination.clone() })?;
+
+        // A partition which is missing from the configuration is kept, unless its disk has a
+        // new partition table written to it.
+        let modified = match disks.get_partition_by_path(&device) {
+            Some(partition) => partition.will_format() || partition.flag_is_enabled(REMOVE),
+            None => current
+                .get_physical_device_with_partition(destination.get_device_path())
+                .and_then(|disk| disks.get_physical_device(disk.get_device_path()))
+                .map_or(false, |disk| disk.mklabel),
+        };
+
+        if modified {
+            return Err(ReinstallError::BackupDestinationModified {
+                device: self.destination.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The bytes which the backup requires, and the bytes which are free at the destination.
+    pub fn space(&self) -> Result<(u64, u64), ReinstallError> {
+        let current = Disks::probe_devices().map_err(|why| ReinstallError::DiskProbe { why })?;
+        let source = Mounted::new(&current, &self.source, MountFlags::RDONLY)?;
+        let destination = Mounted::new(&current, &self.destination, MountFlags::empty())?;
+        self.measure(&source.path, &destination.path)
+    }
+
+    /// Copies the files to the destination, after checking that there is space for them,
+    /// reporting the progress as a percentage.
+    pub fn copy<F: FnMut(i32)>(
+        &self,
+        disks: &Disks,
+        mut callback: F,
+    ) -> Result<(), ReinstallError> {
+        self.validate(disks)?;
+
+        let current = Disks::probe_devices().map_err(|why| ReinstallError::DiskProbe { why })?;
+        let source = Mounted::new(&current, &self.source, MountFlags::RDONLY)?;
+        let destination = Mounted::new(&current, &self.destination, MountFlags::empty())?;
+
+        let (required, available) = self.measure(&source.path, &destination.path)?;
+        if required > available {
+            return Err(ReinstallError::BackupSpace { required, available });
+        }
+
+        let directory = destination.path.join(relative(&self.directory).unwrap_or(&self.directory));
+        info!("copying {} bytes of files to {:?} on {:?}", required, directory, self.destination);
+        fs::create_dir_all(&directory)?;
+
+        let mut copier = Copier { copied: 0, total: required, callback: &mut callback };
+        for path in &self.paths {
+            let path = relative(path).unwrap_or(path);
+            copier.copy(&source.path.join(path), &directory.join(path))?;
+        }
+
+        unsafe {
+            libc::sync();
+        }
+
+        callback(100);
+        Ok(())
+    }
+
+    fn measure(&self, source: &Path, destination: &Path) -> Result<(u64, u64), ReinstallError> {
+        let mut required = 0;
+        for path in &self.paths {
+            let found = relative(path).map(|path| source.join(path));
+            match found {
+                Some(ref found) if found.symlink_metadata().is_ok() => required += size(found)?,
+                _ => return Err(ReinstallError::BackupPath { path: path.clone() }),
+            }
+        }
+
+        Ok((required, free_space(destination)?))
+    }
+}
+
+/// A partition which is mounted for the backup, or was already mounted.
+struct Mounted {
+    path:    PathBuf,
+    _mount:  Option<UnmountDrop<Mount>>,
+    _tmpdir: Option<TempDir>,
+}
+
+impl Mounted {
+    fn new(disks: &Disks, device: &Path, flags: MountFlags) -> Result<Mounted, ReinstallError> {
+        let missing = || ReinstallError::BackupPartition { device: device.to_path_buf() };
+        let partition =
+            disks.get_partition_by_path(misc::canonicalize(device)).ok_or_else(missing)?;
+
+        if let Some(ref path) = partition.mount_point {
+            return Ok(Mounted { path: path.clone(), _mount: None, _tmpdir: None });
+        }
+
+        let fs = partition.filesystem.ok_or_else(missing)?;
+        let tmpdir = TempDir::new("distinst").map_err(|why| ReinstallError::TempDir { why })?;
+        let mount = Mount::new(device, tmpdir.path(), fs, flags, None)
+            .map_err(|why| ReinstallError::PartitionMount { why })?
+            .into_unmount_drop(UnmountFlags::DETACH);
+
+        Ok(Mounted {
+            path:    tmpdir.path().to_path_buf(),
+            _mount:  Some(mount),
+            _tmpdir: Some(tmpdir),
+        })
+    }
+}
+
+/// Copies files, reporting the progress by the bytes which were copied.
+struct Copier<'a> {
+    copied:   u64,
+    total:    u64,
+    callback: &'a mut dyn FnMut(i32),
+}
+
+impl<'a> Copier<'a> {
+    fn copy(&mut self, source: &Path, target: &Path) -> io::Result<()> {
+        let metadata = source.symlink_metadata()?;
+        let file_type = metadata.file_type();
+
+        if file_type.is_dir() {
+            if !target.is_dir() {
+                fs::create_dir_all(target)?;
+            }
+
+            for entry in source.read_dir()? {
+                let entry = entry?;
+                self.copy(&entry.path(), &target.join(entry.file_name()))?;
+            }
+        } else if file_type.is_symlink() {
+            // Symlinks are not supported by every file system that the files may be copied to.
+            let link = fs::read_link(source)?;
+            if let Err(why) = symlink(&link, target) {
+                warn!("unable to copy the symlink at {:?}: {}", source, why);
+            }
+
+            return Ok(());
+        } else if file_type.is_file() {
+            self.file(source, target, &metadata)?;
+        } else {
+            info!("skipping {:?}, which is not a file or directory", source);
+            return Ok(());
+        }
+
+        // Permissions are not supported by every file system either.
+        let _ = fs::set_permissions(target, metadata.permissions());
+        Ok(())
+    }
+
+    fn file(&mut self, source: &Path, target: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+        let mut input = File::open(source)?;
+        let mut output = File::create(target)?;
+        let mut buffer = vec![0; 1024 * 1024];
+
+        loop {
+            let read = input.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+
+            output.write_all(&buffer[..read])?;
+            self.copied += read as u64;
+            if self.total != 0 {
+                (self.callback)((self.copied * 100 / self.total).min(99) as i32);
+            }
+        }
+
+        if let Ok(modified) = metadata.modified() {
+            let _ = output.set_modified(modified);
+        }
+
+        Ok(())
+    }
+}
+
+/// A path of the backup, relative to the root of its partition, which may not leave it.
+fn relative(path: &Path) -> Option<&Path> {
+    let path = path.strip_prefix("/").unwrap_or(path);
+    if path.components().all(|component| matches!(component, Component::Normal(_))) {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Whether either path is within the other, or they are the same.
+fn overlaps(path: &Path, directory: &Path) -> bool {
+    path.starts_with(directory) || directory.starts_with(path)
+}
+
+/// The bytes of the files at or beneath `path`.
+pub(super) fn size(path: &Path) -> io::Result<u64> {
+    let metadata = path.symlink_metadata()?;
+    if metadata.is_dir() {
+        path.read_dir()?.try_fold(0, |total, entry| Ok(total + size(&entry?.path())?))
+    } else if metadata.is_file() {
+        Ok(metadata.len())
+    } else {
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(relative(Path::new("/home/user")), Some(Path::new("home/user")));
+        assert_eq!(relative(Path::new("home/user")), Some(Path::new("home/user")));
+        assert_eq!(relative(Path::new("/home/../etc")), None);
+    }
+
+    #[test]
+    fn overlapping_paths() {
+        let check = |path: &str, directory: &str| overlaps(Path::new(path), Path::new(directory));
+        assert!(check("home/user", "home/user"));
+        assert!(check("home", "home/backup"));
+        assert!(check("home/user/Documents", "home/user"));
+        assert!(!check("home/user", "backup"));
+        assert!(!check("home/user2", "home/user"));
+    }
+
+    #[test]
+    fn copies_files() {
+        let source = TempDir::new("distinst-source").unwrap();
+        let target = TempDir::new("distinst-target").unwrap();
+        fs::create_dir_all(source.path().join("Documents/notes")).unwrap();
+        fs::write(source.path().join("Documents/notes/todo.txt"), b"backup").unwrap();
+        fs::write(source.path().join("Documents/report.txt"), vec![0; 3000]).unwrap();
+        symlink("notes/todo.txt", source.path().join("Documents/link")).unwrap();
+
+        let total = size(&source.path().join("Documents")).unwrap();
+        assert_eq!(total, 3006);
+
+        let mut percents = Vec::new();
+        let mut callback = |percent| percents.push(percent);
+        let mut copier = Copier { copied: 0, total, callback: &mut callback };
+        copier.copy(&source.path().join("Documents"), &target.path().join("Documents")).unwrap();
+        assert_eq!(copier.copied, total);
+
+        let documents = target.path().join("Documents");
+        assert_eq!(fs::read(documents.join("notes/todo.txt")).unwrap(), b"backup");
+        assert_eq!(fs::read_link(documents.join("link")).unwrap(), Path::new("notes/todo.txt"));
+        assert_eq!(percents.last(), Some(&99));
+    }
+}
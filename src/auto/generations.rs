@@ -0,0 +1,188 @@
+//! Generations of the systems which were replaced by refresh installs with `KEEP_OLD_ROOT`.
+//!
+//! Once a refresh install succeeds, the previous system is moved from `/linux.old` into
+//! `/linux.generations`, in a directory named by the time that it was replaced, in seconds
+//! since the Unix epoch. A generation may be restored from a live session, which retains the
+//! current system as a generation in turn, so that a rollback can itself be rolled back.
+
+use super::{copy::size, mount_and_then, retain::read_and_exclude, ReinstallError};
+use crate::chroot::Chroot;
+use crate::installer::steps::ChrootConfigurator;
+use disk_types::FileSystem;
+use os_release::OsRelease;
+use std::{
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use sys_mount::{Mount, MountFlags, Unmount, UnmountFlags};
+
+/// The directory at the root of the partition which contains the generations.
+pub(crate) const GENERATIONS: &str = "linux.generations";
+
+/// A system which was replaced, and may be restored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Generation {
+    /// The time that the system was replaced, in seconds since the Unix epoch.
+    pub id:      u64,
+    /// The `PRETTY_NAME` of the release of the system, such as `Pop!_OS 22.04 LTS`.
+    pub release: Option<String>,
+}
+
+/// The generations of the root at the given device, with the newest first.
+pub fn generations(
+    root_path: &Path,
+    root_fs: FileSystem,
+) -> Result<Vec<Generation>, ReinstallError> {
+    mount_and_then(root_path, root_fs, |base| {
+        let directory = base.join(GENERATIONS);
+        if !directory.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut generations = directory
+            .read_dir()?
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let id = entry.file_name().to_str()?.parse::<u64>().ok()?;
+                let release = OsRelease::new_from(entry.path().join("etc/os-release"))
+                    .ok()
+                    .map(|release| release.pretty_name);
+                Some(Generation { id, release })
+            })
+            .collect::<Vec<Generation>>();
+
+        generations.sort_by(|a, b| b.id.cmp(&a.id));
+        Ok(generations)
+    })
+}
+
+/// The bytes of the files of a generation, which deleting it would free.
+pub fn generation_size(
+    root_path: &Path,
+    root_fs: FileSystem,
+    id: u64,
+) -> Result<u64, ReinstallError> {
+    mount_and_then(root_path, root_fs, |base| Ok(size(&generation(base, id)?)?))
+}
+
+/// Deletes a generation from the root at the given device.
+pub fn delete_generation(
+    root_path: &Path,
+    root_fs: FileSystem,
+    id: u64,
+) -> Result<(), ReinstallError> {
+    info!("removing generation {} of {:?}. This may take a while...", id, root_path);
+    mount_and_then(root_path, root_fs, |base| {
+        fs::remove_dir_all(generation(base, id)?)?;
+        Ok(())
+    })
+}
+
+/// Restores a generation in place of the current system of the root at the given device, which
+/// is retained as a new generation. `/home` is shared by every generation.
+///
+/// The boot loader is then pointed at the kernels of the restored system. On EFI systems, the
+/// `esp` is the partition which the boot loader is installed to, and GRUB is otherwise used. If
+/// that fails, the current system is put back in place.
+pub fn rollback(
+    root_path: &Path,
+    root_fs: FileSystem,
+    id: u64,
+    esp: Option<(&Path, FileSystem)>,
+) -> Result<(), ReinstallError> {
+    mount_and_then(root_path, root_fs, |base| {
+        let restored = generation(base, id)?;
+        let retained = new_generation(base);
+
+        info!("retaining the current system as generation {:?}", retained.file_name());
+        fs::create_dir_all(&retained)?;
+        let exclude = &[OsStr::new("home"), OsStr::new("linux.old"), OsStr::new(GENERATIONS)];
+        move_entries(base, &retained, exclude)?;
+
+        info!("restoring generation {}", id);
+        move_entries(&restored, base, &[OsStr::new("home")])?;
+
+        if let Err(why) = update_bootloader(base, esp) {
+            error!("unable to point the boot loader at generation {}, so undoing the rollback", id);
+            move_entries(base, &restored, exclude)?;
+            move_entries(&retained, base, &[])?;
+            fs::remove_dir(&retained)?;
+            if let Err(why) = update_bootloader(base, esp) {
+                warn!("unable to point the boot loader back at the current system: {}", why);
+            }
+
+            return Err(ReinstallError::Bootloader { why });
+        }
+
+        if let Err(why) = fs::remove_dir(&restored) {
+            warn!("unable to remove the directory of generation {}: {}", id, why);
+        }
+
+        Ok(())
+    })
+}
+
+/// Moves the system which a refresh install replaced into a new generation.
+pub(crate) fn retain_old_root(
+    root_path: &Path,
+    root_fs: FileSystem,
+) -> Result<(), ReinstallError> {
+    mount_and_then(root_path, root_fs, |base| {
+        let old_root = base.join("linux.old");
+        if !old_root.exists() {
+            return Ok(());
+        }
+
+        let retained = new_generation(base);
+        info!("retaining the original system as generation {:?}", retained.file_name());
+        fs::create_dir_all(base.join(GENERATIONS))?;
+        fs::rename(&old_root, &retained)?;
+        Ok(())
+    })
+}
+
+/// Moves each entry of the directory `from` into the directory `to`.
+fn move_entries(from: &Path, to: &Path, exclude: &[&OsStr]) -> Result<(), ReinstallError> {
+    read_and_exclude(from, exclude, |entry| {
+        let filename = entry.file_name().expect("root entry without file name");
+        fs::rename(entry, to.join(filename))?;
+        Ok(())
+    })
+}
+
+/// The directory of an existing generation.
+fn generation(base: &Path, id: u64) -> Result<PathBuf, ReinstallError> {
+    let path = base.join(GENERATIONS).join(id.to_string());
+    if path.is_dir() {
+        Ok(path)
+    } else {
+        Err(ReinstallError::GenerationNotFound { id })
+    }
+}
+
+/// The directory of a generation which is created now.
+fn new_generation(base: &Path) -> PathBuf {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let directory = base.join(GENERATIONS);
+    let id = (now..).find(|id| !directory.join(id.to_string()).exists()).unwrap_or(now);
+    directory.join(id.to_string())
+}
+
+/// Reinstalls the kernels of the system at `base` to the ESP, or regenerates the GRUB menu.
+fn update_bootloader(base: &Path, esp: Option<(&Path, FileSystem)>) -> io::Result<()> {
+    info!("pointing the boot loader at the system at {}", base.display());
+    let chroot = ChrootConfigurator::new(Chroot::new(base)?);
+    match esp {
+        Some((device, fs)) => {
+            let efi = base.join("boot/efi");
+            fs::create_dir_all(&efi)?;
+            let _esp_mount = Mount::new(device, &efi, fs, MountFlags::empty(), None)?
+                .into_unmount_drop(UnmountFlags::DETACH);
+            chroot.bootloader(None)
+        }
+        // kernelstub requires an ESP, so it would fail on systems booted by BIOS.
+        None => chroot.update_grub(),
+    }
+}
@@ -1,14 +1,22 @@
 //! Automatic installation options, and actions executed by them.
 
 mod accounts;
+mod copy;
+mod generations;
 mod options;
 mod retain;
 
 pub(crate) use self::{
     accounts::{AccountFiles, UserData},
+    generations::{retain_old_root, GENERATIONS},
     retain::*,
 };
-pub use self::{options::*, retain::delete_old_install};
+pub use self::{
+    copy::FileBackup,
+    generations::{delete_generation, generation_size, generations, rollback, Generation},
+    options::*,
+    retain::delete_old_install,
+};
 
 use disk_types::FileSystem;
 use std::{
@@ -44,13 +52,31 @@ pub enum ReinstallError {
     InvalidPartitionConfiguration { why: io::Error },
     #[fail(display = "install media at {:?} was not found", path)]
     MissingSquashfs { path: PathBuf },
+    #[fail(display = "backup partition {:?} was not found, or has no file system", device)]
+    BackupPartition { device: PathBuf },
+    #[fail(display = "backup path {:?} was not found within its partition", path)]
+    BackupPath { path: PathBuf },
+    #[fail(display = "backup destination {:?} would be modified by the install", device)]
+    BackupDestinationModified { device: PathBuf },
+    #[fail(display = "backup path {:?} overlaps the directory it would be copied to", path)]
+    BackupOverlap { path: PathBuf },
+    #[fail(display = "backup requires {} bytes, but only {} bytes are free", required, available)]
+    BackupSpace { required: u64, available: u64 },
+    #[fail(display = "no retained system is generation {}", id)]
+    GenerationNotFound { id: u64 },
+    #[fail(display = "unable to point the boot loader at the restored system: {}", why)]
+    Bootloader { why: io::Error },
 }
 
 impl From<io::Error> for ReinstallError {
     fn from(why: io::Error) -> ReinstallError { ReinstallError::IO { why } }
 }
 
-fn mount_and_then<T, F>(device: &Path, fs: FileSystem, mut action: F) -> Result<T, ReinstallError>
+pub(crate) fn mount_and_then<T, F>(
+    device: &Path,
+    fs: FileSystem,
+    mut action: F,
+) -> Result<T, ReinstallError>
 where
     F: FnMut(&Path) -> Result<T, ReinstallError>,
 {
@@ -5,6 +5,8 @@ use std::{fmt, path::PathBuf};
 pub enum AlongsideMethod {
     Shrink { partition: i32, sectors_total: u64, sectors_free: u64, path: PathBuf },
     Free(Region),
+    /// Free extents of an existing LVM volume group, in which new logical volumes are created.
+    VolumeGroup { group: String, sectors_free: u64, sectors_total: u64 },
 }
 
 #[derive(Debug)]
@@ -20,6 +22,7 @@ impl AlongsideOption {
             Some(OS::Linux { ref info, .. }) => info.pretty_name.as_str(),
             Some(OS::Windows(ref name)) => name.as_str(),
             Some(OS::MacOs(ref name)) => name.as_str(),
+            Some(OS::Bsd { ref name, .. }) => name.as_str(),
             None => "none",
         }
     }
@@ -47,6 +50,15 @@ impl fmt::Display for AlongsideOption {
                 device,
                 region.size() / 2048,
             ),
+            AlongsideMethod::VolumeGroup { ref group, sectors_free, sectors_total } => write!(
+                f,
+                "alongside {:?} ({}) in volume group {}: {} of {} MiB free",
+                os,
+                device,
+                group,
+                sectors_free / 2048,
+                sectors_total / 2048
+            ),
         }
     }
 }
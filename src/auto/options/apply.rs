@@ -1,8 +1,8 @@
-use std::{fmt, fs::File, io::BufReader, mem};
+use std::{fmt, fs::File, io::BufReader, mem, path::Path};
 
 use super::{
-    super::super::*, AlongsideMethod, AlongsideOption, EraseOption, InstallOptionError,
-    RecoveryOption, RefreshOption,
+    super::super::*, AlongsideMethod, AlongsideOption, EraseOption, EspOption,
+    InstallOptionError, RecoveryOption, RefreshOption,
 };
 use disk_types::{FileSystem::*, SectorExt};
 
@@ -14,7 +14,14 @@ use proc_mounts::MountIter;
 pub enum InstallOption<'a> {
     Alongside { option: &'a AlongsideOption, password: Option<String>, sectors: u64 },
     Refresh(&'a RefreshOption),
-    Erase { option: &'a EraseOption, password: Option<String> },
+    /// When `encrypt_boot` is set along with a password, `/boot` is stored within the encrypted
+    /// volume, which GRUB will unlock, rather than on a separate unencrypted partition.
+    Erase {
+        option:       &'a EraseOption,
+        password:     Option<String>,
+        erase:        Option<EraseMode>,
+        encrypt_boot: bool,
+    },
     Recovery { option: &'a RecoveryOption, password: Option<String> },
     Upgrade(&'a RecoveryOption),
 }
@@ -32,9 +39,11 @@ impl<'a> fmt::Debug for InstallOption<'a> {
                 write!(f, "InstallOption::UpgradeOption({:?})", option)
             }
             InstallOption::Recovery { .. } => write!(f, "InstallOption::RecoveryOption"),
-            InstallOption::Erase { option, .. } => {
-                write!(f, "InstallOption::EraseOption {{ option: {:?}, .. }}", option)
-            }
+            InstallOption::Erase { option, erase, encrypt_boot, .. } => write!(
+                f,
+                "InstallOption::EraseOption {{ option: {:?}, erase: {:?}, encrypt_boot: {}, .. }}",
+                option, erase, encrypt_boot
+            ),
         }
     }
 }
@@ -72,6 +81,52 @@ fn generate_encryption(
     Ok(value)
 }
 
+/// Labels of the ESP and recovery partition which are created by the planner. A label is
+/// suffixed by the `LabelPolicy` when another install on the system already uses it.
+struct NewLabels {
+    esp:           String,
+    recovery:      String,
+    recovery_name: String,
+}
+
+impl NewLabels {
+    /// Partitions on the `erased` device are not considered, as they are about to be removed.
+    fn new(disks: &Disks, erased: Option<&Path>) -> NewLabels {
+        let in_use = disks
+            .get_physical_devices()
+            .iter()
+            .filter(|disk| erased.map_or(true, |erased| disk.device_path != erased))
+            .flat_map(|disk| disk.partitions.iter())
+            .chain(disks.get_logical_partitions())
+            .flat_map(PartitionInfo::labels)
+            .collect::<Vec<_>>();
+
+        let policy = LabelPolicy::generate();
+        let unique = |base, fs| policy.unique_label(base, fs, in_use.iter().cloned());
+        NewLabels {
+            esp:           unique("EFI", Some(Fat32)),
+            recovery:      unique("recovery", Some(Fat32)),
+            recovery_name: unique("recovery", None),
+        }
+    }
+}
+
+/// Mounts a user-selected ESP at `/boot/efi`, adding its disk to `disks` if necessary.
+fn reuse_esp(disks: &mut Disks, esp: &EspOption) -> Result<(), InstallOptionError> {
+    info!("reusing ESP at {:?}", esp.partition);
+    if disks.get_physical_device(&esp.device).is_none() {
+        let device = Disk::from_name(&esp.device)
+            .ok()
+            .ok_or_else(|| InstallOptionError::DeviceNotFound { path: esp.device.clone() })?;
+        disks.add(device);
+    }
+
+    disks
+        .get_partition_by_path_mut(&esp.partition)
+        .ok_or_else(|| InstallOptionError::DeviceNotFound { path: esp.partition.clone() })
+        .map(|part| part.set_mount("/boot/efi".into()))
+}
+
 impl<'a> InstallOption<'a> {
     /// Applies a given installation option to the `disks` object.
     ///
@@ -81,24 +136,47 @@ impl<'a> InstallOption<'a> {
     ///
     /// Produces error if a partition or configuration file cannot be found.
     pub fn apply(self, disks: &mut Disks) -> Result<(), InstallOptionError> {
+        self.apply_with_esp(disks, None)
+    }
+
+    /// Applies a given installation option to the `disks` object, using the given ESP.
+    ///
+    /// When `esp` is `None`, the ESP is chosen automatically, as with `apply`. Otherwise, the
+    /// selected ESP will be mounted at `/boot/efi` instead of creating or reusing another.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the errors produced by `apply`, an error is produced if an erase option
+    /// was given an ESP that is on the device that is to be erased.
+    pub fn apply_with_esp(
+        self,
+        disks: &mut Disks,
+        esp: Option<&EspOption>,
+    ) -> Result<(), InstallOptionError> {
         disks.rescan_partition_ids();
 
         info!("applying configuration with {disks:#?}");
 
         match self {
             // Install alongside another OS, taking `sectors` from the largest free partition.
-            InstallOption::Alongside { option, password, sectors } => {
-                alongside_config(disks, option, password, sectors)
-            }
+            InstallOption::Alongside { option, password, sectors } => match option.method {
+                // Or in the free extents of an existing volume group, leaving the disks as is.
+                AlongsideMethod::VolumeGroup { ref group, .. } => {
+                    volume_group_config(disks, group, password, sectors, esp)
+                }
+                _ => alongside_config(disks, option, password, sectors, esp),
+            },
             // Reuse existing partitions, without making any modifications.
-            InstallOption::Refresh(option) => refresh_config(disks, option),
+            InstallOption::Refresh(option) => refresh_config(disks, option, esp),
             // Perform a recovery install
             InstallOption::Recovery { option, password } => {
-                recovery_config(disks, option, password)
+                recovery_config(disks, option, password, esp)
             }
             // Reset the `disks` object and designate a disk to be wiped and installed.
-            InstallOption::Erase { option, password } => erase_config(disks, option, password),
-            InstallOption::Upgrade(option) => upgrade_config(disks, option),
+            InstallOption::Erase { option, password, erase, encrypt_boot } => {
+                erase_config(disks, option, password, erase, encrypt_boot, esp)
+            }
+            InstallOption::Upgrade(option) => upgrade_config(disks, option, esp),
         }
     }
 }
@@ -108,6 +186,7 @@ fn alongside_config(
     option: &AlongsideOption,
     password: Option<String>,
     sectors: u64,
+    esp: Option<&EspOption>,
 ) -> Result<(), InstallOptionError> {
     let mut tmp = Disks::default();
     mem::swap(&mut tmp, disks);
@@ -130,6 +209,7 @@ fn alongside_config(
             (resize.end_sector + 1, end)
         }
         AlongsideMethod::Free(ref region) => (region.start + 1, region.end - 1),
+        AlongsideMethod::VolumeGroup { .. } => unreachable!("volume groups are not partitioned"),
     };
 
     let (lvm, root_vg) = match generate_encryption(password)? {
@@ -138,6 +218,7 @@ fn alongside_config(
     };
 
     let bootloader = Bootloader::detect();
+    let labels = NewLabels::new(&tmp, None);
 
     if bootloader == Bootloader::Efi {
         // NOTE: Logic that can enable re-using an existing EFI partition.
@@ -149,21 +230,25 @@ fn alongside_config(
         //     }
         // }
 
-        let esp_end = start + DEFAULT_ESP_SECTORS;
+        if esp.is_none() {
+            let esp_end = start + DEFAULT_ESP_SECTORS;
 
-        device.add_partition(
-            PartitionBuilder::new(start, esp_end, Fat32)
-                .flag(PartitionFlag::PED_PARTITION_ESP)
-                .mount("/boot/efi".into()),
-        )?;
+            device.add_partition(
+                PartitionBuilder::new(start, esp_end, Fat32)
+                    .flag(PartitionFlag::PED_PARTITION_ESP)
+                    .label(labels.esp)
+                    .mount("/boot/efi".into()),
+            )?;
 
-        start = esp_end;
+            start = esp_end;
+        }
 
         let recovery_end = start + DEFAULT_RECOVER_SECTORS;
         device.add_partition(
             PartitionBuilder::new(start, recovery_end, Fat32)
                 .mount("/recovery".into())
-                .name("recovery".into()),
+                .name(labels.recovery_name)
+                .label(labels.recovery),
         )?;
 
         start = recovery_end;
@@ -203,6 +288,11 @@ fn alongside_config(
     }
 
     disks.add(device);
+
+    if let Some(esp) = esp {
+        reuse_esp(disks, esp)?;
+    }
+
     disks.initialize_volume_groups()?;
 
     if let Some(root_vg) = root_vg {
@@ -226,11 +316,103 @@ fn alongside_config(
     Ok(())
 }
 
-fn upgrade_config(disks: &mut Disks, option: &RecoveryOption) -> Result<(), InstallOptionError> {
+/// Creates the root volume, and a swap volume if the group lacks one, in `sectors` of the free
+/// extents of an existing volume group, or in all of them if `sectors` is zero. Without free
+/// space on the disks, the ESP of the existing system is shared, and a recovery partition is
+/// not created.
+fn volume_group_config(
+    disks: &mut Disks,
+    group: &str,
+    password: Option<String>,
+    sectors: u64,
+    esp: Option<&EspOption>,
+) -> Result<(), InstallOptionError> {
+    info!("applying alongside config in volume group {}", group);
+    if password.is_some() {
+        return Err(InstallOptionError::VolumeGroupPassword { vg: group.into() });
+    }
+
+    if disks.get_logical_device(group).is_none() {
+        disks.initialize_volume_groups()?;
+    }
+
+    let encrypted = disks
+        .get_logical_device(group)
+        .ok_or_else(|| InstallOptionError::LogicalDeviceNotFound { vg: group.into() })?
+        .luks_parent
+        .is_some();
+
+    if let Some(esp) = esp {
+        reuse_esp(disks, esp)?;
+    } else if Bootloader::detect() == Bootloader::Efi {
+        let members = disks.find_volume_paths(group);
+        let path = disks
+            .get_physical_devices()
+            .iter()
+            .filter(|disk| members.iter().any(|&(member, _)| member == disk.get_device_path()))
+            .flat_map(|disk| disk.get_partitions())
+            .find(|part| part.is_esp_partition())
+            .map(|part| part.get_device_path().to_path_buf())
+            .ok_or_else(|| InstallOptionError::VolumeGroupWithoutEFI { vg: group.into() })?;
+
+        disks
+            .get_partition_by_path_mut(&path)
+            .ok_or_else(|| InstallOptionError::DeviceNotFound { path: path.clone() })?
+            .set_mount("/boot/efi".into());
+    } else if encrypted {
+        return Err(InstallOptionError::VolumeGroupEncryptedBoot { vg: group.into() });
+    }
+
+    let lvm_device = disks
+        .get_logical_device_mut(group)
+        .ok_or_else(|| InstallOptionError::LogicalDeviceNotFound { vg: group.into() })?;
+
+    let extent = lvm_device.get_pe_size_in_sectors();
+    let free = lvm_device.get_pe_free() * extent;
+    let sectors = if sectors == 0 { free } else { sectors / extent * extent };
+    let swap = !lvm_device.get_partitions().iter().any(|p| p.filesystem == Some(Swap));
+    let required = if swap { DEFAULT_SWAP_SECTORS + extent } else { extent };
+    if sectors > free || sectors < required {
+        return Err(InstallOptionError::VolumeGroupSectors { vg: group.into(), sectors, free });
+    }
+
+    let mut start = lvm_device.get_last_sector() + 1;
+    let end = start + sectors;
+    let root = unique_volume(lvm_device, "root");
+
+    // The last volume is given the remaining extents, so the swap volume is created first.
+    if swap {
+        let swap = start + DEFAULT_SWAP_SECTORS;
+        let name = unique_volume(lvm_device, "swap");
+        lvm_device.add_partition(PartitionBuilder::new(start, swap, Swap).name(name))?;
+        start = swap;
+    }
+
+    lvm_device
+        .add_partition(PartitionBuilder::new(start, end, Ext4).name(root).mount("/".into()))?;
+
+    Ok(())
+}
+
+/// A name for a new logical volume which no other volume in the group has.
+fn unique_volume(device: &LogicalDevice, base: &str) -> String {
+    (1..)
+        .map(|id| if id == 1 { base.to_owned() } else { format!("{}{}", base, id) })
+        .find(|name| device.get_partition(name).is_none())
+        .expect("no unique logical volume name")
+}
+
+fn upgrade_config(
+    disks: &mut Disks,
+    option: &RecoveryOption,
+    esp: Option<&EspOption>,
+) -> Result<(), InstallOptionError> {
     info!("applying upgrade config");
     set_mount_by_identity(disks, &PartitionID::new_uuid(option.root_uuid.clone()), "/")?;
 
-    if let Some(ref efi) = option.efi_uuid {
+    if let Some(esp) = esp {
+        reuse_esp(disks, esp)?;
+    } else if let Some(ref efi) = option.efi_uuid {
         let efi = efi.parse::<PartitionID>().unwrap();
         set_mount_by_identity(disks, &efi, "/boot/efi")?;
     }
@@ -242,7 +424,11 @@ fn upgrade_config(disks: &mut Disks, option: &RecoveryOption) -> Result<(), Inst
 }
 
 /// Apply a `refresh` config to `disks`.
-fn refresh_config(disks: &mut Disks, option: &RefreshOption) -> Result<(), InstallOptionError> {
+fn refresh_config(
+    disks: &mut Disks,
+    option: &RefreshOption,
+    esp: Option<&EspOption>,
+) -> Result<(), InstallOptionError> {
     info!("applying refresh install config");
 
     set_mount_by_identity(disks, &PartitionID::new_uuid(option.root_part.clone()), "/")?;
@@ -251,7 +437,9 @@ fn refresh_config(disks: &mut Disks, option: &RefreshOption) -> Result<(), Insta
         set_mount_by_identity(disks, home, "/home")?;
     }
 
-    if let Some(ref efi) = option.efi_part {
+    if let Some(esp) = esp {
+        reuse_esp(disks, esp)?;
+    } else if let Some(ref efi) = option.efi_part {
         set_mount_by_identity(disks, efi, "/boot/efi")?;
     } else if Bootloader::detect() == Bootloader::Efi {
         return Err(InstallOptionError::RefreshWithoutEFI);
@@ -288,6 +476,7 @@ fn recovery_config(
     disks: &mut Disks,
     option: &RecoveryOption,
     password: Option<String>,
+    esp: Option<&EspOption>,
 ) -> Result<(), InstallOptionError> {
     let mut tmp = Disks::default();
     mem::swap(&mut tmp, disks);
@@ -325,7 +514,7 @@ fn recovery_config(
         let lvm_part: Option<PathBuf> =
             option.luks_uuid.clone().and_then(|uuid| PartitionID::new_uuid(uuid).get_device_path());
 
-        if let Some(ref uuid) = option.efi_uuid {
+        if let (None, Some(uuid)) = (esp, option.efi_uuid.as_ref()) {
             let path =
                 option.parse_efi_id().unwrap().get_device_path().expect("no uuid for efi part");
             recovery_device
@@ -391,6 +580,11 @@ fn recovery_config(
     }
 
     disks.add(recovery_device);
+
+    if let Some(esp) = esp {
+        reuse_esp(disks, esp)?;
+    }
+
     disks.initialize_volume_groups()?;
 
     if let Some(root_vg) = root_vg {
@@ -409,28 +603,63 @@ fn recovery_config(
     Ok(())
 }
 
+/// Only GRUB on BIOS keeps the kernel and its initramfs within the encrypted `/boot`. On EFI,
+/// kernelstub copies them to the ESP, which would expose the key file in the initramfs.
+fn verify_encrypted_boot(bootloader: Bootloader) -> Result<(), InstallOptionError> {
+    match bootloader {
+        Bootloader::Bios => Ok(()),
+        Bootloader::Efi => Err(InstallOptionError::EfiEncryptedBoot),
+        Bootloader::UBoot => Err(InstallOptionError::UBootEncryptedBoot),
+    }
+}
+
 /// Apply an "erase and install" configuration to `disks`;
 fn erase_config(
     disks: &mut Disks,
     option: &EraseOption,
     password: Option<String>,
+    erase: Option<EraseMode>,
+    encrypt_boot: bool,
+    esp: Option<&EspOption>,
 ) -> Result<(), InstallOptionError> {
+    if let Some(esp) = esp {
+        if esp.device == option.device {
+            return Err(InstallOptionError::EspOnErasedDevice { path: esp.partition.clone() });
+        }
+    }
+
     let mut tmp = Disks::default();
     mem::swap(&mut tmp, disks);
 
     let bootloader = Bootloader::detect();
+    if encrypt_boot {
+        verify_encrypted_boot(bootloader)?;
+    }
+
+    let NewLabels { esp: esp_label, recovery: recovery_label, recovery_name } =
+        NewLabels::new(&tmp, Some(&option.device));
+
+    // An ESP will not be created when an existing ESP on another device was selected.
+    let esp_sectors = if esp.is_some() { 0 } else { DEFAULT_ESP_SECTORS };
 
     let start_sector = Sector::Start;
     let boot_sector = Sector::Unit(DEFAULT_ESP_SECTORS);
-    let recovery_sector = Sector::Unit(DEFAULT_ESP_SECTORS + DEFAULT_RECOVER_SECTORS);
+    let recovery_start = if esp.is_some() { start_sector } else { boot_sector };
+    let recovery_sector = Sector::Unit(esp_sectors + DEFAULT_RECOVER_SECTORS);
     let swap_sector = Sector::UnitFromEnd(DEFAULT_SWAP_SECTORS);
     let end_sector = Sector::End;
 
     let (lvm, root_vg) = match generate_encryption(password)? {
-        Some((enc, root)) => (Some((enc, root.clone())), Some(root)),
+        Some((enc, root)) => {
+            let enc = if encrypt_boot { enc.encrypted_boot() } else { enc };
+            (Some((enc, root.clone())), Some(root))
+        }
         None => (None, None),
     };
 
+    // BIOS and U-Boot systems require a separate boot partition, unless GRUB will unlock `/boot`.
+    let separate_boot = lvm.as_ref().map_or(false, |(enc, _)| !enc.encrypted_boot);
+
     {
         let mut device = Disk::from_name(&option.device)
             .ok()
@@ -442,22 +671,28 @@ fn erase_config(
                     .mklabel(PartitionTable::Gpt)
                     // Configure ESP partition
                     .and_then(|_| {
+                        if esp.is_some() {
+                            return Ok(());
+                        }
+
                         let start = device.get_sector(start_sector);
                         let end = device.get_sector(boot_sector);
                         device.add_partition(
                             PartitionBuilder::new(start, end, Fat32)
                                 .partition_type(PartitionType::Primary)
                                 .flag(PartitionFlag::PED_PARTITION_ESP)
+                                .label(esp_label)
                                 .mount("/boot/efi".into()),
                         )
                     })
                     // Configure recovery partition
                     .and_then(|_| {
-                        let start = device.get_sector(boot_sector);
+                        let start = device.get_sector(recovery_start);
                         let end = device.get_sector(recovery_sector);
                         device.add_partition(
                             PartitionBuilder::new(start, end, Fat32)
-                                .name("recovery".into())
+                                .name(recovery_name)
+                                .label(recovery_label)
                                 .mount("/recovery".into()),
                         )
                     })
@@ -468,7 +703,7 @@ fn erase_config(
                     .mklabel(PartitionTable::Msdos)
                     // This is used to ensure LVM installs will work with BIOS
                     .and_then(|_| {
-                        if lvm.is_some() {
+                        if separate_boot {
                             let start = device.get_sector(start_sector);
                             let end = device.get_sector(boot_sector);
                             device
@@ -485,6 +720,30 @@ fn erase_config(
                     })
                     .map(|(start, end)| (device.get_sector(start), device.get_sector(end)))
             }
+            Bootloader::UBoot => {
+                // The blobs which the board boots from are written before the first partition.
+                let reserved = uboot::Board::detect().reserved() / device.get_logical_block_size();
+                let start = device.get_sector(start_sector).max(reserved);
+                let end = device.get_sector(swap_sector);
+                device
+                    .mklabel(PartitionTable::Msdos)
+                    // U-Boot cannot read `/boot` from a logical volume.
+                    .and_then(|_| {
+                        if separate_boot {
+                            let boot_end = device.get_sector(boot_sector);
+                            device
+                                .add_partition(
+                                    PartitionBuilder::new(start, boot_end, Ext4)
+                                        .partition_type(PartitionType::Primary)
+                                        .flag(PartitionFlag::PED_PARTITION_BOOT)
+                                        .mount("/boot".into()),
+                                )
+                                .map(|_| (boot_end, end))
+                        } else {
+                            Ok((start, end))
+                        }
+                    })
+            }
         };
 
         // Configure optionally-encrypted root volume
@@ -505,9 +764,18 @@ fn erase_config(
                 device.add_partition(PartitionBuilder::new(start, end, Swap))
             })?;
 
+        // Optionally erase the existing data before the new partition table is written.
+        if let Some(mode) = erase {
+            device.secure_erase(mode)?;
+        }
+
         disks.add(device);
     }
 
+    if let Some(esp) = esp {
+        reuse_esp(disks, esp)?;
+    }
+
     disks.initialize_volume_groups()?;
 
     if let Some(root_vg) = root_vg {
@@ -525,3 +793,21 @@ fn erase_config(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_boot() {
+        assert!(verify_encrypted_boot(Bootloader::Bios).is_ok());
+        assert!(matches!(
+            verify_encrypted_boot(Bootloader::Efi),
+            Err(InstallOptionError::EfiEncryptedBoot)
+        ));
+        assert!(matches!(
+            verify_encrypted_boot(Bootloader::UBoot),
+            Err(InstallOptionError::UBootEncryptedBoot)
+        ));
+    }
+}
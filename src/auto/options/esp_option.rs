@@ -0,0 +1,95 @@
+use disk_types::{BlockDeviceExt, FileSystem, PartitionExt, SectorExt};
+use crate::disks::PartitionInfo;
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+use sys_mount::{Mount, MountFlags, Unmount, UnmountFlags};
+use tempdir::TempDir;
+
+/// An existing EFI system partition which may be reused by an installation.
+#[derive(Debug)]
+pub struct EspOption {
+    /// The disk which contains the ESP.
+    pub device:        PathBuf,
+    /// The path of the ESP partition itself.
+    pub partition:     PathBuf,
+    pub sectors_total: u64,
+    /// Sectors that are not in use, if the usage could be determined.
+    pub sectors_free:  Option<u64>,
+    /// Names of the directories within `/EFI`, such as `Microsoft` or `systemd`.
+    pub entries:       Vec<String>,
+}
+
+impl EspOption {
+    pub(crate) fn new(device: &Path, part: &PartitionInfo) -> EspOption {
+        let sectors_total = part.get_sectors();
+        let entries = match esp_entries(part) {
+            Ok(entries) => entries,
+            Err(why) => {
+                warn!("unable to read contents of ESP at {:?}: {}", part.get_device_path(), why);
+                Vec::new()
+            }
+        };
+
+        EspOption {
+            device: device.to_path_buf(),
+            partition: part.get_device_path().to_path_buf(),
+            sectors_total,
+            sectors_free: part.sectors_used().ok().map(|used| sectors_total.saturating_sub(used)),
+            entries,
+        }
+    }
+}
+
+impl fmt::Display for EspOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ESP on {} ({}): {} MiB",
+            self.partition.display(),
+            self.device.display(),
+            self.sectors_total / 2048
+        )?;
+
+        if let Some(free) = self.sectors_free {
+            write!(f, ", {} MiB free", free / 2048)?;
+        }
+
+        if !self.entries.is_empty() {
+            write!(f, ", containing {}", self.entries.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Lists the boot loader directories in the ESP, temporarily mounting it if necessary.
+fn esp_entries(part: &PartitionInfo) -> io::Result<Vec<String>> {
+    fn read_entries(base: &Path) -> io::Result<Vec<String>> {
+        let efi = base.join("EFI");
+        if !efi.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = fs::read_dir(efi)?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect::<Vec<String>>();
+
+        entries.sort();
+        Ok(entries)
+    }
+
+    if let Some(ref mount) = part.mount_point {
+        return read_entries(mount);
+    }
+
+    let fs = part.filesystem.unwrap_or(FileSystem::Fat32);
+    let tempdir = TempDir::new("distinst-esp")?;
+    let _mount = Mount::new(part.get_device_path(), tempdir.path(), fs, MountFlags::RDONLY, None)?
+        .into_unmount_drop(UnmountFlags::DETACH);
+
+    read_entries(tempdir.path())
+}
@@ -3,14 +3,17 @@
 mod alongside_option;
 mod apply;
 mod erase_option;
+mod esp_option;
 mod recovery_option;
 mod refresh_option;
 
 pub use self::{
-    alongside_option::*, apply::*, erase_option::*, recovery_option::*, refresh_option::*,
+    alongside_option::*, apply::*, erase_option::*, esp_option::*, recovery_option::*,
+    refresh_option::*,
 };
 
 use super::super::*;
+use crate::disks::operations::volume_sectors_used;
 use disk_types::PartitionExt;
 
 use os_release::OS_RELEASE;
@@ -21,6 +24,10 @@ use std::path::PathBuf;
 pub struct InstallOptions {
     pub alongside_options: Vec<AlongsideOption>,
     pub erase_options:     Vec<EraseOption>,
+    pub esp_options:       Vec<EspOption>,
+    /// Set when disks are hidden by an Intel storage controller in RAID mode, in which case
+    /// the user should be told to switch the controller to AHCI mode in the firmware.
+    pub intel_raid_mode:   Option<IntelRaidMode>,
     pub recovery_option:   Option<RecoveryOption>,
     pub refresh_options:   Vec<RefreshOption>,
 }
@@ -34,8 +41,13 @@ impl InstallOptions {
         let mut erase_options = Vec::new();
         let mut refresh_options = Vec::new();
         let mut alongside_options = Vec::new();
+        let mut esp_options = Vec::new();
 
         let recovery_option = detect_recovery();
+        let intel_raid_mode = IntelRaidMode::detect().filter(IntelRaidMode::hides_disks);
+        if let Some(ref raid) = intel_raid_mode {
+            warn!("disks are hidden by {:?}: {}", raid.controllers, raid);
+        }
         let os_release = OS_RELEASE.as_ref().expect("OS_RELEASE fetch failed");
 
         {
@@ -52,6 +64,11 @@ impl InstallOptions {
                             OS::Windows(ref version) => format!("Windows ({})", version),
                             OS::Linux { ref info, .. } => format!("Linux ({})", info.pretty_name),
                             OS::MacOs(ref version) => format!("Mac OS ({})", version),
+                            OS::Bsd { ref name, ref version } => format!(
+                                "{} ({})",
+                                name,
+                                version.as_ref().map_or("unknown version", String::as_str)
+                            ),
                         }
                     );
 
@@ -94,12 +111,40 @@ impl InstallOptions {
                     continue;
                 }
 
+                // The partitions of an Intel RST array are those of its volume, not its members.
+                if device.is_rst_member() {
+                    info!("install options: skipping RST member {:?}", device.get_device_path());
+                    continue;
+                }
+
                 eprintln!("device: {:?}", device.get_device_path());
 
+                for part in device.get_partitions().iter().filter(|p| p.is_esp_partition()) {
+                    let option = EspOption::new(device.get_device_path(), part);
+                    info!("found ESP option: {}", option);
+                    esp_options.push(option);
+                }
+
+                // BSD file systems cannot be shrunk, but the free space on their disk can be used
+                // to install alongside them.
+                let bsd = device
+                    .get_partitions()
+                    .iter()
+                    .filter(|part| part.filesystem.is_none())
+                    .find_map(|part| part.probe_os());
+
                 let mut last_end_sector = 1024;
 
                 for part in device.get_partitions() {
-                    if let Ok(used) = part.sectors_used() {
+                    // The largest logical volume within a LUKS or LVM partition may be reduced.
+                    let used = match part.filesystem {
+                        Some(fs @ FileSystem::Luks) | Some(fs @ FileSystem::Lvm) => {
+                            volume_sectors_used(part.get_device_path(), fs)
+                        }
+                        _ => part.sectors_used(),
+                    };
+
+                    if let Ok(used) = used {
                         let sectors = part.get_sectors();
                         let free = sectors - used;
                         let os = check_partition(part);
@@ -134,7 +179,7 @@ impl InstallOptions {
                         );
                         alongside_options.push(AlongsideOption {
                             device:    device.get_device_path().to_path_buf(),
-                            alongside: None,
+                            alongside: bsd.clone(),
                             method:    AlongsideMethod::Free(Region::new(
                                 last_end_sector + 1,
                                 part.start_sector - 1,
@@ -155,7 +200,7 @@ impl InstallOptions {
                     );
                     alongside_options.push(AlongsideOption {
                         device:    device.get_device_path().to_path_buf(),
-                        alongside: None,
+                        alongside: bsd.clone(),
                         method:    AlongsideMethod::Free(Region::new(
                             last_end_sector + 1,
                             last_sector,
@@ -200,13 +245,50 @@ impl InstallOptions {
             }
 
             for device in disks.get_logical_devices() {
+                let mut os = None;
                 for part in device.get_partitions() {
-                    check_partition(part);
+                    os = os.or(check_partition(part));
                 }
+
+                // New logical volumes may be created in the free extents of an existing group.
+                let sectors_free = device.get_pe_free() * device.get_pe_size_in_sectors();
+                if !device.is_source || required_space >= sectors_free {
+                    continue;
+                }
+
+                let disk = match disks.find_volume_paths(&device.volume_group).first() {
+                    Some(&(disk, _)) => disk.to_path_buf(),
+                    None => continue,
+                };
+
+                info!(
+                    "found free sectors in volume group {} on {:?}: {} free of {}",
+                    device.volume_group,
+                    disk,
+                    sectors_free,
+                    device.get_sectors()
+                );
+
+                alongside_options.push(AlongsideOption {
+                    device:    disk,
+                    alongside: os,
+                    method:    AlongsideMethod::VolumeGroup {
+                        group: device.volume_group.clone(),
+                        sectors_free,
+                        sectors_total: device.get_sectors(),
+                    },
+                });
             }
         }
 
-        InstallOptions { alongside_options, erase_options, refresh_options, recovery_option }
+        InstallOptions {
+            alongside_options,
+            erase_options,
+            esp_options,
+            intel_raid_mode,
+            refresh_options,
+            recovery_option,
+        }
     }
 }
 
@@ -232,6 +314,20 @@ pub enum InstallOptionError {
     RecoveryNoLvm,
     #[fail(display = "EFI partition is required, but not found on this option")]
     RefreshWithoutEFI,
+    #[fail(display = "selected EFI partition ({:?}) is on the device that will be erased", path)]
+    EspOnErasedDevice { path: PathBuf },
+    #[fail(display = "an EFI partition was not found on the disks of volume group {}", vg)]
+    VolumeGroupWithoutEFI { vg: String },
+    #[fail(display = "/boot cannot be read from the encrypted volume group {} on BIOS", vg)]
+    VolumeGroupEncryptedBoot { vg: String },
+    #[fail(display = "/boot cannot be encrypted on EFI, because the ESP would hold its key file")]
+    EfiEncryptedBoot,
+    #[fail(display = "/boot cannot be encrypted, because U-Boot cannot unlock it")]
+    UBootEncryptedBoot,
+    #[fail(display = "logical volumes in volume group {} are encrypted by the group", vg)]
+    VolumeGroupPassword { vg: String },
+    #[fail(display = "{} sectors of volume group {} cannot be used, of {} free", sectors, vg, free)]
+    VolumeGroupSectors { vg: String, sectors: u64, free: u64 },
     #[fail(display = "failed to retrieve list of mounts from /proc/mounts: {}", why)]
     ProcMounts { why: io::Error },
     #[fail(display = "could not remount /cdrom as rewriteable: {}", _0)]
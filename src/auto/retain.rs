@@ -2,12 +2,15 @@
 
 use crate::bootloader::Bootloader;
 use disk_types::FileSystem;
-use crate::disks::Disks;
+use crate::disks::{validation_result, Disks};
+use crate::minimum_root_size;
 
-use super::{mount_and_then, AccountFiles, ReinstallError, UserData};
+use super::{generations::GENERATIONS, mount_and_then, AccountFiles, ReinstallError, UserData};
 
 use crate::misc;
+use crate::squashfs;
 use std::{
+    collections::HashSet,
     ffi::{OsStr, OsString},
     fs::{self, File, OpenOptions, Permissions},
     io::{self, Read, Seek, SeekFrom, Write},
@@ -18,11 +21,11 @@ use std::{
     path::{Path, PathBuf},
 };
 
-/// Removes all files in the chroot at `/`, except for `/home`.
+/// Removes all files in the chroot at `/`, except for `/home` and the retained generations.
 pub fn remove_root(root_path: &Path, root_fs: FileSystem) -> Result<(), ReinstallError> {
     info!("removing all files except /home. This may take a while...");
     mount_and_then(root_path, root_fs, |base| {
-        read_and_exclude(base, &[OsStr::new("home")], |entry| {
+        read_and_exclude(base, &[OsStr::new("home"), OsStr::new(GENERATIONS)], |entry| {
             if entry.is_dir() {
                 fs::remove_dir_all(entry)?;
             } else {
@@ -49,7 +52,7 @@ pub fn move_root(root_path: &Path, root_fs: FileSystem) -> Result<(), ReinstallE
         fs::create_dir(&old_root)?;
 
         // Migrate the current root system to the old root path.
-        let exclude = &[OsStr::new("home"), OsStr::new("linux.old")];
+        let exclude = &[OsStr::new("home"), OsStr::new("linux.old"), OsStr::new(GENERATIONS)];
         read_and_exclude(base, exclude, |entry| {
             let filename = entry.file_name().expect("root entry without file name");
             fs::rename(entry, base.join("linux.old").join(filename))?;
@@ -63,7 +66,8 @@ pub fn recover_root(root_path: &Path, root_fs: FileSystem) -> Result<(), Reinsta
     info!("attempting to restore the original system");
     mount_and_then(root_path, root_fs, |base| {
         // Remove files installed by the installer.
-        read_and_exclude(base, &[OsStr::new("home"), OsStr::new("linux.old")], |entry| {
+        let exclude = &[OsStr::new("home"), OsStr::new("linux.old"), OsStr::new(GENERATIONS)];
+        read_and_exclude(base, exclude, |entry| {
             if entry.is_dir() {
                 fs::remove_dir_all(entry)?;
             } else {
@@ -98,24 +102,118 @@ pub fn delete_old_install(root_path: &Path, root_fs: FileSystem) -> Result<(), R
     })
 }
 
+/// The packages which were installed manually on the system at the given device, as
+/// `apt-mark showmanual` reports them, so that a refresh install may reinstall them.
+pub fn manual_packages(
+    root_path: &Path,
+    root_fs: FileSystem,
+) -> Result<Vec<String>, ReinstallError> {
+    info!("collecting the manually installed packages");
+    mount_and_then(root_path, root_fs, |base| {
+        let status = fs::read_to_string(base.join("var/lib/dpkg/status"))?;
+        let extended_states =
+            fs::read_to_string(base.join("var/lib/apt/extended_states")).unwrap_or_default();
+        Ok(parse_manual_packages(&status, &extended_states))
+    })
+}
+
+/// Packages which dpkg reports as installed, and which apt did not mark as automatically
+/// installed. Versioned kernel packages are excluded, as the new system ships its own kernel.
+///
+/// Packages of a foreign architecture are qualified with it, as in `libc6:i386`. The native
+/// architecture is that of dpkg, which apt also records for `Architecture: all` packages.
+fn parse_manual_packages(status: &str, extended_states: &str) -> Vec<String> {
+    fn fields<'a>(paragraph: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        paragraph.lines().filter_map(|line| {
+            let mut split = line.splitn(2, ':');
+            Some((split.next()?.trim(), split.next()?.trim()))
+        })
+    }
+
+    let automatic = extended_states
+        .split("\n\n")
+        .filter_map(|paragraph| {
+            let (mut name, mut arch, mut auto) = (None, "", false);
+            for (key, value) in fields(paragraph) {
+                match key {
+                    "Package" => name = Some(value),
+                    "Architecture" => arch = value,
+                    "Auto-Installed" => auto = value == "1",
+                    _ => (),
+                }
+            }
+
+            name.filter(|_| auto).map(|name| (name, arch))
+        })
+        .collect::<HashSet<(&str, &str)>>();
+
+    let is_kernel = |name: &str| {
+        let prefixes = ["linux-image-", "linux-headers-", "linux-modules-extra-", "linux-modules-"];
+        prefixes.iter().any(|prefix| {
+            name.strip_prefix(prefix)
+                .map_or(false, |rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+        })
+    };
+
+    let installed = status
+        .split("\n\n")
+        .filter_map(|paragraph| {
+            let (mut name, mut arch, mut installed) = (None, "", false);
+            for (key, value) in fields(paragraph) {
+                match key {
+                    "Package" => name = Some(value),
+                    "Architecture" => arch = value,
+                    "Status" => installed = value.ends_with(" installed"),
+                    _ => (),
+                }
+            }
+
+            name.filter(|_| installed).map(|name| (name, arch))
+        })
+        .collect::<Vec<(&str, &str)>>();
+
+    let native = installed.iter().find(|&&(name, _)| name == "dpkg").map_or("", |&(_, arch)| arch);
+
+    let mut packages = installed
+        .into_iter()
+        .filter(|&(name, _)| !is_kernel(name))
+        .filter_map(|(name, arch)| {
+            let arch = if arch == "all" { native } else { arch };
+            if automatic.contains(&(name, arch)) {
+                None
+            } else if arch.is_empty() || arch == native {
+                Some(name.to_owned())
+            } else {
+                Some([name, ":", arch].concat())
+            }
+        })
+        .collect::<Vec<String>>();
+
+    packages.sort();
+    packages.dedup();
+    packages
+}
+
 /// Checks to see if the backup install has a chance to succeed, before starting it.
 pub fn validate_backup_conditions<P: AsRef<Path>>(
     disks: &Disks,
     path: P,
 ) -> Result<(), ReinstallError> {
-    partition_configuration_is_valid(&disks).and_then(|_| install_media_exists(path.as_ref()))
+    let path = path.as_ref();
+    partition_configuration_is_valid(&disks, path).and_then(|_| install_media_exists(path))
 }
 
 /// Validate that the configuration in the disks structure is valid for installation.
-fn partition_configuration_is_valid(disks: &Disks) -> Result<(), ReinstallError> {
-    disks
-        .verify_partitions(Bootloader::detect())
-        .map_err(|why| ReinstallError::InvalidPartitionConfiguration { why })
+fn partition_configuration_is_valid(disks: &Disks, squashfs: &Path) -> Result<(), ReinstallError> {
+    let issues = disks.verify_partitions(Bootloader::detect(), minimum_root_size(squashfs));
+    validation_result(&issues).map_err(|why| ReinstallError::InvalidPartitionConfiguration { why })
 }
 
 /// Returns an error if the given path does not exist.
+///
+/// Remote images are not checked, as they will be fetched in the initialization step.
 fn install_media_exists(path: &Path) -> Result<(), ReinstallError> {
-    if path.exists() {
+    if path.exists() || path.to_str().map_or(false, squashfs::fetch::is_remote) {
         Ok(())
     } else {
         Err(ReinstallError::MissingSquashfs { path: path.to_path_buf() })
@@ -124,7 +222,7 @@ fn install_media_exists(path: &Path) -> Result<(), ReinstallError> {
 
 /// Read the given directory at `path`,and apply a `func` to each item that is not in the
 /// exclusion list.
-fn read_and_exclude<F: FnMut(&Path) -> Result<(), ReinstallError>>(
+pub(super) fn read_and_exclude<F: FnMut(&Path) -> Result<(), ReinstallError>>(
     path: &Path,
     exclude: &[&OsStr],
     mut func: F,
@@ -150,6 +248,8 @@ pub struct Backup<'a> {
     pub localtime: Option<PathBuf>,
     pub timezone:  Option<Vec<u8>>,
     pub networks:  Option<Vec<(OsString, Vec<u8>)>>,
+    /// The manually installed packages of the previous system, to reinstall after the refresh.
+    pub packages:  Vec<String>,
 }
 
 impl<'a> Backup<'a> {
@@ -196,7 +296,7 @@ impl<'a> Backup<'a> {
 
             let users = users.iter().filter_map(|user| account_files.get(user)).collect::<Vec<_>>();
 
-            Ok(Backup { users, localtime, timezone, networks })
+            Ok(Backup { users, localtime, timezone, networks, packages: Vec::new() })
         })
     }
 
@@ -363,4 +463,34 @@ mod tests {
             Some(PathBuf::from("../usr/share/zoneinfo/America/Denver"))
         )
     }
+
+    #[test]
+    fn parses_manual_packages() {
+        const STATUS: &str = "Package: dpkg\nStatus: install ok installed\n\
+                              Architecture: amd64\n\n\
+                              Package: firefox\nStatus: install ok installed\n\
+                              Architecture: amd64\n\n\
+                              Package: libfoo1\nStatus: install ok installed\n\
+                              Architecture: amd64\n\n\
+                              Package: libfoo1\nStatus: install ok installed\n\
+                              Architecture: i386\n\n\
+                              Package: fonts-foo\nStatus: install ok installed\n\
+                              Architecture: all\n\n\
+                              Package: gedit\nStatus: deinstall ok config-files\n\n\
+                              Package: linux-image-6.2.6-76060206-generic\n\
+                              Status: install ok installed\n\n\
+                              Package: linux-modules-extra-6.2.6-76060206-generic\n\
+                              Status: install ok installed\n\n\
+                              Package: linux-image-generic\nStatus: install ok installed\n";
+        const EXTENDED: &str = "Package: libfoo1\nArchitecture: amd64\nAuto-Installed: 1\n\n\
+                                Package: fonts-foo\nArchitecture: amd64\nAuto-Installed: 1\n\n\
+                                Package: firefox\nArchitecture: amd64\nAuto-Installed: 0\n";
+
+        assert_eq!(parse_manual_packages(STATUS, EXTENDED), vec![
+            "dpkg".to_owned(),
+            "firefox".to_owned(),
+            "libfoo1:i386".to_owned(),
+            "linux-image-generic".to_owned()
+        ]);
+    }
 }
@@ -0,0 +1,74 @@
+//! Arch Linux and its derivatives, whose packages are managed by pacman.
+//!
+//! Language packs and dependencies are not yet resolved, so the removal list is applied as is.
+//! The Debian packages which distinst installs are only installed if they are in `PACKAGES`.
+
+use super::{rename, PackageBackend};
+use crate::chroot::Chroot;
+use std::io;
+
+/// The Debian packages which distinst installs, and their names in Arch. The pins and hooks of
+/// clevis are a part of its package, and systemd-homed is a part of systemd.
+const PACKAGES: &[(&str, &str)] = &[
+    ("avahi-daemon", "avahi"),
+    ("btrfs-progs", "btrfs-progs"),
+    ("clevis", "clevis"),
+    ("clevis-initramfs", "clevis"),
+    ("clevis-luks", "clevis"),
+    ("clevis-tang", "clevis"),
+    ("cryptsetup", "cryptsetup"),
+    ("dosfstools", "dosfstools"),
+    ("e2fsprogs", "e2fsprogs"),
+    ("efibootmgr", "efibootmgr"),
+    ("exfatprogs", "exfatprogs"),
+    ("f2fs-tools", "f2fs-tools"),
+    ("flatpak", "flatpak"),
+    ("grub-efi-amd64", "grub"),
+    ("grub-efi-arm64", "grub"),
+    ("grub-pc", "grub"),
+    ("libnss-mdns", "nss-mdns"),
+    ("lvm2", "lvm2"),
+    ("mdadm", "mdadm"),
+    ("ntfs-3g", "ntfs-3g"),
+    ("nvme-cli", "nvme-cli"),
+    ("open-iscsi", "open-iscsi"),
+    ("openssh-server", "openssh"),
+    ("systemd-homed", "systemd"),
+    ("xfsprogs", "xfsprogs"),
+];
+
+pub struct Pacman;
+
+impl PackageBackend for Pacman {
+    fn name(&self) -> &'static str { "pacman" }
+
+    fn install(&self, chroot: &Chroot, packages: &[&str]) -> io::Result<()> {
+        info!("installing packages: {:?}", packages);
+        let mut args = vec!["-S", "--noconfirm", "--needed"];
+        args.extend_from_slice(packages);
+        chroot.command("pacman", &args).run()
+    }
+
+    fn remove(&self, chroot: &Chroot, packages: &[&str]) -> io::Result<()> {
+        // pacman refuses to remove packages which are not installed.
+        let installed = chroot.command("pacman", &["-Qq"]).run_with_stdout()?;
+        let installed = installed.lines().collect::<Vec<_>>();
+        let packages = packages.iter().filter(|pkg| installed.contains(*pkg)).collect::<Vec<_>>();
+        if packages.is_empty() {
+            return Ok(());
+        }
+
+        info!("removing packages: {:?}", packages);
+        let mut args = vec!["-Rns", "--noconfirm"];
+        args.extend(packages);
+        chroot.command("pacman", &args).run()
+    }
+
+    fn dependencies(&self, _packages: &[&str]) -> Option<Vec<String>> { None }
+
+    fn language_support(&self, _lang: &str, _chroot: &Chroot) -> io::Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn package_name<'a>(&self, debian: &'a str) -> Option<&'a str> { rename(PACKAGES, debian) }
+}
@@ -1,8 +1,5 @@
-use crate::{
-    bootloader::Bootloader,
-    chroot::Chroot,
-    installer::{bitflags::FileSystemSupport, traits::InstallerDiskOps},
-};
+use super::{file_system_packages, PackageBackend};
+use crate::{bootloader::Bootloader, chroot::Chroot, installer::bitflags::FileSystemSupport, uboot};
 use os_release::OsRelease;
 use std::{
     collections::HashSet,
@@ -11,6 +8,82 @@ use std::{
     process::Command,
 };
 
+/// Options which keep apt from mounting, or detecting, the installation media by itself.
+pub(crate) const APT_OPTIONS: &[&str] = &[
+    "-o",
+    "Acquire::cdrom::AutoDetect=0",
+    "-o",
+    "Acquire::cdrom::mount=/cdrom",
+    "-o",
+    "APT::CDROM::NoMount=1",
+];
+
+pub struct Apt;
+
+impl Apt {
+    /// The names of the packages which the repositories of the chroot provide.
+    pub fn available(chroot: &Chroot) -> io::Result<HashSet<String>> {
+        let names = chroot.command("apt-cache", &["pkgnames"]).run_with_stdout()?;
+        Ok(names.lines().map(String::from).collect())
+    }
+}
+
+impl PackageBackend for Apt {
+    fn name(&self) -> &'static str { "apt" }
+
+    fn install(&self, chroot: &Chroot, packages: &[&str]) -> io::Result<()> {
+        info!("installing packages: {:?}", packages);
+        chroot
+            .command(
+                "apt-get",
+                &cascade! {
+                    Vec::with_capacity(APT_OPTIONS.len() + packages.len() + 3);
+                    ..extend_from_slice(&["install", "-y"]);
+                    ..extend_from_slice(APT_OPTIONS);
+                    ..extend_from_slice(packages);
+                },
+            )
+            .run()
+    }
+
+    fn remove(&self, chroot: &Chroot, packages: &[&str]) -> io::Result<()> {
+        info!("removing packages: {:?}", packages);
+        chroot
+            .command(
+                "apt-get",
+                &cascade! {
+                    Vec::with_capacity(packages.len() + 2);
+                    ..extend_from_slice(&["purge", "-y"]);
+                    ..extend_from_slice(packages);
+                },
+            )
+            .run()?;
+        chroot.command("apt-get", &["autoremove", "-y", "--purge"]).run()
+    }
+
+    fn dependencies(&self, packages: &[&str]) -> Option<Vec<String>> {
+        get_dependencies_from_list(packages)
+    }
+
+    fn language_support(&self, lang: &str, chroot: &Chroot) -> io::Result<Option<String>> {
+        check_language_support(lang, chroot)
+    }
+
+    fn package_name<'a>(&self, debian: &'a str) -> Option<&'a str> { Some(debian) }
+
+    fn bootloader_packages(&self, release: &OsRelease) -> io::Result<&'static [&'static str]> {
+        get_bootloader_packages(release)
+    }
+
+    fn required_packages(
+        &self,
+        flags: FileSystemSupport,
+        release: &OsRelease,
+    ) -> Vec<&'static str> {
+        get_required_packages(flags, release)
+    }
+}
+
 pub fn check_language_support(lang: &str, chroot: &Chroot) -> io::Result<Option<String>> {
     // Takes the locale, such as `en_US.UTF-8`, and changes it into `en`.
     let locale = match lang.find('_') {
@@ -98,6 +171,7 @@ fn parse_dependency_line<F: FnMut(&str)>(line: &str, mut func: F) {
 pub fn get_bootloader_packages(os_release: &OsRelease) -> io::Result<&'static [&'static str]> {
     match (os_release.name.as_str(), os_release.version_id.as_str(), env::consts::ARCH, Bootloader::detect()) {
         (_, _, _, Bootloader::Bios) => Ok(&["grub-common", "grub2-common", "grub-pc"]),
+        (_, _, _, Bootloader::UBoot) => Ok(uboot::Board::detect().packages),
         ("Pop!_OS", _, _, Bootloader::Efi) => Ok(&["kernelstub"]),
         ("Ubuntu", "24.04", "aarch64", Bootloader::Efi) => Ok(&[
             "efibootmgr",
@@ -131,40 +205,11 @@ pub fn get_bootloader_packages(os_release: &OsRelease) -> io::Result<&'static [&
     }
 }
 
-pub fn get_required_packages<D: InstallerDiskOps>(
-    disks: &D,
-    release: &OsRelease,
-) -> Vec<&'static str> {
-    let flags = disks.get_support_flags();
-
-    let mut retain = Vec::new();
-
-    if flags.contains(FileSystemSupport::BTRFS) {
-        retain.extend_from_slice(&["btrfs-progs"]);
-    }
-
-    if flags.contains(FileSystemSupport::EXT4) {
-        retain.push("e2fsprogs");
-    }
-
-    if flags.contains(FileSystemSupport::F2FS) {
-        retain.push("f2fs-tools");
-    }
-
-    if flags.contains(FileSystemSupport::FAT) {
-        retain.push("dosfstools");
-    }
-
-    if flags.contains(FileSystemSupport::NTFS) {
-        retain.push("ntfs-3g");
-    }
-
-    if flags.contains(FileSystemSupport::XFS) {
-        retain.push("xfsprogs");
-    }
+pub fn get_required_packages(flags: FileSystemSupport, release: &OsRelease) -> Vec<&'static str> {
+    let mut retain = file_system_packages(flags);
 
     if flags.contains(FileSystemSupport::LUKS) {
-        retain.extend_from_slice(&["cryptsetup", "cryptsetup-bin"]);
+        retain.push("cryptsetup-bin");
         match (release.id.as_str(), release.version.as_str()) {
             ("ubuntu", "18.10") => {
                 retain.extend_from_slice(&["cryptsetup-initramfs", "cryptsetup-run"])
@@ -174,7 +219,7 @@ pub fn get_required_packages<D: InstallerDiskOps>(
     }
 
     if flags.intersects(FileSystemSupport::LVM | FileSystemSupport::LUKS) {
-        retain.extend_from_slice(&["lvm2", "dmeventd", "dmraid", "kpartx", "kpartx-boot"]);
+        retain.extend_from_slice(&["dmeventd", "dmraid", "kpartx", "kpartx-boot"]);
     }
 
     retain
@@ -0,0 +1,67 @@
+//! Fedora and its derivatives, whose packages are managed by dnf.
+//!
+//! Language packs and dependencies are not yet resolved, so the removal list is applied as is.
+//! The Debian packages which distinst installs are only installed if they are in `PACKAGES`.
+
+use super::{rename, PackageBackend};
+use crate::chroot::Chroot;
+use std::io;
+
+/// The Debian packages which distinst installs, and their names in Fedora. The tang pin is a
+/// part of clevis itself, and dracut takes the place of initramfs-tools.
+const PACKAGES: &[(&str, &str)] = &[
+    ("avahi-daemon", "avahi"),
+    ("btrfs-progs", "btrfs-progs"),
+    ("clevis", "clevis"),
+    ("clevis-initramfs", "clevis-dracut"),
+    ("clevis-luks", "clevis-luks"),
+    ("cryptsetup", "cryptsetup"),
+    ("dosfstools", "dosfstools"),
+    ("e2fsprogs", "e2fsprogs"),
+    ("efibootmgr", "efibootmgr"),
+    ("exfatprogs", "exfatprogs"),
+    ("f2fs-tools", "f2fs-tools"),
+    ("flatpak", "flatpak"),
+    ("grub-efi-amd64", "grub2-efi-x64"),
+    ("grub-efi-arm64", "grub2-efi-aa64"),
+    ("grub-pc", "grub2-pc"),
+    ("kpartx", "kpartx"),
+    ("libnss-mdns", "nss-mdns"),
+    ("lvm2", "lvm2"),
+    ("mdadm", "mdadm"),
+    ("ntfs-3g", "ntfs-3g"),
+    ("nvme-cli", "nvme-cli"),
+    ("open-iscsi", "iscsi-initiator-utils"),
+    ("openssh-server", "openssh-server"),
+    ("shim-signed", "shim-x64"),
+    ("systemd-homed", "systemd-homed"),
+    ("xfsprogs", "xfsprogs"),
+];
+
+pub struct Dnf;
+
+impl PackageBackend for Dnf {
+    fn name(&self) -> &'static str { "dnf" }
+
+    fn install(&self, chroot: &Chroot, packages: &[&str]) -> io::Result<()> {
+        info!("installing packages: {:?}", packages);
+        let mut args = vec!["install", "-y"];
+        args.extend_from_slice(packages);
+        chroot.command("dnf", &args).run()
+    }
+
+    fn remove(&self, chroot: &Chroot, packages: &[&str]) -> io::Result<()> {
+        info!("removing packages: {:?}", packages);
+        let mut args = vec!["remove", "-y"];
+        args.extend_from_slice(packages);
+        chroot.command("dnf", &args).run()
+    }
+
+    fn dependencies(&self, _packages: &[&str]) -> Option<Vec<String>> { None }
+
+    fn language_support(&self, _lang: &str, _chroot: &Chroot) -> io::Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn package_name<'a>(&self, debian: &'a str) -> Option<&'a str> { rename(PACKAGES, debian) }
+}
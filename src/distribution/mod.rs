@@ -1 +1,167 @@
+//! The package managers of the distributions that may be installed, which the configure step
+//! uses to install and remove packages within the target.
+
+pub mod arch;
 pub mod debian;
+pub mod fedora;
+
+use crate::{
+    bootloader::Bootloader, chroot::Chroot, installer::bitflags::FileSystemSupport, uboot,
+};
+use os_release::OsRelease;
+use std::{env, io};
+
+pub use self::{arch::Pacman, debian::Apt, fedora::Dnf};
+
+/// The operations of a package manager which the configure step requires.
+pub trait PackageBackend: Sync {
+    /// The name of the package manager.
+    fn name(&self) -> &'static str;
+
+    /// Installs the packages within the chroot, if they are not already installed.
+    fn install(&self, chroot: &Chroot, packages: &[&str]) -> io::Result<()>;
+
+    /// Removes the packages from the chroot, along with dependencies which are no longer needed.
+    fn remove(&self, chroot: &Chroot, packages: &[&str]) -> io::Result<()>;
+
+    /// The packages, and their immediate dependencies, or `None` if that is not known.
+    fn dependencies(&self, packages: &[&str]) -> Option<Vec<String>>;
+
+    /// The language packs of the locale, separated by whitespace, if the distribution has a
+    /// means of finding them.
+    fn language_support(&self, lang: &str, chroot: &Chroot) -> io::Result<Option<String>>;
+
+    /// The name of the package which provides what the Debian package `debian` does, or `None`
+    /// if the distribution has no such package, or it is not known.
+    fn package_name<'a>(&self, debian: &'a str) -> Option<&'a str>;
+
+    /// The packages of the boot loader of the `release`, named as in Debian.
+    fn bootloader_packages(&self, _release: &OsRelease) -> io::Result<&'static [&'static str]> {
+        Ok(grub_packages())
+    }
+
+    /// The packages which the file systems of the disk configuration require, named as in
+    /// Debian, which must not be removed with the live system's packages.
+    fn required_packages(
+        &self,
+        flags: FileSystemSupport,
+        _release: &OsRelease,
+    ) -> Vec<&'static str> {
+        file_system_packages(flags)
+    }
+}
+
+/// The names of the Debian `packages` for the `backend`, without the packages which it lacks.
+pub fn package_names<'a>(backend: &dyn PackageBackend, packages: &[&'a str]) -> Vec<&'a str> {
+    let mut names = Vec::with_capacity(packages.len());
+    for &package in packages {
+        match backend.package_name(package) {
+            Some(name) if !names.contains(&name) => names.push(name),
+            Some(_) => (),
+            None => warn!("{} has no equivalent of the {} package", backend.name(), package),
+        }
+    }
+
+    names
+}
+
+/// The packages of GRUB, or of U-Boot, for the boot loader of this system, named as in Debian.
+fn grub_packages() -> &'static [&'static str] {
+    match (Bootloader::detect(), env::consts::ARCH) {
+        (Bootloader::Bios, _) => &["grub-pc"],
+        (Bootloader::UBoot, _) => uboot::Board::detect().packages,
+        (Bootloader::Efi, "aarch64") => &["efibootmgr", "grub-efi-arm64"],
+        (Bootloader::Efi, _) => &["efibootmgr", "grub-efi-amd64", "shim-signed"],
+    }
+}
+
+/// The tools of the file systems which are in the disk configuration, named as in Debian.
+pub(crate) fn file_system_packages(flags: FileSystemSupport) -> Vec<&'static str> {
+    const TOOLS: &[(FileSystemSupport, &str)] = &[
+        (FileSystemSupport::BTRFS, "btrfs-progs"),
+        (FileSystemSupport::EXFAT, "exfatprogs"),
+        (FileSystemSupport::EXT4, "e2fsprogs"),
+        (FileSystemSupport::F2FS, "f2fs-tools"),
+        (FileSystemSupport::FAT, "dosfstools"),
+        (FileSystemSupport::NTFS, "ntfs-3g"),
+        (FileSystemSupport::XFS, "xfsprogs"),
+        (FileSystemSupport::LUKS, "cryptsetup"),
+    ];
+
+    let mut packages = TOOLS
+        .iter()
+        .filter(|&&(flag, _)| flags.contains(flag))
+        .map(|&(_, package)| package)
+        .collect::<Vec<_>>();
+
+    if flags.intersects(FileSystemSupport::LVM | FileSystemSupport::LUKS) {
+        packages.push("lvm2");
+    }
+
+    packages
+}
+
+/// Finds the name of the Debian package in a `table` of Debian names and their equivalents.
+fn rename<'a>(table: &[(&str, &'static str)], debian: &str) -> Option<&'a str> {
+    table.iter().find(|&&(name, _)| name == debian).map(|&(_, name)| name)
+}
+
+/// The package manager of the distribution with the `release`, which is apt if it is not known.
+pub fn backend(release: &OsRelease) -> &'static dyn PackageBackend {
+    select(&release.id, &release.id_like)
+}
+
+fn select(id: &str, id_like: &str) -> &'static dyn PackageBackend {
+    for id in Some(id).into_iter().chain(id_like.split_whitespace()) {
+        match id {
+            "debian" | "ubuntu" | "pop" => return &Apt,
+            "fedora" | "rhel" | "centos" => return &Dnf,
+            "arch" => return &Pacman,
+            _ => (),
+        }
+    }
+
+    &Apt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backends() {
+        assert_eq!(select("pop", "ubuntu debian").name(), "apt");
+        assert_eq!(select("elementary", "ubuntu").name(), "apt");
+        assert_eq!(select("ultramarine", "fedora").name(), "dnf");
+        assert_eq!(select("endeavouros", "arch").name(), "pacman");
+        assert_eq!(select("unknown", "").name(), "apt");
+    }
+
+    #[test]
+    fn names() {
+        let packages = ["lvm2", "open-iscsi", "clevis-luks", "clevis-tang", "kernelstub"];
+        assert_eq!(package_names(&Apt, &packages), packages);
+        assert_eq!(
+            package_names(&Dnf, &packages),
+            ["lvm2", "iscsi-initiator-utils", "clevis-luks"]
+        );
+        assert_eq!(package_names(&Pacman, &packages), ["lvm2", "open-iscsi", "clevis"]);
+
+        let grub = ["efibootmgr", "grub-efi-amd64", "shim-signed", "grub-pc"];
+        let names = ["efibootmgr", "grub2-efi-x64", "shim-x64", "grub2-pc"];
+        assert_eq!(package_names(&Dnf, &grub), names);
+        assert_eq!(package_names(&Pacman, &grub), ["efibootmgr", "grub"]);
+    }
+
+    #[test]
+    fn file_systems() {
+        let flags = FileSystemSupport::EXT4 | FileSystemSupport::FAT | FileSystemSupport::LUKS;
+        assert_eq!(file_system_packages(flags), [
+            "e2fsprogs",
+            "dosfstools",
+            "cryptsetup",
+            "lvm2",
+        ]);
+        assert!(file_system_packages(FileSystemSupport::empty()).is_empty());
+    }
+}
@@ -0,0 +1,422 @@
+//! Query and reclaim the space used by boot files on an EFI system partition.
+//!
+//! Systems which have been refreshed many times, and which were given small ESPs, may not have
+//! enough room left for the new install's kernel and initrd. The kernels and initrds that were
+//! copied for root and recovery partitions which no longer exist are superseded, and can be
+//! removed along with the loader entries that refer to them.
+
+use std::{
+    ffi::CString,
+    fs, io, mem,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Files which kernelstub keeps for the previous kernel, and the loader entry that boots them.
+const PREVIOUS_FILES: &[&str] = &["vmlinuz-previous.efi", "initrd.img-previous"];
+const PREVIOUS_ENTRY: &str = "Pop_OS-oldkern.conf";
+/// Room which is left for the boot loader and its entries, in bytes.
+const LOADER_SPACE: u64 = 4 * 1024 * 1024;
+
+/// What a directory within the ESP's `EFI` directory belongs to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EspOwner {
+    /// A kernel and initrd that kernelstub copied for the root partition with this UUID.
+    Kernelstub { root_uuid: String },
+    /// The kernel and initrd of the recovery partition with this UUID.
+    Recovery { uuid: String },
+    /// A boot manager, such as systemd-boot or the fallback loader.
+    BootManager,
+    /// Files that were not placed by a loader that is known to the installer.
+    Unknown,
+}
+
+impl EspOwner {
+    pub(crate) fn from_name(name: &str) -> EspOwner {
+        match name.to_lowercase().as_str() {
+            "boot" | "systemd" => return EspOwner::BootManager,
+            _ => (),
+        }
+
+        // Loader entries refer to the directories in any case, so the prefix is not case-sensitive.
+        let mut fields = name.splitn(2, '-');
+        match (fields.next().map(str::to_lowercase).as_deref(), fields.next()) {
+            (Some("pop_os"), Some(uuid)) if is_uuid(uuid) => {
+                EspOwner::Kernelstub { root_uuid: uuid.into() }
+            }
+            // The recovery partition is FAT, whose UUID is its volume serial number.
+            (Some("recovery"), Some(uuid)) if is_uuid(uuid) || is_fat_serial(uuid) => {
+                EspOwner::Recovery { uuid: uuid.into() }
+            }
+            _ => EspOwner::Unknown,
+        }
+    }
+
+    pub(crate) fn uuid(&self) -> Option<&str> {
+        match *self {
+            EspOwner::Kernelstub { ref root_uuid } => Some(root_uuid),
+            EspOwner::Recovery { ref uuid } => Some(uuid),
+            _ => None,
+        }
+    }
+}
+
+/// A directory within the ESP's `EFI` directory, and the loader entries which boot from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EspDirectory {
+    pub path:    PathBuf,
+    pub owner:   EspOwner,
+    pub size:    u64,
+    pub entries: Vec<PathBuf>,
+}
+
+/// The contents of an ESP, grouped by their owners.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EspUsage {
+    pub path:        PathBuf,
+    pub free:        u64,
+    pub directories: Vec<EspDirectory>,
+}
+
+impl EspUsage {
+    /// Reads the contents of the ESP that is mounted at `esp`.
+    pub fn new<P: AsRef<Path>>(esp: P) -> io::Result<Self> {
+        let esp = esp.as_ref();
+        let entries = loader_entries(esp)?;
+
+        let mut directories = Vec::new();
+        for entry in fs::read_dir(esp.join("EFI"))? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let lowercase = name.to_lowercase();
+            directories.push(EspDirectory {
+                path:    entry.path(),
+                owner:   EspOwner::from_name(&name),
+                size:    dir_size(&entry.path())?,
+                entries: entries
+                    .iter()
+                    .filter(|(_, dirs)| dirs.contains(&lowercase))
+                    .map(|(path, _)| path.clone())
+                    .collect(),
+            });
+        }
+
+        Ok(EspUsage { path: esp.to_path_buf(), free: free_space(esp)?, directories })
+    }
+
+    /// Directories which belong to root or recovery partitions that no longer exist.
+    ///
+    /// The directory of the root partition that is being installed is never superseded. Nothing
+    /// is superseded when it cannot be known which file systems exist, as a LUKS or LVM
+    /// container which has not been opened may hold the partition of any of them.
+    pub fn superseded<'a>(&'a self, root_uuid: &'a str) -> impl Iterator<Item = &'a EspDirectory> {
+        let visible = visible_uuids();
+        self.directories.iter().filter(move |dir| match (dir.owner.uuid(), visible.as_ref()) {
+            (Some(uuid), Some(visible)) => {
+                !uuid.eq_ignore_ascii_case(root_uuid)
+                    && !visible.iter().any(|other| other.eq_ignore_ascii_case(uuid))
+                    && !partition_exists(uuid)
+            }
+            _ => false,
+        })
+    }
+
+    /// Removes superseded boot files until at least `required` bytes are free, returning the
+    /// number of bytes that were freed.
+    ///
+    /// Directories of partitions that no longer exist are removed first, along with their loader
+    /// entries. If that is not enough, the previous kernel of the root partition being installed
+    /// is removed, as kernelstub will copy it again when the next kernel is installed.
+    pub fn prune(&mut self, root_uuid: &str, required: u64) -> io::Result<u64> {
+        let initial = self.free;
+        if self.free >= required {
+            return Ok(0);
+        }
+
+        let superseded = self.superseded(root_uuid).cloned().collect::<Vec<_>>();
+        for dir in superseded {
+            if self.free >= required {
+                break;
+            }
+
+            info!("removing superseded boot files in {}", dir.path.display());
+            fs::remove_dir_all(&dir.path)?;
+            for entry in &dir.entries {
+                remove_if_exists(entry)?;
+            }
+
+            self.directories.retain(|other| other.path != dir.path);
+            self.free = free_space(&self.path)?;
+        }
+
+        self.prune_previous(root_uuid, required)?;
+        Ok(self.free.saturating_sub(initial))
+    }
+
+    /// Removes the previous kernel of the root partition being installed, and its loader entry,
+    /// if fewer than `required` bytes are free, returning the number of bytes that were freed.
+    /// The boot files of other systems are left alone.
+    pub fn prune_previous(&mut self, root_uuid: &str, required: u64) -> io::Result<u64> {
+        let initial = self.free;
+        if self.free >= required {
+            return Ok(0);
+        }
+
+        let current = self
+            .directories
+            .iter()
+            .find(|dir| dir.owner == EspOwner::Kernelstub { root_uuid: root_uuid.into() });
+
+        if let Some(dir) = current {
+            info!("removing the previous kernel in {}", dir.path.display());
+            for file in PREVIOUS_FILES {
+                remove_if_exists(&dir.path.join(file))?;
+            }
+
+            remove_if_exists(&self.path.join("loader/entries").join(PREVIOUS_ENTRY))?;
+            self.free = free_space(&self.path)?;
+        }
+
+        Ok(self.free.saturating_sub(initial))
+    }
+}
+
+/// Parses each loader entry, and collects the lowercased names of the `EFI` directories that
+/// it boots files from.
+fn loader_entries(esp: &Path) -> io::Result<Vec<(PathBuf, Vec<String>)>> {
+    let entries = match fs::read_dir(esp.join("loader/entries")) {
+        Ok(entries) => entries,
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(why) => return Err(why),
+    };
+
+    let mut output = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().map_or(false, |ext| ext == "conf") {
+            let dirs = entry_directories(&fs::read_to_string(&path)?);
+            output.push((path, dirs));
+        }
+    }
+
+    Ok(output)
+}
+
+pub(crate) fn entry_directories(entry: &str) -> Vec<String> {
+    let mut dirs = Vec::new();
+    for line in entry.lines() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("linux") | Some("initrd") | Some("efi") => (),
+            _ => continue,
+        }
+
+        let mut components = fields.next().unwrap_or("").split('/').filter(|c| !c.is_empty());
+        if let (Some(efi), Some(dir)) = (components.next(), components.next()) {
+            let dir = dir.to_lowercase();
+            if efi.eq_ignore_ascii_case("EFI") && !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+
+    dirs
+}
+
+/// A UUID in its canonical form, such as `0d1b2a3c-4e5f-6789-abcd-ef0123456789`.
+fn is_uuid(uuid: &str) -> bool { is_hex_groups(uuid, &[8, 4, 4, 4, 12]) }
+
+/// The serial number of a FAT volume, such as `ABCD-1234`.
+fn is_fat_serial(uuid: &str) -> bool { is_hex_groups(uuid, &[4, 4]) }
+
+fn is_hex_groups(value: &str, lengths: &[usize]) -> bool {
+    let groups = value.split('-').collect::<Vec<_>>();
+    groups.len() == lengths.len()
+        && groups.iter().zip(lengths).all(|(group, &length)| {
+            group.len() == length && group.chars().all(|c| c.is_ascii_hexdigit())
+        })
+}
+
+/// The kinds of devices which hold file systems that are only seen once they are opened.
+const CONTAINERS: &[&str] = &[
+    "crypto_LUKS",
+    "LVM2_member",
+    "linux_raid_member",
+    "isw_raid_member",
+    "ddf_raid_member",
+    "zfs_member",
+    "bcache",
+];
+
+/// The UUIDs of the file systems on the system, or `None` if they cannot all be seen.
+fn visible_uuids() -> Option<Vec<String>> {
+    let output = Command::new("blkid").args(&["-c", "/dev/null", "-o", "export"]).output();
+    match output {
+        Ok(ref output) if output.status.success() => {
+            visible_in(&String::from_utf8_lossy(&output.stdout), |device| {
+                let device = fs::canonicalize(device).unwrap_or_else(|_| device.to_path_buf());
+                let name = device.file_name().map_or(Default::default(), |name| name.to_owned());
+                let holders = Path::new("/sys/class/block").join(name).join("holders");
+                fs::read_dir(holders).map_or(false, |mut holders| holders.next().is_some())
+            })
+        }
+        Ok(_) => {
+            warn!("blkid did not list the file systems, so no boot files will be pruned");
+            None
+        }
+        Err(why) => {
+            warn!("failed to run blkid, so no boot files will be pruned: {}", why);
+            None
+        }
+    }
+}
+
+/// Collects the UUIDs from the `blkid -o export` output, including those of the containers.
+/// The file systems within a LUKS, LVM, RAID, or ZFS container are unknown until it is
+/// `opened`, so `None` is returned if any is closed, as nothing may then be known to be gone.
+fn visible_in<F: Fn(&Path) -> bool>(output: &str, opened: F) -> Option<Vec<String>> {
+    let mut uuids = Vec::new();
+    for device in output.split("\n\n") {
+        let mut name = None;
+        let mut kind = None;
+        for line in device.lines() {
+            match line.find('=').map(|pos| line.split_at(pos)) {
+                Some(("DEVNAME", value)) => name = Some(&value[1..]),
+                Some(("TYPE", value)) => kind = Some(&value[1..]),
+                Some(("UUID", value)) => uuids.push(value[1..].to_owned()),
+                _ => (),
+            }
+        }
+
+        let container = kind.map_or(false, |kind| CONTAINERS.contains(&kind));
+        if let Some(name) = name.filter(|_| container) {
+            if !opened(Path::new(name)) {
+                info!("{} has not been opened, so no boot files will be pruned", name);
+                return None;
+            }
+        }
+    }
+
+    Some(uuids)
+}
+
+/// Whether a file system, or partition, with the UUID is known to udev.
+fn partition_exists(uuid: &str) -> bool {
+    ["/dev/disk/by-uuid", "/dev/disk/by-partuuid"].iter().any(|dir| {
+        Path::new(dir).join(uuid).exists() || Path::new(dir).join(uuid.to_lowercase()).exists()
+    })
+}
+
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() { dir_size(&entry.path())? } else { metadata.len() };
+    }
+
+    Ok(size)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// The bytes that the new install will write to the ESP: the kernel and initrd of the live
+/// image, copied by kernelstub and to the recovery partition, along with the boot loader.
+pub(crate) fn boot_files_size() -> u64 {
+    ["vmlinuz.efi", "initrd.gz"]
+        .iter()
+        .filter_map(|file| fs::metadata(Path::new("/cdrom/casper").join(file)).ok())
+        .map(|metadata| metadata.len() * 2)
+        .sum::<u64>()
+        + LOADER_SPACE
+}
+
+/// The number of bytes that are available on the file system at `path`.
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))?;
+
+    unsafe {
+        let mut stat: libc::statvfs = mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owners() {
+        let uuid = "0d1b2a3c-4e5f-6789-abcd-ef0123456789";
+        assert_eq!(
+            EspOwner::from_name(&["Pop_OS-", uuid].concat()),
+            EspOwner::Kernelstub { root_uuid: uuid.into() }
+        );
+        assert_eq!(EspOwner::from_name("Recovery-ABCD-1234"), EspOwner::Recovery {
+            uuid: "ABCD-1234".into()
+        });
+        assert_eq!(EspOwner::from_name("systemd"), EspOwner::BootManager);
+        assert_eq!(EspOwner::from_name("BOOT"), EspOwner::BootManager);
+        assert_eq!(EspOwner::from_name("Microsoft"), EspOwner::Unknown);
+        assert_eq!(EspOwner::from_name("Pop_OS-"), EspOwner::Unknown);
+        assert_eq!(EspOwner::from_name(&["pop_os-", uuid].concat()), EspOwner::Kernelstub {
+            root_uuid: uuid.into()
+        });
+        assert_eq!(EspOwner::from_name(&["recovery-", uuid].concat()), EspOwner::Recovery {
+            uuid: uuid.into()
+        });
+        assert_eq!(EspOwner::from_name("Pop_OS-ABCD-1234"), EspOwner::Unknown);
+        assert_eq!(EspOwner::from_name("Pop_OS-backup"), EspOwner::Unknown);
+        assert_eq!(EspOwner::from_name("Pop_OS-0d1b2a3c"), EspOwner::Unknown);
+        assert_eq!(EspOwner::from_name(&["ubuntu-", uuid].concat()), EspOwner::Unknown);
+        assert_eq!(EspOwner::from_name("Recovery-ABCD-12345"), EspOwner::Unknown);
+    }
+
+    #[test]
+    fn visible() {
+        let output = "DEVNAME=/dev/sda1\nUUID=ABCD-1234\nTYPE=vfat\n\n\
+                      DEVNAME=/dev/sda2\nUUID=0d1b2a3c-4e5f-6789-abcd-ef0123456789\n\
+                      TYPE=crypto_LUKS\n\n\
+                      DEVNAME=/dev/mapper/data-root\nUUID=1111\nTYPE=ext4\n";
+        assert_eq!(
+            visible_in(output, |_| true),
+            Some(vec![
+                "ABCD-1234".to_owned(),
+                "0d1b2a3c-4e5f-6789-abcd-ef0123456789".to_owned(),
+                "1111".to_owned()
+            ])
+        );
+        assert_eq!(visible_in(output, |_| false), None);
+        assert_eq!(visible_in("DEVNAME=/dev/sdb1\nTYPE=LVM2_member\n", |_| false), None);
+        assert_eq!(visible_in("DEVNAME=/dev/sdb1\nTYPE=zfs_member\n", |_| false), None);
+        assert_eq!(visible_in("DEVNAME=/dev/sdb1\nTYPE=linux_raid_member\n", |_| false), None);
+        assert_eq!(visible_in("DEVNAME=/dev/sdb1\nUUID=2222\nTYPE=xfs\n", |_| false), Some(vec![
+            "2222".to_owned()
+        ]));
+    }
+
+    #[test]
+    fn entries() {
+        let entry = "title Pop!_OS\nlinux /EFI/Pop_OS-1234/vmlinuz.efi\n\
+                     initrd /EFI/Pop_OS-1234/initrd.img\noptions root=UUID=1234 ro\n";
+        assert_eq!(entry_directories(entry), vec!["pop_os-1234".to_owned()]);
+        assert_eq!(entry_directories("title Windows\nefi /efi/Microsoft/Boot/bootmgfw.efi\n"), vec![
+            "microsoft".to_owned()
+        ]);
+    }
+}
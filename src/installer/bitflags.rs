@@ -1,5 +1,41 @@
 bitflags! {
-    pub struct FileSystemSupport: u8 {
+    /// Flags which control the behavior of an installation.
+    pub struct InstallFlags: u32 {
+        /// Make the new install the default boot entry in the EFI boot order. Its boot entries
+        /// are created regardless.
+        const MODIFY_BOOT_ORDER = 1;
+        /// Install packages which support the hardware of the system.
+        const INSTALL_HARDWARE_SUPPORT = 1 << 1;
+        /// Retain the old root partition, rather than removing it. It is kept as a generation
+        /// in `/linux.generations`, which may be rolled back to.
+        const KEEP_OLD_ROOT = 1 << 2;
+        /// Use `ubuntu-drivers` to find and install drivers, which may be proprietary.
+        const RUN_UBUNTU_DRIVERS = 1 << 3;
+        /// Record every external command that is executed, with its output, in
+        /// `COMMAND_JOURNAL`.
+        const JOURNAL_COMMANDS = 1 << 4;
+        /// Give new volume groups a unique name if their name is already in use, rather than
+        /// failing the installation.
+        const RENAME_CONFLICTING_VOLUME_GROUPS = 1 << 5;
+        /// Resolve `.local` names with multicast DNS, through avahi.
+        const ENABLE_MDNS = 1 << 6;
+        /// Mount the root file system as read-only, with writable overlays of `/etc` and `/var`
+        /// that are discarded on each boot, for kiosks and appliances.
+        const READ_ONLY_ROOT = 1 << 7;
+        /// Leave the initramfs with the US keymap, rather than the keyboard layout of the
+        /// installed system, when encrypted volumes are unlocked while booting.
+        const SKIP_INITRAMFS_KEYMAP = 1 << 8;
+        /// Reinstall the packages which were manually installed on the system that a refresh
+        /// install replaces.
+        const RESTORE_PACKAGES = 1 << 9;
+        /// Move an existing first partition of a MSDOS disk on BIOS systems, if the gap before
+        /// it is too small to embed GRUB, rather than falling back to blocklists.
+        const MAKE_MBR_GAP = 1 << 10;
+    }
+}
+
+bitflags! {
+    pub struct FileSystemSupport: u16 {
         const LVM = 1;
         const LUKS = 2;
         const FAT = 4;
@@ -8,5 +44,6 @@ bitflags! {
         const BTRFS = 32;
         const NTFS = 64;
         const F2FS = 128;
+        const EXFAT = 256;
     }
 }
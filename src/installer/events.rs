@@ -0,0 +1,145 @@
+//! A buffer of the most recent status and error events emitted by the installer, so that a
+//! frontend which reconnects after crashing can replay the events that it missed.
+
+use super::{Error, Status};
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// How many of the most recent events are retained for replaying.
+pub const EVENT_BUFFER_LEN: usize = 256;
+
+/// A status or error that was emitted by the installer.
+#[derive(Debug)]
+pub struct Event {
+    /// Increases by one with each event, starting from `1`.
+    pub sequence: u64,
+    pub kind:     EventKind,
+}
+
+#[derive(Debug)]
+pub enum EventKind {
+    Status(Status),
+    Error(Error),
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct EventBuffer {
+    events:   VecDeque<Event>,
+    sequence: u64,
+}
+
+impl EventBuffer {
+    /// Records an event, discarding the oldest event if the buffer is full.
+    pub fn push(&mut self, kind: EventKind) -> &Event {
+        if self.events.len() == EVENT_BUFFER_LEN {
+            self.events.pop_front();
+        }
+
+        self.sequence += 1;
+        self.events.push_back(Event { sequence: self.sequence, kind });
+        self.events.back().expect("event was just pushed")
+    }
+
+    /// The retained events which came after the event with the `since` sequence number.
+    pub fn since(&self, since: u64) -> impl Iterator<Item = &Event> {
+        self.events.iter().filter(move |event| event.sequence > since)
+    }
+}
+
+/// A shared handle to the events retained by an installer, which may be replayed from any
+/// thread while the installation is running.
+#[derive(Clone, Debug, Default)]
+pub struct EventHandle(Arc<Mutex<EventBuffer>>);
+
+impl EventHandle {
+    /// Copies of the retained events which came after the event with the `since_seq` sequence
+    /// number, from the oldest to the newest. They are copied so that the events are not
+    /// locked while they are handled.
+    pub fn replay(&self, since_seq: u64) -> Vec<Event> {
+        self.lock().since(since_seq).map(copy_event).collect()
+    }
+
+    /// Records an event, and returns its sequence number.
+    pub(crate) fn push(&self, kind: EventKind) -> u64 { self.lock().push(kind).sequence }
+
+    fn lock(&self) -> MutexGuard<EventBuffer> {
+        // A poisoned lock still contains valid events.
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn copy_event(event: &Event) -> Event {
+    let kind = match event.kind {
+        EventKind::Status(status) => EventKind::Status(status),
+        EventKind::Error(ref error) => EventKind::Error(copy_error(error)),
+    };
+
+    Event { sequence: event.sequence, kind }
+}
+
+/// Copies an error so that it may be retained, as `io::Error` cannot be cloned.
+pub(crate) fn copy_error(error: &Error) -> Error {
+    let err = match error.err.raw_os_error() {
+        Some(code) => io::Error::from_raw_os_error(code),
+        None => io::Error::new(error.err.kind(), error.err.to_string()),
+    };
+
+    Error { step: error.step, err }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::installer::Step;
+
+    fn status(percent: i32) -> EventKind {
+        EventKind::Status(Status { step: Step::Extract, percent })
+    }
+
+    #[test]
+    fn replay_since() {
+        let mut buffer = EventBuffer::default();
+        for percent in 0..EVENT_BUFFER_LEN as i32 + 10 {
+            buffer.push(status(percent));
+        }
+
+        let sequences = |since| buffer.since(since).map(|event| event.sequence).collect::<Vec<_>>();
+
+        assert_eq!(buffer.since(0).count(), EVENT_BUFFER_LEN);
+        assert_eq!(sequences(0).first(), Some(&11));
+        assert_eq!(sequences(EVENT_BUFFER_LEN as u64 + 7), vec![264, 265, 266]);
+        assert!(sequences(EVENT_BUFFER_LEN as u64 + 10).is_empty());
+
+        buffer.push(EventKind::Error(Error {
+            step: Step::Configure,
+            err:  io::Error::from_raw_os_error(5),
+        }));
+
+        let event = buffer.since(266).next();
+        match event {
+            Some(Event { sequence: 267, kind: EventKind::Error(ref error) }) => {
+                assert_eq!(error.err.raw_os_error(), Some(5))
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn replay_copies() {
+        let handle = EventHandle::default();
+        assert_eq!(handle.push(status(10)), 1);
+        assert_eq!(handle.push(status(20)), 2);
+
+        let events = handle.replay(1);
+        assert_eq!(events.len(), 1);
+        match events[0] {
+            Event { sequence: 2, kind: EventKind::Status(Status { percent: 20, .. }) } => (),
+            ref event => panic!("unexpected event: {:?}", event),
+        }
+
+        assert!(handle.replay(2).is_empty());
+    }
+}
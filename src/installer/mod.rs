@@ -2,39 +2,59 @@ pub mod bitflags;
 pub mod traits;
 
 mod conf;
+mod events;
 mod state;
+mod summary;
 
 pub(crate) mod steps;
+pub(crate) mod workdir;
+
+pub use crate::fstab::TrimPolicy;
+pub use crate::misc::{journal::CommandRecord, metrics::Metric};
+pub use crate::squashfs::{Backend as ExtractBackend, ExtractOptions, IoPriority};
+
+pub use self::{
+    bitflags::InstallFlags,
+    conf::RecoveryEnv,
+    events::{Event, EventHandle, EventKind, EVENT_BUFFER_LEN},
+    state::{InstallProgress, InstallProgressHandle},
+    steps::{BiosInstall, BiosTarget, CleanupProfile, EspPolicy, Step, LOCALE_CATEGORIES},
+    summary::{InstallSummary, PackageChange, SystemSnapshot},
+};
 
-pub use self::{conf::RecoveryEnv, steps::Step};
-
-use self::state::InstallerState;
+use self::{
+    events::copy_error,
+    state::InstallerState,
+    steps::Restore,
+    traits::InstallerDiskOps,
+    workdir::WorkDir,
+};
 
 use crate::auto::{
-    delete_old_install, move_root, recover_root, remove_root, validate_backup_conditions,
-    AccountFiles, Backup, ReinstallError,
+    manual_packages, mount_and_then, move_root, recover_root, remove_root,
+    validate_backup_conditions, AccountFiles, Backup, FileBackup, ReinstallError, GENERATIONS,
 };
 use disk_types::BlockDeviceExt;
-use crate::disks::{Bootloader, Disks};
+use crate::disks::{validation_result, Bootloader, Disks, SessionMount};
 use crate::errors::IoContext;
-use crate::external::luks::deactivate_logical_devices;
 use crate::hostname;
+use crate::misc::{journal, metrics};
 use os_release::OsRelease;
 use partition_identity::PartitionID;
-use crate::squashfs;
+use crate::squashfs::{self, tarball::Compression};
 use std::{
     io,
+    net::IpAddr,
     path::{Path, PathBuf},
-    sync::atomic::Ordering,
+    sync::{atomic::Ordering, Arc, Mutex},
 };
+use sys_mount::Mounts;
 use tempdir::TempDir;
 use crate::timezones::Region;
 use crate::PARTITIONING_TEST;
 
-pub const MODIFY_BOOT_ORDER: u8 = 0b01;
-pub const INSTALL_HARDWARE_SUPPORT: u8 = 0b10;
-pub const KEEP_OLD_ROOT: u8 = 0b100;
-pub const RUN_UBUNTU_DRIVERS: u8 = 0b1000;
+/// Where external commands are journaled when `InstallFlags::JOURNAL_COMMANDS` is set.
+pub const COMMAND_JOURNAL: &str = "/var/log/distinst/commands.log";
 
 macro_rules! percent {
     ($steps:expr) => {
@@ -49,23 +69,214 @@ macro_rules! percent {
 /// Installer configuration
 pub struct Config {
     /// Hostname to assign to the installed system.
-    pub hostname:         String,
+    pub hostname:             String,
     /// The keyboard layout to use with the installed system (such as "us").
-    pub keyboard_layout:  String,
+    pub keyboard_layout:      String,
     /// An optional keyboard model (such as "pc105") to define the keyboard's model.
-    pub keyboard_model:   Option<String>,
+    pub keyboard_model:       Option<String>,
     /// An optional variant of the keyboard (such as "dvorak").
-    pub keyboard_variant: Option<String>,
+    pub keyboard_variant:     Option<String>,
     /// The UUID of the old root partition, for retaining user accounts.
-    pub old_root:         Option<String>,
+    pub old_root:             Option<String>,
     /// The locale to use for the installed system.
-    pub lang:             String,
+    pub lang:                 String,
+    /// Locales of other languages to generate in the installed system, which messages fall
+    /// back to when they have not been translated to the language of `lang`.
+    pub additional_locales:   Vec<String>,
+    /// Categories, such as `LC_TIME` and `LC_MEASUREMENT`, which take their formats from a
+    /// locale other than `lang`.
+    pub locale_overrides:     Vec<(String, String)>,
     /// The file that contains a list of packages to remove.
-    pub remove:           String,
-    /// The archive (`tar` or `squashfs`) which contains the base system.
-    pub squashfs:         String,
+    pub remove:               String,
+    /// For a minimal installation, the file that contains a list of additional packages to
+    /// remove, such as `/cdrom/casper/filesystem.manifest-minimal-remove`.
+    pub minimal_remove:       Option<String>,
+    /// The archive (`squashfs`, or a `tar` which may be compressed with gzip, xz, or zstd) which
+    /// contains the base system.
+    ///
+    /// This may also be an HTTP(S) URL, which is downloaded onto the new root before extraction,
+    /// and removed afterwards. The URL must end with a `#sha256=<digest>` checksum, which the
+    /// download is verified against.
+    pub squashfs:             String,
+    /// On BIOS systems, the device that GRUB will be installed to. If not set, the disk which
+    /// contains `/boot`, or `/`, will be used.
+    pub bios_device:          Option<String>,
     /// Some flags to control the behavior of the installation.
-    pub flags:            u8,
+    pub flags:                InstallFlags,
+    /// Files that will be written into the installed system at the end of the configure step.
+    pub files:                Vec<TargetFile>,
+    /// Default dconf settings for every user, as pairs of absolute keys, such as
+    /// `/org/gnome/desktop/background/picture-uri`, and values in the GVariant text format.
+    pub dconf:                Vec<(String, String)>,
+    /// Entries which are added to `/etc/hosts`, in addition to `localhost`.
+    pub hosts:                Vec<HostEntry>,
+    /// Whether the hardware clock keeps local time, as Windows expects, rather than UTC. The
+    /// clock would otherwise be skewed by the timezone offset after booting into Windows.
+    ///
+    /// If `None`, the hardware clock keeps local time when Windows is found on the system.
+    pub rtc_in_localtime:     Option<bool>,
+    /// What is removed from the installed system once it has been configured, such as the
+    /// packages which apt downloaded.
+    pub cleanup:              CleanupProfile,
+    /// Scripts on the live system which are executed within the installed system, in order,
+    /// once it has been configured. The install is described to them by the `DISTINST_ROOT_UUID`,
+    /// `DISTINST_ESP`, `DISTINST_USERNAME`, and `DISTINST_HOSTNAME` environment variables. The
+    /// installation fails if a script exits unsuccessfully.
+    pub post_install_scripts: Vec<PathBuf>,
+    /// Services which run once, on the first boot of the installed system, until they succeed.
+    pub first_boot_services:  Vec<FirstBootService>,
+    /// A local repository which packages, such as those for hardware support and languages,
+    /// are installed from on machines without a network.
+    pub offline_repository:   Option<OfflineRepository>,
+    /// Remotes which are added to the installed system, for the `flatpaks` to be installed from,
+    /// and for users to install flatpaks from. They are added even if no flatpaks are installed.
+    pub flatpak_remotes:      Vec<FlatpakRemote>,
+    /// Flatpaks which are installed system-wide, by their application ID, such as
+    /// `org.gnome.Calculator`, or by their ref.
+    pub flatpaks:             Vec<String>,
+    /// The variant of the kernel in the casper directory, such as `hwe` or `lowlatency`, which
+    /// is copied into the installed system, along with its meta-package. If not set, the
+    /// `generic` kernel is used.
+    pub kernel_variant:       Option<String>,
+    /// Verifies the image before the disks are modified, so that corrupted installation media
+    /// is found while the existing system is still intact. A remote image is verified once it
+    /// has been downloaded onto the new root.
+    pub image_verification:   Option<ImageVerification>,
+    /// Compares the extracted files to the digests of the files of the image, so that files
+    /// which were corrupted while they were extracted are found before the system is booted.
+    pub extract_verification: Option<ExtractVerification>,
+    /// The threads, I/O priority, and extractor with which the image is extracted. By default,
+    /// these are tuned to the CPUs and memory of the machine.
+    pub extract_options:      ExtractOptions,
+    /// Files which are copied to a partition that the install leaves untouched, before the
+    /// disks are modified, so that they are not lost with the system which is erased.
+    pub file_backup:          Option<FileBackup>,
+    /// Whether an existing ESP may be formatted, or must be reused, and if boot files which
+    /// are superseded may be removed from it to make room for those of the new install.
+    pub esp_policy:           EspPolicy,
+    /// How file systems on disks which support discard, such as SSDs, are trimmed.
+    pub trim_policy:          TrimPolicy,
+    /// A Tang server which the encrypted volumes are bound to with clevis, so that the
+    /// initramfs unlocks them automatically while the server can be reached.
+    pub tang_binding:         Option<TangBinding>,
+}
+
+/// An entry of `/etc/hosts`, which maps one or more names to an address, such as a file server
+/// on the local network.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostEntry {
+    pub address: IpAddr,
+    pub names:   Vec<String>,
+}
+
+/// A file that will be written into the installed system, such as a default wallpaper setting,
+/// an EULA acceptance marker, or a certificate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetFile {
+    /// An absolute path within the installed system.
+    pub path:   PathBuf,
+    /// The permissions of the file, such as `0o644`.
+    pub mode:   u32,
+    /// An owner in the `user[:group]` format. Names are resolved within the installed system,
+    /// so the account which the installer creates may own the file.
+    pub owner:  Option<String>,
+    pub source: TargetFileSource,
+}
+
+/// Where the contents of a `TargetFile` are obtained from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TargetFileSource {
+    Contents(Vec<u8>),
+    /// A file on the live system, which will be copied.
+    Path(PathBuf),
+}
+
+/// A remote which flatpaks are installed from, such as Flathub.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlatpakRemote {
+    pub name: String,
+    /// The URL of the `.flatpakrepo` file which describes the remote.
+    pub url:  String,
+}
+
+/// A flat apt repository on the live system, which has a `Packages` index at its top, and which
+/// is a source of packages only while installing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfflineRepository {
+    pub path:    PathBuf,
+    /// The keyring which verifies the `Release` file of the repository, which may be binary or
+    /// ASCII-armored. If not set, the repository is trusted.
+    pub keyring: Option<PathBuf>,
+}
+
+/// A Tang server, for network-bound disk encryption.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TangBinding {
+    /// The URL of the server, such as `http://tang.example.com`.
+    pub url:        String,
+    /// The thumbprint of the key which signs the advertisement of the server, as given by
+    /// `tang-show-keys`. It is required, as whatever answers at the URL would otherwise be
+    /// trusted with the keys of the volumes.
+    pub thumbprint: String,
+}
+
+impl TangBinding {
+    /// Ensures that the server is reached over HTTP, and that the thumbprint is base64url.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return invalid(format!("{} is not the URL of a Tang server", self.url));
+        }
+
+        let base64url = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if self.thumbprint.is_empty() {
+            return invalid(format!("the Tang server at {} requires a key thumbprint", self.url));
+        } else if !self.thumbprint.chars().all(base64url) {
+            return invalid(format!("{} is not a valid Tang key thumbprint", self.thumbprint));
+        }
+
+        Ok(())
+    }
+}
+
+/// How the image is verified before it is extracted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImageVerification {
+    /// A `SHA256SUMS` file, which lists the digest of the image by its file name.
+    Sha256Sums(PathBuf),
+    /// A detached GPG signature of the image, which is verified with the keys of the keyring.
+    Signature { signature: PathBuf, keyring: PathBuf },
+}
+
+/// What the extracted files are compared against.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExtractVerification {
+    /// The files of the squashfs image itself, which is mounted to generate their digests.
+    Image,
+    /// A manifest of the digests of the files, as written by `sha256sum`, with paths that are
+    /// relative to the root of the image.
+    Manifest(PathBuf),
+}
+
+/// How the image is written to the root of the new install.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExtractMode {
+    /// The image is extracted over the root, which is either new or has been emptied.
+    Overwrite,
+    /// The root is synchronized with the image, leaving unchanged files as they are, and
+    /// removing files which are not in the image. The paths, which are relative to the root,
+    /// are never touched.
+    PreservePaths(Vec<PathBuf>),
+}
+
+/// A systemd service which runs once, on the first boot of the installed system, for tasks such
+/// as enrollment or driver builds. It is enabled by the installer, and run as a oneshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirstBootService {
+    /// The name of the unit, such as `oem-enroll.service`.
+    pub name:   String,
+    /// The text of the unit.
+    pub source: TargetFileSource,
 }
 
 /// Credentials for creating a new user account.
@@ -75,6 +286,73 @@ pub struct UserAccountCreate {
     pub realname: Option<String>,
     pub password: Option<String>,
     pub profile_icon: Option<String>,
+    /// Public keys which may log in as the user over SSH, in the `authorized_keys` format.
+    /// When any are given, the SSH server is installed.
+    pub ssh_authorized_keys: Vec<String>,
+    /// The login shell of the user, instead of `/bin/bash`, or `/usr/sbin/nologin` for system
+    /// accounts.
+    pub shell: Option<String>,
+    /// Supplementary groups which the user is a member of, which are created if they are not
+    /// defined. Administrators are members of the `admin_groups()`, which system accounts may
+    /// not be.
+    pub groups: Vec<String>,
+    /// Creates a system account without a home directory, for services rather than people.
+    pub system: bool,
+    /// Grants the user `sudo` with a drop-in in `/etc/sudoers.d`, independent of their groups.
+    pub sudo: Option<SudoAccess>,
+    /// What creates the account and manages its home directory.
+    pub backend: UserBackend,
+}
+
+/// The access to `sudo` which is granted to a user.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SudoAccess {
+    /// Any command may be run as any user, once the user has entered their password.
+    Password,
+    /// Any command may be run as any user without a password, for kiosks and automation.
+    NoPassword,
+}
+
+/// What creates a user account, and manages its home directory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UserBackend {
+    /// An account in `/etc/passwd`, with a plain directory in `/home`.
+    Passwd,
+    /// An account of systemd-homed, whose home is a LUKS-encrypted image that is unlocked with
+    /// the user's password, and which may be carried to another system. The account is created
+    /// on the first boot, as systemd-homed does not run while installing.
+    Homed,
+}
+
+impl Default for UserBackend {
+    fn default() -> Self { UserBackend::Passwd }
+}
+
+impl UserAccountCreate {
+    /// The groups which allow a user to administer the system and its printers.
+    pub fn admin_groups() -> Vec<String> {
+        ["adm", "sudo", "lpadmin"].iter().map(|&group| group.to_owned()).collect()
+    }
+
+    /// The groups of a user whose groups were not given: none for a system account, and the
+    /// `admin_groups()` for a person.
+    pub fn default_groups(system: bool) -> Vec<String> {
+        if system {
+            Vec::new()
+        } else {
+            UserAccountCreate::admin_groups()
+        }
+    }
+}
+
+/// A line of output from a post-install script.
+#[derive(Debug)]
+pub struct HookOutput<'a> {
+    /// The script on the live system.
+    pub script: &'a Path,
+    pub line:   &'a str,
+    /// Whether the line was written to stderr, rather than stdout.
+    pub stderr: bool,
 }
 
 /// Installer error
@@ -95,8 +373,20 @@ pub struct Status {
 pub struct Installer {
     error_cb:         Option<Box<dyn FnMut(&Error)>>,
     status_cb:        Option<Box<dyn FnMut(&Status)>>,
+    event_cb:         Option<Box<dyn FnMut(&Event)>>,
+    events:           EventHandle,
     timezone_cb:      Option<Box<dyn FnMut() -> Region>>,
-    user_creation_cb: Option<Box<dyn FnMut() -> UserAccountCreate>>,
+    user_creation_cb: Option<Box<dyn FnMut() -> Vec<UserAccountCreate>>>,
+    mok_password_cb:  Option<Box<dyn FnMut() -> Option<String>>>,
+    unmount_cb:       Option<Box<dyn FnMut(&[SessionMount]) -> bool>>,
+    report_consent:   Option<Box<dyn FnMut(&io::Error) -> bool>>,
+    report_upload:    Option<Box<dyn FnMut(&Path)>>,
+    hook_output_cb:   Option<Box<dyn FnMut(&HookOutput)>>,
+    command_cb:       Option<journal::Callback>,
+    metric_cb:        Option<metrics::Callback>,
+    progress:         InstallProgressHandle,
+    work_dir:         Option<WorkDir>,
+    summary:          Option<InstallSummary>,
 }
 
 impl Default for Installer {
@@ -110,8 +400,33 @@ impl Default for Installer {
         Self {
             error_cb:         None,
             status_cb:        None,
+            event_cb:         None,
+            events:           EventHandle::default(),
             timezone_cb:      None,
             user_creation_cb: None,
+            mok_password_cb:  None,
+            unmount_cb:       None,
+            report_consent:   None,
+            report_upload:    None,
+            hook_output_cb:   None,
+            command_cb:       None,
+            metric_cb:        None,
+            progress:         InstallProgressHandle::default(),
+            work_dir:         None,
+            summary:          None,
+        }
+    }
+}
+
+impl Drop for Installer {
+    fn drop(&mut self) {
+        // An installation which panicked may have left the callbacks set.
+        if self.command_cb.is_some() {
+            journal::set_callback(None);
+        }
+
+        if self.metric_cb.is_some() {
+            metrics::set_callback(None);
         }
     }
 }
@@ -138,31 +453,223 @@ impl Installer {
     /// will be applied when configuring the new installation.
     ///
     /// If `config.old_root` is set, then home at that location will be retained.
-    pub fn install(&mut self, mut disks: Disks, config: &Config) -> io::Result<()> {
+    ///
+    /// Artifacts of the installation are recorded in a working directory within
+    /// `/var/log/distinst`, which is copied to the new install once it has been configured.
+    pub fn install(&mut self, disks: Disks, config: &Config) -> io::Result<()> {
+        self.summary = None;
+        self.work_dir = WorkDir::new()
+            .map_err(|why| warn!("unable to create installation working directory: {}", why))
+            .ok();
+
+        if let Some(ref work_dir) = self.work_dir {
+            work_dir.snapshot(config, &disks);
+        }
+
+        // Commands are journaled and timed globally, so the callbacks are only set while this
+        // installer is installing.
+        journal::set_callback(self.command_cb.clone());
+        let journaled = config.flags.contains(InstallFlags::JOURNAL_COMMANDS);
+        if journaled {
+            if let Err(why) = journal::open(Path::new(COMMAND_JOURNAL)) {
+                warn!("unable to open command journal at {}: {}", COMMAND_JOURNAL, why);
+            }
+        }
+
+        metrics::set_callback(self.metric_cb.clone());
+        metrics::begin();
+
+        let result = self.run(disks, config);
+
+        journal::set_callback(None);
+        if journaled {
+            journal::close();
+        }
+
+        metrics::set_callback(None);
+        let summary = metrics::summary(&metrics::take());
+        info!("time spent on each operation:\n{}", summary);
+
+        if let Some(ref work_dir) = self.work_dir {
+            for line in summary.lines() {
+                work_dir.receipt(&["timing: ", line.trim_start()].concat());
+            }
+
+            work_dir.finish(&result);
+        }
+
+        if let Err(ref why) = result {
+            self.report_failure(why);
+        }
+
+        result
+    }
+
+    /// Packages the working directory of a failed installation, and passes it to the uploader
+    /// of the frontend, if the user consents to the report.
+    fn report_failure(&mut self, why: &io::Error) {
+        let Installer { ref work_dir, ref mut report_consent, ref mut report_upload, .. } = *self;
+        let (work_dir, consent, upload) = match (work_dir, report_consent, report_upload) {
+            (Some(work_dir), Some(consent), Some(upload)) => (work_dir, consent, upload),
+            _ => return,
+        };
+
+        if !consent(why) {
+            info!("failure report was declined");
+            return;
+        }
+
+        match work_dir.bundle() {
+            Ok(tarball) => {
+                info!("uploading failure report from {}", tarball.display());
+                upload(&tarball);
+            }
+            Err(why) => warn!("unable to package the failure report: {}", why),
+        }
+    }
+
+    /// The working directory of the most recent installation, if one could be created.
+    pub fn work_dir(&self) -> Option<&Path> { self.work_dir.as_ref().map(WorkDir::path) }
+
+    /// What the most recent installation changed, if it replaced an existing system.
+    pub fn summary(&self) -> Option<&InstallSummary> { self.summary.as_ref() }
+
+    fn run(&mut self, mut disks: Disks, config: &Config) -> io::Result<()> {
+        // The volumes could not be bound once they are created, if the server is unreachable.
+        // It is not contacted when there are no encrypted volumes to bind.
+        if let Some(ref tang) = config.tang_binding {
+            tang.validate()?;
+            if !disks.get_encrypted_volumes().is_empty() {
+                steps::fetch_advertisement(tang)?;
+            }
+        }
+
         let mut recovery_conf = if Path::new("/cdrom/recovery.conf").exists() {
             Some(RecoveryEnv::new()?)
         } else {
             None
         };
 
+        // Windows may be on a disk which is not modified, so it is found before those are removed.
+        let rtc_in_localtime = config.rtc_in_localtime.unwrap_or_else(|| {
+            let windows = disks.contains_windows();
+            if windows {
+                info!("Windows was found, so the hardware clock will keep local time");
+            }
+
+            windows
+        });
+
         disks.remove_untouched_disks();
+
+        let mut plan = vec![
+            Step::Init,
+            Step::Partition,
+            Step::Extract,
+            Step::Configure,
+            Step::Bootloader,
+            Step::Cleanup,
+        ];
+        // Remote images are verified within the extract step, once they have been downloaded.
+        if config.image_verification.is_some() && !squashfs::fetch::is_remote(&config.squashfs) {
+            plan.insert(1, Step::Verify);
+        }
+
+        if config.old_root.is_some() || config.file_backup.is_some() {
+            plan.insert(0, Step::Backup);
+        }
+
+        self.progress.begin(plan);
         let steps = &mut InstallerState::new(self);
 
-        Self::backup(disks, config, steps, |mut disks, config, steps| {
+        Self::backup(disks, config, steps, |mut disks, config, steps, restore| {
             if !hostname::is_valid(&config.hostname) {
                 return Err(io::Error::new(io::ErrorKind::InvalidInput, "hostname is not valid"));
             }
 
+            let valid_host = |entry: &HostEntry| {
+                !entry.names.is_empty() && entry.names.iter().all(|name| hostname::is_valid(name))
+            };
+
+            if let Some(entry) = config.hosts.iter().find(|entry| !valid_host(entry)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("host entry for {} has an invalid name", entry.address),
+                ));
+            }
+
+            let invalid_category = config
+                .locale_overrides
+                .iter()
+                .find(|(category, _)| !LOCALE_CATEGORIES.contains(&category.as_str()));
+
+            if let Some((category, _)) = invalid_category {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not a locale category which may be overridden", category),
+                ));
+            }
+
+            match crate::locale::get_keyboard_layouts() {
+                Ok(layouts) => layouts
+                    .validate(
+                        &config.keyboard_layout,
+                        config.keyboard_model.as_deref().filter(|model| !model.is_empty()),
+                        config.keyboard_variant.as_deref().filter(|variant| !variant.is_empty()),
+                    )
+                    .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))?,
+                Err(why) => warn!("keyboard settings were not validated: {}", why),
+            }
+
             let bootloader = Bootloader::detect();
-            disks
-                .verify_partitions(bootloader)
+            if !bootloader.is_supported() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "this system was booted by neither EFI nor a known U-Boot, so a boot loader \
+                     cannot be installed for it",
+                ));
+            }
+
+            let root_minimum = crate::minimum_root_size(Path::new(&config.squashfs));
+            validation_result(&disks.verify_partitions(bootloader, root_minimum))
                 .with_context(|err| format!("partition validation: {}", err))?;
 
+            if config.flags.contains(InstallFlags::RENAME_CONFLICTING_VOLUME_GROUPS) {
+                disks
+                    .resolve_volume_group_conflicts()
+                    .with_context(|err| format!("volume group renaming: {}", err))?;
+            } else {
+                disks
+                    .verify_volume_groups()
+                    .with_context(|err| format!("volume group validation: {}", err))?;
+            }
+
+            let bios_target = match bootloader {
+                Bootloader::Bios => Some(steps::prepare_bios(&mut disks, config)?),
+                Bootloader::Efi => {
+                    steps::prepare_esp(&disks, config)?;
+                    None
+                }
+                Bootloader::UBoot => {
+                    steps::prepare_uboot(&disks)?;
+                    None
+                }
+            };
+
             let (squashfs, remove_pkgs) = steps.apply(Step::Init, "initializing", |steps| {
                 Installer::initialize(&mut disks, config, percent!(steps))
             })?;
 
+            if let (Some(ref verification), Some(ref squashfs)) =
+                (&config.image_verification, &squashfs)
+            {
+                steps.apply(Step::Verify, "verifying", |steps| {
+                    steps::verify(squashfs, verification, percent!(steps))
+                })?;
+            }
+
             steps.apply(Step::Partition, "partitioning", |steps| {
+                Installer::release_session_mounts(&disks, steps)?;
                 Installer::partition(&mut disks, percent!(steps))
             })?;
 
@@ -184,13 +691,60 @@ impl Installer {
             }
 
             let iso_os_release = steps.apply(Step::Extract, "extracting", |steps| {
-                Installer::extract(squashfs.as_path(), mount_dir.path(), percent!(steps))
+                let mut mode = steps.extract.clone();
+                let mut callback = percent!(steps);
+
+                // A remote image is downloaded onto the new root in the first half of the step,
+                // and verified there, as the disks have been modified by now. It is only removed
+                // once it has been extracted, so that a failed install resumes the download.
+                let (image, offset) = match squashfs {
+                    Some(ref squashfs) => (squashfs.clone(), 0),
+                    None => {
+                        let image = steps::download_image(config, mount_dir.path(), |percent| {
+                            callback(percent / 2)
+                        })?;
+
+                        if let Some(ref verification) = config.image_verification {
+                            steps::verify(&image, verification, |_| ())?;
+                        }
+
+                        if let ExtractMode::PreservePaths(ref mut paths) = mode {
+                            paths.push(PathBuf::from(steps::IMAGE_DIRECTORY));
+                        }
+
+                        (image, 50)
+                    }
+                };
+
+                let os_release = Installer::extract(
+                    image.as_path(),
+                    mount_dir.path(),
+                    &mode,
+                    &config.extract_options,
+                    config.extract_verification.as_ref(),
+                    |percent| callback(offset + percent * (100 - offset) / 100),
+                )?;
+
+                if squashfs.is_none() {
+                    steps::remove_image(mount_dir.path())?;
+                }
+
+                Ok(os_release)
             })?;
 
             let timezone = steps.installer.timezone_cb.as_mut().map(|func| func());
-            let user = steps.installer.user_creation_cb.as_mut().map(|func| func());
-
-            steps.apply(Step::Configure, "configuring chroot", |steps| {
+            let users = steps.installer.user_creation_cb.as_mut().map_or_else(Vec::new, |f| f());
+            let mok_password = if config.flags.contains(InstallFlags::RUN_UBUNTU_DRIVERS)
+                && steps::secure_boot_enabled()
+            {
+                steps.installer.mok_password_cb.as_mut().and_then(|func| func())
+            } else {
+                None
+            };
+
+            // The callback is taken, as the step's progress is reported through `steps`.
+            let mut hook_output = steps.installer.hook_output_cb.take();
+            let configured = steps.apply(Step::Configure, "configuring chroot", |steps| {
                 Installer::configure(
                     recovery_conf.as_mut(),
                     &disks,
@@ -198,46 +752,91 @@ impl Installer {
                     &config,
                     &iso_os_release,
                     timezone.as_ref(),
-                    user.as_ref(),
+                    rtc_in_localtime,
+                    &users,
+                    mok_password.as_deref(),
                     &remove_pkgs,
+                    restore.map_or(&[][..], |restore| &restore.backup.packages[..]),
+                    &mut |output| {
+                        if let Some(ref mut callback) = hook_output {
+                            callback(output);
+                        }
+                    },
                     percent!(steps),
                 )
-            })?;
+            });
+
+            steps.installer.hook_output_cb = hook_output;
+            configured?;
+
+            if let Some(ref work_dir) = steps.installer.work_dir {
+                work_dir.capture("fstab", &mount_dir.path().join("etc/fstab"));
+                work_dir.capture("crypttab", &mount_dir.path().join("etc/crypttab"));
+            }
+
+            if let Some(old_system) = steps.old_system.take() {
+                let root_fs = disks
+                    .get_partition_with_target(Path::new("/"))
+                    .and_then(|root| root.filesystem);
+                let new_system = SystemSnapshot::read(mount_dir.path(), root_fs);
+                let summary = InstallSummary::new(&old_system, &new_system);
+                if let Some(ref work_dir) = steps.installer.work_dir {
+                    work_dir.write("summary", summary.to_string());
+                    for line in summary.headline() {
+                        work_dir.receipt(&["summary: ", &line].concat());
+                    }
+                }
+
+                steps.installer.summary = Some(summary);
+            }
 
             steps.apply(Step::Bootloader, "configuring bootloader", |steps| {
                 Installer::bootloader(
                     &disks,
                     mount_dir.path(),
                     bootloader,
+                    bios_target.as_ref(),
                     &config,
                     &iso_os_release,
                     percent!(steps),
                 )
             })?;
 
-            mounts.unmount(false).with_context(|err| format!("chroot unmount: {}", err))?;
-            mount_dir.close().with_context(|err| format!("closing mount directory: {}", err))
-        })?;
-
-        let _ = deactivate_logical_devices();
+            let mut mount_dir = Some(mount_dir);
+            steps.apply(Step::Cleanup, "cleaning up", |steps| {
+                if let (Some(work_dir), Some(mount_dir)) =
+                    (steps.installer.work_dir.as_ref(), mount_dir.as_ref())
+                {
+                    work_dir.copy_to(mount_dir.path());
+                }
 
-        if let Some(conf) = recovery_conf.as_mut() {
-            conf.remove("MODE");
-            conf.write()?;
-        }
+                Installer::cleanup(
+                    &mut mounts,
+                    &mut mount_dir,
+                    restore,
+                    recovery_conf.as_mut(),
+                    percent!(steps),
+                )
+            })?;
 
-        Ok(())
+            steps.installer.progress.cleaned_up();
+            Ok(())
+        })
     }
 
-    /// Create a backup of key data on the system, execute the given functi on, and then restore
-    /// that backup. If a backup is not requested for the configuration, then it will just
-    /// execute the given function.
-    fn backup<F: FnMut(Disks, &Config, &mut InstallerState) -> io::Result<()>>(
+    /// Create a backup of key data on the system, and execute the given function, which is
+    /// responsible for restoring that backup once the new install has been unmounted. If a
+    /// backup is not requested for the configuration, then it will just execute the given
+    /// function.
+    fn backup<F>(
         disks: Disks,
         config: &Config,
         steps: &mut InstallerState,
         mut func: F,
-    ) -> io::Result<()> {
+    ) -> io::Result<()>
+    where
+        F: FnMut(Disks, &Config, &mut InstallerState, Option<&Restore>) -> io::Result<()>,
+    {
         let account_files;
         let mut old_backup = None;
 
@@ -269,18 +868,48 @@ impl Installer {
 
             account_files = AccountFiles::new(old_root_path, old_root_fs)?;
 
+            steps.old_system = mount_and_then(old_root_path, old_root_fs, |base| {
+                Ok(SystemSnapshot::read(base, Some(old_root_fs)))
+            })
+            .map_err(|why| warn!("unable to read the system which is being replaced: {}", why))
+            .ok();
+
             let backup = steps.apply(Step::Backup, "backing up", |steps| {
                 let mut callback = percent!(steps);
+                // Files are copied before anything is modified.
+                if let Some(ref files) = config.file_backup {
+                    files.copy(&disks, |percent| callback(percent / 4))?;
+                }
+
+                let mut backup =
+                    Backup::new(home_path, home_fs, home_is_root, &account_files)?;
+                if config.flags.contains(InstallFlags::RESTORE_PACKAGES) {
+                    backup.packages = manual_packages(old_root_path, old_root_fs)
+                        .map_err(|why| warn!("unable to list the installed packages: {}", why))
+                        .unwrap_or_default();
+                }
 
-                let backup = Backup::new(home_path, home_fs, home_is_root, &account_files)?;
                 callback(25);
 
                 validate_backup_conditions(&disks, &config.squashfs)?;
                 callback(50);
 
-                if config.flags & KEEP_OLD_ROOT != 0 {
+                // A root which keeps /home is synchronized with the image, rather than emptied.
+                // Only a squashfs image can be synchronized, as tarballs are only extracted.
+                let refreshed_in_place = home_is_root
+                    && !new_root.will_format()
+                    && new_root.get_device_path() == old_root_path
+                    && Compression::detect(Path::new(&config.squashfs)).is_none();
+
+                if config.flags.contains(InstallFlags::KEEP_OLD_ROOT) {
                     move_root(old_root_path, old_root_fs)?;
                     old_backup = Some((old_root_path.to_path_buf(), old_root_fs));
+                } else if refreshed_in_place {
+                    info!("preserving /home while synchronizing the root with the image");
+                    steps.extract = ExtractMode::PreservePaths(vec![
+                        PathBuf::from("home"),
+                        PathBuf::from(GENERATIONS),
+                    ]);
                 } else {
                     remove_root(old_root_path, old_root_fs)?;
                 }
@@ -290,13 +919,20 @@ impl Installer {
                 Ok(backup)
             })?;
 
-            Some((backup, root_path, root_fs))
+            let keep_old_root = config.flags.contains(InstallFlags::KEEP_OLD_ROOT);
+            Some(Restore { backup, root: root_path, root_fs, keep_old_root })
         } else {
+            if let Some(ref files) = config.file_backup {
+                steps.apply(Step::Backup, "backing up files", |steps| {
+                    files.copy(&disks, percent!(steps)).map_err(io::Error::from)
+                })?;
+            }
+
             None
         };
 
         // Do the destructive action of reinstalling the system.
-        if let Err(why) = func(disks, config, steps) {
+        if let Err(why) = func(disks, config, steps, backup.as_ref()) {
             error!("errored while installing system: {}", why);
 
             if let Some((path, fs)) = old_backup {
@@ -306,16 +942,6 @@ impl Installer {
             return Err(why);
         }
 
-        // Then restore the backup, if it exists.
-        if let Some((backup, root_path, root_fs)) = backup {
-            info!("applying backup");
-            backup.restore(&root_path, root_fs)?;
-
-            if let Err(why) = delete_old_install(&root_path, root_fs) {
-                warn!("failed to delete old install: {}", why);
-            }
-        }
-
         info!("finishing job");
         let mut callback = percent!(steps);
         callback(100);
@@ -323,6 +949,17 @@ impl Installer {
         Ok(())
     }
 
+    /// A handle for querying the progress of the installation, which may be shared with
+    /// other threads while the installation is running.
+    ///
+    /// ```ignore,rust
+    /// use distinst::Installer;
+    /// let installer = Installer::new();
+    /// let progress = installer.progress();
+    /// println!("{:?}", progress.snapshot().completed);
+    /// ```
+    pub fn progress(&self) -> InstallProgressHandle { self.progress.clone() }
+
     /// Send an error message
     ///
     /// ```ignore,rust
@@ -335,6 +972,11 @@ impl Installer {
     /// });
     /// ```
     pub fn emit_error(&mut self, error: &Error) {
+        let sequence = self.events.push(EventKind::Error(copy_error(error)));
+        if let Some(ref mut cb) = self.event_cb {
+            cb(&Event { sequence, kind: EventKind::Error(copy_error(error)) });
+        }
+
         if let Some(ref mut cb) = self.error_cb {
             cb(error);
         }
@@ -362,6 +1004,11 @@ impl Installer {
     /// });
     /// ```
     pub fn emit_status(&mut self, status: Status) {
+        let sequence = self.events.push(EventKind::Status(status));
+        if let Some(ref mut cb) = self.event_cb {
+            cb(&Event { sequence, kind: EventKind::Status(status) });
+        }
+
         if let Some(ref mut cb) = self.status_cb {
             cb(&status);
         }
@@ -378,23 +1025,149 @@ impl Installer {
         self.status_cb = Some(Box::new(callback));
     }
 
+    /// Set the callback which receives every status and error, along with its sequence number.
+    ///
+    /// A frontend which reconnects to the installer may pass the last sequence number that it
+    /// received to the `replay` method of the `events` handle to obtain the events that it
+    /// missed.
+    ///
+    /// ```ignore,rust
+    /// use distinst::Installer;
+    /// let mut installer = Installer::new();
+    /// installer.on_event(|event| println!("{}: {:?}", event.sequence, event.kind));
+    /// ```
+    pub fn on_event<F: FnMut(&Event) + 'static>(&mut self, callback: F) {
+        self.event_cb = Some(Box::new(callback));
+    }
+
+    /// Set the callback which receives each line of output from the post-install scripts.
+    pub fn on_hook_output<F: FnMut(&HookOutput) + 'static>(&mut self, callback: F) {
+        self.hook_output_cb = Some(Box::new(callback));
+    }
+
+    /// A handle for replaying the events which were emitted, which may be shared with other
+    /// threads while the installation is running. Only the last `EVENT_BUFFER_LEN` events
+    /// are retained.
+    ///
+    /// ```ignore,rust
+    /// use distinst::Installer;
+    /// let installer = Installer::new();
+    /// for event in installer.events().replay(0) {
+    ///     println!("{}: {:?}", event.sequence, event.kind);
+    /// }
+    /// ```
+    pub fn events(&self) -> EventHandle { self.events.clone() }
+
+    /// Set the callback which receives every external command that is executed while this
+    /// installer is installing, along with its exit status and output.
+    ///
+    /// ```ignore,rust
+    /// use distinst::Installer;
+    /// let mut installer = Installer::new();
+    /// installer.on_command(|record| println!("{}", record));
+    /// ```
+    pub fn on_command<F: FnMut(&CommandRecord) + Send + 'static>(&mut self, callback: F) {
+        self.command_cb = Some(Arc::new(Mutex::new(callback)));
+    }
+
+    /// Set the callback which receives the time taken by each step, and by each external
+    /// command that is executed while this installer is installing.
+    ///
+    /// ```ignore,rust
+    /// use distinst::Installer;
+    /// let mut installer = Installer::new();
+    /// installer.on_metric(|metric| println!("{}: {:?}", metric.operation, metric.duration));
+    /// ```
+    pub fn on_metric<F: FnMut(&Metric) + Send + 'static>(&mut self, callback: F) {
+        self.metric_cb = Some(Arc::new(Mutex::new(callback)));
+    }
+
     /// Set the timezone callback
     pub fn set_timezone_callback<F: FnMut() -> Region + 'static>(&mut self, callback: F) {
         self.timezone_cb = Some(Box::new(callback));
     }
 
-    pub fn set_user_callback<F: FnMut() -> UserAccountCreate + 'static>(&mut self, callback: F) {
+    /// Set the callback which provides the user accounts to create, such as an administrator
+    /// along with standard users.
+    pub fn set_user_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut() -> Vec<UserAccountCreate> + 'static,
+    {
         self.user_creation_cb = Some(Box::new(callback));
     }
 
+    /// Set the callback which provides the password that enrolls the key which DKMS modules
+    /// are signed with, when Secure Boot is enabled and `RUN_UBUNTU_DRIVERS` is set. The
+    /// user must enter the password on the next boot to complete the enrollment. Modules are
+    /// not signed if it returns `None`.
+    pub fn set_mok_password_callback<F: FnMut() -> Option<String> + 'static>(
+        &mut self,
+        callback: F,
+    ) {
+        self.mok_password_cb = Some(Box::new(callback));
+    }
+
+    /// Set the callback which confirms that mounts and swaps of the live session, which hold
+    /// partitions of the disks to be modified, may be released before partitioning. If it
+    /// returns `false`, the installation fails instead. Without a callback, they are released
+    /// without confirmation.
+    pub fn set_unmount_callback<F: FnMut(&[SessionMount]) -> bool + 'static>(
+        &mut self,
+        callback: F,
+    ) {
+        self.unmount_cb = Some(Box::new(callback));
+    }
+
+    /// Set the callbacks which report a failed installation. When the installation fails, the
+    /// `consent` callback is asked whether the user agrees to send a report of the failure. If
+    /// they do, the working directory of the installation is packaged into a tarball, and its
+    /// path is passed to the `upload` callback, which the frontend sends the report with.
+    pub fn set_failure_report_callbacks<C, U>(&mut self, consent: C, upload: U)
+    where
+        C: FnMut(&io::Error) -> bool + 'static,
+        U: FnMut(&Path) + 'static,
+    {
+        self.report_consent = Some(Box::new(consent));
+        self.report_upload = Some(Box::new(upload));
+    }
+
     fn initialize<F: FnMut(i32)>(
         disks: &mut Disks,
         config: &Config,
         callback: F,
-    ) -> io::Result<(PathBuf, Vec<String>)> {
+    ) -> io::Result<(Option<PathBuf>, Vec<String>)> {
         steps::initialize(disks, config, callback)
     }
 
+    /// Releases mounts and swaps of the live session which would cause partitioning to fail,
+    /// such as a partition which the user had opened in the file manager.
+    fn release_session_mounts(disks: &Disks, steps: &mut InstallerState) -> io::Result<()> {
+        let mounts = disks
+            .session_mounts()
+            .with_context(|err| format!("failed to read mounts of the live session: {}", err))?;
+
+        if mounts.is_empty() {
+            return Ok(());
+        }
+
+        let confirmed = steps.installer.unmount_cb.as_mut().map_or(true, |func| func(&mounts));
+        if !confirmed {
+            let mounts = mounts.iter().map(ToString::to_string).collect::<Vec<_>>();
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("disks to be modified are in use: {}", mounts.join(", ")),
+            ));
+        }
+
+        for mount in &mounts {
+            info!("releasing {}", mount);
+            mount.release().with_context(|err| format!("failed to release {}: {}", mount, err))?;
+            steps.receipt(|| format!("Partition: released {}", mount));
+        }
+
+        Ok(())
+    }
+
     /// Apply all partitioning and formatting changes to the disks
     /// configuration specified.
     fn partition<F: FnMut(i32)>(disks: &mut Disks, callback: F) -> io::Result<()> {
@@ -408,11 +1181,33 @@ impl Installer {
     fn extract<P: AsRef<Path>, F: FnMut(i32)>(
         squashfs: P,
         mount_dir: P,
-        callback: F,
+        mode: &ExtractMode,
+        options: &ExtractOptions,
+        verification: Option<&ExtractVerification>,
+        mut callback: F,
     ) -> io::Result<OsRelease> {
         info!("Extracting {}", squashfs.as_ref().display());
-        let mount_dir = mount_dir.as_ref();
-        squashfs::extract(squashfs, mount_dir, callback)?;
+        let (squashfs, mount_dir) = (squashfs.as_ref(), mount_dir.as_ref());
+        let extract = |callback: &mut dyn FnMut(i32)| match mode {
+            ExtractMode::Overwrite => {
+                squashfs::extract_with(squashfs, mount_dir, options, callback)
+            }
+            ExtractMode::PreservePaths(paths) => {
+                steps::sync_image(squashfs, mount_dir, paths, callback)
+            }
+        };
+
+        match verification {
+            // Verification reads every file again, and is given the last fifth of the progress.
+            Some(verification) => {
+                extract(&mut |percent| callback(percent * 4 / 5))?;
+                steps::verify_extraction(squashfs, mount_dir, verification, |percent| {
+                    callback(80 + percent / 5)
+                })?;
+            }
+            None => extract(&mut callback)?,
+        }
+
         OsRelease::new_from(&mount_dir.join("etc/os-release")).with_context(|why| {
             format!("failed to parse /etc/os-release from extracted image: {}", why)
         })
@@ -426,8 +1221,12 @@ impl Installer {
         config: &Config,
         iso_os_release: &OsRelease,
         region: Option<&Region>,
-        user: Option<&UserAccountCreate>,
+        rtc_in_localtime: bool,
+        users: &[UserAccountCreate],
+        mok_password: Option<&str>,
         remove_pkgs: &[S],
+        restore_pkgs: &[String],
+        hook_output: &mut dyn FnMut(&HookOutput),
         callback: F,
     ) -> io::Result<()> {
         steps::configure(
@@ -437,8 +1236,12 @@ impl Installer {
             config,
             iso_os_release,
             region,
-            user,
+            rtc_in_localtime,
+            users,
+            mok_password,
             remove_pkgs,
+            restore_pkgs,
+            hook_output,
             callback,
         )
     }
@@ -448,11 +1251,31 @@ impl Installer {
         disks: &Disks,
         mount_dir: &Path,
         bootloader: Bootloader,
+        bios_target: Option<&BiosTarget>,
         config: &Config,
         iso_os_release: &OsRelease,
         callback: F,
     ) -> io::Result<()> {
-        steps::bootloader(disks, mount_dir, bootloader, config, iso_os_release, callback)
+        steps::bootloader(
+            disks,
+            mount_dir,
+            bootloader,
+            bios_target,
+            config,
+            iso_os_release,
+            callback,
+        )
+    }
+
+    /// Unmounts the new install and releases the devices that it was installed to.
+    fn cleanup<F: FnMut(i32)>(
+        mounts: &mut Mounts,
+        mount_dir: &mut Option<TempDir>,
+        restore: Option<&Restore>,
+        recovery_conf: Option<&mut RecoveryEnv>,
+        callback: F,
+    ) -> io::Result<()> {
+        steps::cleanup(mounts, mount_dir, restore, recovery_conf, callback)
     }
 }
 
@@ -1,16 +1,91 @@
-use super::{Error, Installer, Status, Step};
+use super::{Error, ExtractMode, Installer, Status, Step, SystemSnapshot};
 use libc;
-use std::{io, sync::atomic::Ordering};
+use crate::misc::metrics;
+use std::{
+    io,
+    sync::{atomic::Ordering, Arc, Mutex, MutexGuard},
+    time::Instant,
+};
 use crate::KILL_SWITCH;
 
+/// A snapshot of the progress that an installation has made.
+///
+/// Frontends may use this to restore their interface if they were restarted while the
+/// installation continued in the background.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InstallProgress {
+    /// The step which is currently being executed.
+    pub current:     Option<Step>,
+    /// The progress of the current step.
+    pub percent:     i32,
+    /// Steps which have been successfully completed, in order.
+    pub completed:   Vec<Step>,
+    /// Steps which have yet to be started, in order.
+    pub pending:     Vec<Step>,
+    /// The step which failed, if the installation failed.
+    pub failed:      Option<Step>,
+    /// Whether mounts and logical devices have been cleaned up after the installation.
+    pub cleanup_ran: bool,
+}
+
+/// A shared handle to the progress of an installation, which may be queried from any thread.
+#[derive(Clone, Debug, Default)]
+pub struct InstallProgressHandle(Arc<Mutex<InstallProgress>>);
+
+impl InstallProgressHandle {
+    /// Obtains a copy of the current state of the installation.
+    pub fn snapshot(&self) -> InstallProgress { self.lock().clone() }
+
+    pub(crate) fn begin(&self, steps: Vec<Step>) {
+        *self.lock() = InstallProgress { pending: steps, ..InstallProgress::default() };
+    }
+
+    pub(crate) fn start(&self, step: Step) {
+        let mut progress = self.lock();
+        progress.pending.retain(|&pending| pending != step);
+        progress.current = Some(step);
+        progress.percent = 0;
+    }
+
+    pub(crate) fn set_percent(&self, percent: i32) { self.lock().percent = percent; }
+
+    pub(crate) fn complete(&self, step: Step) {
+        let mut progress = self.lock();
+        progress.current = None;
+        progress.completed.push(step);
+    }
+
+    pub(crate) fn fail(&self, step: Step) {
+        let mut progress = self.lock();
+        progress.current = None;
+        progress.failed = Some(step);
+    }
+
+    pub(crate) fn cleaned_up(&self) { self.lock().cleanup_ran = true; }
+
+    fn lock(&self) -> MutexGuard<InstallProgress> {
+        // A poisoned lock still contains valid progress information.
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
 pub struct InstallerState<'a> {
-    pub installer: &'a mut Installer,
-    pub status:    Status,
+    pub installer:  &'a mut Installer,
+    pub status:     Status,
+    /// The system which is being replaced by a reinstall, which the summary is compared with.
+    pub old_system: Option<SystemSnapshot>,
+    /// How the image is written, which a refresh install may change.
+    pub extract:    ExtractMode,
 }
 
 impl<'a> InstallerState<'a> {
     pub fn new(installer: &'a mut Installer) -> Self {
-        Self { installer, status: Status { step: Step::Init, percent: 0 } }
+        Self {
+            installer,
+            status:     Status { step: Step::Init, percent: 0 },
+            old_system: None,
+            extract:    ExtractMode::Overwrite,
+        }
     }
 
     pub fn apply<T, F>(&mut self, step: Step, msg: &str, mut action: F) -> io::Result<T>
@@ -25,16 +100,32 @@ impl<'a> InstallerState<'a> {
             return Err(io::Error::new(io::ErrorKind::Interrupted, "process killed"));
         }
 
+        self.installer.progress.start(step);
         self.status.step = step;
         self.status.percent = 0;
         let status = self.status;
         self.emit_status(status);
 
         info!("starting {} step", msg);
-        match action(self) {
-            Ok(value) => Ok(value),
+        self.receipt(|| format!("{:?}: started", step));
+        let start = Instant::now();
+        let result = action(self);
+        let elapsed = start.elapsed();
+        metrics::record(format!("step:{:?}", step), elapsed);
+
+        match result {
+            Ok(value) => {
+                self.installer.progress.complete(step);
+                self.receipt(|| {
+                    let (secs, millis) = (elapsed.as_secs(), elapsed.subsec_millis());
+                    format!("{:?}: completed in {}.{:03}s", step, secs, millis)
+                });
+                Ok(value)
+            }
             Err(err) => {
                 error!("{} error: {}", msg, err);
+                self.installer.progress.fail(step);
+                self.receipt(|| format!("{:?}: failed: {}", step, err));
                 let error = Error { step: self.status.step, err };
                 self.emit_error(&error);
                 Err(error.err)
@@ -42,7 +133,16 @@ impl<'a> InstallerState<'a> {
         }
     }
 
-    pub fn emit_status(&mut self, status: Status) { self.installer.emit_status(status); }
+    pub fn emit_status(&mut self, status: Status) {
+        self.installer.progress.set_percent(status.percent);
+        self.installer.emit_status(status);
+    }
 
     pub fn emit_error(&mut self, error: &Error) { self.installer.emit_error(&error); }
+
+    pub(crate) fn receipt<F: FnOnce() -> String>(&self, message: F) {
+        if let Some(ref work_dir) = self.installer.work_dir {
+            work_dir.receipt(&message());
+        }
+    }
 }
@@ -0,0 +1,239 @@
+//! Management of the firmware's boot entries with efibootmgr.
+//!
+//! Entries are listed, removed, and created explicitly, rather than relying on the side
+//! effects of GRUB or kernelstub, so that reinstalling to the same ESP does not accumulate
+//! duplicate entries, and so that the boot order is only changed when it was requested.
+
+use crate::chroot::Chroot;
+use std::{io, path::Path};
+
+/// A boot entry, as listed by `efibootmgr -v`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BootEntry {
+    /// The number of the `BootXXXX` variable.
+    pub number: u16,
+    pub label:  String,
+    /// Whether the entry is marked as active, and may be booted.
+    pub active: bool,
+    /// The device path of the loader, such as `HD(1,GPT,...)/File(\EFI\...)`.
+    pub path:   String,
+}
+
+impl BootEntry {
+    /// Whether the entry loads an image from the partition with the given PartUUID.
+    pub fn is_on_partition(&self, partuuid: &str) -> bool {
+        self.path.to_ascii_lowercase().contains(&partuuid.to_ascii_lowercase())
+    }
+
+    /// Whether the entry loads the `loader`, which is a path such as `\EFI\Pop_OS\shimx64.efi`.
+    pub fn loads(&self, loader: &str) -> bool {
+        self.path.to_ascii_lowercase().contains(&loader.to_ascii_lowercase())
+    }
+}
+
+/// The boot entries of the firmware, and the order in which they are attempted.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BootEntries {
+    pub current: Option<u16>,
+    pub order:   Vec<u16>,
+    pub entries: Vec<BootEntry>,
+}
+
+impl BootEntries {
+    /// Parses the output of `efibootmgr -v`.
+    pub fn parse(output: &str) -> Self {
+        let mut entries = BootEntries::default();
+
+        for line in output.lines() {
+            if let Some(current) = line.strip_prefix("BootCurrent:") {
+                entries.current = parse_number(current.trim());
+            } else if let Some(order) = line.strip_prefix("BootOrder:") {
+                entries.order = order.trim().split(',').filter_map(parse_number).collect();
+            } else if let Some(entry) = parse_entry(line) {
+                entries.entries.push(entry);
+            }
+        }
+
+        entries
+    }
+
+    /// Entries on the partition which load the same loader, or have the same label, as an
+    /// entry that is about to be created, and thus would become duplicates of it.
+    pub fn stale<'a>(
+        &'a self,
+        partuuid: &'a str,
+        label: &'a str,
+        loader: &'a str,
+    ) -> impl Iterator<Item = &'a BootEntry> {
+        self.entries.iter().filter(move |entry| {
+            entry.is_on_partition(partuuid) && (entry.label == label || entry.loads(loader))
+        })
+    }
+}
+
+/// Parses a line such as `Boot0001* Pop!_OS\tHD(1,GPT,...)/File(\EFI\...)`.
+fn parse_entry(line: &str) -> Option<BootEntry> {
+    let line = line.strip_prefix("Boot")?;
+    if line.len() < 5 || !line.is_char_boundary(4) {
+        return None;
+    }
+
+    let number = parse_number(&line[..4])?;
+    let (active, rest) = match line[4..].strip_prefix('*') {
+        Some(rest) => (true, rest),
+        None => (false, &line[4..]),
+    };
+
+    let (label, path) = match rest.find('\t') {
+        Some(pos) => (&rest[..pos], &rest[pos + 1..]),
+        None => (rest, ""),
+    };
+
+    Some(BootEntry {
+        number,
+        label: label.trim().to_owned(),
+        active,
+        path: path.trim().to_owned(),
+    })
+}
+
+fn parse_number(number: &str) -> Option<u16> {
+    if number.len() == 4 {
+        u16::from_str_radix(number, 16).ok()
+    } else {
+        None
+    }
+}
+
+fn format_number(number: u16) -> String { format!("{:04X}", number) }
+
+/// Runs efibootmgr within the installed system.
+pub struct EfiBootManager<'a, 'b> {
+    chroot: &'a Chroot<'b>,
+}
+
+impl<'a, 'b> EfiBootManager<'a, 'b> {
+    pub fn new(chroot: &'a Chroot<'b>) -> Self { EfiBootManager { chroot } }
+
+    /// Lists the boot entries, and the current boot order.
+    pub fn list(&self) -> io::Result<BootEntries> {
+        let output = self.chroot.command("efibootmgr", &["-v"]).run_with_stdout()?;
+        Ok(BootEntries::parse(&output))
+    }
+
+    /// Removes the entry, which also removes it from the boot order.
+    pub fn remove(&self, number: u16) -> io::Result<()> {
+        info!("removing EFI boot entry Boot{}", format_number(number));
+        self.chroot.command("efibootmgr", &["-b", &format_number(number), "-B"]).run()
+    }
+
+    /// Creates an entry which loads the `loader` from a partition, without changing the boot
+    /// order, and returns the number of the new entry.
+    pub fn create(&self, disk: &Path, part: i32, label: &str, loader: &str) -> io::Result<u16> {
+        let before = self.list()?;
+        let part = part.to_string();
+        let disk = disk.to_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "disk path is not valid UTF-8")
+        })?;
+
+        info!("creating EFI boot entry '{}' for {} on {} partition {}", label, loader, disk, part);
+        let output = self
+            .chroot
+            .command(
+                "efibootmgr",
+                &[
+                    "--create-only",
+                    "--disk",
+                    disk,
+                    "--part",
+                    &part,
+                    "--write-signature",
+                    "--label",
+                    label,
+                    "--loader",
+                    loader,
+                ],
+            )
+            .run_with_stdout()?;
+
+        BootEntries::parse(&output)
+            .entries
+            .into_iter()
+            .find(|entry| {
+                entry.label == label && !before.entries.iter().any(|e| e.number == entry.number)
+            })
+            .map(|entry| entry.number)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("efibootmgr did not list the new boot entry '{}'", label),
+                )
+            })
+    }
+
+    /// Replaces the boot order.
+    pub fn set_order(&self, order: &[u16]) -> io::Result<()> {
+        let order = order.iter().cloned().map(format_number).collect::<Vec<_>>().join(",");
+        info!("setting EFI boot order to {}", order);
+        self.chroot.command("efibootmgr", &["-o", &order]).run()
+    }
+
+    /// Places the `first` entries at the front of the boot order, in the order given, followed
+    /// by the rest of the existing boot order.
+    pub fn prepend_order(&self, first: &[u16]) -> io::Result<()> {
+        let current = self.list()?.order;
+        self.set_order(&prepended(first, &current))
+    }
+}
+
+fn prepended(first: &[u16], order: &[u16]) -> Vec<u16> {
+    first.iter().chain(order.iter().filter(|number| !first.contains(number))).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EFIBOOTMGR: &str = "BootCurrent: 0001
+Timeout: 1 seconds
+BootOrder: 0001,0000,0003
+Boot0000* Windows Boot Manager\tHD(1,GPT,c0a0f4d8-2c1b-4e3d-9a0e-1f2e3d4c5b6a,0x800,0x32000)/File(\\EFI\\Microsoft\\Boot\\bootmgfw.efi)WINDOWS.........
+Boot0001* Pop!_OS 22.04 LTS\tHD(1,GPT,C0A0F4D8-2C1B-4E3D-9A0E-1F2E3D4C5B6A,0x800,0x32000)/File(\\EFI\\SYSTEMD\\SYSTEMD-BOOTX64.EFI)
+Boot0002  Linux Boot Manager\tHD(1,GPT,c0a0f4d8-2c1b-4e3d-9a0e-1f2e3d4c5b6a,0x800,0x32000)/File(\\EFI\\systemd\\systemd-bootx64.efi)
+Boot0003* UEFI: PXE IPv4\tPciRoot(0x0)/Pci(0x1c,0x0)/MAC(001122334455,0)/IPv4(0.0.0.00.0.0.0,0,0)
+";
+
+    const PARTUUID: &str = "c0a0f4d8-2c1b-4e3d-9a0e-1f2e3d4c5b6a";
+
+    #[test]
+    fn entries() {
+        let entries = BootEntries::parse(EFIBOOTMGR);
+        assert_eq!(entries.current, Some(1));
+        assert_eq!(entries.order, vec![1, 0, 3]);
+        assert_eq!(entries.entries.len(), 4);
+        assert_eq!(entries.entries[0].label, "Windows Boot Manager");
+        assert!(entries.entries[1].active);
+        assert!(!entries.entries[2].active);
+        assert_eq!(entries.entries[2].label, "Linux Boot Manager");
+        assert!(entries.entries[3].path.starts_with("PciRoot(0x0)"));
+    }
+
+    #[test]
+    fn stale_entries() {
+        let entries = BootEntries::parse(EFIBOOTMGR);
+        let loader = "\\EFI\\systemd\\systemd-bootx64.efi";
+        let stale = entries
+            .stale(PARTUUID, "Pop!_OS 22.04 LTS", loader)
+            .map(|entry| entry.number)
+            .collect::<Vec<_>>();
+
+        assert_eq!(stale, vec![1, 2]);
+        assert_eq!(entries.stale("00000000-0000", "Pop!_OS 22.04 LTS", loader).count(), 0);
+    }
+
+    #[test]
+    fn boot_order() {
+        assert_eq!(prepended(&[4, 5], &[1, 5, 0, 3]), vec![4, 5, 1, 0, 3]);
+        assert_eq!(prepended(&[], &[1, 0]), vec![1, 0]);
+    }
+}
@@ -0,0 +1,595 @@
+pub mod efi;
+
+use self::efi::EfiBootManager;
+use crate::chroot::Chroot;
+use crate::disks::{
+    find_partition, BlockDeviceExt, Bootloader, Disk, DiskError, Disks, FileSystem, MBR_GAP_END,
+    SOURCE,
+};
+use crate::errors::{IntoIoResult, IoContext};
+use crate::esp::{boot_files_size, free_space, EspUsage};
+use crate::uboot::Board;
+use libc;
+use os_release::OsRelease;
+use partition_identity::PartitionID;
+use std::{
+    env,
+    ffi::OsString,
+    fs, io,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::{MetadataExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+};
+use sys_mount::{Mount, MountFlags, Unmount, UnmountFlags};
+use tempdir::TempDir;
+use crate::{Config, InstallFlags, NO_EFI_VARIABLES};
+
+use super::mount_efivars;
+
+/// How GRUB's core image will be stored on a BIOS system.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BiosInstall {
+    /// Embedded within the gap that follows the MBR.
+    Embed,
+    /// Stored in `/boot/grub`, and located by a list of its sectors.
+    Blocklists,
+}
+
+/// The device that GRUB will be installed to on a BIOS system, and how it will be installed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BiosTarget {
+    pub device: PathBuf,
+    pub method: BiosInstall,
+}
+
+/// How an existing EFI system partition is treated by the install.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EspPolicy {
+    /// The ESP is never formatted, nor are files removed from it. The install fails before
+    /// the disks are modified if it lacks the room for the new boot files.
+    Reuse,
+    /// As with `Reuse`, but the boot files of root and recovery partitions which no longer
+    /// exist are removed if there is not enough room otherwise.
+    ReuseWithCleanup,
+    /// The ESP may be formatted by the disk configuration. When it is kept, only the previous
+    /// kernel of the root partition being installed is removed, if room is needed.
+    Format,
+}
+
+impl EspPolicy {
+    /// Whether the disk configuration may format an existing ESP.
+    pub fn may_format(self) -> bool { self == EspPolicy::Format }
+
+    /// Whether the boot files of other systems may be removed from, or modified on, the ESP.
+    pub fn may_prune(self) -> bool { self == EspPolicy::ReuseWithCleanup }
+
+    /// Whether the previous kernel of the root partition being installed may be removed from
+    /// the ESP to make room for the new one.
+    pub fn may_prune_previous(self) -> bool { self != EspPolicy::Reuse }
+}
+
+impl Default for EspPolicy {
+    fn default() -> Self { EspPolicy::Format }
+}
+
+/// Ensures that an existing ESP is only formatted when the `EspPolicy` permits it, and that an
+/// ESP which is reused has room for the boot files of the new install, removing superseded
+/// boot files first with `EspPolicy::ReuseWithCleanup`.
+pub fn prepare_esp(disks: &Disks, config: &Config) -> io::Result<()> {
+    let esp = match find_partition(disks.get_physical_devices(), Path::new("/boot/efi")) {
+        Some((_, esp)) if esp.flag_is_enabled(SOURCE) => esp,
+        // A new ESP is created by the disk configuration, and has nothing to preserve.
+        _ => return Ok(()),
+    };
+
+    let path = esp.get_device_path();
+    if config.esp_policy.may_format() {
+        return Ok(());
+    } else if esp.will_format() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the ESP at {} may not be formatted with the {:?} policy",
+                path.display(),
+                config.esp_policy
+            ),
+        ));
+    }
+
+    let flags =
+        if config.esp_policy.may_prune() { MountFlags::empty() } else { MountFlags::RDONLY };
+
+    let tempdir = TempDir::new("distinst-esp")?;
+    let _mount = match esp.mount_point {
+        Some(_) => None,
+        None => {
+            let fs = esp.filesystem.unwrap_or(FileSystem::Fat32);
+            let mount = Mount::new(path, tempdir.path(), fs, flags, None)
+                .with_context(|err| format!("failed to mount ESP at {}: {}", path.display(), err))?;
+            Some(mount.into_unmount_drop(UnmountFlags::DETACH))
+        }
+    };
+
+    let base = esp.mount_point.as_deref().unwrap_or_else(|| tempdir.path());
+
+    let required = boot_files_size();
+    let mut free = free_space(base)?;
+    if free < required && config.esp_policy.may_prune() {
+        let root_uuid = find_partition(disks.get_physical_devices(), Path::new("/"))
+            .filter(|(_, root)| !root.will_format())
+            .and_then(|(_, root)| root.identifiers.uuid.clone())
+            .unwrap_or_default();
+
+        let mut usage = EspUsage::new(base)
+            .with_context(|err| format!("failed to read ESP at {}: {}", path.display(), err))?;
+        let freed = usage.prune(&root_uuid, required)?;
+        info!("freed {} bytes on the ESP at {}", freed, path.display());
+        free = usage.free;
+    }
+
+    if free < required {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "the ESP at {} has {} MiB free, but {} MiB are required for the boot files",
+                path.display(),
+                free / 1024 / 1024,
+                required / 1024 / 1024
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Determines where GRUB will be installed on a BIOS system, and ensures that the MBR gap on
+/// that device is large enough to embed GRUB's core image.
+///
+/// If the gap is too small, a new first partition is shrunk to make room. An existing first
+/// partition is only moved if `InstallFlags::MAKE_MBR_GAP` is set, and the device is being
+/// modified by the installer. Otherwise, GRUB will be installed with blocklists if `/boot` is
+/// stored on a physical partition with a file system that supports them.
+pub fn prepare_bios(disks: &mut Disks, config: &Config) -> io::Result<BiosTarget> {
+    let device = match config.bios_device {
+        Some(ref device) => PathBuf::from(device),
+        None => {
+            let ((root_dev, _), boot) = disks.get_base_partitions(Bootloader::Bios);
+            boot.map_or(root_dev, |(dev, _)| dev).to_path_buf()
+        }
+    };
+
+    let why = match disks.get_physical_device_mut(&device) {
+        Some(disk) => match disk.make_mbr_gap(config.flags.contains(InstallFlags::MAKE_MBR_GAP)) {
+            Ok(()) => return Ok(BiosTarget { device, method: BiosInstall::Embed }),
+            Err(why) => why,
+        },
+        None => {
+            let disk = Disk::from_name(&device).with_context(|err| {
+                format!("unable to probe BIOS boot device {}: {}", device.display(), err)
+            })?;
+
+            match disk.mbr_gap() {
+                Some(gap) if gap < MBR_GAP_END - 1 => DiskError::MbrGapTooSmall {
+                    device: device.clone(),
+                    gap,
+                    required: MBR_GAP_END - 1,
+                    why: "the device is not being modified by the installer",
+                },
+                _ => return Ok(BiosTarget { device, method: BiosInstall::Embed }),
+            }
+        }
+    };
+
+    let boot = find_partition(disks.get_physical_devices(), Path::new("/boot"))
+        .or_else(|| find_partition(disks.get_physical_devices(), Path::new("/")));
+
+    let supports_blocklists = boot.map_or(false, |(_, part)| match part.filesystem {
+        Some(FileSystem::Ext2) | Some(FileSystem::Ext3) | Some(FileSystem::Ext4) => true,
+        Some(FileSystem::Fat16) | Some(FileSystem::Fat32) | Some(FileSystem::Xfs) => true,
+        _ => false,
+    });
+
+    if !supports_blocklists {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{}, and GRUB cannot fall back to blocklists because /boot is not stored on a \
+                 physical ext4, xfs, or fat partition",
+                why
+            ),
+        ));
+    }
+
+    warn!("{}: falling back to installing GRUB with blocklists", why);
+    Ok(BiosTarget { device, method: BiosInstall::Blocklists })
+}
+
+/// Ensures that the blobs which the board boots from can be written before the first partition
+/// of the boot device, without overwriting its partition table or partitions.
+pub fn prepare_uboot(disks: &Disks) -> io::Result<()> {
+    let board = Board::detect();
+    if board.blobs.is_empty() {
+        return Ok(());
+    }
+
+    let ((root_dev, _), boot) = disks.get_base_partitions(Bootloader::UBoot);
+    let device = boot.map_or(root_dev, |(dev, _)| dev);
+    match disks.get_physical_device(device) {
+        Some(disk) => board.verify(disk),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the boot blobs of {} cannot be written to {}, which is not a disk",
+                board.compatible,
+                device.display()
+            ),
+        )),
+    }
+}
+
+pub fn bootloader<F: FnMut(i32)>(
+    disks: &Disks,
+    mount_dir: &Path,
+    bootloader: Bootloader,
+    bios_target: Option<&BiosTarget>,
+    config: &Config,
+    iso_os_release: &OsRelease,
+    mut callback: F,
+) -> io::Result<()> {
+    // Obtain the root device & partition, with an optional EFI device & partition.
+    let ((root_dev, root_part), boot_opt) = disks.get_base_partitions(bootloader);
+
+    let bootloader_dev = boot_opt.map_or(root_dev, |(dev, _)| dev);
+
+    let bootloader_dev = bios_target.map_or(bootloader_dev, |target| target.device.as_path());
+    let secondary_esp = disks.get_secondary_esp();
+
+    info!("{}: installing bootloader for {:?}", bootloader_dev.display(), bootloader);
+
+    {
+        let efi_path = {
+            let chroot = mount_dir.as_os_str().as_bytes();
+            let mut target_mount: Vec<u8> = if chroot[chroot.len() - 1] == b'/' {
+                chroot.to_owned()
+            } else {
+                let mut temp = chroot.to_owned();
+                temp.push(b'/');
+                temp
+            };
+
+            target_mount.extend_from_slice(b"boot/efi/");
+            PathBuf::from(OsString::from_vec(target_mount))
+        };
+
+        // Also ensure that the /boot/efi directory is created.
+        if bootloader == Bootloader::Efi && boot_opt.is_some() {
+            fs::create_dir_all(&efi_path)
+                .with_context(|err| format!("failed to create efi directory: {}", err))?;
+        }
+
+        {
+            let mut chroot = Chroot::new(mount_dir)?;
+            let efivars_mount = mount_efivars(&mount_dir)?;
+
+            if bootloader == Bootloader::Efi {
+                if let Some((_, esp)) = boot_opt {
+                    verify_mounted(&efi_path, esp.get_device_path())?;
+                }
+
+                if let Some((_, esp)) = secondary_esp {
+                    verify_mounted(&mount_dir.join("boot/efi2"), esp.get_device_path())?;
+                }
+            }
+
+            match bootloader {
+                Bootloader::Bios => {
+                    let grub_target = match env::consts::ARCH {
+                        "x86_64" => "i386-pc",
+                        unknown => return Err(io::Error::new(
+                            io::ErrorKind::Other,
+                            format!("unsupported architecture {} for bootloader {:?}", unknown, bootloader)
+                        )),
+                    };
+
+                    let target = format!("--target={}", grub_target);
+                    let mut args = vec![
+                        // Recreate device map
+                        "--recheck",
+                        // Install for BIOS
+                        &target,
+                        // Install to the bootloader_dev device
+                        bootloader_dev.to_str().unwrap(),
+                    ];
+
+                    // GRUB refuses to install with blocklists unless it is forced to.
+                    if bios_target.map_or(false, |target| target.method == BiosInstall::Blocklists)
+                    {
+                        args.insert(0, "--force");
+                    }
+
+                    chroot.command("grub-install", &args).run()?;
+
+                    chroot.command("update-initramfs", &["-c", "-k", "all"]).run()?;
+                }
+                Bootloader::Efi => {
+                    // Grub disallows whitespaces in the name.
+                    let name = super::normalize_os_release_name(&iso_os_release.name);
+                    if &name == "Pop!_OS" {
+                        chroot
+                            .command(
+                                "bootctl",
+                                &[
+                                    // Install systemd-boot
+                                    "install",
+                                    // Provide path to ESP
+                                    "--path=/boot/efi",
+                                    // Do not set EFI variables
+                                    "--no-variables",
+                                ][..],
+                            )
+                            .run()?;
+                    } else {
+                        let grub_target = match env::consts::ARCH {
+                            "aarch64" => "arm64-efi",
+                            "x86_64" => "x86_64-efi",
+                            unknown => return Err(io::Error::new(
+                                io::ErrorKind::Other,
+                                format!("unsupported architecture {} for bootloader {:?}", unknown, bootloader)
+                            )),
+                        };
+
+                        chroot
+                            .command(
+                                "/usr/bin/env",
+                                &[
+                                    "bash",
+                                    "-c",
+                                    "echo GRUB_ENABLE_CRYPTODISK=y >> /etc/default/grub",
+                                ],
+                            )
+                            .run()?;
+
+                        chroot
+                            .command(
+                                "grub-install",
+                                &[
+                                    &format!("--target={}", grub_target),
+                                    "--efi-directory=/boot/efi",
+                                    &format!("--boot-directory=/boot/efi/EFI/{}", name),
+                                    &format!("--bootloader={}", name),
+                                    "--no-nvram",
+                                    "--recheck",
+                                ],
+                            )
+                            .run()?;
+
+                        chroot
+                            .command(
+                                "grub-mkconfig",
+                                &["-o", &format!("/boot/efi/EFI/{}/grub/grub.cfg", name)],
+                            )
+                            .run()?;
+                    }
+
+                    chroot.command("update-initramfs", &["-c", "-k", "all"]).run()?;
+
+                    if let Some((_, esp)) = secondary_esp {
+                        info!("mirroring the bootloader to {}", esp.get_device_path().display());
+                        let owned = if &name == "Pop!_OS" {
+                            let root_uuid = PartitionID::get_uuid(root_part.get_device_path())
+                                .into_io_result(|| "unable to find the UUID of the root")?;
+                            vec![
+                                "EFI/systemd".into(),
+                                format!("EFI/Pop_OS-{}", root_uuid.id),
+                                "loader/entries/Pop_OS-current.conf".into(),
+                                "loader/entries/Pop_OS-oldkern.conf".into(),
+                            ]
+                        } else {
+                            vec![format!("EFI/{}", name)]
+                        };
+
+                        mirror_esp(&chroot, mount_dir, &owned)?;
+                    }
+
+                    // The entries are always created, so that the firmware can boot the
+                    // install, but they only lead the boot order if it may be modified.
+                    if !NO_EFI_VARIABLES.load(Ordering::Relaxed) {
+                        let efi_arch = match env::consts::ARCH {
+                            "aarch64" => "aa64",
+                            "x86_64" => "x64",
+                            unknown => return Err(io::Error::new(
+                                io::ErrorKind::Other,
+                                format!("unsupported architecture {} for EFI", unknown)
+                            )),
+                        };
+                        let loader = if &name == "Pop!_OS" {
+                            format!("\\EFI\\systemd\\systemd-boot{}.efi", efi_arch)
+                        } else {
+                            format!("\\EFI\\{}\\shim{}.efi", name, efi_arch)
+                        };
+
+                        // The secondary ESP follows the primary ESP in the boot order, as
+                        // its fallback.
+                        let mut esps = Vec::new();
+                        if let Some((disk, esp)) = boot_opt {
+                            esps.push((disk, esp, iso_os_release.pretty_name.clone()));
+                        }
+
+                        if let Some((disk, esp)) = secondary_esp {
+                            let label = format!("{} (secondary)", iso_os_release.pretty_name);
+                            esps.push((disk, esp, label));
+                        }
+
+                        let manager = EfiBootManager::new(&chroot);
+                        let existing = manager.list()?;
+                        let mut created = Vec::with_capacity(esps.len());
+                        for (disk, esp, label) in &esps {
+                            if let Some(partuuid) = PartitionID::get_partuuid(esp.get_device_path())
+                            {
+                                for entry in existing.stale(&partuuid.id, label, &loader) {
+                                    manager.remove(entry.number)?;
+                                }
+                            }
+
+                            created.push(manager.create(disk, esp.number, label, &loader)?);
+                        }
+
+                        if config.flags.contains(InstallFlags::MODIFY_BOOT_ORDER) {
+                            manager.prepend_order(&created)?;
+                        }
+                    }
+                }
+                Bootloader::UBoot => {
+                    let board = Board::detect();
+                    let root_uuid = PartitionID::get_uuid(root_part.get_device_path())
+                        .into_io_result(|| "unable to find the UUID of the root partition")?;
+
+                    chroot.command("update-initramfs", &["-c", "-k", "all"]).run()?;
+
+                    let root_subvolume = root_part
+                        .subvolumes
+                        .iter()
+                        .find(|subvolume| Some(&subvolume.target) == root_part.target.as_ref())
+                        .map(|subvolume| subvolume.name.as_str());
+
+                    board.write_config(
+                        &chroot,
+                        &iso_os_release.pretty_name,
+                        boot_opt.is_some(),
+                        &root_uuid.id,
+                        root_subvolume,
+                    )?;
+                    board.write_blobs(mount_dir, bootloader_dev)?;
+                }
+            }
+
+            // Sync to the disk before unmounting
+            unsafe {
+                libc::sync();
+            }
+
+            drop(efivars_mount);
+            chroot.unmount(true)?;
+        }
+    }
+
+    callback(99);
+
+    Ok(())
+}
+
+/// Ensures that the file system of the `device` is what is mounted at `mount_point`. Otherwise,
+/// boot files would be written to the file system beneath it, leaving a system which appears
+/// to be installed, but which cannot boot.
+pub(crate) fn verify_mounted(mount_point: &Path, device: &Path) -> io::Result<()> {
+    let metadata = |path: &Path| {
+        fs::metadata(path)
+            .with_context(|err| format!("failed to stat {}: {}", path.display(), err))
+    };
+
+    let mounted = metadata(mount_point)?.dev();
+    if mounted == metadata(device)?.rdev() {
+        return Ok(());
+    }
+
+    let parent = mount_point.parent().map_or(Ok(None), |parent| metadata(parent).map(Some))?;
+    let why = if parent.map_or(false, |parent| parent.dev() == mounted) {
+        "nothing is mounted there"
+    } else {
+        "another file system is mounted there"
+    };
+
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        format!("{} is not mounted at {}: {}", device.display(), mount_point.display(), why),
+    ))
+}
+
+/// Copies the `owned` paths of the ESP, which are the bootloader, kernels, and loader entries
+/// of this system, to the secondary ESP. Hooks are installed which keep them in sync as kernels
+/// are installed and removed, and as their initramfs images are updated. The files of other
+/// systems on the secondary ESP are left as they are.
+fn mirror_esp(chroot: &Chroot, mount_dir: &Path, owned: &[String]) -> io::Result<()> {
+    let script = sync_esp_hook(owned);
+    for hooks in SYNC_ESP_HOOK_DIRS {
+        let hooks = mount_dir.join(hooks);
+        fs::create_dir_all(&hooks)
+            .with_context(|err| format!("failed to create {}: {}", hooks.display(), err))?;
+
+        let hook = hooks.join("zzz-distinst-sync-esp");
+        fs::write(&hook, &script)
+            .and_then(|_| fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)))
+            .with_context(|err| format!("failed to write {}: {}", hook.display(), err))?;
+    }
+
+    let hook = ["/", SYNC_ESP_HOOK_DIRS[0], "/zzz-distinst-sync-esp"].concat();
+    chroot.command("/bin/sh", &[hook.as_str()]).run()
+}
+
+/// The hooks of kernel packages, and of initramfs-tools, which kernelstub updates the ESP from.
+const SYNC_ESP_HOOK_DIRS: &[&str] =
+    &["etc/kernel/postinst.d", "etc/kernel/postrm.d", "etc/initramfs/post-update.d"];
+
+/// A hook which replaces each of the `owned` paths on the secondary ESP with its copy on the
+/// ESP. It runs after kernelstub has updated the ESP, as its hooks are named `zz-kernelstub`.
+fn sync_esp_hook(owned: &[String]) -> String {
+    let paths = owned.iter().map(|path| ["'", path, "'"].concat()).collect::<Vec<_>>();
+    format!(
+        r#"#!/bin/sh
+# Mirrors the boot files of this system to the secondary ESP, so that either disk can boot it.
+# Only the paths which belong to this system are replaced, to keep those of other systems.
+set -e
+mountpoint -q /boot/efi2 || exit 0
+for path in {}; do
+    rm -rf "/boot/efi2/$path"
+    if [ -e "/boot/efi/$path" ]; then
+        mkdir -p "$(dirname "/boot/efi2/$path")"
+        cp -r --preserve=timestamps "/boot/efi/$path" "/boot/efi2/$path"
+    fi
+done
+"#,
+        paths.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn unmounted_esp() {
+        let target = TempDir::new("distinst-esp").unwrap();
+        let esp = target.path().join("boot/efi");
+        fs::create_dir_all(&esp).unwrap();
+
+        let why = verify_mounted(&esp, Path::new("/dev/null")).unwrap_err();
+        assert!(why.to_string().ends_with("nothing is mounted there"), "{}", why);
+    }
+
+    #[test]
+    fn sync_esp_paths() {
+        let hook = sync_esp_hook(&["EFI/systemd".into(), "EFI/Pop_OS-1234".into()]);
+        assert!(hook.contains("for path in 'EFI/systemd' 'EFI/Pop_OS-1234'; do"), "{}", hook);
+        assert!(!hook.contains("rsync"));
+    }
+
+    #[test]
+    fn esp_policies() {
+        assert!(!EspPolicy::Reuse.may_format());
+        assert!(!EspPolicy::Reuse.may_prune());
+        assert!(!EspPolicy::Reuse.may_prune_previous());
+
+        assert!(!EspPolicy::ReuseWithCleanup.may_format());
+        assert!(EspPolicy::ReuseWithCleanup.may_prune());
+        assert!(EspPolicy::ReuseWithCleanup.may_prune_previous());
+
+        assert!(EspPolicy::Format.may_format());
+        assert!(!EspPolicy::Format.may_prune());
+        assert!(EspPolicy::Format.may_prune_previous());
+        assert_eq!(EspPolicy::default(), EspPolicy::Format);
+    }
+}
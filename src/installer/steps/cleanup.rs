@@ -0,0 +1,73 @@
+use super::super::RecoveryEnv;
+use crate::auto::{delete_old_install, retain_old_root, Backup};
+use crate::errors::IoContext;
+use crate::external::luks::deactivate_logical_devices;
+use disk_types::FileSystem;
+use std::{io, path::PathBuf};
+use sys_mount::Mounts;
+use tempdir::TempDir;
+
+/// A backup of the previous install, which will be restored to the new root partition.
+pub struct Restore<'a> {
+    pub backup:        Backup<'a>,
+    pub root:          PathBuf,
+    pub root_fs:       FileSystem,
+    /// Retains the previous system as a generation, rather than deleting it.
+    pub keep_old_root: bool,
+}
+
+/// Tears down the new install once it has been configured.
+///
+/// The chroot is unmounted, the backup of the previous install is restored, encrypted
+/// devices and their volume groups are deactivated, and the recovery partition is
+/// informed that the installation has finished.
+pub fn cleanup<F: FnMut(i32)>(
+    mounts: &mut Mounts,
+    mount_dir: &mut Option<TempDir>,
+    restore: Option<&Restore>,
+    recovery_conf: Option<&mut RecoveryEnv>,
+    mut callback: F,
+) -> io::Result<()> {
+    info!("unmounting the chroot");
+    mounts.unmount(false).with_context(|err| format!("chroot unmount: {}", err))?;
+    if let Some(mount_dir) = mount_dir.take() {
+        mount_dir.close().with_context(|err| format!("closing mount directory: {}", err))?;
+    }
+
+    callback(25);
+
+    if let Some(restore) = restore {
+        info!("applying backup");
+        restore
+            .backup
+            .restore(&restore.root, restore.root_fs)
+            .map_err(io::Error::from)
+            .with_context(|err| format!("restoring backup: {}", err))?;
+
+        if restore.keep_old_root {
+            if let Err(why) = retain_old_root(&restore.root, restore.root_fs) {
+                warn!("failed to retain old install: {}", why);
+            }
+        } else if let Err(why) = delete_old_install(&restore.root, restore.root_fs) {
+            warn!("failed to delete old install: {}", why);
+        }
+    }
+
+    callback(50);
+
+    // The system is installed, so devices which are still in use do not fail the install.
+    info!("deactivating logical devices");
+    if let Err(why) = deactivate_logical_devices() {
+        warn!("failed to deactivate logical devices: {}", why);
+    }
+
+    callback(75);
+
+    if let Some(conf) = recovery_conf {
+        conf.remove("MODE");
+        conf.write().with_context(|err| format!("updating recovery.conf: {}", err))?;
+    }
+
+    callback(100);
+    Ok(())
+}
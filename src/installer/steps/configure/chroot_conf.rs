@@ -1,33 +1,33 @@
 use crate::chroot::{Chroot, Command};
-use crate::errors::{IoContext, IntoIoResult};
+use crate::disks::NetworkDisk;
+use crate::distribution::{self, debian::APT_OPTIONS, Apt, PackageBackend};
+use crate::errors::IoContext;
+use crate::external::LuksKey;
 use crate::misc;
-use partition_identity::PartitionID;
-use proc_mounts::MountList;
 use std::{
-    fs,
+    cell::RefCell,
+    ffi::OsStr,
+    fs::{self, Permissions},
     io::{self, Write},
-    path::Path,
+    os::unix::fs::{symlink, PermissionsExt},
+    path::{Path, PathBuf},
     process::Stdio,
 };
 use sys_mount::*;
+use crate::recovery::{RecoveryConf, RecoveryPartition};
 use crate::timezones::Region;
-use crate::Config;
-
-const APT_OPTIONS: &[&str] = &[
-    "-o",
-    "Acquire::cdrom::AutoDetect=0",
-    "-o",
-    "Acquire::cdrom::mount=/cdrom",
-    "-o",
-    "APT::CDROM::NoMount=1",
-];
+use crate::{
+    Config, FirstBootService, FlatpakRemote, HookOutput, HostEntry, OfflineRepository, SudoAccess,
+    TangBinding, TargetFile, TargetFileSource, UserAccountCreate, UserBackend,
+};
+use super::{
+    clevis, first_boot, flatpak, homed, hosts, keymap, locale, network_disks, offline_repo, ssh,
+    users,
+};
 
 // For a clean boot by default, we hide all output and use plymouth
 const BOOT_OPTIONS: &str = "quiet loglevel=0 systemd.show_status=false splash";
 
-// For a reliable boot when using recovery, we show all output and do not use plymouth
-const RECOVERY_BOOT_OPTIONS: &str = "";
-
 pub struct ChrootConfigurator<'a> {
     chroot: Chroot<'a>,
 }
@@ -35,40 +35,103 @@ pub struct ChrootConfigurator<'a> {
 impl<'a> ChrootConfigurator<'a> {
     pub fn new(chroot: Chroot<'a>) -> Self { Self { chroot } }
 
-    /// Install the given packages if they are not already installed.
-    pub fn apt_install(&self, packages: &[&str]) -> io::Result<()> {
-        info!("installing packages: {:?}", packages);
-        let mut command = self.chroot.command(
-            "apt-get",
-            &cascade! {
-                Vec::with_capacity(APT_OPTIONS.len() + packages.len() + 3);
-                ..extend_from_slice(&["install", "-y"]);
-                ..extend_from_slice(APT_OPTIONS);
-                ..extend_from_slice(&packages);
-            },
-        );
+    /// Install the given packages if they are not already installed. The packages are named
+    /// as in Debian, and those which the backend lacks are skipped.
+    pub fn install_packages(
+        &self,
+        backend: &dyn PackageBackend,
+        packages: &[&str],
+    ) -> io::Result<()> {
+        let packages = distribution::package_names(backend, packages);
+        if packages.is_empty() {
+            return Ok(());
+        }
 
-        command.run()
+        backend.install(&self.chroot, &packages)
     }
 
     /// Remove the given packages from the system, if they are installed.
-    pub fn apt_remove(&self, packages: &[&str]) -> io::Result<()> {
-        info!("removing packages: {:?}", packages);
-        self.chroot
-            .command(
-                "apt-get",
-                &cascade! {
-                    Vec::with_capacity(packages.len() + 2);
-                    ..extend_from_slice(&["purge", "-y"]);
-                    ..extend_from_slice(packages);
-                },
-            )
-            .run()?;
-        self.chroot.command("apt-get", &["autoremove", "-y", "--purge"]).run()
+    pub fn remove_packages(
+        &self,
+        backend: &dyn PackageBackend,
+        packages: &[&str],
+    ) -> io::Result<()> {
+        backend.remove(&self.chroot, packages)
+    }
+
+    /// Reinstalls the packages of the previous system which the repositories provide, as some
+    /// may have come from repositories that the new system lacks. The packages are those which
+    /// dpkg recorded, so they are only restored with apt.
+    ///
+    /// If the packages cannot be installed together, they are installed one at a time, so that
+    /// a package which fails to install does not keep the rest from being restored.
+    pub fn restore_packages(
+        &self,
+        backend: &dyn PackageBackend,
+        packages: &[String],
+    ) -> io::Result<()> {
+        if !is_apt(backend) {
+            warn!("packages of the previous system cannot be restored with {}", backend.name());
+            return Ok(());
+        }
+
+        let available = Apt::available(&self.chroot)?;
+        let (restored, missing): (Vec<&str>, Vec<&str>) =
+            packages.iter().map(String::as_str).partition(|pkg| available.contains(*pkg));
+
+        if !missing.is_empty() {
+            warn!("packages of the previous system are not available: {}", missing.join(" "));
+        }
+
+        if restored.is_empty() {
+            return Ok(());
+        }
+
+        info!("reinstalling {} packages of the previous system", restored.len());
+        if let Err(why) = Apt.install(&self.chroot, &restored) {
+            warn!("reinstalling the packages one at a time, as they failed together: {}", why);
+            let failed = restored
+                .iter()
+                .filter(|&&package| Apt.install(&self.chroot, &[package]).is_err())
+                .copied()
+                .collect::<Vec<&str>>();
+
+            if !failed.is_empty() {
+                warn!("packages of the previous system failed to install: {}", failed.join(" "));
+            }
+        }
+
+        Ok(())
     }
 
-    /// Configure the bootloader on the system.
-    pub fn bootloader(&self) -> io::Result<()> {
+    /// Removes the applications of the minimal manifest, retaining the rest of the desktop.
+    /// Only apt is able to retain the dependencies of the metapackages which are removed.
+    pub fn remove_minimal<F: FnMut(i32)>(
+        &self,
+        backend: &dyn PackageBackend,
+        packages: &[&str],
+        progress: F,
+    ) -> io::Result<()> {
+        if is_apt(backend) {
+            super::minimal::remove(&self.chroot, packages, progress)
+        } else {
+            backend.remove(&self.chroot, packages)
+        }
+    }
+
+    /// Install the flatpaks system-wide, from the remotes which are added to the system.
+    pub fn flatpaks<F: FnMut(i32)>(
+        &self,
+        remotes: &[FlatpakRemote],
+        flatpaks: &[String],
+        progress: F,
+    ) -> io::Result<()> {
+        flatpak::install(&self.chroot, remotes, flatpaks, progress)
+    }
+
+    /// Configure the bootloader on the system. A root on a btrfs subvolume, `root_subvolume`,
+    /// must be named on the kernel command line, or the top level of the file system is mounted.
+    pub fn bootloader(&self, root_subvolume: Option<&str>) -> io::Result<()> {
         info!("configuring bootloader");
 
         // If the NVIDIA DKMS driver is installed, add its flags to the cmdline for the simpledrm patch to pick up.
@@ -76,6 +139,9 @@ impl<'a> ChrootConfigurator<'a> {
         // chroot where those come from the host environment.
         let has_nvidia = Path::new("/var/lib/dkms/nvidia").exists();
         let nvidia_boot_options = if has_nvidia { "nvidia-drm.modeset=1" } else { "" };
+        let root_flags = root_subvolume.map_or_else(String::new, |subvolume| {
+            ["rootflags=subvol=", subvolume].concat()
+        });
 
         let result = self
             .chroot
@@ -85,7 +151,7 @@ impl<'a> ChrootConfigurator<'a> {
                     "--esp-path",
                     "/boot/efi",
                     "--add-options",
-                    &format!("{BOOT_OPTIONS} {nvidia_boot_options}"),
+                    &format!("{BOOT_OPTIONS} {nvidia_boot_options} {root_flags}"),
                     "--loader",
                     "--manage-only",
                     "--force-update",
@@ -97,17 +163,20 @@ impl<'a> ChrootConfigurator<'a> {
         match result {
             Ok(()) => Ok(()),
             // If kernelstub was not found, use grub instead.
-            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
-                let args: &[&str] = &[];
-                self.chroot.command("update-grub", args).run()
-            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => self.update_grub(),
             Err(why) => Err(why),
         }
     }
 
+    /// Regenerates the GRUB menu from the kernels of the system.
+    pub fn update_grub(&self) -> io::Result<()> {
+        let args: &[&str] = &[];
+        self.chroot.command("update-grub", args).run()
+    }
+
     /// Add the apt repository on the image, so that packages may be installed from it.
-    pub fn cdrom_add(&self) -> io::Result<()> {
-        if Path::new("/cdrom").exists() {
+    pub fn cdrom_add(&self, backend: &dyn PackageBackend) -> io::Result<()> {
+        if is_apt(backend) && Path::new("/cdrom").exists() {
             info!("adding apt-cdrom to /etc/apt/sources.list");
             self.chroot
                 .command(
@@ -124,8 +193,92 @@ impl<'a> ChrootConfigurator<'a> {
         }
     }
 
-    pub fn install_drivers(&self, install: bool) -> io::Result<()> {
-        if install {
+    /// Add the offline repository, which is mounted in the target, as a source of packages.
+    /// Only its own package lists are fetched. The repository must be one of apt.
+    pub fn offline_repo_add(
+        &self,
+        backend: &dyn PackageBackend,
+        repo: &OfflineRepository,
+    ) -> io::Result<()> {
+        if !is_apt(backend) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("offline repositories cannot be used with {}", backend.name()),
+            ));
+        }
+
+        info!("adding the offline repository at {}", repo.path.display());
+        let mut keyring_path = None;
+        if let Some(ref keyring) = repo.keyring {
+            let contents = fs::read(keyring)
+                .with_context(|err| format!("failed to read {}: {}", keyring.display(), err))?;
+            let path = offline_repo::keyring_path(&contents);
+            let target = self.chroot.path.join(&path[1..]);
+            fs::write(&target, &contents)
+                .with_context(|err| format!("failed to write {}: {}", target.display(), err))?;
+            keyring_path = Some(path);
+        }
+
+        let list = self.chroot.path.join(&offline_repo::SOURCES_LIST[1..]);
+        fs::write(&list, offline_repo::sources_list(keyring_path))
+            .with_context(|err| format!("failed to write {}: {}", list.display(), err))?;
+
+        let sourcelist = ["Dir::Etc::sourcelist=", offline_repo::SOURCES_LIST].concat();
+        self.chroot
+            .command(
+                "apt-get",
+                &[
+                    "update",
+                    "-o",
+                    &sourcelist,
+                    "-o",
+                    "Dir::Etc::sourceparts=-",
+                    "-o",
+                    "APT::Get::List-Cleanup=0",
+                ],
+            )
+            .run()
+    }
+
+    /// Remove the offline repository, with its keyring and package lists, from the target.
+    pub fn offline_repo_remove(&self, backend: &dyn PackageBackend) -> io::Result<()> {
+        if !is_apt(backend) {
+            return Ok(());
+        }
+
+        info!("removing the offline repository");
+        for path in &[
+            offline_repo::SOURCES_LIST,
+            offline_repo::KEYRING,
+            offline_repo::ARMORED_KEYRING,
+        ] {
+            let path = self.chroot.path.join(&path[1..]);
+            if path.exists() {
+                fs::remove_file(&path)
+                    .with_context(|err| format!("failed to remove {}: {}", path.display(), err))?;
+            }
+        }
+
+        let lists = self.chroot.path.join("var/lib/apt/lists");
+        for entry in fs::read_dir(&lists)
+            .with_context(|err| format!("failed to read {}: {}", lists.display(), err))?
+        {
+            let path = entry?.path();
+            if path.file_name().and_then(OsStr::to_str).map_or(false, offline_repo::is_list) {
+                fs::remove_file(&path)
+                    .with_context(|err| format!("failed to remove {}: {}", path.display(), err))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Installs the drivers which ubuntu-drivers recommends for the hardware, with apt.
+    pub fn install_drivers(&self, backend: &dyn PackageBackend, install: bool) -> io::Result<()> {
+        if install && !is_apt(backend) {
+            warn!("drivers for the hardware cannot be found for {}", backend.name());
+            Ok(())
+        } else if install {
             info!("finding drivers for hardware");
             let args: &[&str] = &["list", "--recommended"];
             let output = self.chroot.command("ubuntu-drivers", args).run_with_stdout()?;
@@ -134,17 +287,7 @@ impl<'a> ChrootConfigurator<'a> {
             let packages: Vec<&str> = output.lines().flat_map(|line| line.trim().split(" ")).collect();
 
             info!("installing drivers: {:?}", packages);
-            let mut command = self.chroot.command(
-                "apt-get",
-                &cascade! {
-                    Vec::with_capacity(APT_OPTIONS.len() + packages.len() + 3);
-                    ..extend_from_slice(&["install", "-y"]);
-                    ..extend_from_slice(APT_OPTIONS);
-                    ..extend(packages);
-                },
-            );
-
-            let result = command.run();
+            let result = backend.install(&self.chroot, &packages);
             match result {
                 Ok(()) => Ok(()),
                 // Don't fail the whole install if it wasn't possible to install drivers
@@ -158,9 +301,15 @@ impl<'a> ChrootConfigurator<'a> {
         }
     }
 
+    /// Signs the DKMS modules which were built for the system with a machine owner key, and
+    /// stages the enrollment of that key with the given password.
+    pub fn sign_modules(&self, hostname: &str, password: &str) -> io::Result<()> {
+        super::module_signing::sign_modules(&self.chroot, hostname, password)
+    }
+
     /// Disable that repository, now that they system has been installed.
-    pub fn cdrom_disable(&self) -> io::Result<()> {
-        if Path::new("/cdrom").exists() {
+    pub fn cdrom_disable(&self, backend: &dyn PackageBackend) -> io::Result<()> {
+        if is_apt(backend) && Path::new("/cdrom").exists() {
             info!("disabling apt-cdrom from /etc/apt/sources.list");
             let path = self.chroot.path.join("etc/apt/sources.list");
             misc::sed(&path, "s/deb cdrom:/# deb cdrom:/g")
@@ -169,40 +318,58 @@ impl<'a> ChrootConfigurator<'a> {
         }
     }
 
+    /// Create the groups of the users which are not yet defined in the target.
+    pub fn create_groups(&self, users: &[UserAccountCreate]) -> io::Result<()> {
+        let group_file = fs::read_to_string(self.chroot.path.join("etc/group"))
+            .with_context(|err| format!("failed to read /etc/group: {}", err))?;
+
+        for group in users::missing_groups(&group_file, users) {
+            info!("creating group {}", group);
+            self.chroot.command("groupadd", &[group]).run()?;
+        }
+
+        Ok(())
+    }
+
     /// Create a new user account.
-    pub fn create_user(
-        &self,
-        user: &str,
-        pass: Option<&str>,
-        fullname: Option<&str>,
-        profile_icon: Option<&str>,
-    ) -> io::Result<()> {
-        // Add the user to the system.
-        {
-            const DEFAULT_USERADD_FLAGS: &[&str] = &[
-                "-m",
-                "-G", "adm,sudo,lpadmin",
-                "-s", "/bin/bash"
-            ];
+    pub fn create_user(&self, account: &UserAccountCreate) -> io::Result<()> {
+        let user = account.username.as_str();
+        info!("creating {} user {}", if account.system { "system" } else { "standard" }, user);
 
-            let mut command = self.chroot.command("useradd", DEFAULT_USERADD_FLAGS);
+        // Add the user to the system. System accounts have no home directory, and the users of
+        // systemd-homed are created on the first boot.
+        {
+            let default_shell = if account.system { "/usr/sbin/nologin" } else { "/bin/bash" };
+            let shell = account.shell.as_deref().unwrap_or(default_shell);
+            users::check_shell(&self.chroot.path, shell, account.system)?;
+
+            if account.backend == UserBackend::Homed {
+                homed::stage(&self.chroot, account, shell)?;
+            } else {
+                let mut command = self.chroot.command("useradd", &["-s", shell]);
+                command.arg(if account.system { "--system" } else { "-m" });
+
+                if !account.groups.is_empty() {
+                    command.args(&["-G", &account.groups.join(",")]);
+                }
 
-            if let Some(name) = fullname {
-                command.args(&["-c", name]);
-            }
+                if let Some(ref name) = account.realname {
+                    command.args(&["-c", name]);
+                }
 
-            command.arg(user).run()?;
-        }
+                command.arg(user).run()?;
 
-        // Set the password for the newly-created user.
-        if let Some(pass) = pass {
-            let pass = &[pass, "\n", pass, "\n"].concat();
-            self.chroot.command("passwd", &[user]).stdin_input(pass).run()?;
+                // Set the password for the newly-created user.
+                if let Some(ref pass) = account.password {
+                    let pass = &[pass.as_str(), "\n", pass, "\n"].concat();
+                    self.chroot.command("passwd", &[user]).stdin_input(pass).run()?;
+                }
+            }
         }
 
         // Copy the profile icon to `/var/lib/AccountsService/icons/{user}` and assign that in
         // the config file at `/var/lib/AccountsService/users/{user}`.
-        if let Some(path) = profile_icon {
+        if let Some(ref path) = account.profile_icon {
             let mut dest = self.chroot.path.join(&["var/lib/AccountsService/icons/", user].concat());
 
             if fs::copy(&path, &dest).is_err() {
@@ -215,7 +382,7 @@ impl<'a> ChrootConfigurator<'a> {
             if fs::write(&dest, fomat!(
                 "[User]\n"
                 "Icon=/var/lib/AccountsService/icons/" (user) "\n"
-                "SystemAccount=false\n"
+                "SystemAccount=" (account.system) "\n"
             )).is_err() {
                 let _ = fs::remove_file(&dest);
             }
@@ -224,6 +391,43 @@ impl<'a> ChrootConfigurator<'a> {
         Ok(())
     }
 
+    /// Grants the user `sudo` with a drop-in in `/etc/sudoers.d`, which `visudo` must accept.
+    /// A drop-in which it rejects is removed, as a syntax error would disable `sudo` entirely.
+    pub fn sudoers(&self, user: &str, access: SudoAccess) -> io::Result<()> {
+        info!("granting {:?} sudo access to {}", access, user);
+        let drop_in = ["/etc/sudoers.d/", &users::sudoers_file(user)].concat();
+        let target = self.chroot.path.join(&drop_in[1..]);
+        fs::write(&target, users::sudoers_rule(user, access))
+            .and_then(|_| fs::set_permissions(&target, Permissions::from_mode(0o440)))
+            .with_context(|err| format!("failed to write {:?}: {}", target, err))?;
+
+        let result = self.chroot.command("visudo", &["-c", "-f", &drop_in]).run();
+        if result.is_err() {
+            let _ = fs::remove_file(&target);
+        }
+
+        result
+    }
+
+    /// Authorizes the SSH `keys` to log in as the `user`, in `~/.ssh/authorized_keys`, which
+    /// only the user may read.
+    pub fn authorized_keys(&self, user: &str, keys: &[String]) -> io::Result<()> {
+        info!("authorizing {} SSH keys for {}", keys.len(), user);
+        let contents = ssh::authorized_keys(keys)?;
+
+        let ssh_dir = ["/home/", user, "/.ssh"].concat();
+        let target_dir = self.chroot.path.join(&ssh_dir[1..]);
+        let authorized_keys = target_dir.join("authorized_keys");
+        fs::create_dir_all(&target_dir)
+            .and_then(|_| fs::set_permissions(&target_dir, Permissions::from_mode(0o700)))
+            .and_then(|_| fs::write(&authorized_keys, contents))
+            .and_then(|_| fs::set_permissions(&authorized_keys, Permissions::from_mode(0o600)))
+            .with_context(|err| format!("failed to write {:?}: {}", authorized_keys, err))?;
+
+        // The user's login group is given by the trailing colon.
+        self.chroot.command("chown", &["-R", &[user, ":"].concat(), &ssh_dir]).run()
+    }
+
     /// Disable the nvidia fallback service.
     pub fn disable_nvidia_fallback(&self) {
         info!("attempting to disable nvidia-fallback.service");
@@ -233,17 +437,39 @@ impl<'a> ChrootConfigurator<'a> {
         }
     }
 
-    /// Use locale-gen and update-locale to set the locale of the machine.
-    pub fn generate_locale(&self, locale: &str) -> io::Result<()> {
+    /// Use locale-gen and update-locale to set the locale of the machine, along with any
+    /// additional locales, and the locales of categories which override it.
+    pub fn generate_locale(
+        &self,
+        lang: &str,
+        additional: &[String],
+        overrides: &[(String, String)],
+    ) -> io::Result<()> {
         info!("generating locales via `locale-gen` and `update-locale`");
-        self.chroot.command("locale-gen", &["--purge", locale]).run()?;
-        self.chroot.command("update-locale", &["--reset", &["LANG=", locale].concat()]).run()
+        let mut locales = vec!["--purge"];
+        locales.extend(locale::locales(lang, additional, overrides));
+        self.chroot.command("locale-gen", &locales).run()?;
+
+        let mut settings = vec!["--reset".to_owned()];
+        settings.extend(locale::settings(lang, additional, overrides));
+        self.chroot.command("update-locale", &settings).run()
     }
 
-    /// Generate a new machine ID for /var/lib/dbus/machine-id
-    pub fn generate_machine_id(&self) -> io::Result<()> {
-        info!("generating machine id via `dbus-uuidgen`");
-        self.chroot.command("sh", &["-c", "dbus-uuidgen > /etc/machine-id"]).run()?;
+    /// Generate a new machine ID for /var/lib/dbus/machine-id, or write the given one.
+    pub fn generate_machine_id(&self, machine_id: Option<&str>) -> io::Result<()> {
+        match machine_id {
+            Some(machine_id) => {
+                info!("writing machine id {}", machine_id);
+                let path = self.chroot.path.join("etc/machine-id");
+                fs::write(&path, [machine_id, "\n"].concat())
+                    .with_context(|err| format!("failed to write {}: {}", path.display(), err))?;
+            }
+            None => {
+                info!("generating machine id via `dbus-uuidgen`");
+                self.chroot.command("sh", &["-c", "dbus-uuidgen > /etc/machine-id"]).run()?;
+            }
+        }
+
         self.chroot.command("ln", &["-sf", "/etc/machine-id", "/var/lib/dbus/machine-id"]).run()
     }
 
@@ -256,19 +482,50 @@ impl<'a> ChrootConfigurator<'a> {
             .with_context(|err| format!("failed to write hostname to {:?}: {}", hostfile, err))
     }
 
-    /// Create a default hosts file for the new install.
-    pub fn hosts(&self, hostname: &str) -> io::Result<()> {
+    /// Create a default hosts file for the new install, with any additional entries.
+    pub fn hosts(&self, entries: &[HostEntry]) -> io::Result<()> {
         info!("setting hosts file");
         let hosts = self.chroot.path.join("etc/hosts");
         let mut file = misc::create(&hosts)?;
-        writeln!(
-            &mut file,
-            "# See `man hosts` for details.\n#
-# By default, systemd-resolved or libnss-myhostname will resolve
-# localhost and the system hostname if they're not specified here.
-127.0.0.1\tlocalhost\n::1\t\tlocalhost"
-        )
-        .with_context(|err| format!("failed to write hosts to {:?}: {}", hosts, err))
+        file.write_all(hosts::hosts_file(entries).as_bytes())
+            .with_context(|err| format!("failed to write hosts to {:?}: {}", hosts, err))
+    }
+
+    /// Resolve `.local` names with multicast DNS, by enabling avahi and its name service module.
+    pub fn mdns(&self) -> io::Result<()> {
+        info!("enabling mDNS resolution with avahi");
+        let nsswitch = self.chroot.path.join("etc/nsswitch.conf");
+        fs::read_to_string(&nsswitch)
+            .and_then(|contents| fs::write(&nsswitch, hosts::nsswitch_with_mdns(&contents)))
+            .with_context(|err| format!("failed to update {:?}: {}", nsswitch, err))?;
+
+        self.chroot.command("systemctl", &["enable", "avahi-daemon.service"]).run()
+    }
+
+    /// Trim unused blocks weekly, on disks which support discard.
+    pub fn fstrim_timer(&self) {
+        info!("enabling fstrim.timer");
+        if let Err(why) = self.chroot.command("systemctl", &["enable", "fstrim.timer"]).run() {
+            warn!("enabling fstrim.timer failed: {}", why);
+        }
+    }
+
+    /// Attaches the disks which are reached over the network from the initramfs.
+    pub fn network_disks(&self, disks: &[NetworkDisk]) -> io::Result<()> {
+        info!("attaching {} network disks from the initramfs", disks.len());
+        network_disks::configure(&self.chroot.path, disks)
+    }
+
+    /// Binds the encrypted volumes to a Tang server, for the initramfs to unlock them with.
+    pub fn clevis_bind(&self, tang: &TangBinding, volumes: &[(&Path, LuksKey)]) -> io::Result<()> {
+        volumes.iter().try_for_each(|&(device, key)| clevis::bind(&self.chroot, device, key, tang))
+    }
+
+    /// Creates the staged systemd-homed users on the first boot, with systemd-homed enabled.
+    pub fn homed(&self, ssh: bool) -> io::Result<()> {
+        info!("enabling systemd-homed for its users");
+        homed::enable(&self.chroot.path, ssh)?;
+        self.chroot.command("systemctl", &["enable", "systemd-homed.service"]).run()
     }
 
     pub fn initramfs_disable(&self) -> io::Result<()> {
@@ -408,148 +665,182 @@ impl<'a> ChrootConfigurator<'a> {
             return Ok(());
         }
 
-        let mounts = MountList::new()?;
-        let recovery_mount = mounts
-            .get_mount_by_dest(&recovery_path)
-            .into_io_result(|| "/recovery is mount not associated with block device")?;
-
-        let efi_mount = mounts
-            .get_mount_by_dest(&efi_path)
-            .into_io_result(|| "efi is mount not associated with block device")?;
-
-        let efi_partuuid = PartitionID::get_partuuid(&efi_mount.source)
-            .into_io_result(|| "efi partiton does not have a PartUUID")?;
-
-        let recovery_partuuid = PartitionID::get_partuuid(&recovery_mount.source)
-            .into_io_result(|| "/recovery does not have a PartUUID")?;
-
-        let recovery_uuid = PartitionID::get_uuid(&recovery_mount.source)
-            .or_else(|| PartitionID::get_uuid(&efi_mount.source))
-            .into_io_result(|| "/recovery does not have a UUID")?;
+        let partition = RecoveryPartition::new(&recovery_path, &efi_path)?;
 
         let cdrom_uuid =
             Command::new("findmnt").args(&["-n", "-o", "UUID", "/cdrom"]).run_with_stdout()?;
-        let cdrom_uuid = cdrom_uuid.trim();
 
         // If we are installing from the recovery partition, then we can skip this step.
-        if recovery_uuid.id == cdrom_uuid {
+        if partition.uuid == cdrom_uuid.trim() {
             return Ok(());
         }
 
-        // Erase whatever is on the recovery partition currently
-        if let Ok(dir) = recovery_path.read_dir() {
-            for entry in dir.filter_map(Result::ok) {
-                if let Ok(metadata) = entry.metadata() {
-                    if metadata.is_file() {
-                        let _ = fs::remove_file(&entry.path());
-                    } else if metadata.is_dir() {
-                        let _ = fs::remove_dir_all(&entry.path());
-                    }
+        let conf = RecoveryConf {
+            hostname:    config.hostname.clone(),
+            lang:        config.lang.clone(),
+            kbd_layout:  config.keyboard_layout.clone(),
+            kbd_model:   config.keyboard_model.clone().unwrap_or_default(),
+            kbd_variant: config.keyboard_variant.clone().unwrap_or_default(),
+            root_uuid:   root_uuid.into(),
+            luks_uuid:   luks_uuid.into(),
+        };
+
+        partition.create(Path::new("/cdrom"), name, &conf)
+    }
+
+    pub fn timezone(&self, region: &Region) -> io::Result<()> {
+        self.chroot.command("rm", &["/etc/timezone"]).run()?;
+
+        let args: &[&str] = &[];
+        self.chroot.command("ln", args).arg(region.path()).arg("/etc/timezone").run()
+    }
+
+    /// Writes `/etc/adjtime`, from which `timedatectl` and `hwclock` learn whether the hardware
+    /// clock keeps local time or UTC.
+    pub fn adjtime(&self, localtime: bool) -> io::Result<()> {
+        let mode = if localtime { "LOCAL" } else { "UTC" };
+        info!("setting the hardware clock to {}", mode);
+        let adjtime = self.chroot.path.join("etc/adjtime");
+        fs::write(&adjtime, ["0.0 0 0.0\n0\n", mode, "\n"].concat())
+            .with_context(|err| format!("failed to write {:?}: {}", adjtime, err))
+    }
+
+    /// Writes the files that were supplied by the frontend into the target.
+    ///
+    /// Each file is staged in `staging`, a directory within the target's `/tmp`, and is then
+    /// installed from within the chroot, so that symbolic links, users, and groups are resolved
+    /// against the target rather than the live system.
+    pub fn write_files(&self, files: &[TargetFile], staging: &Path) -> io::Result<()> {
+        let name = "target-file";
+        let staged = staging.join(name);
+        let chroot_staged = Path::new("/tmp").join(staging.file_name().unwrap()).join(name);
+
+        for file in files {
+            if !file.path.is_absolute() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("target file path is not absolute: {}", file.path.display()),
+                ));
+            }
+
+            info!("writing {} to the target", file.path.display());
+            match file.source {
+                TargetFileSource::Contents(ref contents) => fs::write(&staged, contents),
+                TargetFileSource::Path(ref source) => fs::copy(source, &staged).map(|_| ()),
+            }
+            .with_context(|err| format!("failed to stage {}: {}", file.path.display(), err))?;
+
+            let mode = format!("{:o}", file.mode);
+            let mut args: Vec<&OsStr> = vec!["-D".as_ref(), "-m".as_ref(), mode.as_ref()];
+            if let Some(ref owner) = file.owner {
+                let mut fields = owner.splitn(2, ':');
+                if let Some(user) = fields.next().filter(|user| !user.is_empty()) {
+                    args.extend_from_slice(&["-o".as_ref(), user.as_ref()]);
+                }
+
+                if let Some(group) = fields.next().filter(|group| !group.is_empty()) {
+                    args.extend_from_slice(&["-g".as_ref(), group.as_ref()]);
                 }
             }
+
+            args.extend_from_slice(&[chroot_staged.as_os_str(), file.path.as_os_str()]);
+            self.chroot.command("install", &args).run()?;
         }
 
-        let casper_data_: String;
-        let casper_data: &str = if Path::new("/cdrom/recovery.conf").exists() {
-            casper_data_ = ["/cdrom/casper-", cdrom_uuid, "/"].concat();
-            &casper_data_
-        } else {
-            "/cdrom/casper/"
+        Ok(())
+    }
+
+    /// Writes the unit of the service, with a drop-in which runs it until it has succeeded once,
+    /// and enables it for the first boot.
+    pub fn first_boot_service(&self, service: &FirstBootService) -> io::Result<()> {
+        first_boot::validate_name(&service.name)?;
+        info!("enabling {} for the first boot", service.name);
+        let contents = match service.source {
+            TargetFileSource::Contents(ref contents) => contents.clone(),
+            TargetFileSource::Path(ref source) => fs::read(source)
+                .with_context(|err| format!("failed to read {}: {}", source.display(), err))?,
         };
 
-        let casper = ["casper-", &recovery_uuid.id].concat();
-        let recovery = ["Recovery-", &recovery_uuid.id].concat();
-        if recovery_uuid.id != cdrom_uuid {
-            self.chroot
-                .command(
-                    "rsync",
-                    &["-KLavc", "--delete-before", "/cdrom/.disk", "/cdrom/dists", "/cdrom/pool", "/recovery"],
-                )
-                .run()?;
+        let units = self.chroot.path.join("etc/systemd/system");
+        let unit = units.join(&service.name);
+        let drop_ins = units.join([&service.name, ".d"].concat());
+        let wants = units.join("multi-user.target.wants");
+        let link = wants.join(&service.name);
+        let _ = fs::remove_file(&link);
+
+        fs::write(&unit, contents)
+            .and_then(|_| fs::create_dir_all(&drop_ins))
+            .and_then(|_| {
+                fs::write(drop_ins.join(first_boot::DROP_IN), first_boot::drop_in(&service.name))
+            })
+            .and_then(|_| fs::create_dir_all(&wants))
+            .and_then(|_| symlink(Path::new("/etc/systemd/system").join(&service.name), &link))
+            .with_context(|err| format!("failed to enable {}: {}", service.name, err))
+    }
 
-            self.chroot
-                .command("rsync", &["-KLavc", casper_data, &["/recovery/", &casper].concat()])
-                .run()?;
-        }
+    /// Executes each of the `scripts`, which are copied into the target, with the `env` which
+    /// describes the install. Each line that they output is passed to `output`.
+    pub fn post_install_scripts(
+        &self,
+        scripts: &[PathBuf],
+        staging: &Path,
+        env: &[(&str, &str)],
+        output: &mut dyn FnMut(&HookOutput),
+    ) -> io::Result<()> {
+        let name = "post-install-script";
+        let staged = staging.join(name);
+        let chroot_staged = Path::new("/tmp").join(staging.file_name().unwrap()).join(name);
+
+        for script in scripts {
+            info!("running post-install script {}", script.display());
+            fs::copy(script, &staged)
+                .and_then(|_| fs::set_permissions(&staged, Permissions::from_mode(0o755)))
+                .with_context(|err| format!("failed to stage {}: {}", script.display(), err))?;
+
+            let mut command = self.chroot.command(&chroot_staged, &[] as &[&str]);
+            for &(key, value) in env {
+                command.env(key, value);
+            }
 
-        // Create recovery file.
-        let recovery_data = format!(
-            r#"HOSTNAME={}
-LANG={}
-KBD_LAYOUT={}
-KBD_MODEL={}
-KBD_VARIANT={}
-EFI_UUID=PARTUUID={}
-RECOVERY_UUID=PARTUUID={}
-ROOT_UUID={}
-LUKS_UUID={}
-OEM_MODE=0
-"#,
-            config.hostname,
-            config.lang,
-            config.keyboard_layout,
-            config.keyboard_model.as_deref().unwrap_or(""),
-            config.keyboard_variant.as_deref().unwrap_or(""),
-            efi_partuuid.id,
-            recovery_partuuid.id,
-            root_uuid,
-            luks_uuid,
-        );
-
-        // Copy initrd and vmlinuz to EFI partition
-        let recovery_path = self.chroot.path.join("recovery/recovery.conf");
-        let mut recovery_file = misc::create(&recovery_path)?;
-        recovery_file
-            .write_all(recovery_data.as_bytes())
-            .with_context(|err| format!("failed to write recovery file: {}", err))?;
-
-        let efi_recovery = ["boot/efi/EFI/", recovery.as_str()].concat();
-        let efi_initrd = self.chroot.path.join([&efi_recovery, "/initrd.gz"].concat());
-        let efi_vmlinuz = self.chroot.path.join([&efi_recovery, "/vmlinuz.efi"].concat());
-
-        fs::create_dir_all(self.chroot.path.join(efi_recovery))
-            .with_context(|err| format!("failed to create EFI recovery directories: {}", err))?;
-
-        misc::cp(&[casper_data, "initrd.gz"].concat(), &efi_initrd)?;
-        misc::cp(&[casper_data, "vmlinuz.efi"].concat(), &efi_vmlinuz)?;
-
-        // If the NVIDIA DKMS driver is installed, force it to load in the recovery partition
-        // This test must not use /proc or /sys for detection since the installer can run inside a
-        // chroot where those come from the host environment.
-        let has_nvidia = Path::new("/var/lib/dkms/nvidia").exists();
+            let output = RefCell::new(&mut *output);
+            let emit = |line: &str, stderr: bool| {
+                (*output.borrow_mut())(&HookOutput { script, line, stderr });
+            };
 
-        let rec_entry_data = format!(
-            r#"title {0} recovery
-linux /EFI/{1}/vmlinuz.efi
-initrd /EFI/{1}/initrd.gz
-options {2} boot=casper hostname=recovery userfullname=Recovery username=recovery live-media-path=/{3} live-media=/dev/disk/by-partuuid/{4} noprompt {5}
-"#,
-            name,
-            recovery,
-            RECOVERY_BOOT_OPTIONS,
-            casper,
-            recovery_partuuid.id,
-            if has_nvidia { "modules_load=nvidia nvidia-drm.modeset=0" } else { "" }
-        );
-        let loader_entries = self.chroot.path.join("boot/efi/loader/entries/");
-        if !loader_entries.exists() {
-            fs::create_dir_all(&loader_entries)
-                .with_context(|err| format!("failed to create EFI loader directories: {}", err))?;
+            command.run_with_callbacks(
+                |line| {
+                    info!("{}: {}", script.display(), line);
+                    emit(line, false);
+                },
+                |line| {
+                    warn!("{}: {}", script.display(), line);
+                    emit(line, true);
+                },
+            )?;
         }
 
-        let rec_entry_path = loader_entries.join([recovery.as_str(), ".conf"].concat());
-        let mut rec_entry_file = misc::create(&rec_entry_path)?;
-        rec_entry_file
-            .write_all(rec_entry_data.as_bytes())
-            .with_context(|err| format!("failed to write recovery EFI entry: {}", err))?;
         Ok(())
     }
 
-    pub fn timezone(&self, region: &Region) -> io::Result<()> {
-        self.chroot.command("rm", &["/etc/timezone"]).run()?;
+    /// Applies default dconf settings for every user, and compiles the dconf databases.
+    pub fn dconf(&self, settings: &[(String, String)]) -> io::Result<()> {
+        info!("applying dconf overrides");
+        super::dconf::write_overrides(&self.chroot.path, settings)?;
+        self.chroot.command("dconf", &["update"]).run()
+    }
 
-        let args: &[&str] = &[];
-        self.chroot.command("ln", args).arg(region.path()).arg("/etc/timezone").run()
+    /// Configures the initramfs to include the keymap of the keyboard layout. The initramfs is
+    /// generated with it by the bootloader step.
+    pub fn initramfs_keymap(&self, config: &Config) -> io::Result<()> {
+        let generator = match keymap::Generator::detect(&self.chroot.path) {
+            Some(generator) => generator,
+            None => {
+                warn!("no initramfs generator was found to include the keymap in");
+                return Ok(());
+            }
+        };
+
+        keymap::configure(&self.chroot.path, generator, config)
     }
 
     pub fn update_initramfs(&self) -> io::Result<()> {
@@ -559,3 +850,6 @@ options {2} boot=casper hostname=recovery userfullname=Recovery username=recover
             .with_context(|why| format!("failed to update initramfs: {}", why))
     }
 }
+
+/// Whether the packages are managed by apt, whose repositories and tools some steps require.
+fn is_apt(backend: &dyn PackageBackend) -> bool { backend.name() == Apt.name() }
@@ -0,0 +1,128 @@
+//! Removes what the installation leaves behind in the installed system, which it has no further
+//! use for: downloaded packages and the caches of apt, journals, and temporary files. This can
+//! reclaim hundreds of megabytes, which matters most for images and small disks.
+
+use std::{fs, io, path::Path};
+
+/// How much is removed from the installed system once it has been configured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CleanupProfile {
+    /// Nothing is removed.
+    None,
+    /// Downloaded packages, the package caches of apt, journals, and temporary files.
+    Standard,
+    /// The package lists of apt are also removed, for systems whose sources will change on
+    /// their first boot. Packages cannot be installed until the lists have been updated.
+    Full,
+}
+
+impl Default for CleanupProfile {
+    fn default() -> Self { CleanupProfile::None }
+}
+
+/// Removes what the `profile` calls for from the system at `root`, and returns the number of
+/// bytes which were freed.
+pub fn clean(root: &Path, profile: CleanupProfile) -> io::Result<u64> {
+    if profile == CleanupProfile::None {
+        return Ok(0);
+    }
+
+    // The lock files and `partial` directories of apt are expected to exist.
+    let keep_apt =
+        |path: &Path| path.file_name().map_or(false, |name| name == "lock" || name == "partial");
+    let keep_nothing = |_: &Path| false;
+    let keep_uncached = |path: &Path| path.extension().map_or(true, |extension| extension != "bin");
+
+    let mut freed = 0;
+    freed += remove_contents(&root.join("var/cache/apt/archives"), &keep_apt)?;
+    freed += remove_contents(&root.join("var/cache/apt/archives/partial"), &keep_nothing)?;
+    freed += remove_contents(&root.join("var/cache/apt"), &keep_uncached)?;
+    freed += remove_contents(&root.join("var/log/journal"), &keep_nothing)?;
+    freed += remove_contents(&root.join("var/tmp"), &keep_nothing)?;
+    freed += remove_contents(&root.join("tmp"), &keep_nothing)?;
+
+    if profile == CleanupProfile::Full {
+        freed += remove_contents(&root.join("var/lib/apt/lists"), &keep_apt)?;
+        freed += remove_contents(&root.join("var/lib/apt/lists/partial"), &keep_nothing)?;
+    }
+
+    info!("cleanup freed {} bytes from the installed system", freed);
+    Ok(freed)
+}
+
+/// Removes the entries of a directory, which may not exist, that are not to be kept.
+fn remove_contents(dir: &Path, keep: &dyn Fn(&Path) -> bool) -> io::Result<u64> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(why) => return Err(why),
+    };
+
+    let mut freed = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if keep(&path) {
+            continue;
+        }
+
+        freed += size(&path);
+        let metadata = fs::symlink_metadata(&path)?;
+        if metadata.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(freed)
+}
+
+/// The size of a file, or of the files within a directory, without following symlinks.
+fn size(path: &Path) -> u64 {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    fs::read_dir(path)
+        .map(|entries| entries.filter_map(Result::ok).map(|entry| size(&entry.path())).sum())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn profiles() {
+        let root = TempDir::new("distinst-cleanup").unwrap();
+        let write = |path: &str, len: usize| {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, vec![0; len]).unwrap();
+        };
+
+        write("var/cache/apt/archives/lock", 0);
+        write("var/cache/apt/archives/vim_8.2_amd64.deb", 1000);
+        write("var/cache/apt/archives/partial/zsh_5.8_amd64.deb", 100);
+        write("var/cache/apt/pkgcache.bin", 10);
+        write("var/lib/apt/lists/lock", 0);
+        write("var/lib/apt/lists/archive.ubuntu.com_dists_jammy_InRelease", 1);
+        write("tmp/distinst/installer.log", 5);
+
+        assert_eq!(clean(root.path(), CleanupProfile::None).unwrap(), 0);
+        assert_eq!(clean(root.path(), CleanupProfile::Standard).unwrap(), 1115);
+        assert!(root.path().join("var/cache/apt/archives/lock").exists());
+        assert!(root.path().join("var/cache/apt/archives/partial").exists());
+        assert!(root.path().join("tmp").exists());
+        assert!(!root.path().join("tmp/distinst").exists());
+
+        assert_eq!(clean(root.path(), CleanupProfile::Full).unwrap(), 1);
+        assert!(root.path().join("var/lib/apt/lists/lock").exists());
+    }
+}
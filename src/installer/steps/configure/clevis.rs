@@ -0,0 +1,145 @@
+//! Network-bound disk encryption, with which the LUKS volumes of the installed system are bound
+//! to a Tang server through clevis, so that machines on the network of an organization boot
+//! without their passwords.
+//!
+//! The binding is stored in the LUKS header of each volume, beside the password, which still
+//! unlocks the volume away from the network. clevis-initramfs adds a script to the initramfs
+//! which configures the network and recovers the key from the server, before the password would
+//! be asked for. The initramfs is generated with it by the bootloader step.
+
+use crate::chroot::Chroot;
+use crate::errors::IoContext;
+use crate::external::LuksKey;
+use crate::TangBinding;
+use std::{fs, io, os::unix::fs::PermissionsExt, path::Path, time::Duration};
+use tempdir::TempDir;
+
+/// clevis, its Tang pin, and the hook that unlocks LUKS volumes from the initramfs.
+pub const CLEVIS_PACKAGES: &[&str] = &["clevis", "clevis-luks", "clevis-tang", "clevis-initramfs"];
+
+/// How long the server is given to respond with its advertisement.
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Fetches the advertisement of the server, which lists the keys that volumes are bound with,
+/// to ensure that the server is reachable before the install begins.
+pub fn fetch_advertisement(tang: &TangBinding) -> io::Result<()> {
+    let url = [tang.url.trim_end_matches('/'), "/adv"].concat();
+    info!("fetching the advertisement of the Tang server from {}", url);
+    let response = ureq::get(&url).timeout(TIMEOUT).call();
+    if let Some(why) = response.synthetic_error() {
+        return Err(io::Error::new(io::ErrorKind::Other, format!("{}: {}", url, why)));
+    }
+
+    if response.status() != 200 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("{}: server responded with {}", url, response.status()),
+        ));
+    }
+
+    if !is_advertisement(&response.into_string()?) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not the advertisement of a Tang server", url),
+        ));
+    }
+
+    Ok(())
+}
+
+/// The advertisement is a JWS, whose signed payload is the set of keys of the server.
+fn is_advertisement(advertisement: &str) -> bool {
+    let advertisement = advertisement.trim();
+    advertisement.starts_with('{')
+        && advertisement.ends_with('}')
+        && advertisement.contains("\"payload\"")
+        && advertisement.contains("\"signatures\"")
+}
+
+/// Binds the LUKS volume on `device` to the Tang server, which the `key` of the volume
+/// authorizes. The server must be reachable from the live system.
+pub fn bind(chroot: &Chroot, device: &Path, key: LuksKey, tang: &TangBinding) -> io::Result<()> {
+    info!("binding {} to the Tang server at {}", device.display(), tang.url);
+    let device = device.to_string_lossy();
+    let config = pin_config(tang);
+
+    // The advertisement is verified with the thumbprint, so its keys are trusted without asking.
+    match key {
+        LuksKey::Password(password) => chroot
+            .command("clevis", &["luks", "bind", "-k", "-", "-d", &*device, "tang", &config])
+            .stdin_input(password)
+            .run(),
+        LuksKey::Keyfile(keyfile) => {
+            // The keyfile is on the live system, so a copy is given to clevis in the target.
+            let dir = TempDir::new_in(chroot.path.join("tmp"), "distinst-clevis")?;
+            let copy = dir.path().join("key");
+            fs::copy(keyfile, &copy)
+                .and_then(|_| fs::set_permissions(&copy, fs::Permissions::from_mode(0o600)))
+                .with_context(|err| format!("failed to copy {}: {}", keyfile.display(), err))?;
+
+            let name = dir.path().file_name().unwrap().to_string_lossy();
+            let key = ["/tmp/", &name, "/key"].concat();
+            chroot
+                .command("clevis", &["luks", "bind", "-k", &key, "-d", &*device, "tang", &config])
+                .run()
+        }
+    }
+}
+
+/// The JSON configuration of the Tang pin.
+fn pin_config(tang: &TangBinding) -> String {
+    let (url, thumbprint) = (json_string(&tang.url), json_string(&tang.thumbprint));
+    ["{\"url\":", &url, ",\"thp\":", &thumbprint, "}"].concat()
+}
+
+fn json_string(value: &str) -> String {
+    let mut string = String::with_capacity(value.len() + 2);
+    string.push('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                string.push('\\');
+                string.push(c);
+            }
+            c if c.is_control() => string.push_str(&format!("\\u{:04x}", c as u32)),
+            c => string.push(c),
+        }
+    }
+
+    string.push('"');
+    string
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THUMBPRINT: &str = "x100_1k6GPiDOaMlL3WbpCjHOy9u";
+
+    fn tang(url: &str, thumbprint: &str) -> TangBinding {
+        TangBinding { url: url.into(), thumbprint: thumbprint.into() }
+    }
+
+    #[test]
+    fn validation() {
+        assert!(tang("https://10.0.0.5:7500", THUMBPRINT).validate().is_ok());
+        assert!(tang("tang.example.com", THUMBPRINT).validate().is_err());
+        assert!(tang("http://tang.example.com", "").validate().is_err());
+        assert!(tang("http://tang.example.com", "abc def").validate().is_err());
+    }
+
+    #[test]
+    fn advertisements() {
+        assert!(is_advertisement(r#"{"payload":"eyJrZXlzIjpbXX0","signatures":[]}"#));
+        assert!(!is_advertisement("<html><body>Not Found</body></html>"));
+        assert!(!is_advertisement(r#"{"keys":[]}"#));
+    }
+
+    #[test]
+    fn pin_configs() {
+        assert_eq!(
+            pin_config(&tang("http://tang.example.com/\"a\"", THUMBPRINT)),
+            r#"{"url":"http://tang.example.com/\"a\"","thp":"x100_1k6GPiDOaMlL3WbpCjHOy9u"}"#
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! LUKS volumes which the installation is mounted from, but which were not configured by
+//! distinst, such as a partition that was unlocked before a custom install.
+//!
+//! Without a crypttab entry for these, the initramfs cannot unlock the root file system, and
+//! drops to a busybox shell. The volumes are found by resolving the dm-crypt devices that each
+//! mount of the installation depends upon through the `slaves` of each device in sysfs.
+
+use crate::fstab::{DeviceIds, SystemDeviceIds};
+use proc_mounts::MountList;
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+const SYS_CLASS_BLOCK: &str = "/sys/class/block";
+
+/// An opened LUKS volume, and the device which contains it.
+#[derive(Debug, PartialEq)]
+struct CryptVolume {
+    /// The name of the device mapping, such as `cryptdata`.
+    name:   String,
+    /// The kernel name of the device which contains the volume, such as `nvme0n1p3`.
+    parent: String,
+}
+
+/// Appends entries to the `crypttab` for LUKS volumes that the mounts within `mount_dir`
+/// depend upon, but which are missing from it.
+pub fn append_unconfigured(crypttab: &mut OsString, mount_dir: &Path) -> io::Result<()> {
+    let mounts = MountList::new()?;
+    let devices = mounts
+        .0
+        .iter()
+        .filter(|mount| mount.dest.starts_with(mount_dir))
+        .filter_map(|mount| fs::canonicalize(&mount.source).ok())
+        .filter_map(|source| source.file_name().map(|name| name.to_string_lossy().into_owned()))
+        .collect::<Vec<_>>();
+
+    let existing = crypttab.to_string_lossy().into_owned();
+    let entries = entries(Path::new(SYS_CLASS_BLOCK), &devices, &existing, &SystemDeviceIds);
+    if !entries.is_empty() {
+        info!("adding LUKS volumes which were not configured to the crypttab:\n{}", entries);
+        crypttab.push(entries);
+    }
+
+    Ok(())
+}
+
+/// Crypttab entries for the LUKS volumes which the `devices` depend upon, excluding volumes
+/// that are already named in the `crypttab`.
+fn entries<I: DeviceIds>(sys: &Path, devices: &[String], crypttab: &str, ids: &I) -> String {
+    let mut names = crypttab
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|name| !name.starts_with('#'))
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    let mut volumes = Vec::new();
+    for device in devices {
+        crypt_volumes(sys, device, &mut volumes);
+    }
+
+    let mut entries = String::new();
+    for volume in volumes {
+        if names.contains(&volume.name) {
+            continue;
+        }
+
+        match ids.uuid(&PathBuf::from("/dev").join(&volume.parent)) {
+            Some(uuid) => {
+                let entry = [volume.name.as_str(), " UUID=", &uuid.id, " none luks\n"].concat();
+                entries.push_str(&entry);
+                names.push(volume.name);
+            }
+            None => warn!("unable to find UUID for /dev/{} -- skipping", volume.parent),
+        }
+    }
+
+    entries
+}
+
+/// Collects the LUKS volumes among the `device` and the devices beneath it, such as the LUKS
+/// volume which contains the physical volume of a logical volume.
+fn crypt_volumes(sys: &Path, device: &str, volumes: &mut Vec<CryptVolume>) {
+    let path = sys.join(device);
+    let slaves = match fs::read_dir(path.join("slaves")) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>(),
+        Err(_) => return,
+    };
+
+    let is_luks = read_attribute(&path.join("dm/uuid"))
+        .map_or(false, |uuid| uuid.starts_with("CRYPT-LUKS"));
+
+    if is_luks {
+        let name = read_attribute(&path.join("dm/name"));
+        if let (Some(name), Some(parent)) = (name, slaves.first()) {
+            let volume = CryptVolume { name, parent: parent.clone() };
+            if !volumes.contains(&volume) {
+                volumes.push(volume);
+            }
+        }
+    }
+
+    for slave in &slaves {
+        crypt_volumes(sys, slave, volumes);
+    }
+}
+
+fn read_attribute(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|value| value.trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use partition_identity::{PartitionID, PartitionSource};
+    use tempdir::TempDir;
+
+    struct TestIds;
+
+    impl DeviceIds for TestIds {
+        fn uuid(&self, device: &Path) -> Option<PartitionID> {
+            let name = device.file_name()?.to_str()?;
+            Some(PartitionID { id: ["uuid-", name].concat(), variant: PartitionSource::UUID })
+        }
+
+        fn partuuid(&self, _device: &Path) -> Option<PartitionID> { None }
+    }
+
+    fn device(sys: &Path, name: &str, slaves: &[&str], dm: Option<(&str, &str)>) {
+        let path = sys.join(name);
+        fs::create_dir_all(path.join("slaves")).unwrap();
+        for slave in slaves {
+            fs::create_dir(path.join("slaves").join(slave)).unwrap();
+        }
+
+        if let Some((dm_name, dm_uuid)) = dm {
+            fs::create_dir(path.join("dm")).unwrap();
+            fs::write(path.join("dm/name"), [dm_name, "\n"].concat()).unwrap();
+            fs::write(path.join("dm/uuid"), [dm_uuid, "\n"].concat()).unwrap();
+        }
+    }
+
+    fn names(devices: &[&str]) -> Vec<String> { devices.iter().map(|s| s.to_string()).collect() }
+
+    #[test]
+    fn lvm_on_luks() {
+        let tempdir = TempDir::new("distinst").unwrap();
+        let sys = tempdir.path();
+        device(sys, "nvme0n1p1", &[], None);
+        device(sys, "nvme0n1p3", &[], None);
+        device(sys, "dm-0", &["nvme0n1p3"], Some(("cryptdata", "CRYPT-LUKS2-0123-cryptdata")));
+        device(sys, "dm-1", &["dm-0"], Some(("data-root", "LVM-abcdef")));
+        device(sys, "dm-2", &["dm-0"], Some(("data-home", "LVM-fedcba")));
+
+        let devices = names(&["nvme0n1p1", "dm-1", "dm-2"]);
+        let expected = "cryptdata UUID=uuid-nvme0n1p3 none luks\n";
+        assert_eq!(entries(sys, &devices, "", &TestIds), expected);
+        assert_eq!(entries(sys, &devices, expected, &TestIds), "");
+    }
+
+    #[test]
+    fn luks_without_lvm() {
+        let tempdir = TempDir::new("distinst").unwrap();
+        let sys = tempdir.path();
+        device(sys, "sda2", &[], None);
+        device(sys, "sdb1", &[], None);
+        device(sys, "dm-0", &["sda2"], Some(("luks-root", "CRYPT-LUKS1-4567-luks-root")));
+        device(sys, "dm-1", &["sdb1"], Some(("luks-home", "CRYPT-LUKS2-89ab-luks-home")));
+        device(sys, "dm-2", &["sdb1"], Some(("plain", "CRYPT-PLAIN-plain")));
+
+        let crypttab = "# <target name> <source device> <key file> <options>\n\
+                        luks-home UUID=uuid-sdb1 none luks\n";
+        assert_eq!(
+            entries(sys, &names(&["dm-0", "dm-1", "dm-2"]), crypttab, &TestIds),
+            "luks-root UUID=uuid-sda2 none luks\n"
+        );
+    }
+}
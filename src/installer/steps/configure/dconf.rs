@@ -0,0 +1,106 @@
+//! Default dconf settings for every user of the installed system, such as the wallpaper,
+//! favorite applications, and power settings.
+//!
+//! Settings are written into the `local` system database, which the `user` profile is
+//! configured to read beneath each user's own database.
+
+use crate::errors::IoContext;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+/// Where the settings are written within the installed system.
+const KEYFILE: &str = "etc/dconf/db/local.d/00-distinst";
+const PROFILE: &str = "etc/dconf/profile/user";
+
+/// Writes the settings as a keyfile into the local database, and ensures that the user
+/// profile reads from it. `dconf update` must be run afterwards to compile the database.
+pub fn write_overrides(target: &Path, settings: &[(String, String)]) -> io::Result<()> {
+    let keyfile = keyfile(settings)?;
+
+    let path = target.join(KEYFILE);
+    fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| fs::write(&path, keyfile))
+        .with_context(|err| format!("failed to write dconf overrides: {}", err))?;
+
+    let profile = target.join(PROFILE);
+    let contents = fs::read_to_string(&profile).unwrap_or_default();
+    if !contents.lines().any(|line| line.trim() == "system-db:local") {
+        let mut contents = if contents.is_empty() { "user-db:user\n".into() } else { contents };
+        if !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+
+        contents.push_str("system-db:local\n");
+        fs::create_dir_all(profile.parent().unwrap())
+            .and_then(|_| fs::write(&profile, contents))
+            .with_context(|err| format!("failed to write dconf user profile: {}", err))?;
+    }
+
+    Ok(())
+}
+
+/// Generates a keyfile from settings, which are pairs of absolute keys, such as
+/// `/org/gnome/desktop/background/picture-uri`, and values in the GVariant text format.
+fn keyfile(settings: &[(String, String)]) -> io::Result<String> {
+    let mut groups: BTreeMap<&str, Vec<(&str, &str)>> = BTreeMap::new();
+    for (key, value) in settings {
+        let invalid = || {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("invalid dconf key: '{}'", key))
+        };
+
+        if !key.starts_with('/') || key.contains("//") || value.contains('\n') {
+            return Err(invalid());
+        }
+
+        let (dir, name) = match key[1..].rfind('/') {
+            Some(split) => (&key[1..split + 1], &key[split + 2..]),
+            None => return Err(invalid()),
+        };
+
+        if dir.is_empty() || name.is_empty() {
+            return Err(invalid());
+        }
+
+        groups.entry(dir).or_default().push((name, value));
+    }
+
+    let mut output = Vec::new();
+    for (dir, keys) in groups {
+        let _ = writeln!(output, "[{}]", dir);
+        for (name, value) in keys {
+            let _ = writeln!(output, "{}={}", name, value);
+        }
+
+        let _ = writeln!(output);
+    }
+
+    Ok(String::from_utf8(output).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyfiles() {
+        let settings = vec![
+            ("/org/gnome/desktop/background/picture-uri".into(), "'file:///kate.jpg'".into()),
+            ("/org/gnome/shell/favorite-apps".into(), "['firefox.desktop']".into()),
+            ("/org/gnome/desktop/background/picture-options".into(), "'zoom'".into()),
+        ];
+
+        assert_eq!(
+            keyfile(&settings).unwrap(),
+            "[org/gnome/desktop/background]\npicture-uri='file:///kate.jpg'\n\
+             picture-options='zoom'\n\n[org/gnome/shell]\nfavorite-apps=['firefox.desktop']\n\n"
+        );
+
+        for key in &["org/gnome/shell/favorite-apps", "/org/gnome/shell/", "/favorite-apps"] {
+            assert!(keyfile(&[((*key).into(), "''".into())]).is_err());
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! An encrypted `/boot`, which GRUB unlocks with its cryptodisk feature before loading the
+//! kernel.
+//!
+//! The initramfs would otherwise ask for the same password a second time, so a keyfile is
+//! enrolled into the encrypted volume, and copied into the initramfs, which is only readable
+//! by root.
+
+use crate::disks::BOOT_KEYFILE;
+use crate::errors::IoContext;
+use crate::external::{add_key, list_slots, remove_key, LuksKey};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::Path,
+};
+
+const GRUB_CONFIG: &str = "etc/default/grub.d/distinst-cryptodisk.cfg";
+const CRYPTSETUP_HOOK: &str = "etc/cryptsetup-initramfs/conf-hook";
+const INITRAMFS_CONFIG: &str = "etc/initramfs-tools/conf.d/distinst-umask.conf";
+
+/// Enrolls a keyfile into the encrypted `device`, and configures GRUB and the initramfs of the
+/// installed system at `target` to unlock it.
+///
+/// The key slot of the keyfile is wiped again if the system could not be configured, so that
+/// the volume is not left with a credential which nothing uses.
+pub fn configure(target: &Path, device: &Path, password: &str) -> io::Result<()> {
+    info!("configuring {} to be unlocked by GRUB", device.display());
+    let keyfile = target.join(BOOT_KEYFILE.trim_start_matches('/'));
+    generate_keyfile(&keyfile)
+        .with_context(|err| format!("failed to generate {}: {}", keyfile.display(), err))?;
+
+    let slots = list_slots(device)?;
+    add_key(device, LuksKey::Password(password), LuksKey::Keyfile(&keyfile), None)?;
+
+    let result = configure_unlock(target);
+    if result.is_err() {
+        let current = list_slots(device).unwrap_or_else(|why| {
+            warn!("failed to list the key slots of {}: {}", device.display(), why);
+            Vec::new()
+        });
+
+        for slot in current.into_iter().filter(|slot| !slots.contains(slot)) {
+            if let Err(why) = remove_key(device, slot, LuksKey::Password(password)) {
+                warn!("failed to remove key slot {} of {}: {}", slot, device.display(), why);
+            }
+        }
+    }
+
+    result
+}
+
+/// Configures GRUB to unlock its encrypted `/boot`, and the initramfs to include the keyfile.
+fn configure_unlock(target: &Path) -> io::Result<()> {
+    write(&target.join(GRUB_CONFIG), "GRUB_ENABLE_CRYPTODISK=y\n")?;
+    write(&target.join(INITRAMFS_CONFIG), "UMASK=0077\n")?;
+
+    let hook = target.join(CRYPTSETUP_HOOK);
+    let pattern = format!("KEYFILE_PATTERN=\"{}/*.keyfile\"", parent(BOOT_KEYFILE));
+    let contents = fs::read_to_string(&hook).unwrap_or_default();
+    if !contents.lines().any(|line| line.trim() == pattern) {
+        let mut contents = contents;
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+
+        contents.push_str(&pattern);
+        contents.push('\n');
+        write(&hook, &contents)?;
+    }
+
+    Ok(())
+}
+
+/// Writes 512 random bytes to a file that is only readable by root.
+fn generate_keyfile(path: &Path) -> io::Result<()> {
+    let directory = path.parent().expect("keyfile has a parent directory");
+    fs::create_dir_all(directory)?;
+    fs::set_permissions(directory, fs::Permissions::from_mode(0o700))?;
+
+    let mut key = [0u8; 512];
+    fs::File::open("/dev/urandom")?.read_exact(&mut key)?;
+
+    let mut keyfile = OpenOptions::new().write(true).create(true).mode(0o400).open(path)?;
+    keyfile.write_all(&key)?;
+    keyfile.sync_all()
+}
+
+fn write(path: &Path, contents: &str) -> io::Result<()> {
+    fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| fs::write(path, contents))
+        .with_context(|err| format!("failed to write {}: {}", path.display(), err))
+}
+
+fn parent(path: &str) -> &str { path.rfind('/').map_or(path, |pos| &path[..pos]) }
@@ -0,0 +1,91 @@
+//! Services which run once, on the first boot of the installed system, for tasks that cannot
+//! be performed while installing, such as enrollment, telemetry opt-in, or driver builds.
+//!
+//! Each service is given a drop-in which runs it as a oneshot, and which records that it has
+//! succeeded in `/var/lib/distinst/first-boot`. A service which fails is run again on the next
+//! boot. The changes to `/var` of a read-only root are discarded on each boot, so the services
+//! may only be used with one if `/var` is mounted from its own partition.
+
+use super::read_only;
+use std::io;
+
+/// Where the services which have succeeded are recorded.
+const DONE_DIR: &str = "/var/lib/distinst/first-boot";
+
+/// The name of the drop-in which is given to each service.
+pub const DROP_IN: &str = "distinst-first-boot.conf";
+
+/// Ensures that the name is that of a service unit, which may be written to the unit directory.
+pub fn validate_name(name: &str) -> io::Result<()> {
+    let stem = name.trim_end_matches(".service");
+    let valid = stem.len() != name.len()
+        && !stem.is_empty()
+        && !stem.starts_with('.')
+        && stem.chars().all(|c| c.is_ascii_alphanumeric() || ":-_.\\@".contains(c));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not the name of a service unit", name),
+        ))
+    }
+}
+
+/// Ensures that the services will be able to record that they have succeeded on a read-only
+/// root, whose `fstab` must mount `/var`.
+pub fn validate_read_only(fstab: &str) -> io::Result<()> {
+    if read_only::is_mounted(fstab, "/var") {
+        return Ok(());
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "first boot services on a read-only root require a partition for /var, where they record \
+         that they have succeeded",
+    ))
+}
+
+/// The drop-in which runs the service until it has succeeded once.
+pub fn drop_in(name: &str) -> String {
+    fomat!(
+        "[Unit]\n"
+        "ConditionPathExists=!" (DONE_DIR) "/" (name) "\n"
+        "\n"
+        "[Service]\n"
+        "Type=oneshot\n"
+        "ExecStartPost=/bin/mkdir -p " (DONE_DIR) "\n"
+        "ExecStartPost=/bin/touch " (DONE_DIR) "/" (name) "\n"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn services() {
+        assert!(validate_name("oem-enroll.service").is_ok());
+        assert!(validate_name("getty@tty1.service").is_ok());
+        assert!(validate_name("oem-enroll").is_err());
+        assert!(validate_name(".service").is_err());
+        assert!(validate_name("../oem-enroll.service").is_err());
+
+        assert_eq!(
+            drop_in("oem-enroll.service"),
+            "[Unit]\nConditionPathExists=!/var/lib/distinst/first-boot/oem-enroll.service\n\n\
+             [Service]\nType=oneshot\nExecStartPost=/bin/mkdir -p /var/lib/distinst/first-boot\n\
+             ExecStartPost=/bin/touch /var/lib/distinst/first-boot/oem-enroll.service\n"
+        );
+    }
+
+    #[test]
+    fn read_only_roots() {
+        let fstab = "UUID=root  /  ext4  noatime,errors=remount-ro  0  1\n";
+        assert!(validate_read_only(fstab).is_err());
+
+        let fstab = [fstab, "UUID=var  /var  ext4  noatime  0  2\n"].concat();
+        assert!(validate_read_only(&fstab).is_ok());
+    }
+}
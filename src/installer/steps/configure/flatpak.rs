@@ -0,0 +1,92 @@
+//! Flatpaks which are installed system-wide in the installed system, from remotes which are
+//! added to it, so that derivatives may ship default applications without shell hooks.
+
+use crate::chroot::Chroot;
+use crate::FlatpakRemote;
+use std::io;
+
+/// Flatpak itself, which is installed when any flatpaks, or remotes, are.
+pub const FLATPAK_PACKAGES: &[&str] = &["flatpak"];
+
+/// Ensures that the names of the remotes, and the flatpaks, would not be mistaken for options.
+pub fn validate(remotes: &[FlatpakRemote], flatpaks: &[String]) -> io::Result<()> {
+    let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    if let Some(remote) = remotes.iter().find(|remote| !is_valid_name(&remote.name)) {
+        return invalid(format!("{} is not a valid flatpak remote name", remote.name));
+    }
+
+    if let Some(flatpak) = flatpaks.iter().find(|flatpak| !is_valid_ref(flatpak)) {
+        return invalid(format!("{} is not a valid flatpak ref", flatpak));
+    }
+
+    Ok(())
+}
+
+/// Adds each of the remotes, and installs each of the flatpaks from them, reporting the
+/// progress of the installation as a percentage.
+pub fn install<F: FnMut(i32)>(
+    chroot: &Chroot,
+    remotes: &[FlatpakRemote],
+    flatpaks: &[String],
+    mut progress: F,
+) -> io::Result<()> {
+    for remote in remotes {
+        info!("adding the flatpak remote {} from {}", remote.name, remote.url);
+        let args = &["remote-add", "--system", "--if-not-exists", &remote.name, &remote.url];
+        chroot.command("flatpak", args).run()?;
+    }
+
+    for (installed, flatpak) in flatpaks.iter().enumerate() {
+        info!("installing the flatpak {}", flatpak);
+        let args = &["install", "--system", "--noninteractive", "-y", flatpak];
+        chroot.command("flatpak", args).run()?;
+        progress(((installed + 1) * 100 / flatpaks.len()) as i32);
+    }
+
+    Ok(())
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Whether the flatpak is an application ID, such as `org.gnome.Calculator`, or a ref, such as
+/// `app/org.gnome.Calculator/x86_64/stable`.
+fn is_valid_ref(flatpak: &str) -> bool {
+    let id = match flatpak.split('/').collect::<Vec<_>>().as_slice() {
+        [id] => *id,
+        [kind, id, ..] if *kind == "app" || *kind == "runtime" => *id,
+        _ => return false,
+    };
+
+    id.split('.').count() >= 3 && id.split('.').all(is_valid_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refs() {
+        let flathub = FlatpakRemote {
+            name: "flathub".into(),
+            url:  "https://dl.flathub.org/repo/flathub.flatpakrepo".into(),
+        };
+
+        let flatpaks = vec![
+            "org.gnome.Calculator".to_owned(),
+            "app/com.valvesoftware.Steam/x86_64/stable".to_owned(),
+            "runtime/org.freedesktop.Platform.GL.default/x86_64/23.08".to_owned(),
+        ];
+
+        assert!(validate(&[flathub.clone()], &flatpaks).is_ok());
+        assert!(validate(&[flathub.clone()], &["--user".to_owned()]).is_err());
+        assert!(validate(&[flathub.clone()], &["calculator".to_owned()]).is_err());
+        assert!(validate(&[flathub], &["extension/org.gnome.Calculator".to_owned()]).is_err());
+
+        let remote = FlatpakRemote { name: "--user".into(), url: "https://example.com".into() };
+        assert!(validate(&[remote], &[]).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! Users whose homes are managed by systemd-homed, as LUKS-encrypted images which are unlocked
+//! with the user's password, and which may be carried to another system.
+//!
+//! systemd-homed does not run within the chroot, so the users cannot be created during the
+//! install. Their records, and their passwords as credentials which `systemd-creds` encrypts,
+//! are instead staged in the target, where a unit creates them with `homectl` on the first boot,
+//! before anyone can log in, and removes what was staged. What was staged is also removed from
+//! the target when the install fails.
+//!
+//! The credentials are encrypted with the host key of the target, and with its TPM if it has
+//! one, so that the passwords are never written to the disk in cleartext.
+
+use crate::chroot::Chroot;
+use crate::errors::IoContext;
+use crate::UserAccountCreate;
+use std::{
+    fs::{self, Permissions},
+    io,
+    os::unix::fs::{symlink, PermissionsExt},
+    path::Path,
+};
+
+/// The package of systemd-homed, which is installed when a user's home is managed by it.
+pub const HOMED_PACKAGES: &[&str] = &["systemd-homed"];
+
+/// Where the records and passwords of the users are staged until the first boot.
+const STAGING_DIR: &str = "/var/lib/distinst/homed";
+
+/// Encrypts the passwords of the users, and decrypts them on the first boot.
+const CREDS: &str = "/usr/bin/systemd-creds";
+
+const UNIT_DIR: &str = "etc/systemd/system";
+
+const UNIT_NAME: &str = "distinst-homed-users.service";
+
+const SCRIPT: &str = "/var/lib/distinst/create-homed-users";
+
+const UNIT: &str = "[Unit]
+Description=Create the systemd-homed Users of the Installation
+Requires=systemd-homed.service
+After=systemd-homed.service
+Before=systemd-user-sessions.service display-manager.service
+ConditionDirectoryNotEmpty=/var/lib/distinst/homed
+
+[Service]
+Type=oneshot
+ExecStart=/var/lib/distinst/create-homed-users
+
+[Install]
+WantedBy=multi-user.target
+";
+
+/// Creates each staged user, and removes their files whether or not they were created, so that
+/// no password is left on the disk. A user which could not be created is reported by the unit.
+const SCRIPT_CONTENTS: &str = r#"#!/bin/sh
+cd /var/lib/distinst/homed || exit 1
+trap 'rm -f /var/lib/distinst/homed/*.cred' EXIT
+status=0
+for identity in *.json; do
+    [ -e "$identity" ] || continue
+    user="${identity%.json}"
+    password="$(systemd-creds decrypt --name=password "$user.cred" -)"
+    decrypted=$?
+    rm -f "$user.cred"
+    if [ $decrypted -ne 0 ] || ! NEWPASSWORD="$password" \
+        homectl create --identity="$identity" --enforce-password-policy=no
+    then
+        echo "failed to create the user $user" >&2
+        status=1
+    fi
+    rm -f "$identity"
+done
+exit $status
+"#;
+
+/// Allows sshd to read the authorized keys of the users from their records.
+const SSHD_CONFIG: &str = "AuthorizedKeysCommand /usr/bin/userdbctl ssh-authorized-keys %u
+AuthorizedKeysCommandUser root
+";
+
+/// Stages the record of the user, and their encrypted password, within the system of the
+/// `chroot`, where only root may read them.
+pub fn stage(chroot: &Chroot, account: &UserAccountCreate, shell: &str) -> io::Result<()> {
+    info!("staging the systemd-homed user {} for the first boot", account.username);
+    if !chroot.path.join(&CREDS[1..]).exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is required in the target to stage the passwords of users", CREDS),
+        ));
+    }
+
+    let staging = chroot.path.join(&STAGING_DIR[1..]);
+    fs::create_dir_all(&staging)
+        .and_then(|_| fs::set_permissions(&staging, Permissions::from_mode(0o700)))
+        .with_context(|err| format!("failed to create {}: {}", staging.display(), err))?;
+
+    let identity_path = staging.join([&account.username, ".json"].concat());
+    let credential = [STAGING_DIR, "/", &account.username, ".cred"].concat();
+    let password = account.password.as_deref().unwrap_or_default();
+
+    // The credential is not bound to any PCRs, which would differ when the system boots.
+    let result = fs::write(&identity_path, "")
+        .and_then(|_| fs::set_permissions(&identity_path, Permissions::from_mode(0o600)))
+        .and_then(|_| fs::write(&identity_path, identity(account, shell)))
+        .and_then(|_| {
+            chroot
+                .command(CREDS, &["encrypt", "--name=password", "--tpm2-pcrs=", "-", &credential])
+                .stdin_input(password)
+                .run()
+        });
+
+    if let Err(why) = result {
+        let _ = fs::remove_file(&identity_path);
+        let _ = fs::remove_file(chroot.path.join(&credential[1..]));
+        return Err(io::Error::new(
+            why.kind(),
+            format!("failed to stage the user {}: {}", account.username, why),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Removes the staged users, and their passwords, from the system at `target` when dropped,
+/// unless the install has succeeded and they are to be created on the first boot.
+pub struct Staging<'a> {
+    target:    &'a Path,
+    succeeded: bool,
+}
+
+impl<'a> Staging<'a> {
+    pub fn new(target: &'a Path) -> Self { Staging { target, succeeded: false } }
+
+    /// Keeps the staged users for the first boot.
+    pub fn succeeded(mut self) { self.succeeded = true; }
+}
+
+impl<'a> Drop for Staging<'a> {
+    fn drop(&mut self) {
+        let staging = self.target.join(&STAGING_DIR[1..]);
+        if !self.succeeded && staging.exists() {
+            info!("removing the staged systemd-homed users from {}", staging.display());
+            if let Err(why) = fs::remove_dir_all(&staging) {
+                error!("failed to remove {}: {}", staging.display(), why);
+            }
+        }
+    }
+}
+
+/// Writes and enables the unit which creates the staged users on the first boot. When `ssh` is
+/// set, sshd is also configured to accept the keys in the records of the users.
+pub fn enable(target: &Path, ssh: bool) -> io::Result<()> {
+    let script = target.join(&SCRIPT[1..]);
+    fs::write(&script, SCRIPT_CONTENTS)
+        .and_then(|_| fs::set_permissions(&script, Permissions::from_mode(0o755)))
+        .with_context(|err| format!("failed to write {}: {}", script.display(), err))?;
+
+    let unit = target.join(UNIT_DIR).join(UNIT_NAME);
+    fs::write(&unit, UNIT)
+        .with_context(|err| format!("failed to write {}: {}", unit.display(), err))?;
+
+    let wants = target.join(UNIT_DIR).join("multi-user.target.wants");
+    let link = wants.join(UNIT_NAME);
+    let _ = fs::remove_file(&link);
+    fs::create_dir_all(&wants)
+        .and_then(|_| symlink(Path::new("/").join(UNIT_DIR).join(UNIT_NAME), &link))
+        .with_context(|err| format!("failed to enable {}: {}", UNIT_NAME, err))?;
+
+    if ssh {
+        let config = target.join("etc/ssh/sshd_config.d/50-distinst-userdb.conf");
+        fs::create_dir_all(config.parent().unwrap())
+            .and_then(|_| fs::write(&config, SSHD_CONFIG))
+            .with_context(|err| format!("failed to write {}: {}", config.display(), err))?;
+    }
+
+    Ok(())
+}
+
+/// The JSON user record of the account, which `homectl create` accepts as its identity.
+fn identity(account: &UserAccountCreate, shell: &str) -> String {
+    let list = |values: &[String]| {
+        let values = values.iter().map(|value| json_string(value)).collect::<Vec<_>>();
+        ["[", &values.join(","), "]"].concat()
+    };
+
+    let mut fields = vec![
+        ("userName", json_string(&account.username)),
+        ("disposition", json_string("regular")),
+        ("storage", json_string("luks")),
+        ("shell", json_string(shell)),
+        ("memberOf", list(&account.groups)),
+    ];
+
+    if let Some(ref name) = account.realname {
+        fields.push(("realName", json_string(name)));
+    }
+
+    if !account.ssh_authorized_keys.is_empty() {
+        fields.push(("sshAuthorizedKeys", list(&account.ssh_authorized_keys)));
+    }
+
+    let fields = fields
+        .iter()
+        .map(|(key, value)| [json_string(key).as_str(), ":", value].concat())
+        .collect::<Vec<_>>();
+
+    ["{", &fields.join(","), "}\n"].concat()
+}
+
+fn json_string(value: &str) -> String {
+    let mut output = String::with_capacity(value.len() + 2);
+    output.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+
+    output.push('"');
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UserBackend;
+    use tempdir::TempDir;
+
+    fn account() -> UserAccountCreate {
+        UserAccountCreate {
+            username:            "traveler".into(),
+            realname:            Some("Ada \"Traveler\" Lovelace".into()),
+            password:            Some("password".into()),
+            profile_icon:        None,
+            ssh_authorized_keys: Vec::new(),
+            shell:               None,
+            groups:              vec!["adm".into(), "sudo".into()],
+            system:              false,
+            sudo:                None,
+            backend:             UserBackend::Homed,
+        }
+    }
+
+    #[test]
+    fn staging() {
+        let target = TempDir::new("distinst-homed").unwrap();
+        let staging = target.path().join(&STAGING_DIR[1..]);
+
+        let credential = staging.join("traveler.cred");
+        let stage = || {
+            fs::create_dir_all(&staging).unwrap();
+            fs::write(&credential, "").unwrap();
+        };
+
+        let guard = Staging::new(target.path());
+        stage();
+        guard.succeeded();
+        assert!(credential.exists());
+
+        let guard = Staging::new(target.path());
+        stage();
+        drop(guard);
+        assert!(!staging.exists());
+    }
+
+    #[test]
+    fn identities() {
+        let mut account = account();
+        assert_eq!(
+            identity(&account, "/bin/bash"),
+            "{\"userName\":\"traveler\",\"disposition\":\"regular\",\"storage\":\"luks\",\
+             \"shell\":\"/bin/bash\",\"memberOf\":[\"adm\",\"sudo\"],\
+             \"realName\":\"Ada \\\"Traveler\\\" Lovelace\"}\n"
+        );
+
+        account.realname = None;
+        account.groups.clear();
+        account.ssh_authorized_keys.push("ssh-ed25519 AAAA traveler@laptop".into());
+        assert_eq!(
+            identity(&account, "/usr/bin/zsh"),
+            "{\"userName\":\"traveler\",\"disposition\":\"regular\",\"storage\":\"luks\",\
+             \"shell\":\"/usr/bin/zsh\",\"memberOf\":[],\
+             \"sshAuthorizedKeys\":[\"ssh-ed25519 AAAA traveler@laptop\"]}\n"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+//! The hosts file of the installed system, and the resolution of `.local` names with multicast
+//! DNS, which small networks without a DNS server rely upon to find each other.
+
+use crate::HostEntry;
+
+const HOSTS_HEADER: &str = "# See `man hosts` for details.
+#
+# By default, systemd-resolved or libnss-myhostname will resolve
+# localhost and the system hostname if they're not specified here.
+127.0.0.1\tlocalhost
+::1\t\tlocalhost
+";
+
+/// The avahi daemon, and the name service module which queries it.
+pub const MDNS_PACKAGES: &[&str] = &["avahi-daemon", "libnss-mdns"];
+
+/// Resolves `.local` names with mDNS, and no other module, if avahi is running.
+const MDNS_MODULES: &[&str] = &["mdns4_minimal", "[NOTFOUND=return]"];
+
+/// The contents of `/etc/hosts`, with each of the additional `entries`.
+pub fn hosts_file(entries: &[HostEntry]) -> String {
+    let mut hosts = String::from(HOSTS_HEADER);
+    if !entries.is_empty() {
+        hosts.push('\n');
+    }
+
+    for entry in entries {
+        hosts.push_str(&format!("{}\t{}\n", entry.address, entry.names.join(" ")));
+    }
+
+    hosts
+}
+
+/// Places the mDNS module before the `resolve` or `dns` module of the `hosts` database in
+/// `nsswitch.conf`, unless it is already there. The action which follows `resolve` would
+/// otherwise return before `.local` names reach the mDNS module.
+pub fn nsswitch_with_mdns(nsswitch: &str) -> String {
+    let mut found = false;
+    let mut output = String::with_capacity(nsswitch.len() + 32);
+
+    for line in nsswitch.lines() {
+        match line.strip_prefix("hosts:") {
+            Some(modules) if !found => {
+                found = true;
+                let columns = modules.len() - modules.trim_start().len();
+                let mut modules = modules.split_whitespace().collect::<Vec<_>>();
+                if !modules.contains(&MDNS_MODULES[0]) {
+                    let dns = modules.iter().position(|&m| matches!(m, "resolve" | "dns"));
+                    let position = dns.unwrap_or(modules.len());
+                    modules.splice(position..position, MDNS_MODULES.iter().cloned());
+                }
+
+                output.push_str("hosts:");
+                output.push_str(&" ".repeat(columns.max(1)));
+                output.push_str(&modules.join(" "));
+            }
+            _ => output.push_str(line),
+        }
+
+        output.push('\n');
+    }
+
+    if !found {
+        output.push_str("hosts: files ");
+        output.push_str(&MDNS_MODULES.join(" "));
+        output.push_str(" dns\n");
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn additional_hosts() {
+        let entries = vec![
+            HostEntry {
+                address: "192.168.1.10".parse().unwrap(),
+                names:   vec!["nas".into(), "nas.office.lan".into()],
+            },
+            HostEntry { address: "fd00::20".parse().unwrap(), names: vec!["printer".into()] },
+        ];
+
+        assert_eq!(hosts_file(&[]), HOSTS_HEADER);
+        assert_eq!(
+            hosts_file(&entries),
+            [HOSTS_HEADER, "\n192.168.1.10\tnas nas.office.lan\nfd00::20\tprinter\n"].concat()
+        );
+    }
+
+    #[test]
+    fn nsswitch() {
+        const NSSWITCH: &str = "passwd:         files systemd
+hosts:          files resolve [!UNAVAIL=return] dns myhostname
+networks:       files
+";
+
+        let expected = "passwd:         files systemd
+hosts:          files mdns4_minimal [NOTFOUND=return] resolve [!UNAVAIL=return] dns myhostname
+networks:       files
+";
+
+        assert_eq!(nsswitch_with_mdns(NSSWITCH), expected);
+        assert_eq!(nsswitch_with_mdns(expected), expected);
+        assert_eq!(
+            nsswitch_with_mdns("hosts: files\n"),
+            "hosts: files mdns4_minimal [NOTFOUND=return]\n"
+        );
+        assert_eq!(
+            nsswitch_with_mdns("passwd: files\n"),
+            "passwd: files\nhosts: files mdns4_minimal [NOTFOUND=return] dns\n"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! The keymap of the initramfs, which is otherwise the US layout, so that the passphrases of
+//! encrypted volumes can be typed with the keyboard layout of the installed system.
+//!
+//! initramfs-tools takes the keymap that console-setup saves from `/etc/default/keyboard`, and
+//! dracut takes it from `/etc/vconsole.conf`.
+
+use crate::errors::IoContext;
+use crate::Config;
+use std::{fs, io, path::Path};
+
+const INITRAMFS_CONFIG: &str = "etc/initramfs-tools/conf.d/distinst-keymap.conf";
+const DRACUT_CONFIG: &str = "etc/dracut.conf.d/distinst-keymap.conf";
+const VCONSOLE_CONFIG: &str = "etc/vconsole.conf";
+
+/// The generators of the initramfs that may be installed in the target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Generator {
+    InitramfsTools,
+    Dracut,
+}
+
+impl Generator {
+    /// The generator which is installed in the system at `target`, if any.
+    pub fn detect(target: &Path) -> Option<Generator> {
+        if target.join("usr/sbin/update-initramfs").exists() {
+            Some(Generator::InitramfsTools)
+        } else if target.join("usr/bin/dracut").exists() {
+            Some(Generator::Dracut)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether the crypttab has volumes which must be unlocked while booting.
+pub fn unlocks_volumes(crypttab: &str) -> bool {
+    crypttab.lines().map(str::trim).any(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+/// Configures the generator to include the keymap of the `config` in the initramfs, which the
+/// bootloader step generates afterwards.
+pub fn configure(target: &Path, generator: Generator, config: &Config) -> io::Result<()> {
+    info!("including the {} keymap in the initramfs", config.keyboard_layout);
+    let (path, contents) = match generator {
+        Generator::InitramfsTools => (target.join(INITRAMFS_CONFIG), "KEYMAP=y\n"),
+        Generator::Dracut => {
+            let vconsole = target.join(VCONSOLE_CONFIG);
+            fs::write(&vconsole, vconsole_conf(config))
+                .with_context(|err| format!("failed to write {}: {}", vconsole.display(), err))?;
+
+            (target.join(DRACUT_CONFIG), "add_dracutmodules+=\" i18n \"\n")
+        }
+    };
+
+    fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| fs::write(&path, contents))
+        .with_context(|err| format!("failed to write {}: {}", path.display(), err))
+}
+
+/// The console keymap, and the X11 layout, of the `config`.
+fn vconsole_conf(config: &Config) -> String {
+    let variant = config.keyboard_variant.as_deref().filter(|variant| !variant.is_empty());
+    let mut output = match variant {
+        Some(variant) => format!("KEYMAP={}-{}\n", config.keyboard_layout, variant),
+        None => format!("KEYMAP={}\n", config.keyboard_layout),
+    };
+
+    output.push_str(&format!("XKBLAYOUT={}\n", config.keyboard_layout));
+    if let Some(model) = config.keyboard_model.as_deref() {
+        output.push_str(&format!("XKBMODEL={}\n", model));
+    }
+
+    if let Some(variant) = variant {
+        output.push_str(&format!("XKBVARIANT={}\n", variant));
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crypttab() {
+        assert!(!unlocks_volumes(""));
+        assert!(!unlocks_volumes("# <target name> <source device> <key file> <options>\n\n"));
+        assert!(unlocks_volumes("cryptdata UUID=1234 none luks\n"));
+    }
+}
@@ -0,0 +1,98 @@
+//! The locales of the installed system. Besides the locale of its language, locales of other
+//! languages may be generated, and categories such as `LC_TIME` may take their formats from
+//! the locale of another region, such as English messages with German dates.
+
+/// The categories which may be set to a locale other than that of `LANG`.
+pub const LOCALE_CATEGORIES: &[&str] = &[
+    "LC_ADDRESS",
+    "LC_COLLATE",
+    "LC_CTYPE",
+    "LC_IDENTIFICATION",
+    "LC_MEASUREMENT",
+    "LC_MESSAGES",
+    "LC_MONETARY",
+    "LC_NAME",
+    "LC_NUMERIC",
+    "LC_PAPER",
+    "LC_TELEPHONE",
+    "LC_TIME",
+];
+
+/// Every locale which must be generated, without duplicates, beginning with `lang`.
+pub fn locales<'a>(
+    lang: &'a str,
+    additional: &'a [String],
+    overrides: &'a [(String, String)],
+) -> Vec<&'a str> {
+    let mut locales = vec![lang];
+    let others = additional.iter().chain(overrides.iter().map(|(_, locale)| locale));
+    for locale in others.map(String::as_str) {
+        if !locales.contains(&locale) {
+            locales.push(locale);
+        }
+    }
+
+    locales
+}
+
+/// The variables of `/etc/default/locale`, as given to `update-locale`.
+///
+/// When additional locales are given, `LANGUAGE` lists their languages after that of `lang`,
+/// so that messages which have not been translated to it fall back to the next language.
+pub fn settings(lang: &str, additional: &[String], overrides: &[(String, String)]) -> Vec<String> {
+    let mut settings = vec![["LANG=", lang].concat()];
+    if !additional.is_empty() {
+        let mut languages = vec![language(lang)];
+        for language in additional.iter().map(|locale| language(locale)) {
+            if !languages.contains(&language) {
+                languages.push(language);
+            }
+        }
+
+        settings.push(["LANGUAGE=", &languages.join(":")].concat());
+    }
+
+    for (category, locale) in overrides {
+        settings.push(format!("{}={}", category, locale));
+    }
+
+    settings
+}
+
+/// The locale without its encoding or modifier, as `LANGUAGE` expects.
+fn language(locale: &str) -> &str {
+    locale.split(|c| c == '.' || c == '@').next().unwrap_or(locale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_locale() {
+        assert_eq!(locales("en_US.UTF-8", &[], &[]), vec!["en_US.UTF-8"]);
+        assert_eq!(settings("en_US.UTF-8", &[], &[]), vec!["LANG=en_US.UTF-8"]);
+    }
+
+    #[test]
+    fn formats_of_another_region() {
+        let additional = vec!["de_DE.UTF-8".to_owned(), "en_US.UTF-8".to_owned()];
+        let overrides = vec![
+            ("LC_TIME".to_owned(), "de_DE.UTF-8".to_owned()),
+            ("LC_MEASUREMENT".to_owned(), "en_GB.UTF-8".to_owned()),
+        ];
+
+        assert_eq!(locales("en_US.UTF-8", &additional, &overrides), vec![
+            "en_US.UTF-8",
+            "de_DE.UTF-8",
+            "en_GB.UTF-8"
+        ]);
+
+        assert_eq!(settings("en_US.UTF-8", &additional, &overrides), vec![
+            "LANG=en_US.UTF-8",
+            "LANGUAGE=en_US:de_DE",
+            "LC_TIME=de_DE.UTF-8",
+            "LC_MEASUREMENT=en_GB.UTF-8",
+        ]);
+    }
+}
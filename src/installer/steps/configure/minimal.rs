@@ -0,0 +1,182 @@
+//! Minimal installations, which remove a curated manifest of applications, such as the office
+//! suite and games, from the installed system.
+//!
+//! Metapackages which depend on the applications must be removed along with them. As the rest
+//! of the desktop would then be autoremoved, the dependencies of those metapackages are marked
+//! as manually installed before the applications are removed.
+
+use crate::chroot::Chroot;
+use apt_cli_wrappers::AptUpgradeEvent;
+use std::{cell::RefCell, collections::HashSet, io, process::Stdio};
+
+/// Removes each of the `packages` which are installed, with the packages that they alone
+/// depended upon, reporting the progress of the removal as a percentage.
+pub fn remove<F: FnMut(i32)>(
+    chroot: &Chroot,
+    packages: &[&str],
+    mut progress: F,
+) -> io::Result<()> {
+    let output = chroot
+        .command("dpkg-query", &["-W", "-f=${db:Status-Abbrev} ${Package}\\n"])
+        .run_with_stdout()?;
+    let installed = installed_packages(&output);
+
+    let packages: Vec<&str> =
+        packages.iter().cloned().filter(|package| installed.contains(package)).collect();
+
+    if packages.is_empty() {
+        info!("none of the packages of the minimal manifest are installed");
+        return Ok(());
+    }
+
+    info!("removing {} packages for a minimal installation", packages.len());
+
+    let simulation =
+        chroot.command("apt-get", args(&["-s", "purge"], &packages)).run_with_stdout()?;
+    let metapackages: Vec<&str> = removed_packages(&simulation)
+        .filter(|package| !packages.contains(package))
+        .collect();
+
+    progress(10);
+
+    if !metapackages.is_empty() {
+        info!("removing metapackages which depend on the minimal manifest: {:?}", metapackages);
+        let options = &[
+            "depends",
+            "--installed",
+            "--no-suggests",
+            "--no-conflicts",
+            "--no-breaks",
+            "--no-replaces",
+            "--no-enhances",
+        ];
+
+        let depends = chroot.command("apt-cache", args(options, &metapackages)).run_with_stdout()?;
+        let retain: Vec<&str> = dependencies(&depends)
+            .filter(|package| installed.contains(package))
+            .filter(|package| !packages.contains(package) && !metapackages.contains(package))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        if !retain.is_empty() {
+            chroot.command("apt-mark", args(&["manual"], &retain)).run()?;
+        }
+    }
+
+    progress(20);
+
+    apt_get(chroot, &args(&["purge", "-y", "--show-progress"], &packages), |percent| {
+        progress(20 + percent * 60 / 100)
+    })?;
+
+    apt_get(chroot, &["autoremove", "-y", "--purge", "--show-progress"], |percent| {
+        progress(80 + percent * 20 / 100)
+    })
+}
+
+fn args<'a>(options: &[&'a str], packages: &[&'a str]) -> Vec<&'a str> {
+    options.iter().chain(packages).cloned().collect()
+}
+
+/// Runs apt-get, passing the percentage of its progress to the callback.
+fn apt_get<F: FnMut(i32)>(chroot: &Chroot, args: &[&str], progress: F) -> io::Result<()> {
+    let progress = RefCell::new(progress);
+    chroot
+        .command("apt-get", args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .run_with_callbacks(
+            |info| {
+                info!("apt-info: '{}'", info);
+                if let Ok(AptUpgradeEvent::Progress { percent }) = info.parse::<AptUpgradeEvent>() {
+                    (progress.borrow_mut())(i32::from(percent));
+                }
+            },
+            |error| warn!("apt-err: '{}'", error),
+        )
+}
+
+/// Packages listed by `dpkg-query -W -f='${db:Status-Abbrev} ${Package}\n'` as installed.
+fn installed_packages(output: &str) -> HashSet<&str> {
+    output
+        .lines()
+        .filter(|line| line.starts_with("ii"))
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .collect()
+}
+
+/// Packages that an `apt-get -s` simulation would remove.
+fn removed_packages(output: &str) -> impl Iterator<Item = &str> {
+    output
+        .lines()
+        .filter(|line| line.starts_with("Purg ") || line.starts_with("Remv "))
+        .filter_map(|line| line.split_whitespace().nth(1))
+}
+
+/// The dependencies and recommendations listed by `apt-cache depends`, excluding virtual
+/// packages.
+fn dependencies(output: &str) -> impl Iterator<Item = &str> {
+    output.lines().filter_map(|line| {
+        let line = line.trim_start().trim_start_matches('|');
+        let relation = ["Depends:", "PreDepends:", "Recommends:"]
+            .iter()
+            .find(|relation| line.starts_with(*relation))?;
+
+        let package = line[relation.len()..].trim();
+        if package.starts_with('<') {
+            None
+        } else {
+            Some(package)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn installed() {
+        let output = "ii  libreoffice-core\nrc  aisleriot\nii  gnome-mahjongg\nun  thunderbird\n";
+        let installed = installed_packages(output);
+        assert!(installed.contains("libreoffice-core"));
+        assert!(installed.contains("gnome-mahjongg"));
+        assert!(!installed.contains("aisleriot"));
+        assert!(!installed.contains("thunderbird"));
+    }
+
+    #[test]
+    fn simulated_removals() {
+        const SIMULATION: &str = r#"Reading package lists...
+Building dependency tree...
+The following packages will be REMOVED:
+  gnome-mahjongg* pop-desktop*
+Purg gnome-mahjongg [1:3.38.3-1]
+Purg pop-desktop [1.6.1~1679072542~22.04~7da2a34]
+"#;
+
+        assert_eq!(
+            removed_packages(SIMULATION).collect::<Vec<_>>(),
+            vec!["gnome-mahjongg", "pop-desktop"]
+        );
+    }
+
+    #[test]
+    fn metapackage_dependencies() {
+        const DEPENDS: &str = r#"pop-desktop
+  Depends: gnome-shell
+ |Depends: gdm3
+  Depends: lightdm
+  PreDepends: dpkg
+  Depends: <default-display-manager>
+    gdm3
+  Recommends: gnome-mahjongg
+"#;
+
+        assert_eq!(
+            dependencies(DEPENDS).collect::<Vec<_>>(),
+            vec!["gnome-shell", "gdm3", "lightdm", "dpkg", "gnome-mahjongg"]
+        );
+    }
+}
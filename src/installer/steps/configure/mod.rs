@@ -1,16 +1,42 @@
 use crate::bootloader::Bootloader;
 mod chroot_conf;
-use self::chroot_conf::ChrootConfigurator;
-use super::{mount_cdrom, mount_efivars};
+mod cleanup;
+mod clevis;
+mod crypt_volumes;
+mod dconf;
+mod encrypted_boot;
+mod first_boot;
+mod flatpak;
+mod homed;
+mod hosts;
+mod keymap;
+mod locale;
+mod minimal;
+mod offline_repo;
+mod module_signing;
+mod network_disks;
+mod read_only;
+mod ssh;
+mod users;
+pub(crate) use self::{chroot_conf::ChrootConfigurator, clevis::fetch_advertisement};
+pub use self::{
+    cleanup::CleanupProfile, locale::LOCALE_CATEGORIES, module_signing::secure_boot_enabled,
+};
+use super::{
+    mount_bind_if_exists, mount_cdrom, mount_efivars, verify_mounted, EspPolicy, Propagation,
+};
 use crate::installer::{conf::RecoveryEnv, steps::normalize_os_release_name};
 use crate::chroot::Chroot;
 use crate::distribution;
 use crate::errors::*;
+use crate::esp::{boot_files_size, EspUsage};
 use crate::external::remount_rw;
 use crate::hardware_support;
 use crate::installer::traits::InstallerDiskOps;
 use libc;
+use crate::kernels;
 use crate::misc;
+use crate::systemd_boot;
 use os_release::OsRelease;
 use partition_identity::PartitionID;
 use rayon;
@@ -23,9 +49,8 @@ use std::{
 use tempdir::TempDir;
 use crate::timezones::Region;
 use crate::Config;
-use crate::UserAccountCreate;
-use crate::INSTALL_HARDWARE_SUPPORT;
-use crate::RUN_UBUNTU_DRIVERS;
+use crate::{HookOutput, TrimPolicy, UserAccountCreate, UserBackend};
+use crate::InstallFlags;
 
 /// Self-explanatory -- the fstab file will be generated with this header.
 const FSTAB_HEADER: &[u8] = b"# /etc/fstab: static file system information.
@@ -71,8 +96,12 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
     config: &Config,
     iso_os_release: &OsRelease,
     region: Option<&Region>,
-    user: Option<&UserAccountCreate>,
+    rtc_in_localtime: bool,
+    users: &[UserAccountCreate],
+    mok_password: Option<&str>,
     remove_pkgs: &[S],
+    restore_pkgs: &[String],
+    hook_output: &mut dyn FnMut(&HookOutput),
     mut callback: F,
 ) -> io::Result<()> {
     let mount_dir = mount_dir.as_ref().canonicalize().unwrap();
@@ -81,11 +110,69 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
     let configure_dir = TempDir::new_in(&tpath, "distinst")
         .with_context(|err| format!("creating tempdir at {:?}: {}", tpath, err))?;
 
+    let packages = distribution::backend(iso_os_release);
+    info!("managing packages with {}", packages.name());
+
     let install_pkgs = &mut cascade! {
         Vec::with_capacity(32);
-        ..extend_from_slice(distribution::debian::get_bootloader_packages(&iso_os_release)?);
+        ..extend_from_slice(packages.bootloader_packages(&iso_os_release)?);
     };
 
+    if config.flags.contains(InstallFlags::ENABLE_MDNS) {
+        install_pkgs.extend_from_slice(hosts::MDNS_PACKAGES);
+    }
+
+    if users.iter().any(|user| !user.ssh_authorized_keys.is_empty()) {
+        install_pkgs.extend_from_slice(ssh::SSH_PACKAGES);
+    }
+
+    let has_flatpaks = !config.flatpak_remotes.is_empty() || !config.flatpaks.is_empty();
+    if has_flatpaks {
+        flatpak::validate(&config.flatpak_remotes, &config.flatpaks)?;
+        install_pkgs.extend_from_slice(flatpak::FLATPAK_PACKAGES);
+    }
+
+    let homed_users = users.iter().filter(|user| user.backend == UserBackend::Homed);
+    let homed_ssh = homed_users.clone().any(|user| !user.ssh_authorized_keys.is_empty());
+    let has_homed_users = homed_users.count() != 0;
+    if has_homed_users {
+        // The staged users would be discarded with the overlay of /var before they are created.
+        if config.flags.contains(InstallFlags::READ_ONLY_ROOT) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "systemd-homed users cannot be created on a read-only root",
+            ));
+        }
+
+        install_pkgs.extend_from_slice(homed::HOMED_PACKAGES);
+    }
+
+    let network_disks = disks.network_disks();
+    install_pkgs.extend(network_disks::packages(&network_disks));
+
+    let tang_volumes = match config.tang_binding {
+        Some(ref tang) => {
+            let volumes = disks.get_encrypted_volumes();
+            if volumes.is_empty() {
+                warn!("no encrypted volumes will be bound to the Tang server at {}", tang.url);
+            } else {
+                install_pkgs.extend_from_slice(clevis::CLEVIS_PACKAGES);
+            }
+
+            volumes
+        }
+        None => Vec::new(),
+    };
+
+    let kernel = match config.kernel_variant {
+        Some(ref variant) => Some(kernels::find(kernels::CASPER, variant)?),
+        None => None,
+    };
+
+    let kernel_meta = kernel
+        .as_ref()
+        .and_then(|kernel| kernels::meta_package(&kernel.variant, iso_os_release));
+
     callback(5);
 
     let lvm_autodetection = || {
@@ -98,7 +185,16 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
     };
 
     let generate_fstabs = || {
-        let (crypttab, fstab) = disks.generate_fstabs();
+        let (mut crypttab, mut fstab) = disks.generate_fstabs(config.trim_policy);
+        crypt_volumes::append_unconfigured(&mut crypttab, &mount_dir)?;
+        if config.flags.contains(InstallFlags::READ_ONLY_ROOT) {
+            let contents = fstab.to_string_lossy().into_owned();
+            if !config.first_boot_services.is_empty() {
+                first_boot::validate_read_only(&contents)?;
+            }
+
+            fstab = read_only::configure(&mount_dir, &contents)?.into();
+        }
 
         let (a, b) = rayon::join(
             || {
@@ -125,7 +221,7 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
             s.spawn(|_| b = lvm_autodetection());
             s.spawn(|_| c = generate_fstabs());
             s.spawn(|_| {
-                if config.flags & INSTALL_HARDWARE_SUPPORT != 0 {
+                if config.flags.contains(InstallFlags::INSTALL_HARDWARE_SUPPORT) {
                     hardware_support::append_packages(install_pkgs, &iso_os_release);
                 }
 
@@ -142,6 +238,16 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
         configure_graphics?
     };
 
+    if let Some((device, enc)) = disks.get_encrypted_boot() {
+        if let Some(ref password) = enc.password {
+            encrypted_boot::configure(&mount_dir, device, password)
+                .with_context(|why| format!("error configuring the encrypted /boot: {}", why))?;
+        }
+    }
+
+    // The passwords of systemd-homed users must not be left in the target of a failed install.
+    let homed_staging = homed::Staging::new(&mount_dir);
+
     {
         info!("chrooting into target on {}", mount_dir.display());
 
@@ -187,11 +293,29 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
 
         callback(30);
 
+        // Kernels and loader entries are written to the ESP, which must have been mounted.
+        if Bootloader::detect() == Bootloader::Efi {
+            let esp = disks.get_block_info_of("/boot/efi")?;
+            if let Some(device) = esp.uid.get_device_path() {
+                verify_mounted(&mount_dir.join("boot/efi"), &device)?;
+            }
+
+            // Boot files are only removed from an ESP which the policy permits to be cleaned.
+            let esp = mount_dir.join("boot/efi");
+            reclaim_esp_space(&esp, &root_uuid.id, config.esp_policy);
+
+            // The loader entries of other systems are only modified when the policy permits.
+            if config.esp_policy.may_prune() {
+                repair_loader_entries(&esp, &root_uuid.id);
+            }
+        }
+
+        let support = disks.get_support_flags();
         let (retain, lang_output) = rayon::join(
             // Get packages required by this disk configuration.
-            || distribution::debian::get_required_packages(disks, iso_os_release),
-            // Attempt to run the check-language-support external command.
-            || distribution::debian::check_language_support(&config.lang, &chroot),
+            || packages.required_packages(support, iso_os_release),
+            // Find the language packs of the locales, such as with check-language-support.
+            || language_support(packages, config, &chroot),
         );
 
         let lang_output = lang_output?;
@@ -205,9 +329,9 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
             Some(output) => {
                 // Packages in the output are delimited with spaces.
                 // This is collected as a Cow<'_, str>.
-                let packages = output.split_whitespace().collect::<Vec<_>>();
+                let language_packs = output.split_whitespace().collect::<Vec<_>>();
 
-                match distribution::debian::get_dependencies_from_list(&packages) {
+                match packages.dependencies(&language_packs) {
                     Some(dependencies) => {
                         lang_output_ = dependencies;
                         &lang_output_[..]
@@ -223,6 +347,11 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
         // the removal list.
         install_pkgs.extend_from_slice(&retain);
 
+        // The meta-package of the kernel variant installs it with its modules, and keeps it up
+        // to date. Its initramfs is generated by the bootloader step.
+        let install_pkgs =
+            install_pkgs.iter().copied().chain(kernel_meta.as_deref()).collect::<Vec<&str>>();
+
         // Filter the discovered language packs and installed packages from the remove list.
         let mut remove = remove_pkgs
             .iter()
@@ -232,7 +361,7 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
 
         // Remove incompatible bootloader packages
         match Bootloader::detect() {
-            Bootloader::Bios => {
+            Bootloader::Bios | Bootloader::UBoot => {
                 if iso_os_release.name == "Pop!_OS" {
                     remove.push("kernelstub");
                 }
@@ -248,10 +377,15 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
         chroot.initramfs_disable()?;
 
         let hostname = chroot.hostname(&config.hostname);
-        let hosts = chroot.hosts(&config.hostname);
-        let machine_id = chroot.generate_machine_id();
+        let hosts = chroot.hosts(&config.hosts);
+        let machine_id = disks.label_policy().map(|policy| policy.machine_id());
+        let machine_id = chroot.generate_machine_id(machine_id.as_deref());
         let netresolv = chroot.netresolve();
-        let locale = chroot.generate_locale(&config.lang);
+        let locale = chroot.generate_locale(
+            &config.lang,
+            &config.additional_locales,
+            &config.locale_overrides,
+        );
         let kernel_copy = chroot.kernel_copy();
 
         let timezone = if let Some(tz) = region {
@@ -260,22 +394,58 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
             Ok(())
         };
 
-        let useradd = if let Some(ref user) = user {
-            chroot.create_user(
-                &user.username,
-                user.password.as_deref(),
-                user.realname.as_deref(),
-                user.profile_icon.as_deref(),
-            )
-        } else {
-            Ok(())
+        let adjtime = chroot.adjtime(rtc_in_localtime);
+
+        let useradd = users::validate(users)
+            .and_then(|_| chroot.create_groups(users))
+            .and_then(|_| {
+                users.iter().try_for_each(|user| {
+                    chroot.create_user(user)?;
+                    if let Some(access) = user.sudo {
+                        chroot.sudoers(&user.username, access)?;
+                    }
+
+                    // The keys of systemd-homed users are kept in their records.
+                    if user.ssh_authorized_keys.is_empty() || user.backend == UserBackend::Homed {
+                        return Ok(());
+                    }
+
+                    chroot.authorized_keys(&user.username, &user.ssh_authorized_keys)
+                })
+            })
+            .and_then(|_| users::own_foreign_mounts(&mount_dir, users));
+
+        // The offline repository is only mounted while packages are installed from it.
+        let offline_repo_mount = match config.offline_repository {
+            Some(ref repo) => {
+                let target = mount_dir.join(&offline_repo::MOUNT_POINT[1..]);
+                offline_repo::validate(&repo.path)
+                    .and_then(|_| mount_bind_if_exists(&repo.path, &target, Propagation::Private))
+                    .with_context(|why| format!("error mounting the offline repository: {}", why))?
+                    .map(|mount| (mount, target))
+            }
+            None => None,
         };
 
         let apt_install = chroot
-            .cdrom_add()
-            .and_then(|_| chroot.apt_install(&install_pkgs))
-            .and_then(|_| chroot.install_drivers(config.flags & RUN_UBUNTU_DRIVERS != 0))
-            .and_then(|_| chroot.cdrom_disable());
+            .cdrom_add(packages)
+            .and_then(|_| match config.offline_repository {
+                Some(ref repo) => chroot.offline_repo_add(packages, repo),
+                None => Ok(()),
+            })
+            .and_then(|_| chroot.install_packages(packages, &install_pkgs))
+            .and_then(|_| {
+                let drivers = config.flags.contains(InstallFlags::RUN_UBUNTU_DRIVERS);
+                chroot.install_drivers(packages, drivers)
+            })
+            .and_then(|_| chroot.cdrom_disable(packages));
+
+        if let Some((mount, target)) = offline_repo_mount {
+            let removed = chroot.offline_repo_remove(packages);
+            drop(mount);
+            let _ = fs::remove_dir(&target);
+            removed.with_context(|why| format!("error removing the offline repository: {}", why))?;
+        }
 
         map_errors! {
             hostname => "error writing hostname";
@@ -286,12 +456,56 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
             apt_install => "error installing packages";
             kernel_copy => "error copying kernel from casper to chroot";
             timezone => "error setting timezone";
-            useradd => "error creating user account"
+            adjtime => "error writing /etc/adjtime";
+            useradd => "error creating user accounts"
+        }
+
+        if config.flags.contains(InstallFlags::ENABLE_MDNS) {
+            chroot.mdns().with_context(|why| format!("error enabling mDNS: {}", why))?;
+        }
+
+        if config.trim_policy == TrimPolicy::Periodic && disks.is_trimmable() {
+            chroot.fstrim_timer();
+        }
+
+        if !network_disks.is_empty() {
+            chroot.network_disks(&network_disks).with_context(|why| {
+                format!("error attaching network disks from the initramfs: {}", why)
+            })?;
+        }
+
+        if let Some(ref tang) = config.tang_binding {
+            chroot.clevis_bind(tang, &tang_volumes).with_context(|why| {
+                format!("error binding encrypted volumes to the Tang server: {}", why)
+            })?;
+        }
+
+        if has_homed_users {
+            chroot.homed(homed_ssh).with_context(|why| {
+                format!("error enabling the creation of systemd-homed users: {}", why)
+            })?;
+        }
+
+        // Modules which are not signed will not be loaded while Secure Boot is enabled, but
+        // the install may continue without them, as with the drivers themselves.
+        if let Some(password) = mok_password {
+            if let Err(why) = chroot.sign_modules(&config.hostname, password) {
+                warn!("unable to sign DKMS modules for Secure Boot: {}", why);
+            }
+        }
+
+        if has_flatpaks {
+            callback(60);
+            chroot
+                .flatpaks(&config.flatpak_remotes, &config.flatpaks, |percent| {
+                    callback(60 + percent * 10 / 100)
+                })
+                .with_context(|why| format!("error installing flatpaks: {}", why))?;
         }
 
         callback(70);
 
-        let apt_remove = chroot.apt_remove(&remove);
+        let apt_remove = chroot.remove_packages(packages, &remove);
         let recovery = chroot.recovery(
             config,
             &normalize_os_release_name(&iso_os_release.name),
@@ -304,9 +518,38 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
             recovery => "error creating recovery partition"
         }
 
+        if let Some(ref manifest) = config.minimal_remove {
+            let manifest = fs::read_to_string(manifest)
+                .with_context(|why| format!("error reading {}: {}", manifest, why))?;
+
+            // Language packs and installed packages are retained, as with the remove list.
+            let minimal = manifest
+                .lines()
+                .map(str::trim)
+                .filter(|pkg| !pkg.is_empty())
+                .filter(|pkg| !lang_packs.iter().any(|x| pkg == x) && !install_pkgs.contains(&pkg))
+                .collect::<Vec<&str>>();
+
+            chroot
+                .remove_minimal(packages, &minimal, |percent| callback(70 + percent * 5 / 100))
+                .with_context(|why| format!("error removing minimal packages: {}", why))?;
+        }
+
         callback(75);
 
-        chroot.bootloader().with_context(|why| format!("error installing bootloader: {}", why))?;
+        // Packages of a refreshed system are reinstalled on a best-effort basis.
+        if !restore_pkgs.is_empty() {
+            if let Err(why) = chroot.restore_packages(packages, restore_pkgs) {
+                warn!("failed to reinstall the packages of the previous system: {}", why);
+            }
+        }
+
+        // U-Boot boots the kernel from a config which is written by the bootloader step.
+        if Bootloader::detect() != Bootloader::UBoot {
+            chroot
+                .bootloader(root_entry.subvol)
+                .with_context(|why| format!("error installing bootloader: {}", why))?;
+        }
 
         callback(80);
 
@@ -317,10 +560,55 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
         chroot
             .keyboard_layout(config)
             .with_context(|why| format!("error setting keyboard layout: {}", why))?;
+
+        chroot
+            .write_files(&config.files, configure_dir.path())
+            .with_context(|why| format!("error writing files to the target: {}", why))?;
+
+        for service in &config.first_boot_services {
+            chroot.first_boot_service(service).with_context(|why| {
+                format!("error enabling {} for the first boot: {}", service.name, why)
+            })?;
+        }
+
+        if !config.dconf.is_empty() {
+            chroot
+                .dconf(&config.dconf)
+                .with_context(|why| format!("error applying dconf overrides: {}", why))?;
+        }
         callback(85);
 
         chroot.initramfs_reenable()?;
 
+        let crypttab = fs::read_to_string(mount_dir.join("etc/crypttab")).unwrap_or_default();
+        if !config.flags.contains(InstallFlags::SKIP_INITRAMFS_KEYMAP)
+            && keymap::unlocks_volumes(&crypttab)
+        {
+            chroot.initramfs_keymap(config).with_context(|why| {
+                format!("error applying the keymap to the initramfs: {}", why)
+            })?;
+        }
+
+        if !config.post_install_scripts.is_empty() {
+            let username = users.iter().find(|user| !user.system).map_or("", |user| &user.username);
+            let esp = if Bootloader::detect() == Bootloader::Efi { "/boot/efi" } else { "" };
+            let env = [
+                ("DISTINST_ROOT_UUID", root_uuid.id.as_str()),
+                ("DISTINST_ESP", esp),
+                ("DISTINST_USERNAME", username),
+                ("DISTINST_HOSTNAME", config.hostname.as_str()),
+            ];
+
+            chroot
+                .post_install_scripts(
+                    &config.post_install_scripts,
+                    configure_dir.path(),
+                    &env,
+                    hook_output,
+                )
+                .with_context(|why| format!("error running post-install script: {}", why))?;
+        }
+
         callback(90);
 
         // Sync to the disk before unmounting
@@ -338,12 +626,87 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
         callback(95);
     }
 
+    homed_staging.succeeded();
     configure_dir.close()?;
+
+    // The system is already installed, so what was left behind is not worth failing over.
+    if let Err(why) = cleanup::clean(&mount_dir, config.cleanup) {
+        warn!("failed to clean up the installed system: {}", why);
+    }
+
     callback(100);
 
     Ok(())
 }
 
+/// The language packs of the locale of the system, and of each of its additional locales, as
+/// a space-delimited list. `None` if the distribution has no means of finding them.
+fn language_support(
+    packages: &dyn distribution::PackageBackend,
+    config: &Config,
+    chroot: &Chroot,
+) -> io::Result<Option<String>> {
+    let mut output: Option<String> = None;
+    for lang in Some(&config.lang).into_iter().chain(&config.additional_locales) {
+        if let Some(packs) = packages.language_support(lang, chroot)? {
+            let output = output.get_or_insert_with(String::new);
+            for pack in packs.split_whitespace() {
+                if !output.split_whitespace().any(|existing| existing == pack) {
+                    output.push_str(pack);
+                    output.push(' ');
+                }
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+/// Removes superseded boot files from the ESP if it lacks the room for the new kernels and
+/// initrds, which will be copied into it by kernelstub and the recovery partition. The boot
+/// files of other systems are only removed when the `policy` permits.
+fn reclaim_esp_space(esp: &Path, root_uuid: &str, policy: EspPolicy) {
+    if !esp.join("EFI").exists() || !policy.may_prune_previous() {
+        return;
+    }
+
+    let result = EspUsage::new(esp).and_then(|mut usage| {
+        let freed = if policy.may_prune() {
+            usage.prune(root_uuid, boot_files_size())?
+        } else {
+            usage.prune_previous(root_uuid, boot_files_size())?
+        };
+
+        Ok((freed, usage.free))
+    });
+
+    match result {
+        Ok((0, _)) => (),
+        Ok((freed, free)) => info!("freed {} bytes on the ESP; {} bytes are free", freed, free),
+        Err(why) => warn!("failed to reclaim space on the ESP: {}", why),
+    }
+}
+
+/// Removes the loader entries which boot files that are no longer on the ESP, and points the
+/// `root=` of the entry for the root with `root_uuid` at its new PARTUUID.
+fn repair_loader_entries(esp: &Path, root_uuid: &str) {
+    let result = systemd_boot::audit(esp, root_uuid).and_then(|mut audit| {
+        let fixed = audit.fix()?;
+        let removed = audit.prune()?;
+        for entry in audit.flagged() {
+            warn!("the loader entry {} has issues: {:?}", entry.path.display(), entry.issues);
+        }
+
+        Ok(fixed.len() + removed.len())
+    });
+
+    match result {
+        Ok(0) => (),
+        Ok(repaired) => info!("repaired {} loader entries on the ESP", repaired),
+        Err(why) => warn!("failed to audit the loader entries on the ESP: {}", why),
+    }
+}
+
 fn update_recovery_config(
     recovery_conf: &mut RecoveryEnv,
     mount: &Path,
@@ -0,0 +1,309 @@
+//! Signing of the DKMS modules, such as the NVIDIA driver, which are built for the installed
+//! system, so that they may be loaded while Secure Boot is enabled.
+//!
+//! A machine owner key (MOK) is generated in the chroot, at the path where DKMS and
+//! `update-secureboot-policy` look for it, so that modules which are rebuilt by later kernel
+//! updates are signed with the same key. The modules that were built during the install are
+//! signed with it, and its enrollment is staged with `mokutil`. Shim completes the enrollment
+//! on the next boot, once the user has entered the password.
+//!
+//! The signature of a compressed module lies within its compression, so such modules are
+//! decompressed to be checked and signed, and compressed again once they have been signed.
+
+use crate::chroot::Chroot;
+use crate::errors::IoContext;
+use crate::NO_EFI_VARIABLES;
+use std::{
+    fs::{self, File, Permissions},
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+};
+
+const SECURE_BOOT_VAR: &str =
+    "/sys/firmware/efi/efivars/SecureBoot-8be4df61-93ca-11d2-aa0d-e0984b1ddeb5";
+
+/// Where the key is kept within the installed system.
+const MOK_DIR: &str = "/var/lib/shim-signed/mok";
+const MOK_KEY: &str = "/var/lib/shim-signed/mok/MOK.priv";
+const MOK_CERT: &str = "/var/lib/shim-signed/mok/MOK.der";
+
+/// The marker which the kernel expects at the end of a signed module.
+const SIGNATURE_MAGIC: &[u8] = b"~Module signature appended~\n";
+
+/// A module which was built by DKMS, and the kernel that it was built for.
+#[derive(Debug, PartialEq)]
+struct Module {
+    kernel:      String,
+    /// The path of the module within the installed system.
+    path:        PathBuf,
+    compression: Compression,
+}
+
+impl Module {
+    /// The path of the module within the installed system once it has been decompressed.
+    fn decompressed(&self) -> PathBuf {
+        match self.compression {
+            Compression::None => self.path.clone(),
+            _ => self.path.with_extension(""),
+        }
+    }
+}
+
+/// How a module is compressed, which the kernel decompresses when it loads the module.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    None,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression of the module with the file `name`, or `None` if it is not a module.
+    fn of(name: &str) -> Option<Self> {
+        if name.ends_with(".ko") {
+            Some(Compression::None)
+        } else if name.ends_with(".ko.xz") {
+            Some(Compression::Xz)
+        } else if name.ends_with(".ko.zst") {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// The command which decompresses a module beside itself, keeping the compressed module.
+    fn decompress(self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            Compression::None => None,
+            Compression::Xz => Some(("xz", &["-d", "-q", "-f", "-k"])),
+            Compression::Zstd => Some(("zstd", &["-d", "-q", "-f", "-k"])),
+        }
+    }
+
+    /// The command which replaces the compressed module with the decompressed one. The kernel
+    /// only accepts modules that are compressed by xz with CRC32 checks.
+    fn compress(self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            Compression::None => None,
+            Compression::Xz => Some(("xz", &["-q", "-f", "--check=crc32"])),
+            Compression::Zstd => Some(("zstd", &["-q", "-f", "--rm"])),
+        }
+    }
+}
+
+/// Whether the firmware has Secure Boot enabled, and thus requires modules to be signed.
+pub fn secure_boot_enabled() -> bool {
+    // Enrollment requires the EFI variables, which were ordered to be left alone.
+    if NO_EFI_VARIABLES.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    fs::read(SECURE_BOOT_VAR).map_or(false, |var| is_enabled(&var))
+}
+
+/// The variable is prefixed with four bytes of attributes, followed by a byte which is `1`
+/// when Secure Boot is enabled.
+fn is_enabled(var: &[u8]) -> bool { var.get(4) == Some(&1) }
+
+/// Signs the unsigned DKMS modules of the installed system with a MOK, and stages its
+/// enrollment with the given `password`. Nothing is done if there are no such modules.
+pub fn sign_modules(chroot: &Chroot, hostname: &str, password: &str) -> io::Result<()> {
+    let modules = dkms_modules(&chroot.path)?;
+    for module in &modules {
+        if let Some((program, args)) = module.compression.decompress() {
+            let path = module.path.to_string_lossy();
+            chroot.command(program, args.iter().chain(&[&*path])).run()?;
+        }
+    }
+
+    let modules = unsigned_modules(&chroot.path, modules)?;
+    if modules.is_empty() {
+        info!("no unsigned DKMS modules were found");
+        return Ok(());
+    }
+
+    if password.is_empty() || password.len() > 256 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the MOK password must be between 1 and 256 characters",
+        ));
+    }
+
+    generate_key(chroot, hostname)?;
+
+    for module in &modules {
+        info!("signing {} for Secure Boot", module.path.display());
+        let sign_file = ["/usr/src/linux-headers-", &module.kernel, "/scripts/sign-file"].concat();
+        let path = module.decompressed();
+        let path = path.to_string_lossy();
+        chroot.command(sign_file, &["sha256", MOK_KEY, MOK_CERT, &*path]).run()?;
+
+        if let Some((program, args)) = module.compression.compress() {
+            chroot.command(program, args.iter().chain(&[&*path])).run()?;
+        }
+    }
+
+    info!("staging enrollment of the MOK, which will be completed on the next boot");
+    let input = [password, "\n", password, "\n"].concat();
+    chroot.command("mokutil", &["--import", MOK_CERT]).stdin_input(&input).run()
+}
+
+/// Generates the MOK, unless the installed system already has one.
+fn generate_key(chroot: &Chroot, hostname: &str) -> io::Result<()> {
+    let dir = chroot.path.join(MOK_DIR.trim_start_matches('/'));
+    if dir.join("MOK.priv").exists() && dir.join("MOK.der").exists() {
+        info!("using the existing MOK of the installed system");
+        return Ok(());
+    }
+
+    info!("generating a MOK for signing modules");
+    fs::create_dir_all(&dir)
+        .and_then(|_| fs::set_permissions(&dir, Permissions::from_mode(0o700)))
+        .with_context(|err| format!("failed to create {}: {}", dir.display(), err))?;
+
+    let subject = ["/CN=", hostname, " Secure Boot Module Signature key/"].concat();
+    chroot
+        .command("openssl", &[
+            "req",
+            "-new",
+            "-x509",
+            "-newkey",
+            "rsa:2048",
+            "-nodes",
+            "-days",
+            "36500",
+            "-outform",
+            "DER",
+            "-keyout",
+            MOK_KEY,
+            "-out",
+            MOK_CERT,
+            "-subj",
+            &subject,
+            "-addext",
+            "extendedKeyUsage=codeSigning,1.3.6.1.4.1.2312.16.1.2",
+        ])
+        .run()
+}
+
+/// The modules within `/lib/modules/<kernel>/updates/dkms` of the `target`.
+fn dkms_modules(target: &Path) -> io::Result<Vec<Module>> {
+    let mut modules = Vec::new();
+    let kernels = match target.join("lib/modules").read_dir() {
+        Ok(kernels) => kernels,
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(modules),
+        Err(why) => return Err(why),
+    };
+
+    for kernel in kernels.filter_map(Result::ok) {
+        let kernel = kernel.file_name().to_string_lossy().into_owned();
+        let dir = ["lib/modules/", &kernel, "/updates/dkms"].concat();
+        let entries = match target.join(&dir).read_dir() {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let compression = match Compression::of(&entry.file_name().to_string_lossy()) {
+                Some(compression) => compression,
+                None => continue,
+            };
+
+            let path = Path::new("/").join(&dir).join(entry.file_name());
+            modules.push(Module { kernel: kernel.clone(), path, compression });
+        }
+    }
+
+    modules.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(modules)
+}
+
+/// The `modules` of the `target` which are not signed, which must have been decompressed.
+/// Compressed modules which are already signed have their decompressed copies removed.
+fn unsigned_modules(target: &Path, modules: Vec<Module>) -> io::Result<Vec<Module>> {
+    let mut unsigned = Vec::new();
+    for module in modules {
+        let path = target.join(module.decompressed().strip_prefix("/").unwrap());
+        if !is_signed(&path)? {
+            unsigned.push(module);
+        } else if module.compression != Compression::None {
+            fs::remove_file(&path)
+                .with_context(|err| format!("failed to remove {}: {}", path.display(), err))?;
+        }
+    }
+
+    Ok(unsigned)
+}
+
+fn is_signed(module: &Path) -> io::Result<bool> {
+    let mut file = File::open(module)
+        .with_context(|err| format!("failed to open {}: {}", module.display(), err))?;
+
+    let len = file.metadata()?.len();
+    if len < SIGNATURE_MAGIC.len() as u64 {
+        return Ok(false);
+    }
+
+    let mut tail = vec![0; SIGNATURE_MAGIC.len()];
+    file.seek(SeekFrom::End(-(SIGNATURE_MAGIC.len() as i64)))?;
+    file.read_exact(&mut tail)?;
+    Ok(tail == SIGNATURE_MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn secure_boot_variable() {
+        assert!(is_enabled(&[6, 0, 0, 0, 1]));
+        assert!(!is_enabled(&[6, 0, 0, 0, 0]));
+        assert!(!is_enabled(&[]));
+    }
+
+    #[test]
+    fn unsigned() {
+        let tempdir = TempDir::new("distinst").unwrap();
+        let target = tempdir.path();
+        let dkms = target.join("lib/modules/5.15.0-generic/updates/dkms");
+        fs::create_dir_all(&dkms).unwrap();
+        fs::create_dir_all(target.join("lib/modules/5.11.0-generic")).unwrap();
+
+        let signed = [b"\x7fELF" as &[u8], SIGNATURE_MAGIC].concat();
+        fs::write(dkms.join("nvidia.ko"), b"\x7fELF").unwrap();
+        fs::write(dkms.join("nvidia-drm.ko"), &signed).unwrap();
+        fs::write(dkms.join("nvidia-modeset.ko.xz"), b"").unwrap();
+        fs::write(dkms.join("nvidia-uvm.ko.zst"), b"").unwrap();
+        fs::write(dkms.join("nvidia.mod"), b"").unwrap();
+
+        let module = |name: &str, compression| Module {
+            kernel:      "5.15.0-generic".into(),
+            path:        Path::new("/lib/modules/5.15.0-generic/updates/dkms").join(name),
+            compression,
+        };
+
+        let modules = dkms_modules(target).unwrap();
+        assert_eq!(modules, vec![
+            module("nvidia-drm.ko", Compression::None),
+            module("nvidia-modeset.ko.xz", Compression::Xz),
+            module("nvidia-uvm.ko.zst", Compression::Zstd),
+            module("nvidia.ko", Compression::None),
+        ]);
+
+        // The compressed modules, as they would be decompressed.
+        fs::write(dkms.join("nvidia-modeset.ko"), &signed).unwrap();
+        fs::write(dkms.join("nvidia-uvm.ko"), b"\x7fELF").unwrap();
+
+        assert_eq!(unsigned_modules(target, modules).unwrap(), vec![
+            module("nvidia-uvm.ko.zst", Compression::Zstd),
+            module("nvidia.ko", Compression::None),
+        ]);
+        assert!(!dkms.join("nvidia-modeset.ko").exists());
+        assert!(dkms.join("nvidia-uvm.ko").exists());
+
+        assert!(dkms_modules(&target.join("missing")).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,363 @@
+//! Disks which are attached over the network, through iSCSI or NVMe over Fabrics.
+//!
+//! The root of the installed system may be on such a disk, so the initramfs attaches them before
+//! the root is mounted. open-iscsi's initramfs hook logs in to the target which is described by
+//! `/etc/iscsi/iscsi.initramfs`, and the live system's node records of every target are copied,
+//! so that each is logged in to automatically. nvme-cli has no such hook, so a hook and a script
+//! which connects to each subsystem once the network is configured are written. The target and
+//! subsystem may only admit known hosts, so the installed system keeps the identity and the CHAP
+//! credentials of the live system.
+
+use crate::disks::NetworkDisk;
+use crate::errors::IoContext;
+use std::{
+    fs::{self, Permissions},
+    io,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+/// Logs in to iSCSI targets, from the installed system and its initramfs.
+pub const ISCSI_PACKAGES: &[&str] = &["open-iscsi"];
+
+/// Connects to NVMe over Fabrics subsystems.
+pub const NVME_PACKAGES: &[&str] = &["nvme-cli"];
+
+/// Copies `nvme`, the identity of the host, and the transport modules into the initramfs.
+const NVME_HOOK: &str = r#"#!/bin/sh
+PREREQ=""
+prereqs() { echo "$PREREQ"; }
+case "$1" in prereqs) prereqs; exit 0 ;; esac
+
+. /usr/share/initramfs-tools/hook-functions
+copy_exec /usr/sbin/nvme /usr/sbin
+mkdir -p "$DESTDIR/etc/nvme"
+for file in hostnqn hostid; do
+    [ -e "/etc/nvme/$file" ] && cp "/etc/nvme/$file" "$DESTDIR/etc/nvme/"
+done
+manual_add_modules nvme-tcp nvme-rdma nvme-fc
+"#;
+
+/// Configures the network, after which each subsystem is connected to.
+const NVME_LOCAL_TOP: &str = r#"#!/bin/sh
+PREREQ=""
+prereqs() { echo "$PREREQ"; }
+case "$1" in prereqs) prereqs; exit 0 ;; esac
+
+. /scripts/functions
+configure_networking
+"#;
+
+/// The records of the iSCSI nodes which have been discovered, with their settings and credentials.
+const ISCSI_NODES: &str = "etc/iscsi/nodes";
+
+/// The settings of a node record which carry over to the initramfs, as CHAP credentials.
+const ISCSI_AUTH: &[(&str, &str)] = &[
+    ("node.session.auth.username", "ISCSI_USERNAME"),
+    ("node.session.auth.password", "ISCSI_PASSWORD"),
+    ("node.session.auth.username_in", "ISCSI_IN_USERNAME"),
+    ("node.session.auth.password_in", "ISCSI_IN_PASSWORD"),
+];
+
+/// The files which identify this system to NVMe subsystems.
+const IDENTITIES: &[&str] = &["etc/nvme/hostnqn", "etc/nvme/hostid"];
+
+/// The packages which attach the disks in the installed system.
+pub fn packages(disks: &[NetworkDisk]) -> Vec<&'static str> {
+    let mut packages = Vec::new();
+    if disks.iter().any(|disk| matches!(disk, NetworkDisk::Iscsi { .. })) {
+        packages.extend_from_slice(ISCSI_PACKAGES);
+    }
+
+    if disks.iter().any(|disk| matches!(disk, NetworkDisk::NvmeOverFabrics { .. })) {
+        packages.extend_from_slice(NVME_PACKAGES);
+    }
+
+    packages
+}
+
+/// Attaches the disks from the initramfs of the installed system, as the live system does.
+/// This must follow the installation of the packages, which generate identities of their own.
+pub fn configure(target: &Path, disks: &[NetworkDisk]) -> io::Result<()> {
+    let live = Path::new("/");
+    let mut initramfs = None;
+    for disk in disks {
+        if let NetworkDisk::Iscsi { target: ref name, ref address, ref port, ref initiator } = *disk
+        {
+            let records = iscsi_records(live, name, address, port)?;
+            if records.is_empty() {
+                warn!("{} has no node record at {}:{} to copy", name, address, port);
+            }
+
+            let mut contents = Vec::with_capacity(records.len());
+            for record in &records {
+                let path = live.join(record);
+                let record = fs::read_to_string(&path)
+                    .with_context(|err| format!("failed to read {}: {}", path.display(), err))?;
+                contents.push(record);
+            }
+
+            for (record, contents) in records.iter().zip(&contents) {
+                write(target, record, &automatic_startup(contents), 0o600)?;
+            }
+
+            if initramfs.is_none() {
+                let name = ["InitiatorName=", initiator, "\n"].concat();
+                write(target, "etc/iscsi/initiatorname.iscsi", &name, 0o600)?;
+                let record = contents.first().map(String::as_str);
+                initramfs = Some(iscsi_initramfs(disk, record));
+            }
+        }
+    }
+
+    if let Some(initramfs) = initramfs {
+        // The CHAP credentials of the target may be within.
+        write(target, "etc/iscsi/iscsi.initramfs", &initramfs, 0o600)?;
+    }
+
+    let nvme = nvme_local_top(disks);
+    if !nvme.is_empty() {
+        for identity in IDENTITIES {
+            let live = Path::new("/").join(identity);
+            if live.exists() {
+                let dest = target.join(identity);
+                fs::copy(&live, &dest)
+                    .with_context(|err| format!("failed to copy {}: {}", live.display(), err))?;
+            }
+        }
+
+        write(target, "etc/initramfs-tools/hooks/nvmf", NVME_HOOK, 0o755)?;
+        write(target, "etc/initramfs-tools/scripts/local-top/nvmf", &nvme, 0o755)?;
+    }
+
+    Ok(())
+}
+
+/// The node records of the target at the given portal, relative to `root`.
+///
+/// Records are found at `nodes/<target>/<address>,<port>,<tpgt>/<iface>`, or at
+/// `nodes/<target>/<address>,<port>,<tpgt>` by older versions of open-iscsi.
+fn iscsi_records(root: &Path, target: &str, address: &str, port: &str) -> io::Result<Vec<PathBuf>> {
+    let directory = Path::new(ISCSI_NODES).join(target);
+    let portal = [address, ",", port, ","].concat();
+
+    let mut records = Vec::new();
+    let entries = match fs::read_dir(root.join(&directory)) {
+        Ok(entries) => entries,
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(records),
+        Err(why) => return Err(why),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        if !name.to_str().map_or(false, |name| name.starts_with(&portal)) {
+            continue;
+        }
+
+        let path = directory.join(&name);
+        if entry.file_type()?.is_dir() {
+            for iface in fs::read_dir(root.join(&path))? {
+                records.push(path.join(iface?.file_name()));
+            }
+        } else {
+            records.push(path);
+        }
+    }
+
+    records.sort();
+    Ok(records)
+}
+
+/// Marks a node record to be logged in to when the system starts.
+fn automatic_startup(record: &str) -> String {
+    let mut output = String::with_capacity(record.len());
+    for line in record.lines() {
+        let key = line.split('=').next().unwrap_or("").trim();
+        if key == "node.startup" || (key.starts_with("node.conn[") && key.ends_with("].startup")) {
+            output.push_str(&[key, " = automatic"].concat());
+        } else {
+            output.push_str(line);
+        }
+
+        output.push('\n');
+    }
+
+    output
+}
+
+/// The target which open-iscsi's initramfs script logs in to, with the CHAP credentials from
+/// its node `record`, if it has one.
+fn iscsi_initramfs(disk: &NetworkDisk, record: Option<&str>) -> String {
+    let mut config = match *disk {
+        NetworkDisk::Iscsi { ref target, ref address, ref port, ref initiator } => format!(
+            "ISCSI_INITIATOR=\"{}\"\nISCSI_TARGET_NAME=\"{}\"\nISCSI_TARGET_IP=\"{}\"\n\
+             ISCSI_TARGET_PORT=\"{}\"\n",
+            initiator, target, address, port
+        ),
+        NetworkDisk::NvmeOverFabrics { .. } => return String::new(),
+    };
+
+    for line in record.into_iter().flat_map(str::lines) {
+        let mut fields = line.splitn(2, '=');
+        let key = fields.next().unwrap_or("").trim();
+        let value = fields.next().unwrap_or("").trim();
+        if value.is_empty() || value == "<empty>" {
+            continue;
+        }
+
+        if let Some(&(_, variable)) = ISCSI_AUTH.iter().find(|&&(setting, _)| setting == key) {
+            config.push_str(&[variable, "=\"", &shell_escape(value), "\"\n"].concat());
+        }
+    }
+
+    config
+}
+
+/// Escapes a value, so that it may be placed within double quotes in a shell script.
+fn shell_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        if let '"' | '\\' | '$' | '`' = character {
+            escaped.push('\\');
+        }
+
+        escaped.push(character);
+    }
+
+    escaped
+}
+
+/// The initramfs script which connects to each subsystem, or nothing if there are none.
+fn nvme_local_top(disks: &[NetworkDisk]) -> String {
+    let mut script = String::new();
+    let mut modules = Vec::new();
+    for disk in disks {
+        if let NetworkDisk::NvmeOverFabrics { ref transport, ref address, ref service, ref nqn } =
+            *disk
+        {
+            if !modules.contains(&transport) {
+                modules.push(transport);
+                script.push_str(&["modprobe nvme-", transport, "\n"].concat());
+            }
+
+            script.push_str(&format!(
+                "nvme connect --transport={} --traddr={} --nqn={}",
+                transport, address, nqn
+            ));
+
+            if let Some(ref service) = *service {
+                script.push_str(&[" --trsvcid=", service].concat());
+            }
+
+            script.push('\n');
+        }
+    }
+
+    if script.is_empty() {
+        script
+    } else {
+        [NVME_LOCAL_TOP, &script].concat()
+    }
+}
+
+fn write<P: AsRef<Path>>(target: &Path, file: P, contents: &str, mode: u32) -> io::Result<()> {
+    let path = target.join(file);
+    path.parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, contents))
+        .and_then(|_| fs::set_permissions(&path, Permissions::from_mode(mode)))
+        .with_context(|err| format!("failed to write {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    const RECORD: &str = "node.name = iqn.2004-01.com.example:storage\n\
+                          node.startup = manual\n\
+                          node.session.auth.authmethod = CHAP\n\
+                          node.session.auth.username = workstation\n\
+                          node.session.auth.password = pa$$\"word\n\
+                          node.session.auth.username_in = <empty>\n\
+                          node.conn[0].startup = manual\n";
+
+    fn nvme(address: &str, service: Option<&str>) -> NetworkDisk {
+        NetworkDisk::NvmeOverFabrics {
+            transport: "tcp".into(),
+            address:   address.into(),
+            service:   service.map(String::from),
+            nqn:       "nqn.2014-08.org.example:workstation".into(),
+        }
+    }
+
+    #[test]
+    fn iscsi() {
+        let disk = NetworkDisk::Iscsi {
+            target:    "iqn.2004-01.com.example:storage".into(),
+            address:   "192.168.1.10".into(),
+            port:      "3260".into(),
+            initiator: "iqn.2004-10.com.ubuntu:01:workstation".into(),
+        };
+
+        assert_eq!(packages(std::slice::from_ref(&disk)), ISCSI_PACKAGES);
+        assert_eq!(
+            iscsi_initramfs(&disk, None),
+            "ISCSI_INITIATOR=\"iqn.2004-10.com.ubuntu:01:workstation\"\n\
+             ISCSI_TARGET_NAME=\"iqn.2004-01.com.example:storage\"\n\
+             ISCSI_TARGET_IP=\"192.168.1.10\"\n\
+             ISCSI_TARGET_PORT=\"3260\"\n"
+        );
+        assert!(iscsi_initramfs(&disk, Some(RECORD)).ends_with(
+            "ISCSI_TARGET_PORT=\"3260\"\n\
+             ISCSI_USERNAME=\"workstation\"\n\
+             ISCSI_PASSWORD=\"pa\\$\\$\\\"word\"\n"
+        ));
+        assert_eq!(nvme_local_top(&[disk]), "");
+    }
+
+    #[test]
+    fn iscsi_node_records() {
+        let root = TempDir::new("distinst-iscsi").unwrap();
+        let target = Path::new(ISCSI_NODES).join("iqn.2004-01.com.example:storage");
+        let portal = target.join("192.168.1.10,3260,1");
+        fs::create_dir_all(root.path().join(&portal)).unwrap();
+        fs::write(root.path().join(portal.join("default")), RECORD).unwrap();
+        fs::write(root.path().join(target.join("192.168.1.11,3260,1")), RECORD).unwrap();
+
+        let records = |address| {
+            iscsi_records(root.path(), "iqn.2004-01.com.example:storage", address, "3260").unwrap()
+        };
+
+        assert_eq!(records("192.168.1.10"), vec![portal.join("default")]);
+        assert_eq!(records("192.168.1.11"), vec![target.join("192.168.1.11,3260,1")]);
+        assert!(records("192.168.1.1").is_empty());
+        assert!(iscsi_records(root.path(), "iqn.missing", "192.168.1.10", "3260")
+            .unwrap()
+            .is_empty());
+
+        let record = automatic_startup(RECORD);
+        assert!(record.contains("\nnode.startup = automatic\n"));
+        assert!(record.contains("\nnode.conn[0].startup = automatic\n"));
+        assert!(!record.contains("manual"));
+    }
+
+    #[test]
+    fn nvme_over_fabrics() {
+        let disks = [nvme("192.168.1.10", Some("4420")), nvme("192.168.1.11", None)];
+        assert_eq!(packages(&disks), NVME_PACKAGES);
+        assert_eq!(
+            nvme_local_top(&disks),
+            [
+                NVME_LOCAL_TOP,
+                "modprobe nvme-tcp\n\
+                 nvme connect --transport=tcp --traddr=192.168.1.10 \
+                 --nqn=nqn.2014-08.org.example:workstation --trsvcid=4420\n\
+                 nvme connect --transport=tcp --traddr=192.168.1.11 \
+                 --nqn=nqn.2014-08.org.example:workstation\n",
+            ]
+            .concat()
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! A local apt repository which packages are installed from while configuring, for machines
+//! which have no network. The repository is bind-mounted into the target and added as a source
+//! only for the install, and no trace of it is left behind in the installed system.
+
+use std::{io, path::Path};
+
+/// Where the repository is mounted within the target.
+pub const MOUNT_POINT: &str = "/media/distinst-offline-repo";
+
+/// The source list which refers to the repository.
+pub const SOURCES_LIST: &str = "/etc/apt/sources.list.d/distinst-offline-repo.list";
+
+/// Where the keyring which verifies the repository is copied to, when it is a binary keyring.
+pub const KEYRING: &str = "/etc/apt/distinst-offline-repo.gpg";
+
+/// Where an ASCII-armored keyring is copied to, as apt only accepts those with this extension.
+pub const ARMORED_KEYRING: &str = "/etc/apt/distinst-offline-repo.asc";
+
+/// Where the keyring which verifies the repository is copied to, by its `contents`.
+pub fn keyring_path(contents: &[u8]) -> &'static str {
+    if is_armored(contents) {
+        ARMORED_KEYRING
+    } else {
+        KEYRING
+    }
+}
+
+/// Whether the keyring is ASCII-armored, rather than binary.
+fn is_armored(contents: &[u8]) -> bool {
+    let start = contents.iter().position(|byte| !byte.is_ascii_whitespace()).unwrap_or(0);
+    contents[start..].starts_with(b"-----BEGIN PGP PUBLIC KEY BLOCK-----")
+}
+
+/// The indexes, one of which a flat repository must have at its top.
+const INDEXES: &[&str] = &["Packages", "Packages.gz", "Packages.xz", "InRelease", "Release"];
+
+/// Ensures that the repository is a flat repository, with an index of its packages.
+pub fn validate(repo: &Path) -> io::Result<()> {
+    if INDEXES.iter().any(|index| repo.join(index).is_file()) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not an apt repository with a Packages index", repo.display()),
+        ))
+    }
+}
+
+/// The source list entry of the repository, which is verified by the `keyring` at the given
+/// path, or else trusted.
+pub fn sources_list(keyring: Option<&str>) -> String {
+    let option = keyring.map_or_else(|| "trusted=yes".into(), |path| ["signed-by=", path].concat());
+    format!("deb [{}] file:{} ./\n", option, MOUNT_POINT)
+}
+
+/// Whether the file in `/var/lib/apt/lists` was fetched from the repository.
+pub fn is_list(name: &str) -> bool {
+    name.starts_with(&[&MOUNT_POINT.replace('/', "_"), "_"].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources() {
+        assert_eq!(
+            sources_list(None),
+            "deb [trusted=yes] file:/media/distinst-offline-repo ./\n"
+        );
+        assert_eq!(
+            sources_list(Some(KEYRING)),
+            "deb [signed-by=/etc/apt/distinst-offline-repo.gpg] \
+             file:/media/distinst-offline-repo ./\n"
+        );
+        assert_eq!(
+            sources_list(Some(ARMORED_KEYRING)),
+            "deb [signed-by=/etc/apt/distinst-offline-repo.asc] \
+             file:/media/distinst-offline-repo ./\n"
+        );
+
+        assert!(is_list("_media_distinst-offline-repo_._Packages"));
+        assert!(!is_list("_media_distinst-offline-repository_._Packages"));
+        assert!(!is_list("archive.ubuntu.com_ubuntu_dists_jammy_InRelease"));
+    }
+
+    #[test]
+    fn keyrings() {
+        let armored = b"\n-----BEGIN PGP PUBLIC KEY BLOCK-----\n\nmQINBF...\n";
+        assert_eq!(keyring_path(armored), ARMORED_KEYRING);
+        assert_eq!(keyring_path(&[0x99, 0x02, 0x0d, 0x04]), KEYRING);
+        assert_eq!(keyring_path(b""), KEYRING);
+    }
+}
@@ -0,0 +1,233 @@
+//! A read-only root file system, for kiosks and appliances which should return to the state
+//! that they were installed in with each boot.
+//!
+//! The root is mounted as `ro` by the fstab. As the system cannot run without writing to
+//! `/etc`, `/var`, `/tmp`, and the home directories, systemd units mount a tmpfs at `/tmp`, and
+//! overlays at `/etc`, `/var`, `/home`, and `/root`, whose writable layers are kept in `/run`.
+//! Targets which are mounted from their own partitions remain writable, and are left alone, so
+//! a separate `/home` keeps the changes of its users.
+
+use crate::errors::IoContext;
+use std::{fs, io, os::unix::fs::symlink, path::Path};
+
+const UNIT_DIR: &str = "etc/systemd/system";
+
+/// Creates the directories of the writable layers of each overlay.
+const OVERLAY_DIRS_UNIT: &str = "distinst-overlay-dirs.service";
+
+/// Where the writable layers of the overlays are kept.
+const OVERLAY_DIR: &str = "/run/distinst-overlay";
+
+/// Targets which are mounted as overlays of the read-only root.
+const OVERLAY_TARGETS: &[&str] = &["/etc", "/var", "/home", "/root"];
+
+const TMP_UNIT: &str = "[Unit]
+Description=Temporary Directory of the Read-Only Root
+Documentation=man:file-hierarchy(7)
+DefaultDependencies=no
+Conflicts=umount.target
+Before=local-fs.target umount.target
+
+[Mount]
+What=tmpfs
+Where=/tmp
+Type=tmpfs
+Options=mode=1777,strictatime,nosuid,nodev
+
+[Install]
+WantedBy=local-fs.target
+";
+
+/// Marks the root as read-only in the `fstab`, and writes the units which mount the writable
+/// directories of the installed system at `target`.
+pub fn configure(target: &Path, fstab: &str) -> io::Result<String> {
+    info!("configuring a read-only root file system");
+    let units = units(fstab);
+    let wants = target.join(UNIT_DIR).join("local-fs.target.wants");
+    fs::create_dir_all(&wants)
+        .with_context(|err| format!("failed to create {}: {}", wants.display(), err))?;
+
+    for (name, contents) in &units {
+        let path = target.join(UNIT_DIR).join(name);
+        fs::write(&path, contents)
+            .with_context(|err| format!("failed to write {}: {}", path.display(), err))?;
+
+        if name.ends_with(".mount") {
+            let link = wants.join(name);
+            let _ = fs::remove_file(&link);
+            symlink(Path::new("/").join(UNIT_DIR).join(name), &link)
+                .with_context(|err| format!("failed to enable {}: {}", name, err))?;
+        }
+    }
+
+    Ok(read_only_fstab(fstab))
+}
+
+/// Adds the `ro` option to the root entry of the `fstab`.
+fn read_only_fstab(fstab: &str) -> String {
+    let mut output = String::with_capacity(fstab.len() + 4);
+    for line in fstab.lines() {
+        let mut fields = line.split_whitespace().map(String::from).collect::<Vec<_>>();
+        if !line.starts_with('#') && fields.len() >= 4 && fields[1] == "/" {
+            fields[3] = fields[3]
+                .split(',')
+                .filter(|&option| option != "rw" && option != "ro")
+                .chain(Some("ro"))
+                .collect::<Vec<_>>()
+                .join(",");
+            output.push_str(&fields.join("  "));
+        } else {
+            output.push_str(line);
+        }
+
+        output.push('\n');
+    }
+
+    output
+}
+
+/// The names and contents of the units which are required by the `fstab`, which excludes
+/// targets that are already mounted by it.
+fn units(fstab: &str) -> Vec<(String, String)> {
+    let mounted = |target: &str| is_mounted(fstab, target);
+
+    let mut units = Vec::new();
+    if !mounted("/tmp") {
+        units.push(("tmp.mount".to_owned(), TMP_UNIT.to_owned()));
+    }
+
+    let overlays = OVERLAY_TARGETS.iter().filter(|&&target| !mounted(target)).collect::<Vec<_>>();
+    if !overlays.is_empty() {
+        let dirs = overlays
+            .iter()
+            .flat_map(|target| {
+                let layer = [OVERLAY_DIR, target].concat();
+                vec![[&layer, "/upper"].concat(), [&layer, "/work"].concat()]
+            })
+            .collect::<Vec<_>>();
+
+        units.push((OVERLAY_DIRS_UNIT.to_owned(), overlay_dirs_unit(&dirs)));
+        for target in overlays {
+            units.push((unit_name(target), overlay_unit(target)));
+        }
+    }
+
+    units
+}
+
+/// Whether the `target` is mounted by the `fstab`, rather than being a part of the root. Such
+/// targets remain writable, and their changes persist.
+pub fn is_mounted(fstab: &str, target: &str) -> bool {
+    fstab
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .any(|line| line.split_whitespace().nth(1) == Some(target))
+}
+
+fn overlay_dirs_unit(dirs: &[String]) -> String {
+    format!(
+        "[Unit]
+Description=Writable Layers of the Read-Only Root
+DefaultDependencies=no
+RequiresMountsFor=/run
+
+[Service]
+Type=oneshot
+RemainAfterExit=yes
+ExecStart=/bin/mkdir -p {}
+",
+        dirs.join(" ")
+    )
+}
+
+fn overlay_unit(target: &str) -> String {
+    let layer = [OVERLAY_DIR, target].concat();
+    format!(
+        "[Unit]
+Description=Writable Overlay of {target}
+DefaultDependencies=no
+Requires={dirs}
+After={dirs}
+Conflicts=umount.target
+Before=local-fs.target umount.target
+
+[Mount]
+What=overlay
+Where={target}
+Type=overlay
+Options=lowerdir={target},upperdir={layer}/upper,workdir={layer}/work
+
+[Install]
+WantedBy=local-fs.target
+",
+        target = target,
+        dirs = OVERLAY_DIRS_UNIT,
+        layer = layer
+    )
+}
+
+/// The name of the mount unit of a `target`, which systemd requires to match its path.
+fn unit_name(target: &str) -> String {
+    [&target.trim_start_matches('/').replace('/', "-"), ".mount"].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FSTAB: &str = "# <file system>  <mount point>  <type>  <options>  <dump>  <pass>
+PARTUUID=esp  /boot/efi  vfat  umask=0077  0  0
+UUID=root  /  btrfs  subvol=@root,defaults  0  1
+UUID=root  /home  btrfs  subvol=@home,defaults  0  0
+";
+
+    #[test]
+    fn read_only_root() {
+        assert_eq!(
+            read_only_fstab(FSTAB),
+            "# <file system>  <mount point>  <type>  <options>  <dump>  <pass>
+PARTUUID=esp  /boot/efi  vfat  umask=0077  0  0
+UUID=root  /  btrfs  subvol=@root,defaults,ro  0  1
+UUID=root  /home  btrfs  subvol=@home,defaults  0  0
+"
+        );
+
+        assert_eq!(
+            read_only_fstab("UUID=root\t/\text4\trw,noatime\t0\t1\n"),
+            "UUID=root  /  ext4  noatime,ro  0  1\n"
+        );
+    }
+
+    #[test]
+    fn writable_targets() {
+        let names = |fstab: &str| units(fstab).into_iter().map(|unit| unit.0).collect::<Vec<_>>();
+        assert_eq!(names(FSTAB), vec![
+            "tmp.mount",
+            "distinst-overlay-dirs.service",
+            "etc.mount",
+            "var.mount",
+            "root.mount",
+        ]);
+
+        let separate = [FSTAB, "UUID=var  /var  ext4  defaults  0  2\n"].concat();
+        let units = units(&separate);
+        assert_eq!(units.len(), 4);
+        assert_eq!(units[2].0, "etc.mount");
+        assert_eq!(units[3].0, "root.mount");
+        assert!(units[1].1.contains(
+            "ExecStart=/bin/mkdir -p /run/distinst-overlay/etc/upper \
+             /run/distinst-overlay/etc/work /run/distinst-overlay/root/upper \
+             /run/distinst-overlay/root/work\n"
+        ));
+        assert!(units[2].1.contains(
+            "Options=lowerdir=/etc,upperdir=/run/distinst-overlay/etc/upper,\
+             workdir=/run/distinst-overlay/etc/work\n"
+        ));
+    }
+
+    #[test]
+    fn unit_names() {
+        assert_eq!(unit_name("/var"), "var.mount");
+        assert_eq!(unit_name("/var/lib"), "var-lib.mount");
+    }
+}
@@ -0,0 +1,71 @@
+//! SSH access to the account which the installer creates, so that servers and headless systems
+//! may be reached over the network as soon as they have booted.
+
+use std::io;
+
+/// The SSH server, which is installed when keys are authorized.
+pub const SSH_PACKAGES: &[&str] = &["openssh-server"];
+
+/// The types of public keys which OpenSSH accepts.
+const KEY_TYPES: &[&str] = &[
+    "ssh-ed25519",
+    "ssh-rsa",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+];
+
+/// The contents of `authorized_keys`, with each key on its own line.
+pub fn authorized_keys(keys: &[String]) -> io::Result<String> {
+    let mut contents = String::new();
+    for key in keys.iter().map(|key| key.trim()) {
+        if !is_public_key(key) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not an SSH public key: {}", key),
+            ));
+        }
+
+        contents.push_str(key);
+        contents.push('\n');
+    }
+
+    Ok(contents)
+}
+
+/// Whether the line holds a key type followed by the key, which may be preceded by options,
+/// such as `from="192.168.1.0/24"`, and followed by a comment.
+fn is_public_key(line: &str) -> bool {
+    if line.contains('\n') {
+        return false;
+    }
+
+    let mut fields = line.split_whitespace();
+    fields.any(|field| KEY_TYPES.contains(&field)) && fields.next().is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        let keys = vec![
+            " ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIG4n user@laptop\n".to_owned(),
+            "from=\"192.168.1.0/24\" ssh-rsa AAAAB3NzaC1yc2EAAAADAQAB".to_owned(),
+        ];
+
+        assert_eq!(
+            authorized_keys(&keys).unwrap(),
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIG4n user@laptop\n\
+             from=\"192.168.1.0/24\" ssh-rsa AAAAB3NzaC1yc2EAAAADAQAB\n"
+        );
+
+        assert_eq!(authorized_keys(&[]).unwrap(), "");
+        assert!(authorized_keys(&["ssh-ed25519".to_owned()]).is_err());
+        assert!(authorized_keys(&["AAAAC3NzaC1lZDI1NTE5AAAAIG4n".to_owned()]).is_err());
+        assert!(authorized_keys(&["ssh-rsa AAAA\nssh-rsa BBBB".to_owned()]).is_err());
+    }
+}
@@ -0,0 +1,344 @@
+//! Checks on the user accounts which the installer creates, before any of them are created, so
+//! that a mistake in one account will not leave the system with only some of its accounts.
+
+use crate::{SudoAccess, UserAccountCreate, UserBackend};
+use std::{
+    fs, io,
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+};
+
+/// The most symbolic links which are followed while resolving a path, as with `ELOOP`.
+const MAX_LINKS: usize = 40;
+
+/// Ensures that usernames are unique, that group names are valid, that system accounts are not
+/// administrators, nor given SSH keys without a home directory, and that the homes of
+/// systemd-homed users have a password to be encrypted with.
+pub fn validate(users: &[UserAccountCreate]) -> io::Result<()> {
+    let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    for (id, user) in users.iter().enumerate() {
+        if users[..id].iter().any(|other| other.username == user.username) {
+            return invalid(format!("the user {} is created more than once", user.username));
+        }
+
+        if let Some(group) = user.groups.iter().find(|group| !is_valid_name(group)) {
+            return invalid(format!("{} is not a valid group name", group));
+        }
+
+        if user.system {
+            let admin_groups = UserAccountCreate::admin_groups();
+            if let Some(group) = user.groups.iter().find(|group| admin_groups.contains(group)) {
+                return invalid(format!(
+                    "the system user {} may not be a member of the {} group",
+                    user.username, group
+                ));
+            }
+        }
+
+        if user.system && !user.ssh_authorized_keys.is_empty() {
+            return invalid(format!(
+                "the system user {} has no home directory for SSH keys",
+                user.username
+            ));
+        }
+
+        if user.backend == UserBackend::Homed {
+            if user.system {
+                let user = &user.username;
+                return invalid(format!("the system user {} cannot use systemd-homed", user));
+            }
+
+            if user.password.as_ref().map_or(true, String::is_empty) {
+                return invalid(format!(
+                    "the systemd-homed user {} requires a password to encrypt their home",
+                    user.username
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Ensures that the login shell is an executable of the target at `root`, and that the shells
+/// of users who log in are listed in the target's `/etc/shells`. System accounts are exempt
+/// from the latter, as `nologin` is not a valid shell.
+pub fn check_shell(root: &Path, shell: &str, system: bool) -> io::Result<()> {
+    let not_found = |message: String| Err(io::Error::new(io::ErrorKind::NotFound, message));
+    if !Path::new(shell).is_absolute() {
+        return not_found(format!("the shell {} is not an absolute path", shell));
+    }
+
+    let executable = resolve(root, Path::new(shell))
+        .and_then(|path| fs::metadata(path).ok())
+        .map_or(false, |meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0);
+    if !executable {
+        return not_found(format!("the shell {} is not installed in the target", shell));
+    }
+
+    if !system {
+        let shells = fs::read_to_string(root.join("etc/shells")).unwrap_or_default();
+        if !shells.lines().any(|line| line.trim() == shell) {
+            return not_found(format!("the shell {} is not in /etc/shells of the target", shell));
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves a `path` of the target within its `root`, following symbolic links as the target
+/// would, where absolute links are relative to the root rather than to the live system.
+fn resolve(root: &Path, path: &Path) -> Option<PathBuf> {
+    let mut pending: Vec<PathBuf> = path.components().rev().map(|c| c.as_os_str().into()).collect();
+    let mut resolved = PathBuf::new();
+    let mut links = 0;
+    while let Some(component) = pending.pop() {
+        match component.components().next()? {
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => (),
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => {
+                let candidate = resolved.join(name);
+                let meta = fs::symlink_metadata(root.join(&candidate)).ok()?;
+                if !meta.file_type().is_symlink() {
+                    resolved = candidate;
+                    continue;
+                }
+
+                links += 1;
+                if links > MAX_LINKS {
+                    return None;
+                }
+
+                let target = fs::read_link(root.join(&candidate)).ok()?;
+                if target.is_absolute() {
+                    resolved = PathBuf::new();
+                }
+
+                pending.extend(target.components().rev().map(|c| c.as_os_str().into()));
+            }
+        }
+    }
+
+    Some(root.join(resolved))
+}
+
+/// The groups of the users which are not defined in the `group` file of the target.
+pub fn missing_groups<'a>(group_file: &str, users: &'a [UserAccountCreate]) -> Vec<&'a str> {
+    let mut missing: Vec<&str> = Vec::new();
+    for group in users.iter().flat_map(|user| user.groups.iter()) {
+        let defined = group_file.lines().any(|line| line.split(':').next() == Some(group));
+        if !defined && !missing.contains(&group.as_str()) {
+            missing.push(group);
+        }
+    }
+
+    missing
+}
+
+/// The name of the user's drop-in in `/etc/sudoers.d`. Files which contain a `.` are ignored
+/// by sudo, so it is escaped as `_2e`, and `_` as `_5f`, so that no two users share a drop-in.
+pub fn sudoers_file(user: &str) -> String {
+    let mut file = String::from("50-distinst-");
+    for character in user.chars() {
+        match character {
+            '.' | '_' => file.push_str(&format!("_{:02x}", character as u32)),
+            character => file.push(character),
+        }
+    }
+
+    file
+}
+
+/// The rule which grants the user `sudo`.
+pub fn sudoers_rule(user: &str, access: SudoAccess) -> String {
+    match access {
+        SudoAccess::Password => format!("{} ALL=(ALL:ALL) ALL\n", user),
+        SudoAccess::NoPassword => format!("{} ALL=(ALL:ALL) NOPASSWD: ALL\n", user),
+    }
+}
+
+/// Gives the first user who logs in ownership of the exFAT and NTFS file systems in the
+/// target's `/etc/fstab`, once the accounts have been created, as these file systems have no
+/// owners of their own. Without such a user, they are left to be owned by root.
+pub fn own_foreign_mounts(root: &Path, users: &[UserAccountCreate]) -> io::Result<()> {
+    let user = users.iter().find(|user| !user.system && user.backend == UserBackend::Passwd);
+    let user = match user {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+
+    let passwd = fs::read_to_string(root.join("etc/passwd"))?;
+    let (uid, gid) = passwd_ids(&passwd, &user.username).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("the user {} is not in /etc/passwd", user.username),
+        )
+    })?;
+
+    let path = root.join("etc/fstab");
+    let fstab = fs::read_to_string(&path)?;
+    let owned = with_owner(&fstab, uid, gid);
+    if owned != fstab {
+        info!("mounting exFAT and NTFS file systems as {} ({}:{})", user.username, uid, gid);
+        fs::write(&path, owned)?;
+    }
+
+    Ok(())
+}
+
+/// The UID and GID of the user in the `passwd` file.
+fn passwd_ids(passwd: &str, user: &str) -> Option<(u32, u32)> {
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next() != Some(user) {
+            return None;
+        }
+
+        let mut ids = fields.skip(1).map(|field| field.parse::<u32>().ok());
+        Some((ids.next()??, ids.next()??))
+    })
+}
+
+/// Adds the `uid` and `gid` options to the exFAT and NTFS entries of the fstab which have no
+/// owner.
+fn with_owner(fstab: &str, uid: u32, gid: u32) -> String {
+    let mut output = String::with_capacity(fstab.len());
+    for line in fstab.lines() {
+        let mut fields = line.split_whitespace().collect::<Vec<_>>();
+        let foreign = !line.starts_with('#')
+            && fields.len() >= 4
+            && (fields[2] == "exfat" || fields[2] == "ntfs")
+            && !fields[3].split(',').any(|option| option.starts_with("uid="));
+
+        if foreign {
+            let options = format!("uid={},gid={},{}", uid, gid, fields[3]);
+            fields[3] = &options;
+            output.push_str(&fields.join("  "));
+        } else {
+            output.push_str(line);
+        }
+
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Whether the name is accepted by `groupadd` without `--badname`.
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().map_or(false, |c| c.is_ascii_lowercase() || c == '_')
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+        && name.len() <= 32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn user(username: &str, groups: &[&str]) -> UserAccountCreate {
+        UserAccountCreate {
+            username:            username.into(),
+            realname:            None,
+            password:            None,
+            profile_icon:        None,
+            ssh_authorized_keys: Vec::new(),
+            shell:               None,
+            groups:              groups.iter().map(|&group| group.into()).collect(),
+            system:              false,
+            sudo:                None,
+            backend:             UserBackend::Passwd,
+        }
+    }
+
+    #[test]
+    fn accounts() {
+        let users = vec![user("admin", &["adm", "sudo", "lpadmin"]), user("kid", &["kids"])];
+        assert!(validate(&users).is_ok());
+
+        let group_file = "root:x:0:\nadm:x:4:syslog\nsudo:x:27:\n";
+        assert_eq!(missing_groups(group_file, &users), vec!["lpadmin", "kids"]);
+
+        assert!(validate(&[user("admin", &[]), user("admin", &[])]).is_err());
+        assert!(validate(&[user("admin", &["Sudo"])]).is_err());
+
+        let mut service = user("backup", &[]);
+        service.system = true;
+        service.ssh_authorized_keys.push("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIG4n".into());
+        assert!(validate(&[service]).is_err());
+
+        let mut service = user("backup", &["backup"]);
+        service.system = true;
+        assert!(validate(&[service.clone()]).is_ok());
+        service.groups.push("sudo".into());
+        assert!(validate(&[service]).is_err());
+
+        let mut traveler = user("traveler", &[]);
+        traveler.backend = UserBackend::Homed;
+        assert!(validate(&[traveler.clone()]).is_err());
+        traveler.password = Some("password".into());
+        assert!(validate(&[traveler]).is_ok());
+    }
+
+    #[test]
+    fn shells() {
+        let root = TempDir::new("distinst-shells").unwrap();
+        let executable = |path: &str| {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, b"").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        };
+
+        executable("usr/bin/bash");
+        executable("usr/bin/fish");
+        executable("usr/sbin/nologin");
+        std::os::unix::fs::symlink("usr/bin", root.path().join("bin")).unwrap();
+        std::os::unix::fs::symlink("/usr/bin/bash", root.path().join("usr/bin/sh")).unwrap();
+        fs::create_dir(root.path().join("etc")).unwrap();
+        fs::write(root.path().join("etc/shells"), "# shells\n/bin/bash\n/bin/sh\n").unwrap();
+
+        assert!(check_shell(root.path(), "/bin/bash", false).is_ok());
+        assert!(check_shell(root.path(), "/bin/sh", false).is_ok());
+        assert!(check_shell(root.path(), "/usr/sbin/nologin", true).is_ok());
+        assert!(check_shell(root.path(), "/bin/fish", false).is_err());
+        assert!(check_shell(root.path(), "/bin/zsh", true).is_err());
+        assert!(check_shell(root.path(), "bin/bash", false).is_err());
+    }
+
+    #[test]
+    fn foreign_mounts() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\noem:x:1001:1001::/home/oem:/bin/bash\n";
+        assert_eq!(passwd_ids(passwd, "oem"), Some((1001, 1001)));
+        assert_eq!(passwd_ids(passwd, "admin"), None);
+
+        let fstab = "# /etc/fstab\n\
+                     UUID=root  /  ext4  noatime,errors=remount-ro  0  1\n\
+                     UUID=data  /mnt/data  exfat  umask=022,nofail  0  0\n\
+                     UUID=win  /mnt/win  ntfs  windows_names,nofail  0  0\n\
+                     UUID=usb  /mnt/usb  exfat  uid=1002,nofail  0  0\n";
+        assert_eq!(
+            with_owner(fstab, 1001, 1001),
+            "# /etc/fstab\n\
+             UUID=root  /  ext4  noatime,errors=remount-ro  0  1\n\
+             UUID=data  /mnt/data  exfat  uid=1001,gid=1001,umask=022,nofail  0  0\n\
+             UUID=win  /mnt/win  ntfs  uid=1001,gid=1001,windows_names,nofail  0  0\n\
+             UUID=usb  /mnt/usb  exfat  uid=1002,nofail  0  0\n"
+        );
+    }
+
+    #[test]
+    fn sudoers() {
+        assert_eq!(sudoers_file("first.last"), "50-distinst-first_2elast");
+        assert_eq!(sudoers_file("first_last"), "50-distinst-first_5flast");
+        assert_eq!(sudoers_file("kiosk"), "50-distinst-kiosk");
+        assert_eq!(
+            sudoers_rule("kiosk", SudoAccess::NoPassword),
+            "kiosk ALL=(ALL:ALL) NOPASSWD: ALL\n"
+        );
+        assert_eq!(sudoers_rule("admin", SudoAccess::Password), "admin ALL=(ALL:ALL) ALL\n");
+    }
+}
@@ -1,19 +1,27 @@
 use crate::disks::*;
+use crate::errors::IoContext;
 use crate::misc;
+use crate::squashfs::fetch::{self, RemoteImage};
 use rayon;
 use std::{
+    fs,
     io::{self, BufRead},
     path::{Path, PathBuf},
 };
 use crate::Config;
 
+/// Validates the config and prepares the disks, returning the path of a local image and the
+/// packages which are to be removed. Remote images are only parsed, as they are downloaded onto
+/// the new root by `download_image` once it has been mounted.
 pub fn initialize<F: FnMut(i32)>(
     disks: &mut Disks,
     config: &Config,
     mut callback: F,
-) -> io::Result<(PathBuf, Vec<String>)> {
+) -> io::Result<(Option<PathBuf>, Vec<String>)> {
     info!("Initializing");
 
+    let remote = fetch::is_remote(&config.squashfs);
+
     let fetch_squashfs = || match Path::new(&config.squashfs).canonicalize() {
         Ok(squashfs) => {
             if squashfs.exists() {
@@ -65,7 +73,7 @@ pub fn initialize<F: FnMut(i32)>(
     let mut res_a = Ok(());
     let mut res_b = Ok(Vec::new());
     let mut res_c = Ok(());
-    let mut res_d = Ok(PathBuf::new());
+    let mut res_d = Ok(None);
 
     rayon::scope(|s| {
         s.spawn(|_| {
@@ -94,7 +102,11 @@ pub fn initialize<F: FnMut(i32)>(
 
         s.spawn(|_| res_b = fetch_packages());
         s.spawn(|_| res_c = verify_disks(disks));
-        s.spawn(|_| res_d = fetch_squashfs());
+        if remote {
+            s.spawn(|_| res_d = RemoteImage::parse(&config.squashfs).map(|_| None));
+        } else {
+            s.spawn(|_| res_d = fetch_squashfs().map(Some));
+        }
     });
 
     let (remove_pkgs, squashfs) =
@@ -125,3 +137,39 @@ pub fn initialize<F: FnMut(i32)>(
 
     Ok((squashfs, remove_pkgs))
 }
+
+/// The directory, relative to the root of the new system, which remote images are downloaded
+/// into. It is not random, so that an interrupted download is resumed when the install is tried
+/// again.
+pub const IMAGE_DIRECTORY: &str = ".distinst-image";
+
+/// Downloads the remote image of the config into `IMAGE_DIRECTORY` on the mounted `root` of the
+/// new system, as the temporary directory of a live session is held in memory. A partial image
+/// that was left there by a previous attempt is resumed.
+///
+/// The directory is left in place, so that it may be resumed, until `remove_image` is called.
+pub fn download_image<F: FnMut(i32)>(
+    config: &Config,
+    root: &Path,
+    mut callback: F,
+) -> io::Result<PathBuf> {
+    let image = RemoteImage::parse(&config.squashfs)?;
+    let directory = root.join(IMAGE_DIRECTORY);
+
+    info!("config.squashfs: downloading {} to {}", image.url, directory.display());
+    image
+        .fetch(&directory, |read, total| {
+            if let Some(total) = total.filter(|&total| total != 0) {
+                callback((read * 100 / total) as i32);
+            }
+        })
+        .with_context(|why| format!("failed to download {}: {}", image.url, why))
+}
+
+/// Removes the directory of a remote image from the `root` of the new system, once it has been
+/// extracted.
+pub fn remove_image(root: &Path) -> io::Result<()> {
+    let directory = root.join(IMAGE_DIRECTORY);
+    fs::remove_dir_all(&directory)
+        .with_context(|why| format!("failed to remove {}: {}", directory.display(), why))
+}
@@ -1,14 +1,22 @@
 mod bootloader;
+mod cleanup;
 mod configure;
 mod initialize;
 mod partition;
+mod sync;
+mod verify;
 
-pub use self::{bootloader::*, configure::*, initialize::*, partition::*};
+pub use self::{
+    bootloader::*, cleanup::*, configure::*, initialize::*, partition::*, sync::*, verify::*,
+};
 
 use std::{
     borrow::Cow,
+    ffi::CString,
     fs, io,
+    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
+    ptr,
     sync::atomic::Ordering,
 };
 
@@ -20,16 +28,19 @@ use crate::NO_EFI_VARIABLES;
 pub enum Step {
     Backup,
     Init,
+    Verify,
     Partition,
     Extract,
     Configure,
     Bootloader,
+    Cleanup,
 }
 
 fn mount_cdrom(mount_dir: &Path) -> io::Result<Option<(UnmountDrop<Mount>, PathBuf)>> {
     let cdrom_source = Path::new("/cdrom");
     let cdrom_target = mount_dir.join("cdrom");
-    mount_bind_if_exists(&cdrom_source, &cdrom_target).map(|res| res.map(|m| (m, cdrom_target)))
+    mount_bind_if_exists(&cdrom_source, &cdrom_target, Propagation::Slave)
+        .map(|res| res.map(|m| (m, cdrom_target)))
 }
 
 pub fn mount_efivars(mount_dir: &Path) -> io::Result<Option<UnmountDrop<Mount>>> {
@@ -39,17 +50,56 @@ pub fn mount_efivars(mount_dir: &Path) -> io::Result<Option<UnmountDrop<Mount>>>
     } else {
         let efivars_source = Path::new("/sys/firmware/efi/efivars");
         let efivars_target = mount_dir.join("sys/firmware/efi/efivars");
-        mount_bind_if_exists(&efivars_source, &efivars_target)
+        mount_bind_if_exists(&efivars_source, &efivars_target, Propagation::Private)
+    }
+}
+
+/// Whether mount and unmount events are shared between a bind mount and its source, as with
+/// `mount --make-private` and `mount --make-slave`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Propagation {
+    /// Events are neither received from, nor sent to, the source.
+    Private,
+    /// Events are received from the source, but never sent back to it.
+    Slave,
+}
+
+/// Changes the propagation of the mount at `target`, and of every mount beneath it.
+///
+/// A bind mount joins the peer group of its source when the source is shared, which systemd
+/// does for every mount of the live session. Unmounting the chroot would then unmount the
+/// source as well, unless the bind mount is first made private, or a slave of its source.
+pub fn set_propagation(target: &Path, propagation: Propagation) -> io::Result<()> {
+    let flag = match propagation {
+        Propagation::Private => libc::MS_PRIVATE,
+        Propagation::Slave => libc::MS_SLAVE,
+    };
+
+    let target = CString::new(target.as_os_str().as_bytes())
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))?;
+
+    let result = unsafe {
+        libc::mount(ptr::null(), target.as_ptr(), ptr::null(), flag | libc::MS_REC, ptr::null())
+    };
+
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
     }
 }
 
-fn mount_bind_if_exists(source: &Path, target: &Path) -> io::Result<Option<UnmountDrop<Mount>>> {
+fn mount_bind_if_exists(
+    source: &Path,
+    target: &Path,
+    propagation: Propagation,
+) -> io::Result<Option<UnmountDrop<Mount>>> {
     if source.exists() {
         let _ = fs::create_dir_all(&target);
-        Ok(Some(
-            Mount::new(source, &target, "none", MountFlags::BIND, None)?
-                .into_unmount_drop(UnmountFlags::empty()),
-        ))
+        let mount = Mount::new(source, &target, "none", MountFlags::BIND, None)?
+            .into_unmount_drop(UnmountFlags::empty());
+        set_propagation(target, propagation)?;
+        Ok(Some(mount))
     } else {
         Ok(None)
     }
@@ -68,3 +118,71 @@ fn normalize_os_release_name(name: &str) -> Cow<str> {
         Cow::Borrowed(name)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use tempdir::TempDir;
+
+    /// Runs the test in a thread with a mount namespace of its own, so that its mounts are not
+    /// visible to the rest of the system. The test is skipped without the privileges to do so.
+    fn in_namespace<F: FnOnce() + Send + 'static>(test: F) {
+        thread::spawn(move || {
+            if unsafe { libc::unshare(libc::CLONE_NEWNS) } == -1 {
+                eprintln!("skipping test: {}", io::Error::last_os_error());
+                return;
+            }
+
+            set_propagation(Path::new("/"), Propagation::Private).unwrap();
+            test();
+        })
+        .join()
+        .unwrap();
+    }
+
+    fn tmpfs(target: &Path) -> UnmountDrop<Mount> {
+        Mount::new("tmpfs", target, "tmpfs", MountFlags::empty(), None)
+            .unwrap()
+            .into_unmount_drop(UnmountFlags::DETACH)
+    }
+
+    /// Binds a shared mount, as the mounts of the live session are, into a chroot, and mounts
+    /// over a directory of the bind mount, which must not be seen by the source.
+    fn bind_and_mount_within(propagation: Propagation) {
+        let tempdir = TempDir::new("distinst").unwrap();
+        let source = tempdir.path().join("source");
+        let target = tempdir.path().join("chroot/target");
+        fs::create_dir_all(&source).unwrap();
+
+        let _source = tmpfs(&source);
+        let path = CString::new(source.as_os_str().as_bytes()).unwrap();
+        let flags = libc::MS_SHARED | libc::MS_REC;
+        let result =
+            unsafe { libc::mount(ptr::null(), path.as_ptr(), ptr::null(), flags, ptr::null()) };
+        assert_eq!(result, 0, "{}", io::Error::last_os_error());
+
+        fs::create_dir(source.join("nested")).unwrap();
+        fs::write(source.join("nested/file"), "live").unwrap();
+
+        let bind = mount_bind_if_exists(&source, &target, propagation).unwrap().unwrap();
+        let within = tmpfs(&target.join("nested"));
+        fs::write(target.join("nested/file"), "chroot").unwrap();
+        assert_eq!(fs::read_to_string(source.join("nested/file")).unwrap(), "live");
+
+        drop(within);
+        drop(bind);
+        assert_eq!(fs::read_to_string(source.join("nested/file")).unwrap(), "live");
+        assert!(!target.join("nested").exists());
+    }
+
+    #[test]
+    fn private_bind_does_not_propagate() {
+        in_namespace(|| bind_and_mount_within(Propagation::Private));
+    }
+
+    #[test]
+    fn slave_bind_does_not_propagate() {
+        in_namespace(|| bind_and_mount_within(Propagation::Slave));
+    }
+}
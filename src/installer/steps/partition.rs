@@ -1,30 +1,51 @@
 use crate::disks::{operations::FormatPartitions, Disks};
 use crate::errors::IoContext;
-use crate::external::{blockdev, pvs, vgactivate, vgdeactivate};
+use crate::external::{pvs, reread_partitions, udev_settle, vgactivate, vgdeactivate};
 use itertools::Itertools;
-use rayon::{self, prelude::*};
-use std::{collections::BTreeMap, io, path::PathBuf, thread::sleep, time::Duration};
+use rayon::prelude::*;
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    thread,
+};
 
 pub fn partition<F: FnMut(i32)>(disks: &mut Disks, mut callback: F) -> io::Result<()> {
     let (pvs_result, commit_result): (
         io::Result<BTreeMap<PathBuf, Option<String>>>,
         io::Result<()>,
-    ) = rayon::join(
-        || {
-            // This collection of physical volumes and their optional volume groups
-            // will be used to obtain a list of volume groups associated with our
-            // modified partitions.
-            pvs().with_context(|why| format!("failed to get PVS map: {}", why))
-        },
-        || {
-            // Perform layout changes serially, due to libparted thread safety issues,
-            // and collect a list of partitions to format which can be done in parallel.
-            // Once partitions have been formatted in parallel, reload the disk configuration.
+    ) = thread::scope(|scope| {
+        // This collection of physical volumes and their optional volume groups
+        // will be used to obtain a list of volume groups associated with our
+        // modified partitions.
+        let pvs_thread =
+            scope.spawn(|| pvs().with_context(|why| format!("failed to get PVS map: {}", why)));
+
+        // Perform layout changes serially, due to libparted thread safety issues,
+        // and collect a list of partitions to format which can be done in parallel.
+        // Once partitions have been formatted in parallel, reload the disk configuration.
+        //
+        // This is done on the calling thread so that the progress of moving and
+        // resizing partitions can be reported through the step callback, which
+        // occupies the first half of the partitioning step.
+        let commit = (|| -> io::Result<()> {
             let mut partitions_to_format = FormatPartitions(Vec::new());
-            for disk in disks.get_physical_devices_mut() {
+            let ndisks = disks.get_physical_devices().len().max(1) as u64;
+            for (id, disk) in disks.get_physical_devices_mut().iter_mut().enumerate() {
                 info!("{}: Committing changes to disk", disk.path().display());
-                if let Some(partitions) =
-                    disk.commit().with_context(|why| format!("disk commit error: {}", why))?
+                let mut last_percent = None;
+                let progress = |path: &Path, done: u64, total: u64| {
+                    let percent = done.min(total) * 100 / total.max(1);
+                    if last_percent != Some(percent) {
+                        info!("{}: {}% complete", path.display(), percent);
+                        last_percent = Some(percent);
+                        callback(((id as u64 * 100 + percent) * 50 / (ndisks * 100)) as i32);
+                    }
+                };
+
+                if let Some(partitions) = disk
+                    .commit_with_progress(progress)
+                    .with_context(|why| format!("disk commit error: {}", why))?
                 {
                     partitions_to_format.0.extend_from_slice(&partitions.0);
                 }
@@ -33,12 +54,18 @@ pub fn partition<F: FnMut(i32)>(disks: &mut Disks, mut callback: F) -> io::Resul
             partitions_to_format.format()?;
 
             disks.physical.iter_mut().map(|disk| disk.reload().map_err(io::Error::from)).collect()
-        },
-    );
+        })();
+
+        let pvs = pvs_thread.join().unwrap_or_else(|_| {
+            Err(io::Error::new(io::ErrorKind::Other, "failed to get PVS map: thread panicked"))
+        });
+
+        (pvs, commit)
+    });
 
     let pvs = commit_result.and(pvs_result)?;
 
-    callback(25);
+    callback(50);
 
     // Utilizes the physical volume collection to generate a vector of volume
     // groups which we will need to deactivate pre-`blockdev`, and will be
@@ -56,18 +83,20 @@ pub fn partition<F: FnMut(i32)>(disks: &mut Disks, mut callback: F) -> io::Resul
     vgs.iter().map(|vg| vgdeactivate(vg)).collect::<io::Result<()>>()?;
 
     // Ensure that the logical volumes have had time to deactivate.
-    sleep(Duration::from_secs(1));
-    callback(50);
+    let _ = udev_settle();
+    callback(65);
 
     // This is to ensure that everything's been written and the OS is ready to
     // proceed.
     disks.physical.par_iter().for_each(|disk| {
-        let _ = blockdev(&disk.path(), &["--flushbufs", "--rereadpt"]);
+        if let Err(why) = reread_partitions(&disk.path()) {
+            warn!("{}: failed to re-read partition table: {}", disk.path().display(), why);
+        }
     });
 
-    // Give a bit of time to ensure that logical volumes can be re-activated.
-    sleep(Duration::from_secs(1));
-    callback(75);
+    // Ensure that logical volumes can be re-activated.
+    let _ = udev_settle();
+    callback(80);
 
     // Reactivate the logical volumes.
     vgs.iter().map(|vg| vgactivate(vg)).collect::<io::Result<()>>()?;
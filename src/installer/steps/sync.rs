@@ -0,0 +1,349 @@
+//! Synchronizes an existing root with the image, rather than extracting the image over an
+//! emptied root, for refresh installs whose `/home` is on the root partition.
+//!
+//! Files whose size and modification time match those of the image are left as they are, and
+//! files which are not in the image are removed. Preserved paths are never touched, and files
+//! are never removed from the other file systems which are mounted within the root.
+
+use crate::errors::IoContext;
+use crate::squashfs::tarball::Compression;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{CString, OsString},
+    fs::{self, Metadata},
+    io,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{symlink, MetadataExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+use sys_mount::*;
+use tempdir::TempDir;
+
+/// Synchronizes the tree at `root` with the squashfs `image`, reporting the progress as a
+/// percentage. The `preserve` paths are relative to the root.
+pub fn sync_image<F: FnMut(i32)>(
+    image: &Path,
+    root: &Path,
+    preserve: &[PathBuf],
+    callback: F,
+) -> io::Result<()> {
+    if Compression::detect(image).is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "paths can only be preserved while extracting a squashfs image",
+        ));
+    }
+
+    let mounted = TempDir::new("distinst-image")?;
+    let _mount = Mount::new(image, mounted.path(), "squashfs", MountFlags::RDONLY, None)
+        .with_context(|why| format!("failed to mount {}: {}", image.display(), why))?
+        .into_unmount_drop(UnmountFlags::DETACH);
+
+    info!("synchronizing {} with {}, preserving {:?}", root.display(), image.display(), preserve);
+    sync_tree(mounted.path(), root, preserve, callback)
+}
+
+/// Synchronizes the tree at `target` with the tree at `source`.
+fn sync_tree<F: FnMut(i32)>(
+    source: &Path,
+    target: &Path,
+    preserve: &[PathBuf],
+    callback: F,
+) -> io::Result<()> {
+    let preserve = preserve.iter().map(|path| relative(path).to_path_buf()).collect::<Vec<_>>();
+    let mut synchronizer = Synchronizer {
+        source,
+        target,
+        device:   fs::metadata(target)?.dev(),
+        total:    count(source, Path::new(""), &preserve)?.max(1),
+        preserve,
+        links:    HashMap::new(),
+        synced:   0,
+        last:     -1,
+        callback,
+    };
+
+    synchronizer.directory(Path::new(""))
+}
+
+/// A path which is relative to the root, without its leading `/`.
+fn relative(path: &Path) -> &Path { path.strip_prefix("/").unwrap_or(path) }
+
+/// The number of entries which are synchronized within a directory of the `source`.
+fn count(source: &Path, relative: &Path, preserve: &[PathBuf]) -> io::Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(source.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        if preserve.contains(&path) {
+            continue;
+        }
+
+        total += 1;
+        if entry.file_type()?.is_dir() {
+            total += count(source, &path, preserve)?;
+        }
+    }
+
+    Ok(total)
+}
+
+struct Synchronizer<'a, F> {
+    source:   &'a Path,
+    target:   &'a Path,
+    /// The device of the root, whose files are removed when they are not in the image.
+    device:   u64,
+    preserve: Vec<PathBuf>,
+    /// The first path of each file in the image which has several links, by its inode.
+    links:    HashMap<(u64, u64), PathBuf>,
+    total:    u64,
+    synced:   u64,
+    last:     i32,
+    callback: F,
+}
+
+impl<'a, F: FnMut(i32)> Synchronizer<'a, F> {
+    fn progress(&mut self) {
+        self.synced += 1;
+        let progress = (self.synced * 100 / self.total).min(100) as i32;
+        if progress != self.last {
+            (self.callback)(progress);
+            self.last = progress;
+        }
+    }
+
+    fn directory(&mut self, relative: &Path) -> io::Result<()> {
+        let (source, target) = (self.source.join(relative), self.target.join(relative));
+        let mut names = HashSet::new();
+        for entry in fs::read_dir(&source)? {
+            let name = entry?.file_name();
+            let path = relative.join(&name);
+            names.insert(name);
+            if !self.preserve.contains(&path) {
+                self.entry(&path)?;
+            }
+        }
+
+        // Other file systems, such as the ESP, are only written to, as extraction would.
+        for entry in fs::read_dir(&target)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let stale = !names.contains(&name) && !self.preserve.contains(&relative.join(&name));
+            if stale && entry.metadata()?.dev() == self.device {
+                remove(&entry.path())?;
+            }
+        }
+
+        set_metadata(&fs::symlink_metadata(&source)?, &source, &target)
+    }
+
+    fn entry(&mut self, relative: &Path) -> io::Result<()> {
+        let (source, target) = (self.source.join(relative), self.target.join(relative));
+        let metadata = fs::symlink_metadata(&source)?;
+        let existing = fs::symlink_metadata(&target).ok();
+        let file_type = metadata.file_type();
+
+        if file_type.is_dir() {
+            if !existing.as_ref().map_or(false, Metadata::is_dir) {
+                remove(&target)?;
+                fs::create_dir(&target)?;
+            }
+
+            self.directory(relative)?;
+            self.progress();
+            return Ok(());
+        }
+
+        if metadata.nlink() > 1 {
+            let inode = (metadata.dev(), metadata.ino());
+            if let Some(original) = self.links.get(&inode) {
+                let original = self.target.join(original);
+                if !existing.map_or(false, |existing| is_same_file(&existing, &original)) {
+                    remove(&target)?;
+                    fs::hard_link(&original, &target)?;
+                }
+
+                self.progress();
+                return Ok(());
+            }
+
+            self.links.insert(inode, relative.to_path_buf());
+        }
+
+        let unchanged = existing.as_ref().map_or(false, |existing| {
+            if file_type.is_symlink() {
+                existing.file_type().is_symlink()
+                    && fs::read_link(&source).ok() == fs::read_link(&target).ok()
+            } else {
+                existing.file_type() == file_type
+                    && existing.len() == metadata.len()
+                    && existing.mtime() == metadata.mtime()
+                    && existing.mtime_nsec() == metadata.mtime_nsec()
+            }
+        });
+
+        if !unchanged {
+            remove(&target)?;
+            if file_type.is_symlink() {
+                symlink(fs::read_link(&source)?, &target)?;
+            } else if file_type.is_file() {
+                fs::copy(&source, &target).with_context(|why| {
+                    format!("failed to copy {} from the image: {}", relative.display(), why)
+                })?;
+            } else {
+                let path = cstring(&target)?;
+                cvt(unsafe { libc::mknod(path.as_ptr(), metadata.mode(), metadata.rdev()) })?;
+            }
+        }
+
+        set_metadata(&metadata, &source, &target)?;
+        self.progress();
+        Ok(())
+    }
+}
+
+fn is_same_file(existing: &Metadata, original: &Path) -> bool {
+    fs::symlink_metadata(original).map_or(false, |original| {
+        (original.dev(), original.ino()) == (existing.dev(), existing.ino())
+    })
+}
+
+/// Removes a file or directory, if it exists.
+fn remove(path: &Path) -> io::Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(ref metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(why) => Err(why),
+    };
+
+    result.with_context(|why| format!("failed to remove {}: {}", path.display(), why))
+}
+
+fn cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))
+}
+
+fn cvt(result: libc::c_int) -> io::Result<()> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Applies the ownership, extended attributes, permissions, and modification time of the
+/// `source` to the `target`. Ownership is applied first, as changing it drops file
+/// capabilities and the setuid bit.
+fn set_metadata(metadata: &Metadata, source: &Path, target: &Path) -> io::Result<()> {
+    let (source_path, target_path) = (cstring(source)?, cstring(target)?);
+    cvt(unsafe { libc::lchown(target_path.as_ptr(), metadata.uid(), metadata.gid()) })?;
+
+    for name in xattrs(&source_path)? {
+        let name = CString::new(name.as_bytes()).expect("xattr names are split at NUL bytes");
+        let value = xattr(&source_path, &name)?;
+        let buffer = value.as_ptr() as *const libc::c_void;
+        let result =
+            unsafe { libc::lsetxattr(target_path.as_ptr(), name.as_ptr(), buffer, value.len(), 0) };
+
+        if let Err(why) = cvt(result) {
+            warn!("failed to set the {:?} xattr of {}: {}", name, target.display(), why);
+        }
+    }
+
+    if !metadata.file_type().is_symlink() {
+        fs::set_permissions(target, fs::Permissions::from_mode(metadata.mode() & 0o7777))?;
+    }
+
+    let time = |sec: i64, nsec: i64| libc::timespec {
+        tv_sec:  sec as libc::time_t,
+        tv_nsec: nsec as libc::c_long,
+    };
+
+    let times = [
+        time(metadata.atime(), metadata.atime_nsec()),
+        time(metadata.mtime(), metadata.mtime_nsec()),
+    ];
+
+    let flags = libc::AT_SYMLINK_NOFOLLOW;
+    cvt(unsafe { libc::utimensat(libc::AT_FDCWD, target_path.as_ptr(), times.as_ptr(), flags) })
+}
+
+/// The names of the extended attributes of a file.
+fn xattrs(path: &CString) -> io::Result<Vec<OsString>> {
+    let size = unsafe { libc::llistxattr(path.as_ptr(), std::ptr::null_mut(), 0) };
+    if size <= 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut names = vec![0u8; size as usize];
+    let buffer = names.as_mut_ptr() as *mut libc::c_char;
+    let size = unsafe { libc::llistxattr(path.as_ptr(), buffer, names.len()) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    names.truncate(size as usize);
+    Ok(names
+        .split(|&byte| byte == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| std::ffi::OsStr::from_bytes(name).to_owned())
+        .collect())
+}
+
+/// The value of an extended attribute of a file.
+fn xattr(path: &CString, name: &CString) -> io::Result<Vec<u8>> {
+    let size = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut value = vec![0u8; size as usize];
+    let buffer = value.as_mut_ptr() as *mut libc::c_void;
+    let size = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), buffer, value.len()) };
+
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    value.truncate(size as usize);
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preserves_paths() {
+        let tempdir = TempDir::new("distinst-sync").unwrap();
+        let (source, target) = (tempdir.path().join("image"), tempdir.path().join("root"));
+        for directory in &["image/etc", "image/home", "root/etc", "root/home/user", "root/opt"] {
+            fs::create_dir_all(tempdir.path().join(directory)).unwrap();
+        }
+
+        fs::write(source.join("etc/hostname"), "pop-os\n").unwrap();
+        fs::hard_link(source.join("etc/hostname"), source.join("etc/hostname.link")).unwrap();
+        symlink("etc/hostname", source.join("hostname")).unwrap();
+        fs::write(target.join("etc/hostname"), "old\n").unwrap();
+        fs::write(target.join("home/user/notes"), "kept").unwrap();
+        fs::write(target.join("opt/stale"), "").unwrap();
+
+        let mut progress = Vec::new();
+        sync_tree(&source, &target, &[PathBuf::from("/home")], |p| progress.push(p)).unwrap();
+        assert_eq!(progress.last(), Some(&100));
+
+        assert_eq!(fs::read_to_string(target.join("etc/hostname")).unwrap(), "pop-os\n");
+        assert_eq!(fs::read_link(target.join("hostname")).unwrap(), Path::new("etc/hostname"));
+        assert_eq!(fs::read_to_string(target.join("home/user/notes")).unwrap(), "kept");
+        assert!(!target.join("opt").exists());
+
+        let hostname = fs::metadata(target.join("etc/hostname")).unwrap();
+        let link = fs::metadata(target.join("etc/hostname.link")).unwrap();
+        assert_eq!(hostname.ino(), link.ino());
+        assert_eq!(hostname.mtime(), fs::metadata(source.join("etc/hostname")).unwrap().mtime());
+    }
+}
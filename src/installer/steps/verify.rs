@@ -0,0 +1,86 @@
+use crate::errors::IoContext;
+use crate::squashfs::{manifest, tarball::Compression, verify};
+use crate::{ExtractVerification, ImageVerification};
+use std::{fs, io, path::Path};
+use sys_mount::*;
+use tempdir::TempDir;
+
+/// The most mismatches which are described by the error of the extract step. Each of them is
+/// logged.
+const REPORTED_MISMATCHES: usize = 5;
+
+/// Verifies the image before the disks are modified, so that corrupted installation media is
+/// found while the existing system is still intact.
+pub fn verify<F: FnMut(i32)>(
+    image: &Path,
+    verification: &ImageVerification,
+    mut callback: F,
+) -> io::Result<()> {
+    info!("Verifying {}", image.display());
+    match verification {
+        ImageVerification::Sha256Sums(ref sums) => verify::sha256sums(image, sums, callback),
+        ImageVerification::Signature { ref signature, ref keyring } => {
+            verify::signature(image, signature, keyring)?;
+            callback(100);
+            Ok(())
+        }
+    }
+    .with_context(|why| format!("image verification failed: {}", why))
+}
+
+/// Compares the files which were extracted to `root` with the digests of the files of the
+/// image, reporting the progress as a percentage. The extracted files are synced and dropped
+/// from the page cache before they are read, so that what was written to the disk is compared.
+pub fn verify_extraction<F: FnMut(i32)>(
+    image: &Path,
+    root: &Path,
+    verification: &ExtractVerification,
+    mut callback: F,
+) -> io::Result<()> {
+    info!("Verifying the files extracted to {}", root.display());
+    let (entries, offset) = match verification {
+        ExtractVerification::Manifest(ref path) => {
+            let manifest = fs::read_to_string(path)
+                .with_context(|why| format!("failed to read {}: {}", path.display(), why))?;
+            (manifest::parse(&manifest)?, 0)
+        }
+        ExtractVerification::Image if Compression::detect(image).is_some() => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the files of a tarball can only be verified against a manifest",
+            ));
+        }
+        ExtractVerification::Image => {
+            let mounted = TempDir::new("distinst-image")?;
+            let _mount = Mount::new(image, mounted.path(), "squashfs", MountFlags::RDONLY, None)
+                .with_context(|why| format!("failed to mount {}: {}", image.display(), why))?
+                .into_unmount_drop(UnmountFlags::DETACH);
+
+            (manifest::generate(mounted.path(), |percent| callback(percent / 2))?, 50)
+        }
+    };
+
+    let scale = 100 - offset;
+    let mismatches = manifest::verify(root, &entries, |percent| {
+        callback(offset + percent * scale / 100)
+    })?;
+
+    for mismatch in &mismatches {
+        error!("extracted file {}", mismatch);
+    }
+
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+
+    let reported = mismatches.iter().take(REPORTED_MISMATCHES).map(ToString::to_string);
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "{} of {} extracted files do not match the image: {}",
+            mismatches.len(),
+            entries.len(),
+            reported.collect::<Vec<_>>().join("; ")
+        ),
+    ))
+}
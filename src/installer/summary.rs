@@ -0,0 +1,240 @@
+//! A summary of what a reinstall changed, comparing the system that was replaced with the
+//! system that was installed, so that support may see exactly what an upgrade changed.
+
+use disk_types::FileSystem;
+use os_release::OsRelease;
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+/// The release, kernel, and packages of an installed system.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SystemSnapshot {
+    /// The `PRETTY_NAME` of the release, such as `Pop!_OS 22.04 LTS`.
+    pub release:  Option<String>,
+    /// The version of the default kernel, which `/boot/vmlinuz` links to.
+    pub kernel:   Option<String>,
+    pub root_fs:  Option<FileSystem>,
+    /// The installed packages, by `name:architecture`, and their versions.
+    pub packages: BTreeMap<String, String>,
+}
+
+impl SystemSnapshot {
+    /// Reads the snapshot of the system whose root file system is mounted at `root`.
+    pub fn read(root: &Path, root_fs: Option<FileSystem>) -> SystemSnapshot {
+        let kernel = fs::read_link(root.join("boot/vmlinuz")).ok().and_then(|link| {
+            let name = link.file_name()?.to_str()?;
+            name.strip_prefix("vmlinuz-").map(String::from)
+        });
+
+        SystemSnapshot {
+            release:  OsRelease::new_from(root.join("etc/os-release"))
+                .ok()
+                .map(|release| release.pretty_name),
+            kernel,
+            root_fs,
+            packages: fs::read_to_string(root.join("var/lib/dpkg/status"))
+                .map(|status| installed_packages(&status))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// A package which was added, removed, or changed to another version.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageChange {
+    pub name: String,
+    pub old:  Option<String>,
+    pub new:  Option<String>,
+}
+
+/// The differences between the system which was replaced, and the system which was installed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstallSummary {
+    pub old_release: Option<String>,
+    pub new_release: Option<String>,
+    pub old_kernel:  Option<String>,
+    pub new_kernel:  Option<String>,
+    pub old_root_fs: Option<FileSystem>,
+    pub new_root_fs: Option<FileSystem>,
+    /// Packages which differ, ordered by name.
+    pub packages:    Vec<PackageChange>,
+}
+
+impl InstallSummary {
+    pub fn new(old: &SystemSnapshot, new: &SystemSnapshot) -> InstallSummary {
+        let mut packages = Vec::new();
+        for (name, version) in &old.packages {
+            let new_version = new.packages.get(name);
+            if new_version != Some(version) {
+                packages.push(PackageChange {
+                    name: name.clone(),
+                    old:  Some(version.clone()),
+                    new:  new_version.cloned(),
+                });
+            }
+        }
+
+        for (name, version) in &new.packages {
+            if !old.packages.contains_key(name) {
+                packages.push(PackageChange {
+                    name: name.clone(),
+                    old:  None,
+                    new:  Some(version.clone()),
+                });
+            }
+        }
+
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+        InstallSummary {
+            old_release: old.release.clone(),
+            new_release: new.release.clone(),
+            old_kernel:  old.kernel.clone(),
+            new_kernel:  new.kernel.clone(),
+            old_root_fs: old.root_fs,
+            new_root_fs: new.root_fs,
+            packages,
+        }
+    }
+
+    /// The number of packages which were added, removed, and changed to another version.
+    pub fn package_counts(&self) -> (usize, usize, usize) {
+        self.packages.iter().fold((0, 0, 0), |(added, removed, changed), package| {
+            match (&package.old, &package.new) {
+                (None, _) => (added + 1, removed, changed),
+                (_, None) => (added, removed + 1, changed),
+                _ => (added, removed, changed + 1),
+            }
+        })
+    }
+
+    /// The release, kernel, root file system, and package counts, one per line.
+    pub fn headline(&self) -> Vec<String> {
+        let (added, removed, changed) = self.package_counts();
+        vec![
+            format!("release: {}", change(&self.old_release, &self.new_release)),
+            format!("kernel: {}", change(&self.old_kernel, &self.new_kernel)),
+            format!("root file system: {}", change(&self.old_root_fs, &self.new_root_fs)),
+            format!("packages: {} added, {} removed, {} changed", added, removed, changed),
+        ]
+    }
+}
+
+impl fmt::Display for InstallSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.headline() {
+            writeln!(f, "{}", line)?;
+        }
+
+        for package in &self.packages {
+            match (&package.old, &package.new) {
+                (None, Some(new)) => writeln!(f, "+ {} {}", package.name, new)?,
+                (Some(old), None) => writeln!(f, "- {} {}", package.name, old)?,
+                (Some(old), Some(new)) => writeln!(f, "~ {} {} -> {}", package.name, old, new)?,
+                (None, None) => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Describes a value which may have changed, such as `ext4 -> btrfs`.
+fn change<T: fmt::Display + PartialEq>(old: &Option<T>, new: &Option<T>) -> String {
+    let describe =
+        |value: &Option<T>| value.as_ref().map_or_else(|| "unknown".into(), ToString::to_string);
+    if old == new {
+        describe(new)
+    } else {
+        [describe(old), describe(new)].join(" -> ")
+    }
+}
+
+/// The packages, and their versions, which the dpkg `status` file reports as installed.
+/// Packages are keyed by their name and architecture, as multiarch packages may be installed
+/// for several architectures at once.
+fn installed_packages(status: &str) -> BTreeMap<String, String> {
+    let mut packages = BTreeMap::new();
+    for paragraph in status.split("\n\n") {
+        let (mut name, mut arch, mut version, mut installed) = (None, None, None, false);
+        for line in paragraph.lines() {
+            if let Some(value) = line.strip_prefix("Package: ") {
+                name = Some(value.trim());
+            } else if let Some(value) = line.strip_prefix("Architecture: ") {
+                arch = Some(value.trim());
+            } else if let Some(value) = line.strip_prefix("Version: ") {
+                version = Some(value.trim());
+            } else if let Some(value) = line.strip_prefix("Status: ") {
+                installed = value.trim().ends_with(" installed");
+            }
+        }
+
+        if let (Some(name), Some(version), true) = (name, version, installed) {
+            let key = match arch {
+                Some(arch) => [name, ":", arch].concat(),
+                None => name.to_owned(),
+            };
+
+            packages.insert(key, version.to_owned());
+        }
+    }
+
+    packages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: &str = "Package: bash\nStatus: install ok installed\nArchitecture: amd64\n\
+                          Version: 5.1-6\n\n\
+                          Package: gedit\nStatus: deinstall ok config-files\nVersion: 41.0\n\n\
+                          Package: linux-system76\nStatus: install ok installed\nVersion: 6.2.6\n";
+
+    #[test]
+    fn packages() {
+        let packages = installed_packages(STATUS);
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages["bash:amd64"], "5.1-6");
+        assert_eq!(packages["linux-system76"], "6.2.6");
+
+        let multiarch = "Package: libc6\nStatus: install ok installed\nArchitecture: amd64\n\
+                         Version: 2.35\n\n\
+                         Package: libc6\nStatus: install ok installed\nArchitecture: i386\n\
+                         Version: 2.35\n";
+        let packages = installed_packages(multiarch);
+        assert_eq!(packages.keys().collect::<Vec<_>>(), vec!["libc6:amd64", "libc6:i386"]);
+    }
+
+    #[test]
+    fn summary() {
+        let old = SystemSnapshot {
+            release:  Some("Pop!_OS 21.10".into()),
+            kernel:   Some("5.15.5-76051505-generic".into()),
+            root_fs:  Some(FileSystem::Ext4),
+            packages: installed_packages(STATUS),
+        };
+
+        let mut new = SystemSnapshot {
+            release: Some("Pop!_OS 22.04 LTS".into()),
+            root_fs: Some(FileSystem::Ext4),
+            ..old.clone()
+        };
+
+        new.packages.remove("bash:amd64");
+        new.packages.insert("linux-system76".into(), "6.4.6".into());
+        new.packages.insert("zsh".into(), "5.8.1".into());
+
+        let summary = InstallSummary::new(&old, &new);
+        assert_eq!(summary.package_counts(), (1, 1, 1));
+        assert_eq!(
+            summary.to_string(),
+            "release: Pop!_OS 21.10 -> Pop!_OS 22.04 LTS\n\
+             kernel: 5.15.5-76051505-generic\n\
+             root file system: ext4\n\
+             packages: 1 added, 1 removed, 1 changed\n\
+             - bash:amd64 5.1-6\n\
+             ~ linux-system76 6.2.6 -> 6.4.6\n\
+             + zsh 5.8.1\n"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! A working directory which is created for each installation, and collects everything that
+//! is needed to debug it: a snapshot of the configuration, the generated fstab and crypttab,
+//! a transcript of the log, and a receipt of each step that was executed.
+
+use super::Config;
+use crate::chroot::Command;
+use crate::disks::Disks;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Where working directories are created, on the live system and within the target.
+const WORK_ROOT: &str = "var/log/distinst";
+
+/// The transcript of the installation that is currently running, which receives every log
+/// message, including the commands that were executed and their output.
+static TRANSCRIPT: Mutex<Option<File>> = Mutex::new(None);
+
+/// Records a log message in the transcript of the current installation, if there is one.
+pub(crate) fn record(message: &str) {
+    if let Some(ref mut transcript) = *transcript() {
+        let _ = writeln!(transcript, "{}", message);
+    }
+}
+
+fn transcript() -> MutexGuard<'static, Option<File>> {
+    TRANSCRIPT.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// A directory in `/var/log/distinst`, named after the time that the installation began.
+///
+/// Artifacts are written on a best-effort basis: failing to record one will be logged, but
+/// will never cause the installation to fail.
+#[derive(Debug)]
+pub struct WorkDir {
+    path: PathBuf,
+}
+
+impl WorkDir {
+    /// Creates the working directory, and begins recording the transcript into it.
+    pub fn new() -> io::Result<Self> {
+        let path = Path::new("/").join(WORK_ROOT).join(timestamp(SystemTime::now()));
+        fs::create_dir_all(&path)?;
+        *transcript() = Some(File::create(path.join("transcript.log"))?);
+        info!("recording installation artifacts in {}", path.display());
+        Ok(WorkDir { path })
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// Records the configuration of the installation, and the disks it will be applied to.
+    pub fn snapshot(&self, config: &Config, disks: &Disks) {
+        self.write("config", config_snapshot(config));
+        self.write("disks", format!("{:#?}\n", disks));
+    }
+
+    /// Copies a file that was generated for the new install, such as `/etc/fstab`.
+    pub fn capture(&self, name: &str, source: &Path) {
+        if let Err(why) = fs::copy(source, self.path.join(name)) {
+            warn!("failed to capture {} from {}: {}", name, source.display(), why);
+        }
+    }
+
+    /// Writes an artifact into the working directory.
+    pub fn write<C: AsRef<[u8]>>(&self, name: &str, contents: C) {
+        if let Err(why) = fs::write(self.path.join(name), contents) {
+            warn!("failed to write {} to {}: {}", name, self.path.display(), why);
+        }
+    }
+
+    /// Appends a line to the receipt, which records the outcome of each step.
+    pub fn receipt(&self, message: &str) {
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path.join("receipt"))
+            .and_then(|mut file| writeln!(file, "{} {}", timestamp(SystemTime::now()), message));
+
+        if let Err(why) = result {
+            warn!("failed to write receipt to {}: {}", self.path.display(), why);
+        }
+    }
+
+    /// Copies the working directory into the same location within the new install.
+    pub fn copy_to(&self, target: &Path) {
+        let copy = || -> io::Result<()> {
+            let destination = target.join(WORK_ROOT).join(self.path.file_name().unwrap());
+            fs::create_dir_all(&destination)?;
+            for entry in fs::read_dir(&self.path)? {
+                let entry = entry?;
+                fs::copy(entry.path(), destination.join(entry.file_name()))?;
+            }
+
+            Ok(())
+        };
+
+        if let Err(why) = copy() {
+            warn!("failed to copy installation artifacts to {}: {}", target.display(), why);
+        }
+    }
+
+    /// Packages the working directory into a gzipped tarball beside it, which may be attached
+    /// to a bug report.
+    pub fn bundle(&self) -> io::Result<PathBuf> {
+        let tarball = self.path.with_extension("tar.gz");
+        Command::new("tar")
+            .arg("-czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(self.path.parent().expect("working directory has a parent"))
+            .arg(self.path.file_name().expect("working directory has a name"))
+            .run()?;
+
+        Ok(tarball)
+    }
+
+    /// Records the result of the installation, and stops recording the transcript.
+    pub fn finish(&self, result: &io::Result<()>) {
+        match *result {
+            Ok(()) => self.receipt("installation succeeded"),
+            Err(ref why) => self.receipt(&format!("installation failed: {}", why)),
+        }
+
+        *transcript() = None;
+    }
+}
+
+/// Describes the configuration, excluding the callbacks that supply user credentials.
+fn config_snapshot(config: &Config) -> String {
+    format!(
+        "hostname = {}\nkeyboard_layout = {}\nkeyboard_model = {:?}\nkeyboard_variant = {:?}\n\
+         old_root = {:?}\nlang = {}\nadditional_locales = {:?}\nlocale_overrides = {:?}\n\
+         remove = {}\nminimal_remove = {:?}\nsquashfs = {}\n\
+         bios_device = {:?}\nflags = {:?}\nfiles = {:?}\ndconf = {:?}\nhosts = {:?}\n\
+         rtc_in_localtime = {:?}\ncleanup = {:?}\npost_install_scripts = {:?}\n\
+         first_boot_services = {:?}\noffline_repository = {:?}\n\
+         flatpak_remotes = {:?}\nflatpaks = {:?}\nkernel_variant = {:?}\n\
+         image_verification = {:?}\nextract_verification = {:?}\nextract_options = {:?}\n\
+         file_backup = {:?}\n\
+         esp_policy = {:?}\ntrim_policy = {:?}\ntang_binding = {:?}\n",
+        config.hostname,
+        config.keyboard_layout,
+        config.keyboard_model,
+        config.keyboard_variant,
+        config.old_root,
+        config.lang,
+        config.additional_locales,
+        config.locale_overrides,
+        config.remove,
+        config.minimal_remove,
+        config.squashfs,
+        config.bios_device,
+        config.flags,
+        config.files.iter().map(|file| &file.path).collect::<Vec<_>>(),
+        config.dconf,
+        config.hosts,
+        config.rtc_in_localtime,
+        config.cleanup,
+        config.post_install_scripts,
+        config.first_boot_services.iter().map(|service| &service.name).collect::<Vec<_>>(),
+        config.offline_repository,
+        config.flatpak_remotes,
+        config.flatpaks,
+        config.kernel_variant,
+        config.image_verification,
+        config.extract_verification,
+        config.extract_options,
+        config.file_backup,
+        config.esp_policy,
+        config.trim_policy,
+        config.tang_binding
+    )
+}
+
+/// Formats the time as `YYYYMMDD-HHMMSS`, in UTC.
+fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+
+    // Converts days since the epoch into a civil date.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn timestamps() {
+        let at = |secs| timestamp(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "19700101-000000");
+        assert_eq!(at(951_782_400), "20000229-000000");
+        assert_eq!(at(1_792_152_305), "20261016-120505");
+    }
+}
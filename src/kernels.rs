@@ -0,0 +1,170 @@
+//! Kernels which are available in the casper directory of the installation media.
+//!
+//! The default kernel is `vmlinuz`, and other variants, such as the HWE or lowlatency
+//! kernels, are prefixed with the name of their variant, as in `hwe-vmlinuz`. Each kernel may
+//! be given an EFI stub's extension. A variant other than the default is installed by its
+//! meta-package, with the modules that match it.
+
+use os_release::OsRelease;
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// The casper directory of the installation media.
+pub const CASPER: &str = "/cdrom/casper";
+
+/// The variant of the kernel which the installed system is given by default.
+pub const DEFAULT_VARIANT: &str = "generic";
+
+/// A kernel in the casper directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CasperKernel {
+    /// The variant of the kernel, such as `generic` or `hwe`.
+    pub variant: String,
+    pub vmlinuz: PathBuf,
+}
+
+impl CasperKernel {
+    /// The release of the kernel, such as `6.8.0-45-generic`, from the header of its image.
+    pub fn release(&self) -> io::Result<String> { kernel_release(&self.vmlinuz) }
+}
+
+/// Each kernel in the `casper` directory, with the default kernel first.
+pub fn available<P: AsRef<Path>>(casper: P) -> io::Result<Vec<CasperKernel>> {
+    let casper = casper.as_ref();
+    let mut kernels = Vec::new();
+    for entry in fs::read_dir(casper)? {
+        let name = entry?.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+
+        let stem = name.trim_end_matches(".efi");
+        let prefix = match stem.strip_suffix("vmlinuz") {
+            Some(prefix) if prefix.is_empty() || prefix.ends_with('-') => prefix,
+            _ => continue,
+        };
+
+        kernels.push(CasperKernel {
+            variant: prefix.strip_suffix('-').unwrap_or(DEFAULT_VARIANT).to_owned(),
+            vmlinuz: casper.join(name),
+        });
+    }
+
+    kernels.sort_by(|a, b| {
+        (a.variant != DEFAULT_VARIANT, &a.variant).cmp(&(b.variant != DEFAULT_VARIANT, &b.variant))
+    });
+
+    kernels.dedup_by(|a, b| a.variant == b.variant);
+    Ok(kernels)
+}
+
+/// The kernel of the `variant` in the `casper` directory.
+pub fn find<P: AsRef<Path>>(casper: P, variant: &str) -> io::Result<CasperKernel> {
+    let kernels = available(casper)?;
+    let variants = kernels.iter().map(|kernel| kernel.variant.as_str()).collect::<Vec<_>>();
+    let message = format!(
+        "the {} kernel was not found in the casper directory, which has: {}",
+        variant,
+        variants.join(", ")
+    );
+
+    kernels
+        .into_iter()
+        .find(|kernel| kernel.variant == variant)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, message))
+}
+
+/// The meta-package which keeps the `variant` of the kernel up to date, if it is not the
+/// default variant, which the image already has.
+pub fn meta_package(variant: &str, release: &OsRelease) -> Option<String> {
+    if variant == DEFAULT_VARIANT {
+        return None;
+    }
+
+    Some(match variant.strip_suffix("hwe") {
+        Some(flavour) => {
+            let flavour = flavour.trim_end_matches('-');
+            let flavour = if flavour.is_empty() { DEFAULT_VARIANT } else { flavour };
+            format!("linux-{}-hwe-{}", flavour, release.version_id)
+        }
+        None => ["linux-", variant].concat(),
+    })
+}
+
+/// Reads the release of a kernel from the header of its bzImage.
+pub fn kernel_release(vmlinuz: &Path) -> io::Result<String> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a bzImage with a version string", vmlinuz.display()),
+        )
+    };
+
+    let mut file = File::open(vmlinuz)?;
+    let mut header = [0u8; 0x10];
+    file.seek(SeekFrom::Start(0x202))?;
+    file.read_exact(&mut header)?;
+
+    // The header begins with `HdrS`, and the offset of the version string follows at 0x20E.
+    if &header[..4] != b"HdrS" {
+        return Err(invalid());
+    }
+
+    let offset = u16::from_le_bytes([header[0xC], header[0xD]]);
+    let mut version = [0u8; 256];
+    file.seek(SeekFrom::Start(u64::from(offset) + 0x200))?;
+    let read = file.read(&mut version)?;
+
+    version[..read]
+        .split(|&byte| byte == 0 || byte == b' ')
+        .next()
+        .filter(|release| !release.is_empty())
+        .and_then(|release| std::str::from_utf8(release).ok())
+        .map(String::from)
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn bzimage(release: &str) -> Vec<u8> {
+        let mut image = vec![0u8; 0x400];
+        image[0x202..0x206].copy_from_slice(b"HdrS");
+        image[0x20E..0x210].copy_from_slice(&0x100u16.to_le_bytes());
+        let version = format!("{} (buildd@lcy02-amd64) #45-Ubuntu SMP\0", release);
+        image[0x300..0x300 + version.len()].copy_from_slice(version.as_bytes());
+        image
+    }
+
+    #[test]
+    fn casper_kernels() {
+        let casper = TempDir::new("distinst-casper").unwrap();
+        let files: &[(&str, Vec<u8>)] = &[
+            ("vmlinuz.efi", bzimage("6.8.0-45-generic")),
+            ("initrd.gz", Vec::new()),
+            ("hwe-vmlinuz", bzimage("6.11.0-8-generic")),
+            ("hwe-initrd", Vec::new()),
+            ("lowlatency-vmlinuz", b"not a kernel".to_vec()),
+            ("filesystem.squashfs", Vec::new()),
+        ];
+
+        for (name, contents) in files {
+            fs::write(casper.path().join(name), contents).unwrap();
+        }
+
+        let kernels = available(casper.path()).unwrap();
+        let variants = kernels.iter().map(|kernel| kernel.variant.as_str()).collect::<Vec<_>>();
+        assert_eq!(variants, ["generic", "hwe", "lowlatency"]);
+        assert_eq!(kernels[1].vmlinuz, casper.path().join("hwe-vmlinuz"));
+
+        assert_eq!(kernels[0].release().unwrap(), "6.8.0-45-generic");
+        assert_eq!(kernels[1].release().unwrap(), "6.11.0-8-generic");
+        assert!(kernels[2].release().is_err());
+    }
+}
@@ -9,6 +9,7 @@ pub extern crate distinst_bootloader as bootloader;
 pub extern crate distinst_chroot as chroot;
 pub extern crate distinst_disks as disks;
 pub extern crate distinst_external_commands as external;
+extern crate distinst_fstab as fstab;
 pub extern crate distinst_hardware_support as hardware_support;
 pub extern crate distinst_locale_support as locale;
 pub extern crate distinst_squashfs as squashfs;
@@ -48,6 +49,7 @@ extern crate logind_dbus;
 extern crate rayon;
 extern crate systemd_boot_conf;
 extern crate tempdir;
+extern crate ureq;
 
 pub use crate::bootloader::*;
 pub use disk_types::*;
@@ -63,6 +65,11 @@ mod logging;
 mod upgrade;
 
 pub mod auto;
+pub mod esp;
+pub mod kernels;
+pub mod recovery;
+pub mod systemd_boot;
+pub mod uboot;
 pub(crate) mod errors;
 
 /// Useful DBus interfaces for installers to implement.
@@ -85,7 +92,9 @@ use systemd_boot_conf::SystemdBootConf;
 pub use self::{installer::*, logging::log};
 
 /// When set to true, this will stop the installation process.
-pub static KILL_SWITCH: AtomicBool = AtomicBool::new(false);
+///
+/// Partition moves which are in progress will be rolled back before stopping.
+pub use crate::disks::operations::KILL_SWITCH;
 
 pub use crate::bootloader::FORCE_BOOTLOADER;
 
@@ -108,6 +117,30 @@ pub fn device_map_exists(name: &str) -> bool {
     dmlist().ok().map_or(false, |list| list.contains(&name.into()))
 }
 
+/// Applies the partitioning changes of `disks` without installing a system, so that partitions
+/// may be resized, moved, removed, created, or given a new table on their own.
+///
+/// The changes are applied in the same manner as the partitioning step of an install.
+pub fn apply_partitioning<F: FnMut(i32)>(disks: &mut Disks, callback: F) -> io::Result<()> {
+    disks.deactivate_device_maps()?;
+    disks.unmount_devices()?;
+    for mount in disks.session_mounts()? {
+        info!("releasing {}", mount);
+        mount.release()?;
+    }
+
+    let unmount =
+        disks.physical.iter().map(|disk| !disk.contains_mount("/", disks)).collect::<Vec<bool>>();
+
+    for (disk, _) in disks.physical.iter_mut().zip(unmount).filter(|&(_, unmount)| unmount) {
+        disk.unmount_all_partitions_with_target().map_err(|(path, why)| {
+            io::Error::new(why.kind(), format!("unable to unmount {:?}: {}", path, why))
+        })?;
+    }
+
+    installer::steps::partition(disks, callback)
+}
+
 /// Gets the minimum number of sectors required. The input should be in sectors, not bytes.
 ///
 /// The number of sectors required is calculated through:
@@ -116,6 +149,7 @@ pub fn device_map_exists(name: &str) -> bool {
 /// - The size of a default boot / esp partition
 /// - The size of a default swap partition
 /// - The size of a default recovery partition.
+/// - The space before the first partition which U-Boot boards reserve for their blobs.
 ///
 /// The input parameter will undergo a max comparison to the estimated minimum requirement.
 pub fn minimum_disk_size(default: u64) -> u64 {
@@ -126,16 +160,26 @@ pub fn minimum_disk_size(default: u64) -> u64 {
         // Convert bytes read into sectors required + 1
         .map_or(default, |size| ((size / 512) + 1).max(default));
 
-    // EFI installs will contain an EFI partition with a recovery partition.
-    let bootloader = if Bootloader::detect() == Bootloader::Efi {
-        DEFAULT_ESP_SECTORS + DEFAULT_RECOVER_SECTORS
-    } else {
-        0
+    // EFI installs will contain an EFI partition with a recovery partition, and U-Boot
+    // boards write their blobs before the first partition.
+    let bootloader = match Bootloader::detect() {
+        Bootloader::Efi => DEFAULT_ESP_SECTORS + DEFAULT_RECOVER_SECTORS,
+        Bootloader::UBoot => uboot::Board::detect().reserved() / 512,
+        Bootloader::Bios => 0,
     };
 
     casper + bootloader + DEFAULT_SWAP_SECTORS
 }
 
+/// The bytes which the root partition requires for the image at `squashfs`, read from the
+/// `filesystem.size` file beside it, or 0 if the size of the image is unknown.
+pub fn minimum_root_size(squashfs: &Path) -> u64 {
+    std::fs::read_to_string(squashfs.with_file_name("filesystem.size"))
+        .ok()
+        .and_then(|size| size.trim().parse::<u64>().ok())
+        .unwrap_or(0)
+}
+
 pub fn unset_mode() -> anyhow::Result<()> {
     let mut conf = RecoveryEnv::new().context("failed to read recovery.conf")?;
 
@@ -1,3 +1,4 @@
+use crate::installer::workdir::record as record_transcript;
 use dirs;
 use fern;
 use log::{Level, LevelFilter};
@@ -14,6 +15,10 @@ pub fn log<F: Fn(Level, &str) + Send + Sync + 'static>(callback: F) -> Result<()
         .chain(fern::Output::call(move |record| {
             callback(record.level(), &format!("{}", record.args()))
         }))
+        // Records the logs in the working directory of the installation which is running
+        .chain(fern::Output::call(|record| {
+            record_transcript(&format!("[{}] {}", record.level(), record.args()))
+        }))
         // Whereas this will handle displaying the logs to the terminal & a log file
         .chain({
             let mut logger = fern::Dispatch::new()
@@ -0,0 +1,329 @@
+//! The recovery partition, which holds a copy of the live media that the system was installed
+//! from, so that the system may be repaired, refreshed, or upgraded without external media.
+//!
+//! Its live session is booted by systemd-boot, from a kernel and initrd which are copied into
+//! `EFI/Recovery-<UUID>` on the ESP, with a loader entry that refers to them. The live session
+//! is configured by the `recovery.conf` at the root of the partition.
+
+use crate::chroot::Command;
+use crate::errors::{IntoIoResult, IoContext};
+use crate::misc;
+use partition_identity::PartitionID;
+use proc_mounts::MountList;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The boot options of the live session. All output is shown, and plymouth is not used, so
+/// that the recovery partition boots reliably.
+const BOOT_OPTIONS: &str = "";
+
+/// The directories of the live media which are copied to the recovery partition.
+const MEDIA_DIRS: &[&str] = &[".disk", "dists", "pool"];
+
+/// The files of the live media which are booted from the ESP.
+const BOOT_FILES: &[&str] = &["initrd.gz", "vmlinuz.efi"];
+
+/// The settings which the live session of the recovery partition is configured with.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecoveryConf {
+    pub hostname:    String,
+    pub lang:        String,
+    pub kbd_layout:  String,
+    pub kbd_model:   String,
+    pub kbd_variant: String,
+    /// The UUID of the root file system of the installed system.
+    pub root_uuid:   String,
+    /// The UUID of the LUKS volume which contains the root, or an empty string.
+    pub luks_uuid:   String,
+}
+
+/// A recovery partition, and the ESP which boots it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecoveryPartition {
+    /// Where the recovery partition is mounted.
+    pub path:         PathBuf,
+    /// Where the ESP is mounted.
+    pub efi:          PathBuf,
+    /// The UUID of the file system of the recovery partition.
+    pub uuid:         String,
+    pub partuuid:     String,
+    pub efi_partuuid: String,
+}
+
+impl RecoveryPartition {
+    /// Identifies the recovery partition which is mounted at `path`, and the ESP which is
+    /// mounted at `efi`.
+    pub fn new(path: &Path, efi: &Path) -> io::Result<Self> {
+        let mounts = MountList::new()?;
+        let recovery_mount = mounts
+            .get_mount_by_dest(path)
+            .into_io_result(|| format!("{} is not the mount of a block device", path.display()))?;
+
+        let efi_mount = mounts
+            .get_mount_by_dest(efi)
+            .into_io_result(|| format!("{} is not the mount of a block device", efi.display()))?;
+
+        let efi_partuuid = PartitionID::get_partuuid(&efi_mount.source)
+            .into_io_result(|| "efi partiton does not have a PartUUID")?;
+
+        let partuuid = PartitionID::get_partuuid(&recovery_mount.source)
+            .into_io_result(|| "recovery partition does not have a PartUUID")?;
+
+        let uuid = PartitionID::get_uuid(&recovery_mount.source)
+            .or_else(|| PartitionID::get_uuid(&efi_mount.source))
+            .into_io_result(|| "recovery partition does not have a UUID")?;
+
+        Ok(RecoveryPartition {
+            path:         path.to_path_buf(),
+            efi:          efi.to_path_buf(),
+            uuid:         uuid.id,
+            partuuid:     partuuid.id,
+            efi_partuuid: efi_partuuid.id,
+        })
+    }
+
+    /// Replaces the contents of the recovery partition with the live media at `source`, and
+    /// configures it to boot with the given name.
+    pub fn create(&self, source: &Path, name: &str, conf: &RecoveryConf) -> io::Result<()> {
+        info!("creating recovery partition at {}", self.path.display());
+        if let Ok(dir) = self.path.read_dir() {
+            for entry in dir.filter_map(Result::ok) {
+                if let Ok(metadata) = entry.metadata() {
+                    if metadata.is_file() {
+                        let _ = fs::remove_file(&entry.path());
+                    } else if metadata.is_dir() {
+                        let _ = fs::remove_dir_all(&entry.path());
+                    }
+                }
+            }
+        }
+
+        self.sync(source)?;
+
+        let conf_path = self.path.join("recovery.conf");
+        fs::write(&conf_path, recovery_conf(conf, &self.efi_partuuid, &self.partuuid))
+            .with_context(|err| format!("failed to write recovery file: {}", err))?;
+
+        self.install_boot_files(source, &[name, " recovery"].concat())
+    }
+
+    /// Refreshes the recovery partition with the live media at `source`, such as a newer ISO,
+    /// retaining its configuration and the title of its loader entry.
+    pub fn update(&self, source: &Path) -> io::Result<()> {
+        info!("updating recovery partition at {} from {}", self.path.display(), source.display());
+        if !self.path.join("recovery.conf").exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not contain a recovery partition", self.path.display()),
+            ));
+        }
+
+        let title = self.title();
+        self.sync(source)?;
+        self.install_boot_files(source, &title)
+    }
+
+    /// Copies the kernel and initrd of the recovery partition to the ESP again, and rewrites
+    /// its loader entry, so that the ESP can boot it after it was modified.
+    pub fn repair(&self) -> io::Result<()> {
+        info!("reinstalling the boot files of the recovery partition at {}", self.path.display());
+        self.install_boot_files(&self.path, &self.title())
+    }
+
+    /// Ensures that the recovery partition contains live media, and that the ESP can boot it.
+    pub fn verify(&self) -> io::Result<()> {
+        let casper = self.path.join(self.casper());
+        let missing = [self.path.join("recovery.conf"), casper.join("filesystem.squashfs")]
+            .iter()
+            .cloned()
+            .chain(BOOT_FILES.iter().map(|file| self.efi.join(self.efi_dir()).join(file)))
+            .chain(Some(self.loader_entry()))
+            .filter(|path| !path.exists())
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("recovery partition is missing: {}", missing.join(", ")),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Copies the live media at `source` to the recovery partition.
+    fn sync(&self, source: &Path) -> io::Result<()> {
+        let casper_source = casper_dir(source)?;
+
+        let mut args = vec!["-KLavc".to_owned(), "--delete-before".to_owned()];
+        args.extend(MEDIA_DIRS.iter().map(|dir| source.join(dir).to_string_lossy().into_owned()));
+        args.push(self.path.to_string_lossy().into_owned());
+        Command::new("rsync").args(&args).run()?;
+
+        Command::new("rsync")
+            .args(&["-KLavc", "--delete-before"])
+            .arg([casper_source.to_string_lossy().as_ref(), "/"].concat())
+            .arg(self.path.join(self.casper()))
+            .run()
+    }
+
+    /// Copies the kernel and initrd of the live media to the ESP, and writes a loader entry
+    /// for them.
+    fn install_boot_files(&self, source: &Path, title: &str) -> io::Result<()> {
+        let casper_source = casper_dir(source)?;
+        let efi_dir = self.efi.join(self.efi_dir());
+        fs::create_dir_all(&efi_dir)
+            .with_context(|err| format!("failed to create EFI recovery directories: {}", err))?;
+
+        for file in BOOT_FILES {
+            misc::cp(casper_source.join(file), efi_dir.join(file))?;
+        }
+
+        // If the NVIDIA DKMS driver is installed, force it to load in the recovery partition
+        // This test must not use /proc or /sys for detection since the installer can run inside a
+        // chroot where those come from the host environment.
+        let has_nvidia = Path::new("/var/lib/dkms/nvidia").exists();
+
+        let entry = self.loader_entry();
+        fs::create_dir_all(entry.parent().unwrap())
+            .with_context(|err| format!("failed to create EFI loader directories: {}", err))?;
+
+        fs::write(&entry, loader_entry(title, &self.uuid, &self.partuuid, has_nvidia))
+            .with_context(|err| format!("failed to write recovery EFI entry: {}", err))
+    }
+
+    /// The title of the existing loader entry.
+    fn title(&self) -> String {
+        fs::read_to_string(self.loader_entry())
+            .ok()
+            .and_then(|entry| entry_title(&entry).map(String::from))
+            .unwrap_or_else(|| "Recovery".into())
+    }
+
+    fn casper(&self) -> String { ["casper-", &self.uuid].concat() }
+
+    fn efi_dir(&self) -> String { ["EFI/Recovery-", &self.uuid].concat() }
+
+    fn loader_entry(&self) -> PathBuf {
+        self.efi.join(["loader/entries/Recovery-", &self.uuid, ".conf"].concat())
+    }
+}
+
+/// The casper directory of the live media, which is suffixed with the UUID of the recovery
+/// partition if the live media is itself a recovery partition.
+fn casper_dir(source: &Path) -> io::Result<PathBuf> {
+    let casper = source.join("casper");
+    if casper.is_dir() {
+        return Ok(casper);
+    }
+
+    source
+        .read_dir()
+        .with_context(|err| format!("failed to read {}: {}", source.display(), err))?
+        .filter_map(Result::ok)
+        .find(|entry| entry.file_name().to_string_lossy().starts_with("casper-"))
+        .map(|entry| entry.path())
+        .into_io_result(|| format!("{} does not contain live media", source.display()))
+}
+
+fn recovery_conf(conf: &RecoveryConf, efi_partuuid: &str, recovery_partuuid: &str) -> String {
+    format!(
+        r#"HOSTNAME={}
+LANG={}
+KBD_LAYOUT={}
+KBD_MODEL={}
+KBD_VARIANT={}
+EFI_UUID=PARTUUID={}
+RECOVERY_UUID=PARTUUID={}
+ROOT_UUID={}
+LUKS_UUID={}
+OEM_MODE=0
+"#,
+        conf.hostname,
+        conf.lang,
+        conf.kbd_layout,
+        conf.kbd_model,
+        conf.kbd_variant,
+        efi_partuuid,
+        recovery_partuuid,
+        conf.root_uuid,
+        conf.luks_uuid,
+    )
+}
+
+fn loader_entry(title: &str, uuid: &str, partuuid: &str, nvidia: bool) -> String {
+    format!(
+        r#"title {0}
+linux /EFI/Recovery-{1}/vmlinuz.efi
+initrd /EFI/Recovery-{1}/initrd.gz
+options {2} boot=casper hostname=recovery userfullname=Recovery username=recovery live-media-path=/casper-{1} live-media=/dev/disk/by-partuuid/{3} noprompt {4}
+"#,
+        title,
+        uuid,
+        BOOT_OPTIONS,
+        partuuid,
+        if nvidia { "modules_load=nvidia nvidia-drm.modeset=0" } else { "" }
+    )
+}
+
+fn entry_title(entry: &str) -> Option<&str> {
+    entry.lines().find_map(|line| line.strip_prefix("title ")).map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn entry() {
+        let entry = loader_entry("Pop_OS recovery", "AB12-CD34", "0123-4567", false);
+        let lines = entry.lines().collect::<Vec<_>>();
+        assert_eq!(lines[..3], [
+            "title Pop_OS recovery",
+            "linux /EFI/Recovery-AB12-CD34/vmlinuz.efi",
+            "initrd /EFI/Recovery-AB12-CD34/initrd.gz",
+        ]);
+        assert!(lines[3].contains(" live-media-path=/casper-AB12-CD34 "));
+        assert!(lines[3].contains(" live-media=/dev/disk/by-partuuid/0123-4567 "));
+        assert!(!lines[3].contains("nvidia"));
+
+        assert_eq!(entry_title(&entry), Some("Pop_OS recovery"));
+        assert_eq!(entry_title("linux /vmlinuz\n"), None);
+    }
+
+    #[test]
+    fn verify() {
+        let tempdir = TempDir::new("distinst").unwrap();
+        let partition = RecoveryPartition {
+            path:         tempdir.path().join("recovery"),
+            efi:          tempdir.path().join("efi"),
+            uuid:         "AB12-CD34".into(),
+            partuuid:     "0123-4567".into(),
+            efi_partuuid: "89ab-cdef".into(),
+        };
+
+        let casper = partition.path.join("casper-AB12-CD34");
+        fs::create_dir_all(&casper).unwrap();
+        fs::write(partition.path.join("recovery.conf"), "").unwrap();
+        for file in &["filesystem.squashfs", "initrd.gz", "vmlinuz.efi"] {
+            fs::write(casper.join(file), file).unwrap();
+        }
+
+        assert!(partition.verify().is_err());
+
+        assert_eq!(casper_dir(&partition.path).unwrap(), casper);
+        partition.install_boot_files(&partition.path, "Pop_OS recovery").unwrap();
+        partition.verify().unwrap();
+
+        // The boot files are reinstalled from the partition, under the title of its entry.
+        fs::remove_file(partition.efi.join(partition.efi_dir()).join("vmlinuz.efi")).unwrap();
+        assert!(partition.verify().is_err());
+        partition.repair().unwrap();
+        partition.verify().unwrap();
+        assert_eq!(partition.title(), "Pop_OS recovery");
+    }
+}
@@ -0,0 +1,344 @@
+//! Audit the loader entries of systemd-boot on an EFI system partition.
+//!
+//! Entries outlive what they boot: a refresh install replaces the kernels of the previous root,
+//! and recreating a partition gives it a new PARTUUID. systemd-boot still offers such entries,
+//! which fail once they are selected. An audit finds the entries that boot files which are not
+//! on the ESP, or that refer to partitions which do not exist, and can prune or fix them. Only
+//! the `root=` reference of the entry of the system being installed is ever rewritten.
+
+use crate::esp::{entry_directories, EspOwner};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Why a loader entry would fail to boot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryIssue {
+    /// A kernel, initrd, or EFI binary which is not on the ESP.
+    MissingFile(String),
+    /// A file system UUID which no partition has.
+    UnknownUuid(String),
+    /// A PARTUUID which no partition has. If it is the `root=` reference of the entry of the
+    /// system being installed, the current PARTUUID of its root is the `replacement`.
+    UnknownPartuuid { partuuid: String, replacement: Option<String> },
+}
+
+/// A loader entry in `loader/entries`, and the issues that were found with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoaderEntry {
+    pub path:   PathBuf,
+    pub title:  Option<String>,
+    pub issues: Vec<EntryIssue>,
+}
+
+impl LoaderEntry {
+    /// Whether the entry boots a file which is missing, which no fix can resolve.
+    pub fn is_broken(&self) -> bool {
+        self.issues.iter().any(|issue| matches!(issue, EntryIssue::MissingFile(_)))
+    }
+}
+
+/// The loader entries of an ESP.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audit {
+    pub esp:     PathBuf,
+    pub entries: Vec<LoaderEntry>,
+}
+
+/// Audits each loader entry of the ESP that is mounted at `esp`, where `root_uuid` is the file
+/// system UUID of the root being installed.
+pub fn audit<P: AsRef<Path>>(esp: P, root_uuid: &str) -> io::Result<Audit> {
+    audit_with(esp.as_ref(), Path::new("/dev/disk"), root_uuid)
+}
+
+/// Audits the entries against the `by-uuid` and `by-partuuid` links within `devices`.
+fn audit_with(esp: &Path, devices: &Path, root_uuid: &str) -> io::Result<Audit> {
+    let dir = esp.join("loader/entries");
+    let mut paths = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?,
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(why) => return Err(why),
+    };
+
+    paths.retain(|path| path.extension().and_then(|ext| ext.to_str()) == Some("conf"));
+    paths.sort();
+
+    let mut entries = Vec::with_capacity(paths.len());
+    for path in paths {
+        let contents = fs::read_to_string(&path)?;
+        entries.push(LoaderEntry {
+            title: contents
+                .lines()
+                .find_map(|line| line.strip_prefix("title "))
+                .map(|title| title.trim().to_owned()),
+            issues: issues(esp, devices, root_uuid, &contents),
+            path,
+        });
+    }
+
+    Ok(Audit { esp: esp.to_path_buf(), entries })
+}
+
+impl Audit {
+    /// Entries which have any issue.
+    pub fn flagged(&self) -> impl Iterator<Item = &LoaderEntry> {
+        self.entries.iter().filter(|entry| !entry.issues.is_empty())
+    }
+
+    /// Removes the entries which boot files that are missing, returning their paths.
+    pub fn prune(&mut self) -> io::Result<Vec<PathBuf>> {
+        let mut removed = Vec::new();
+        for entry in self.entries.iter().filter(|entry| entry.is_broken()) {
+            info!("removing the loader entry {}, which cannot boot", entry.path.display());
+            fs::remove_file(&entry.path)?;
+            removed.push(entry.path.clone());
+        }
+
+        self.entries.retain(|entry| !entry.is_broken());
+        Ok(removed)
+    }
+
+    /// Replaces each unknown `root=` PARTUUID which has a replacement, returning the paths of
+    /// the entries which were fixed. Other references to the same PARTUUID are left as they are.
+    pub fn fix(&mut self) -> io::Result<Vec<PathBuf>> {
+        let mut fixed = Vec::new();
+        for entry in &mut self.entries {
+            let replacements = entry
+                .issues
+                .iter()
+                .filter_map(|issue| match issue {
+                    EntryIssue::UnknownPartuuid { partuuid, replacement: Some(replacement) } => {
+                        Some((partuuid.clone(), replacement.clone()))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+
+            if replacements.is_empty() {
+                continue;
+            }
+
+            info!("replacing stale PARTUUIDs in the loader entry {}", entry.path.display());
+            let contents = fs::read_to_string(&entry.path)?;
+            let contents = contents
+                .lines()
+                .map(|line| match line.strip_prefix("options ") {
+                    Some(options) => {
+                        let options = options
+                            .split_whitespace()
+                            .map(|option| replace_root(option, &replacements))
+                            .collect::<Vec<_>>();
+                        ["options ", &options.join(" ")].concat()
+                    }
+                    None => line.to_owned(),
+                })
+                .collect::<Vec<_>>();
+
+            fs::write(&entry.path, [contents.join("\n").as_str(), "\n"].concat())?;
+            entry.issues.retain(|issue| {
+                !matches!(issue, EntryIssue::UnknownPartuuid { replacement: Some(_), .. })
+            });
+
+            fixed.push(entry.path.clone());
+        }
+
+        Ok(fixed)
+    }
+}
+
+/// The `option`, with its PARTUUID replaced if it is a `root=` reference with a replacement.
+fn replace_root(option: &str, replacements: &[(String, String)]) -> String {
+    if option.starts_with("root=") {
+        for (partuuid, replacement) in replacements {
+            if let Some(prefix) = option.strip_suffix(partuuid.as_str()) {
+                return [prefix, replacement].concat();
+            }
+        }
+    }
+
+    option.to_owned()
+}
+
+/// The issues of the entry with the `contents`.
+fn issues(esp: &Path, devices: &Path, root_uuid: &str, contents: &str) -> Vec<EntryIssue> {
+    let mut issues = Vec::new();
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next()) {
+            (Some("linux"), Some(file))
+            | (Some("initrd"), Some(file))
+            | (Some("efi"), Some(file))
+            | (Some("devicetree"), Some(file))
+                if !esp.join(file.trim_start_matches('/')).exists() =>
+            {
+                issues.push(EntryIssue::MissingFile(file.to_owned()));
+            }
+            _ => (),
+        }
+    }
+
+    // The UUID of the partition that the entry belongs to is in the name of its directory, and
+    // only the entry of the root being installed may have its root reference replaced.
+    let installing = entry_directories(contents)
+        .iter()
+        .map(|dir| EspOwner::from_name(dir))
+        .filter_map(|owner| owner.uuid().map(String::from))
+        .any(|uuid| uuid.eq_ignore_ascii_case(root_uuid));
+
+    let options = contents.lines().filter_map(|line| line.strip_prefix("options "));
+    for (option, kind, id) in options.flat_map(partition_references) {
+        let links = devices.join(kind);
+        if links.join(id).exists() || links.join(id.to_lowercase()).exists() {
+            continue;
+        }
+
+        issues.push(if kind == "by-uuid" {
+            EntryIssue::UnknownUuid(id.to_owned())
+        } else {
+            EntryIssue::UnknownPartuuid {
+                partuuid:    id.to_owned(),
+                replacement: if installing && option.starts_with("root=") {
+                    partuuid_of(devices, root_uuid)
+                } else {
+                    None
+                },
+            }
+        });
+    }
+
+    issues
+}
+
+/// The partitions which kernel options refer to, as the option, the directory of their links
+/// within `/dev/disk`, and their ID.
+fn partition_references(options: &str) -> Vec<(&str, &'static str, &str)> {
+    let mut references = Vec::new();
+    for option in options.split_whitespace() {
+        let patterns = [
+            ("PARTUUID=", "by-partuuid"),
+            ("/by-partuuid/", "by-partuuid"),
+            ("UUID=", "by-uuid"),
+            ("/by-uuid/", "by-uuid"),
+        ];
+
+        let found = patterns.iter().find_map(|&(pattern, kind)| {
+            option.find(pattern).map(|start| (kind, &option[start + pattern.len()..]))
+        });
+
+        if let Some((kind, id)) = found.filter(|(_, id)| !id.is_empty()) {
+            references.push((option, kind, id));
+        }
+    }
+
+    references
+}
+
+/// The current PARTUUID of the partition whose file system has the `uuid`, which is lowercase in
+/// the names of the directories on the ESP, but may be uppercase for FAT file systems.
+fn partuuid_of(devices: &Path, uuid: &str) -> Option<String> {
+    let device = fs::canonicalize(devices.join("by-uuid").join(uuid))
+        .or_else(|_| fs::canonicalize(devices.join("by-uuid").join(uuid.to_uppercase())))
+        .ok()?;
+
+    fs::read_dir(devices.join("by-partuuid"))
+        .ok()?
+        .filter_map(Result::ok)
+        .find(|link| fs::canonicalize(link.path()).ok().as_ref() == Some(&device))
+        .map(|link| link.file_name().to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempdir::TempDir;
+
+    #[test]
+    fn references() {
+        assert_eq!(
+            partition_references(
+                "root=UUID=1234 ro live-media=/dev/disk/by-partuuid/abcd resume=PARTUUID=ef01"
+            ),
+            vec![
+                ("root=UUID=1234", "by-uuid", "1234"),
+                ("live-media=/dev/disk/by-partuuid/abcd", "by-partuuid", "abcd"),
+                ("resume=PARTUUID=ef01", "by-partuuid", "ef01"),
+            ]
+        );
+        assert!(partition_references("quiet splash rd.luks.uuid=1234").is_empty());
+    }
+
+    #[test]
+    fn audit_prune_and_fix() {
+        let tmp = TempDir::new("distinst-systemd-boot").unwrap();
+        let (esp, devices) = (tmp.path().join("esp"), tmp.path().join("dev/disk"));
+        let write = |path: &Path, contents: &str| {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+
+        // The root being installed was recreated with a new PARTUUID.
+        write(&tmp.path().join("dev/sda2"), "");
+        fs::create_dir_all(devices.join("by-uuid")).unwrap();
+        fs::create_dir_all(devices.join("by-partuuid")).unwrap();
+        let by_uuid = devices.join("by-uuid/0a1b2c3d-4e5f-6071-8293-a4b5c6d7e8f9");
+        symlink(tmp.path().join("dev/sda2"), by_uuid).unwrap();
+        symlink(tmp.path().join("dev/sda2"), devices.join("by-partuuid/new-partuuid")).unwrap();
+
+        write(&esp.join("EFI/Pop_OS-0a1b2c3d-4e5f-6071-8293-a4b5c6d7e8f9/vmlinuz.efi"), "");
+        write(
+            &esp.join("loader/entries/Pop_OS-current.conf"),
+            "title Pop!_OS\nlinux /EFI/Pop_OS-0a1b2c3d-4e5f-6071-8293-a4b5c6d7e8f9/vmlinuz.efi\n\
+             options root=PARTUUID=old-partuuid resume=PARTUUID=old-partuuid\n",
+        );
+
+        write(&esp.join("EFI/Recovery-ABCD-1234/vmlinuz.efi"), "");
+        write(&esp.join("EFI/Recovery-ABCD-1234/initrd.gz"), "");
+        write(
+            &esp.join("loader/entries/Recovery-ABCD-1234.conf"),
+            "title Pop!_OS recovery\nlinux /EFI/Recovery-ABCD-1234/vmlinuz.efi\n\
+             initrd /EFI/Recovery-ABCD-1234/initrd.gz\n\
+             options boot=casper live-media=/dev/disk/by-partuuid/old-partuuid\n",
+        );
+        write(
+            &esp.join("loader/entries/Pop_OS-oldkern.conf"),
+            "title Pop!_OS\nlinux /EFI/Pop_OS-5678/vmlinuz-previous.efi\noptions root=UUID=5678\n",
+        );
+
+        let mut audit = audit_with(&esp, &devices, "0a1b2c3d-4e5f-6071-8293-a4b5c6d7e8f9").unwrap();
+        assert_eq!(audit.entries.len(), 3);
+        assert_eq!(audit.entries[0].issues, vec![
+            EntryIssue::UnknownPartuuid {
+                partuuid:    "old-partuuid".into(),
+                replacement: Some("new-partuuid".into()),
+            },
+            EntryIssue::UnknownPartuuid { partuuid: "old-partuuid".into(), replacement: None },
+        ]);
+        assert_eq!(audit.entries[1].issues, vec![
+            EntryIssue::MissingFile("/EFI/Pop_OS-5678/vmlinuz-previous.efi".into()),
+            EntryIssue::UnknownUuid("5678".into()),
+        ]);
+
+        // The recovery entry belongs to another partition, so it is only reported.
+        assert_eq!(audit.entries[2].title.as_deref(), Some("Pop!_OS recovery"));
+        assert_eq!(audit.entries[2].issues, vec![EntryIssue::UnknownPartuuid {
+            partuuid:    "old-partuuid".into(),
+            replacement: None,
+        }]);
+
+        assert_eq!(audit.prune().unwrap(), vec![esp.join("loader/entries/Pop_OS-oldkern.conf")]);
+        assert!(!esp.join("loader/entries/Pop_OS-oldkern.conf").exists());
+
+        let current = esp.join("loader/entries/Pop_OS-current.conf");
+        assert_eq!(audit.fix().unwrap(), vec![current.clone()]);
+        assert_eq!(
+            fs::read_to_string(&current).unwrap(),
+            "title Pop!_OS\nlinux /EFI/Pop_OS-0a1b2c3d-4e5f-6071-8293-a4b5c6d7e8f9/vmlinuz.efi\n\
+             options root=PARTUUID=new-partuuid resume=PARTUUID=old-partuuid\n"
+        );
+        assert_eq!(audit.flagged().count(), 2);
+    }
+}
@@ -0,0 +1,457 @@
+//! Install to ARM boards whose firmware is U-Boot, which boots the kernel that is described by
+//! an `extlinux/extlinux.conf` or a `boot.scr` on the boot partition.
+//!
+//! The SoCs of many boards load U-Boot itself from blobs at fixed offsets of the boot device,
+//! before the first partition. The board is identified by the compatible strings of its device
+//! tree, and the blobs are written from the U-Boot package of the installed system. Boards
+//! which are not known are expected to boot U-Boot from where it was flashed, such as SPI flash.
+
+use crate::chroot::Chroot;
+use crate::disks::{Disk, REMOVE};
+use crate::errors::IoContext;
+use disk_types::{BlockDeviceExt, PartitionTable, SectorExt};
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+};
+
+/// The compatible strings of the board, from the most to the least specific.
+const COMPATIBLE: &str = "/proc/device-tree/compatible";
+
+/// The kernel parameters which follow the root.
+const BOOT_OPTIONS: &str = "ro quiet splash";
+
+/// The bytes of the partition entries of a GPT, which follow the protective MBR and header.
+const GPT_ENTRIES: u64 = 16 * KIB;
+
+/// The hooks of kernel packages, which run after the links to the kernels are updated.
+const KERNEL_HOOK_DIRS: &[&str] = &["etc/kernel/postinst.d", "etc/kernel/postrm.d"];
+
+/// Keeps an uncompressed `Image` beside each of the links to the kernels, as U-Boot only
+/// decompresses a kernel for an extlinux config when its environment has been set up to.
+const IMAGE_HOOK: &str = r#"#!/bin/sh
+# Decompresses the kernels which extlinux.conf boots, as U-Boot cannot boot the gzip images.
+set -e
+for kernel in vmlinuz vmlinuz.old; do
+    image="/boot/Image${kernel#vmlinuz}"
+    if [ -e "/boot/$kernel" ]; then
+        zcat -f "/boot/$kernel" > "$image.new"
+        mv "$image.new" "$image"
+    else
+        rm -f "$image"
+    fi
+done
+"#;
+
+const KIB: u64 = 1024;
+const MIB: u64 = 1024 * 1024;
+
+/// How the kernel is described to U-Boot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BootConfig {
+    /// An `extlinux/extlinux.conf`, which U-Boot's distro boot prefers.
+    Extlinux,
+    /// A `boot.scr`, compiled from `boot.cmd` with `mkimage`.
+    Script,
+}
+
+/// A blob which the SoC loads from a fixed offset of the boot device.
+#[derive(Debug, PartialEq)]
+pub struct Blob {
+    /// The path of the blob within the installed system.
+    pub path:   &'static str,
+    /// The byte offset of the boot device which it is written to.
+    pub offset: u64,
+    /// The byte offset where the space which is reserved for it ends.
+    pub end:    u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Board {
+    /// A compatible string of the device tree, such as `pine64,rockpro64`.
+    pub compatible: &'static str,
+    pub config:     BootConfig,
+    pub blobs:      &'static [Blob],
+    /// The packages which provide the blobs, and the tools which the config requires.
+    pub packages:   &'static [&'static str],
+}
+
+static GENERIC: Board =
+    Board { compatible: "", config: BootConfig::Extlinux, blobs: &[], packages: &[] };
+
+static BOARDS: &[Board] = &[
+    Board {
+        compatible: "pine64,rockpro64",
+        config:     BootConfig::Extlinux,
+        blobs:      &[
+            Blob {
+                path:   "/usr/lib/u-boot/rockpro64-rk3399/idbloader.img",
+                offset: 32 * KIB,
+                end:    8 * MIB,
+            },
+            Blob {
+                path:   "/usr/lib/u-boot/rockpro64-rk3399/u-boot.itb",
+                offset: 8 * MIB,
+                end:    16 * MIB,
+            },
+        ],
+        packages:   &["u-boot-rockchip"],
+    },
+    Board {
+        compatible: "pine64,pinebook-pro",
+        config:     BootConfig::Extlinux,
+        blobs:      &[
+            Blob {
+                path:   "/usr/lib/u-boot/pinebook-pro-rk3399/idbloader.img",
+                offset: 32 * KIB,
+                end:    8 * MIB,
+            },
+            Blob {
+                path:   "/usr/lib/u-boot/pinebook-pro-rk3399/u-boot.itb",
+                offset: 8 * MIB,
+                end:    16 * MIB,
+            },
+        ],
+        packages:   &["u-boot-rockchip"],
+    },
+    Board {
+        compatible: "pine64,pine64-plus",
+        config:     BootConfig::Extlinux,
+        blobs:      &[Blob {
+            path:   "/usr/lib/u-boot/pine64_plus/u-boot-sunxi-with-spl.bin",
+            offset: 8 * KIB,
+            end:    MIB,
+        }],
+        packages:   &["u-boot-sunxi"],
+    },
+    Board {
+        compatible: "hardkernel,odroid-n2",
+        config:     BootConfig::Script,
+        blobs:      &[],
+        packages:   &["u-boot-tools"],
+    },
+];
+
+impl Board {
+    /// The board that this system is running on.
+    pub fn detect() -> &'static Board {
+        fs::read(COMPATIBLE).ok().map_or(&GENERIC, |compatible| Board::find(&compatible))
+    }
+
+    /// The known board which is the most specific match of the compatible strings.
+    fn find(compatible: &[u8]) -> &'static Board {
+        compatible
+            .split(|&byte| byte == 0)
+            .filter_map(|compatible| std::str::from_utf8(compatible).ok())
+            .find_map(|compatible| BOARDS.iter().find(|board| board.compatible == compatible))
+            .unwrap_or(&GENERIC)
+    }
+
+    /// The bytes at the start of the boot device which the blobs require.
+    pub fn reserved(&self) -> u64 { self.blobs.iter().map(|blob| blob.end).max().unwrap_or(0) }
+
+    /// Ensures that the blobs may be written to the disk without overwriting its partition
+    /// table, or the partitions that it will have.
+    pub fn verify(&self, disk: &Disk) -> io::Result<()> {
+        let sector = disk.get_logical_block_size();
+        let table_end = match disk.table_type {
+            Some(PartitionTable::Gpt) => gpt_end(sector),
+            _ => sector,
+        };
+
+        let mut regions = vec![("the partition table".to_owned(), 0, table_end)];
+        regions.extend(disk.partitions.iter().filter(|part| !part.flag_is_enabled(REMOVE)).map(
+            |part| {
+                let name = format!("the partition at sector {}", part.start_sector);
+                (name, part.start_sector * sector, (part.end_sector + 1) * sector)
+            },
+        ));
+
+        match self.overlap(&regions) {
+            Some((blob, region)) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} is written at byte {} of {}, which would overwrite {}",
+                    blob.path,
+                    blob.offset,
+                    disk.get_device_path().display(),
+                    region
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The first blob which overlaps a region, and the name of that region.
+    fn overlap<'a>(&self, regions: &'a [(String, u64, u64)]) -> Option<(&Blob, &'a str)> {
+        self.blobs.iter().find_map(|blob| {
+            regions
+                .iter()
+                .find(|&&(_, start, end)| blob.offset < end && start < blob.end)
+                .map(|(name, ..)| (blob, name.as_str()))
+        })
+    }
+
+    /// Writes the blobs from the installed system at `target` to the boot `device`.
+    pub fn write_blobs(&self, target: &Path, device: &Path) -> io::Result<()> {
+        if self.blobs.is_empty() {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(device)
+            .with_context(|err| format!("failed to open {}: {}", device.display(), err))?;
+
+        for blob in self.blobs {
+            let path = target.join(blob.path.trim_start_matches('/'));
+            let data = fs::read(&path)
+                .with_context(|err| format!("failed to read {}: {}", path.display(), err))?;
+
+            if data.len() as u64 > blob.end - blob.offset {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is larger than the space reserved for it", blob.path),
+                ));
+            }
+
+            info!("writing {} to {} at byte {}", blob.path, device.display(), blob.offset);
+            file.seek(SeekFrom::Start(blob.offset))
+                .and_then(|_| file.write_all(&data))
+                .with_context(|err| format!("failed to write {}: {}", blob.path, err))?;
+        }
+
+        file.sync_all()
+    }
+
+    /// Describes the kernel of the installed system to U-Boot. The kernel and initrd are
+    /// found through the links which the kernel packages maintain in `/boot`, so that the
+    /// config remains valid as kernels are updated. A root on a btrfs subvolume,
+    /// `root_subvolume`, is named on the kernel command line.
+    pub fn write_config(
+        &self,
+        chroot: &Chroot,
+        name: &str,
+        separate_boot: bool,
+        root_uuid: &str,
+        root_subvolume: Option<&str>,
+    ) -> io::Result<()> {
+        // U-Boot reads the files from the partition which holds `/boot`.
+        let prefix = if separate_boot { "/" } else { "/boot/" };
+        let bootargs = bootargs(root_uuid, root_subvolume);
+        let boot = chroot.path.join("boot");
+        match self.config {
+            BootConfig::Extlinux => {
+                // The arm64 kernels of Ubuntu are compressed with gzip, whereas the zImage of
+                // arm decompresses itself.
+                let kernel = if env::consts::ARCH == "aarch64" {
+                    write_image_hooks(chroot)?;
+                    "Image"
+                } else {
+                    "vmlinuz"
+                };
+
+                let dir = boot.join("extlinux");
+                let conf = dir.join("extlinux.conf");
+                info!("writing {}", conf.display());
+                fs::create_dir_all(&dir)
+                    .and_then(|_| fs::write(&conf, extlinux_conf(name, prefix, kernel, &bootargs)))
+                    .with_context(|err| format!("failed to write {}: {}", conf.display(), err))
+            }
+            BootConfig::Script => {
+                let cmd = boot.join("boot.cmd");
+                info!("writing {}", cmd.display());
+                fs::write(&cmd, boot_cmd(env::consts::ARCH, prefix, &bootargs))
+                    .with_context(|err| format!("failed to write {}: {}", cmd.display(), err))?;
+
+                let arch = if env::consts::ARCH == "aarch64" { "arm64" } else { "arm" };
+                chroot
+                    .command("mkimage", &[
+                        "-A",
+                        arch,
+                        "-O",
+                        "linux",
+                        "-T",
+                        "script",
+                        "-C",
+                        "none",
+                        "-d",
+                        "/boot/boot.cmd",
+                        "/boot/boot.scr",
+                    ])
+                    .run()
+            }
+        }
+    }
+}
+
+/// The byte where the partition entries of a GPT end, on a disk with the `sector` size. The
+/// protective MBR and the header occupy a sector each.
+fn gpt_end(sector: u64) -> u64 { (2 + (GPT_ENTRIES + sector - 1) / sector) * sector }
+
+/// Installs the hooks which keep the uncompressed kernels up to date, and runs them to create
+/// the kernels of the installed system.
+fn write_image_hooks(chroot: &Chroot) -> io::Result<()> {
+    for hooks in KERNEL_HOOK_DIRS {
+        let hooks = chroot.path.join(hooks);
+        let hook = hooks.join("zz-distinst-uboot-image");
+        fs::create_dir_all(&hooks)
+            .and_then(|_| fs::write(&hook, IMAGE_HOOK))
+            .and_then(|_| fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)))
+            .with_context(|err| format!("failed to write {}: {}", hook.display(), err))?;
+    }
+
+    let hook = ["/", KERNEL_HOOK_DIRS[0], "/zz-distinst-uboot-image"].concat();
+    chroot.command("/bin/sh", &[hook.as_str()]).run()
+}
+
+/// The menu of the `kernel`, and of the kernel before it. The device tree which U-Boot was
+/// built with is given to the kernel.
+fn extlinux_conf(name: &str, prefix: &str, kernel: &str, bootargs: &str) -> String {
+    format!(
+        "default l0\nmenu title {name}\nprompt 0\ntimeout 30\n\n\
+         label l0\n    menu label {name}\n    linux {prefix}{kernel}\n    \
+         initrd {prefix}initrd.img\n    append {bootargs}\n\n\
+         label l1\n    menu label {name} (previous kernel)\n    linux {prefix}{kernel}.old\n    \
+         initrd {prefix}initrd.img.old\n    append {bootargs}\n",
+        name = name,
+        prefix = prefix,
+        kernel = kernel,
+        bootargs = bootargs
+    )
+}
+
+/// The script which loads the kernel and initrd from the partition it was loaded from.
+///
+/// The arm64 kernels of Ubuntu are compressed with gzip, which `booti` only decompresses when
+/// it is given the size of the kernel, and an address to move it to while it does so. Boards
+/// whose environment lacks that address are given the space after the initrd.
+fn boot_cmd(arch: &str, prefix: &str, bootargs: &str) -> String {
+    let load = "load ${devtype} ${devnum}:${distro_bootpart}";
+    let (kernel, boot) = if arch == "aarch64" {
+        let kernel = format!(
+            "{load} ${{kernel_addr_r}} {prefix}vmlinuz\n\
+             setenv kernel_comp_size ${{filesize}}\n\
+             {load} ${{ramdisk_addr_r}} {prefix}initrd.img\n\
+             setenv ramdisk_size ${{filesize}}\n\
+             if test -z \"${{kernel_comp_addr_r}}\"; then \
+             setexpr kernel_comp_addr_r ${{ramdisk_addr_r}} + ${{filesize}}; fi\n",
+            load = load,
+            prefix = prefix
+        );
+
+        (kernel, "booti ${kernel_addr_r} ${ramdisk_addr_r}:${ramdisk_size} ${fdtcontroladdr}\n")
+    } else {
+        let kernel = format!(
+            "{load} ${{kernel_addr_r}} {prefix}vmlinuz\n\
+             {load} ${{ramdisk_addr_r}} {prefix}initrd.img\n",
+            load = load,
+            prefix = prefix
+        );
+
+        (kernel, "bootz ${kernel_addr_r} ${ramdisk_addr_r}:${filesize} ${fdtcontroladdr}\n")
+    };
+
+    format!("setenv bootargs {}\n{}{}", bootargs, kernel, boot)
+}
+
+/// The kernel parameters, which name the root by the UUID of its file system, and its
+/// subvolume if it has one.
+fn bootargs(root_uuid: &str, subvolume: Option<&str>) -> String {
+    match subvolume {
+        Some(subvolume) => {
+            format!("root=UUID={} rootflags=subvol={} {}", root_uuid, subvolume, BOOT_OPTIONS)
+        }
+        None => format!("root=UUID={} {}", root_uuid, BOOT_OPTIONS),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootloader::UBOOT_BOARDS;
+
+    #[test]
+    fn detected_boards() {
+        for board in BOARDS {
+            assert!(
+                UBOOT_BOARDS.contains(&board.compatible),
+                "{} is not detected",
+                board.compatible
+            );
+        }
+    }
+
+    #[test]
+    fn boards() {
+        let board = Board::find(b"pine64,rockpro64-v2.1\0pine64,rockpro64\0rockchip,rk3399\0");
+        assert_eq!(board.compatible, "pine64,rockpro64");
+        assert_eq!(board.reserved(), 16 * MIB);
+
+        let board = Board::find(b"raspberrypi,4-model-b\0brcm,bcm2711\0");
+        assert_eq!(board, &GENERIC);
+        assert_eq!(board.reserved(), 0);
+    }
+
+    #[test]
+    fn overlaps() {
+        let board = Board::find(b"pine64,rockpro64\0");
+        let regions = |start| {
+            vec![("the partition table".to_owned(), 0, 512), ("root".to_owned(), start, 1 << 30)]
+        };
+
+        assert!(board.overlap(&regions(16 * MIB)).is_none());
+        let overlapped = regions(MIB);
+        let (blob, region) = board.overlap(&overlapped).unwrap();
+        assert_eq!((blob.offset, region), (32 * KIB, "root"));
+
+        // The partition entries of a GPT end at 17 KiB, before the blob of Allwinner boards.
+        let board = Board::find(b"pine64,pine64-plus\0");
+        let gpt = vec![("the partition table".to_owned(), 0, gpt_end(512))];
+        assert_eq!(board.overlap(&gpt).map(|(_, region)| region), Some("the partition table"));
+
+        // On 4Kn disks, the entries occupy four sectors, ending at 24 KiB.
+        assert_eq!((gpt_end(512), gpt_end(4096)), (34 * 512, 24 * KIB));
+    }
+
+    #[test]
+    fn configs() {
+        assert_eq!(
+            extlinux_conf("Pop!_OS 24.04 LTS", "/boot/", "Image", &bootargs("ROOT", None)),
+            "default l0\nmenu title Pop!_OS 24.04 LTS\nprompt 0\ntimeout 30\n\n\
+             label l0\n    menu label Pop!_OS 24.04 LTS\n    linux /boot/Image\n    \
+             initrd /boot/initrd.img\n    append root=UUID=ROOT ro quiet splash\n\n\
+             label l1\n    menu label Pop!_OS 24.04 LTS (previous kernel)\n    \
+             linux /boot/Image.old\n    initrd /boot/initrd.img.old\n    \
+             append root=UUID=ROOT ro quiet splash\n"
+        );
+
+        assert_eq!(
+            boot_cmd("aarch64", "/", &bootargs("ROOT", None)),
+            "setenv bootargs root=UUID=ROOT ro quiet splash\n\
+             load ${devtype} ${devnum}:${distro_bootpart} ${kernel_addr_r} /vmlinuz\n\
+             setenv kernel_comp_size ${filesize}\n\
+             load ${devtype} ${devnum}:${distro_bootpart} ${ramdisk_addr_r} /initrd.img\n\
+             setenv ramdisk_size ${filesize}\n\
+             if test -z \"${kernel_comp_addr_r}\"; then \
+             setexpr kernel_comp_addr_r ${ramdisk_addr_r} + ${filesize}; fi\n\
+             booti ${kernel_addr_r} ${ramdisk_addr_r}:${ramdisk_size} ${fdtcontroladdr}\n"
+        );
+
+        assert_eq!(
+            boot_cmd("arm", "/boot/", &bootargs("ROOT", None)),
+            "setenv bootargs root=UUID=ROOT ro quiet splash\n\
+             load ${devtype} ${devnum}:${distro_bootpart} ${kernel_addr_r} /boot/vmlinuz\n\
+             load ${devtype} ${devnum}:${distro_bootpart} ${ramdisk_addr_r} /boot/initrd.img\n\
+             bootz ${kernel_addr_r} ${ramdisk_addr_r}:${filesize} ${fdtcontroladdr}\n"
+        );
+
+        assert_eq!(
+            bootargs("ROOT", Some("@")),
+            "root=UUID=ROOT rootflags=subvol=@ ro quiet splash"
+        );
+    }
+}
@@ -6,6 +6,7 @@ use crate::disks::Disks;
 use crate::errors::IoContext;
 use crate::external::remount_rw;
 use crate::installer::{steps::mount_efivars, RecoveryEnv};
+use crate::recovery::RecoveryPartition;
 use std::{io, path::Path, process::Stdio};
 use systemd_boot_conf::SystemdBootConf;
 use tempdir::TempDir;
@@ -110,10 +111,26 @@ pub fn upgrade<F: Fn(UpgradeEvent), R: Fn() -> bool>(
         .map_err(UpgradeError::UpgradeFlag)?;
 
     systemd_boot_entry_restore(mount_dir)?;
+    verify_recovery(&mount_dir.join("boot/efi"));
 
     Ok(())
 }
 
+/// The upgrade is performed from the live session of the recovery partition, which must remain
+/// bootable from the ESP at `efi` once the upgraded system has modified it.
+fn verify_recovery(efi: &Path) {
+    let result = RecoveryPartition::new(Path::new("/cdrom"), efi).and_then(|recovery| {
+        recovery.verify().or_else(|why| {
+            warn!("{}: reinstalling its boot files", why);
+            recovery.repair().and_then(|_| recovery.verify())
+        })
+    });
+
+    if let Err(why) = result {
+        warn!("the recovery partition may not boot: {}", why);
+    }
+}
+
 fn systemd_boot_entry_restore<P: AsRef<Path>>(base: P) -> Result<(), UpgradeError> {
     let mut systemd_boot_conf = SystemdBootConf::new(base.as_ref().join("boot/efi"))
         .map_err(UpgradeError::SystemdBootConf)?;
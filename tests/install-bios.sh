@@ -26,7 +26,7 @@ done
 set -e -x
 
 # Install
-sudo target/debug/distinst \
+sudo target/debug/distinst install \
     -s "${FS}" \
     -r "${REMOVE}" \
     -h "pop-testing" \
@@ -43,7 +43,7 @@ sudo target/debug/distinst \
     -n "$1:logical:-4096M:end:swap"
 
 # Reinstall
-sudo target/debug/distinst \
+sudo target/debug/distinst install \
     -s "${FS}" \
     -r "${REMOVE}" \
     -h "pop-testing" \
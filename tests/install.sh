@@ -25,7 +25,7 @@ done
 
 set -e -x
 
-echo "system76" | sudo target/debug/distinst \
+echo "system76" | sudo target/debug/distinst install \
     -s "${FS}" \
     -r "${REMOVE}" \
     -h "pop-testing" \
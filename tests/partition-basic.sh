@@ -28,7 +28,7 @@ set -e -x
 
 echo 'Running new partitioning tests'
 index=0; while test ${index} -ne ${RUNS}; do
-    sudo target/debug/distinst --test \
+    sudo target/debug/distinst install --test \
         -s "${FS}" \
         -r "${REMOVE}" \
         -h "pop-testing" \
@@ -44,7 +44,7 @@ done
 
 echo 'Running re-use partition tests'
 index=0; while test ${index} -ne ${RUNS}; do
-    sudo target/debug/distinst --test \
+    sudo target/debug/distinst install --test \
         -s "${FS}" \
         -r "${REMOVE}" \
         -h "pop-testing" \
@@ -59,7 +59,7 @@ done
 
 echo 'Running partition removal tests'
 index=0; while test ${index} -ne ${RUNS}; do
-    sudo target/debug/distinst --test \
+    sudo target/debug/distinst install --test \
         -s "${FS}" \
         -r "${REMOVE}" \
         -h "pop-testing" \
@@ -30,7 +30,7 @@ set -e -x
 
 echo 'Running LVM on LUKS test'
 index=0; while test ${index} -ne ${RUNS}; do
-    sudo env RUST_BACKTRACE=1 target/debug/distinst --test \
+    sudo env RUST_BACKTRACE=1 target/debug/distinst install --test \
             -s "${FS}" \
             -r "${REMOVE}" \
             -h "pop-testing" \
@@ -26,7 +26,7 @@ done
 
 set -e -x
 
-sudo target/debug/distinst --test \
+sudo target/debug/distinst install --test \
     -s "${FS}" \
     -r "${REMOVE}" \
     -h "pop-testing" \
@@ -39,7 +39,7 @@ sudo target/debug/distinst --test \
 
 echo 'Running LVM on LUKS test'
 index=0; while test ${index} -ne ${RUNS}; do
-    sudo target/debug/distinst --test \
+    sudo target/debug/distinst install --test \
         -s "${FS}" \
         -r "${REMOVE}" \
         -h "pop-testing" \
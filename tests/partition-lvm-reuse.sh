@@ -28,7 +28,7 @@ set -e -x
 
 echo 'Running LVM on LUKS test'
 index=0; while test ${index} -ne ${RUNS}; do
-    sudo target/debug/distinst --test \
+    sudo target/debug/distinst install --test \
         -s "${FS}" \
         -r "${REMOVE}" \
         -h "pop-testing" \
@@ -40,7 +40,7 @@ index=0; while test ${index} -ne ${RUNS}; do
         --logical "data:root:-4096M:ext4:mount=/" \
         --logical "data:swap:4080M:swap"
 
-    sudo target/debug/distinst --test \
+    sudo target/debug/distinst install --test \
         -s "${FS}" \
         -r "${REMOVE}" \
         -h "pop-testing" \
@@ -37,7 +37,7 @@ fi
 
 echo 'Running resize tests'
 index=0; while test ${index} -ne ${RUNS}; do
-    sudo target/debug/distinst --test \
+    sudo target/debug/distinst install --test \
         -s "${FS}" \
         -r "${REMOVE}" \
         -h "pop-testing" \
@@ -57,7 +57,7 @@ index=0; while test ${index} -ne ${RUNS}; do
     sudo sh -c "echo more data > temp/another_file"
     sudo umount temp
 
-    sudo target/debug/distinst --test \
+    sudo target/debug/distinst install --test \
         -s "${FS}" \
         -r "${REMOVE}" \
         -h "pop-testing" \
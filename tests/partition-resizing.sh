@@ -28,7 +28,7 @@ set -e -x
 
 echo 'Running resize tests'
 index=0; while test ${index} -ne ${RUNS}; do
-    sudo target/debug/distinst --test \
+    sudo target/debug/distinst install --test \
         -s "${FS}" \
         -r "${REMOVE}" \
         -h "pop-testing" \
@@ -41,7 +41,7 @@ index=0; while test ${index} -ne ${RUNS}; do
         -n "$1:primary:2048M:4096M:ext4" \
         -n "$1:primary:-512M:end:swap"
 
-    sudo target/debug/distinst --test \
+    sudo target/debug/distinst install --test \
         -s "${FS}" \
         -r "${REMOVE}" \
         -h "pop-testing" \